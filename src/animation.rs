@@ -0,0 +1,366 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use anyhow::{bail, Context, Result};
+use glam::{Quat, Vec3, Vec4};
+use serde::{Deserialize, Serialize};
+
+use crate::scene::SceneEntity;
+use crate::transform::Transform;
+
+// Keyframe animation in the same shape glTF stores it: every track animates
+// one property of one named node, with its keyframe values flattened into a
+// list of floats. Importers can fill these in directly, and small ones are
+// easy enough to write by hand in a scene file.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Property {
+    Translation,
+    // Quaternions as x, y, z, w
+    Rotation,
+    Scale,
+    // Morph target weights, as many per keyframe as the mesh has targets
+    Weights,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Interpolation {
+    Step,
+    #[default]
+    Linear,
+    // Hermite spline, every keyframe stores an in tangent, the value and an
+    // out tangent (in that order) like glTF's CUBICSPLINE
+    CubicSpline,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Track {
+    // Name of the scene entity this animates
+    pub target: String,
+    pub property: Property,
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    // Keyframe times in seconds, in increasing order
+    pub times: Vec<f32>,
+    pub values: Vec<f32>,
+}
+
+impl Track {
+    // Floats that make up one value
+    pub fn components(&self) -> usize {
+        match self.property {
+            Property::Translation | Property::Scale => 3,
+            Property::Rotation => 4,
+            Property::Weights => {
+                let per_key = if self.interpolation == Interpolation::CubicSpline { 3 } else { 1 };
+                self.values.len() / (self.times.len() * per_key).max(1)
+            }
+        }
+    }
+
+    // Checks there are as many values as the keyframes need, so sample()
+    // doesn't read past the end. Scenes check every track when they load.
+    pub fn validate(&self) -> Result<()> {
+        if self.times.windows(2).any(|pair| pair[1] < pair[0]) {
+            bail!("track for {:?} {:?} has keyframe times out of order", self.target, self.property);
+        }
+        let per_key = if self.interpolation == Interpolation::CubicSpline { 3 } else { 1 };
+        let keys = self.times.len() * per_key;
+        let expected = match self.property {
+            // However many weights there are, every keyframe needs the same
+            Property::Weights if keys > 0 => self.values.len().div_ceil(keys).max(1) * keys,
+            _ => keys * self.components(),
+        };
+        if self.values.len() != expected {
+            bail!(
+                "track for {:?} {:?} has {} values, its {} keyframes need {}",
+                self.target,
+                self.property,
+                self.values.len(),
+                self.times.len(),
+                expected
+            );
+        }
+        Ok(())
+    }
+
+    // Writes the value at `time` into `out`, which needs components() floats
+    pub fn sample(&self, time: f32, out: &mut [f32]) {
+        let n = self.components();
+        if self.times.is_empty() || n == 0 {
+            return;
+        }
+        let cubic = self.interpolation == Interpolation::CubicSpline;
+        let stride = if cubic { n * 3 } else { n };
+        // The value itself sits in the middle of the cubic triplet
+        let value = |key: usize| {
+            let start = key * stride + if cubic { n } else { 0 };
+            &self.values[start..start + n]
+        };
+
+        let last = self.times.len() - 1;
+        let next = self.times.partition_point(|&t| t <= time);
+        if next == 0 || next > last {
+            out[..n].copy_from_slice(value(next.min(last)));
+            return;
+        }
+        let key = next - 1;
+        let dt = self.times[next] - self.times[key];
+        let t = if dt > 0.0 { (time - self.times[key]) / dt } else { 0.0 };
+
+        match self.interpolation {
+            Interpolation::Step => out[..n].copy_from_slice(value(key)),
+            Interpolation::Linear if self.property == Property::Rotation => {
+                let a = Quat::from_slice(value(key));
+                let b = Quat::from_slice(value(next));
+                a.slerp(b, t).write_to_slice(out);
+            }
+            Interpolation::Linear => {
+                for (i, (a, b)) in value(key).iter().zip(value(next)).enumerate() {
+                    out[i] = a + (b - a) * t;
+                }
+            }
+            Interpolation::CubicSpline => {
+                let out_tangent = &self.values[key * stride + 2 * n..key * stride + 3 * n];
+                let in_tangent = &self.values[next * stride..next * stride + n];
+                let (t2, t3) = (t * t, t * t * t);
+                let h00 = 2.0 * t3 - 3.0 * t2 + 1.0;
+                let h10 = t3 - 2.0 * t2 + t;
+                let h01 = -2.0 * t3 + 3.0 * t2;
+                let h11 = t3 - t2;
+                for i in 0..n {
+                    out[i] = h00 * value(key)[i]
+                        + h10 * dt * out_tangent[i]
+                        + h01 * value(next)[i]
+                        + h11 * dt * in_tangent[i];
+                }
+                if self.property == Property::Rotation {
+                    Quat::from_slice(out).normalize().write_to_slice(out);
+                }
+            }
+        }
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.times.last().copied().unwrap_or(0.0)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AnimationClip {
+    pub name: String,
+    pub tracks: Vec<Track>,
+}
+
+impl AnimationClip {
+    pub fn validate(&self) -> Result<()> {
+        for track in &self.tracks {
+            track.validate().with_context(|| format!("in animation {:?}", self.name))?;
+        }
+        Ok(())
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.tracks.iter().map(Track::duration).fold(0.0, f32::max)
+    }
+}
+
+// Plays a clip against a set of scene entities. Tracks get matched to
+// entities by name once, in bind(), instead of every frame.
+pub struct AnimationPlayer {
+    clip: Rc<AnimationClip>,
+    bindings: Vec<Option<usize>>,
+    time: f32,
+    pub speed: f32,
+    pub looping: bool,
+    pub playing: bool,
+    // Morph target weights by entity name, nothing draws these yet
+    weights: HashMap<String, Vec<f32>>,
+}
+
+impl AnimationPlayer {
+    pub fn new(clip: Rc<AnimationClip>) -> Self {
+        Self {
+            clip,
+            bindings: Vec::new(),
+            time: 0.0,
+            speed: 1.0,
+            looping: true,
+            playing: true,
+            weights: HashMap::new(),
+        }
+    }
+
+    pub fn clip(&self) -> &AnimationClip {
+        &self.clip
+    }
+
+    pub fn bind(&mut self, entities: &[SceneEntity]) {
+        self.bindings = self
+            .clip
+            .tracks
+            .iter()
+            .map(|track| {
+                let entity = entities.iter().position(|e| e.name == track.target);
+                if entity.is_none() {
+                    tracing::warn!("Animation {} targets a missing entity {}", self.clip.name, track.target);
+                }
+                entity
+            })
+            .collect();
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn seek(&mut self, time: f32) {
+        self.time = time;
+    }
+
+    pub fn finished(&self) -> bool {
+        !self.looping && self.time >= self.clip.duration()
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        if !self.playing {
+            return;
+        }
+        self.time += dt * self.speed;
+        let duration = self.clip.duration();
+        if self.looping && duration > 0.0 {
+            self.time = self.time.rem_euclid(duration);
+        } else {
+            self.time = self.time.clamp(0.0, duration);
+        }
+    }
+
+    // Overwrites the animated parts of the bound entities' local transforms
+    pub fn apply(&mut self, local: &mut [Transform]) {
+        let mut buffer = Vec::new();
+        for (track, binding) in self.clip.tracks.iter().zip(&self.bindings) {
+            let Some(transform) = binding.and_then(|index| local.get_mut(index)) else {
+                continue;
+            };
+            buffer.clear();
+            buffer.resize(track.components(), 0.0);
+            track.sample(self.time, &mut buffer);
+            match track.property {
+                Property::Translation => transform.translation = Vec3::from_slice(&buffer),
+                Property::Rotation => transform.rotation = Quat::from_slice(&buffer),
+                Property::Scale => transform.scale = Vec3::from_slice(&buffer),
+                Property::Weights => {
+                    self.weights.insert(track.target.clone(), buffer.clone());
+                }
+            }
+        }
+    }
+
+    pub fn weights(&self, entity: &str) -> Option<&[f32]> {
+        self.weights.get(entity).map(Vec::as_slice)
+    }
+
+    // Adds the clip at the current time into `pose`, counting `weight` times
+    // as much as whatever else goes in there. For blending clips together
+    // instead of apply()ing one.
+    pub fn accumulate(&self, pose: &mut Pose, weight: f32) {
+        if weight <= 0.0 {
+            return;
+        }
+        let mut buffer = Vec::new();
+        for (track, binding) in self.clip.tracks.iter().zip(&self.bindings) {
+            let Some(index) = *binding else {
+                continue;
+            };
+            buffer.clear();
+            buffer.resize(track.components(), 0.0);
+            track.sample(self.time, &mut buffer);
+            if track.property == Property::Weights {
+                let (sum, total) = pose.weights.entry(track.target.clone()).or_default();
+                sum.resize(sum.len().max(buffer.len()), 0.0);
+                for (sum, value) in sum.iter_mut().zip(&buffer) {
+                    *sum += value * weight;
+                }
+                *total += weight;
+                continue;
+            }
+            if pose.entities.len() <= index {
+                pose.entities.resize(index + 1, Blended::default());
+            }
+            let blended = &mut pose.entities[index];
+            match track.property {
+                Property::Translation => {
+                    blended.translation += Vec3::from_slice(&buffer) * weight;
+                    blended.totals[0] += weight;
+                }
+                Property::Rotation => {
+                    // q and -q are the same rotation, keep them all on one
+                    // side so they don't cancel out
+                    let rotation = Vec4::from_slice(&buffer);
+                    let sign = if blended.rotation.dot(rotation) < 0.0 { -1.0 } else { 1.0 };
+                    blended.rotation += rotation * sign * weight;
+                    blended.totals[1] += weight;
+                }
+                Property::Scale => {
+                    blended.scale += Vec3::from_slice(&buffer) * weight;
+                    blended.totals[2] += weight;
+                }
+                Property::Weights => {}
+            }
+        }
+    }
+}
+
+// Sums of weighted transforms for one entity, and the weights that went into
+// each part
+#[derive(Clone, Copy, Debug, Default)]
+struct Blended {
+    translation: Vec3,
+    rotation: Vec4,
+    scale: Vec3,
+    totals: [f32; 3],
+}
+
+// Clips mixed together with AnimationPlayer::accumulate(). Every part of
+// every entity is the weighted average of the clips that animate it, parts
+// none of them animate are left as they were.
+#[derive(Clone, Debug, Default)]
+pub struct Pose {
+    // By entity index, like bind() matched them
+    entities: Vec<Blended>,
+    // Morph target weights by entity name
+    weights: HashMap<String, (Vec<f32>, f32)>,
+}
+
+impl Pose {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Empty again, keeping the memory for the next frame's blend
+    pub fn clear(&mut self) {
+        self.entities.iter_mut().for_each(|blended| *blended = Blended::default());
+        self.weights.clear();
+    }
+
+    // Overwrites the animated parts of the entities' local transforms
+    pub fn apply(&self, local: &mut [Transform]) {
+        for (blended, transform) in self.entities.iter().zip(local) {
+            let [translation, rotation, scale] = blended.totals;
+            if translation > 0.0 {
+                transform.translation = blended.translation / translation;
+            }
+            if rotation > 0.0 && blended.rotation.length_squared() > 0.0 {
+                transform.rotation = Quat::from_vec4(blended.rotation).normalize();
+            }
+            if scale > 0.0 {
+                transform.scale = blended.scale / scale;
+            }
+        }
+    }
+
+    pub fn weights(&self, entity: &str) -> Option<Vec<f32>> {
+        let (sum, total) = self.weights.get(entity)?;
+        Some(sum.iter().map(|value| value / total).collect())
+    }
+}
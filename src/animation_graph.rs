@@ -0,0 +1,402 @@
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::animation::{AnimationClip, AnimationPlayer, Pose};
+use crate::scene::SceneEntity;
+use crate::transform::Transform;
+
+// What a state plays, by clip name
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Motion {
+    Clip(String),
+    // Clips placed along a parameter, blending the two either side of its
+    // value, e.g. idle at 0, walk at 2 and run at 6 by speed. The clips are
+    // kept in step, so feet land together however they're mixed.
+    Blend { parameter: String, clips: Vec<(f32, String)> },
+}
+
+impl Motion {
+    pub fn clip(name: &str) -> Self {
+        Self::Clip(name.to_string())
+    }
+
+    pub fn blend(parameter: &str, clips: &[(f32, &str)]) -> Self {
+        Self::Blend {
+            parameter: parameter.to_string(),
+            clips: clips.iter().map(|(at, name)| (*at, name.to_string())).collect(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AnimationState {
+    pub name: String,
+    pub motion: Motion,
+    #[serde(default = "one")]
+    pub speed: f32,
+    #[serde(default = "yes")]
+    pub looping: bool,
+}
+
+fn one() -> f32 {
+    1.0
+}
+
+fn yes() -> bool {
+    true
+}
+
+impl AnimationState {
+    pub fn new(name: &str, motion: Motion) -> Self {
+        Self {
+            name: name.to_string(),
+            motion,
+            speed: 1.0,
+            looping: true,
+        }
+    }
+
+    pub fn with_speed(mut self, speed: f32) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    pub fn with_looping(mut self, looping: bool) -> Self {
+        self.looping = looping;
+        self
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Condition {
+    // A parameter above or below a value
+    Greater(String, f32),
+    Less(String, f32),
+    // Set by Animator::trigger(), used up by the transition it sets off
+    Trigger(String),
+    // The state played to its end, or round once when it loops
+    Finished,
+}
+
+// Crossfades from one state to another over `duration` seconds once all of
+// its conditions hold. No conditions at all means as soon as possible, which
+// with Finished is how one-off states go back to where they came from.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Transition {
+    // None goes from any state
+    pub from: Option<String>,
+    pub to: String,
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    #[serde(default)]
+    pub duration: f32,
+}
+
+impl Transition {
+    pub fn new(from: &str, to: &str, duration: f32) -> Self {
+        Self {
+            from: Some(from.to_string()),
+            to: to.to_string(),
+            conditions: Vec::new(),
+            duration,
+        }
+    }
+
+    pub fn from_any(to: &str, duration: f32) -> Self {
+        Self {
+            from: None,
+            ..Self::new("", to, duration)
+        }
+    }
+
+    pub fn with_condition(mut self, condition: Condition) -> Self {
+        self.conditions.push(condition);
+        self
+    }
+}
+
+// States to play clips in and the transitions between them, with no clips
+// or entities of its own, so one graph can drive any number of characters
+// that share clip names. The first state is where it starts.
+//
+//     let moving = Motion::blend("speed", &[(0.0, "idle"), (2.0, "walk"), (6.0, "run")]);
+//     let graph = AnimationGraph::new()
+//         .with_state(AnimationState::new("move", moving))
+//         .with_state(AnimationState::new("jump", Motion::clip("jump")).with_looping(false))
+//         .with_transition(Transition::from_any("jump", 0.1).with_condition(Condition::Trigger("jump".into())))
+//         .with_transition(Transition::new("jump", "move", 0.25).with_condition(Condition::Finished));
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AnimationGraph {
+    pub states: Vec<AnimationState>,
+    #[serde(default)]
+    pub transitions: Vec<Transition>,
+}
+
+impl AnimationGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_state(mut self, state: AnimationState) -> Self {
+        self.states.push(state);
+        self
+    }
+
+    pub fn with_transition(mut self, transition: Transition) -> Self {
+        self.transitions.push(transition);
+        self
+    }
+}
+
+// A state's clips, each with a player bound to the entities, and where each
+// sits along the blend parameter
+struct StateClips {
+    players: Vec<(f32, AnimationPlayer)>,
+    parameter: Option<String>,
+}
+
+// A state that's playing, fading in or out
+#[derive(Clone, Copy, Debug)]
+struct Running {
+    state: usize,
+    // How far through the state's clips, 0 to 1
+    phase: f32,
+    finished: bool,
+}
+
+struct Fade {
+    from: Running,
+    elapsed: f32,
+    duration: f32,
+}
+
+// Runs an AnimationGraph on a set of scene entities. Set parameters and fire
+// triggers, then every frame:
+//
+//     animator.set_parameter("speed", velocity.length());
+//     animator.update(dt);
+//     animator.apply(&mut local_transforms);
+pub struct Animator {
+    graph: AnimationGraph,
+    clips: Vec<StateClips>,
+    parameters: HashMap<String, f32>,
+    triggers: HashSet<String>,
+    current: Running,
+    fade: Option<Fade>,
+    pose: Pose,
+}
+
+impl Animator {
+    // Errors when a state plays a clip that isn't in `clips`, blends none at
+    // all, or a transition names a state the graph doesn't have
+    pub fn new(graph: AnimationGraph, clips: &[Rc<AnimationClip>], entities: &[SceneEntity]) -> Result<Self> {
+        if graph.states.is_empty() {
+            bail!("animation graph has no states");
+        }
+        let find_clip = |name: &str| match clips.iter().find(|clip| clip.name == name) {
+            Some(clip) => {
+                let mut player = AnimationPlayer::new(clip.clone());
+                player.bind(entities);
+                player.playing = false;
+                Ok(player)
+            }
+            None => bail!("no animation clip called {}", name),
+        };
+        let mut state_clips = Vec::new();
+        for state in &graph.states {
+            state_clips.push(match &state.motion {
+                Motion::Clip(name) => StateClips {
+                    players: vec![(0.0, find_clip(name)?)],
+                    parameter: None,
+                },
+                Motion::Blend { clips, .. } if clips.is_empty() => bail!("{} blends no clips", state.name),
+                Motion::Blend { parameter, clips } => {
+                    let mut players = Vec::new();
+                    for (at, name) in clips {
+                        players.push((*at, find_clip(name)?));
+                    }
+                    players.sort_by(|a, b| a.0.total_cmp(&b.0));
+                    StateClips {
+                        players,
+                        parameter: Some(parameter.clone()),
+                    }
+                }
+            });
+        }
+        for transition in &graph.transitions {
+            for name in transition.from.iter().chain([&transition.to]) {
+                if !graph.states.iter().any(|state| state.name == *name) {
+                    bail!("a transition goes to or from {}, which isn't a state", name);
+                }
+            }
+        }
+        Ok(Self {
+            graph,
+            clips: state_clips,
+            parameters: HashMap::new(),
+            triggers: HashSet::new(),
+            current: Running {
+                state: 0,
+                phase: 0.0,
+                finished: false,
+            },
+            fade: None,
+            pose: Pose::new(),
+        })
+    }
+
+    pub fn set_parameter(&mut self, name: &str, value: f32) {
+        self.parameters.insert(name.to_string(), value);
+    }
+
+    // 0 for parameters that were never set
+    pub fn parameter(&self, name: &str) -> f32 {
+        self.parameters.get(name).copied().unwrap_or(0.0)
+    }
+
+    // Sets off the transitions waiting on it next update(). Triggers nothing
+    // was waiting on are forgotten then too.
+    pub fn trigger(&mut self, name: &str) {
+        self.triggers.insert(name.to_string());
+    }
+
+    pub fn current_state(&self) -> &str {
+        &self.graph.states[self.current.state].name
+    }
+
+    // Whether the last state is still fading out
+    pub fn is_transitioning(&self) -> bool {
+        self.fade.is_some()
+    }
+
+    // Crossfades to the state called `name` whatever the transitions say.
+    // Does nothing when there isn't one.
+    pub fn play(&mut self, name: &str, duration: f32) {
+        if let Some(state) = self.graph.states.iter().position(|state| state.name == name) {
+            self.start(state, duration);
+        }
+    }
+
+    fn start(&mut self, state: usize, duration: f32) {
+        let from = self.current;
+        self.current = Running {
+            state,
+            phase: 0.0,
+            finished: false,
+        };
+        // Fading from a fade drops whatever was fading out before
+        self.fade = (duration > 0.0).then_some(Fade {
+            from,
+            elapsed: 0.0,
+            duration,
+        });
+    }
+
+    // Takes the first transition out of the current state that's ready, then
+    // moves every playing state on by `dt` seconds
+    pub fn update(&mut self, dt: f32) {
+        let current = &self.graph.states[self.current.state].name;
+        let ready = self.graph.transitions.iter().find(|transition| {
+            let from = match &transition.from {
+                Some(from) => from == current,
+                // Not into itself, or it'd start over every frame
+                None => transition.to != *current,
+            };
+            from && transition.conditions.iter().all(|condition| self.holds(condition))
+        });
+        if let Some(transition) = ready.cloned() {
+            for condition in &transition.conditions {
+                if let Condition::Trigger(name) = condition {
+                    self.triggers.remove(name);
+                }
+            }
+            let to = self.graph.states.iter().position(|state| state.name == transition.to).expect("checked in new()");
+            self.start(to, transition.duration);
+        }
+        self.triggers.clear();
+
+        self.current = self.advance(self.current, dt);
+        if let Some(mut fade) = self.fade.take() {
+            fade.elapsed += dt;
+            if fade.elapsed < fade.duration {
+                fade.from = self.advance(fade.from, dt);
+                self.fade = Some(fade);
+            }
+        }
+    }
+
+    fn holds(&self, condition: &Condition) -> bool {
+        match condition {
+            Condition::Greater(name, value) => self.parameter(name) > *value,
+            Condition::Less(name, value) => self.parameter(name) < *value,
+            Condition::Trigger(name) => self.triggers.contains(name),
+            Condition::Finished => self.current.finished,
+        }
+    }
+
+    fn advance(&self, mut running: Running, dt: f32) -> Running {
+        let state = &self.graph.states[running.state];
+        let players = &self.clips[running.state].players;
+        let weights = self.blend_weights(running.state);
+        let duration = players.iter().zip(weights).map(|((_, player), weight)| player.clip().duration() * weight);
+        let duration = duration.sum::<f32>();
+        if duration <= 0.0 {
+            running.finished = true;
+            return running;
+        }
+        running.phase += dt * state.speed / duration;
+        if running.phase >= 1.0 {
+            running.finished = true;
+            running.phase = if state.looping { running.phase.fract() } else { 1.0 };
+        }
+        running
+    }
+
+    // How much each of a state's clips counts, from where the blend
+    // parameter is between them
+    fn blend_weights(&self, state: usize) -> Vec<f32> {
+        let clips = &self.clips[state];
+        let value = clips.parameter.as_deref().map_or(0.0, |name| self.parameter(name));
+        let points = clips.players.iter().map(|(at, _)| *at).collect::<Vec<_>>();
+        let mut weights = vec![0.0; points.len()];
+        // The first point past the value, the one before it is the other
+        // half of the blend. Past either end it's all the end clip.
+        match points.partition_point(|at| *at <= value) {
+            0 => weights[0] = 1.0,
+            next if next == points.len() => weights[next - 1] = 1.0,
+            next => {
+                let (a, b) = (points[next - 1], points[next]);
+                let t = (value - a) / (b - a);
+                weights[next - 1] = 1.0 - t;
+                weights[next] = t;
+            }
+        }
+        weights
+    }
+
+    // Overwrites the animated parts of the entities' local transforms with
+    // the current state, mixed with the last one while it's fading out
+    pub fn apply(&mut self, local: &mut [Transform]) {
+        let mut pose = std::mem::take(&mut self.pose);
+        pose.clear();
+        let fade_in = self.fade.as_ref().map_or(1.0, |fade| fade.elapsed / fade.duration);
+        let running = [Some((self.current, fade_in)), self.fade.as_ref().map(|fade| (fade.from, 1.0 - fade_in))];
+        for (running, weight) in running.into_iter().flatten() {
+            let weights = self.blend_weights(running.state);
+            for ((_, player), blend) in self.clips[running.state].players.iter_mut().zip(weights) {
+                player.seek(running.phase * player.clip().duration());
+                player.accumulate(&mut pose, weight * blend);
+            }
+        }
+        pose.apply(local);
+        self.pose = pose;
+    }
+
+    // Morph target weights from the last apply()
+    pub fn weights(&self, entity: &str) -> Option<Vec<f32>> {
+        self.pose.weights(entity)
+    }
+}
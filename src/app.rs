@@ -0,0 +1,483 @@
+use glam::{Mat4, Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::assets::{Assets, Handle};
+use crate::buffer_pool::MeshPool;
+use crate::bounds::Ray;
+use crate::bvh::Bvh;
+#[cfg(feature = "editor")]
+use crate::console::Console;
+use crate::events::EventBus;
+use crate::exposure::{AutoExposure, CameraExposure};
+use crate::bloom::BloomSettings;
+use crate::crt::CrtSettings;
+use crate::fog::FogSettings;
+use crate::outline::OutlineSettings;
+use crate::pixel_art::PixelArtSettings;
+use crate::particles::Particles;
+use crate::pointer::Pointer;
+use crate::render_graph::RenderGraph;
+use crate::scene::SceneCamera;
+use crate::settings::DisplaySettings;
+use crate::shake::CameraShake;
+use crate::shaders::ShaderConstants;
+use crate::screen::Screen;
+use crate::sky::{Lighting, Sky, SkySettings};
+use crate::sprite::{Sprite, SpriteBatch};
+use crate::stereo::StereoSettings;
+use crate::text::{Fonts, GlyphAtlas, RichText, TextLayout, TextStyle};
+use crate::texture::Texture;
+use crate::upload::AppUploader;
+use crate::viewports::ViewportCamera;
+
+// What the main pass does with last frame's contents
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClearMode {
+    Color(wgpu::Color),
+    // Draw on top of the previous frame, for trails and other accumulation
+    // effects. The scene is rendered into a texture that lives across frames
+    // and copied to the surface, since surface contents don't survive present.
+    PreserveLastFrame,
+}
+
+// Part of a render target, either as fractions of its size (which keeps up
+// with resizes) or in physical pixels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rect {
+    Fraction { x: f32, y: f32, width: f32, height: f32 },
+    Pixels { x: u32, y: u32, width: u32, height: u32 },
+}
+
+impl Rect {
+    pub const FULL: Rect = Rect::Fraction { x: 0.0, y: 0.0, width: 1.0, height: 1.0 };
+
+    // x, y, width and height in pixels, clamped to the target
+    pub fn resolve(&self, target: (u32, u32)) -> [u32; 4] {
+        let [x, y, width, height] = match *self {
+            Rect::Fraction { x, y, width, height } => {
+                let (w, h) = (target.0 as f32, target.1 as f32);
+                [x * w, y * h, width * w, height * h].map(|v| v.round().max(0.0) as u32)
+            }
+            Rect::Pixels { x, y, width, height } => [x, y, width, height],
+        };
+        let x = x.min(target.0);
+        let y = y.min(target.1);
+        [x, y, width.min(target.0 - x), height.min(target.1 - y)]
+    }
+
+    // Whether `point`, in physical pixels, is inside the resolved rect
+    pub fn contains(&self, target: (u32, u32), point: Vec2) -> bool {
+        let [x, y, width, height] = self.resolve(target);
+        let (x, y) = (point.x - x as f32, point.y - y as f32);
+        (0.0..width as f32).contains(&x) && (0.0..height as f32).contains(&y)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+    pub rect: Rect,
+    pub min_depth: f32,
+    pub max_depth: f32,
+}
+
+impl Viewport {
+    pub fn new(rect: Rect) -> Self {
+        Self {
+            rect,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }
+    }
+}
+
+// Where in the render target a pass draws. The viewport maps clip space onto
+// a rectangle, the scissor throws away anything outside of one.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PassRegion {
+    pub viewport: Option<Viewport>,
+    pub scissor: Option<Rect>,
+}
+
+impl PassRegion {
+    pub fn apply(&self, render_pass: &mut wgpu::RenderPass, target: (u32, u32)) {
+        if let Some(viewport) = &self.viewport {
+            let [x, y, width, height] = viewport.rect.resolve(target);
+            // wgpu rejects empty viewports, that can happen mid resize
+            if width == 0 || height == 0 {
+                return;
+            }
+            render_pass.set_viewport(x as f32, y as f32, width as f32, height as f32, viewport.min_depth, viewport.max_depth);
+        }
+        if let Some(scissor) = &self.scissor {
+            let [x, y, width, height] = scissor.resolve(target);
+            render_pass.set_scissor_rect(x, y, width, height);
+        }
+    }
+
+    // The area the viewport covers in pixels, all of `target` without one
+    pub fn pixels(&self, target: (u32, u32)) -> [u32; 4] {
+        match &self.viewport {
+            Some(viewport) => viewport.rect.resolve(target),
+            None => [0, 0, target.0, target.1],
+        }
+    }
+
+    // Width over height of the area the viewport covers
+    pub fn aspect(&self, target: (u32, u32)) -> f32 {
+        let [_, _, width, height] = self.pixels(target);
+        width as f32 / height.max(1) as f32
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PassOps {
+    pub clear: ClearMode,
+    // Whether what the pass drew is kept. Only worth turning off when
+    // nothing after the pass looks at the scene target.
+    pub store: bool,
+    pub depth: wgpu::Operations<f32>,
+    pub region: PassRegion,
+}
+
+impl Default for PassOps {
+    fn default() -> Self {
+        Self {
+            clear: ClearMode::Color(wgpu::Color {
+                r: 0.013,
+                g: 0.013,
+                b: 0.013,
+                a: 1.0,
+            }),
+            store: true,
+            depth: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(1.0),
+                store: true,
+            },
+            region: PassRegion::default(),
+        }
+    }
+}
+
+// A camera of its own drawing the scene into a rect of the scene target, see
+// RenderSettings::viewports
+#[derive(Clone, Debug, PartialEq)]
+pub struct SceneViewport {
+    pub rect: Rect,
+    pub camera: SceneCamera,
+}
+
+impl SceneViewport {
+    pub fn new(rect: Rect, camera: SceneCamera) -> Self {
+        Self { rect, camera }
+    }
+
+    // Draws into the rect and nowhere else
+    pub fn region(&self) -> PassRegion {
+        PassRegion {
+            viewport: Some(Viewport::new(self.rect)),
+            scissor: Some(self.rect),
+        }
+    }
+}
+
+// How bright the HDR scene comes out before it's tonemapped
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Exposure {
+    // Multiplies the scene by this
+    Fixed(f32),
+    // Like a camera with its aperture, shutter speed and ISO set by hand.
+    // Meant for scenes lit in physical units, see LightUnits in scene.rs.
+    Manual(CameraExposure),
+    // Follows how bright the scene is over time, like eyes adjusting to the
+    // dark. Needs compute shaders, without them it stays at 1.
+    Auto(AutoExposure),
+}
+
+// The curve that brings the exposed scene into the 0 to 1 the screen shows
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Tonemapping {
+    // Anything brighter than 1 is cut off, how LDR rendering looks
+    #[default]
+    Clamp = 0,
+    Reinhard = 1,
+    // Filmic, keeps highlights and adds some contrast
+    Aces = 2,
+}
+
+// Everything about rendering that apps can change while running
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderSettings {
+    pub main_pass: PassOps,
+    // When false the main pass only clears, for apps that draw everything
+    // themselves
+    pub draw_scene: bool,
+    // Sprites always draw on top of whatever is there
+    pub sprite_pass: PassRegion,
+    // The ground grid and world axes, G toggles it
+    pub grid: bool,
+    // Outlines of the scene's lights and the camera frozen with F4, F3
+    // toggles it
+    pub debug_volumes: bool,
+    // Frame timings as bars in the top left corner, F6 toggles it and F7
+    // freezes it on the current frame
+    pub profiler: bool,
+    // F10 switches between fixed and automatic exposure
+    pub exposure: Exposure,
+    pub tonemapping: Tonemapping,
+    // Noise too faint to see on its own added as the scene is tonemapped,
+    // which hides the banding smooth gradients like the sky and fog get in 8
+    // bits. N toggles it.
+    pub dither: bool,
+    // Volumetric fog with light shafts, added to the scene before it's
+    // tonemapped. F11 toggles it. Needs compute shaders.
+    pub fog: Option<FogSettings>,
+    // Lines along silhouettes and creases everywhere in the scene, for a
+    // drawn look. Added before the fog, so it fades them with distance. O
+    // toggles it.
+    pub outline: Option<OutlineSettings>,
+    // A glow around anything brighter than the threshold, like emissive
+    // materials and the sun. Added before exposure and tonemapping. B
+    // toggles it.
+    pub bloom: Option<BloomSettings>,
+    // A procedural sky behind the scene, lit by its sun. Its sun and
+    // ambient light reach apps through RenderContext::lighting.
+    pub sky: Option<SkySettings>,
+    // Draws the scene at a small fixed resolution and scales it up without
+    // smoothing, for a retro look. Everything in the scene is drawn at that
+    // size, post effects included, while sprites, text and the editor stay
+    // sharp on top. Sprites are sampled nearest by default meanwhile, see
+    // SpriteFilter. P toggles it.
+    pub pixel_art: Option<PixelArtSettings>,
+    // Shows the picture as if on an old CRT screen, with scanlines, a bulge
+    // and a fading afterglow. The last post effect, after tonemapping, and
+    // in pixel art mode every row of pixels gets its own scanline. T toggles
+    // it.
+    pub crt: Option<CrtSettings>,
+    // Split screen and editor layouts. When there are any, the scene is drawn
+    // once into each of their rects from their own camera instead of from
+    // the main one, only with what that camera can see. The rects shouldn't
+    // overlap, they share the depth buffer. Mirrors, fog, outlines and the
+    // grid need the single main camera and are left out meanwhile.
+    //
+    // The camera controller moves the camera of the viewport under the
+    // cursor, and clicks pick through it. Apps steering cameras themselves,
+    // like one per player, just overwrite them every update().
+    pub viewports: Vec<SceneViewport>,
+    // A left and a right eye drawn in one pass with multiview and shown
+    // side by side or as an anaglyph, instead of the main camera and any
+    // viewports. Plain materials only so far, without the sky, mirrors,
+    // fog, the grid or lines. V toggles it. Needs Features::MULTIVIEW.
+    pub stereo: Option<StereoSettings>,
+    // Tints whatever the cursor is over, H toggles it
+    pub hover_highlight: bool,
+    // Bytes of GPU memory streamed scene textures may use, they drop their
+    // biggest mips to stay under it
+    pub texture_budget: u64,
+    // How many samples streamed (mipmapped) textures may take along surfaces
+    // seen at an angle, 1 turns it off. Rounded down to a power of two up to
+    // 16, see texture::anisotropy_clamp().
+    pub max_anisotropy: u8,
+    // How far from the main camera things are still drawn, when it's closer
+    // than the camera's own far plane. Viewports keep theirs.
+    pub draw_distance: Option<f32>,
+    // Shakes the main camera's view after the controller has moved it, see
+    // shake.rs. Apps add_shake() to it for hits and explosions.
+    pub camera_shake: CameraShake,
+    // Resolution, fullscreen and vsync, see settings.rs for saving them
+    // along with which post effects are on
+    pub display: DisplaySettings,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            main_pass: PassOps::default(),
+            draw_scene: true,
+            sprite_pass: PassRegion::default(),
+            grid: false,
+            debug_volumes: false,
+            profiler: false,
+            exposure: Exposure::Fixed(1.0),
+            tonemapping: Tonemapping::Clamp,
+            dither: true,
+            fog: None,
+            outline: None,
+            bloom: None,
+            sky: None,
+            pixel_art: None,
+            crt: None,
+            viewports: Vec::new(),
+            stereo: None,
+            hover_highlight: false,
+            texture_budget: crate::streaming::DEFAULT_BUDGET,
+            max_anisotropy: crate::texture::DEFAULT_ANISOTROPY,
+            draw_distance: None,
+            camera_shake: CameraShake::default(),
+            display: DisplaySettings::default(),
+        }
+    }
+}
+
+impl RenderSettings {
+    // Which of the viewports `point` (in physical pixels) is over on a
+    // `target` sized surface, the last one listed when rects overlap
+    pub fn viewport_at(&self, target: (u32, u32), point: Vec2) -> Option<usize> {
+        self.viewports.iter().rposition(|viewport| viewport.rect.contains(target, point))
+    }
+}
+
+// Handed to App::init(), for subscribing to events and creating pipelines
+pub struct Setup<'a> {
+    pub events: &'a mut EventBus,
+    pub settings: &'a mut RenderSettings,
+    pub device: &'a wgpu::Device,
+    pub queue: &'a wgpu::Queue,
+    pub surface_format: wgpu::TextureFormat,
+    // What the scene is drawn in, for pipelines that draw into it rather
+    // than onto the surface
+    pub hdr_format: wgpu::TextureFormat,
+    // Group 1 of the mesh pipeline, for pipelines that want the camera too
+    pub camera_bind_group_layout: &'a wgpu::BindGroupLayout,
+    // The frame uniform (time, resolution, mouse), see frame.rs
+    pub frame_bind_group_layout: &'a wgpu::BindGroupLayout,
+    // Filled into the `override`s of the built in shaders, and handy for
+    // ShaderConstants::apply() on your own
+    pub shader_constants: &'a mut ShaderConstants,
+    // Where every mesh lives, add_stream() here gives them extra attributes
+    // which the mesh pipeline then binds too
+    pub mesh_pool: &'a mut MeshPool,
+    // The fallback chain Overlay::draw_text() draws with, empty until fonts
+    // are added
+    pub fonts: &'a mut Fonts,
+    // For loading textures, models and sounds. Handles are cheap to keep.
+    pub assets: &'a mut Assets,
+    // Clone it to spawn particle effects from update(), see particles.rs
+    pub particles: &'a Particles,
+    // Commands of your own for the drop-down console
+    #[cfg(feature = "editor")]
+    pub console: &'a mut Console,
+}
+
+// What passes added by App::render() get to work with. The graph already has
+// "surface", "depth" and `scene_target` in it, the main pass has drawn the
+// scene and it's been tonemapped onto the surface.
+#[derive(Clone, Copy)]
+pub struct RenderContext<'g> {
+    pub device: &'g wgpu::Device,
+    pub queue: &'g wgpu::Queue,
+    // For buffers updated every frame, rather than queue.write_buffer()
+    pub uploader: &'g AppUploader<'g>,
+    pub surface_format: wgpu::TextureFormat,
+    // The HDR texture the scene is drawn into, in `hdr_format`. Passes that
+    // write to it still run before tonemapping, since that reads it.
+    pub scene_target: &'static str,
+    pub hdr_format: wgpu::TextureFormat,
+    pub surface_size: (u32, u32),
+    // What the scene is drawn at, smaller than the surface in pixel art mode
+    // and the same otherwise. Transient textures sized TextureSize::Surface
+    // follow it, like `scene_target` and "depth" do.
+    pub scene_size: (u32, u32),
+    // Where the camera is and what it sees, the same as in the camera bind
+    // group, for culling and picking levels of detail
+    pub camera_position: Vec3,
+    pub view_proj: Mat4,
+    pub camera_bind_group: &'g wgpu::BindGroup,
+    // The same for each of RenderSettings::viewports, in order, for passes
+    // that draw into every one of them. Empty when the main camera is used.
+    pub viewports: &'g [ViewportCamera],
+    // Where the mouse points into the world, through whichever camera it's
+    // over. None while it's outside the scene.
+    pub cursor_ray: Option<Ray>,
+    pub frame_bind_group: &'g wgpu::BindGroup,
+    // The world bounds of everything drawn this frame, for picking and
+    // visibility queries. Objects are indices into the instance buffer.
+    pub scene_bvh: &'g Bvh,
+    // The sun and the sky's ambient light, from the sky when it's on and
+    // Lighting::default() otherwise
+    pub lighting: Lighting,
+    // For drawing the sky into passes of your own, like reflections. Some
+    // when RenderSettings::sky is.
+    pub sky: Option<&'g Sky>,
+}
+
+// Handed to App::overlay(), for 2D things drawn on top of everything else
+// in logical pixels, with (0, 0) the top left corner of the window
+pub struct Overlay<'a> {
+    pub(crate) sprites: &'a mut SpriteBatch,
+    pub(crate) fonts: &'a Fonts,
+    pub(crate) atlas: &'a mut GlyphAtlas,
+    pub(crate) screen: Screen,
+    pub(crate) white_texture: Handle<Texture>,
+    pub(crate) pointer: &'a mut Pointer,
+}
+
+impl<'a> Overlay<'a> {
+    // The window's size in logical pixels
+    pub fn size(&self) -> Vec2 {
+        self.screen.logical_size()
+    }
+
+    pub fn sprite(&mut self, sprite: Sprite) {
+        self.sprites.push(sprite);
+    }
+
+    // A rectangle of one colour
+    pub fn rect(&mut self, position: Vec2, size: Vec2, color: [f32; 4]) {
+        self.sprites.push(Sprite::new(self.white_texture, position, size).with_color(color));
+    }
+
+    // `text` with its top left corner at `position`, laid out as
+    // Fonts::layout() does. The layout that comes back says where every line
+    // and glyph went, for hit testing clicks.
+    //
+    //     overlay.draw_text("Score: 10", Vec2::new(8.0, 8.0), TextStyle::new(24.0));
+    //     overlay.draw_text(RichText::markup("[b]Paused[/b]"), position, style.with_align(Align::Center));
+    pub fn draw_text(&mut self, text: impl Into<RichText>, position: Vec2, style: TextStyle) -> TextLayout {
+        let layout = self.fonts.layout(&text.into(), &style);
+        self.draw_layout(&layout, position);
+        layout
+    }
+
+    // Text laid out already, e.g. kept from an earlier frame
+    pub fn draw_layout(&mut self, layout: &TextLayout, position: Vec2) {
+        self.atlas.draw(self.fonts, layout, position, self.screen.scale_factor as f32, self.sprites);
+    }
+
+    // For laying text out without drawing it, to measure it
+    pub fn fonts(&self) -> &Fonts {
+        self.fonts
+    }
+
+    // Where the mouse is and what its left button did this frame
+    pub fn pointer(&self) -> Pointer {
+        *self.pointer
+    }
+
+    // Keeps clicks and scrolling from reaching the scene until the next
+    // frame, for when the mouse is over something drawn here
+    pub fn capture_pointer(&mut self) {
+        self.pointer.captured = true;
+    }
+}
+
+// Hooks for code using the crate. Everything has a default, so an app only
+// implements what it needs.
+pub trait App: 'static {
+    // Called once before the first frame, a good place to subscribe to events
+    fn init(&mut self, _setup: &mut Setup) {}
+
+    // Called every frame before rendering, `dt` is in seconds
+    fn update(&mut self, _settings: &mut RenderSettings, _dt: f32) {}
+
+    // Add passes of your own to the frame. They run after the main pass, in
+    // the order they're added, and before sprites go on top.
+    fn render<'g>(&'g mut self, _graph: &mut RenderGraph<'g>, _context: RenderContext<'g>) {}
+
+    // Text, HUDs and other 2D things, drawn over the scene and every pass
+    // render() added. Called every frame.
+    fn overlay(&mut self, _overlay: &mut Overlay) {}
+}
+
+// The plain renderer, with nothing added
+impl App for () {}
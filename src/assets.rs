@@ -0,0 +1,996 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+
+use crate::bounds::{Aabb, BoundingSphere};
+use crate::buffer_pool::{MeshAllocation, MeshPool, StreamId};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::hot_reload::FileWatcher;
+use crate::mesh::Mesh;
+use crate::particles::ParticleEffect;
+use crate::profiler;
+use crate::simplify;
+use crate::streaming::{self, TextureStreamer};
+use crate::texture::{ColorSpace, SamplerCache, SamplerOptions, Texture, DEFAULT_ANISOTROPY};
+use crate::vertex::VertexLightmapUv;
+use crate::Vertex;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct AssetId(u64);
+
+// A cheap reference to something the asset manager is loading or has loaded
+pub struct Handle<T> {
+    id: AssetId,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+    fn new(id: AssetId) -> Self {
+        Self { id, _marker: PhantomData }
+    }
+
+    pub fn id(&self) -> AssetId {
+        self.id
+    }
+}
+
+// Derives would put bounds on T, which handles don't need
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl<T> std::fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Handle<{}>({})", std::any::type_name::<T>(), self.id.0)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LoadState {
+    Loading,
+    Loaded,
+    Failed(String),
+}
+
+pub struct ModelMesh {
+    pub name: String,
+    pub mesh: MeshAllocation,
+    // Around the vertices in model space, for culling, picking and choosing
+    // a LOD. The LODs fit inside them too.
+    pub aabb: Aabb,
+    pub sphere: BoundingSphere,
+    // Simplified versions for drawing further away, each with about half the
+    // triangles of the one before (see simplify.rs). Empty if the mesh
+    // couldn't be simplified.
+    pub lods: Vec<MeshAllocation>,
+}
+
+pub struct Model {
+    pub meshes: Vec<ModelMesh>,
+    // Around all of the meshes
+    pub aabb: Aabb,
+    pub sphere: BoundingSphere,
+}
+
+impl Model {
+    pub fn new(meshes: Vec<ModelMesh>) -> Self {
+        let aabb = meshes.iter().fold(Aabb::EMPTY, |aabb, mesh| aabb.union(mesh.aabb));
+        let sphere = meshes.iter().map(|mesh| mesh.sphere).reduce(BoundingSphere::union).unwrap_or_default();
+        Self { meshes, aabb, sphere }
+    }
+}
+
+// WGSL source. Turning it into a pipeline is left to whoever owns the
+// pipeline, since that's also where a broken shader has to be dealt with.
+pub struct Shader {
+    pub source: String,
+}
+
+// A sound file as it is on disk, WAV, Ogg Vorbis, FLAC or MP3. It's decoded
+// while it plays, so long music doesn't sit in memory uncompressed.
+pub struct Sound {
+    pub data: Arc<[u8]>,
+}
+
+// Where an asset comes from, kept around so it can be loaded again later
+#[derive(Clone, Debug)]
+pub enum AssetSource {
+    Path(PathBuf),
+    Bytes(&'static [u8]),
+    // Made at runtime by whoever inserted it, there's nothing to load again
+    Generated,
+}
+
+// What Assets::list() says an asset is
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AssetType {
+    Texture,
+    Model,
+    Shader,
+    Sound,
+    ParticleEffect,
+}
+
+// One of the assets Assets::list() goes through
+#[derive(Clone, Debug)]
+pub struct AssetInfo {
+    pub id: AssetId,
+    pub kind: AssetType,
+    pub label: String,
+    pub state: LoadState,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum AssetKind {
+    Texture,
+    StreamedTexture,
+    Model,
+    Shader,
+    Sound,
+    ParticleEffect,
+}
+
+struct CpuMesh {
+    name: String,
+    vertices: Vec<Vertex>,
+    // For the mesh pool's lightmap stream, one per vertex
+    lightmap_coords: Vec<VertexLightmapUv>,
+    indices: Vec<u32>,
+    aabb: Aabb,
+    sphere: BoundingSphere,
+    lods: Vec<(Vec<Vertex>, Vec<u32>)>,
+}
+
+// What the background threads hand back. Only the GPU upload is left to do.
+enum Decoded {
+    Texture(image::DynamicImage),
+    // Every mip, the streamer decides which go to the GPU
+    StreamedTexture(Vec<image::RgbaImage>),
+    Model(Vec<CpuMesh>),
+    Shader(String),
+    Sound(Arc<[u8]>),
+    ParticleEffect(Box<ParticleEffect>),
+}
+
+struct Slot<T> {
+    label: String,
+    source: AssetSource,
+    // Reloads read from here instead of the original source, e.g. the file on
+    // disk that an embedded asset was built from
+    watch_path: Option<PathBuf>,
+    state: LoadState,
+    asset: Option<T>,
+}
+
+impl<T> Slot<T> {
+    fn new(label: String, source: AssetSource) -> Self {
+        Self {
+            label,
+            source,
+            watch_path: None,
+            state: LoadState::Loading,
+            asset: None,
+        }
+    }
+
+    fn reload_source(&self) -> AssetSource {
+        match &self.watch_path {
+            Some(path) => AssetSource::Path(path.clone()),
+            None => self.source.clone(),
+        }
+    }
+
+    fn is_watching(&self, changed: &Path) -> bool {
+        match (&self.watch_path, &self.source) {
+            (Some(path), _) | (None, AssetSource::Path(path)) => {
+                path.canonicalize().is_ok_and(|path| path == changed)
+            }
+            _ => false,
+        }
+    }
+}
+
+// Handle based loading of textures, models, shaders, sounds and particle effects. Files are read and decoded on
+// background threads, update() uploads whatever finished to the GPU. Until
+// then textures and models hand out a placeholder so callers never have to
+// wait on a load.
+pub struct Assets {
+    next_id: u64,
+    textures: HashMap<AssetId, Slot<Texture>>,
+    models: HashMap<AssetId, Slot<Model>>,
+    shaders: HashMap<AssetId, Slot<Shader>>,
+    sounds: HashMap<AssetId, Slot<Sound>>,
+    effects: HashMap<AssetId, Slot<ParticleEffect>>,
+    // Textures loaded with load_streamed_texture(), which the streamer hands
+    // out once they're decoded
+    streamed: HashSet<AssetId>,
+    streamer: TextureStreamer,
+    // How each texture asked to be sampled when it was loaded, and whether
+    // it holds colours or data
+    texture_options: HashMap<AssetId, (SamplerOptions, ColorSpace)>,
+    samplers: SamplerCache,
+    // Caps every texture's SamplerOptions::anisotropy
+    max_anisotropy: u8,
+    // Set when max_anisotropy changed, so textures already on the GPU get
+    // new samplers next update()
+    resample: bool,
+    #[cfg(not(target_arch = "wasm32"))]
+    watcher: Option<FileWatcher>,
+    placeholder_texture: Texture,
+    placeholder_model: Model,
+    // Where models' lightmap coordinates go in the mesh pool
+    lightmap_stream: StreamId,
+    sender: Sender<(AssetId, Result<Decoded>)>,
+    receiver: Receiver<(AssetId, Result<Decoded>)>,
+}
+
+impl Assets {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        mesh_pool: &mut MeshPool,
+        lightmap_stream: StreamId,
+    ) -> Self {
+        // Magenta and black checkers, hard to miss
+        let checker = image::RgbaImage::from_fn(8, 8, |x, y| {
+            if (x + y) % 2 == 0 {
+                image::Rgba([255, 0, 255, 255])
+            } else {
+                image::Rgba([0, 0, 0, 255])
+            }
+        });
+        let placeholder_texture =
+            Texture::from_image(device, queue, &image::DynamicImage::ImageRgba8(checker), Some("placeholder_texture"))
+                .expect("Failed to create placeholder texture");
+
+        let cube = placeholder_cube();
+        let placeholder_model = Model::new(vec![ModelMesh {
+            mesh: allocate(
+                device,
+                queue,
+                mesh_pool,
+                lightmap_stream,
+                &cube.vertices,
+                &cube.lightmap_coords,
+                &cube.indices,
+            ),
+            name: cube.name,
+            aabb: cube.aabb,
+            sphere: cube.sphere,
+            lods: Vec::new(),
+        }]);
+
+        let (sender, receiver) = channel();
+        Self {
+            next_id: 0,
+            textures: HashMap::new(),
+            models: HashMap::new(),
+            shaders: HashMap::new(),
+            sounds: HashMap::new(),
+            effects: HashMap::new(),
+            streamed: HashSet::new(),
+            streamer: TextureStreamer::new(streaming::DEFAULT_BUDGET),
+            texture_options: HashMap::new(),
+            samplers: SamplerCache::new(),
+            max_anisotropy: DEFAULT_ANISOTROPY,
+            resample: false,
+            #[cfg(not(target_arch = "wasm32"))]
+            watcher: None,
+            placeholder_texture,
+            placeholder_model,
+            lightmap_stream,
+            sender,
+            receiver,
+        }
+    }
+
+    fn next_id(&mut self) -> AssetId {
+        self.next_id += 1;
+        AssetId(self.next_id)
+    }
+
+    pub fn load_texture(&mut self, path: impl AsRef<Path>) -> Handle<Texture> {
+        self.load_texture_with(path, SamplerOptions::default(), ColorSpace::Srgb)
+    }
+
+    // load_texture() sampled some other way, e.g. repeating or pixelated, or
+    // holding linear data like a normal map
+    pub fn load_texture_with(
+        &mut self,
+        path: impl AsRef<Path>,
+        sampler: SamplerOptions,
+        color_space: ColorSpace,
+    ) -> Handle<Texture> {
+        let path = path.as_ref().to_path_buf();
+        self.add_texture(path.display().to_string(), AssetSource::Path(path), (sampler, color_space))
+    }
+
+    pub fn load_texture_from_bytes(&mut self, label: &str, bytes: &'static [u8]) -> Handle<Texture> {
+        self.add_texture(label.to_string(), AssetSource::Bytes(bytes), Default::default())
+    }
+
+    // A texture made at runtime rather than loaded, like the text's glyph
+    // atlas. It's there straight away, and writing to it is up to whoever
+    // made it.
+    pub fn insert_texture(&mut self, label: &str, texture: Texture) -> Handle<Texture> {
+        let id = self.next_id();
+        let mut slot = Slot::new(label.to_string(), AssetSource::Generated);
+        slot.asset = Some(texture);
+        slot.state = LoadState::Loaded;
+        self.textures.insert(id, slot);
+        Handle::new(id)
+    }
+
+    fn add_texture(
+        &mut self,
+        label: String,
+        source: AssetSource,
+        options: (SamplerOptions, ColorSpace),
+    ) -> Handle<Texture> {
+        let id = self.next_id();
+        self.spawn(id, AssetKind::Texture, source.clone());
+        self.textures.insert(id, Slot::new(label, source));
+        self.texture_options.insert(id, options);
+        Handle::new(id)
+    }
+
+    // For big textures, like the ones scenes use. Only the smallest mips go
+    // to the GPU at first, bigger ones follow as request_texture() asks for
+    // them and the budget allows (see streaming.rs).
+    pub fn load_streamed_texture(&mut self, path: impl AsRef<Path>) -> Handle<Texture> {
+        self.load_streamed_texture_with(path, SamplerOptions::mipmapped(), ColorSpace::Srgb)
+    }
+
+    pub fn load_streamed_texture_with(
+        &mut self,
+        path: impl AsRef<Path>,
+        sampler: SamplerOptions,
+        color_space: ColorSpace,
+    ) -> Handle<Texture> {
+        let path = path.as_ref().to_path_buf();
+        let id = self.next_id();
+        let source = AssetSource::Path(path.clone());
+        self.spawn(id, AssetKind::StreamedTexture, source.clone());
+        self.textures.insert(id, Slot::new(path.display().to_string(), source));
+        self.streamed.insert(id);
+        self.texture_options.insert(id, (sampler, color_space));
+        Handle::new(id)
+    }
+
+    // The one texture `id` gets sampled with, under max_anisotropy
+    fn sampler(&mut self, device: &wgpu::Device, id: AssetId) -> Arc<wgpu::Sampler> {
+        let (mut options, _) = self.texture_options.get(&id).copied().unwrap_or_default();
+        options.anisotropy = options.anisotropy.min(self.max_anisotropy);
+        self.samplers.get(device, options)
+    }
+
+    fn color_space(&self, id: AssetId) -> ColorSpace {
+        self.texture_options.get(&id).map(|(_, color_space)| *color_space).unwrap_or_default()
+    }
+
+    // The texture covers about `pixels` across on screen this frame. Does
+    // nothing for textures that aren't streamed.
+    pub fn request_texture(&mut self, handle: Handle<Texture>, pixels: f32) {
+        self.streamer.request(handle.id, pixels);
+    }
+
+    // In bytes, how much streamed textures may take up on the GPU together
+    pub fn set_texture_budget(&mut self, budget: u64) {
+        self.streamer.set_budget(budget);
+    }
+
+    // The most anisotropic filtering any texture gets, whatever its
+    // SamplerOptions ask for. See texture::anisotropy_clamp().
+    pub fn set_max_anisotropy(&mut self, anisotropy: u8) {
+        if self.max_anisotropy != anisotropy {
+            self.max_anisotropy = anisotropy;
+            self.resample = true;
+        }
+    }
+
+    // Bytes of streamed textures on the GPU, and the budget
+    pub fn texture_memory(&self) -> (u64, u64) {
+        self.streamer.memory()
+    }
+
+    pub fn load_model(&mut self, path: impl AsRef<Path>) -> Handle<Model> {
+        let path = path.as_ref().to_path_buf();
+        let id = self.next_id();
+        let source = AssetSource::Path(path.clone());
+        self.spawn(id, AssetKind::Model, source.clone());
+        self.models.insert(id, Slot::new(path.display().to_string(), source));
+        Handle::new(id)
+    }
+
+    // Shaders are tiny, so embedded ones are available straight away
+    pub fn add_shader(&mut self, label: &str, source: &'static str) -> Handle<Shader> {
+        let id = self.next_id();
+        let mut slot = Slot::new(label.to_string(), AssetSource::Bytes(source.as_bytes()));
+        slot.asset = Some(Shader {
+            source: source.to_string(),
+        });
+        slot.state = LoadState::Loaded;
+        self.shaders.insert(id, slot);
+        Handle::new(id)
+    }
+
+    pub fn load_shader(&mut self, path: impl AsRef<Path>) -> Handle<Shader> {
+        let path = path.as_ref().to_path_buf();
+        let id = self.next_id();
+        let source = AssetSource::Path(path.clone());
+        self.spawn(id, AssetKind::Shader, source.clone());
+        self.shaders.insert(id, Slot::new(path.display().to_string(), source));
+        Handle::new(id)
+    }
+
+    pub fn load_sound(&mut self, path: impl AsRef<Path>) -> Handle<Sound> {
+        let path = path.as_ref().to_path_buf();
+        self.add_sound(path.display().to_string(), AssetSource::Path(path))
+    }
+
+    pub fn load_sound_from_bytes(&mut self, label: &str, bytes: &'static [u8]) -> Handle<Sound> {
+        self.add_sound(label.to_string(), AssetSource::Bytes(bytes))
+    }
+
+    fn add_sound(&mut self, label: String, source: AssetSource) -> Handle<Sound> {
+        let id = self.next_id();
+        self.spawn(id, AssetKind::Sound, source.clone());
+        self.sounds.insert(id, Slot::new(label, source));
+        Handle::new(id)
+    }
+
+    // A RON file of a particle effect, see particles.rs
+    pub fn load_effect(&mut self, path: impl AsRef<Path>) -> Handle<ParticleEffect> {
+        let path = path.as_ref().to_path_buf();
+        let id = self.next_id();
+        self.spawn(id, AssetKind::ParticleEffect, AssetSource::Path(path.clone()));
+        self.effects.insert(id, Slot::new(path.display().to_string(), AssetSource::Path(path)));
+        Handle::new(id)
+    }
+
+    // An effect made in code. Like an inserted texture it's never reloaded.
+    pub fn insert_effect(&mut self, label: &str, effect: ParticleEffect) -> Handle<ParticleEffect> {
+        let id = self.next_id();
+        let mut slot = Slot::new(label.to_string(), AssetSource::Generated);
+        slot.asset = Some(effect);
+        slot.state = LoadState::Loaded;
+        self.effects.insert(id, slot);
+        Handle::new(id)
+    }
+
+    // Reload the asset from this file when it changes instead of from its
+    // original source. Handy for assets that get embedded with include_bytes!
+    // but should still pick up edits to the file during development.
+    pub fn watch_file(&mut self, id: AssetId, path: impl AsRef<Path>) {
+        let path = Some(path.as_ref().to_path_buf());
+        if let Some(slot) = self.textures.get_mut(&id) {
+            slot.watch_path = path;
+        } else if let Some(slot) = self.models.get_mut(&id) {
+            slot.watch_path = path;
+        } else if let Some(slot) = self.shaders.get_mut(&id) {
+            slot.watch_path = path;
+        } else if let Some(slot) = self.sounds.get_mut(&id) {
+            slot.watch_path = path;
+        } else if let Some(slot) = self.effects.get_mut(&id) {
+            slot.watch_path = path;
+        }
+    }
+
+    // Starts watching a directory, any loaded asset whose file changes in
+    // there gets reloaded automatically from update().
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn watch(&mut self, dir: impl AsRef<Path>) -> Result<()> {
+        self.watcher = Some(FileWatcher::new(dir)?);
+        Ok(())
+    }
+
+    // Loads the asset again from where it originally came from. The current
+    // version stays in use until the new one has been uploaded.
+    pub fn reload(&mut self, id: AssetId) {
+        if let Some(slot) = self.textures.get(&id) {
+            if let AssetSource::Generated = slot.source {
+                return;
+            }
+            let kind = if self.streamed.contains(&id) { AssetKind::StreamedTexture } else { AssetKind::Texture };
+            self.spawn(id, kind, slot.reload_source());
+        } else if let Some(slot) = self.models.get(&id) {
+            self.spawn(id, AssetKind::Model, slot.reload_source());
+        } else if let Some(slot) = self.shaders.get(&id) {
+            self.spawn(id, AssetKind::Shader, slot.reload_source());
+        } else if let Some(slot) = self.sounds.get(&id) {
+            self.spawn(id, AssetKind::Sound, slot.reload_source());
+        } else if let Some(slot) = self.effects.get(&id) {
+            if let AssetSource::Generated = slot.source {
+                return;
+            }
+            self.spawn(id, AssetKind::ParticleEffect, slot.reload_source());
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn reload_changed_files(&mut self) {
+        let changed = match &self.watcher {
+            Some(watcher) => watcher.changed_files(),
+            None => return,
+        };
+
+        for path in changed {
+            let ids = self
+                .textures
+                .iter()
+                .filter(|(_, slot)| slot.is_watching(&path))
+                .map(|(id, _)| *id)
+                .chain(self.models.iter().filter(|(_, slot)| slot.is_watching(&path)).map(|(id, _)| *id))
+                .chain(self.shaders.iter().filter(|(_, slot)| slot.is_watching(&path)).map(|(id, _)| *id))
+                .chain(self.sounds.iter().filter(|(_, slot)| slot.is_watching(&path)).map(|(id, _)| *id))
+                .chain(self.effects.iter().filter(|(_, slot)| slot.is_watching(&path)).map(|(id, _)| *id))
+                .collect::<Vec<_>>();
+            for id in ids {
+                tracing::info!("Reloading {}", path.display());
+                self.reload(id);
+            }
+        }
+    }
+
+    fn spawn(&self, id: AssetId, kind: AssetKind, source: AssetSource) {
+        let sender = self.sender.clone();
+        // Made here so it sits inside whatever span asked for the load
+        let span = match &source {
+            AssetSource::Path(path) => tracing::info_span!("load asset", ?kind, path = %path.display()),
+            AssetSource::Bytes(bytes) => tracing::info_span!("load asset", ?kind, embedded_bytes = bytes.len()),
+            AssetSource::Generated => tracing::info_span!("load asset", ?kind, generated = true),
+        };
+        let job = move || {
+            let _span = span.entered();
+            // The receiver only goes away when the asset manager does
+            let _ = sender.send((id, decode(kind, &source)));
+        };
+
+        // No threads in the browser, decode right away instead
+        #[cfg(not(target_arch = "wasm32"))]
+        std::thread::spawn(job);
+        #[cfg(target_arch = "wasm32")]
+        job();
+    }
+
+    // Uploads everything that finished decoding since the last call. Returns
+    // the assets whose GPU resources changed, so anything that captured the
+    // old ones (bind groups, render bundles) can be rebuilt.
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, mesh_pool: &mut MeshPool) -> Vec<AssetId> {
+        #[cfg(not(target_arch = "wasm32"))]
+        self.reload_changed_files();
+
+        let _scope = profiler::scope("upload assets");
+        let mut changed = Vec::new();
+
+        while let Ok((id, decoded)) = self.receiver.try_recv() {
+            match decoded {
+                Ok(Decoded::Texture(image)) => {
+                    let sampler = self.sampler(device, id);
+                    let color_space = self.color_space(id);
+                    if let Some(slot) = self.textures.get_mut(&id) {
+                        let label = Some(slot.label.as_str());
+                        match Texture::from_image_with_sampler(device, queue, &image, label, sampler, color_space) {
+                            Ok(texture) => {
+                                slot.asset = Some(texture);
+                                slot.state = LoadState::Loaded;
+                                changed.push(id);
+                            }
+                            Err(e) => fail(&mut slot.state, &slot.label, e),
+                        }
+                    }
+                }
+                Ok(Decoded::StreamedTexture(mips)) => {
+                    let sampler = self.sampler(device, id);
+                    let color_space = self.color_space(id);
+                    if let Some(slot) = self.textures.get_mut(&id) {
+                        let texture = self.streamer.insert(device, queue, id, mips, &slot.label, sampler, color_space);
+                        slot.asset = Some(texture);
+                        slot.state = LoadState::Loaded;
+                        changed.push(id);
+                    }
+                }
+                Ok(Decoded::Model(meshes)) => {
+                    if let Some(slot) = self.models.get_mut(&id) {
+                        let meshes = meshes
+                            .into_iter()
+                            .map(|cpu| ModelMesh {
+                                mesh: allocate(
+                                    device,
+                                    queue,
+                                    mesh_pool,
+                                    self.lightmap_stream,
+                                    &cpu.vertices,
+                                    &cpu.lightmap_coords,
+                                    &cpu.indices,
+                                ),
+                                lods: cpu
+                                    .lods
+                                    .iter()
+                                    .map(|(vertices, indices)| {
+                                        // Models only have the one set of
+                                        // texture coordinates to go on
+                                        let lightmap_coords = vertices
+                                            .iter()
+                                            .map(|v| VertexLightmapUv { lightmap_coords: v.tex_coords })
+                                            .collect::<Vec<_>>();
+                                        let stream = self.lightmap_stream;
+                                        allocate(device, queue, mesh_pool, stream, vertices, &lightmap_coords, indices)
+                                    })
+                                    .collect(),
+                                name: cpu.name,
+                                aabb: cpu.aabb,
+                                sphere: cpu.sphere,
+                            })
+                            .collect();
+                        if let Some(old) = slot.asset.replace(Model::new(meshes)) {
+                            for mesh in old.meshes {
+                                mesh_pool.free(mesh.mesh);
+                                for lod in mesh.lods {
+                                    mesh_pool.free(lod);
+                                }
+                            }
+                        }
+                        slot.state = LoadState::Loaded;
+                        changed.push(id);
+                    }
+                }
+                Ok(Decoded::Shader(source)) => {
+                    if let Some(slot) = self.shaders.get_mut(&id) {
+                        slot.asset = Some(Shader { source });
+                        slot.state = LoadState::Loaded;
+                        changed.push(id);
+                    }
+                }
+                Ok(Decoded::Sound(data)) => {
+                    if let Some(slot) = self.sounds.get_mut(&id) {
+                        slot.asset = Some(Sound { data });
+                        slot.state = LoadState::Loaded;
+                        changed.push(id);
+                    }
+                }
+                Ok(Decoded::ParticleEffect(effect)) => {
+                    if let Some(slot) = self.effects.get_mut(&id) {
+                        slot.asset = Some(*effect);
+                        slot.state = LoadState::Loaded;
+                        changed.push(id);
+                    }
+                }
+                Err(e) => {
+                    if let Some(slot) = self.textures.get_mut(&id) {
+                        fail(&mut slot.state, &slot.label, e);
+                    } else if let Some(slot) = self.models.get_mut(&id) {
+                        fail(&mut slot.state, &slot.label, e);
+                    } else if let Some(slot) = self.shaders.get_mut(&id) {
+                        fail(&mut slot.state, &slot.label, e);
+                    } else if let Some(slot) = self.sounds.get_mut(&id) {
+                        fail(&mut slot.state, &slot.label, e);
+                    } else if let Some(slot) = self.effects.get_mut(&id) {
+                        fail(&mut slot.state, &slot.label, e);
+                    }
+                }
+            }
+        }
+
+        for (id, texture) in self.streamer.update(device, queue) {
+            if let Some(slot) = self.textures.get_mut(&id) {
+                slot.asset = Some(texture);
+                changed.push(id);
+            }
+        }
+
+        if std::mem::take(&mut self.resample) {
+            let ids = self.textures.keys().copied().collect::<Vec<_>>();
+            for id in ids {
+                let sampler = self.sampler(device, id);
+                let Some(texture) = self.textures.get_mut(&id).and_then(|slot| slot.asset.as_mut()) else {
+                    continue;
+                };
+                if Arc::ptr_eq(&texture.sampler, &sampler) {
+                    continue;
+                }
+                texture.sampler = sampler.clone();
+                self.streamer.set_sampler(id, sampler);
+                if !changed.contains(&id) {
+                    changed.push(id);
+                }
+            }
+        }
+
+        changed
+    }
+
+    pub fn texture(&self, handle: Handle<Texture>) -> &Texture {
+        self.textures
+            .get(&handle.id)
+            .and_then(|slot| slot.asset.as_ref())
+            .unwrap_or(&self.placeholder_texture)
+    }
+
+    pub fn model(&self, handle: Handle<Model>) -> &Model {
+        self.models
+            .get(&handle.id)
+            .and_then(|slot| slot.asset.as_ref())
+            .unwrap_or(&self.placeholder_model)
+    }
+
+    // A unit cube, also what models are drawn as while they're loading
+    pub fn cube(&self) -> &Model {
+        &self.placeholder_model
+    }
+
+    // Not loaded yet or failed to load. There's no sensible placeholder shader.
+    pub fn shader(&self, handle: Handle<Shader>) -> Option<&Shader> {
+        self.shaders.get(&handle.id).and_then(|slot| slot.asset.as_ref())
+    }
+
+    // Not loaded yet or failed to load, there's no placeholder sound either
+    pub fn sound(&self, handle: Handle<Sound>) -> Option<&Sound> {
+        self.sounds.get(&handle.id).and_then(|slot| slot.asset.as_ref())
+    }
+
+    // Not loaded yet or failed to load. A broken edit keeps the last version
+    // that loaded.
+    pub fn effect(&self, handle: Handle<ParticleEffect>) -> Option<&ParticleEffect> {
+        self.effects.get(&handle.id).and_then(|slot| slot.asset.as_ref())
+    }
+
+    // For changing an effect while it plays, like the editor's effect panel
+    // does. Emitters pick the changes up on their next update.
+    pub fn effect_mut(&mut self, handle: Handle<ParticleEffect>) -> Option<&mut ParticleEffect> {
+        self.effects.get_mut(&handle.id).and_then(|slot| slot.asset.as_mut())
+    }
+
+    // The file an effect was loaded from, or is watched at, for saving edits
+    // back to. None for inserted ones.
+    pub fn effect_path(&self, handle: Handle<ParticleEffect>) -> Option<&Path> {
+        let slot = self.effects.get(&handle.id)?;
+        match (&slot.watch_path, &slot.source) {
+            (Some(path), _) | (None, AssetSource::Path(path)) => Some(path),
+            _ => None,
+        }
+    }
+
+    pub fn load_state(&self, id: AssetId) -> LoadState {
+        self.textures
+            .get(&id)
+            .map(|slot| &slot.state)
+            .or_else(|| self.models.get(&id).map(|slot| &slot.state))
+            .or_else(|| self.shaders.get(&id).map(|slot| &slot.state))
+            .or_else(|| self.sounds.get(&id).map(|slot| &slot.state))
+            .or_else(|| self.effects.get(&id).map(|slot| &slot.state))
+            .cloned()
+            .unwrap_or_else(|| LoadState::Failed("unknown asset".to_string()))
+    }
+
+    pub fn is_loaded(&self, id: AssetId) -> bool {
+        self.load_state(id) == LoadState::Loaded
+    }
+
+    // Every asset there's a handle to, textures then models then shaders then
+    // sounds then particle effects, each sorted by label. For showing what's loaded, like the editor's
+    // asset browser does.
+    pub fn list(&self) -> Vec<AssetInfo> {
+        fn infos<T>(kind: AssetType, slots: &HashMap<AssetId, Slot<T>>) -> impl Iterator<Item = AssetInfo> + '_ {
+            slots.iter().map(move |(&id, slot)| AssetInfo {
+                id,
+                kind,
+                label: slot.label.clone(),
+                state: slot.state.clone(),
+            })
+        }
+        let mut list = infos(AssetType::Texture, &self.textures)
+            .chain(infos(AssetType::Model, &self.models))
+            .chain(infos(AssetType::Shader, &self.shaders))
+            .chain(infos(AssetType::Sound, &self.sounds))
+            .chain(infos(AssetType::ParticleEffect, &self.effects))
+            .collect::<Vec<_>>();
+        list.sort_by(|a, b| (a.kind, &a.label, a.id).cmp(&(b.kind, &b.label, b.id)));
+        list
+    }
+
+    // The handle of a texture found through list()
+    pub fn texture_handle(&self, id: AssetId) -> Option<Handle<Texture>> {
+        self.textures.contains_key(&id).then(|| Handle::new(id))
+    }
+
+    // The handle of a particle effect found through list()
+    pub fn effect_handle(&self, id: AssetId) -> Option<Handle<ParticleEffect>> {
+        self.effects.contains_key(&id).then(|| Handle::new(id))
+    }
+
+    // Number of assets still being read or decoded
+    pub fn pending(&self) -> usize {
+        let loading = |state: &LoadState| *state == LoadState::Loading;
+        self.textures.values().filter(|s| loading(&s.state)).count()
+            + self.models.values().filter(|s| loading(&s.state)).count()
+            + self.shaders.values().filter(|s| loading(&s.state)).count()
+            + self.sounds.values().filter(|s| loading(&s.state)).count()
+            + self.effects.values().filter(|s| loading(&s.state)).count()
+    }
+}
+
+fn fail(state: &mut LoadState, label: &str, error: anyhow::Error) {
+    tracing::warn!("Failed to load {}: {:?}", label, error);
+    *state = LoadState::Failed(format!("{:#}", error));
+}
+
+fn decode(kind: AssetKind, source: &AssetSource) -> Result<Decoded> {
+    let _scope = profiler::scope(match kind {
+        AssetKind::Texture => "decode texture",
+        AssetKind::StreamedTexture => "decode streamed texture",
+        AssetKind::Model => "decode model",
+        AssetKind::Shader => "read shader",
+        AssetKind::Sound => "read sound",
+        AssetKind::ParticleEffect => "parse particle effect",
+    });
+    match kind {
+        AssetKind::Texture => {
+            let image = match source {
+                AssetSource::Path(path) => image::open(path).with_context(|| format!("reading {}", path.display()))?,
+                AssetSource::Bytes(bytes) => image::load_from_memory(bytes)?,
+                AssetSource::Generated => bail!("textures made at runtime can't be loaded again"),
+            };
+            Ok(Decoded::Texture(image))
+        }
+        AssetKind::StreamedTexture => {
+            let AssetSource::Path(path) = source else {
+                bail!("streamed textures can only be loaded from files");
+            };
+            let image = image::open(path).with_context(|| format!("reading {}", path.display()))?;
+            Ok(Decoded::StreamedTexture(streaming::generate_mips(image.into_rgba8())))
+        }
+        AssetKind::Model => match source {
+            AssetSource::Path(path) => Ok(Decoded::Model(load_model(path)?)),
+            _ => bail!("models can only be loaded from files"),
+        },
+        AssetKind::Shader => {
+            let source = match source {
+                AssetSource::Path(path) => {
+                    std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?
+                }
+                AssetSource::Bytes(bytes) => String::from_utf8(bytes.to_vec())?,
+                AssetSource::Generated => bail!("shaders can't be made at runtime"),
+            };
+            Ok(Decoded::Shader(source))
+        }
+        AssetKind::Sound => {
+            let data: Arc<[u8]> = match source {
+                AssetSource::Path(path) => {
+                    std::fs::read(path).with_context(|| format!("reading {}", path.display()))?.into()
+                }
+                AssetSource::Bytes(bytes) => (*bytes).into(),
+                AssetSource::Generated => bail!("sounds can't be made at runtime"),
+            };
+            // Caught here rather than the first time it's played
+            #[cfg(all(feature = "audio", not(target_arch = "wasm32")))]
+            crate::audio::check(&data)?;
+            Ok(Decoded::Sound(data))
+        }
+        AssetKind::ParticleEffect => {
+            let AssetSource::Path(path) = source else {
+                bail!("particle effects can only be loaded from files");
+            };
+            let text = std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+            let effect = ParticleEffect::from_ron(&text).with_context(|| format!("parsing {}", path.display()))?;
+            Ok(Decoded::ParticleEffect(Box::new(effect)))
+        }
+    }
+}
+
+// How many simplified versions imported meshes get, and how far (relative to
+// the mesh's size) they may stray from the original
+const LOD_LEVELS: usize = 3;
+const LOD_MAX_ERROR: f32 = 0.02;
+
+// Every mesh in an .obj file with its name, as it is on disk. OBJ only has
+// the one set of texture coordinates, which lightmaps use as well.
+pub fn read_obj(path: &Path) -> Result<Vec<(String, Mesh)>> {
+    let (models, _materials) =
+        tobj::load_obj(path, &tobj::GPU_LOAD_OPTIONS).with_context(|| format!("reading {}", path.display()))?;
+
+    Ok(models
+        .into_iter()
+        .map(|model| {
+            let obj = model.mesh;
+            let mut mesh = Mesh::new(obj.positions.chunks_exact(3).map(|p| [p[0], p[1], p[2]]).collect(), obj.indices);
+            // OBJ has v going up, wgpu has it going down
+            mesh.tex_coords = obj.texcoords.chunks_exact(2).map(|uv| [uv[0], 1.0 - uv[1]]).collect();
+            mesh.normals = obj.normals.chunks_exact(3).map(|n| [n[0], n[1], n[2]]).collect();
+            mesh.colors = obj.vertex_color.chunks_exact(3).map(|c| [c[0], c[1], c[2], 1.0]).collect();
+            mesh.generate_missing();
+            (model.name, mesh)
+        })
+        .collect())
+}
+
+// The meshes in a model file, .gltf and .glb files through gltf.rs and
+// anything else as .obj
+pub fn read_model(path: &Path) -> Result<Vec<(String, Mesh)>> {
+    if is_gltf(path) {
+        crate::gltf::read_gltf(path)
+    } else {
+        read_obj(path)
+    }
+}
+
+fn is_gltf(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("gltf") || ext.eq_ignore_ascii_case("glb"))
+}
+
+// Whether read_model() knows the file's format
+pub fn is_model(path: &Path) -> bool {
+    is_gltf(path) || path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("obj"))
+}
+
+fn load_model(path: &Path) -> Result<Vec<CpuMesh>> {
+    Ok(read_model(path)?
+        .into_iter()
+        .map(|(name, mesh)| {
+            let vertices = mesh.vertices::<Vertex>();
+            // Done here so it happens on the loading thread
+            let lods = simplify::generate_lods(&vertices, &mesh.indices, |v| v.position, LOD_LEVELS, LOD_MAX_ERROR);
+            CpuMesh {
+                name,
+                aabb: mesh.aabb(),
+                sphere: mesh.bounding_sphere(),
+                vertices,
+                lightmap_coords: mesh.vertices(),
+                indices: mesh.indices,
+                lods,
+            }
+        })
+        .collect())
+}
+
+fn placeholder_cube() -> CpuMesh {
+    let mesh = Mesh::cube(1.0);
+    CpuMesh {
+        name: "placeholder_cube".to_string(),
+        aabb: mesh.aabb(),
+        sphere: mesh.bounding_sphere(),
+        vertices: mesh.vertices(),
+        lightmap_coords: mesh.vertices(),
+        indices: mesh.indices,
+        lods: Vec::new(),
+    }
+}
+
+// Puts a mesh in the pool along with its lightmap coordinates
+fn allocate(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    mesh_pool: &mut MeshPool,
+    lightmap_stream: StreamId,
+    vertices: &[Vertex],
+    lightmap_coords: &[VertexLightmapUv],
+    indices: &[u32],
+) -> MeshAllocation {
+    let allocation = mesh_pool.allocate(device, queue, vertices, indices);
+    mesh_pool.write_stream(queue, lightmap_stream, &allocation, lightmap_coords);
+    allocation
+}
@@ -0,0 +1,321 @@
+use std::cell::RefCell;
+use std::io::Cursor;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use anyhow::Result;
+use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink};
+use serde::{Deserialize, Serialize};
+
+use crate::assets::{Assets, Handle, LoadState, Sound};
+use crate::plugin::{Plugin, PluginContext};
+
+// Which volume slider a sound answers to, on top of the master volume
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VolumeGroup {
+    Music,
+    #[default]
+    Effects,
+    Voice,
+    Interface,
+}
+
+impl VolumeGroup {
+    pub const ALL: [Self; 4] = [Self::Music, Self::Effects, Self::Voice, Self::Interface];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SoundOptions {
+    // Times the group's and the master volume
+    pub volume: f32,
+    // 2 plays twice as fast and an octave higher
+    pub speed: f32,
+    pub group: VolumeGroup,
+    // Starts over whenever it ends, until it's stopped
+    pub looping: bool,
+}
+
+impl Default for SoundOptions {
+    fn default() -> Self {
+        Self {
+            volume: 1.0,
+            speed: 1.0,
+            group: VolumeGroup::Effects,
+            looping: false,
+        }
+    }
+}
+
+impl SoundOptions {
+    pub fn with_volume(mut self, volume: f32) -> Self {
+        self.volume = volume;
+        self
+    }
+
+    pub fn with_speed(mut self, speed: f32) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    pub fn with_group(mut self, group: VolumeGroup) -> Self {
+        self.group = group;
+        self
+    }
+
+    pub fn with_looping(mut self, looping: bool) -> Self {
+        self.looping = looping;
+        self
+    }
+}
+
+// One play of a sound, for stopping it or changing its volume later
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SoundId(u64);
+
+struct Playing {
+    id: SoundId,
+    sink: Sink,
+    options: SoundOptions,
+}
+
+struct Mixer {
+    // None when there's no output device, sounds are quietly dropped then
+    output: Option<(OutputStream, OutputStreamHandle)>,
+    master: f32,
+    groups: [f32; VolumeGroup::ALL.len()],
+    next_id: u64,
+    // Waiting on their sound to finish loading
+    queued: Vec<(SoundId, Handle<Sound>, SoundOptions)>,
+    playing: Vec<Playing>,
+    music: Option<SoundId>,
+}
+
+impl Mixer {
+    fn volume(&self, options: &SoundOptions) -> f32 {
+        options.volume * self.groups[options.group.index()] * self.master
+    }
+
+    fn start(&mut self, id: SoundId, sound: &Sound, options: SoundOptions) -> Result<()> {
+        let Some((_, handle)) = &self.output else {
+            return Ok(());
+        };
+        let sink = Sink::try_new(handle)?;
+        let data = Cursor::new(sound.data.clone());
+        if options.looping {
+            sink.append(Decoder::new_looped(data)?);
+        } else {
+            sink.append(Decoder::new(data)?);
+        }
+        sink.set_volume(self.volume(&options));
+        sink.set_speed(options.speed);
+        self.playing.push(Playing { id, sink, options });
+        Ok(())
+    }
+
+    fn stop(&mut self, id: SoundId) {
+        self.queued.retain(|(queued, ..)| *queued != id);
+        // Dropping a sink stops it
+        self.playing.retain(|playing| playing.id != id);
+    }
+
+    fn apply_volumes(&self) {
+        for playing in &self.playing {
+            playing.sink.set_volume(self.volume(&playing.options));
+        }
+    }
+}
+
+// Plays sounds loaded through Assets::load_sound(). Cloning it is cheap and
+// every clone plays through the same speakers, so the app keeps a clone of
+// the one added as a plugin, see main.rs:
+//
+//     let audio = Audio::new();
+//     AppBuilder::new(config, Game::new(audio.clone())).add_plugin(audio)
+//
+// and in init():
+//
+//     self.jump = setup.assets.load_sound("assets/jump.ogg");
+//     self.audio.play_music(setup.assets.load_sound("assets/theme.ogg"));
+//
+// then whenever:
+//
+//     self.audio.play_sound(self.jump);
+//
+// Sounds asked for before they've loaded start as soon as they have, ones
+// that fail to load never do.
+#[derive(Clone)]
+pub struct Audio {
+    mixer: Rc<RefCell<Mixer>>,
+}
+
+impl Default for Audio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Audio {
+    // Plays through the default output device. Without one everything still
+    // works, there's just nothing to hear.
+    pub fn new() -> Self {
+        let output = match OutputStream::try_default() {
+            Ok(output) => Some(output),
+            Err(e) => {
+                tracing::warn!("No audio output, sounds are muted: {}", e);
+                None
+            }
+        };
+        Self {
+            mixer: Rc::new(RefCell::new(Mixer {
+                output,
+                master: 1.0,
+                groups: [1.0; VolumeGroup::ALL.len()],
+                next_id: 0,
+                queued: Vec::new(),
+                playing: Vec::new(),
+                music: None,
+            })),
+        }
+    }
+
+    // Once, in the Effects group
+    pub fn play_sound(&self, sound: Handle<Sound>) -> SoundId {
+        self.play_sound_with(sound, SoundOptions::default())
+    }
+
+    pub fn play_sound_with(&self, sound: Handle<Sound>, options: SoundOptions) -> SoundId {
+        let mut mixer = self.mixer.borrow_mut();
+        mixer.next_id += 1;
+        let id = SoundId(mixer.next_id);
+        mixer.queued.push((id, sound, options));
+        id
+    }
+
+    // Loops in the Music group, instead of whatever music was playing
+    pub fn play_music(&self, sound: Handle<Sound>) -> SoundId {
+        self.stop_music();
+        let options = SoundOptions::default().with_group(VolumeGroup::Music).with_looping(true);
+        let id = self.play_sound_with(sound, options);
+        self.mixer.borrow_mut().music = Some(id);
+        id
+    }
+
+    pub fn stop_music(&self) {
+        let music = self.mixer.borrow_mut().music.take();
+        if let Some(id) = music {
+            self.stop(id);
+        }
+    }
+
+    pub fn stop(&self, id: SoundId) {
+        self.mixer.borrow_mut().stop(id);
+    }
+
+    // Stops every sound and the music
+    pub fn stop_all(&self) {
+        let mut mixer = self.mixer.borrow_mut();
+        mixer.queued.clear();
+        mixer.playing.clear();
+        mixer.music = None;
+    }
+
+    pub fn set_paused(&self, id: SoundId, paused: bool) {
+        let mixer = self.mixer.borrow();
+        if let Some(playing) = mixer.playing.iter().find(|playing| playing.id == id) {
+            if paused {
+                playing.sink.pause();
+            } else {
+                playing.sink.play();
+            }
+        }
+    }
+
+    // Waiting to load counts, paused doesn't
+    pub fn is_playing(&self, id: SoundId) -> bool {
+        let mixer = self.mixer.borrow();
+        mixer.queued.iter().any(|(queued, ..)| *queued == id)
+            || mixer.playing.iter().any(|playing| playing.id == id && !playing.sink.is_paused())
+    }
+
+    // The volume it was played with, before the group's and the master's
+    pub fn set_volume(&self, id: SoundId, volume: f32) {
+        let mut mixer = self.mixer.borrow_mut();
+        for (queued, _, options) in &mut mixer.queued {
+            if *queued == id {
+                options.volume = volume;
+            }
+        }
+        if let Some(index) = mixer.playing.iter().position(|playing| playing.id == id) {
+            mixer.playing[index].options.volume = volume;
+            let volume = mixer.volume(&mixer.playing[index].options);
+            mixer.playing[index].sink.set_volume(volume);
+        }
+    }
+
+    pub fn master_volume(&self) -> f32 {
+        self.mixer.borrow().master
+    }
+
+    pub fn set_master_volume(&self, volume: f32) {
+        let mut mixer = self.mixer.borrow_mut();
+        mixer.master = volume.max(0.0);
+        mixer.apply_volumes();
+    }
+
+    pub fn group_volume(&self, group: VolumeGroup) -> f32 {
+        self.mixer.borrow().groups[group.index()]
+    }
+
+    pub fn set_group_volume(&self, group: VolumeGroup, volume: f32) {
+        let mut mixer = self.mixer.borrow_mut();
+        mixer.groups[group.index()] = volume.max(0.0);
+        mixer.apply_volumes();
+    }
+
+    // Starts sounds whose asset finished loading and forgets ones that
+    // finished playing
+    fn update(&self, assets: &Assets) {
+        let mut mixer = self.mixer.borrow_mut();
+        mixer.playing.retain(|playing| !playing.sink.empty());
+        let queued = std::mem::take(&mut mixer.queued);
+        for (id, handle, options) in queued {
+            match assets.load_state(handle.id()) {
+                LoadState::Loading => mixer.queued.push((id, handle, options)),
+                // Assets::update() logged why
+                LoadState::Failed(_) => {}
+                LoadState::Loaded => {
+                    let Some(sound) = assets.sound(handle) else {
+                        continue;
+                    };
+                    if let Err(e) = mixer.start(id, sound, options) {
+                        tracing::warn!("Failed to play {:?}: {:?}", handle, e);
+                    }
+                }
+            }
+        }
+        // Music only ends when it failed to load
+        if let Some(music) = mixer.music {
+            let queued = mixer.queued.iter().any(|(id, ..)| *id == music);
+            if !queued && !mixer.playing.iter().any(|playing| playing.id == music) {
+                mixer.music = None;
+            }
+        }
+    }
+}
+
+impl Plugin for Audio {
+    fn pre_update(&mut self, context: &mut PluginContext) {
+        self.update(context.assets);
+    }
+}
+
+// Whether rodio can make sense of `data`, for Assets to fail the load if not
+pub(crate) fn check(data: &Arc<[u8]>) -> Result<()> {
+    Decoder::new(Cursor::new(data.clone()))?;
+    Ok(())
+}
@@ -0,0 +1,381 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail, Context, Result};
+use glam::{Vec2, Vec3};
+use rayon::prelude::*;
+
+use crate::bounds::{Aabb, Ray};
+use crate::bvh::Bvh;
+use crate::lightmap::IRRADIANCE_SCALE;
+use crate::mesh::Mesh;
+use crate::noise::hash;
+use crate::scene::{LightmapContents, MaterialRef, MeshRef, Scene, SceneLight};
+use crate::sky::Lighting;
+
+// Bakes the lightmaps of a scene's lightmapped materials on the CPU, one
+// texel at a time, and writes them where the materials look for them:
+//
+//     cargo run --release -- --bake-lightmaps scene.ron --samples 256
+//
+// Every texel casts rays out over the hemisphere above it, more of them
+// straight up than sideways like light arrives (cosine weighted), and counts
+// how many get out. For ambient occlusion that's the whole answer, for
+// irradiance it's how much of the sky's ambient light gets there, plus the
+// sun if nothing's in the way. Light doesn't bounce, so places the sun
+// doesn't reach only get the sky.
+//
+// Every entity in the scene casts shadows, lightmapped or not. Lightmaps are
+// baked with the scene's sun, or the default lighting's without one, and the
+// default ambient light, so irradiance won't follow a sky that changes.
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BakeConfig {
+    pub scene: PathBuf,
+    // Texels along each side of every lightmap
+    pub resolution: u32,
+    // Rays cast from every texel, more is less noisy and slower
+    pub samples: u32,
+    // How far away something still darkens ambient occlusion, in world
+    // units. Irradiance looks all the way out to the sky.
+    pub distance: f32,
+}
+
+impl Default for BakeConfig {
+    fn default() -> Self {
+        Self {
+            scene: PathBuf::from("scene.ron"),
+            resolution: 256,
+            samples: 128,
+            distance: 1.0,
+        }
+    }
+}
+
+impl BakeConfig {
+    // `[scene] [--resolution N] [--samples N] [--distance D]`, whatever comes
+    // after --bake-lightmaps on the command line
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut config = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| anyhow!("{} needs a value", arg));
+            match arg.as_str() {
+                "--resolution" => config.resolution = value()?.parse().context("--resolution")?,
+                "--samples" => config.samples = value()?.parse().context("--samples")?,
+                "--distance" => config.distance = value()?.parse().context("--distance")?,
+                _ if arg.starts_with("--") => bail!("unknown bake option {}", arg),
+                _ => config.scene = arg.into(),
+            }
+        }
+        if config.resolution == 0 {
+            bail!("--resolution must be at least 1");
+        }
+        if config.samples == 0 {
+            bail!("--samples must be at least 1");
+        }
+        Ok(config)
+    }
+}
+
+// Loads the scene in `config`, bakes every lightmap its materials use and
+// saves them, returning where they went
+pub fn bake_lightmaps(config: &BakeConfig) -> Result<Vec<PathBuf>> {
+    let scene = Scene::load(&config.scene)?;
+    let baker = Baker::new(&scene)?;
+    let mut written = Vec::new();
+    for (path, image) in baker.bake(&scene, config)? {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+        }
+        image.save(&path).with_context(|| format!("writing {}", path.display()))?;
+        tracing::info!("Baked {}", path.display());
+        written.push(path);
+    }
+    Ok(written)
+}
+
+// A surface point a texel stands for. Both sides of a surface get drawn
+// but a texel only has room for one, so it's baked for the front, the side
+// the triangle's corners go around counter clockwise from.
+#[derive(Clone, Copy, Debug)]
+struct Texel {
+    x: u32,
+    y: u32,
+    position: Vec3,
+    normal: Vec3,
+}
+
+// A scene's triangles in world space, ready to have rays cast at them
+pub struct Baker {
+    // Every entity's meshes in world space, in the scene's order
+    entities: Vec<Vec<Mesh>>,
+    triangles: Vec<[Vec3; 3]>,
+    bvh: Bvh,
+    lighting: Lighting,
+}
+
+impl Baker {
+    // Reads the models the scene uses from disk
+    pub fn new(scene: &Scene) -> Result<Self> {
+        let mut models: HashMap<PathBuf, Vec<Mesh>> = HashMap::new();
+        let transforms = scene.world_transforms();
+        let mut entities: Vec<Vec<Mesh>> = Vec::with_capacity(scene.entities.len());
+        for (entity, transform) in scene.entities.iter().zip(&transforms) {
+            let meshes = match &entity.mesh {
+                MeshRef::Quad => vec![crate::quad_geometry()],
+                MeshRef::Cube => vec![Mesh::cube(1.0)],
+                MeshRef::Model(path) => {
+                    if !models.contains_key(path) {
+                        let meshes = crate::assets::read_model(path)?.into_iter().map(|(_, mesh)| mesh).collect();
+                        models.insert(path.clone(), meshes);
+                    }
+                    models[path].clone()
+                }
+            };
+            let matrix = transform.matrix();
+            let meshes = meshes
+                .into_iter()
+                .map(|mut mesh| {
+                    for position in &mut mesh.positions {
+                        *position = matrix.transform_point3(Vec3::from(*position)).into();
+                    }
+                    mesh
+                })
+                .collect();
+            entities.push(meshes);
+        }
+
+        let triangles = entities
+            .iter()
+            .flatten()
+            .flat_map(|mesh| {
+                mesh.indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]].map(|i| Vec3::from(mesh.positions[i as usize])))
+            })
+            .collect::<Vec<[Vec3; 3]>>();
+        // Flat triangles have flat boxes, which rays travelling along them
+        // would slip past
+        let bounds = triangles
+            .iter()
+            .map(|&[a, b, c]| {
+                let aabb = Aabb::from_points([a, b, c]);
+                Aabb::new(aabb.min - 1e-4, aabb.max + 1e-4)
+            })
+            .collect::<Vec<_>>();
+
+        // The same sun the renderer lights the scene with
+        let lighting = scene
+            .lights
+            .iter()
+            .find_map(|light| match *light {
+                SceneLight::Directional { direction, .. } => Some(Lighting {
+                    sun_direction: -Vec3::from(direction).normalize_or_zero(),
+                    sun_color: light.radiance(scene.light_units),
+                    ..Lighting::default()
+                }),
+                SceneLight::Point { .. } => None,
+            })
+            .unwrap_or_default();
+
+        Ok(Self {
+            entities,
+            bvh: Bvh::build(&bounds),
+            triangles,
+            lighting,
+        })
+    }
+
+    // How far along `ray` it first hits something, if it does
+    fn cast(&self, ray: &Ray) -> Option<f32> {
+        let hit = self.bvh.cast_ray(ray, |triangle, _| {
+            let [a, b, c] = self.triangles[triangle];
+            ray.intersect_triangle(a, b, c).map(|(distance, _)| distance)
+        });
+        hit.map(|(_, distance)| distance)
+    }
+
+    // Ambient occlusion at `point` on a surface facing `normal`: how much of
+    // the hemisphere above it is open, from 0 (none) to 1 (all of it), with
+    // anything further than `distance` away not counting. Infinity for how
+    // much sky it sees. `seed` picks which rays get cast.
+    pub fn sky_visibility(&self, point: Vec3, normal: Vec3, samples: u32, distance: f32, seed: u32) -> f32 {
+        let normal = normal.normalize();
+        let (tangent, bitangent) = normal.any_orthonormal_pair();
+        let origin = point + normal * RAY_OFFSET;
+        // A different twist of the same set of directions for every texel,
+        // which turns banding into noise
+        let shift = Vec2::new(hash(seed as i32, 0, 0, 0), hash(seed as i32, 0, 1, 0));
+        let open = (0..samples)
+            .filter(|&i| {
+                let uv = (hammersley(i, samples) + shift).fract();
+                // Cosine weighted, points spread evenly over a disc and
+                // lifted up onto the hemisphere
+                let (radius, angle) = (uv.x.sqrt(), uv.y * std::f32::consts::TAU);
+                let (x, y) = (radius * angle.cos(), radius * angle.sin());
+                let direction = tangent * x + bitangent * y + normal * (1.0 - uv.x).max(0.0).sqrt();
+                self.cast(&Ray::new(origin, direction)).is_none_or(|hit| hit > distance)
+            })
+            .count();
+        open as f32 / samples as f32
+    }
+
+    // All the light reaching `point` on a surface facing `normal`, in the
+    // same units as the realtime lighting
+    pub fn irradiance(&self, point: Vec3, normal: Vec3, samples: u32, seed: u32) -> Vec3 {
+        let normal = normal.normalize();
+        let sky = self.sky_visibility(point, normal, samples, f32::INFINITY, seed) * self.lighting.ambient;
+        let facing = normal.dot(self.lighting.sun_direction);
+        if facing <= 0.0 {
+            return sky;
+        }
+        let shadow_ray = Ray::new(point + normal * RAY_OFFSET, self.lighting.sun_direction);
+        let lit = self.cast(&shadow_ray).is_none();
+        sky + self.lighting.sun_color * facing * lit as u32 as f32
+    }
+
+    // Every lightmap the materials of `scene`, the one the baker was made
+    // from, use, as the image to save and where it goes. Entities sharing a
+    // lightmap get baked into the same one, so their lightmap coordinates
+    // mustn't overlap.
+    pub fn bake(&self, scene: &Scene, config: &BakeConfig) -> Result<Vec<(PathBuf, image::RgbaImage)>> {
+        let mut lightmaps: Vec<(PathBuf, LightmapContents, Vec<usize>)> = Vec::new();
+        for (i, entity) in scene.entities.iter().enumerate() {
+            let MaterialRef::Lightmapped(material) = &entity.material else {
+                continue;
+            };
+            match lightmaps.iter_mut().find(|(path, ..)| *path == material.lightmap) {
+                Some((path, contents, _)) if *contents != material.contents => {
+                    bail!("{} is baked with different contents by two materials", path.display())
+                }
+                Some((.., entities)) => entities.push(i),
+                None => lightmaps.push((material.lightmap.clone(), material.contents, vec![i])),
+            }
+        }
+
+        let size = config.resolution;
+        let mut images = Vec::new();
+        for (path, contents, entities) in lightmaps {
+            let texels = entities
+                .iter()
+                .flat_map(|&i| &self.entities[i])
+                .flat_map(|mesh| rasterize(mesh, size))
+                .collect::<Vec<_>>();
+            tracing::info!("Baking {} texels into {}", texels.len(), path.display());
+            let lit = texels
+                .par_iter()
+                .map(|texel| {
+                    let (position, normal, seed) = (texel.position, texel.normal, texel.y * size + texel.x);
+                    match contents {
+                        LightmapContents::AmbientOcclusion => {
+                            Vec3::splat(self.sky_visibility(position, normal, config.samples, config.distance, seed))
+                        }
+                        LightmapContents::Irradiance => {
+                            self.irradiance(position, normal, config.samples, seed) / IRRADIANCE_SCALE
+                        }
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            let mut light = vec![None; (size * size) as usize];
+            for (texel, value) in texels.iter().zip(lit) {
+                light[(texel.y * size + texel.x) as usize] = Some(value);
+            }
+            dilate(&mut light, size);
+            let image = image::RgbaImage::from_fn(size, size, |x, y| {
+                let value = light[(y * size + x) as usize].unwrap_or(Vec3::ZERO);
+                let [r, g, b] = value.to_array().map(|v| (linear_to_srgb(v.clamp(0.0, 1.0)) * 255.0).round() as u8);
+                image::Rgba([r, g, b, 255])
+            });
+            images.push((path, image));
+        }
+        Ok(images)
+    }
+}
+
+// How far off the surface rays start, so they don't hit the triangle they
+// leave from
+const RAY_OFFSET: f32 = 1e-3;
+
+// How many texels out lightmaps get filled in past the edges of their
+// triangles, so filtering and mips near the edges don't pull in black
+const DILATION: u32 = 4;
+
+// The surface points of the texels whose middles `mesh`'s triangles cover
+// in a `size` by `size` lightmap
+fn rasterize(mesh: &Mesh, size: u32) -> Vec<Texel> {
+    let mut texels = Vec::new();
+    for t in mesh.indices.chunks_exact(3) {
+        let [a, b, c] = [t[0], t[1], t[2]].map(|i| i as usize);
+        let [pa, pb, pc] = [a, b, c].map(|i| Vec3::from(mesh.positions[i]));
+        let normal = (pb - pa).cross(pc - pa);
+        if normal.length_squared() == 0.0 {
+            continue;
+        }
+        let [ua, ub, uc] = [a, b, c].map(|i| mesh.lightmap_coord(i) * size as f32);
+        let area = (ub - ua).perp_dot(uc - ua);
+        if area.abs() < 1e-8 {
+            continue;
+        }
+        let min = ua.min(ub).min(uc).floor().max(Vec2::ZERO);
+        let max = ua.max(ub).max(uc).ceil().min(Vec2::splat(size as f32));
+        for y in min.y as u32..max.y as u32 {
+            for x in min.x as u32..max.x as u32 {
+                let p = Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+                // Barycentric weights, all positive inside
+                let wa = (ub - p).perp_dot(uc - p) / area;
+                let wb = (uc - p).perp_dot(ua - p) / area;
+                let wc = 1.0 - wa - wb;
+                if wa < 0.0 || wb < 0.0 || wc < 0.0 {
+                    continue;
+                }
+                let position = pa * wa + pb * wb + pc * wc;
+                texels.push(Texel {
+                    x,
+                    y,
+                    position,
+                    normal: normal.normalize(),
+                });
+            }
+        }
+    }
+    texels
+}
+
+// Grows what's been baked out into the empty texels around it, one ring at
+// a time, each taking the average of its baked neighbours
+fn dilate(light: &mut [Option<Vec3>], size: u32) {
+    for _ in 0..DILATION {
+        let before = light.to_vec();
+        for y in 0..size as i32 {
+            for x in 0..size as i32 {
+                if before[(y as u32 * size + x as u32) as usize].is_some() {
+                    continue;
+                }
+                let neighbours = [(-1, 0), (1, 0), (0, -1), (0, 1), (-1, -1), (1, -1), (-1, 1), (1, 1)]
+                    .into_iter()
+                    .map(|(dx, dy)| (x + dx, y + dy))
+                    .filter(|&(x, y)| x >= 0 && y >= 0 && x < size as i32 && y < size as i32)
+                    .filter_map(|(x, y)| before[(y as u32 * size + x as u32) as usize])
+                    .collect::<Vec<_>>();
+                if !neighbours.is_empty() {
+                    let sum = neighbours.iter().copied().sum::<Vec3>();
+                    light[(y as u32 * size + x as u32) as usize] = Some(sum / neighbours.len() as f32);
+                }
+            }
+        }
+    }
+}
+
+// Point `i` of `count` spread evenly over the unit square
+fn hammersley(i: u32, count: u32) -> Vec2 {
+    Vec2::new((i as f32 + 0.5) / count as f32, i.reverse_bits() as f32 / 4_294_967_296.0)
+}
+
+// Lightmaps are loaded as sRGB, which keeps more detail in the shadows
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
@@ -0,0 +1,199 @@
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Serialize;
+
+use crate::app::App;
+use crate::draw::DrawStats;
+use crate::scene::Scene;
+use crate::State;
+
+// Renders a scene offscreen as fast as it goes and reports how long frames
+// took, for catching performance regressions:
+//
+//     cargo run --release -- --bench scene.ron --frames 500 > before.json
+//
+// Frames are timed from the start of the update to the GPU finishing them,
+// so the numbers include GPU time that a windowed run would hide behind
+// presenting.
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BenchConfig {
+    // The demo scene when None, like a normal run without scene.ron
+    pub scene: Option<PathBuf>,
+    pub frames: u32,
+    // Rendered first and thrown away, while caches and drivers settle
+    pub warmup: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        Self {
+            scene: None,
+            frames: 300,
+            warmup: 30,
+            width: 1280,
+            height: 720,
+        }
+    }
+}
+
+impl BenchConfig {
+    // `[scene] [--frames N] [--warmup N] [--size WIDTHxHEIGHT]`, whatever
+    // comes after --bench on the command line
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut config = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| anyhow!("{} needs a value", arg));
+            match arg.as_str() {
+                "--frames" => config.frames = value()?.parse().context("--frames")?,
+                "--warmup" => config.warmup = value()?.parse().context("--warmup")?,
+                "--size" => {
+                    let size = value()?;
+                    let (width, height) = size
+                        .split_once('x')
+                        .ok_or_else(|| anyhow!("--size should look like 1280x720, not {}", size))?;
+                    config.width = width.parse().context("--size width")?;
+                    config.height = height.parse().context("--size height")?;
+                }
+                _ if arg.starts_with("--") => bail!("unknown bench option {}", arg),
+                _ => config.scene = Some(arg.into()),
+            }
+        }
+        if config.frames == 0 {
+            bail!("--frames must be at least 1");
+        }
+        if config.width == 0 || config.height == 0 {
+            bail!("--size can't be empty");
+        }
+        Ok(config)
+    }
+}
+
+// Milliseconds per frame
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct FrameTimes {
+    pub mean: f64,
+    pub min: f64,
+    pub max: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p95: f64,
+    pub p99: f64,
+}
+
+impl FrameTimes {
+    fn from_samples(mut samples: Vec<f64>) -> Self {
+        if samples.is_empty() {
+            return Self::default();
+        }
+        samples.sort_by(f64::total_cmp);
+        // Nearest rank, so every number is a frame that actually happened
+        let percentile = |p: f64| samples[((samples.len() - 1) as f64 * p / 100.0).round() as usize];
+        Self {
+            mean: samples.iter().sum::<f64>() / samples.len() as f64,
+            min: samples[0],
+            max: samples[samples.len() - 1],
+            p50: percentile(50.0),
+            p90: percentile(90.0),
+            p95: percentile(95.0),
+            p99: percentile(99.0),
+        }
+    }
+}
+
+// What the last benchmarked frame drew
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct SceneStats {
+    pub instances: usize,
+    // Draws recorded once into a render bundle, see StaticBundle
+    pub static_draws: usize,
+    // Draws sorted and batched every frame
+    pub dynamic: DrawStats,
+    pub mesh_pool_used_bytes: u64,
+    pub mesh_pool_capacity_bytes: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BenchReport {
+    pub scene: String,
+    pub adapter: String,
+    pub backend: String,
+    pub width: u32,
+    pub height: u32,
+    pub frames: u32,
+    // From the start of loading to the first frame with everything in it
+    pub load_ms: f64,
+    pub frame_ms: FrameTimes,
+    pub draws: SceneStats,
+}
+
+// How long to wait on assets before benchmarking with whatever did load
+const LOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub async fn bench(config: &BenchConfig) -> Result<BenchReport> {
+    let started = Instant::now();
+    let size = winit::dpi::PhysicalSize::new(config.width, config.height);
+    let mut state = State::headless(size).await.ok_or_else(|| anyhow!("no GPU adapter to render with"))?;
+    if let Some(path) = &config.scene {
+        let scene = Scene::load(path).with_context(|| format!("loading {}", path.display()))?;
+        state.apply_scene(scene);
+    }
+
+    // Timing frames that are still waiting on textures and models would
+    // measure the loading instead
+    while state.assets.pending() > 0 {
+        if started.elapsed() > LOAD_TIMEOUT {
+            tracing::warn!("{} assets still loading, benchmarking without them", state.assets.pending());
+            break;
+        }
+        state.update();
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    let load_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+    let mut app = ();
+    let mut samples = Vec::with_capacity(config.frames as usize);
+    for frame in 0..config.warmup + config.frames {
+        let start = Instant::now();
+        render_frame(&mut state, &mut app)?;
+        if frame >= config.warmup {
+            samples.push(start.elapsed().as_secs_f64() * 1000.0);
+        }
+    }
+
+    let (mesh_pool_used_bytes, mesh_pool_capacity_bytes) = state.mesh_pool.memory();
+    Ok(BenchReport {
+        scene: match &config.scene {
+            Some(path) => path.display().to_string(),
+            None => "demo".to_string(),
+        },
+        adapter: state.adapter_info.name.clone(),
+        backend: format!("{:?}", state.adapter_info.backend),
+        width: config.width,
+        height: config.height,
+        frames: config.frames,
+        load_ms,
+        frame_ms: FrameTimes::from_samples(samples),
+        draws: SceneStats {
+            instances: state.instances.len(),
+            static_draws: state.static_geometry.len(),
+            dynamic: state.draw_list.stats(),
+            mesh_pool_used_bytes,
+            mesh_pool_capacity_bytes,
+        },
+    })
+}
+
+// One iteration of the run loop, waiting for the GPU before returning
+fn render_frame(state: &mut State, app: &mut dyn App) -> Result<()> {
+    state.update();
+    state.render(app, &mut []).map_err(|e| anyhow!("rendering failed: {:?}", e))?;
+    state.device.poll(wgpu::Maintain::Wait);
+    state.profiler.end_frame(&state.device);
+    Ok(())
+}
@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use std::num::NonZeroU64;
+use std::rc::Rc;
+
+// Bind groups that haven't been asked for in this many frames get dropped
+const KEEP_UNUSED_FRAMES: u64 = 60;
+
+// wgpu doesn't give us a way to tell resources apart, so anything that goes
+// into a cached bind group gets an id from the cache. The generation changes
+// whenever the resource is recreated, which makes old bind groups unreachable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ResourceId {
+    index: u32,
+    generation: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum BindingKey {
+    Buffer {
+        id: ResourceId,
+        offset: wgpu::BufferAddress,
+        size: Option<NonZeroU64>,
+    },
+    Other(ResourceId),
+}
+
+impl BindingKey {
+    fn id(&self) -> ResourceId {
+        match *self {
+            BindingKey::Buffer { id, .. } | BindingKey::Other(id) => id,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct BindGroupKey {
+    layout: ResourceId,
+    entries: Vec<(u32, BindingKey)>,
+}
+
+impl BindGroupKey {
+    fn references(&self, index: u32) -> bool {
+        self.layout.index == index || self.entries.iter().any(|(_, key)| key.id().index == index)
+    }
+}
+
+pub struct CachedBinding<'a> {
+    pub binding: u32,
+    pub id: ResourceId,
+    pub resource: wgpu::BindingResource<'a>,
+}
+
+struct CachedGroup {
+    group: Rc<wgpu::BindGroup>,
+    last_used: u64,
+}
+
+#[derive(Default)]
+pub struct BindGroupCache {
+    generations: Vec<u32>,
+    groups: HashMap<BindGroupKey, CachedGroup>,
+    frame: u64,
+}
+
+impl BindGroupCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Hands out an id for a buffer, texture view, sampler or layout
+    pub fn register(&mut self) -> ResourceId {
+        self.generations.push(0);
+        ResourceId {
+            index: self.generations.len() as u32 - 1,
+            generation: 0,
+        }
+    }
+
+    // Call this after recreating the resource behind `id` (e.g. a resized
+    // buffer). Every bind group that used the old one is thrown away and the
+    // returned id should be used from now on.
+    pub fn recreated(&mut self, id: ResourceId) -> ResourceId {
+        let generation = &mut self.generations[id.index as usize];
+        *generation += 1;
+        let id = ResourceId {
+            index: id.index,
+            generation: *generation,
+        };
+        self.groups.retain(|key, _| !key.references(id.index));
+        id
+    }
+
+    fn is_current(&self, id: ResourceId) -> bool {
+        self.generations[id.index as usize] == id.generation
+    }
+
+    pub fn get_or_create(
+        &mut self,
+        device: &wgpu::Device,
+        label: Option<&str>,
+        layout_id: ResourceId,
+        layout: &wgpu::BindGroupLayout,
+        bindings: &[CachedBinding],
+    ) -> Rc<wgpu::BindGroup> {
+        debug_assert!(self.is_current(layout_id), "bind group layout id is stale");
+        debug_assert!(
+            bindings.iter().all(|b| self.is_current(b.id)),
+            "bind group uses a stale resource id, use the id returned by recreated()"
+        );
+
+        let key = BindGroupKey {
+            layout: layout_id,
+            entries: bindings
+                .iter()
+                .map(|b| {
+                    let key = match &b.resource {
+                        wgpu::BindingResource::Buffer(buffer) => BindingKey::Buffer {
+                            id: b.id,
+                            offset: buffer.offset,
+                            size: buffer.size,
+                        },
+                        _ => BindingKey::Other(b.id),
+                    };
+                    (b.binding, key)
+                })
+                .collect(),
+        };
+
+        let frame = self.frame;
+        let cached = self.groups.entry(key).or_insert_with(|| {
+            let entries = bindings
+                .iter()
+                .map(|b| wgpu::BindGroupEntry {
+                    binding: b.binding,
+                    resource: b.resource.clone(),
+                })
+                .collect::<Vec<_>>();
+            let group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label,
+                layout,
+                entries: &entries,
+            });
+            CachedGroup {
+                group: Rc::new(group),
+                last_used: frame,
+            }
+        });
+        cached.last_used = frame;
+        cached.group.clone()
+    }
+
+    // Drops bind groups nobody has asked for in a while
+    pub fn end_frame(&mut self) {
+        let frame = self.frame;
+        self.groups.retain(|_, cached| cached.last_used + KEEP_UNUSED_FRAMES >= frame);
+        self.frame += 1;
+    }
+
+    pub fn len(&self) -> usize {
+        self.groups.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+}
@@ -0,0 +1,348 @@
+use crate::render_graph::{GraphResources, RenderGraph, TextureSize, TransientTexture};
+use crate::upload::Uploader;
+
+// Levels in the chain, each half the size of the one before
+const LEVELS: usize = 5;
+
+// How bloom looks, see RenderSettings::bloom in app.rs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BloomSettings {
+    // Brightness (after everything's been added up, before exposure) where
+    // things start glowing. Above 1 only HDR values like emissive materials
+    // and the sun glow.
+    pub threshold: f32,
+    // How far below the threshold the glow fades in
+    pub knee: f32,
+    // How much of the glow gets added to the scene
+    pub intensity: f32,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self {
+            threshold: 1.0,
+            knee: 0.5,
+            intensity: 0.3,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct BloomUniform {
+    threshold: f32,
+    knee: f32,
+    intensity: f32,
+    _padding: f32,
+}
+
+// Makes bright parts of the HDR scene glow. What's over the threshold gets
+// halved in size a few times over, then added back up from the smallest
+// level to the biggest with a little blur at every step, which spreads it
+// out wide without any big blur kernels. The result goes on top of the
+// scene in a new texture, before exposure and tonemapping.
+//
+// The chain is all render passes, so unlike fog and eye adaptation it
+// works on WebGL too.
+pub struct Bloom {
+    prefilter: wgpu::RenderPipeline,
+    downsample: wgpu::RenderPipeline,
+    upsample: wgpu::RenderPipeline,
+    composite: wgpu::RenderPipeline,
+    chain_layout: wgpu::PipelineLayout,
+    composite_layout: wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    source_layout: wgpu::BindGroupLayout,
+    scene_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Bloom {
+    // Names of the chain's transient textures in the render graph
+    pub const LEVELS: [&'static str; LEVELS] = ["bloom_0", "bloom_1", "bloom_2", "bloom_3", "bloom_4"];
+
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, shader_source: &str) -> Self {
+        let source_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("bloom_source_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let uniform_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("bloom_bind_group_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let scene_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("bloom_scene_bind_group_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    // Read with textureLoad, no sampler involved
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                },
+                count: None,
+            }],
+        });
+        let chain_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Bloom Pipeline Layout"),
+            bind_group_layouts: &[&source_layout, &uniform_layout],
+            push_constant_ranges: &[],
+        });
+        let composite_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Bloom Composite Pipeline Layout"),
+            bind_group_layouts: &[&source_layout, &uniform_layout, &scene_layout],
+            push_constant_ranges: &[],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Bloom Buffer"),
+            size: std::mem::size_of::<BloomUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("bloom_bind_group"),
+            layout: &uniform_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+
+        let [prefilter, downsample, upsample, composite] =
+            Self::create_pipelines(device, &chain_layout, &composite_layout, format, shader_source);
+        Self {
+            prefilter,
+            downsample,
+            upsample,
+            composite,
+            chain_layout,
+            composite_layout,
+            format,
+            source_layout,
+            scene_layout,
+            sampler,
+            buffer,
+            bind_group,
+        }
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        chain_layout: &wgpu::PipelineLayout,
+        composite_layout: &wgpu::PipelineLayout,
+        format: wgpu::TextureFormat,
+        shader_source: &str,
+    ) -> [wgpu::RenderPipeline; 4] {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Bloom Shader"),
+            source: wgpu::ShaderSource::Wgsl(shader_source.into()),
+        });
+        let pipeline = |label, layout, entry_point, blend| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        let additive = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::One,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+        [
+            pipeline("Bloom Prefilter Pipeline", chain_layout, "fs_prefilter", None),
+            pipeline("Bloom Downsample Pipeline", chain_layout, "fs_downsample", None),
+            pipeline(
+                "Bloom Upsample Pipeline",
+                chain_layout,
+                "fs_upsample",
+                Some(wgpu::BlendState {
+                    color: additive,
+                    alpha: additive,
+                }),
+            ),
+            pipeline("Bloom Composite Pipeline", composite_layout, "fs_composite", None),
+        ]
+    }
+
+    // Called when the shader file changed, keeps the old pipelines if the
+    // new source doesn't compile
+    pub fn reload_shader(&mut self, device: &wgpu::Device, shader_source: &str) {
+        if let Some([prefilter, downsample, upsample, composite]) = crate::shaders::try_create(device, || {
+            Self::create_pipelines(device, &self.chain_layout, &self.composite_layout, self.format, shader_source)
+        }) {
+            self.prefilter = prefilter;
+            self.downsample = downsample;
+            self.upsample = upsample;
+            self.composite = composite;
+        }
+    }
+
+    pub fn upload(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        uploader: &mut Uploader,
+        settings: &BloomSettings,
+    ) {
+        let uniform = BloomUniform {
+            threshold: settings.threshold.max(0.0),
+            knee: settings.knee.max(0.0),
+            intensity: settings.intensity.max(0.0),
+            _padding: 0.0,
+        };
+        uploader.write(device, encoder, &self.buffer, 0, &[uniform]);
+    }
+
+    // Adds a "bloom" pass that reads `source` and writes it with the glow
+    // on top into `target`, a texture of the same size. Textures for the
+    // chain get added to the graph too.
+    pub fn add_pass<'g>(
+        &'g self,
+        graph: &mut RenderGraph<'g>,
+        device: &'g wgpu::Device,
+        source: &'static str,
+        target: &'static str,
+    ) {
+        for (level, name) in Self::LEVELS.into_iter().enumerate() {
+            graph.create_texture(
+                name,
+                TransientTexture {
+                    size: TextureSize::Divided(2 << level),
+                    ..TransientTexture::new(self.format)
+                },
+            );
+        }
+        graph.create_texture(target, TransientTexture::new(self.format));
+
+        let mut writes = Self::LEVELS.to_vec();
+        writes.push(target);
+        graph.add_pass("bloom").reads(&[source]).writes(&writes).execute(move |encoder, resources| {
+            let bind_source = |view| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("bloom_source_bind_group"),
+                    layout: &self.source_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Sampler(&self.sampler),
+                        },
+                    ],
+                })
+            };
+
+            // Down the chain, the scene into level 0 and each level into the
+            // next
+            let source_group = bind_source(resources.view(source));
+            self.draw(encoder, resources, Self::LEVELS[0], &self.prefilter, &source_group, None, true);
+            let levels = Self::LEVELS.map(|name| bind_source(resources.view(name)));
+            for level in 1..LEVELS {
+                self.draw(encoder, resources, Self::LEVELS[level], &self.downsample, &levels[level - 1], None, true);
+            }
+            // And back up, each level spread out over the one above it
+            for level in (0..LEVELS - 1).rev() {
+                self.draw(encoder, resources, Self::LEVELS[level], &self.upsample, &levels[level + 1], None, false);
+            }
+
+            let scene = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("bloom_scene_bind_group"),
+                layout: &self.scene_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(resources.view(source)),
+                }],
+            });
+            self.draw(encoder, resources, target, &self.composite, &levels[0], Some(&scene), true);
+        });
+    }
+
+    // One step of the chain, a triangle over all of `target`
+    #[allow(clippy::too_many_arguments)]
+    fn draw(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        resources: &GraphResources,
+        target: &'static str,
+        pipeline: &wgpu::RenderPipeline,
+        source: &wgpu::BindGroup,
+        scene: Option<&wgpu::BindGroup>,
+        clear: bool,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Bloom Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: resources.view(target),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: if clear { wgpu::LoadOp::Clear(wgpu::Color::BLACK) } else { wgpu::LoadOp::Load },
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, source, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        if let Some(scene) = scene {
+            render_pass.set_bind_group(2, scene, &[]);
+        }
+        render_pass.draw(0..3, 0..1);
+    }
+}
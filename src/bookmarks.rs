@@ -0,0 +1,64 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use winit::event::VirtualKeyCode;
+
+use crate::scene::SceneCamera;
+
+// One for each of the number keys
+pub const MAX_BOOKMARKS: usize = 9;
+
+// Camera positions to come back to, handy when looking over the same bits of
+// a model again and again. Ctrl+1 to Ctrl+9 save one, 1 to 9 glide back to
+// it. They're written out as RON whenever one's saved, so they're still
+// there next run.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraBookmarks {
+    slots: [Option<SceneCamera>; MAX_BOOKMARKS],
+}
+
+impl CameraBookmarks {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        Ok(ron::from_str(&text)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        std::fs::write(path, text).with_context(|| format!("writing {}", path.display()))?;
+        Ok(())
+    }
+
+    // `slot` counts from 0, so the 1 key is slot 0
+    pub fn get(&self, slot: usize) -> Option<&SceneCamera> {
+        self.slots.get(slot)?.as_ref()
+    }
+
+    pub fn set(&mut self, slot: usize, camera: SceneCamera) {
+        if let Some(bookmark) = self.slots.get_mut(slot) {
+            *bookmark = Some(camera);
+        }
+    }
+}
+
+// Which slot a number key along the top of the keyboard or on the numpad
+// stands for
+pub fn slot_for_key(key: VirtualKeyCode) -> Option<usize> {
+    use VirtualKeyCode::*;
+    match key {
+        Key1 | Numpad1 => Some(0),
+        Key2 | Numpad2 => Some(1),
+        Key3 | Numpad3 => Some(2),
+        Key4 | Numpad4 => Some(3),
+        Key5 | Numpad5 => Some(4),
+        Key6 | Numpad6 => Some(5),
+        Key7 | Numpad7 => Some(6),
+        Key8 | Numpad8 => Some(7),
+        Key9 | Numpad9 => Some(8),
+        _ => None,
+    }
+}
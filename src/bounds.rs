@@ -0,0 +1,303 @@
+use glam::{Mat4, Vec2, Vec3, Vec4};
+
+// Axis aligned bounding box
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    // Contains nothing, and growing it by anything gives back that thing
+    pub const EMPTY: Self = Self {
+        min: Vec3::splat(f32::INFINITY),
+        max: Vec3::splat(f32::NEG_INFINITY),
+    };
+
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Self {
+        points.into_iter().fold(Self::EMPTY, |aabb, point| aabb.grow(point))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.cmpgt(self.max).any()
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    // From the center to the max corner
+    pub fn half_extents(&self) -> Vec3 {
+        (self.max - self.min) * 0.5
+    }
+
+    pub fn grow(self, point: Vec3) -> Self {
+        Self {
+            min: self.min.min(point),
+            max: self.max.max(point),
+        }
+    }
+
+    pub fn union(self, other: Self) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    pub fn contains(&self, point: Vec3) -> bool {
+        point.cmpge(self.min).all() && point.cmple(self.max).all()
+    }
+
+    pub fn intersects(&self, other: &Self) -> bool {
+        self.min.cmple(other.max).all() && self.max.cmpge(other.min).all()
+    }
+
+    // The box around this one after it's been transformed, which is bigger
+    // than it needs to be if there's any rotation
+    pub fn transformed(&self, transform: Mat4) -> Self {
+        if self.is_empty() {
+            return *self;
+        }
+        // Arvo's method: each column of the matrix moves the center by the
+        // extents along it, in whichever direction is furthest
+        let center = transform.transform_point3(self.center());
+        let half = self.half_extents();
+        let extents = transform.x_axis.truncate().abs() * half.x
+            + transform.y_axis.truncate().abs() * half.y
+            + transform.z_axis.truncate().abs() * half.z;
+        Self::new(center - extents, center + extents)
+    }
+}
+
+impl Default for Aabb {
+    fn default() -> Self {
+        Self::EMPTY
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    pub fn new(center: Vec3, radius: f32) -> Self {
+        Self { center, radius }
+    }
+
+    // Centered on the points' box, which is close enough to the smallest
+    // sphere for culling and much simpler to find
+    pub fn from_points(points: &[Vec3]) -> Self {
+        let center = Aabb::from_points(points.iter().copied()).center();
+        let radius = points.iter().map(|p| p.distance_squared(center)).fold(0.0, f32::max).sqrt();
+        Self { center, radius }
+    }
+
+    // The sphere around both
+    pub fn union(self, other: Self) -> Self {
+        let offset = other.center - self.center;
+        let distance = offset.length();
+        if distance + other.radius <= self.radius {
+            return self;
+        }
+        if distance + self.radius <= other.radius {
+            return other;
+        }
+        let radius = (distance + self.radius + other.radius) * 0.5;
+        let center = self.center + offset * ((radius - self.radius) / distance);
+        Self { center, radius }
+    }
+
+    pub fn contains(&self, point: Vec3) -> bool {
+        point.distance_squared(self.center) <= self.radius * self.radius
+    }
+
+    // Scaled by the transform's largest scale, so it still covers everything
+    // when the scale isn't uniform
+    pub fn transformed(&self, transform: Mat4) -> Self {
+        let scale = transform
+            .x_axis
+            .truncate()
+            .length_squared()
+            .max(transform.y_axis.truncate().length_squared())
+            .max(transform.z_axis.truncate().length_squared())
+            .sqrt();
+        Self {
+            center: transform.transform_point3(self.center),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: Vec3,
+    // Doesn't have to be normalized, distances are in multiples of it
+    pub direction: Vec3,
+}
+
+impl Ray {
+    pub fn new(origin: Vec3, direction: Vec3) -> Self {
+        Self { origin, direction }
+    }
+
+    pub fn at(&self, distance: f32) -> Vec3 {
+        self.origin + self.direction * distance
+    }
+
+    // The same ray in another space, like an object's own. Distances along
+    // it stay the same, so hits can be compared across objects.
+    pub fn transformed(&self, matrix: Mat4) -> Self {
+        Self::new(matrix.transform_point3(self.origin), matrix.transform_vector3(self.direction))
+    }
+
+    // From the camera through `point` on screen, for a camera drawn with
+    // `view_proj` into `viewport`: x, y, width and height in pixels, like
+    // Rect::resolve() gives. Starts on the near plane and reaches the far
+    // one at 1. None when the point is outside the viewport.
+    pub fn from_screen(view_proj: Mat4, viewport: [u32; 4], point: Vec2) -> Option<Self> {
+        let [x, y, width, height] = viewport.map(|v| v as f32);
+        let (x, y) = ((point.x - x) / width, (point.y - y) / height);
+        if !(0.0..=1.0).contains(&x) || !(0.0..=1.0).contains(&y) {
+            return None;
+        }
+        // Screen y goes down, clip space y up
+        let (x, y) = (x * 2.0 - 1.0, 1.0 - y * 2.0);
+        let inverse = view_proj.inverse();
+        let near = inverse.project_point3(Vec3::new(x, y, 0.0));
+        let far = inverse.project_point3(Vec3::new(x, y, 1.0));
+        Some(Self::new(near, far - near))
+    }
+
+    // How far along the ray it enters the box, 0 if it starts inside.
+    // The slab test: clip the ray against each pair of planes in turn.
+    pub fn intersect_aabb(&self, aabb: &Aabb) -> Option<f32> {
+        let inverse = self.direction.recip();
+        let t1 = (aabb.min - self.origin) * inverse;
+        let t2 = (aabb.max - self.origin) * inverse;
+        let near = t1.min(t2).max_element().max(0.0);
+        let far = t1.max(t2).min_element();
+        // NaN from 0 * infinity on a face fails both, which counts as a miss
+        (near <= far).then_some(near)
+    }
+
+    pub fn intersect_sphere(&self, sphere: &BoundingSphere) -> Option<f32> {
+        let offset = self.origin - sphere.center;
+        let a = self.direction.length_squared();
+        let b = offset.dot(self.direction);
+        let c = offset.length_squared() - sphere.radius * sphere.radius;
+        let discriminant = b * b - a * c;
+        if discriminant < 0.0 || a == 0.0 {
+            return None;
+        }
+        let far = (-b + discriminant.sqrt()) / a;
+        (far >= 0.0).then(|| ((-b - discriminant.sqrt()) / a).max(0.0))
+    }
+
+    // How far along the ray it hits the triangle, from either side, and
+    // where on it as weights for `b` and `c` (`a` gets what's left), for
+    // blending whatever the corners carry. Möller and Trumbore's test.
+    pub fn intersect_triangle(&self, a: Vec3, b: Vec3, c: Vec3) -> Option<(f32, Vec2)> {
+        let (ab, ac) = (b - a, c - a);
+        let p = self.direction.cross(ac);
+        let determinant = ab.dot(p);
+        // Parallel to it
+        if determinant.abs() < 1e-12 {
+            return None;
+        }
+        let inverse = determinant.recip();
+        let offset = self.origin - a;
+        let u = offset.dot(p) * inverse;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = offset.cross(ab);
+        let v = self.direction.dot(q) * inverse;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let distance = ac.dot(q) * inverse;
+        (distance >= 0.0).then_some((distance, Vec2::new(u, v)))
+    }
+}
+
+// Where `point` shows up on screen in pixels, for a camera drawn with
+// `view_proj` into `viewport` like Ray::from_screen(). It can be outside the
+// viewport, which is handy for arrows pointing at things off screen. None
+// when it's behind the camera.
+pub fn world_to_screen(view_proj: Mat4, viewport: [u32; 4], point: Vec3) -> Option<Vec2> {
+    let clip = view_proj * point.extend(1.0);
+    if clip.w <= 0.0 {
+        return None;
+    }
+    let ndc = clip.truncate().truncate() / clip.w;
+    let [x, y, width, height] = viewport.map(|v| v as f32);
+    Some(Vec2::new(x + (ndc.x + 1.0) * 0.5 * width, y + (1.0 - ndc.y) * 0.5 * height))
+}
+
+// The six planes around what a camera can see, pointing inwards
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frustum {
+    pub planes: [Vec4; 6],
+}
+
+impl Frustum {
+    // Gribb and Hartmann's method, for wgpu's 0 to 1 depth range
+    pub fn from_view_proj(view_proj: Mat4) -> Self {
+        let rows = [view_proj.row(0), view_proj.row(1), view_proj.row(2), view_proj.row(3)];
+        let planes = [
+            rows[3] + rows[0],
+            rows[3] - rows[0],
+            rows[3] + rows[1],
+            rows[3] - rows[1],
+            rows[2],
+            rows[3] - rows[2],
+        ]
+        .map(|plane| plane / plane.truncate().length());
+        Self { planes }
+    }
+
+    // The part of the camera's view behind a rectangle on screen, `min` and
+    // `max` corners in pixels, for a camera drawn with `view_proj` into
+    // `viewport` like Ray::from_screen(). The rectangle gets stretched over
+    // the whole of clip space, so its edges become the frustum's sides.
+    pub fn from_screen_rect(view_proj: Mat4, viewport: [u32; 4], min: Vec2, max: Vec2) -> Self {
+        let [x, y, width, height] = viewport.map(|v| v as f32);
+        let to_ndc = |point: Vec2| Vec2::new((point.x - x) / width * 2.0 - 1.0, 1.0 - (point.y - y) / height * 2.0);
+        // Screen y goes down, so the top is the min corner
+        let (low, high) = (to_ndc(Vec2::new(min.x, max.y)), to_ndc(Vec2::new(max.x, min.y)));
+        // A rectangle with no area would divide by zero
+        let size = (high - low).max(Vec2::splat(1e-6));
+        let scale = 2.0 / size;
+        let offset = -(low + high) / size;
+        let stretch = Mat4::from_cols(
+            Vec4::new(scale.x, 0.0, 0.0, 0.0),
+            Vec4::new(0.0, scale.y, 0.0, 0.0),
+            Vec4::Z,
+            Vec4::new(offset.x, offset.y, 0.0, 1.0),
+        );
+        Self::from_view_proj(stretch * view_proj)
+    }
+
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.truncate().dot(sphere.center) + plane.w >= -sphere.radius)
+    }
+
+    // Can give false positives near the corners, which is fine for culling
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner furthest along the plane's normal
+            let normal = plane.truncate();
+            let corner = Vec3::select(normal.cmpge(Vec3::ZERO), aabb.max, aabb.min);
+            normal.dot(corner) + plane.w >= 0.0
+        })
+    }
+}
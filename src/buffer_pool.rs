@@ -0,0 +1,358 @@
+use std::ops::Range;
+
+use crate::vertex::VertexLayout;
+
+// First fit allocator over a range of elements. Freed ranges are merged with
+// their neighbours so the free list stays short.
+#[derive(Debug)]
+struct RangeAllocator {
+    size: u32,
+    free: Vec<Range<u32>>,
+}
+
+impl RangeAllocator {
+    fn new(size: u32) -> Self {
+        let mut allocator = Self { size, free: Vec::new() };
+        allocator.free(0..size);
+        allocator
+    }
+
+    fn allocate(&mut self, count: u32) -> Option<Range<u32>> {
+        if count == 0 {
+            return Some(0..0);
+        }
+        let index = self.free.iter().position(|r| r.end - r.start >= count)?;
+        let range = &mut self.free[index];
+        let allocated = range.start..range.start + count;
+        range.start += count;
+        if range.start == range.end {
+            self.free.remove(index);
+        }
+        Some(allocated)
+    }
+
+    fn free(&mut self, range: Range<u32>) {
+        if range.is_empty() {
+            return;
+        }
+        let index = self.free.partition_point(|r| r.start < range.start);
+        self.free.insert(index, range);
+        if index + 1 < self.free.len() && self.free[index].end == self.free[index + 1].start {
+            self.free[index].end = self.free[index + 1].end;
+            self.free.remove(index + 1);
+        }
+        if index > 0 && self.free[index - 1].end == self.free[index].start {
+            self.free[index - 1].end = self.free[index].end;
+            self.free.remove(index);
+        }
+    }
+
+    fn used(&self) -> u32 {
+        self.size - self.free.iter().map(|r| r.end - r.start).sum::<u32>()
+    }
+
+    fn grow(&mut self, size: u32) {
+        let old = self.size;
+        self.size = size;
+        self.free(old..size);
+    }
+}
+
+// Where a mesh ended up inside a MeshPool. Draw it with
+// `draw_indexed(allocation.indices(), allocation.base_vertex, ..)` after
+// binding the index buffer for its index format.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MeshAllocation {
+    pub vertices: Range<u32>,
+    pub first_index: u32,
+    pub index_count: u32,
+    // 16 bit unless the mesh has too many vertices for it
+    pub index_format: wgpu::IndexFormat,
+    // 16 bit indices are allocated in pairs so every write stays 4 byte
+    // aligned
+    index_range: Range<u32>,
+}
+
+impl MeshAllocation {
+    // Which of MeshPool::index_buffers() it's in, which is also the `mesh`
+    // draw commands want
+    pub fn buffers_index(&self) -> u32 {
+        match self.index_format {
+            wgpu::IndexFormat::Uint16 => 0,
+            wgpu::IndexFormat::Uint32 => 1,
+        }
+    }
+
+    pub fn base_vertex(&self) -> i32 {
+        self.vertices.start as i32
+    }
+
+    pub fn indices(&self) -> Range<u32> {
+        self.first_index..self.first_index + self.index_count
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct StreamId(usize);
+
+// An extra per-vertex buffer next to the pool's main one, for data only some
+// pipelines want like baked AO or bone weights. It's laid out like the main
+// buffer, so a mesh's base vertex finds its data in every stream at once.
+struct AttributeStream {
+    stride: wgpu::BufferAddress,
+    attributes: Vec<wgpu::VertexAttribute>,
+    buffer: wgpu::Buffer,
+}
+
+// One index buffer of a MeshPool, for one index format
+struct IndexPool {
+    format: wgpu::IndexFormat,
+    buffer: wgpu::Buffer,
+    allocator: RangeAllocator,
+}
+
+impl IndexPool {
+    fn index_size(&self) -> wgpu::BufferAddress {
+        match self.format {
+            wgpu::IndexFormat::Uint16 => 2,
+            wgpu::IndexFormat::Uint32 => 4,
+        }
+    }
+}
+
+// Packs lots of small meshes into one big vertex buffer and an index buffer
+// per index format. Meshes with few enough vertices get 16 bit indices to
+// save memory, bigger ones 32 bit. Every mesh in the pool shares the same
+// vertex layout, and draws pick their mesh with the base vertex and first
+// index instead of rebinding buffers, which is also what merging draws
+// together later relies on.
+pub struct MeshPool {
+    label: String,
+    vertex_stride: wgpu::BufferAddress,
+    vertex_buffer: wgpu::Buffer,
+    streams: Vec<AttributeStream>,
+    vertex_allocator: RangeAllocator,
+    // 16 and 32 bit, in buffers_index() order
+    index_pools: [IndexPool; 2],
+    // Bumped every time the buffers get recreated, anything holding on to the
+    // old buffers (bind groups, render bundles) needs rebuilding then.
+    generation: u32,
+}
+
+impl MeshPool {
+    pub fn new(
+        device: &wgpu::Device,
+        label: &str,
+        vertex_stride: wgpu::BufferAddress,
+        vertex_capacity: u32,
+        index_capacity: u32,
+    ) -> Self {
+        let index_capacity = index_capacity + index_capacity % 2;
+        let index_pool = |format, capacity: u32, size| IndexPool {
+            format,
+            buffer: Self::create_buffer(device, label, wgpu::BufferUsages::INDEX, capacity as u64 * size),
+            allocator: RangeAllocator::new(capacity),
+        };
+        Self {
+            label: label.to_string(),
+            vertex_stride,
+            vertex_buffer: Self::create_buffer(device, label, wgpu::BufferUsages::VERTEX, vertex_capacity as u64 * vertex_stride),
+            streams: Vec::new(),
+            vertex_allocator: RangeAllocator::new(vertex_capacity),
+            // Most meshes are small, the 32 bit buffer starts out empty
+            index_pools: [
+                index_pool(wgpu::IndexFormat::Uint16, index_capacity, 2),
+                index_pool(wgpu::IndexFormat::Uint32, 0, 4),
+            ],
+            generation: 0,
+        }
+    }
+
+    fn create_buffer(device: &wgpu::Device, label: &str, usage: wgpu::BufferUsages, size: wgpu::BufferAddress) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            // Always at least 4 bytes, wgpu doesn't like binding empty buffers
+            size: size.max(4),
+            usage: usage | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        })
+    }
+
+    pub fn vertex_buffer(&self) -> &wgpu::Buffer {
+        &self.vertex_buffer
+    }
+
+    // Bind one of these per draw, pick it with MeshAllocation::buffers_index()
+    pub fn index_buffers(&self) -> [(&wgpu::Buffer, wgpu::IndexFormat); 2] {
+        self.index_pools.each_ref().map(|pool| (&pool.buffer, pool.format))
+    }
+
+    // Adds a vertex buffer for V's attributes, which pipelines drawing from
+    // the pool bind after the main vertex buffer and the instances. Meshes
+    // start out with zeroes in it until write_stream() fills them in.
+    pub fn add_stream<V: VertexLayout>(&mut self, device: &wgpu::Device) -> StreamId {
+        let layout = V::desc();
+        let size = self.vertex_allocator.size as u64 * layout.array_stride;
+        self.streams.push(AttributeStream {
+            stride: layout.array_stride,
+            attributes: layout.attributes.to_vec(),
+            buffer: Self::create_buffer(device, &self.label, wgpu::BufferUsages::VERTEX, size),
+        });
+        // Pipelines and bundles need to know about the new buffer
+        self.generation += 1;
+        StreamId(self.streams.len() - 1)
+    }
+
+    pub fn write_stream<V: VertexLayout>(&self, queue: &wgpu::Queue, stream: StreamId, allocation: &MeshAllocation, data: &[V]) {
+        let stream = &self.streams[stream.0];
+        assert_eq!(
+            std::mem::size_of::<V>() as wgpu::BufferAddress,
+            stream.stride,
+            "vertex type doesn't match the stream's stride"
+        );
+        assert_eq!(data.len(), allocation.vertices.len(), "one element per vertex");
+        queue.write_buffer(&stream.buffer, allocation.vertices.start as u64 * stream.stride, bytemuck::cast_slice(data));
+    }
+
+    pub fn stream_buffers(&self) -> impl Iterator<Item = &wgpu::Buffer> {
+        self.streams.iter().map(|stream| &stream.buffer)
+    }
+
+    // In the order the streams get bound
+    pub fn stream_layouts(&self) -> Vec<wgpu::VertexBufferLayout<'_>> {
+        self.streams
+            .iter()
+            .map(|stream| wgpu::VertexBufferLayout {
+                array_stride: stream.stride,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: &stream.attributes,
+            })
+            .collect()
+    }
+
+    pub fn stream_count(&self) -> usize {
+        self.streams.len()
+    }
+
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    // Bytes taken up by meshes and bytes the buffers hold, over the vertex
+    // buffer, the streams and both index buffers
+    pub fn memory(&self) -> (u64, u64) {
+        let vertex_stride = self.vertex_stride + self.streams.iter().map(|stream| stream.stride).sum::<u64>();
+        let vertices = &self.vertex_allocator;
+        let mut used = vertices.used() as u64 * vertex_stride;
+        let mut capacity = vertices.size as u64 * vertex_stride;
+        for pool in &self.index_pools {
+            used += pool.allocator.used() as u64 * pool.index_size();
+            capacity += pool.allocator.size as u64 * pool.index_size();
+        }
+        (used, capacity)
+    }
+
+    // Indices are relative to the mesh's first vertex
+    pub fn allocate<V: bytemuck::Pod>(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        vertices: &[V],
+        indices: &[u32],
+    ) -> MeshAllocation {
+        assert_eq!(
+            std::mem::size_of::<V>() as wgpu::BufferAddress,
+            self.vertex_stride,
+            "vertex type doesn't match the pool's vertex stride"
+        );
+
+        let vertex_count = vertices.len() as u32;
+        let index_count = indices.len() as u32;
+        let (pool, padded_index_count) = if vertices.len() <= u16::MAX as usize + 1 {
+            (0, index_count + index_count % 2)
+        } else {
+            (1, index_count)
+        };
+
+        let (vertex_range, index_range) = loop {
+            if let Some(vertex_range) = self.vertex_allocator.allocate(vertex_count) {
+                if let Some(index_range) = self.index_pools[pool].allocator.allocate(padded_index_count) {
+                    break (vertex_range, index_range);
+                }
+                self.vertex_allocator.free(vertex_range);
+            }
+            self.grow(device, queue, vertex_count, pool, padded_index_count);
+        };
+
+        queue.write_buffer(
+            &self.vertex_buffer,
+            vertex_range.start as u64 * self.vertex_stride,
+            bytemuck::cast_slice(vertices),
+        );
+        let index_pool = &self.index_pools[pool];
+        let offset = index_range.start as u64 * index_pool.index_size();
+        match index_pool.format {
+            wgpu::IndexFormat::Uint16 => {
+                let mut narrow = indices.iter().map(|&i| i as u16).collect::<Vec<_>>();
+                narrow.resize(padded_index_count as usize, 0);
+                queue.write_buffer(&index_pool.buffer, offset, bytemuck::cast_slice(&narrow));
+            }
+            wgpu::IndexFormat::Uint32 => queue.write_buffer(&index_pool.buffer, offset, bytemuck::cast_slice(indices)),
+        }
+        // Whatever a freed mesh left behind shouldn't show up in this one
+        for stream in &self.streams {
+            let zeroes = vec![0u8; (vertex_count as u64 * stream.stride) as usize];
+            queue.write_buffer(&stream.buffer, vertex_range.start as u64 * stream.stride, &zeroes);
+        }
+
+        MeshAllocation {
+            vertices: vertex_range,
+            first_index: index_range.start,
+            index_count,
+            index_format: index_pool.format,
+            index_range,
+        }
+    }
+
+    pub fn free(&mut self, allocation: MeshAllocation) {
+        self.vertex_allocator.free(allocation.vertices.clone());
+        self.index_pools[allocation.buffers_index() as usize].allocator.free(allocation.index_range);
+    }
+
+    // Doubles the vertex buffer and the index buffer in use if they're too
+    // small (or more if one mesh needs it) and copies the existing contents
+    // across on the GPU.
+    fn grow(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, vertex_count: u32, pool: usize, index_count: u32) {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Mesh Pool Grow Encoder"),
+        });
+
+        let old_vertex_size = self.vertex_allocator.size;
+        if self.vertex_allocator.free.iter().all(|range| range.end - range.start < vertex_count) {
+            let vertex_size = (old_vertex_size * 2).max(old_vertex_size + vertex_count);
+            let vertex_buffer = Self::create_buffer(device, &self.label, wgpu::BufferUsages::VERTEX, vertex_size as u64 * self.vertex_stride);
+            encoder.copy_buffer_to_buffer(&self.vertex_buffer, 0, &vertex_buffer, 0, old_vertex_size as u64 * self.vertex_stride);
+            self.vertex_buffer = vertex_buffer;
+            for stream in &mut self.streams {
+                let buffer = Self::create_buffer(device, &self.label, wgpu::BufferUsages::VERTEX, vertex_size as u64 * stream.stride);
+                encoder.copy_buffer_to_buffer(&stream.buffer, 0, &buffer, 0, old_vertex_size as u64 * stream.stride);
+                stream.buffer = buffer;
+            }
+            self.vertex_allocator.grow(vertex_size);
+        }
+
+        let index_pool = &mut self.index_pools[pool];
+        let old_index_size = index_pool.allocator.size;
+        if index_pool.allocator.free.iter().all(|range| range.end - range.start < index_count) {
+            let mut index_size = (old_index_size * 2).max(old_index_size + index_count);
+            index_size += index_size % 2;
+            let buffer = Self::create_buffer(device, &self.label, wgpu::BufferUsages::INDEX, index_size as u64 * index_pool.index_size());
+            encoder.copy_buffer_to_buffer(&index_pool.buffer, 0, &buffer, 0, old_index_size as u64 * index_pool.index_size());
+            index_pool.buffer = buffer;
+            index_pool.allocator.grow(index_size);
+        }
+
+        queue.submit(std::iter::once(encoder.finish()));
+        self.generation += 1;
+    }
+}
@@ -0,0 +1,214 @@
+use crate::bounds::{Aabb, Frustum, Ray};
+
+// Bounding volume hierarchy over a list of boxes, so rays and frustums only
+// have to look at the few objects near them instead of all of them. Objects
+// are referred to by their index in the slice the tree was built from.
+//
+// When objects move, refit() updates the boxes without changing the tree.
+// That's much cheaper than building it again, but queries slow down if
+// things end up far from where they started, so rebuild once in a while
+// (or whenever objects are added or removed).
+#[derive(Clone, Debug, Default)]
+pub struct Bvh {
+    // The root is first and children always come after their parent
+    nodes: Vec<Node>,
+    // Object indices, each leaf owns a range of them
+    objects: Vec<u32>,
+    // Each object's box, by object index
+    bounds: Vec<Aabb>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Node {
+    aabb: Aabb,
+    // Leaves: where their objects start. Others: the first of two children,
+    // which sit next to each other.
+    first: u32,
+    // 0 for nodes with children
+    count: u32,
+}
+
+// Splitting any further costs more than checking a few boxes
+const MAX_LEAF_SIZE: usize = 4;
+
+impl Bvh {
+    pub fn build(bounds: &[Aabb]) -> Self {
+        let mut bvh = Self {
+            nodes: Vec::with_capacity(bounds.len().max(1) * 2),
+            objects: (0..bounds.len() as u32).collect(),
+            bounds: bounds.to_vec(),
+        };
+        if bounds.is_empty() {
+            return bvh;
+        }
+        let centers = bounds.iter().map(Aabb::center).collect::<Vec<_>>();
+        bvh.nodes.push(Node {
+            aabb: Aabb::EMPTY,
+            first: 0,
+            count: bounds.len() as u32,
+        });
+        bvh.split(0, &centers);
+        bvh
+    }
+
+    // Median split along whichever axis the centers are most spread out on
+    fn split(&mut self, node: usize, centers: &[glam::Vec3]) {
+        let Node { first, count, .. } = self.nodes[node];
+        let range = first as usize..(first + count) as usize;
+        self.nodes[node].aabb = self.leaf_aabb(first, count);
+        if range.len() <= MAX_LEAF_SIZE {
+            return;
+        }
+
+        let spread = Aabb::from_points(self.objects[range.clone()].iter().map(|&i| centers[i as usize]));
+        let extent = spread.max - spread.min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+        let half = range.len() / 2;
+        self.objects[range.clone()].select_nth_unstable_by(half, |&a, &b| {
+            centers[a as usize][axis].total_cmp(&centers[b as usize][axis])
+        });
+
+        let children = self.nodes.len() as u32;
+        self.nodes.push(Node {
+            aabb: Aabb::EMPTY,
+            first,
+            count: half as u32,
+        });
+        self.nodes.push(Node {
+            aabb: Aabb::EMPTY,
+            first: first + half as u32,
+            count: count - half as u32,
+        });
+        self.nodes[node].first = children;
+        self.nodes[node].count = 0;
+        self.split(children as usize, centers);
+        self.split(children as usize + 1, centers);
+    }
+
+    fn leaf_objects(&self, first: u32, count: u32) -> &[u32] {
+        &self.objects[first as usize..(first + count) as usize]
+    }
+
+    fn leaf_aabb(&self, first: u32, count: u32) -> Aabb {
+        self.leaf_objects(first, count)
+            .iter()
+            .fold(Aabb::EMPTY, |aabb, &object| aabb.union(self.bounds[object as usize]))
+    }
+
+    // Number of objects
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    // Takes the objects' new bounds, in the same order and as many as the
+    // tree was built with
+    pub fn refit(&mut self, bounds: &[Aabb]) {
+        debug_assert_eq!(bounds.len(), self.objects.len(), "refit with a different number of objects");
+        self.bounds.copy_from_slice(bounds);
+        // Backwards, so children are done before their parents
+        for i in (0..self.nodes.len()).rev() {
+            let Node { first, count, .. } = self.nodes[i];
+            self.nodes[i].aabb = if count > 0 {
+                self.leaf_aabb(first, count)
+            } else {
+                self.nodes[first as usize].aabb.union(self.nodes[first as usize + 1].aabb)
+            };
+        }
+    }
+
+    // Refits if the number of objects is the same, builds again otherwise
+    pub fn update(&mut self, bounds: &[Aabb]) {
+        if bounds.len() == self.objects.len() {
+            self.refit(bounds);
+        } else {
+            *self = Self::build(bounds);
+        }
+    }
+
+    // The closest object along the ray and how far away it is. `hit` gets
+    // each object whose box the ray goes through, along with the distance
+    // to the box, and returns where the ray really hits the object if it
+    // does. Use `|_, distance| Some(distance)` to pick by boxes alone.
+    pub fn cast_ray(&self, ray: &Ray, mut hit: impl FnMut(usize, f32) -> Option<f32>) -> Option<(usize, f32)> {
+        let distance = ray.intersect_aabb(&self.nodes.first()?.aabb)?;
+        let mut closest: Option<(usize, f32)> = None;
+        let mut stack = vec![(0, distance)];
+        while let Some((node, distance)) = stack.pop() {
+            // Something closer was found since this was pushed
+            if closest.is_some_and(|(_, closest)| closest < distance) {
+                continue;
+            }
+            let Node { first, count, .. } = self.nodes[node];
+            if count > 0 {
+                for &object in self.leaf_objects(first, count) {
+                    let Some(t) = ray.intersect_aabb(&self.bounds[object as usize]) else {
+                        continue;
+                    };
+                    if let Some(t) = hit(object as usize, t) {
+                        if closest.is_none_or(|(_, closest)| t < closest) {
+                            closest = Some((object as usize, t));
+                        }
+                    }
+                }
+                continue;
+            }
+            // The nearer child goes on top so it's looked at first
+            let left = first as usize;
+            let mut children = [left, left + 1]
+                .map(|child| (child, ray.intersect_aabb(&self.nodes[child].aabb)));
+            if let [(_, Some(a)), (_, Some(b))] = children {
+                if a < b {
+                    children.swap(0, 1);
+                }
+            }
+            for (child, distance) in children {
+                if let Some(distance) = distance {
+                    stack.push((child, distance));
+                }
+            }
+        }
+        closest
+    }
+
+    // Every object whose box is at least partly inside the frustum
+    pub fn query_frustum(&self, frustum: &Frustum, mut visit: impl FnMut(usize)) {
+        self.query(|aabb| frustum.intersects_aabb(aabb), &mut visit);
+    }
+
+    // Every object whose box overlaps `aabb`
+    pub fn query_aabb(&self, aabb: &Aabb, mut visit: impl FnMut(usize)) {
+        self.query(|node| node.intersects(aabb), &mut visit);
+    }
+
+    fn query(&self, overlaps: impl Fn(&Aabb) -> bool, visit: &mut impl FnMut(usize)) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let Node { aabb, first, count } = self.nodes[node];
+            if !overlaps(&aabb) {
+                continue;
+            }
+            if count > 0 {
+                for &object in self.leaf_objects(first, count) {
+                    if overlaps(&self.bounds[object as usize]) {
+                        visit(object as usize);
+                    }
+                }
+            } else {
+                stack.extend([first as usize, first as usize + 1]);
+            }
+        }
+    }
+}
@@ -0,0 +1,71 @@
+// Copy and paste through the system clipboard. When there isn't one (on the
+// web, or no display server) it falls back to a clipboard that only works
+// within the app, so text fields behave the same either way.
+pub struct Clipboard {
+    #[cfg(not(target_arch = "wasm32"))]
+    system: Option<arboard::Clipboard>,
+    local_text: String,
+}
+
+impl Default for Clipboard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clipboard {
+    pub fn new() -> Self {
+        Self {
+            #[cfg(not(target_arch = "wasm32"))]
+            system: arboard::Clipboard::new()
+                .map_err(|e| tracing::warn!("No system clipboard, copy and paste stays inside the app: {}", e))
+                .ok(),
+            local_text: String::new(),
+        }
+    }
+
+    pub fn text(&mut self) -> Option<String> {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(system) = &mut self.system {
+            return system.get_text().ok();
+        }
+        Some(self.local_text.clone()).filter(|text| !text.is_empty())
+    }
+
+    pub fn set_text(&mut self, text: impl Into<String>) {
+        let text = text.into();
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(system) = &mut self.system {
+            if let Err(e) = system.set_text(text.clone()) {
+                tracing::warn!("Failed to copy to the clipboard: {}", e);
+            }
+        }
+        self.local_text = text;
+    }
+
+    // A copied image, e.g. a screenshot. Only the system clipboard has these.
+    pub fn image(&mut self) -> Option<image::RgbaImage> {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(system) = &mut self.system {
+            let image = system.get_image().ok()?;
+            return image::RgbaImage::from_raw(image.width as u32, image.height as u32, image.bytes.into_owned());
+        }
+        None
+    }
+
+    pub fn set_image(&mut self, image: &image::RgbaImage) {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(system) = &mut self.system {
+            let data = arboard::ImageData {
+                width: image.width() as usize,
+                height: image.height() as usize,
+                bytes: image.as_raw().into(),
+            };
+            if let Err(e) = system.set_image(data) {
+                tracing::warn!("Failed to copy the image to the clipboard: {}", e);
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        let _ = image;
+    }
+}
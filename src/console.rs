@@ -0,0 +1,187 @@
+use std::collections::BTreeMap;
+
+use anyhow::bail;
+use tracing::Level;
+
+use crate::logging;
+
+// The commands State runs itself, since they need the renderer
+const BUILT_IN: &[(&str, &str)] = &[
+    ("help", "lists every command"),
+    (
+        "set",
+        "set clear_color r g b, or grid, dither, bloom, hover_highlight, debug_volumes or profiler on/off",
+    ),
+    ("spawn", "spawn cube, quad or path/to/model.obj, .gltf or .glb in front of the camera"),
+    ("reload", "reload shaders or assets from disk"),
+    ("settings", "settings save or load, the graphics settings in settings.toml"),
+    ("quality", "quality low, medium, high or ultra"),
+    ("probes", "probes capture, draws the scene into the reflection probes again"),
+    ("shake", "shake amount, shakes the camera by 0 to 1 trauma"),
+];
+
+// Gets the words after the command's name, whatever it gives back is shown
+// in the console and an error is shown as a warning
+type Handler = Box<dyn FnMut(&[&str]) -> anyhow::Result<String>>;
+
+struct Command {
+    help: String,
+    handler: Handler,
+}
+
+// A drop-down console showing the log, with a line to type commands into.
+// ` opens and closes it. The editor draws it with egui, see show().
+//
+// What's typed is split on whitespace, the first word picks the command.
+// Apps add their own with register(), usually from App::init().
+#[derive(Default)]
+pub struct Console {
+    open: bool,
+    // Whether the input line should take the keyboard next frame
+    focus: bool,
+    input: String,
+    history: Vec<String>,
+    // Which line of history Up and Down got to, None when not browsing it
+    history_index: Option<usize>,
+    commands: BTreeMap<String, Command>,
+    // Lines entered since take_submitted()
+    submitted: Vec<String>,
+}
+
+impl Console {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+        self.focus = self.open;
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    // Adds a command, replacing any other one with the same name
+    pub fn register(
+        &mut self,
+        name: &str,
+        help: &str,
+        handler: impl FnMut(&[&str]) -> anyhow::Result<String> + 'static,
+    ) {
+        if BUILT_IN.iter().any(|(built_in, _)| *built_in == name) {
+            tracing::warn!("The console's own {} command hides the one being registered", name);
+        }
+        let command = Command {
+            help: help.to_string(),
+            handler: Box::new(handler),
+        };
+        self.commands.insert(name.to_string(), command);
+    }
+
+    // Runs `line` next frame as if it was typed in
+    pub fn submit(&mut self, line: impl Into<String>) {
+        let line = line.into();
+        if line.trim().is_empty() {
+            return;
+        }
+        if self.history.last() != Some(&line) {
+            self.history.push(line.clone());
+        }
+        self.history_index = None;
+        self.submitted.push(line);
+    }
+
+    // What's been entered since last time, for State to run
+    pub fn take_submitted(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.submitted)
+    }
+
+    // Runs a registered command, None when there isn't one called `name`
+    pub fn run(&mut self, name: &str, args: &[&str]) -> Option<anyhow::Result<String>> {
+        self.commands.get_mut(name).map(|command| (command.handler)(args))
+    }
+
+    // A line for every command and what it does
+    pub fn help(&self) -> String {
+        let registered = self.commands.iter().map(|(name, command)| (name.as_str(), command.help.as_str()));
+        BUILT_IN
+            .iter()
+            .copied()
+            .chain(registered)
+            .map(|(name, help)| format!("{} - {}", name, help))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    // The log and the input line, dropping down from the top of the window
+    pub fn show(&mut self, ctx: &egui::Context) {
+        if !self.open {
+            return;
+        }
+        let height = ctx.input().screen_rect().height() * 0.4;
+        egui::TopBottomPanel::top("console").resizable(false).height_range(height..=height).show(ctx, |ui| {
+            let input_height = ui.spacing().interact_size.y + ui.spacing().item_spacing.y;
+            egui::ScrollArea::vertical()
+                .max_height(ui.available_height() - input_height)
+                .stick_to_bottom(true)
+                .auto_shrink([false, false])
+                .show(ui, |ui| {
+                    for line in logging::recent_lines() {
+                        let color = match line.level {
+                            Level::ERROR => egui::Color32::LIGHT_RED,
+                            Level::WARN => egui::Color32::YELLOW,
+                            Level::INFO => ui.visuals().text_color(),
+                            _ => egui::Color32::GRAY,
+                        };
+                        // What the console says itself doesn't need its source
+                        let text = match line.target.as_str() {
+                            "console" => line.message,
+                            target => format!("{}: {}", target, line.message),
+                        };
+                        ui.label(egui::RichText::new(text).monospace().color(color));
+                    }
+                });
+            let response = ui.add(
+                egui::TextEdit::singleline(&mut self.input)
+                    .font(egui::TextStyle::Monospace)
+                    .desired_width(f32::INFINITY),
+            );
+            if std::mem::take(&mut self.focus) {
+                response.request_focus();
+            }
+            // Enter takes the focus away from single line edits
+            if response.lost_focus() && ui.input().key_pressed(egui::Key::Enter) {
+                let line = std::mem::take(&mut self.input);
+                self.submit(line);
+                response.request_focus();
+            } else if response.has_focus() && ui.input().key_pressed(egui::Key::ArrowUp) {
+                self.browse_history(true);
+            } else if response.has_focus() && ui.input().key_pressed(egui::Key::ArrowDown) {
+                self.browse_history(false);
+            }
+        });
+    }
+
+    // Up goes back through what was typed before, Down forwards again and
+    // then to an empty line
+    fn browse_history(&mut self, back: bool) {
+        let index = match (self.history_index, back) {
+            (None, true) => self.history.len().checked_sub(1),
+            (None, false) => None,
+            (Some(index), true) => Some(index.saturating_sub(1)),
+            (Some(index), false) => (index + 1 < self.history.len()).then_some(index + 1),
+        };
+        self.history_index = index;
+        self.input = index.map_or_else(String::new, |index| self.history[index].clone());
+    }
+}
+
+// An on/off value for `set`, for commands of your own too
+pub fn switch(args: &[&str]) -> anyhow::Result<bool> {
+    match args {
+        ["on" | "true" | "1"] => Ok(true),
+        ["off" | "false" | "0"] => Ok(false),
+        _ => bail!("expected on or off"),
+    }
+}
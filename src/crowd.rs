@@ -0,0 +1,528 @@
+use anyhow::{bail, ensure, Result};
+use glam::{Mat3, Mat4, Vec3};
+use wgpu::util::DeviceExt;
+
+use crate::animation::{AnimationClip, AnimationPlayer};
+use crate::bounds::{Aabb, Frustum};
+use crate::mesh::Mesh;
+use crate::scene::Scene;
+use crate::sky::{Lighting, LightingUniform};
+use crate::texture::Texture;
+use crate::upload::AppUploader;
+use crate::transform::Transform;
+use crate::vertex::VertexLayout;
+
+const SOURCE: &str = include_str!("shaders/crowd.wgsl");
+
+crate::vertex_layout! {
+    #[derive(Debug)]
+    struct CrowdVertex {
+        #[location(0)] tex_coords: [f32; 2],
+    }
+}
+
+crate::vertex_layout! {
+    step_mode: Instance,
+    #[derive(Debug)]
+    struct CrowdInstanceRaw {
+        #[location(1)] model_0: [f32; 4],
+        #[location(2)] model_1: [f32; 4],
+        #[location(3)] model_2: [f32; 4],
+        #[location(4)] model_3: [f32; 4],
+        #[location(5)] animation: [f32; 4],
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct CrowdUniform {
+    vertex_count: u32,
+    width: u32,
+    _padding: [u32; 2],
+    lighting: LightingUniform,
+}
+
+// Where a clip's frames are in the animation texture
+#[derive(Clone, Debug, PartialEq)]
+pub struct BakedClip {
+    pub name: String,
+    pub first_frame: u32,
+    pub frames: u32,
+    pub duration: f32,
+}
+
+// A character's animations played ahead of time, with every vertex of every
+// frame posed and written down. The character is a scene's entity hierarchy,
+// its parts the meshes hung off some of those entities, like a robot's limbs
+// off its joints. Entities without a part still move the ones under them.
+//
+//     let bake = AnimationBake::new(&scene, &[("body", &body), ("head", &head)], &scene.animations, 30.0)?;
+//
+// Frames are sampled evenly through each clip and blended between when
+// drawn, and clips are taken to loop.
+pub struct AnimationBake {
+    // The parts merged into one mesh. Positions and normals are left out,
+    // they come from the frames.
+    tex_coords: Vec<[f32; 2]>,
+    indices: Vec<u32>,
+    // Positions and normals of every vertex, frame after frame, with w unused
+    frames: Vec<[f32; 4]>,
+    clips: Vec<BakedClip>,
+    // Around every frame of every clip
+    bounds: Aabb,
+}
+
+impl AnimationBake {
+    // Errors when a part names an entity the scene doesn't have, or there's
+    // nothing to bake
+    pub fn new(scene: &Scene, parts: &[(&str, &Mesh)], clips: &[AnimationClip], frame_rate: f32) -> Result<Self> {
+        ensure!(!parts.is_empty(), "a baked animation needs at least one part");
+        ensure!(!clips.is_empty(), "a baked animation needs at least one clip");
+        ensure!(frame_rate > 0.0, "the frame rate has to be above 0");
+        let mut entities = Vec::new();
+        let mut tex_coords = Vec::new();
+        let mut indices = Vec::new();
+        for (name, mesh) in parts {
+            let Some(entity) = scene.entities.iter().position(|e| e.name == *name) else {
+                bail!("no scene entity called {}", name);
+            };
+            let base = tex_coords.len() as u32;
+            tex_coords.extend((0..mesh.positions.len()).map(|i| mesh.tex_coords.get(i).copied().unwrap_or_default()));
+            indices.extend(mesh.indices.iter().map(|i| base + i));
+            entities.push(entity);
+        }
+        ensure!(!indices.is_empty(), "the parts have no triangles");
+        // Normals get worked out for parts that came without them
+        let parts = parts
+            .iter()
+            .map(|(_, mesh)| {
+                let mut mesh = (*mesh).clone();
+                if mesh.normals.len() != mesh.positions.len() {
+                    mesh.recompute_normals();
+                }
+                mesh
+            })
+            .collect::<Vec<_>>();
+
+        let rest = scene.entities.iter().map(|e| Transform::from(&e.transform)).collect::<Vec<_>>();
+        let mut frames = Vec::new();
+        let mut baked = Vec::new();
+        let mut bounds = Aabb::EMPTY;
+        let mut first_frame = 0;
+        for clip in clips {
+            let mut player = AnimationPlayer::new(clip.clone().into());
+            player.bind(&scene.entities);
+            let duration = clip.duration();
+            // A clip with no length still gets its one pose
+            let count = ((duration * frame_rate).round() as u32).max(1);
+            for frame in 0..count {
+                player.seek(duration * frame as f32 / count as f32);
+                let mut local = rest.clone();
+                player.apply(&mut local);
+                let world = scene.world_transforms_from(&local);
+                for (mesh, &entity) in parts.iter().zip(&entities) {
+                    let matrix = world[entity].matrix();
+                    // Inverse transpose, so squashed parts keep normals that
+                    // point out of their surface
+                    let normal_matrix = Mat3::from_mat4(matrix).inverse().transpose();
+                    for (position, normal) in mesh.positions.iter().zip(&mesh.normals) {
+                        let position = matrix.transform_point3(Vec3::from(*position));
+                        let normal = (normal_matrix * Vec3::from(*normal)).normalize_or_zero();
+                        bounds = bounds.union(Aabb::new(position, position));
+                        frames.push(position.extend(1.0).into());
+                        frames.push(normal.extend(0.0).into());
+                    }
+                }
+            }
+            baked.push(BakedClip {
+                name: clip.name.clone(),
+                first_frame,
+                frames: count,
+                duration,
+            });
+            first_frame += count;
+        }
+        Ok(Self {
+            tex_coords,
+            indices,
+            frames,
+            clips: baked,
+            bounds,
+        })
+    }
+
+    pub fn vertex_count(&self) -> u32 {
+        self.tex_coords.len() as u32
+    }
+
+    pub fn frame_count(&self) -> u32 {
+        self.clips.iter().map(|clip| clip.frames).sum()
+    }
+
+    pub fn clips(&self) -> &[BakedClip] {
+        &self.clips
+    }
+
+    // The index CrowdInstance::new() takes for the clip called `name`
+    pub fn clip(&self, name: &str) -> Option<usize> {
+        self.clips.iter().position(|clip| clip.name == name)
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.bounds
+    }
+}
+
+// One character in a crowd
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CrowdInstance {
+    pub transform: Transform,
+    // Index into AnimationBake::clips()
+    pub clip: usize,
+    // How far through the clip it was at time 0, from 0 to 1. Giving every
+    // instance a different one keeps them from moving in lockstep.
+    pub phase: f32,
+    pub speed: f32,
+}
+
+impl CrowdInstance {
+    pub fn new(transform: Transform, clip: usize) -> Self {
+        Self {
+            transform,
+            clip,
+            phase: 0.0,
+            speed: 1.0,
+        }
+    }
+
+    pub fn with_phase(mut self, phase: f32) -> Self {
+        self.phase = phase;
+        self
+    }
+
+    pub fn with_speed(mut self, speed: f32) -> Self {
+        self.speed = speed;
+        self
+    }
+}
+
+// Draws thousands of one baked character at once, in a single instanced
+// draw. The vertex shader reads each instance's pose straight out of the
+// animation texture by its clip and time, so all that goes up per character
+// is its transform, clip and phase. Instances out of view are skipped in
+// prepare().
+//
+// Every frame: prepare() with the camera, then draw() in a pass that writes
+// the scene target and depth, like Foliage.
+pub struct Crowd {
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    uniform: CrowdUniform,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    index_count: u32,
+    clips: Vec<BakedClip>,
+    bounds: Aabb,
+    instances: Vec<CrowdInstance>,
+    instance_buffer: wgpu::Buffer,
+    instance_capacity: usize,
+    // Instances the last prepare() wrote to the buffer
+    visible: u32,
+}
+
+impl Crowd {
+    // Errors when the frames don't fit in the biggest texture the device
+    // can make
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color_format: wgpu::TextureFormat,
+        camera_layout: &wgpu::BindGroupLayout,
+        frame_layout: &wgpu::BindGroupLayout,
+        bake: &AnimationBake,
+        diffuse: &Texture,
+    ) -> Result<Self> {
+        // Frames run along rows as wide as the device allows and wrap onto
+        // the next, which keeps big characters inside WebGL's limits too
+        let max = device.limits().max_texture_dimension_2d;
+        let width = max.min(bake.frames.len() as u32).max(1);
+        let height = (bake.frames.len() as u32).div_ceil(width);
+        ensure!(height <= max, "{} baked frames don't fit in a texture", bake.frame_count());
+        let mut texels = bake.frames.clone();
+        texels.resize((width * height) as usize, [0.0; 4]);
+        let animation = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some("Crowd Animation Texture"),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba32Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+            },
+            bytemuck::cast_slice(&texels),
+        );
+        let animation_view = animation.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let texture_entry = |binding, filterable| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("crowd_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // 32 bit floats can't be filtered everywhere, the shader
+                // blends frames itself
+                texture_entry(1, false),
+                texture_entry(2, true),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let uniform = CrowdUniform {
+            vertex_count: bake.vertex_count(),
+            width,
+            _padding: [0; 2],
+            lighting: Lighting::default().into(),
+        };
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Crowd Buffer"),
+            contents: bytemuck::bytes_of(&uniform),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("crowd_bind_group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&animation_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&diffuse.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&diffuse.sampler),
+                },
+            ],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Crowd Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, frame_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let vertices = bake.tex_coords.iter().map(|&tex_coords| CrowdVertex { tex_coords }).collect::<Vec<_>>();
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Crowd Vertex Buffer"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Crowd Index Buffer"),
+            contents: bytemuck::cast_slice(&bake.indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        tracing::info!(
+            "Baked {} frames of {} vertices into a {}x{} texture",
+            bake.frame_count(),
+            bake.vertex_count(),
+            width,
+            height
+        );
+
+        Ok(Self {
+            pipeline: Self::create_pipeline(device, &layout, color_format, SOURCE),
+            layout,
+            color_format,
+            uniform,
+            buffer,
+            bind_group,
+            vertex_buffer,
+            index_buffer,
+            index_count: bake.indices.len() as u32,
+            clips: bake.clips.clone(),
+            bounds: bake.bounds,
+            instances: Vec::new(),
+            instance_buffer: Self::create_instance_buffer(device, 1),
+            instance_capacity: 1,
+            visible: 0,
+        })
+    }
+
+    fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Crowd Instance Buffer"),
+            size: (capacity * std::mem::size_of::<CrowdInstanceRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        shader_source: &str,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Crowd Shader"),
+            source: wgpu::ShaderSource::Wgsl(shader_source.into()),
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Crowd Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[CrowdVertex::desc(), CrowdInstanceRaw::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+
+    // For apps watching their own copy of crowd.wgsl, keeps the old
+    // pipeline if the new source doesn't compile
+    pub fn reload_shader(&mut self, device: &wgpu::Device, shader_source: &str) {
+        if let Some(pipeline) = crate::shaders::try_create(device, || {
+            Self::create_pipeline(device, &self.layout, self.color_format, shader_source)
+        }) {
+            self.pipeline = pipeline;
+        }
+    }
+
+    pub fn instances(&self) -> &[CrowdInstance] {
+        &self.instances
+    }
+
+    // Instances with a clip the bake doesn't have are left out with a warning
+    pub fn set_instances(&mut self, device: &wgpu::Device, instances: Vec<CrowdInstance>) {
+        let clips = self.clips.len();
+        self.instances = instances
+            .into_iter()
+            .filter(|instance| {
+                let known = instance.clip < clips;
+                if !known {
+                    tracing::warn!("Crowd instance plays clip {}, but only {} were baked", instance.clip, clips);
+                }
+                known
+            })
+            .collect();
+        if self.instances.len() > self.instance_capacity {
+            self.instance_capacity = self.instances.len().next_power_of_two();
+            self.instance_buffer = Self::create_instance_buffer(device, self.instance_capacity);
+        }
+    }
+
+    // Instances the last prepare() picked to draw
+    pub fn visible_count(&self) -> u32 {
+        self.visible
+    }
+
+    // Writes the instances in view to the instance buffer, lit by `lighting`
+    pub fn prepare(&mut self, uploader: &AppUploader, view_proj: Mat4, lighting: &Lighting) {
+        let frustum = Frustum::from_view_proj(view_proj);
+        let raw = self
+            .instances
+            .iter()
+            .filter_map(|instance| {
+                let model = instance.transform.matrix();
+                if !frustum.intersects_aabb(&self.bounds.transformed(model)) {
+                    return None;
+                }
+                let clip = &self.clips[instance.clip];
+                let frames = clip.frames as f32;
+                let rate = if clip.duration > 0.0 { frames / clip.duration } else { 0.0 };
+                let [model_0, model_1, model_2, model_3] = model.to_cols_array_2d();
+                let start = instance.phase.fract() * frames;
+                Some(CrowdInstanceRaw {
+                    model_0,
+                    model_1,
+                    model_2,
+                    model_3,
+                    animation: [clip.first_frame as f32, frames, rate * instance.speed, start],
+                })
+            })
+            .collect::<Vec<_>>();
+        uploader.write(&self.instance_buffer, 0, &raw);
+        self.visible = raw.len() as u32;
+
+        self.uniform.lighting = (*lighting).into();
+        uploader.write(&self.buffer, 0, &[self.uniform]);
+    }
+
+    // Expects the camera at group 0 and the frame at group 1
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera: &'a wgpu::BindGroup,
+        frame: &'a wgpu::BindGroup,
+    ) {
+        if self.visible == 0 {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera, &[]);
+        render_pass.set_bind_group(1, frame, &[]);
+        render_pass.set_bind_group(2, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(0..self.index_count, 0, 0..self.visible);
+    }
+}
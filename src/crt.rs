@@ -0,0 +1,287 @@
+use crate::texture::Texture;
+use crate::upload::Uploader;
+
+// How many lines the picture is split into when there's no pixel art mode
+// to take them from, about what an old TV showed
+pub const DEFAULT_LINES: u32 = 240;
+
+// How the CRT effect looks, see RenderSettings::crt in app.rs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CrtSettings {
+    // How much the screen bulges out, 0 is flat
+    pub curvature: f32,
+    // How dark the gaps between lines get, from 0 (no gaps) to 1 (black)
+    pub scanlines: f32,
+    // How many lines down the screen. None is one for every row of pixel art
+    // mode's picture, or DEFAULT_LINES without it.
+    pub lines: Option<u32>,
+    // How strongly the red, green and blue stripes of the aperture grille
+    // show, from 0 to 1
+    pub mask: f32,
+    // How much light spreads out from bright parts of the picture
+    pub glow: f32,
+    // How much of the last frame is still glowing on the phosphor, from 0
+    // (none) to 1 (never fades). Leaves trails behind things that move.
+    pub persistence: f32,
+}
+
+impl Default for CrtSettings {
+    fn default() -> Self {
+        Self {
+            curvature: 0.06,
+            scanlines: 0.5,
+            lines: None,
+            mask: 0.25,
+            glow: 0.25,
+            persistence: 0.4,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct CrtParams {
+    // Where the picture is on the surface, x, y, width and height in 0..1
+    rect: [f32; 4],
+    lines: f32,
+    curvature: f32,
+    scanlines: f32,
+    mask: f32,
+    glow: f32,
+    persistence: f32,
+    _padding: [f32; 2],
+}
+
+// Puts the tonemapped picture on the surface as if shown on an old CRT
+// screen (see shaders/crt.wgsl): bent outwards, cut into lines, seen through
+// the stripes of its aperture grille, glowing a little around bright parts
+// and with the last frames fading out behind the new one.
+//
+// It's last in the post chain and takes over from pixel art mode's scaling,
+// so in pixel art mode every row of the picture gets a line of its own.
+// Fading needs what was on the screen last frame, which it keeps in a
+// texture of its own, written next to the surface.
+pub struct Crt {
+    layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::RenderPipeline,
+    surface_format: wgpu::TextureFormat,
+    sampler: wgpu::Sampler,
+    params: wgpu::Buffer,
+    // Last frame and this one, taking turns, along with the size they are
+    history: Option<([Texture; 2], (u32, u32))>,
+    // Which of them this frame writes
+    written: usize,
+}
+
+impl Crt {
+    pub fn new(device: &wgpu::Device, surface_format: wgpu::TextureFormat, shader_source: &str) -> Self {
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("crt_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // The picture
+                texture_entry(1),
+                // Last frame's screen
+                texture_entry(2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("CRT Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("CRT Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let params = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("CRT Params"),
+            size: std::mem::size_of::<CrtParams>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            pipeline: Self::create_pipeline(device, &pipeline_layout, surface_format, shader_source),
+            layout,
+            pipeline_layout,
+            surface_format,
+            sampler,
+            params,
+            history: None,
+            written: 0,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        surface_format: wgpu::TextureFormat,
+        shader_source: &str,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("CRT Shader"),
+            source: wgpu::ShaderSource::Wgsl(shader_source.into()),
+        });
+        let target = Some(wgpu::ColorTargetState {
+            format: surface_format,
+            blend: None,
+            write_mask: wgpu::ColorWrites::ALL,
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("CRT Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                // The surface and the history, which get the same
+                targets: &[target.clone(), target],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+
+    // Keeps the old pipeline if the new source doesn't compile
+    pub fn reload_shader(&mut self, device: &wgpu::Device, shader_source: &str) {
+        if let Some(pipeline) = crate::shaders::try_create(device, || {
+            Self::create_pipeline(device, &self.pipeline_layout, self.surface_format, shader_source)
+        }) {
+            self.pipeline = pipeline;
+        }
+    }
+
+    // Once a frame before run(). `rect` is where the picture goes on the
+    // `surface_size` surface and `picture_rows` how many rows pixel art
+    // mode's picture has, if it's on.
+    #[allow(clippy::too_many_arguments)]
+    pub fn upload(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        uploader: &mut Uploader,
+        settings: &CrtSettings,
+        rect: [u32; 4],
+        surface_size: (u32, u32),
+        picture_rows: Option<u32>,
+    ) {
+        if self.history.as_ref().is_none_or(|(_, size)| *size != surface_size) {
+            let create = || Texture::create_render_target(device, surface_size, self.surface_format, "CRT History");
+            self.history = Some(([create(), create()], surface_size));
+        }
+        self.written ^= 1;
+
+        let (width, height) = (surface_size.0.max(1) as f32, surface_size.1.max(1) as f32);
+        let [x, y, rect_width, rect_height] = rect.map(|v| v as f32);
+        let lines = settings.lines.or(picture_rows).unwrap_or(DEFAULT_LINES).max(1);
+        let params = CrtParams {
+            rect: [x / width, y / height, rect_width / width, rect_height / height],
+            lines: lines as f32,
+            curvature: settings.curvature.max(0.0),
+            scanlines: settings.scanlines.clamp(0.0, 1.0),
+            mask: settings.mask.clamp(0.0, 1.0),
+            glow: settings.glow.max(0.0),
+            persistence: settings.persistence.clamp(0.0, 1.0),
+            _padding: [0.0; 2],
+        };
+        uploader.write(device, encoder, &self.params, 0, &[params]);
+    }
+
+    // Drops last frame's screen, so turning the effect back on later doesn't
+    // fade out whatever was there back then
+    pub fn release(&mut self) {
+        self.history = None;
+    }
+
+    // Draws `picture`, already tonemapped, onto `target`, which is the
+    // size upload() was given
+    pub fn run(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        picture: &wgpu::TextureView,
+        target: &wgpu::TextureView,
+    ) {
+        let Some((history, _)) = &self.history else {
+            return;
+        };
+        let (last, current) = (&history[self.written ^ 1], &history[self.written]);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("crt_bind_group"),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.params.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(picture),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&last.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        });
+        let attachment = |view| {
+            Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })
+        };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("CRT Pass"),
+            color_attachments: &[attachment(target), attachment(&current.view)],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
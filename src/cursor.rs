@@ -0,0 +1,104 @@
+use glam::Vec2;
+use winit::event::WindowEvent;
+use winit::window::{CursorIcon, Window};
+
+use crate::assets::Handle;
+use crate::screen::Screen;
+use crate::sprite::Sprite;
+use crate::texture::Texture;
+
+// A texture drawn where the mouse is instead of the system cursor
+#[derive(Clone, Copy, Debug)]
+pub struct CustomCursor {
+    pub texture: Handle<Texture>,
+    // Both in logical pixels, the hotspot is the point of the texture that
+    // sits exactly on the mouse position
+    pub size: Vec2,
+    pub hotspot: Vec2,
+}
+
+// Tracks where the mouse is and what the cursor should look like. Changes
+// get applied to the window from the event loop with apply().
+pub struct Cursor {
+    position: Option<Vec2>,
+    icon: CursorIcon,
+    visible: bool,
+    custom: Option<CustomCursor>,
+    dirty: bool,
+}
+
+impl Default for Cursor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Cursor {
+    pub fn new() -> Self {
+        Self {
+            position: None,
+            icon: CursorIcon::Default,
+            visible: true,
+            custom: None,
+            dirty: false,
+        }
+    }
+
+    // In logical pixels, None while the mouse is outside the window
+    pub fn position(&self) -> Option<Vec2> {
+        self.position
+    }
+
+    pub fn icon(&self) -> CursorIcon {
+        self.icon
+    }
+
+    pub fn set_icon(&mut self, icon: CursorIcon) {
+        self.dirty |= self.icon != icon;
+        self.icon = icon;
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    // Hides the custom cursor too
+    pub fn set_visible(&mut self, visible: bool) {
+        self.dirty |= self.visible != visible;
+        self.visible = visible;
+    }
+
+    pub fn custom(&self) -> Option<&CustomCursor> {
+        self.custom.as_ref()
+    }
+
+    // Some hides the system cursor and draws the texture instead
+    pub fn set_custom(&mut self, custom: Option<CustomCursor>) {
+        self.dirty |= self.custom.is_some() != custom.is_some();
+        self.custom = custom;
+    }
+
+    pub fn handle_event(&mut self, event: &WindowEvent, screen: &Screen) {
+        match event {
+            WindowEvent::CursorMoved { position, .. } => self.position = Some(screen.to_logical(*position)),
+            WindowEvent::CursorLeft { .. } => self.position = None,
+            _ => {}
+        }
+    }
+
+    pub fn apply(&mut self, window: &Window) {
+        if !self.dirty {
+            return;
+        }
+        self.dirty = false;
+        window.set_cursor_icon(self.icon);
+        window.set_cursor_visible(self.visible && self.custom.is_none());
+    }
+
+    // The custom cursor as a sprite, for the sprite batch to draw last
+    pub fn sprite(&self) -> Option<Sprite> {
+        let custom = self.custom.as_ref().filter(|_| self.visible)?;
+        let position = self.position?;
+        Some(Sprite::new(custom.texture, position - custom.hotspot, custom.size))
+    }
+}
@@ -0,0 +1,163 @@
+use std::sync::Mutex;
+
+use glam::{Mat4, Vec3};
+
+use crate::bounds::Aabb;
+use crate::lines::LineBatch;
+use crate::scene::SceneLight;
+
+// Shapes for seeing what code is doing, callable from anywhere without
+// getting hold of the renderer: debug::line(a, b, color) in an update, an
+// ECS system or a background thread and it shows up that frame. Everything
+// is in world space and only lasts one frame, so keep calling it for as long
+// as you want to see it.
+//
+// Shapes are depth tested against the scene, after set_depth_test(false)
+// they're drawn on top of it instead until it's turned back on.
+
+struct DebugLines {
+    lines: Vec<(Vec3, Vec3, [f32; 4], bool)>,
+    depth_test: bool,
+}
+
+static LINES: Mutex<DebugLines> = Mutex::new(DebugLines {
+    lines: Vec::new(),
+    depth_test: true,
+});
+
+const CIRCLE_SEGMENTS: usize = 32;
+
+fn with_lines(f: impl FnOnce(&mut Vec<(Vec3, Vec3, [f32; 4], bool)>, bool)) {
+    // A panic while drawing doesn't leave anything half done worth worrying about
+    let mut debug = LINES.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let DebugLines { lines, depth_test } = &mut *debug;
+    f(lines, !*depth_test);
+}
+
+// Whether shapes added from now on are hidden behind the scene
+pub fn set_depth_test(enabled: bool) {
+    LINES.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).depth_test = enabled;
+}
+
+pub fn line(a: Vec3, b: Vec3, color: [f32; 4]) {
+    with_lines(|lines, on_top| lines.push((a, b, color, on_top)));
+}
+
+// The twelve edges of the box
+pub fn aabb(aabb: &Aabb, color: [f32; 4]) {
+    if aabb.is_empty() {
+        return;
+    }
+    hexahedron(
+        |i| {
+            Vec3::new(
+                if i & 1 == 0 { aabb.min.x } else { aabb.max.x },
+                if i & 2 == 0 { aabb.min.y } else { aabb.max.y },
+                if i & 4 == 0 { aabb.min.z } else { aabb.max.z },
+            )
+        },
+        color,
+    );
+}
+
+// What a camera with this view projection sees, from its near plane to its
+// far one
+pub fn frustum(view_proj: Mat4, color: [f32; 4]) {
+    let inverse = view_proj.inverse();
+    // Clip space goes from -1 to 1 on x and y but 0 to 1 on z
+    hexahedron(
+        |i| {
+            inverse.project_point3(Vec3::new(
+                if i & 1 == 0 { -1.0 } else { 1.0 },
+                if i & 2 == 0 { -1.0 } else { 1.0 },
+                if i & 4 == 0 { 0.0 } else { 1.0 },
+            ))
+        },
+        color,
+    );
+}
+
+// Any box-like shape, `corner` gets 0 to 7 where bit 0 picks the side on x,
+// bit 1 on y and bit 2 on z
+fn hexahedron(corner: impl Fn(usize) -> Vec3, color: [f32; 4]) {
+    let corners = std::array::from_fn::<_, 8, _>(corner);
+    with_lines(|lines, on_top| {
+        for i in 0..8 {
+            // Each corner to its neighbours with a higher index, so every
+            // edge is drawn once
+            for bit in [1, 2, 4] {
+                if i & bit == 0 {
+                    lines.push((corners[i], corners[i | bit], color, on_top));
+                }
+            }
+        }
+    });
+}
+
+// Where a light reaches, in its own colour. Point lights get a sphere out to
+// their range, directional ones a few arrows around the origin since they
+// don't have a position.
+pub fn light(light: &SceneLight) {
+    match *light {
+        SceneLight::Point { position, color, range, .. } => {
+            let [r, g, b] = color;
+            let position = Vec3::from(position);
+            sphere(position, range, [r, g, b, 1.0]);
+            // And a small star where the light itself is
+            for axis in [Vec3::X, Vec3::Y, Vec3::Z] {
+                line(position - axis * 0.1, position + axis * 0.1, [r, g, b, 1.0]);
+            }
+        }
+        SceneLight::Directional { direction, color, .. } => {
+            let [r, g, b] = color;
+            let direction = Vec3::from(direction).normalize_or_zero();
+            let (side, other) = direction.any_orthonormal_pair();
+            for offset in [Vec3::ZERO, side, -side, other, -other] {
+                let from = offset * 0.5 - direction;
+                arrow(from, from + direction, [r, g, b, 1.0]);
+            }
+        }
+    }
+}
+
+// Three circles, one around each axis
+pub fn sphere(center: Vec3, radius: f32, color: [f32; 4]) {
+    with_lines(|lines, on_top| {
+        for (side, other) in [(Vec3::Y, Vec3::Z), (Vec3::Z, Vec3::X), (Vec3::X, Vec3::Y)] {
+            let point = |i: usize| {
+                let angle = i as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU;
+                center + (side * angle.cos() + other * angle.sin()) * radius
+            };
+            for i in 0..CIRCLE_SEGMENTS {
+                lines.push((point(i), point(i + 1), color, on_top));
+            }
+        }
+    });
+}
+
+// A line from `from` to `to` with a head at `to`
+pub fn arrow(from: Vec3, to: Vec3, color: [f32; 4]) {
+    let direction = (to - from).normalize_or_zero();
+    if direction == Vec3::ZERO {
+        return;
+    }
+    let (side, other) = direction.any_orthonormal_pair();
+    let length = from.distance(to);
+    let base = to - direction * length * 0.15;
+    let width = length * 0.05;
+    with_lines(|lines, on_top| {
+        lines.push((from, to, color, on_top));
+        for offset in [side, -side, other, -other] {
+            lines.push((to, base + offset * width, color, on_top));
+        }
+    });
+}
+
+// Moves everything drawn since last time into `batch`, the renderer calls
+// this once a frame before preparing its lines
+pub fn flush(batch: &mut LineBatch) {
+    let mut debug = LINES.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    for (a, b, color, on_top) in debug.lines.drain(..) {
+        batch.line(a, b, color, on_top);
+    }
+}
@@ -0,0 +1,299 @@
+use std::ops::Range;
+
+use serde::Serialize;
+use wgpu::util::RenderEncoder;
+
+// Draws refer to pipelines, materials and meshes by index into a
+// DrawResources, which keeps the draw list itself cheap to sort and compare.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DrawCommand {
+    pub pipeline: u32,
+    pub material: u32,
+    pub mesh: u32,
+    // View space distance. Opaque draws want ascending depth (front to back),
+    // transparent ones can pass a negated depth to get back to front.
+    pub depth: f32,
+    pub indices: Range<u32>,
+    pub base_vertex: i32,
+    pub instances: Range<u32>,
+}
+
+pub struct MeshBuffers<'a> {
+    pub vertex_buffer: wgpu::BufferSlice<'a>,
+    // Bound on slot 1 along with the vertices. Required so a mesh can never
+    // be drawn with the last mesh's instances.
+    pub instance_buffer: wgpu::BufferSlice<'a>,
+    // Extra per-vertex attribute streams, bound from slot 2 on
+    pub streams: Vec<wgpu::BufferSlice<'a>>,
+    pub index_buffer: wgpu::BufferSlice<'a>,
+    pub index_format: wgpu::IndexFormat,
+}
+
+pub struct DrawResources<'a> {
+    // Bound once at the start of every recording, e.g. the camera. Render
+    // bundles don't inherit anything from the pass so they need these too.
+    pub globals: Vec<(u32, &'a wgpu::BindGroup)>,
+    pub pipelines: Vec<&'a wgpu::RenderPipeline>,
+    pub materials: Vec<&'a wgpu::BindGroup>,
+    pub meshes: Vec<MeshBuffers<'a>>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct DrawStats {
+    pub submitted: usize,
+    pub draw_calls: usize,
+    pub pipeline_changes: usize,
+    pub material_changes: usize,
+}
+
+#[derive(Default)]
+pub struct DrawList {
+    commands: Vec<DrawCommand>,
+    stats: DrawStats,
+}
+
+fn adjacent(a: &Range<u32>, b: &Range<u32>) -> bool {
+    a.end == b.start || b.end == a.start
+}
+
+fn union(a: &Range<u32>, b: &Range<u32>) -> Range<u32> {
+    a.start.min(b.start)..a.end.max(b.end)
+}
+
+impl DrawList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.commands.clear();
+        self.stats = DrawStats::default();
+    }
+
+    pub fn push(&mut self, command: DrawCommand) {
+        self.commands.push(command);
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    pub fn commands(&self) -> &[DrawCommand] {
+        &self.commands
+    }
+
+    pub fn stats(&self) -> DrawStats {
+        self.stats
+    }
+
+    // Sorts by pipeline, then material, then depth, so state changes stay
+    // few while each material still draws front to back for early depth
+    // rejection. Neighbours left drawing the same mesh that only differ by an
+    // adjacent index or instance range are merged into one draw.
+    pub fn sort_and_batch(&mut self) {
+        let submitted = self.commands.len();
+        self.commands.sort_by(|a, b| {
+            a.pipeline
+                .cmp(&b.pipeline)
+                .then(a.material.cmp(&b.material))
+                .then(a.depth.total_cmp(&b.depth))
+                // Only to keep the order the same from frame to frame when
+                // depths tie
+                .then(a.mesh.cmp(&b.mesh))
+                .then(a.base_vertex.cmp(&b.base_vertex))
+                .then(a.indices.start.cmp(&b.indices.start))
+        });
+
+        let mut batched: Vec<DrawCommand> = Vec::with_capacity(self.commands.len());
+        for command in self.commands.drain(..) {
+            if let Some(last) = batched.last_mut() {
+                let same_state = last.pipeline == command.pipeline
+                    && last.material == command.material
+                    && last.mesh == command.mesh
+                    && last.base_vertex == command.base_vertex;
+
+                if same_state && last.indices == command.indices && adjacent(&last.instances, &command.instances) {
+                    last.instances = union(&last.instances, &command.instances);
+                    continue;
+                }
+                if same_state && last.instances == command.instances && adjacent(&last.indices, &command.indices) {
+                    last.indices = union(&last.indices, &command.indices);
+                    continue;
+                }
+            }
+            batched.push(command);
+        }
+        self.commands = batched;
+
+        self.stats.submitted = submitted;
+        self.stats.draw_calls = self.commands.len();
+    }
+
+    // Records the list into a render pass, only touching pipeline, bind group
+    // and buffer state when it actually changes between draws.
+    pub fn draw<'a>(&mut self, render_pass: &mut wgpu::RenderPass<'a>, material_group: u32, resources: &DrawResources<'a>) {
+        let stats = record(&self.commands, render_pass, material_group, resources);
+        self.stats.pipeline_changes += stats.pipeline_changes;
+        self.stats.material_changes += stats.material_changes;
+    }
+
+    // Splits the list into one chunk per thread and records each chunk into its
+    // own render bundle in parallel. Execute the bundles in order with
+    // `RenderPass::execute_bundles`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn draw_parallel<'a>(
+        &mut self,
+        device: &wgpu::Device,
+        bundle: &wgpu::RenderBundleEncoderDescriptor,
+        material_group: u32,
+        resources: &DrawResources<'a>,
+    ) -> Vec<wgpu::RenderBundle> {
+        use rayon::prelude::*;
+
+        let threads = rayon::current_num_threads().max(1);
+        let chunk_size = self.commands.len().div_ceil(threads).max(MIN_DRAWS_PER_BUNDLE);
+
+        let recorded = self
+            .commands
+            .par_chunks(chunk_size)
+            .map(|commands| {
+                let mut encoder = device.create_render_bundle_encoder(bundle);
+                let stats = record(commands, &mut encoder, material_group, resources);
+                (encoder.finish(&wgpu::RenderBundleDescriptor { label: bundle.label }), stats)
+            })
+            .collect::<Vec<_>>();
+
+        recorded
+            .into_iter()
+            .map(|(bundle, stats)| {
+                self.stats.pipeline_changes += stats.pipeline_changes;
+                self.stats.material_changes += stats.material_changes;
+                bundle
+            })
+            .collect()
+    }
+}
+
+// Records draws straight into a pass without counting them, for drawing the
+// same commands again from somewhere else, like a mirror's camera
+pub fn draw_commands<'a>(
+    commands: &[DrawCommand],
+    render_pass: &mut wgpu::RenderPass<'a>,
+    material_group: u32,
+    resources: &DrawResources<'a>,
+) {
+    record(commands, render_pass, material_group, resources);
+}
+
+// Smaller chunks than this aren't worth the overhead of a separate bundle
+#[cfg(not(target_arch = "wasm32"))]
+const MIN_DRAWS_PER_BUNDLE: usize = 64;
+
+// Shared by render passes and render bundles, which both implement RenderEncoder
+fn record<'a, E: RenderEncoder<'a>>(
+    commands: &[DrawCommand],
+    encoder: &mut E,
+    material_group: u32,
+    resources: &DrawResources<'a>,
+) -> DrawStats {
+    let mut stats = DrawStats::default();
+    let mut pipeline = None;
+    let mut material = None;
+    let mut mesh = None;
+
+    for &(index, group) in &resources.globals {
+        encoder.set_bind_group(index, group, &[]);
+    }
+
+    for command in commands {
+        if pipeline != Some(command.pipeline) {
+            encoder.set_pipeline(resources.pipelines[command.pipeline as usize]);
+            pipeline = Some(command.pipeline);
+            stats.pipeline_changes += 1;
+        }
+        if material != Some(command.material) {
+            encoder.set_bind_group(material_group, resources.materials[command.material as usize], &[]);
+            material = Some(command.material);
+            stats.material_changes += 1;
+        }
+        if mesh != Some(command.mesh) {
+            let buffers = &resources.meshes[command.mesh as usize];
+            encoder.set_vertex_buffer(0, buffers.vertex_buffer);
+            encoder.set_vertex_buffer(1, buffers.instance_buffer);
+            for (slot, stream) in (2..).zip(&buffers.streams) {
+                encoder.set_vertex_buffer(slot, *stream);
+            }
+            encoder.set_index_buffer(buffers.index_buffer, buffers.index_format);
+            mesh = Some(command.mesh);
+        }
+        encoder.draw_indexed(command.indices.clone(), command.base_vertex, command.instances.clone());
+    }
+
+    stats
+}
+
+// Draws that don't change from frame to frame, recorded into a render bundle
+// once and replayed every frame. The bundle is only re-recorded when the set
+// of commands changes or something it references gets recreated.
+#[derive(Default)]
+pub struct StaticBundle {
+    commands: Vec<DrawCommand>,
+    bundle: Option<wgpu::RenderBundle>,
+}
+
+impl StaticBundle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Takes the static draws, only throwing away the recorded bundle if they
+    // differ from what was recorded last time.
+    pub fn update(&mut self, list: &DrawList) {
+        if self.commands != list.commands() {
+            self.commands = list.commands().to_vec();
+            self.bundle = None;
+        }
+    }
+
+    // Bundles hold on to the exact pipelines, bind groups and buffers they
+    // were recorded with, so call this after recreating any of them.
+    pub fn invalidate(&mut self) {
+        self.bundle = None;
+    }
+
+    pub fn is_recorded(&self) -> bool {
+        self.bundle.is_some()
+    }
+
+    pub fn commands(&self) -> &[DrawCommand] {
+        &self.commands
+    }
+
+    // How many draw calls the bundle makes
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    pub fn bundle<'a>(
+        &mut self,
+        device: &wgpu::Device,
+        desc: &wgpu::RenderBundleEncoderDescriptor,
+        material_group: u32,
+        resources: &DrawResources<'a>,
+    ) -> &wgpu::RenderBundle {
+        let commands = &self.commands;
+        self.bundle.get_or_insert_with(|| {
+            let mut encoder = device.create_render_bundle_encoder(desc);
+            record(commands, &mut encoder, material_group, resources);
+            encoder.finish(&wgpu::RenderBundleDescriptor { label: desc.label })
+        })
+    }
+}
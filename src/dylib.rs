@@ -0,0 +1,174 @@
+use std::path::{Path, PathBuf};
+
+use instant::Instant;
+use libloading::Library;
+
+use crate::app::{App, Overlay, RenderContext, RenderSettings, Setup};
+use crate::hot_reload::FileWatcher;
+use crate::render_graph::RenderGraph;
+
+// What the library exports, as
+//
+//     #[no_mangle]
+//     pub fn create_game(saved: &[u8]) -> Box<dyn GameLogic> { ... }
+//
+// `saved` is what the last version's save() gave back, empty the first time.
+pub const CREATE_SYMBOL: &[u8] = b"create_game";
+
+type CreateGame = fn(&[u8]) -> Box<dyn GameLogic>;
+
+// How long the library has to stay the same before it's loaded, the linker
+// writes it in several goes
+const SETTLE_TIME: f32 = 0.5;
+
+// Gameplay code living in a library of its own that gets reloaded whenever
+// it's rebuilt, while the window, the renderer and everything it loaded stay
+// as they are. Like App without init(): anything the library subscribes to
+// or hands the engine would outlive its code once it's unloaded.
+pub trait GameLogic {
+    // Called every frame before rendering, `dt` is in seconds
+    fn update(&mut self, _settings: &mut RenderSettings, _dt: f32) {}
+
+    // The same as App::render()
+    fn render<'g>(&'g mut self, _graph: &mut RenderGraph<'g>, _context: RenderContext<'g>) {}
+
+    // The same as App::overlay()
+    fn overlay(&mut self, _overlay: &mut Overlay) {}
+
+    // Whatever should make it into the next version, handed to its
+    // create_game(). The game starts over when it's left empty.
+    fn save(&self) -> Vec<u8> {
+        Vec::new()
+    }
+}
+
+// Runs the GameLogic a library makes, loading it again when it's rebuilt.
+// The library is a crate with `crate-type = ["cdylib"]` depending on this one
+// with the same features, built by the same compiler, since the two only
+// agree on what GameLogic and RenderSettings look like then. It keeps its own
+// copy of this crate's statics, so its logging doesn't show up in the
+// console.
+//
+//     run_app(WindowConfig::new("game"), DylibApp::new("target/debug/libgame.so"))
+//
+// then `cargo build` the library while it runs.
+pub struct DylibApp {
+    path: PathBuf,
+    watcher: Option<FileWatcher>,
+    // When the library last changed, while it's waiting to settle
+    changed: Option<Instant>,
+    // Copies get loaded, a path that's loaded already would hand back the
+    // old library and some platforms lock what's loaded
+    generation: u32,
+    // Dropped before the library, its code is in there
+    logic: Option<Box<dyn GameLogic>>,
+    library: Option<(Library, PathBuf)>,
+}
+
+impl DylibApp {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        Self {
+            path: path.canonicalize().unwrap_or(path),
+            watcher: None,
+            changed: None,
+            generation: 0,
+            logic: None,
+            library: None,
+        }
+    }
+
+    // Swaps in the library on disk. If it can't be loaded the old one keeps
+    // running.
+    fn reload(&mut self) {
+        self.generation += 1;
+        let name = format!("learning_wgpu_dylib_{}_{}", std::process::id(), self.generation);
+        let copy = std::env::temp_dir().join(name);
+        if let Err(e) = std::fs::copy(&self.path, &copy) {
+            tracing::warn!("Failed to copy {}: {:?}", self.path.display(), e);
+            return;
+        }
+        // Safety: running the library's initialisers is what loading it is
+        // for, it's trusted like the rest of the game's code
+        let library = match unsafe { Library::new(&copy) } {
+            Ok(library) => library,
+            Err(e) => {
+                tracing::warn!("Failed to load {}: {}", self.path.display(), e);
+                let _ = std::fs::remove_file(&copy);
+                return;
+            }
+        };
+        // Safety: only as sound as the library exporting the signature
+        // CREATE_SYMBOL documents, which nothing can check
+        let create = match unsafe { library.get::<CreateGame>(CREATE_SYMBOL) } {
+            Ok(create) => *create,
+            Err(e) => {
+                tracing::warn!("{} has no create_game(): {}", self.path.display(), e);
+                let _ = std::fs::remove_file(&copy);
+                return;
+            }
+        };
+        let saved = self.logic.as_ref().map(|logic| logic.save()).unwrap_or_default();
+        // The old logic's code goes away with its library
+        self.logic = None;
+        self.unload();
+        self.logic = Some(create(&saved));
+        self.library = Some((library, copy));
+        tracing::info!("Loaded {}", self.path.display());
+    }
+
+    fn unload(&mut self) {
+        if let Some((library, copy)) = self.library.take() {
+            drop(library);
+            let _ = std::fs::remove_file(copy);
+        }
+    }
+
+    fn watch(&mut self, path: &Path) {
+        let dir = path.parent().map(PathBuf::from).unwrap_or_default();
+        match FileWatcher::new(&dir) {
+            Ok(watcher) => self.watcher = Some(watcher),
+            Err(e) => tracing::warn!("Reloading {} is disabled: {:?}", path.display(), e),
+        }
+    }
+}
+
+impl Drop for DylibApp {
+    fn drop(&mut self) {
+        self.logic = None;
+        self.unload();
+    }
+}
+
+impl App for DylibApp {
+    fn init(&mut self, _setup: &mut Setup) {
+        self.watch(&self.path.clone());
+        self.reload();
+    }
+
+    fn update(&mut self, settings: &mut RenderSettings, dt: f32) {
+        if self.watcher.as_ref().is_some_and(|watcher| watcher.changed_files().contains(&self.path)) {
+            self.changed = Some(Instant::now());
+        }
+        // Wall clock time, dt stands still while the editor has paused
+        if self.changed.is_some_and(|changed| changed.elapsed().as_secs_f32() >= SETTLE_TIME) {
+            self.changed = None;
+            self.reload();
+        }
+        if let Some(logic) = &mut self.logic {
+            logic.update(settings, dt);
+        }
+    }
+
+    fn render<'g>(&'g mut self, graph: &mut RenderGraph<'g>, context: RenderContext<'g>) {
+        if let Some(logic) = &mut self.logic {
+            logic.render(graph, context);
+        }
+    }
+
+    fn overlay(&mut self, overlay: &mut Overlay) {
+        if let Some(logic) = &mut self.logic {
+            logic.overlay(overlay);
+        }
+    }
+}
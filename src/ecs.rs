@@ -0,0 +1,233 @@
+pub use bevy_ecs;
+
+use bevy_ecs::prelude::*;
+
+pub use crate::transform::Transform;
+use crate::scene::{Gloss, MaterialRef, MeshRef, Mirror, Scene, SceneCamera, SceneLight};
+use crate::trail::{Trail, TrailPoints};
+
+// Entities and components for scenes that change while running. With the
+// `ecs` feature on, the renderer spawns the loaded scene into a World and
+// pulls everything it needs to draw out of it every frame, so gameplay code
+// only has to touch components.
+//
+// What works on the built-in scene list and not here yet, see UNSUPPORTED.
+// The renderer warns about it at startup and whenever a scene that needs it
+// is loaded, and the editor says so where the selection would be.
+
+pub const UNSUPPORTED: &str = "click to select, the transform gizmo, marquee selection, \
+scene animations and IK chains aren't available with the ecs feature yet";
+
+#[derive(Component, Clone, Debug, PartialEq)]
+pub struct MeshRenderer {
+    pub mesh: MeshRef,
+    pub material: MaterialRef,
+}
+
+#[derive(Component, Clone, Debug, PartialEq)]
+pub struct Light(pub SceneLight);
+
+// Next to a MeshRenderer, makes it a mirror
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct Reflective(pub Mirror);
+
+// Next to a MeshRenderer, makes it reflect the closest reflection probe
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct Glossy(pub Gloss);
+
+// Next to a Transform, leaves a ribbon behind it as it moves. The renderer
+// adds to its points every frame.
+#[derive(Component, Clone, Debug)]
+pub struct TrailEmitter {
+    pub trail: Trail,
+    pub points: TrailPoints,
+}
+
+impl TrailEmitter {
+    pub fn new(trail: Trail) -> Self {
+        Self {
+            trail,
+            points: TrailPoints::new(),
+        }
+    }
+}
+
+// Only the first camera found gets rendered from
+#[derive(Component, Clone, Debug, PartialEq)]
+pub struct Camera(pub SceneCamera);
+
+#[derive(Component, Clone, Debug, PartialEq, Eq)]
+pub struct Name(pub String);
+
+// Something to draw this frame, copied out of the world by extract_meshes
+#[derive(Clone, Debug)]
+pub struct ExtractedMesh {
+    pub transform: Transform,
+    pub mesh: MeshRef,
+    pub material: MaterialRef,
+    pub mirror: Option<Mirror>,
+    pub gloss: Option<Gloss>,
+}
+
+#[derive(Resource, Default)]
+pub struct ExtractedMeshes(pub Vec<ExtractedMesh>);
+
+pub fn extract_meshes(
+    query: Query<(&Transform, &MeshRenderer, Option<&Reflective>, Option<&Glossy>)>,
+    mut extracted: ResMut<ExtractedMeshes>,
+) {
+    extracted.0.clear();
+    extracted.0.extend(query.iter().map(|(transform, renderer, reflective, glossy)| ExtractedMesh {
+        transform: *transform,
+        mesh: renderer.mesh.clone(),
+        material: renderer.material.clone(),
+        mirror: reflective.map(|reflective| reflective.0),
+        gloss: glossy.map(|glossy| glossy.0),
+    }));
+}
+
+// Every Light, and which entity each one came from
+#[derive(Resource, Default)]
+pub struct ExtractedLights {
+    pub lights: Vec<SceneLight>,
+    pub entities: Vec<Entity>,
+}
+
+pub fn extract_lights(query: Query<(Entity, &Light)>, mut extracted: ResMut<ExtractedLights>) {
+    let ExtractedLights { lights, entities } = &mut *extracted;
+    lights.clear();
+    entities.clear();
+    for (entity, light) in query.iter() {
+        lights.push(light.0.clone());
+        entities.push(entity);
+    }
+}
+
+#[derive(StageLabel)]
+pub struct ExtractStage;
+
+pub struct EcsWorld {
+    pub world: World,
+    // Systems that copy render data out of the world, add more to ExtractStage
+    pub extract: Schedule,
+    // What the renderer last put in the Camera, to tell when something else
+    // moved it
+    synced_camera: Option<SceneCamera>,
+}
+
+impl EcsWorld {
+    pub fn new() -> Self {
+        let mut world = World::new();
+        world.init_resource::<ExtractedMeshes>();
+        world.init_resource::<ExtractedLights>();
+        let mut extract = Schedule::default();
+        let stage = SystemStage::single_threaded().with_system(extract_meshes).with_system(extract_lights);
+        extract.add_stage(ExtractStage, stage);
+        Self {
+            world,
+            extract,
+            synced_camera: None,
+        }
+    }
+
+    // Throws away every entity and spawns the scene's instead
+    pub fn spawn_scene(&mut self, scene: &Scene) {
+        self.world.clear_entities();
+        self.world.spawn(Camera(scene.camera.clone()));
+        self.synced_camera = Some(scene.camera.clone());
+        // Parents are flattened away, every entity gets its world transform
+        for (entity, transform) in scene.entities.iter().zip(scene.world_transforms()) {
+            let mut spawned = self.world.spawn((
+                Name(entity.name.clone()),
+                transform,
+                MeshRenderer {
+                    mesh: entity.mesh.clone(),
+                    material: entity.material.clone(),
+                },
+            ));
+            if let Some(mirror) = entity.mirror {
+                spawned.insert(Reflective(mirror));
+            }
+            if let Some(gloss) = entity.gloss {
+                spawned.insert(Glossy(gloss));
+            }
+            if let Some(trail) = &entity.trail {
+                spawned.insert(TrailEmitter::new(trail.clone()));
+            }
+        }
+        for light in &scene.lights {
+            self.world.spawn(Light(light.clone()));
+        }
+    }
+
+    pub fn camera(&mut self) -> Option<&SceneCamera> {
+        self.world.query::<&Camera>().iter(&self.world).next().map(|camera| &camera.0)
+    }
+
+    // The camera, if it was changed since set_camera() last put it there
+    pub fn moved_camera(&mut self) -> Option<SceneCamera> {
+        let synced = self.synced_camera.clone();
+        self.camera().filter(|camera| Some(*camera) != synced.as_ref()).cloned()
+    }
+
+    // Where the renderer's camera ended up this frame, spawns one if there
+    // isn't a Camera
+    pub fn set_camera(&mut self, camera: SceneCamera) {
+        let mut query = self.world.query::<&mut Camera>();
+        match query.iter_mut(&mut self.world).next() {
+            Some(mut existing) => {
+                // Only touched when it moved, so change detection means something
+                if existing.0 != camera {
+                    existing.0 = camera.clone();
+                }
+            }
+            None => {
+                self.world.spawn(Camera(camera.clone()));
+            }
+        }
+        self.synced_camera = Some(camera);
+    }
+
+    // Every Light, as of the last extract()
+    pub fn lights(&self) -> &[SceneLight] {
+        &self.world.resource::<ExtractedLights>().lights
+    }
+
+    // Puts the ones that differ from what lights() gave back into their
+    // entities, `lights` in the same order
+    pub fn set_lights(&mut self, lights: &[SceneLight]) {
+        let extracted = self.world.resource::<ExtractedLights>();
+        let changed = (extracted.entities.iter().zip(&extracted.lights).zip(lights))
+            .filter(|((_, before), after)| before != after)
+            .map(|((entity, _), after)| (*entity, after.clone()))
+            .collect::<Vec<_>>();
+        for (entity, light) in changed {
+            // Unless it's been despawned since
+            if let Some(mut existing) = self.world.get_mut::<Light>(entity) {
+                existing.0 = light;
+            }
+        }
+    }
+
+    // Adds where every TrailEmitter is now to its points
+    pub fn record_trails(&mut self, dt: f32) {
+        let mut query = self.world.query::<(&Transform, &mut TrailEmitter)>();
+        for (transform, mut emitter) in query.iter_mut(&mut self.world) {
+            let TrailEmitter { trail, points } = &mut *emitter;
+            points.record(trail, transform.translation, dt);
+        }
+    }
+
+    // Runs the extract systems and hands back the meshes they found, the
+    // lights are in lights()
+    pub fn extract(&mut self) -> &[ExtractedMesh] {
+        self.extract.run_once(&mut self.world);
+        &self.world.resource::<ExtractedMeshes>().0
+    }
+}
+
+impl Default for EcsWorld {
+    fn default() -> Self {
+        Self::new()
+    }
+}
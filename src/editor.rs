@@ -0,0 +1,527 @@
+use std::path::Path;
+
+use egui::{Pos2, Vec2};
+use winit::event::{
+    ElementState, KeyboardInput, ModifiersState, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent,
+};
+
+use crate::app::{RenderContext, Setup};
+use crate::assets::{AssetId, AssetType, Assets, LoadState};
+use crate::egui_renderer::EguiRenderer;
+use crate::gizmo::GizmoMode;
+use crate::inspect::Inspect;
+use crate::plugin::{Plugin, PluginContext};
+use crate::render_graph::RenderGraph;
+use crate::scene::SceneEntity;
+use crate::screen::Screen;
+
+// The asset browser's preview, as egui sees it
+const PREVIEW: egui::TextureId = egui::TextureId::User(0);
+
+// What the inspector changed in an entity, which needs more than the new
+// values to show up
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct InspectorEdits {
+    transform: bool,
+    // A material, mirror or gloss changed, so the scene's batches are out of date
+    materials: bool,
+}
+
+// A small level editor over the scene: a toolbar with play and pause and
+// the gizmo's modes, the scene's entities as a tree to pick them from, an
+// inspector for the picked one and the lights, a browser of every asset
+// that's loaded and a window for authoring the particle effect picked in it.
+// F1 opens and closes it. The engine's console is drawn with it too, it has
+// its own key.
+//
+// It's a plugin, `cargo run --features editor` adds it. Window events go
+// through on_event() first, it keeps the ones meant for egui away from the
+// camera and picking. Every frame pre_update() lays the panels out and
+// render() puts them on the surface, over everything else.
+pub struct Editor {
+    context: egui::Context,
+    input: egui::RawInput,
+    started: instant::Instant,
+    // Made in setup(), once there's a device
+    renderer: Option<EguiRenderer>,
+    // What pre_update() laid out, for render() to upload and draw
+    primitives: Vec<egui::ClippedPrimitive>,
+    // As of the last frame, events come in between
+    screen: Screen,
+    open: bool,
+    // Whether the console should be open, it's the engine's so it only
+    // finds out in pre_update()
+    console_open: bool,
+    // Pasting waits for pre_update() too, the clipboard comes with it
+    paste: bool,
+    // The texture the asset browser shows a preview of
+    preview: Option<AssetId>,
+    // The particle effect the effect window edits
+    effect: Option<AssetId>,
+    // What the last save of it said, shown under the save button
+    effect_status: String,
+    modifiers: ModifiersState,
+    // In points, egui's logical pixels
+    pointer: Pos2,
+}
+
+impl Editor {
+    pub fn new() -> Self {
+        Self {
+            context: egui::Context::default(),
+            input: egui::RawInput {
+                has_focus: true,
+                ..Default::default()
+            },
+            started: instant::Instant::now(),
+            renderer: None,
+            primitives: Vec::new(),
+            screen: Screen::new(winit::dpi::PhysicalSize::new(1, 1), 1.0),
+            open: false,
+            console_open: false,
+            paste: false,
+            preview: None,
+            effect: None,
+            effect_status: String::new(),
+            modifiers: ModifiersState::empty(),
+            pointer: Pos2::ZERO,
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    // Whether egui has anything on screen to take events
+    fn is_visible(&self) -> bool {
+        self.open || self.console_open
+    }
+
+    // Hands `event` on to egui. True when egui is using it, so nothing
+    // behind the panels should react to it.
+    fn handle_event(&mut self, event: &WindowEvent) -> bool {
+        let scale = self.screen.scale_factor as f32;
+        let modifiers = egui_modifiers(self.modifiers);
+        let open = self.is_visible();
+        match event {
+            WindowEvent::ModifiersChanged(state) => {
+                self.modifiers = *state;
+                false
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.pointer = Pos2::new(position.x as f32 / scale, position.y as f32 / scale);
+                self.input.events.push(egui::Event::PointerMoved(self.pointer));
+                // Gizmo drags and the camera want to see the cursor move too
+                false
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.input.events.push(egui::Event::PointerGone);
+                false
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                let button = match button {
+                    MouseButton::Left => egui::PointerButton::Primary,
+                    MouseButton::Right => egui::PointerButton::Secondary,
+                    MouseButton::Middle => egui::PointerButton::Middle,
+                    MouseButton::Other(_) => return false,
+                };
+                let pressed = *state == ElementState::Pressed;
+                self.input.events.push(egui::Event::PointerButton { pos: self.pointer, button, pressed, modifiers });
+                // Letting go always gets through, so drags that started
+                // outside the panels end
+                open && pressed && self.context.wants_pointer_input()
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let delta = match delta {
+                    MouseScrollDelta::LineDelta(x, y) => Vec2::new(*x, *y) * 50.0,
+                    MouseScrollDelta::PixelDelta(delta) => Vec2::new(delta.x as f32, delta.y as f32) / scale,
+                };
+                self.input.events.push(egui::Event::Scroll(delta));
+                open && self.context.wants_pointer_input()
+            }
+            // The console's key, it shouldn't end up typed into it
+            WindowEvent::ReceivedCharacter('`') => true,
+            WindowEvent::ReceivedCharacter(c) if !c.is_control() => {
+                self.input.events.push(egui::Event::Text(c.to_string()));
+                open && self.context.wants_keyboard_input()
+            }
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(VirtualKeyCode::Grave),
+                    ..
+                },
+                ..
+            } => {
+                self.console_open = !self.console_open;
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(VirtualKeyCode::F1),
+                    ..
+                },
+                ..
+            } => {
+                self.toggle();
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state,
+                    virtual_keycode: Some(keycode),
+                    ..
+                },
+                ..
+            } => {
+                let pressed = *state == ElementState::Pressed;
+                if pressed && modifiers.command {
+                    match keycode {
+                        VirtualKeyCode::C => self.input.events.push(egui::Event::Copy),
+                        VirtualKeyCode::X => self.input.events.push(egui::Event::Cut),
+                        VirtualKeyCode::V => self.paste = true,
+                        _ => {}
+                    }
+                }
+                if let Some(key) = egui_key(*keycode) {
+                    self.input.events.push(egui::Event::Key { key, pressed, modifiers });
+                }
+                open && self.context.wants_keyboard_input()
+            }
+            WindowEvent::Focused(focused) => {
+                self.input.has_focus = *focused;
+                false
+            }
+            _ => false,
+        }
+    }
+
+}
+
+impl Default for Editor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Plugin for Editor {
+    fn setup(&mut self, setup: &mut Setup) {
+        self.renderer = Some(EguiRenderer::new(setup.device, setup.surface_format));
+    }
+
+    // Lays the panels out for this frame and gets them ready to draw. What
+    // the inspector changed goes in the context's scene_changes.
+    fn pre_update(&mut self, context: &mut PluginContext) {
+        self.screen = context.screen;
+        if std::mem::take(&mut self.paste) {
+            if let Some(text) = context.clipboard.text() {
+                self.input.events.push(egui::Event::Paste(text));
+            }
+        }
+        if context.console.is_open() != self.console_open {
+            context.console.toggle();
+        }
+        let mut input = std::mem::take(&mut self.input);
+        self.input.has_focus = input.has_focus;
+        self.primitives.clear();
+        if !self.is_visible() {
+            return;
+        }
+        let Some(renderer) = &mut self.renderer else {
+            return;
+        };
+        let size = self.screen.logical_size();
+        input.screen_rect = Some(egui::Rect::from_min_size(Pos2::ZERO, Vec2::new(size.x, size.y)));
+        input.pixels_per_point = Some(self.screen.scale_factor as f32);
+        input.time = Some(self.started.elapsed().as_secs_f64());
+        input.modifiers = egui_modifiers(self.modifiers);
+
+        let (open, preview) = (self.open, &mut self.preview);
+        let (effect, effect_status) = (&mut self.effect, &mut self.effect_status);
+        let (console, paused, selection) = (&mut *context.console, &mut *context.paused, &mut context.selection);
+        let (scene, assets) = (&mut *context.scene, &mut *context.assets);
+        let mut edits = InspectorEdits::default();
+        let output = self.context.run(input, |ctx| {
+            // Above the toolbar, it drops down from the very top
+            console.show(ctx);
+            if !open {
+                return;
+            }
+            egui::TopBottomPanel::top("editor_toolbar").show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    if ui.button(if *paused { "Play" } else { "Pause" }).clicked() {
+                        *paused = !*paused;
+                    }
+                    if let Some((_, mode)) = selection {
+                        ui.separator();
+                        ui.selectable_value(*mode, GizmoMode::Translate, "Move");
+                        ui.selectable_value(*mode, GizmoMode::Rotate, "Rotate");
+                        ui.selectable_value(*mode, GizmoMode::Scale, "Scale");
+                    }
+                });
+            });
+            egui::SidePanel::left("editor_scene").resizable(true).show(ctx, |ui| {
+                ui.heading("Scene");
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    let mut selected = selection.as_mut().map(|(selected, _)| &mut **selected);
+                    entity_tree(ui, &scene.entities, None, &mut selected);
+                });
+            });
+            // Wide enough for three numbers side by side
+            egui::SidePanel::right("editor_inspector").resizable(true).default_width(280.0).show(ctx, |ui| {
+                ui.heading("Inspector");
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    let selected = selection.as_ref().and_then(|(selected, _)| **selected);
+                    if let Some(entity) = selected.filter(|&entity| entity < scene.entities.len()) {
+                        edits = inspect_entity(ui, &mut scene.entities, entity);
+                    } else if selection.is_none() {
+                        ui.label("Selecting entities isn't available with the ecs feature yet");
+                    } else {
+                        ui.label("Nothing selected");
+                    }
+                    ui.separator();
+                    egui::CollapsingHeader::new("Lights").default_open(true).show(ui, |ui| {
+                        if scene.lights.is_empty() {
+                            ui.label("No lights");
+                        }
+                        for (index, light) in scene.lights.iter_mut().enumerate() {
+                            // Each light's fields need their own ids
+                            ui.push_id(index, |ui| light.inspect(ui));
+                            ui.separator();
+                        }
+                    });
+                });
+            });
+            egui::TopBottomPanel::bottom("editor_assets").resizable(true).show(ctx, |ui| {
+                ui.heading("Assets");
+                asset_browser(ui, assets, preview, effect);
+            });
+            effect_window(ctx, assets, effect, effect_status);
+        });
+
+        if !output.platform_output.copied_text.is_empty() {
+            context.clipboard.set_text(output.platform_output.copied_text);
+        }
+        if let Some(handle) = self.preview.and_then(|id| context.assets.texture_handle(id)) {
+            // Bound again every frame, the texture may have been reloaded
+            renderer.set_user_texture(context.device, 0, context.assets.texture(handle));
+        }
+        renderer.update_textures(context.device, context.queue, &output.textures_delta);
+        self.primitives = self.context.tessellate(output.shapes);
+        context.scene_changes.moved |= edits.transform;
+        context.scene_changes.rebuilt |= edits.materials;
+    }
+
+    fn render<'g>(&'g mut self, graph: &mut RenderGraph<'g>, context: RenderContext<'g>) {
+        let Some(renderer) = self.renderer.as_mut() else {
+            return;
+        };
+        renderer.prepare(context.device, context.uploader, &self.screen, &self.primitives);
+        if !renderer.has_draws() {
+            return;
+        }
+        let renderer: &'g EguiRenderer = renderer;
+        graph.add_pass("editor").writes(&["surface"]).execute(|encoder, resources| {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Editor Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: resources.view("surface"),
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            renderer.draw(&mut render_pass);
+        });
+    }
+
+    fn on_event(&mut self, event: &WindowEvent) -> bool {
+        self.handle_event(event)
+    }
+}
+
+// The entities under `parent` and everything under them, clicking one
+// selects it. Entities whose parent doesn't exist show up at the top.
+fn entity_tree(
+    ui: &mut egui::Ui,
+    entities: &[SceneEntity],
+    parent: Option<&str>,
+    selected: &mut Option<&mut Option<usize>>,
+) {
+    let is_root = |entity: &SceneEntity| match &entity.parent {
+        Some(name) => !entities.iter().any(|e| e.name == *name),
+        None => true,
+    };
+    for (index, entity) in entities.iter().enumerate() {
+        let under_parent = match parent {
+            Some(parent) => entity.parent.as_deref() == Some(parent),
+            None => is_root(entity),
+        };
+        if !under_parent {
+            continue;
+        }
+        let mut label = |ui: &mut egui::Ui| match selected {
+            Some(selected) => {
+                if ui.selectable_label(**selected == Some(index), &entity.name).clicked() {
+                    **selected = Some(index);
+                }
+            }
+            None => {
+                ui.label(&entity.name);
+            }
+        };
+        if entities.iter().any(|e| e.parent.as_deref() == Some(&entity.name)) {
+            let id = ui.make_persistent_id(("editor_entity", index));
+            egui::collapsing_header::CollapsingState::load_with_default_open(ui.ctx(), id, true)
+                .show_header(ui, label)
+                .body(|ui| entity_tree(ui, entities, Some(&entity.name), selected));
+        } else {
+            label(ui);
+        }
+    }
+}
+
+// The inspector for entities[entity], working out which of its parts changed
+fn inspect_entity(ui: &mut egui::Ui, entities: &mut [SceneEntity], entity: usize) -> InspectorEdits {
+    let before = entities[entity].clone();
+    let after = &mut entities[entity];
+    if !after.inspect(ui) {
+        return InspectorEdits::default();
+    }
+    InspectorEdits {
+        transform: after.transform != before.transform,
+        materials: after.material != before.material
+            || after.mirror != before.mirror
+            || after.gloss != before.gloss,
+    }
+}
+
+// Every asset with how it's loading, a button to load it again and a
+// preview of whichever texture was clicked last
+fn asset_browser(ui: &mut egui::Ui, assets: &mut Assets, preview: &mut Option<AssetId>, effect: &mut Option<AssetId>) {
+    ui.horizontal_top(|ui| {
+        egui::ScrollArea::vertical().max_height(160.0).show(ui, |ui| {
+            egui::Grid::new("editor_asset_grid").striped(true).show(ui, |ui| {
+                for asset in assets.list() {
+                    ui.label(match asset.kind {
+                        AssetType::Texture => "Texture",
+                        AssetType::Model => "Model",
+                        AssetType::Shader => "Shader",
+                        AssetType::Sound => "Sound",
+                        AssetType::ParticleEffect => "Effect",
+                    });
+                    if asset.kind == AssetType::Texture {
+                        if ui.selectable_label(*preview == Some(asset.id), &asset.label).clicked() {
+                            *preview = Some(asset.id);
+                        }
+                    } else if asset.kind == AssetType::ParticleEffect {
+                        if ui.selectable_label(*effect == Some(asset.id), &asset.label).clicked() {
+                            *effect = Some(asset.id);
+                        }
+                    } else {
+                        ui.label(&asset.label);
+                    }
+                    match &asset.state {
+                        LoadState::Loading => ui.label("loading"),
+                        LoadState::Loaded => ui.label("loaded"),
+                        LoadState::Failed(error) => {
+                            ui.colored_label(egui::Color32::LIGHT_RED, "failed").on_hover_text(error)
+                        }
+                    };
+                    if ui.small_button("Reload").clicked() {
+                        assets.reload(asset.id);
+                    }
+                    ui.end_row();
+                }
+            });
+        });
+        if preview.is_some() {
+            ui.separator();
+            ui.image(PREVIEW, Vec2::splat(128.0));
+        }
+    });
+}
+
+// The picked particle effect's inspector, in a window of its own so there's
+// room for its curves. Edits go straight into the loaded effect, emitters
+// playing it change as they're made, and Save writes them back to its file.
+fn effect_window(ctx: &egui::Context, assets: &mut Assets, effect: &mut Option<AssetId>, status: &mut String) {
+    let Some(handle) = effect.and_then(|id| assets.effect_handle(id)) else {
+        return;
+    };
+    let path = assets.effect_path(handle).map(Path::to_path_buf);
+    let mut open = true;
+    egui::Window::new("Particle Effect").open(&mut open).vscroll(true).show(ctx, |ui| {
+        let Some(definition) = assets.effect_mut(handle) else {
+            ui.label("Not loaded");
+            return;
+        };
+        definition.inspect(ui);
+        ui.separator();
+        ui.horizontal(|ui| {
+            let save = ui.add_enabled(path.is_some(), egui::Button::new("Save"));
+            if let (true, Some(path)) = (save.clicked(), &path) {
+                // Saving it sets off a hot reload, which reads back what
+                // was just written
+                let saved = definition.to_ron().and_then(|text| Ok(std::fs::write(path, text)?));
+                *status = match saved {
+                    Ok(()) => format!("Saved {}", path.display()),
+                    Err(e) => format!("Failed to save: {:#}", e),
+                };
+            }
+            ui.label(status.as_str());
+        });
+    });
+    if !open {
+        *effect = None;
+        status.clear();
+    }
+}
+
+fn egui_modifiers(modifiers: ModifiersState) -> egui::Modifiers {
+    egui::Modifiers {
+        alt: modifiers.alt(),
+        ctrl: modifiers.ctrl(),
+        shift: modifiers.shift(),
+        mac_cmd: cfg!(target_os = "macos") && modifiers.logo(),
+        // Cmd on macOS
+        command: if cfg!(target_os = "macos") { modifiers.logo() } else { modifiers.ctrl() },
+    }
+}
+
+// The keys egui's text fields and shortcuts listen for
+fn egui_key(keycode: VirtualKeyCode) -> Option<egui::Key> {
+    use egui::Key;
+    Some(match keycode {
+        VirtualKeyCode::Down => Key::ArrowDown,
+        VirtualKeyCode::Left => Key::ArrowLeft,
+        VirtualKeyCode::Right => Key::ArrowRight,
+        VirtualKeyCode::Up => Key::ArrowUp,
+        VirtualKeyCode::Escape => Key::Escape,
+        VirtualKeyCode::Tab => Key::Tab,
+        VirtualKeyCode::Back => Key::Backspace,
+        VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => Key::Enter,
+        VirtualKeyCode::Space => Key::Space,
+        VirtualKeyCode::Insert => Key::Insert,
+        VirtualKeyCode::Delete => Key::Delete,
+        VirtualKeyCode::Home => Key::Home,
+        VirtualKeyCode::End => Key::End,
+        VirtualKeyCode::PageUp => Key::PageUp,
+        VirtualKeyCode::PageDown => Key::PageDown,
+        VirtualKeyCode::A => Key::A,
+        VirtualKeyCode::K => Key::K,
+        VirtualKeyCode::U => Key::U,
+        VirtualKeyCode::W => Key::W,
+        VirtualKeyCode::Z => Key::Z,
+        VirtualKeyCode::Y => Key::Y,
+        _ => return None,
+    })
+}
@@ -0,0 +1,350 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use egui::epaint::textures::TexturesDelta;
+use egui::epaint::{ImageData, Primitive, TextureId, Vertex};
+use egui::ClippedPrimitive;
+
+use crate::screen::Screen;
+use crate::texture::Texture;
+use crate::upload::AppUploader;
+
+const SOURCE: &str = include_str!("shaders/egui.wgsl");
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct EguiGlobals {
+    projection: [[f32; 4]; 4],
+}
+
+// One mesh egui tessellated, as a range of the index buffer
+struct EguiDraw {
+    texture: TextureId,
+    indices: Range<u32>,
+    base_vertex: i32,
+    // x, y, width and height in physical pixels
+    scissor: [u32; 4],
+}
+
+// Draws what an egui::Context put out, like the sprite batch does sprites.
+// egui manages its own textures (the font atlas and anything it's handed as
+// images), they arrive and leave through TexturesDelta. Textures of the
+// engine's own show up as TextureId::User, see set_user_texture().
+//
+// Every frame: update_textures(), prepare() the tessellated shapes and then
+// draw() into a pass on the surface.
+pub struct EguiRenderer {
+    pipeline: wgpu::RenderPipeline,
+    texture_layout: wgpu::BindGroupLayout,
+    globals_buffer: wgpu::Buffer,
+    globals_bind_group: wgpu::BindGroup,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    // In vertices and indices
+    capacity: (usize, usize),
+    textures: HashMap<TextureId, (wgpu::Texture, wgpu::BindGroup)>,
+    user_textures: HashMap<u64, wgpu::BindGroup>,
+    // Freed once the frame that might still draw them is over
+    to_free: Vec<TextureId>,
+    draws: Vec<EguiDraw>,
+}
+
+impl EguiRenderer {
+    pub fn new(device: &wgpu::Device, color_format: wgpu::TextureFormat) -> Self {
+        use wgpu::util::DeviceExt;
+
+        let texture_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("egui_texture_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let globals_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("egui_globals_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let globals_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("egui Globals"),
+            contents: bytemuck::cast_slice(&[EguiGlobals {
+                projection: glam::Mat4::IDENTITY.to_cols_array_2d(),
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let globals_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("egui_globals"),
+            layout: &globals_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: globals_buffer.as_entire_binding(),
+            }],
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("egui Pipeline Layout"),
+            bind_group_layouts: &[&texture_layout, &globals_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("egui Shader"),
+            source: wgpu::ShaderSource::Wgsl(SOURCE.into()),
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("egui Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2, 2 => Unorm8x4],
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: match color_format.describe().srgb {
+                    true => "fs_linear",
+                    false => "fs_gamma",
+                },
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    // egui's colours come with the alpha already multiplied in
+                    blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            // egui doesn't keep its triangles facing one way
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        let capacity = (1024, 4096);
+        let vertex_size = capacity.0 * std::mem::size_of::<Vertex>();
+        Self {
+            pipeline,
+            texture_layout,
+            globals_buffer,
+            globals_bind_group,
+            vertex_buffer: Self::create_buffer(device, "egui Vertex Buffer", wgpu::BufferUsages::VERTEX, vertex_size),
+            index_buffer: Self::create_buffer(device, "egui Index Buffer", wgpu::BufferUsages::INDEX, capacity.1 * 4),
+            capacity,
+            textures: HashMap::new(),
+            user_textures: HashMap::new(),
+            to_free: Vec::new(),
+            draws: Vec::new(),
+        }
+    }
+
+    fn create_buffer(device: &wgpu::Device, label: &str, usage: wgpu::BufferUsages, size: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: size as wgpu::BufferAddress,
+            usage: usage | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn bind_group(&self, device: &wgpu::Device, view: &wgpu::TextureView, sampler: &wgpu::Sampler) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("egui_texture"),
+            layout: &self.texture_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        })
+    }
+
+    // Creates, patches and frees egui's textures
+    pub fn update_textures(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, delta: &TexturesDelta) {
+        for id in self.to_free.drain(..) {
+            self.textures.remove(&id);
+        }
+        self.to_free.extend_from_slice(&delta.free);
+
+        for (id, image_delta) in &delta.set {
+            let (size, pixels) = match &image_delta.image {
+                ImageData::Color(image) => (image.size, image.pixels.clone()),
+                // Coverage, turned into white with that much alpha
+                ImageData::Font(image) => (image.size, image.srgba_pixels(1.0).collect()),
+            };
+            let extent = wgpu::Extent3d {
+                width: size[0] as u32,
+                height: size[1] as u32,
+                depth_or_array_layers: 1,
+            };
+            let origin = match image_delta.pos {
+                Some([x, y]) => wgpu::Origin3d { x: x as u32, y: y as u32, z: 0 },
+                None => {
+                    let texture = device.create_texture(&wgpu::TextureDescriptor {
+                        label: Some("egui Texture"),
+                        size: extent,
+                        mip_level_count: 1,
+                        sample_count: 1,
+                        dimension: wgpu::TextureDimension::D2,
+                        format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                    });
+                    let filter = match image_delta.filter {
+                        egui::TextureFilter::Nearest => wgpu::FilterMode::Nearest,
+                        egui::TextureFilter::Linear => wgpu::FilterMode::Linear,
+                    };
+                    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+                        label: Some("egui Sampler"),
+                        mag_filter: filter,
+                        min_filter: filter,
+                        ..Default::default()
+                    });
+                    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+                    let bind_group = self.bind_group(device, &view, &sampler);
+                    self.textures.insert(*id, (texture, bind_group));
+                    wgpu::Origin3d::ZERO
+                }
+            };
+            // A patch of a texture that's been freed already
+            let Some((texture, _)) = self.textures.get(id) else {
+                continue;
+            };
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture,
+                    mip_level: 0,
+                    origin,
+                },
+                bytemuck::cast_slice(&pixels),
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(4 * extent.width),
+                    rows_per_image: std::num::NonZeroU32::new(extent.height),
+                },
+                extent,
+            );
+        }
+    }
+
+    // Makes `texture` drawable as TextureId::User(id), e.g. for egui::Image.
+    // Call again after the texture's been reloaded.
+    pub fn set_user_texture(&mut self, device: &wgpu::Device, id: u64, texture: &Texture) {
+        let bind_group = self.bind_group(device, &texture.view, &texture.sampler);
+        self.user_textures.insert(id, bind_group);
+    }
+
+    // Writes this frame's meshes into the buffers. Callbacks aren't supported.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        uploader: &AppUploader,
+        screen: &Screen,
+        primitives: &[ClippedPrimitive],
+    ) {
+        let globals = EguiGlobals {
+            projection: screen.ui_projection().to_cols_array_2d(),
+        };
+        uploader.write(&self.globals_buffer, 0, &[globals]);
+
+        self.draws.clear();
+        let (mut vertices, mut indices) = (Vec::<Vertex>::new(), Vec::<u32>::new());
+        let scale = screen.scale_factor as f32;
+        let (width, height) = (screen.physical_size.width, screen.physical_size.height);
+        for ClippedPrimitive { clip_rect, primitive } in primitives {
+            let Primitive::Mesh(mesh) = primitive else {
+                continue;
+            };
+            // Points to physical pixels, kept inside the surface
+            let min = (clip_rect.min.to_vec2() * scale).round();
+            let max = (clip_rect.max.to_vec2() * scale).round();
+            let (x, y) = ((min.x.max(0.0) as u32).min(width), (min.y.max(0.0) as u32).min(height));
+            let (right, bottom) = ((max.x.max(0.0) as u32).min(width), (max.y.max(0.0) as u32).min(height));
+            if mesh.indices.is_empty() || right <= x || bottom <= y {
+                continue;
+            }
+            let start = indices.len() as u32;
+            self.draws.push(EguiDraw {
+                texture: mesh.texture_id,
+                indices: start..start + mesh.indices.len() as u32,
+                base_vertex: vertices.len() as i32,
+                scissor: [x, y, right - x, bottom - y],
+            });
+            vertices.extend_from_slice(&mesh.vertices);
+            indices.extend_from_slice(&mesh.indices);
+        }
+
+        if vertices.len() > self.capacity.0 {
+            self.capacity.0 = vertices.len().next_power_of_two();
+            let size = self.capacity.0 * std::mem::size_of::<Vertex>();
+            self.vertex_buffer = Self::create_buffer(device, "egui Vertex Buffer", wgpu::BufferUsages::VERTEX, size);
+        }
+        if indices.len() > self.capacity.1 {
+            self.capacity.1 = indices.len().next_power_of_two();
+            let size = self.capacity.1 * 4;
+            self.index_buffer = Self::create_buffer(device, "egui Index Buffer", wgpu::BufferUsages::INDEX, size);
+        }
+        // Buffer writes have to be a multiple of 4 bytes
+        if indices.len() % 2 == 1 {
+            indices.push(0);
+        }
+        uploader.write(&self.vertex_buffer, 0, &vertices);
+        uploader.write(&self.index_buffer, 0, &indices);
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if self.draws.is_empty() {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(1, &self.globals_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        for draw in &self.draws {
+            let bind_group = match draw.texture {
+                TextureId::Managed(_) => self.textures.get(&draw.texture).map(|(_, bind_group)| bind_group),
+                TextureId::User(id) => self.user_textures.get(&id),
+            };
+            let Some(bind_group) = bind_group else {
+                continue;
+            };
+            let [x, y, width, height] = draw.scissor;
+            render_pass.set_scissor_rect(x, y, width, height);
+            render_pass.set_bind_group(0, bind_group, &[]);
+            render_pass.draw_indexed(draw.indices.clone(), draw.base_vertex, 0..1);
+        }
+    }
+
+    // Whether prepare() left anything to draw
+    pub fn has_draws(&self) -> bool {
+        !self.draws.is_empty()
+    }
+}
@@ -0,0 +1,160 @@
+use std::rc::Rc;
+
+use wgpu::util::DeviceExt;
+
+use crate::bind_group_cache::{BindGroupCache, CachedBinding, ResourceId};
+use crate::texture::Texture;
+
+// Where the emissive pipeline goes in the scene passes' DrawResources, after
+// the mesh and parallax pipelines
+pub const PIPELINE: u32 = 2;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct EmissiveUniform {
+    color: [f32; 3],
+    _padding: f32,
+}
+
+// Materials that give off light of their own, like neon signs and lava. The
+// emissive colour (times the emissive texture) is added on top of the
+// texture as it's drawn into the HDR scene, where it can go well past 1 so
+// bloom (see bloom.rs) makes it glow.
+//
+// Emissive materials are drawn with their own pipeline (PIPELINE in the
+// draw commands) and material bind group, the other groups are the same as
+// the mesh pipeline's.
+pub struct Emissive {
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    material_layout: wgpu::BindGroupLayout,
+    material_layout_id: ResourceId,
+}
+
+impl Emissive {
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        camera_layout: &wgpu::BindGroupLayout,
+        frame_layout: &wgpu::BindGroupLayout,
+        bind_group_cache: &mut BindGroupCache,
+        shader_source: &str,
+        // Extra attribute streams from the mesh pool, like the mesh pipeline
+        streams: &[wgpu::VertexBufferLayout],
+    ) -> Self {
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let material_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("emissive_material_bind_group_layout"),
+            entries: &[
+                texture_entry(0),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                texture_entry(2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Emissive Pipeline Layout"),
+            bind_group_layouts: &[&material_layout, camera_layout, frame_layout],
+            push_constant_ranges: &[],
+        });
+
+        Self {
+            pipeline: crate::create_render_pipeline(device, &layout, color_format, shader_source, streams),
+            layout,
+            color_format,
+            material_layout,
+            material_layout_id: bind_group_cache.register(),
+        }
+    }
+
+    // Called when the shader file changed or the mesh pool got new streams,
+    // keeps the old pipeline if the new source doesn't compile
+    pub fn reload_shader(&mut self, device: &wgpu::Device, shader_source: &str, streams: &[wgpu::VertexBufferLayout]) {
+        if let Some(pipeline) = crate::shaders::try_create(device, || {
+            crate::create_render_pipeline(device, &self.layout, self.color_format, shader_source, streams)
+        }) {
+            self.pipeline = pipeline;
+        }
+    }
+
+    // The emissive colour of one material, for material_bind_group()
+    pub fn create_material_buffer(device: &wgpu::Device, color: [f32; 3]) -> wgpu::Buffer {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Emissive Material Buffer"),
+            contents: bytemuck::bytes_of(&EmissiveUniform {
+                color: color.map(|c| c.max(0.0)),
+                _padding: 0.0,
+            }),
+            usage: wgpu::BufferUsages::UNIFORM,
+        })
+    }
+
+    // Group 0 for an emissive material. `id` stands in for all of it, so it
+    // has to be recreated when either texture is.
+    pub fn material_bind_group(
+        &self,
+        device: &wgpu::Device,
+        bind_group_cache: &mut BindGroupCache,
+        id: ResourceId,
+        texture: &Texture,
+        emissive: &Texture,
+        buffer: &wgpu::Buffer,
+    ) -> Rc<wgpu::BindGroup> {
+        bind_group_cache.get_or_create(
+            device,
+            Some("emissive_material_bind_group"),
+            self.material_layout_id,
+            &self.material_layout,
+            &[
+                CachedBinding {
+                    binding: 0,
+                    id,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                CachedBinding {
+                    binding: 1,
+                    id,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+                CachedBinding {
+                    binding: 2,
+                    id,
+                    resource: wgpu::BindingResource::TextureView(&emissive.view),
+                },
+                CachedBinding {
+                    binding: 3,
+                    id,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+        )
+    }
+
+    pub fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+}
@@ -0,0 +1,121 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use glam::Vec2;
+use winit::dpi::PhysicalSize;
+use winit::event::{ElementState, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent};
+
+use crate::screen::Screen;
+
+// Engine events anything can subscribe to, handed out by run() as they come
+// in from the window
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Resized {
+    pub physical_size: PhysicalSize<u32>,
+    pub scale_factor: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyInput {
+    pub key: VirtualKeyCode,
+    pub pressed: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MouseInput {
+    pub button: MouseButton,
+    pub pressed: bool,
+}
+
+// Logical pixels, None when the cursor left the window
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CursorMoved(pub Option<Vec2>);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileDropped(pub PathBuf);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Focused(pub bool);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+type Callback<E> = Box<dyn FnMut(&E)>;
+
+// Holds a Callback<E> for the E its TypeId belongs to
+type Subscriber = (SubscriptionId, Box<dyn Any>);
+
+// Callbacks by event type
+#[derive(Default)]
+pub struct EventBus {
+    subscribers: HashMap<TypeId, Vec<Subscriber>>,
+    next_id: u64,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn subscribe<E: 'static>(&mut self, callback: impl FnMut(&E) + 'static) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id);
+        self.next_id += 1;
+        let callback: Callback<E> = Box::new(callback);
+        self.subscribers.entry(TypeId::of::<E>()).or_default().push((id, Box::new(callback)));
+        id
+    }
+
+    pub fn unsubscribe(&mut self, id: SubscriptionId) {
+        for subscribers in self.subscribers.values_mut() {
+            subscribers.retain(|(subscription, _)| *subscription != id);
+        }
+    }
+
+    // Calls every subscriber of E, in the order they subscribed
+    pub fn emit<E: 'static>(&mut self, event: &E) {
+        let Some(subscribers) = self.subscribers.get_mut(&TypeId::of::<E>()) else {
+            return;
+        };
+        for (_, callback) in subscribers {
+            if let Some(callback) = callback.downcast_mut::<Callback<E>>() {
+                callback(event);
+            }
+        }
+    }
+
+    // Turns a window event into whichever engine events it stands for
+    pub fn emit_window_event(&mut self, event: &WindowEvent, screen: &Screen) {
+        match event {
+            WindowEvent::Resized(physical_size) => self.emit(&Resized {
+                physical_size: *physical_size,
+                scale_factor: screen.scale_factor,
+            }),
+            WindowEvent::ScaleFactorChanged { scale_factor, new_inner_size } => self.emit(&Resized {
+                physical_size: **new_inner_size,
+                scale_factor: *scale_factor,
+            }),
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state,
+                    virtual_keycode: Some(key),
+                    ..
+                },
+                ..
+            } => self.emit(&KeyInput {
+                key: *key,
+                pressed: *state == ElementState::Pressed,
+            }),
+            WindowEvent::MouseInput { state, button, .. } => self.emit(&MouseInput {
+                button: *button,
+                pressed: *state == ElementState::Pressed,
+            }),
+            WindowEvent::CursorMoved { position, .. } => self.emit(&CursorMoved(Some(screen.to_logical(*position)))),
+            WindowEvent::CursorLeft { .. } => self.emit(&CursorMoved(None)),
+            WindowEvent::DroppedFile(path) => self.emit(&FileDropped(path.clone())),
+            WindowEvent::Focused(focused) => self.emit(&Focused(*focused)),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,307 @@
+use std::f32::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+use crate::upload::Uploader;
+
+// The illuminance of a clear day's sun, in lux. Light in physical units
+// (see LightUnits in scene.rs) is divided by it before shading, so a noon
+// sun comes out as 1 like the scenes in relative units have it and the rest
+// of the renderer keeps working with the numbers it was tuned for.
+pub const DAYLIGHT_LUX: f32 = 100_000.0;
+
+// The luminance, in candela per square metre, of what comes out as 1 in the
+// HDR scene: a white surface facing the DAYLIGHT_LUX sun
+pub const LUMINANCE_UNIT: f32 = DAYLIGHT_LUX / PI;
+
+// A camera's settings, for exposing the scene like a photo of it would be.
+// Each stop of aperture, halving of the shutter speed or halving of the ISO
+// takes half the light. The default is the "sunny 16" rule, right for a
+// scene in daylight.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraExposure {
+    // The f-number, bigger lets less light in
+    pub aperture: f32,
+    // How long the shutter is open, in seconds
+    pub shutter_speed: f32,
+    // The sensor's sensitivity
+    pub iso: f32,
+}
+
+impl Default for CameraExposure {
+    fn default() -> Self {
+        Self {
+            aperture: 16.0,
+            shutter_speed: 1.0 / 125.0,
+            iso: 100.0,
+        }
+    }
+}
+
+impl CameraExposure {
+    pub fn new(aperture: f32, shutter_speed: f32, iso: f32) -> Self {
+        Self {
+            aperture,
+            shutter_speed,
+            iso,
+        }
+    }
+
+    // The exposure value these settings are at, as the equivalent at ISO
+    // 100. Higher needs more light, daylight is around 15 and a lit room
+    // around 7.
+    pub fn ev100(&self) -> f32 {
+        let aperture = self.aperture.max(0.1);
+        let shutter_speed = self.shutter_speed.max(1e-6);
+        let iso = self.iso.max(1.0);
+        (aperture * aperture / shutter_speed * 100.0 / iso).log2()
+    }
+
+    // What the HDR scene gets multiplied by. The sensor saturates at a
+    // luminance of 1.2 * 2^EV100 (the standard's saturation based
+    // sensitivity), which is made to come out as 1.
+    pub fn exposure(&self) -> f32 {
+        LUMINANCE_UNIT / (1.2 * 2f32.powf(self.ev100()))
+    }
+}
+
+// How automatic exposure behaves, see Exposure::Auto in app.rs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AutoExposure {
+    // The range of luminance it adapts over, as log2. Scenes darker or
+    // brighter than this stay under or over exposed.
+    pub min_log_luminance: f32,
+    pub max_log_luminance: f32,
+    // Roughly how many times a second exposure catches up with the scene
+    // getting brighter and darker. Eyes adjust to light faster than to dark.
+    pub speed_up: f32,
+    pub speed_down: f32,
+    // In stops, positive brightens everything
+    pub compensation: f32,
+}
+
+impl Default for AutoExposure {
+    fn default() -> Self {
+        Self {
+            min_log_luminance: -8.0,
+            max_log_luminance: 4.0,
+            speed_up: 3.0,
+            speed_down: 1.0,
+            compensation: 0.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ExposureParams {
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    speed_up: f32,
+    speed_down: f32,
+    compensation: f32,
+    delta_time: f32,
+    _padding: [f32; 2],
+}
+
+// Buckets in the luminance histogram, one per thread of the average pass
+const HISTOGRAM_BINS: u64 = 256;
+
+// Eye adaptation as two compute passes over the HDR scene (see
+// shaders/exposure.wgsl). The first counts pixels into a histogram of log
+// luminance, the second averages it and eases the exposure towards what that
+// average needs. The result stays on the GPU and gets copied straight into
+// the tonemap pass's uniform, so nothing waits on a readback.
+//
+// Needs compute shaders, so it's left out on WebGL.
+pub struct EyeAdaptation {
+    bind_group_layout: wgpu::BindGroupLayout,
+    layout: wgpu::PipelineLayout,
+    histogram_pipeline: wgpu::ComputePipeline,
+    average_pipeline: wgpu::ComputePipeline,
+    histogram: wgpu::Buffer,
+    // Adapted luminance and exposure, two f32s
+    exposure: wgpu::Buffer,
+    params: wgpu::Buffer,
+}
+
+impl EyeAdaptation {
+    pub fn new(device: &wgpu::Device, shader_source: &str) -> Self {
+        let storage = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("exposure_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                storage(1),
+                storage(2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Exposure Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let [histogram_pipeline, average_pipeline] = Self::create_pipelines(device, &layout, shader_source);
+
+        // Buffers start out zeroed, which is an empty histogram and no
+        // luminance seen yet
+        let histogram = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Luminance Histogram"),
+            size: HISTOGRAM_BINS * 4,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let exposure = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Exposure Buffer"),
+            size: 8,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let params = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Exposure Params"),
+            size: std::mem::size_of::<ExposureParams>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Self {
+            bind_group_layout,
+            layout,
+            histogram_pipeline,
+            average_pipeline,
+            histogram,
+            exposure,
+            params,
+        }
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader_source: &str,
+    ) -> [wgpu::ComputePipeline; 2] {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Exposure Shader"),
+            source: wgpu::ShaderSource::Wgsl(shader_source.into()),
+        });
+        ["build_histogram", "average"].map(|entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(layout),
+                module: &shader,
+                entry_point,
+            })
+        })
+    }
+
+    // Called when the shader file changed, keeps the old pipelines if the new
+    // source doesn't compile
+    pub fn reload_shader(&mut self, device: &wgpu::Device, shader_source: &str) {
+        if let Some([histogram, average]) =
+            crate::shaders::try_create(device, || Self::create_pipelines(device, &self.layout, shader_source))
+        {
+            self.histogram_pipeline = histogram;
+            self.average_pipeline = average;
+        }
+    }
+
+    // Once a frame before run(). `reset` forgets what it adapted to, so the
+    // next frame jumps straight to the right exposure instead of easing in.
+    pub fn upload(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        uploader: &mut Uploader,
+        settings: &AutoExposure,
+        delta_time: f32,
+        reset: bool,
+    ) {
+        let params = ExposureParams {
+            min_log_luminance: settings.min_log_luminance,
+            log_luminance_range: (settings.max_log_luminance - settings.min_log_luminance).max(0.001),
+            speed_up: settings.speed_up,
+            speed_down: settings.speed_down,
+            compensation: settings.compensation,
+            delta_time,
+            _padding: [0.0; 2],
+        };
+        uploader.write(device, encoder, &self.params, 0, &[params]);
+        if reset {
+            uploader.write(device, encoder, &self.exposure, 0, &[0.0f32, 1.0]);
+        }
+    }
+
+    // Measures `scene`, which is `size`, and copies the new exposure into the
+    // first four bytes past `offset` in `target`
+    pub fn run(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        scene: &wgpu::TextureView,
+        size: (u32, u32),
+        target: &wgpu::Buffer,
+        offset: wgpu::BufferAddress,
+    ) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("exposure_bind_group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(scene),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: self.histogram.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.exposure.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: self.params.as_entire_binding(),
+                },
+            ],
+        });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Exposure Pass"),
+            });
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.set_pipeline(&self.histogram_pipeline);
+            pass.dispatch_workgroups(size.0.div_ceil(16), size.1.div_ceil(16), 1);
+            pass.set_pipeline(&self.average_pipeline);
+            pass.dispatch_workgroups(1, 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(&self.exposure, 4, target, offset, 4);
+    }
+}
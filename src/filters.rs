@@ -0,0 +1,310 @@
+use wgpu::util::DeviceExt;
+
+const SOURCE: &str = include_str!("shaders/filters.wgsl");
+
+// Largest blur radius in pixels, sigmas past a third of this get cut off
+const MAX_BLUR_RADIUS: i32 = 32;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct FilterParams {
+    direction: [i32; 2],
+    radius: i32,
+    sigma: f32,
+    amount: f32,
+    _padding: [f32; 3],
+}
+
+impl FilterParams {
+    fn new() -> Self {
+        Self {
+            direction: [0, 0],
+            radius: 0,
+            sigma: 1.0,
+            amount: 0.0,
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+// Blur, downsample, sharpen and edge detection as compute shaders (see
+// shaders/filters.wgsl). Each call records a compute pass into the encoder it
+// gets, so they fit into a render graph pass just as well as into an encoder
+// of your own:
+//
+//     graph.add_pass("blur").reads(&["scene"]).writes(&["blurred"]).execute(move |encoder, resources| {
+//         filters.blur(device, encoder, resources.view("scene"), &scratch, resources.view("blurred"), size, 4.0);
+//     });
+//
+// Sources can be any float texture that can be sampled. Targets are written
+// as storage textures, so they need STORAGE_BINDING and the format the
+// filters were created with, create_texture() makes one that fits. WebGL
+// has no compute and wgpu's GL backend can't bind storage textures, so these
+// need Vulkan, Metal, DX12 or WebGPU.
+pub struct ImageFilters {
+    bind_group_layout: wgpu::BindGroupLayout,
+    layout: wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    blur: wgpu::ComputePipeline,
+    downsample: wgpu::ComputePipeline,
+    sharpen: wgpu::ComputePipeline,
+    sobel: wgpu::ComputePipeline,
+}
+
+// What the format is called in WGSL, for the ones that can be written as
+// storage textures everywhere
+fn storage_format_name(format: wgpu::TextureFormat) -> Option<&'static str> {
+    match format {
+        wgpu::TextureFormat::Rgba8Unorm => Some("rgba8unorm"),
+        wgpu::TextureFormat::Rgba16Float => Some("rgba16float"),
+        wgpu::TextureFormat::Rgba32Float => Some("rgba32float"),
+        _ => None,
+    }
+}
+
+impl ImageFilters {
+    // `format` is what targets are written as, Rgba8Unorm, Rgba16Float or
+    // Rgba32Float. sRGB formats can't be storage textures, so filter in
+    // linear space and convert at the end.
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        assert!(
+            storage_format_name(format).is_some(),
+            "{:?} can't be written by the image filters",
+            format
+        );
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("filters_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        // Only ever loaded, so float formats that can't be
+                        // filtered work too
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Filters Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let [blur, downsample, sharpen, sobel] = Self::create_pipelines(device, &layout, format, SOURCE);
+        Self {
+            bind_group_layout,
+            layout,
+            format,
+            blur,
+            downsample,
+            sharpen,
+            sobel,
+        }
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        format: wgpu::TextureFormat,
+        shader_source: &str,
+    ) -> [wgpu::ComputePipeline; 4] {
+        // The shader is written for rgba16float targets
+        let format_name = storage_format_name(format).expect("checked in new()");
+        let source = shader_source.replace("rgba16float", format_name);
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Filters Shader"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        ["blur", "downsample", "sharpen", "sobel"].map(|entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(layout),
+                module: &shader,
+                entry_point,
+            })
+        })
+    }
+
+    // For apps watching their own copy of filters.wgsl, keeps the old
+    // pipelines if the new source doesn't compile
+    pub fn reload_shader(&mut self, device: &wgpu::Device, shader_source: &str) {
+        if let Some([blur, downsample, sharpen, sobel]) = crate::shaders::try_create(device, || {
+            Self::create_pipelines(device, &self.layout, self.format, shader_source)
+        }) {
+            self.blur = blur;
+            self.downsample = downsample;
+            self.sharpen = sharpen;
+            self.sobel = sobel;
+        }
+    }
+
+    pub fn format(&self) -> wgpu::TextureFormat {
+        self.format
+    }
+
+    // A texture the filters can write to, that can also be sampled and copied
+    // in and out of
+    pub fn create_texture(&self, device: &wgpu::Device, size: (u32, u32), label: &str) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: size.0,
+                height: size.1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.format,
+            usage: wgpu::TextureUsages::STORAGE_BINDING
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::COPY_DST,
+        })
+    }
+
+    // Gaussian blur with a standard deviation of `sigma` pixels, done as a
+    // horizontal pass into `scratch` and a vertical one into `target`. All
+    // three are `size`, and `source` can be the same texture as `target`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn blur(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        source: &wgpu::TextureView,
+        scratch: &wgpu::TextureView,
+        target: &wgpu::TextureView,
+        size: (u32, u32),
+        sigma: f32,
+    ) {
+        let sigma = sigma.max(0.01);
+        let radius = ((sigma * 3.0).ceil() as i32).min(MAX_BLUR_RADIUS);
+        let params = FilterParams {
+            radius,
+            sigma,
+            ..FilterParams::new()
+        };
+        let horizontal = FilterParams {
+            direction: [1, 0],
+            ..params
+        };
+        let vertical = FilterParams {
+            direction: [0, 1],
+            ..params
+        };
+        self.dispatch(device, encoder, &self.blur, source, scratch, size, horizontal);
+        self.dispatch(device, encoder, &self.blur, scratch, target, size, vertical);
+    }
+
+    // Halves the size, `target_size` being the size of `target`. Repeating
+    // this builds a chain for bloom and the like.
+    pub fn downsample(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        source: &wgpu::TextureView,
+        target: &wgpu::TextureView,
+        target_size: (u32, u32),
+    ) {
+        self.dispatch(device, encoder, &self.downsample, source, target, target_size, FilterParams::new());
+    }
+
+    // Sharpens by `amount`, somewhere around 0.5 to 2. Both textures are
+    // `size`.
+    pub fn sharpen(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        source: &wgpu::TextureView,
+        target: &wgpu::TextureView,
+        size: (u32, u32),
+        amount: f32,
+    ) {
+        let params = FilterParams {
+            amount,
+            ..FilterParams::new()
+        };
+        self.dispatch(device, encoder, &self.sharpen, source, target, size, params);
+    }
+
+    // Edges as grey, brighter where the brightness changes faster. Both
+    // textures are `size`.
+    pub fn sobel(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        source: &wgpu::TextureView,
+        target: &wgpu::TextureView,
+        size: (u32, u32),
+    ) {
+        self.dispatch(device, encoder, &self.sobel, source, target, size, FilterParams::new());
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn dispatch(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::ComputePipeline,
+        source: &wgpu::TextureView,
+        target: &wgpu::TextureView,
+        target_size: (u32, u32),
+        params: FilterParams,
+    ) {
+        // A small buffer per dispatch, so several filters can be recorded
+        // into the same encoder with different parameters
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Filter Params"),
+            contents: bytemuck::bytes_of(&params),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("filters_bind_group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(target),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+        });
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Filter Pass"),
+        });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.dispatch_workgroups(target_size.0.div_ceil(8), target_size.1.div_ceil(8), 1);
+    }
+}
@@ -0,0 +1,443 @@
+use std::num::NonZeroU32;
+
+use glam::{Mat4, Vec3};
+
+use crate::upload::Uploader;
+
+// How volumetric fog looks, see RenderSettings::fog in app.rs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FogSettings {
+    // How much light the fog takes out per world unit at base_height
+    pub density: f32,
+    // How quickly it thins out going up from base_height, below it the fog
+    // stays as thick as at base_height
+    pub height_falloff: f32,
+    pub base_height: f32,
+    // How far from the camera the fog goes. Beyond that, and in the sky,
+    // things are fogged as if they were this far away.
+    pub range: f32,
+    // Between -1 and 1. Towards 1 the fog glows brighter looking towards the
+    // sun, which is what makes light shafts stand out.
+    pub anisotropy: f32,
+    // Towards the sun, and its colour times brightness. The scene's first
+    // directional light takes over from these when there is one.
+    pub sun_direction: Vec3,
+    pub sun_color: Vec3,
+    // Light reaching the fog from the sky, all around
+    pub ambient: Vec3,
+}
+
+impl Default for FogSettings {
+    fn default() -> Self {
+        Self {
+            density: 0.02,
+            height_falloff: 0.15,
+            base_height: 0.0,
+            range: 100.0,
+            anisotropy: 0.6,
+            sun_direction: Vec3::new(0.4, 0.8, 0.3).normalize(),
+            sun_color: Vec3::new(4.0, 3.6, 3.0),
+            ambient: Vec3::new(0.3, 0.4, 0.55),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct FogParams {
+    inverse_view_proj: [[f32; 4]; 4],
+    view_proj: [[f32; 4]; 4],
+    eye: [f32; 3],
+    near: f32,
+    sun_direction: [f32; 3],
+    far: f32,
+    sun_color: [f32; 3],
+    density: f32,
+    ambient: [f32; 3],
+    height_falloff: f32,
+    base_height: f32,
+    anisotropy: f32,
+    _padding: [f32; 2],
+}
+
+// Froxels across, down and deep, the same as GRID in fog.wgsl. Rows of the
+// 3D texture get copied from a buffer, so 8 bytes times the width has to
+// be a multiple of 256.
+const GRID: [u32; 3] = [160, 90, 64];
+// Where the first slice starts, in world units from the camera
+const NEAR: f32 = 0.5;
+
+// Fog lit by the sun, worked out in a grid of froxels that follows the
+// camera (see shaders/fog.wgsl). One compute pass fills each froxel with how
+// thick the fog is there and how much sunlight it catches, another adds them
+// up front to back into a 3D texture, and a fullscreen pass then fogs the
+// HDR scene by looking up that texture at each pixel's depth before it gets
+// tonemapped. Froxels the sun can't reach, going by the depth buffer, catch
+// none of its light, which leaves light shafts behind whatever is in the way.
+//
+// Needs compute shaders, so it's left out on WebGL.
+pub struct VolumetricFog {
+    compute_layout: wgpu::BindGroupLayout,
+    composite_layout: wgpu::BindGroupLayout,
+    compute_pipeline_layout: wgpu::PipelineLayout,
+    composite_pipeline_layout: wgpu::PipelineLayout,
+    scatter_pipeline: wgpu::ComputePipeline,
+    integrate_pipeline: wgpu::ComputePipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+    hdr_format: wgpu::TextureFormat,
+    params: wgpu::Buffer,
+    params_bind_group: wgpu::BindGroup,
+    froxels: wgpu::Buffer,
+    integrated: wgpu::Buffer,
+    volume: wgpu::Texture,
+    volume_view: wgpu::TextureView,
+    sampler: wgpu::Sampler,
+}
+
+impl VolumetricFog {
+    pub fn new(device: &wgpu::Device, hdr_format: wgpu::TextureFormat, shader_source: &str) -> Self {
+        let params_entry = |visibility| wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let texture_entry = |binding, visibility| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            },
+            count: None,
+        };
+        let storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let compute_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("fog_compute_bind_group_layout"),
+            entries: &[
+                params_entry(wgpu::ShaderStages::COMPUTE),
+                // The depth buffer
+                texture_entry(1, wgpu::ShaderStages::COMPUTE),
+                storage_entry(2),
+                storage_entry(3),
+            ],
+        });
+        let params_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("fog_params_bind_group_layout"),
+            entries: &[params_entry(wgpu::ShaderStages::FRAGMENT)],
+        });
+        let composite_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("fog_composite_bind_group_layout"),
+            entries: &[
+                texture_entry(0, wgpu::ShaderStages::FRAGMENT),
+                texture_entry(1, wgpu::ShaderStages::FRAGMENT),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let compute_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Fog Compute Pipeline Layout"),
+            bind_group_layouts: &[&compute_layout],
+            push_constant_ranges: &[],
+        });
+        let composite_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Fog Composite Pipeline Layout"),
+            bind_group_layouts: &[&params_layout, &composite_layout],
+            push_constant_ranges: &[],
+        });
+        let (scatter_pipeline, integrate_pipeline, composite_pipeline) = Self::create_pipelines(
+            device,
+            &compute_pipeline_layout,
+            &composite_pipeline_layout,
+            hdr_format,
+            shader_source,
+        );
+
+        let params = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Fog Params"),
+            size: std::mem::size_of::<FogParams>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let params_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("fog_params_bind_group"),
+            layout: &params_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: params.as_entire_binding(),
+            }],
+        });
+        let froxel_count = (GRID[0] * GRID[1] * GRID[2]) as wgpu::BufferAddress;
+        let froxels = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Froxels"),
+            size: froxel_count * 16,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let integrated = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Integrated Froxels"),
+            size: froxel_count * 8,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let volume = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Fog Volume"),
+            size: wgpu::Extent3d {
+                width: GRID[0],
+                height: GRID[1],
+                depth_or_array_layers: GRID[2],
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format: wgpu::TextureFormat::Rgba16Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        });
+        let volume_view = volume.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            compute_layout,
+            composite_layout,
+            compute_pipeline_layout,
+            composite_pipeline_layout,
+            scatter_pipeline,
+            integrate_pipeline,
+            composite_pipeline,
+            hdr_format,
+            params,
+            params_bind_group,
+            froxels,
+            integrated,
+            volume,
+            volume_view,
+            sampler,
+        }
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        compute_layout: &wgpu::PipelineLayout,
+        composite_layout: &wgpu::PipelineLayout,
+        hdr_format: wgpu::TextureFormat,
+        shader_source: &str,
+    ) -> (wgpu::ComputePipeline, wgpu::ComputePipeline, wgpu::RenderPipeline) {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Fog Shader"),
+            source: wgpu::ShaderSource::Wgsl(shader_source.into()),
+        });
+        let [scatter, integrate] = ["scatter", "integrate"].map(|entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(compute_layout),
+                module: &shader,
+                entry_point,
+            })
+        });
+        let composite = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Fog Composite Pipeline"),
+            layout: Some(composite_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: hdr_format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        (scatter, integrate, composite)
+    }
+
+    // Called when the shader file changed, keeps the old pipelines if the new
+    // source doesn't compile
+    pub fn reload_shader(&mut self, device: &wgpu::Device, shader_source: &str) {
+        if let Some((scatter, integrate, composite)) = crate::shaders::try_create(device, || {
+            Self::create_pipelines(
+                device,
+                &self.compute_pipeline_layout,
+                &self.composite_pipeline_layout,
+                self.hdr_format,
+                shader_source,
+            )
+        }) {
+            self.scatter_pipeline = scatter;
+            self.integrate_pipeline = integrate;
+            self.composite_pipeline = composite;
+        }
+    }
+
+    // Once a frame before run(), with the camera the scene is drawn from
+    pub fn upload(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        uploader: &mut Uploader,
+        settings: &FogSettings,
+        eye: Vec3,
+        view_proj: Mat4,
+    ) {
+        let params = FogParams {
+            inverse_view_proj: view_proj.inverse().to_cols_array_2d(),
+            view_proj: view_proj.to_cols_array_2d(),
+            eye: eye.into(),
+            near: NEAR,
+            sun_direction: settings.sun_direction.normalize_or_zero().into(),
+            far: settings.range.max(NEAR * 2.0),
+            sun_color: settings.sun_color.into(),
+            density: settings.density.max(0.0),
+            ambient: settings.ambient.into(),
+            height_falloff: settings.height_falloff.max(0.0),
+            base_height: settings.base_height,
+            anisotropy: settings.anisotropy.clamp(-0.99, 0.99),
+            _padding: [0.0; 2],
+        };
+        uploader.write(device, encoder, &self.params, 0, &[params]);
+    }
+
+    // Fills the froxels from `depth` and draws `scene` fogged into `target`,
+    // all three the same size
+    pub fn run(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        scene: &wgpu::TextureView,
+        depth: &wgpu::TextureView,
+        target: &wgpu::TextureView,
+    ) {
+        let compute_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("fog_compute_bind_group"),
+            layout: &self.compute_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.params.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(depth),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.froxels.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: self.integrated.as_entire_binding(),
+                },
+            ],
+        });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Fog Pass"),
+            });
+            pass.set_bind_group(0, &compute_bind_group, &[]);
+            pass.set_pipeline(&self.scatter_pipeline);
+            pass.dispatch_workgroups(GRID[0].div_ceil(8), GRID[1].div_ceil(8), GRID[2]);
+            pass.set_pipeline(&self.integrate_pipeline);
+            pass.dispatch_workgroups(GRID[0].div_ceil(8), GRID[1].div_ceil(8), 1);
+        }
+        // Into a texture for the composite pass, which gets trilinear
+        // filtering between froxels for free that way
+        encoder.copy_buffer_to_texture(
+            wgpu::ImageCopyBuffer {
+                buffer: &self.integrated,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(GRID[0] * 8),
+                    rows_per_image: NonZeroU32::new(GRID[1]),
+                },
+            },
+            self.volume.as_image_copy(),
+            wgpu::Extent3d {
+                width: GRID[0],
+                height: GRID[1],
+                depth_or_array_layers: GRID[2],
+            },
+        );
+
+        let composite_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("fog_composite_bind_group"),
+            layout: &self.composite_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(scene),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(depth),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&self.volume_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        });
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Fog Composite Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.composite_pipeline);
+        render_pass.set_bind_group(0, &self.params_bind_group, &[]);
+        render_pass.set_bind_group(1, &composite_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
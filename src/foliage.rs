@@ -0,0 +1,449 @@
+use anyhow::{ensure, Result};
+use glam::{Mat4, Vec2, Vec3};
+use wgpu::util::DeviceExt;
+
+use crate::bounds::{Aabb, Frustum};
+use crate::noise::hash;
+use crate::sky::{Lighting, LightingUniform};
+use crate::terrain::Terrain;
+use crate::texture::Texture;
+use crate::upload::AppUploader;
+use crate::vertex::VertexLayout;
+
+const SOURCE: &str = include_str!("shaders/foliage.wgsl");
+
+crate::vertex_layout! {
+    #[derive(Debug)]
+    struct TuftVertex {
+        #[location(0)] corner: [f32; 2],
+    }
+}
+
+crate::vertex_layout! {
+    step_mode: Instance,
+    #[derive(Debug, PartialEq)]
+    pub struct Tuft {
+        #[location(1)] pub position: [f32; 3],
+        #[location(2)] pub size: [f32; 2],
+        #[location(3)] pub seed: f32,
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct FoliageUniform {
+    eye: [f32; 3],
+    fade_start: f32,
+    wind: [f32; 2],
+    fade_end: f32,
+    _padding: f32,
+    lighting: LightingUniform,
+}
+
+// How much grows where, from 0 for bare ground to 1 for as thick as the
+// spacing allows. Stretched over the whole terrain, row by row.
+#[derive(Clone, Debug)]
+pub struct DensityMap {
+    width: u32,
+    depth: u32,
+    values: Vec<f32>,
+}
+
+impl DensityMap {
+    pub fn new(width: u32, depth: u32, values: Vec<f32>) -> Result<Self> {
+        ensure!(width >= 1 && depth >= 1, "density maps need at least one value");
+        ensure!(values.len() == (width * depth) as usize, "expected {} values", width * depth);
+        Ok(Self { width, depth, values })
+    }
+
+    // Greyscale, white growing the most
+    pub fn from_image(image: &image::DynamicImage) -> Result<Self> {
+        let luma = image.to_luma8();
+        let values = luma.pixels().map(|p| p.0[0] as f32 / 255.0).collect();
+        Self::new(luma.width(), luma.height(), values)
+    }
+
+    // Calls `density` with the uv of every value, 0 to 1 across the terrain
+    pub fn from_fn(width: u32, depth: u32, density: impl Fn(Vec2) -> f32) -> Self {
+        let mut values = Vec::with_capacity((width * depth) as usize);
+        let last = Vec2::new((width - 1).max(1) as f32, (depth - 1).max(1) as f32);
+        for z in 0..depth {
+            for x in 0..width {
+                let uv = Vec2::new(x as f32, z as f32) / last;
+                values.push(density(uv).clamp(0.0, 1.0));
+            }
+        }
+        Self { width, depth, values }
+    }
+
+    // Grass on the flat ground between `min_height` and the snow line,
+    // thinning out on slopes. With the water level as `min_height` nothing
+    // grows under water.
+    pub fn from_terrain(terrain: &Terrain, min_height: f32) -> Self {
+        let config = terrain.config();
+        let heightmap = terrain.heightmap();
+        let (width, depth) = heightmap.size();
+        let spacing = config.size / (width.max(depth) - 1) as f32;
+        Self::from_fn(width, depth, |uv| {
+            let (x, z) = ((uv.x * (width - 1) as f32) as u32, (uv.y * (depth - 1) as f32) as u32);
+            let height = heightmap.get(x, z);
+            let slope = Vec2::new(
+                heightmap.get(x + 1, z) - heightmap.get(x.saturating_sub(1), z),
+                heightmap.get(x, z + 1) - heightmap.get(x, z.saturating_sub(1)),
+            ) * config.height
+                / (2.0 * spacing);
+            let world_height = config.origin.y + height * config.height;
+            let flat = 1.0 - smoothstep(0.4, 0.8, slope.length());
+            let dry = 1.0 - smoothstep(0.55, 0.7, height);
+            let wet = smoothstep(min_height, min_height + 0.5, world_height);
+            flat * dry * wet
+        })
+    }
+
+    // Bilinear between the values
+    pub fn sample(&self, uv: Vec2) -> f32 {
+        let size = Vec2::new((self.width - 1) as f32, (self.depth - 1) as f32);
+        let texel = (uv * size).clamp(Vec2::ZERO, size);
+        let (x, z) = (texel.x as u32, texel.y as u32);
+        let t = texel - texel.floor();
+        let get = |x: u32, z: u32| self.values[(z.min(self.depth - 1) * self.width + x.min(self.width - 1)) as usize];
+        let top = get(x, z) + (get(x + 1, z) - get(x, z)) * t.x;
+        let bottom = get(x, z + 1) + (get(x + 1, z + 1) - get(x, z + 1)) * t.x;
+        top + (bottom - top) * t.y
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FoliageConfig {
+    // World units between tufts where the density is 1
+    pub spacing: f32,
+    // Tufts vary between half and all of this
+    pub size: Vec2,
+    // Tufts shrink away between these distances from the camera. Past
+    // fade_end nothing is drawn at all.
+    pub fade_start: f32,
+    pub fade_end: f32,
+    // Which way the wind blows and how far it pushes the tips, as a
+    // fraction of their height
+    pub wind: Vec2,
+    // Width of the square patches tufts get culled in
+    pub patch_size: f32,
+    pub seed: u32,
+}
+
+impl Default for FoliageConfig {
+    fn default() -> Self {
+        Self {
+            spacing: 0.35,
+            size: Vec2::new(0.6, 0.5),
+            fade_start: 25.0,
+            fade_end: 40.0,
+            wind: Vec2::new(0.25, 0.1),
+            patch_size: 8.0,
+            seed: 1,
+        }
+    }
+}
+
+// A run of tufts in the instance buffer that sit in the same patch
+struct Patch {
+    bounds: Aabb,
+    first: u32,
+    count: u32,
+}
+
+// Grass tufts scattered over a terrain, as many as a density map allows.
+// Tufts are placed once, each on a jittered grid so they don't line up, and
+// kept in the instance buffer patch by patch. Every frame only the patches
+// that are in view and closer than fade_end get drawn, one draw each, so the
+// wind animation and fading all happen in the vertex shader.
+//
+// Every frame: prepare() with the camera, then draw() after the terrain in
+// the same pass, like TerrainApp does.
+pub struct Foliage {
+    config: FoliageConfig,
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    uniform: FoliageUniform,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    index_count: u32,
+    instance_buffer: wgpu::Buffer,
+    patches: Vec<Patch>,
+    // Indices into patches, picked by the last prepare()
+    visible: Vec<usize>,
+}
+
+impl Foliage {
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        camera_layout: &wgpu::BindGroupLayout,
+        frame_layout: &wgpu::BindGroupLayout,
+        terrain: &Terrain,
+        density: &DensityMap,
+        config: FoliageConfig,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("foliage_bind_group_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let uniform = FoliageUniform {
+            eye: [0.0; 3],
+            fade_start: config.fade_start,
+            wind: config.wind.into(),
+            fade_end: config.fade_end,
+            _padding: 0.0,
+            lighting: Lighting::default().into(),
+        };
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Foliage Buffer"),
+            contents: bytemuck::bytes_of(&uniform),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("foliage_bind_group"),
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Foliage Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, frame_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        // A strip four quads tall, so the wind can curve it
+        let segments = 4;
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        for i in 0..=segments {
+            let y = i as f32 / segments as f32;
+            vertices.push(TuftVertex { corner: [-0.5, y] });
+            vertices.push(TuftVertex { corner: [0.5, y] });
+            if i < segments {
+                let base = i * 2;
+                indices.extend([base, base + 1, base + 2, base + 2, base + 1, base + 3]);
+            }
+        }
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Foliage Vertex Buffer"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Foliage Index Buffer"),
+            contents: bytemuck::cast_slice(&indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        let (tufts, patches) = scatter(terrain, density, &config);
+        // wgpu doesn't allow empty buffers
+        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Foliage Instance Buffer"),
+            contents: if tufts.is_empty() {
+                &[0; std::mem::size_of::<Tuft>()]
+            } else {
+                bytemuck::cast_slice(&tufts)
+            },
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        tracing::info!("Scattered {} grass tufts in {} patches", tufts.len(), patches.len());
+
+        Self {
+            config,
+            pipeline: Self::create_pipeline(device, &layout, color_format, SOURCE),
+            layout,
+            color_format,
+            uniform,
+            buffer,
+            bind_group,
+            vertex_buffer,
+            index_buffer,
+            index_count: indices.len() as u32,
+            instance_buffer,
+            patches,
+            visible: Vec::new(),
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        shader_source: &str,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Foliage Shader"),
+            source: wgpu::ShaderSource::Wgsl(shader_source.into()),
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Foliage Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[TuftVertex::desc(), Tuft::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    // Cut out with discard, so no sorting is needed
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            // Tufts face the camera, but the wind can tip them past it
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+
+    // For apps watching their own copy of foliage.wgsl, keeps the old
+    // pipeline if the new source doesn't compile
+    pub fn reload_shader(&mut self, device: &wgpu::Device, shader_source: &str) {
+        if let Some(pipeline) = crate::shaders::try_create(device, || {
+            Self::create_pipeline(device, &self.layout, self.color_format, shader_source)
+        }) {
+            self.pipeline = pipeline;
+        }
+    }
+
+    pub fn config(&self) -> &FoliageConfig {
+        &self.config
+    }
+
+    pub fn set_wind(&mut self, wind: Vec2) {
+        self.config.wind = wind;
+        self.uniform.wind = wind.into();
+    }
+
+    // Tufts the last prepare() picked to draw
+    pub fn visible_count(&self) -> u32 {
+        self.visible.iter().map(|&i| self.patches[i].count).sum()
+    }
+
+    // Picks the patches to draw from where the camera is, lit by `lighting`
+    pub fn prepare(&mut self, uploader: &AppUploader, eye: Vec3, view_proj: Mat4, lighting: &Lighting) {
+        let frustum = Frustum::from_view_proj(view_proj);
+        let fade_end = self.config.fade_end;
+        self.visible = (0..self.patches.len())
+            .filter(|&i| {
+                let bounds = &self.patches[i].bounds;
+                eye.clamp(bounds.min, bounds.max).distance(eye) < fade_end && frustum.intersects_aabb(bounds)
+            })
+            .collect();
+
+        self.uniform.eye = eye.into();
+        self.uniform.lighting = (*lighting).into();
+        uploader.write(&self.buffer, 0, &[self.uniform]);
+    }
+
+    // Expects the camera at group 0 and the frame at group 1
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera: &'a wgpu::BindGroup,
+        frame: &'a wgpu::BindGroup,
+    ) {
+        if self.visible.is_empty() {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera, &[]);
+        render_pass.set_bind_group(1, frame, &[]);
+        render_pass.set_bind_group(2, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        for &i in &self.visible {
+            let patch = &self.patches[i];
+            render_pass.draw_indexed(0..self.index_count, 0, patch.first..patch.first + patch.count);
+        }
+    }
+}
+
+// One tuft per cell of a `spacing` grid, moved somewhere random inside it
+// and kept with a chance of the density there. Patch by patch, so every
+// patch's tufts end up next to each other.
+fn scatter(terrain: &Terrain, density: &DensityMap, config: &FoliageConfig) -> (Vec<Tuft>, Vec<Patch>) {
+    let terrain_config = terrain.config();
+    let origin = Vec2::new(terrain_config.origin.x, terrain_config.origin.z);
+    let spacing = config.spacing.max(0.01);
+    let cells_per_patch = (config.patch_size / spacing).ceil().max(1.0) as i32;
+    let patch_count = (terrain_config.size / (cells_per_patch as f32 * spacing)).ceil() as i32;
+
+    let mut tufts = Vec::new();
+    let mut patches = Vec::new();
+    for patch_z in 0..patch_count {
+        for patch_x in 0..patch_count {
+            let first = tufts.len();
+            let mut bounds = Aabb::EMPTY;
+            for cell_z in patch_z * cells_per_patch..(patch_z + 1) * cells_per_patch {
+                for cell_x in patch_x * cells_per_patch..(patch_x + 1) * cells_per_patch {
+                    let jitter = Vec2::new(hash(cell_x, cell_z, 0, config.seed), hash(cell_x, cell_z, 1, config.seed));
+                    let point = origin + (Vec2::new(cell_x as f32, cell_z as f32) + jitter) * spacing;
+                    let uv = (point - origin) / terrain_config.size;
+                    if hash(cell_x, cell_z, 2, config.seed) >= density.sample(uv) {
+                        continue;
+                    }
+                    let Some(height) = terrain.height_at(point.x, point.y) else {
+                        continue;
+                    };
+                    let seed = hash(cell_x, cell_z, 3, config.seed);
+                    let size = config.size * (0.5 + 0.5 * hash(cell_x, cell_z, 4, config.seed));
+                    let position = Vec3::new(point.x, height, point.y);
+                    // Room for the tuft's height and the wind pushing it
+                    // sideways
+                    let reach = size.x.max(size.y) * (1.0 + config.wind.length());
+                    bounds = bounds.union(Aabb::new(
+                        position - Vec3::new(reach, 0.0, reach),
+                        position + Vec3::splat(reach),
+                    ));
+                    tufts.push(Tuft {
+                        position: position.into(),
+                        size: size.into(),
+                        seed,
+                    });
+                }
+            }
+            if tufts.len() > first {
+                patches.push(Patch {
+                    bounds,
+                    first: first as u32,
+                    count: (tufts.len() - first) as u32,
+                });
+            }
+        }
+    }
+    (tufts, patches)
+}
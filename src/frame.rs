@@ -0,0 +1,111 @@
+use glam::Vec2;
+
+use crate::upload::Uploader;
+
+// Per-frame values every pipeline can read, for animated shaders. It's bound
+// as group 2 of the mesh and sprite pipelines and group 1 of tonemap, declared
+// in WGSL as
+//
+//     struct Frame {
+//         time: f32,
+//         delta_time: f32,
+//         resolution: vec2<f32>,
+//         mouse: vec2<f32>,
+//         frame: u32,
+//     };
+//     @group(2) @binding(0)
+//     var<uniform> frame: Frame;
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct FrameUniform {
+    // Seconds since startup
+    pub time: f32,
+    pub delta_time: f32,
+    // What the scene is drawn at, the surface's size in physical pixels
+    // except in pixel art mode (RenderSettings::pixel_art)
+    pub resolution: [f32; 2],
+    // The scene's pixels from the top left, like @builtin(position). Stays
+    // where the cursor last was when it leaves the window.
+    pub mouse: [f32; 2],
+    // Counts up by one every frame
+    pub frame: u32,
+    // Uniforms get padded to 16 bytes
+    _padding: u32,
+}
+
+pub struct FrameGlobals {
+    uniform: FrameUniform,
+    buffer: wgpu::Buffer,
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+}
+
+impl FrameGlobals {
+    pub fn new(device: &wgpu::Device) -> Self {
+        use wgpu::util::DeviceExt;
+
+        let uniform = FrameUniform::default();
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Frame Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("frame_bind_group_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        // Nothing in it ever gets recreated, so there's no need for the cache
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("frame_bind_group"),
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+        Self {
+            uniform,
+            buffer,
+            layout,
+            bind_group,
+        }
+    }
+
+    pub fn uniform(&self) -> &FrameUniform {
+        &self.uniform
+    }
+
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    // `resolution` is what the scene is drawn at and `mouse` is in its
+    // pixels, see FrameUniform
+    pub fn update(&mut self, dt: f32, resolution: (u32, u32), mouse: Option<Vec2>) {
+        let uniform = &mut self.uniform;
+        uniform.time += dt;
+        uniform.delta_time = dt;
+        uniform.resolution = [resolution.0 as f32, resolution.1 as f32];
+        if let Some(mouse) = mouse {
+            uniform.mouse = mouse.into();
+        }
+        uniform.frame = uniform.frame.wrapping_add(1);
+    }
+
+    pub fn upload(&self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, uploader: &mut Uploader) {
+        uploader.write(device, encoder, &self.buffer, 0, &[self.uniform]);
+    }
+}
@@ -0,0 +1,232 @@
+use glam::{Quat, Vec3};
+
+use crate::bounds::Ray;
+use crate::lines::LineBatch;
+use crate::transform::Transform;
+
+// Sent after a gizmo drag changed a scene entity. `transform` is the entity's
+// new local transform, the same thing that ends up in the scene file. Never
+// sent with the ecs feature, which has no gizmo.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TransformEdited {
+    pub entity: usize,
+    pub transform: Transform,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GizmoMode {
+    #[default]
+    Translate,
+    Rotate,
+    Scale,
+}
+
+const AXIS_COLORS: [[f32; 4]; 3] = [[0.9, 0.2, 0.2, 1.0], [0.3, 0.85, 0.3, 1.0], [0.25, 0.45, 1.0, 1.0]];
+const ACTIVE_COLOR: [f32; 4] = [1.0, 0.85, 0.1, 1.0];
+// Fraction of the distance to the camera, so the gizmo stays about the same
+// size on screen
+const SCREEN_SIZE: f32 = 0.2;
+// How close to a handle counts as on it, relative to the gizmo's size
+const GRAB_DISTANCE: f32 = 0.07;
+const CIRCLE_SEGMENTS: usize = 48;
+
+// Handles for moving, rotating and scaling one object along an axis at a
+// time. Works on world transforms: give it the object's transform and a ray
+// from the cursor, and it gives back where the object should be while
+// dragging. Translate and rotate go along the world axes, scale along the
+// object's own.
+#[derive(Clone, Debug, Default)]
+pub struct Gizmo {
+    pub mode: GizmoMode,
+    drag: Option<Drag>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Drag {
+    mode: GizmoMode,
+    axis: usize,
+    direction: Vec3,
+    start: Transform,
+    // Where on the axis (translate, scale) or the circle's plane (rotate)
+    // the drag started
+    grab: Vec3,
+}
+
+impl Gizmo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    fn size(transform: &Transform, eye: Vec3) -> f32 {
+        eye.distance(transform.translation).max(0.001) * SCREEN_SIZE
+    }
+
+    fn axes(&self, transform: &Transform) -> [Vec3; 3] {
+        match self.mode {
+            GizmoMode::Scale => [Vec3::X, Vec3::Y, Vec3::Z].map(|axis| transform.rotation * axis),
+            GizmoMode::Translate | GizmoMode::Rotate => [Vec3::X, Vec3::Y, Vec3::Z],
+        }
+    }
+
+    // The handle under the ray, if any, and where the ray meets it
+    fn hit(&self, ray: &Ray, transform: &Transform, eye: Vec3) -> Option<(usize, Vec3)> {
+        let center = transform.translation;
+        let size = Self::size(transform, eye);
+        let grab = size * GRAB_DISTANCE;
+        let mut closest: Option<(usize, Vec3, f32)> = None;
+        for (axis, direction) in self.axes(transform).into_iter().enumerate() {
+            let point = match self.mode {
+                GizmoMode::Translate | GizmoMode::Scale => {
+                    let Some(along) = closest_on_line(ray, center, direction) else {
+                        continue;
+                    };
+                    let point = center + direction * along.clamp(0.0, size);
+                    let distance = point_ray_distance(ray, point);
+                    if distance > grab {
+                        continue;
+                    }
+                    point
+                }
+                GizmoMode::Rotate => {
+                    let Some(point) = plane_hit(ray, center, direction) else {
+                        continue;
+                    };
+                    if (point.distance(center) - size).abs() > grab {
+                        continue;
+                    }
+                    point
+                }
+            };
+            let t = (point - ray.origin).dot(ray.direction);
+            if closest.is_none_or(|(.., closest)| t < closest) {
+                closest = Some((axis, point, t));
+            }
+        }
+        closest.map(|(axis, point, _)| (axis, point))
+    }
+
+    // Starts dragging if the ray is on one of the handles
+    pub fn begin_drag(&mut self, ray: &Ray, transform: &Transform, eye: Vec3) -> bool {
+        let Some((axis, grab)) = self.hit(ray, transform, eye) else {
+            return false;
+        };
+        self.drag = Some(Drag {
+            mode: self.mode,
+            axis,
+            direction: self.axes(transform)[axis],
+            start: *transform,
+            grab,
+        });
+        true
+    }
+
+    // The transform the object should have with the cursor's ray here, None
+    // if nothing is being dragged or the ray is too edge on to tell
+    pub fn drag(&self, ray: &Ray) -> Option<Transform> {
+        let Drag { mode, axis, direction, start, grab } = self.drag?;
+        let center = start.translation;
+        let mut transform = start;
+        match mode {
+            GizmoMode::Translate => {
+                let along = closest_on_line(ray, center, direction)?;
+                transform.translation = center + direction * (along - (grab - center).dot(direction));
+            }
+            GizmoMode::Scale => {
+                let along = closest_on_line(ray, center, direction)?;
+                let from = (grab - center).dot(direction);
+                if from.abs() <= f32::EPSILON {
+                    return None;
+                }
+                transform.scale[axis] = (start.scale[axis] * along / from).max(0.001);
+            }
+            GizmoMode::Rotate => {
+                let point = plane_hit(ray, center, direction)?;
+                let (from, to) = (grab - center, point - center);
+                let angle = direction.dot(from.cross(to)).atan2(from.dot(to));
+                transform.rotation = (Quat::from_axis_angle(direction, angle) * start.rotation).normalize();
+            }
+        }
+        Some(transform)
+    }
+
+    pub fn end_drag(&mut self) {
+        self.drag = None;
+    }
+
+    // Handles for an object at `transform`, on top of everything
+    pub fn draw(&self, lines: &mut LineBatch, transform: &Transform, eye: Vec3) {
+        let center = transform.translation;
+        let size = Self::size(transform, eye);
+        let axes = self.axes(transform);
+        for (axis, direction) in axes.into_iter().enumerate() {
+            let color = match self.drag {
+                Some(drag) if drag.axis == axis => ACTIVE_COLOR,
+                _ => AXIS_COLORS[axis],
+            };
+            // Two directions perpendicular to the axis
+            let (side, other) = (axes[(axis + 1) % 3], axes[(axis + 2) % 3]);
+            match self.mode {
+                GizmoMode::Translate => {
+                    let tip = center + direction * size;
+                    lines.line(center, tip, color, true);
+                    let base = tip - direction * size * 0.15;
+                    for offset in [side, -side, other, -other] {
+                        lines.line(tip, base + offset * size * 0.06, color, true);
+                    }
+                }
+                GizmoMode::Scale => {
+                    let tip = center + direction * size;
+                    lines.line(center, tip, color, true);
+                    // A small square across the end
+                    let half = size * 0.05;
+                    let corners = [side + other, side - other, -side - other, -side + other].map(|c| tip + c * half);
+                    for i in 0..4 {
+                        lines.line(corners[i], corners[(i + 1) % 4], color, true);
+                    }
+                }
+                GizmoMode::Rotate => {
+                    let point = |i: usize| {
+                        let angle = i as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU;
+                        center + (side * angle.cos() + other * angle.sin()) * size
+                    };
+                    for i in 0..CIRCLE_SEGMENTS {
+                        lines.line(point(i), point(i + 1), color, true);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// How far along the line through `origin` in `direction` the point closest
+// to the ray is. None when they're nearly parallel.
+fn closest_on_line(ray: &Ray, origin: Vec3, direction: Vec3) -> Option<f32> {
+    let d = ray.direction.normalize_or_zero();
+    let b = direction.dot(d);
+    let denominator = 1.0 - b * b;
+    if denominator < 1e-4 {
+        return None;
+    }
+    let w = origin - ray.origin;
+    Some((b * d.dot(w) - direction.dot(w)) / denominator)
+}
+
+fn point_ray_distance(ray: &Ray, point: Vec3) -> f32 {
+    let d = ray.direction.normalize_or_zero();
+    let t = (point - ray.origin).dot(d).max(0.0);
+    point.distance(ray.origin + d * t)
+}
+
+// Where the ray crosses the plane through `origin` facing `normal`
+fn plane_hit(ray: &Ray, origin: Vec3, normal: Vec3) -> Option<Vec3> {
+    let facing = ray.direction.dot(normal);
+    if facing.abs() < 1e-4 {
+        return None;
+    }
+    let t = (origin - ray.origin).dot(normal) / facing;
+    (t >= 0.0).then(|| ray.at(t))
+}
@@ -0,0 +1,126 @@
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use glam::{Mat3, Mat4, Vec3};
+
+use crate::mesh::Mesh;
+
+// Reads the meshes out of a glTF 2.0 model, either a .gltf file with its
+// buffers next to it or embedded as data URIs, or a single .glb. Like .obj
+// files only the geometry is kept: positions, normals, tangents, vertex
+// colors and both sets of texture coordinates. Every primitive a node in the
+// default scene uses becomes one mesh with the node's transform baked in, so
+// the model comes out the way it was laid out in the file.
+//
+// The gltf crate parses the file, loads the buffers and reads the accessors,
+// sparse ones included. Materials, skins, morph targets and animations
+// aren't kept, and primitives that aren't triangle lists are skipped.
+
+// Every mesh in a .gltf or .glb file, named after its node or its mesh
+pub fn read_gltf(path: &Path) -> Result<Vec<(String, Mesh)>> {
+    let ::gltf::Gltf { document, blob } =
+        ::gltf::Gltf::open(path).with_context(|| format!("parsing {}", path.display()))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    // Images aren't loaded, only the geometry is kept
+    let buffers = ::gltf::import_buffers(&document, Some(dir), blob)
+        .with_context(|| format!("loading buffers for {}", path.display()))?;
+
+    // The default scene's nodes, or without any scenes every node nothing
+    // else has as a child
+    let roots = match document.default_scene().or_else(|| document.scenes().next()) {
+        Some(scene) => scene.nodes().collect::<Vec<_>>(),
+        None => {
+            let children = document.nodes().flat_map(|node| node.children()).map(|child| child.index());
+            let children = children.collect::<Vec<_>>();
+            document.nodes().filter(|node| !children.contains(&node.index())).collect()
+        }
+    };
+
+    let mut meshes = Vec::new();
+    let node_count = document.nodes().len();
+    let mut stack = roots.into_iter().map(|node| (node, Mat4::IDENTITY, 0)).collect::<Vec<_>>();
+    while let Some((node, parent, depth)) = stack.pop() {
+        // Nodes form a tree, a cycle means the file is broken
+        if depth > node_count {
+            bail!("{} has a cycle in its node hierarchy", path.display());
+        }
+        let world = parent * Mat4::from_cols_array_2d(&node.transform().matrix());
+        if let Some(gltf_mesh) = node.mesh() {
+            let name = (node.name().or_else(|| gltf_mesh.name()).map(str::to_string))
+                .unwrap_or_else(|| format!("mesh{}", gltf_mesh.index()));
+            for (i, primitive) in gltf_mesh.primitives().enumerate() {
+                if primitive.mode() != ::gltf::mesh::Mode::Triangles {
+                    tracing::warn!("Skipping {} primitive {}, only triangle lists are supported", name, i);
+                    continue;
+                }
+                let mut mesh =
+                    read_primitive(&primitive, &buffers).with_context(|| format!("reading {} primitive {}", name, i))?;
+                transform_mesh(&mut mesh, world);
+                // Primitives after the first get numbered
+                let name = if i == 0 { name.clone() } else { format!("{}.{}", name, i) };
+                meshes.push((name, mesh));
+            }
+        }
+        stack.extend(node.children().collect::<Vec<_>>().into_iter().rev().map(|child| (child, world, depth + 1)));
+    }
+    Ok(meshes)
+}
+
+fn read_primitive(primitive: &::gltf::Primitive, buffers: &[::gltf::buffer::Data]) -> Result<Mesh> {
+    let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|data| &data.0[..]));
+    let positions = reader.read_positions().context("primitive has no positions")?.collect::<Vec<_>>();
+    let indices = match reader.read_indices() {
+        // Read as integers, floats can't hold every index past 2^24
+        Some(indices) => indices.into_u32().collect::<Vec<_>>(),
+        // Without indices every three vertices are a triangle
+        None => (0..positions.len() as u32).collect(),
+    };
+    if let Some(&bad) = indices.iter().find(|&&index| index as usize >= positions.len()) {
+        bail!("index {} is past the {} vertices", bad, positions.len());
+    }
+
+    let mut mesh = Mesh::new(positions, indices);
+    mesh.normals = reader.read_normals().map_or_else(Vec::new, Iterator::collect);
+    mesh.tangents = reader.read_tangents().map_or_else(Vec::new, Iterator::collect);
+    // glTF's texture coordinates already start at the top like wgpu's
+    mesh.tex_coords = reader.read_tex_coords(0).map_or_else(Vec::new, |uvs| uvs.into_f32().collect());
+    mesh.lightmap_coords = reader.read_tex_coords(1).map_or_else(Vec::new, |uvs| uvs.into_f32().collect());
+    mesh.colors = reader.read_colors(0).map_or_else(Vec::new, |colors| colors.into_rgba_f32().collect());
+    for (name, len) in [
+        ("NORMAL", mesh.normals.len()),
+        ("TANGENT", mesh.tangents.len()),
+        ("TEXCOORD_0", mesh.tex_coords.len()),
+        ("TEXCOORD_1", mesh.lightmap_coords.len()),
+        ("COLOR_0", mesh.colors.len()),
+    ] {
+        if len != 0 && len != mesh.positions.len() {
+            bail!("{} has {} entries for {} positions", name, len, mesh.positions.len());
+        }
+    }
+    mesh.generate_missing();
+    Ok(mesh)
+}
+
+// Moves the mesh into the space of the node it's placed with
+fn transform_mesh(mesh: &mut Mesh, matrix: Mat4) {
+    if matrix == Mat4::IDENTITY {
+        return;
+    }
+    let normal_matrix = Mat3::from_mat4(matrix).inverse().transpose();
+    for position in &mut mesh.positions {
+        *position = matrix.transform_point3(Vec3::from(*position)).into();
+    }
+    for normal in &mut mesh.normals {
+        *normal = (normal_matrix * Vec3::from(*normal)).normalize_or_zero().into();
+    }
+    for tangent in &mut mesh.tangents {
+        let direction = matrix.transform_vector3(Vec3::new(tangent[0], tangent[1], tangent[2])).normalize_or_zero();
+        *tangent = direction.extend(tangent[3]).into();
+    }
+    // A mirroring transform turns the triangles inside out
+    if matrix.determinant() < 0.0 {
+        for triangle in mesh.indices.chunks_exact_mut(3) {
+            triangle.swap(1, 2);
+        }
+    }
+}
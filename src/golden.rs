@@ -0,0 +1,183 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use image::{Rgba, RgbaImage};
+
+use crate::readback;
+use crate::scene::Scene;
+use crate::{State, Target};
+
+// Golden image tests: scenes rendered offscreen and compared against
+// reference images checked into the repo, so a change to the renderer that
+// alters what comes out doesn't go unnoticed. See tests/golden.rs, which
+// runs every scene in tests/golden/.
+//
+// Different GPUs and drivers never agree down to the last bit, so images are
+// compared the way a person would look at them: a pixel only counts as
+// different if its colour moved noticeably, and a few of those are allowed
+// along edges where rasterisers disagree.
+
+// Set to re-render the reference images instead of comparing against them,
+// after a change that's meant to alter the output
+pub const UPDATE_VAR: &str = "UPDATE_GOLDEN";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tolerance {
+    // How far apart two pixels can be before they count as different, from 0
+    // (exactly equal) to 1 (black versus white)
+    pub threshold: f32,
+    // Fraction of the pixels allowed to be different
+    pub max_differing: f32,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self {
+            threshold: 0.1,
+            max_differing: 0.005,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Comparison {
+    pub differing: usize,
+    pub total: usize,
+    // The biggest difference between any two pixels, 0 to 1 like
+    // Tolerance::threshold
+    pub max_difference: f32,
+    // The expected image faded out, with the differing pixels in red
+    pub diff: RgbaImage,
+}
+
+impl Comparison {
+    pub fn passes(&self, tolerance: &Tolerance) -> bool {
+        self.differing as f32 <= self.total as f32 * tolerance.max_differing
+    }
+}
+
+// The largest value color_difference() gives, for black against white
+const MAX_DIFFERENCE: f32 = 35215.0;
+
+// How different two colours look, from the YIQ colour space since people
+// notice brightness changes far more than hue ones. Translucent pixels are
+// blended onto white first.
+fn color_difference(a: Rgba<u8>, b: Rgba<u8>) -> f32 {
+    let yiq = |Rgba([r, g, b, a]): Rgba<u8>| {
+        let alpha = a as f32 / 255.0;
+        let [r, g, b] = [r, g, b].map(|c| 255.0 + (c as f32 - 255.0) * alpha);
+        [
+            0.298_895 * r + 0.586_622 * g + 0.114_482 * b,
+            0.595_978 * r - 0.274_176 * g - 0.321_802 * b,
+            0.211_470 * r - 0.522_617 * g + 0.311_147 * b,
+        ]
+    };
+    let [y1, i1, q1] = yiq(a);
+    let [y2, i2, q2] = yiq(b);
+    0.5053 * (y1 - y2).powi(2) + 0.299 * (i1 - i2).powi(2) + 0.1957 * (q1 - q2).powi(2)
+}
+
+// Errors if the images aren't the same size, since then there's nothing to
+// compare
+pub fn compare(actual: &RgbaImage, expected: &RgbaImage, tolerance: &Tolerance) -> Result<Comparison> {
+    if actual.dimensions() != expected.dimensions() {
+        bail!("rendered {:?} but the reference is {:?}", actual.dimensions(), expected.dimensions());
+    }
+    // The difference is squared, so the threshold is too
+    let threshold = MAX_DIFFERENCE * tolerance.threshold * tolerance.threshold;
+    let mut differing = 0;
+    let mut max_difference: f32 = 0.0;
+    let diff = RgbaImage::from_fn(actual.width(), actual.height(), |x, y| {
+        let expected = *expected.get_pixel(x, y);
+        let difference = color_difference(*actual.get_pixel(x, y), expected);
+        max_difference = max_difference.max(difference);
+        if difference > threshold {
+            differing += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            let Rgba([r, g, b, _]) = expected;
+            let gray = (0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32) as u8;
+            let faded = 191 + gray / 4;
+            Rgba([faded, faded, faded, 255])
+        }
+    });
+    Ok(Comparison {
+        differing,
+        total: (actual.width() * actual.height()) as usize,
+        max_difference: (max_difference / MAX_DIFFERENCE).sqrt(),
+        diff,
+    })
+}
+
+// Compares `actual` against `dir/name.png`. When they differ too much the
+// rendered image and the diff are saved to `dir/failed/` to look at.
+//
+// A missing reference is written out and reported as a failure, so new
+// scenes get their first image looked at before it's checked in. With
+// UPDATE_GOLDEN set, references are overwritten and nothing is compared.
+pub fn check(dir: &Path, name: &str, actual: &RgbaImage, tolerance: &Tolerance) -> Result<()> {
+    let reference = dir.join(name).with_extension("png");
+    if std::env::var_os(UPDATE_VAR).is_some() {
+        actual.save(&reference).with_context(|| format!("writing {}", reference.display()))?;
+        return Ok(());
+    }
+    if !reference.exists() {
+        actual.save(&reference).with_context(|| format!("writing {}", reference.display()))?;
+        bail!("there was no reference image, so the render was saved to {}", reference.display());
+    }
+    let expected = image::open(&reference)
+        .with_context(|| format!("reading {}", reference.display()))?
+        .into_rgba8();
+    let comparison = compare(actual, &expected, tolerance)?;
+    if comparison.passes(tolerance) {
+        return Ok(());
+    }
+
+    let failed = dir.join("failed");
+    std::fs::create_dir_all(&failed)?;
+    let actual_path = failed.join(format!("{}.actual.png", name));
+    let diff_path = failed.join(format!("{}.diff.png", name));
+    actual.save(&actual_path)?;
+    comparison.diff.save(&diff_path)?;
+    bail!(
+        "{} of {} pixels differ (up to {:.3}), see {} and {}",
+        comparison.differing,
+        comparison.total,
+        comparison.max_difference,
+        actual_path.display(),
+        diff_path.display()
+    );
+}
+
+// How long to wait on a scene's assets before giving up on it
+const LOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Renders one frame of `scene` with no time passing, so it comes out the same
+// every run. None if there's no GPU adapter to render with, which tests
+// should treat as a reason to skip rather than fail.
+pub async fn render_scene(scene: Scene, width: u32, height: u32) -> Result<Option<RgbaImage>> {
+    let Some(mut state) = State::headless(winit::dpi::PhysicalSize::new(width, height)).await else {
+        return Ok(None);
+    };
+    state.apply_scene(scene);
+    // With the ecs feature materials only get looked up (and their textures
+    // loaded) while drawing, so one frame gets rendered to get them going
+    state.step(0.0);
+    state.render(&mut (), &mut []).map_err(|e| anyhow!("rendering failed: {:?}", e))?;
+    let started = Instant::now();
+    while state.assets.pending() > 0 {
+        if started.elapsed() > LOAD_TIMEOUT {
+            bail!("{} assets still loading after {:?}", state.assets.pending(), LOAD_TIMEOUT);
+        }
+        state.step(0.0);
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    state.step(0.0);
+    state.render(&mut (), &mut []).map_err(|e| anyhow!("rendering failed: {:?}", e))?;
+    let Target::Offscreen(target) = &state.target else {
+        unreachable!("headless states render offscreen");
+    };
+    let image = readback::read_texture(&state.device, &state.queue, &target.texture, state.config.format, (width, height))?;
+    Ok(Some(image))
+}
@@ -0,0 +1,170 @@
+use wgpu::util::DeviceExt;
+
+use crate::draw::{DrawCommand, DrawResources, MeshBuffers};
+use crate::texture::Texture;
+use crate::vertex::VertexLayout;
+use crate::{InstanceRaw, Vertex};
+
+// What goes over the hovered object, the alpha is how strongly
+pub const COLOR: [f32; 4] = [1.0, 0.8, 0.3, 0.35];
+
+// Tints whatever is under the cursor, see RenderSettings::hover_highlight.
+// The object's draws are recorded a second time with this pipeline instead
+// of their own and its tint standing in for every material, so it works the
+// same whichever material the object has. The tint is blended over what the
+// first draw left, where the depth matches.
+pub struct Highlight {
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    // The tint, in the material's group
+    material: wgpu::BindGroup,
+}
+
+impl Highlight {
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        camera_layout: &wgpu::BindGroupLayout,
+        frame_layout: &wgpu::BindGroupLayout,
+        shader_source: &str,
+        // Extra attribute streams from the mesh pool, like the mesh pipeline
+        streams: &[wgpu::VertexBufferLayout],
+    ) -> Self {
+        let material_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("highlight_bind_group_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Highlight Pipeline Layout"),
+            bind_group_layouts: &[&material_layout, camera_layout, frame_layout],
+            push_constant_ranges: &[],
+        });
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Highlight Buffer"),
+            contents: bytemuck::cast_slice(&COLOR),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let material = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("highlight_bind_group"),
+            layout: &material_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+
+        Self {
+            pipeline: Self::create_pipeline(device, &layout, color_format, shader_source, streams),
+            layout,
+            color_format,
+            material,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        shader_source: &str,
+        streams: &[wgpu::VertexBufferLayout],
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Highlight Shader"),
+            source: wgpu::ShaderSource::Wgsl(shader_source.into()),
+        });
+        let mut buffers = vec![Vertex::desc(), InstanceRaw::desc()];
+        buffers.extend_from_slice(streams);
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Highlight Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &buffers,
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::COLOR,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                // Other pipelines work out positions in a slightly different
+                // order, pulling the tint a little closer makes sure it still
+                // passes over their depth
+                bias: wgpu::DepthBiasState {
+                    constant: -2,
+                    slope_scale: -1.0,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+
+    // Called when the shader file changed or the mesh pool got new streams,
+    // keeps the old pipeline if the new source doesn't compile
+    pub fn reload_shader(&mut self, device: &wgpu::Device, shader_source: &str, streams: &[wgpu::VertexBufferLayout]) {
+        if let Some(pipeline) = crate::shaders::try_create(device, || {
+            Self::create_pipeline(device, &self.layout, self.color_format, shader_source, streams)
+        }) {
+            self.pipeline = pipeline;
+        }
+    }
+
+    // The part of `commands` that draws `instance`, with the highlight as
+    // their pipeline and material. Toon outlines are left out, they're drawn
+    // inside out and would tint over the object.
+    pub fn commands(commands: &[DrawCommand], instance: u32) -> Vec<DrawCommand> {
+        commands
+            .iter()
+            .filter(|command| command.instances.contains(&instance))
+            .filter(|command| command.pipeline != crate::toon::OUTLINE_PIPELINE)
+            .map(|command| DrawCommand {
+                pipeline: 0,
+                material: 0,
+                instances: instance..instance + 1,
+                ..command.clone()
+            })
+            .collect()
+    }
+
+    // Draws commands() into a pass over the scene that already has them in
+    // its depth buffer
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        commands: &[DrawCommand],
+        camera: &'a wgpu::BindGroup,
+        frame: &'a wgpu::BindGroup,
+        meshes: Vec<MeshBuffers<'a>>,
+    ) {
+        let resources = DrawResources {
+            globals: vec![(1, camera), (2, frame)],
+            pipelines: vec![&self.pipeline],
+            materials: vec![&self.material],
+            meshes,
+        };
+        crate::draw::draw_commands(commands, render_pass, 0, &resources);
+    }
+}
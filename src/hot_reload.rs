@@ -0,0 +1,46 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+
+use anyhow::Result;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+
+// Watches directories for changes and reports which files were modified.
+// Editors tend to write a file in several steps, so everything that changed
+// since the last poll is collapsed into one list without duplicates.
+pub struct FileWatcher {
+    // Has to stay alive for events to keep coming
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<notify::Event>>,
+}
+
+impl FileWatcher {
+    pub fn new(dir: impl AsRef<Path>) -> Result<Self> {
+        let (sender, events) = channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        watcher.watch(dir.as_ref(), RecursiveMode::Recursive)?;
+        tracing::info!("Watching {} for changes", dir.as_ref().display());
+        Ok(Self {
+            _watcher: watcher,
+            events,
+        })
+    }
+
+    pub fn changed_files(&self) -> Vec<PathBuf> {
+        let mut changed = Vec::new();
+        while let Ok(event) = self.events.try_recv() {
+            match event {
+                Ok(event) if event.kind.is_modify() || event.kind.is_create() => {
+                    for path in event.paths {
+                        let path = path.canonicalize().unwrap_or(path);
+                        if !changed.contains(&path) {
+                            changed.push(path);
+                        }
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => tracing::warn!("File watcher error: {:?}", e),
+            }
+        }
+        changed
+    }
+}
@@ -0,0 +1,267 @@
+use glam::{Quat, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::scene::{SceneEntity, SceneHierarchy};
+use crate::transform::Transform;
+
+// Keeps the solver away from fully stretched and fully folded chains, where
+// the bend direction is undefined
+const EPSILON: f32 = 1e-4;
+
+// Where a chain reaches for
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum IkTarget {
+    // Wherever the entity ended up after animating, e.g. an empty one a
+    // script moves onto the ground under a foot
+    Entity(String),
+    Position([f32; 3]),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum IkChain {
+    // A hip, knee and foot, or a shoulder, elbow and hand. The root and the
+    // middle joint turn so the end lands on the target, bending towards the
+    // pole when there is one, like a knee pointing forward.
+    TwoBone {
+        root: String,
+        middle: String,
+        end: String,
+        target: IkTarget,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pole: Option<IkTarget>,
+        #[serde(default = "one")]
+        weight: f32,
+    },
+    // Turns the entity so its forward (-Z) points at the target, like a head
+    // following something. It turns at most `max_angle` degrees away from
+    // where the animation had it.
+    LookAt {
+        entity: String,
+        target: IkTarget,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_angle: Option<f32>,
+        #[serde(default = "one")]
+        weight: f32,
+    },
+}
+
+fn one() -> f32 {
+    1.0
+}
+
+impl IkChain {
+    // 0 leaves the animation alone, 1 reaches the target all the way
+    pub fn weight(&self) -> f32 {
+        match self {
+            Self::TwoBone { weight, .. } | Self::LookAt { weight, .. } => *weight,
+        }
+    }
+
+    pub fn set_weight(&mut self, new: f32) {
+        match self {
+            Self::TwoBone { weight, .. } | Self::LookAt { weight, .. } => *weight = new,
+        }
+    }
+}
+
+// Entity indices a chain's names were found at
+enum Bound {
+    TwoBone {
+        joints: [usize; 3],
+        target: BoundTarget,
+        pole: Option<BoundTarget>,
+    },
+    LookAt {
+        entity: usize,
+        target: BoundTarget,
+    },
+}
+
+#[derive(Clone, Copy)]
+enum BoundTarget {
+    Entity(usize),
+    Position(Vec3),
+}
+
+impl BoundTarget {
+    fn position(self, world: &[Transform]) -> Vec3 {
+        match self {
+            Self::Entity(index) => world[index].translation,
+            Self::Position(position) => position,
+        }
+    }
+}
+
+// Scene IK chains, solved in order on the local transforms an animation
+// posed, before they're turned into world transforms for drawing. Chains are
+// matched to entities by name once, in bind(), like AnimationPlayer does.
+#[derive(Default)]
+pub struct IkRig {
+    chains: Vec<IkChain>,
+    // None for chains naming an entity that isn't there
+    bound: Vec<Option<Bound>>,
+}
+
+impl IkRig {
+    pub fn new(chains: Vec<IkChain>) -> Self {
+        Self { chains, bound: Vec::new() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chains.is_empty()
+    }
+
+    pub fn chains(&self) -> &[IkChain] {
+        &self.chains
+    }
+
+    // For fading chains in and out, e.g. feet while they're planted
+    pub fn chains_mut(&mut self) -> &mut [IkChain] {
+        &mut self.chains
+    }
+
+    pub fn bind(&mut self, entities: &[SceneEntity]) {
+        let find = |name: &str| {
+            let index = entities.iter().position(|e| e.name == name);
+            if index.is_none() {
+                tracing::warn!("IK chain names a missing entity {}", name);
+            }
+            index
+        };
+        let find_target = |target: &IkTarget| match target {
+            IkTarget::Entity(name) => find(name).map(BoundTarget::Entity),
+            IkTarget::Position(position) => Some(BoundTarget::Position(Vec3::from(*position))),
+        };
+        self.bound = self
+            .chains
+            .iter()
+            .map(|chain| match chain {
+                IkChain::TwoBone {
+                    root,
+                    middle,
+                    end,
+                    target,
+                    pole,
+                    ..
+                } => Some(Bound::TwoBone {
+                    joints: [find(root)?, find(middle)?, find(end)?],
+                    target: find_target(target)?,
+                    pole: match pole {
+                        Some(pole) => Some(find_target(pole)?),
+                        None => None,
+                    },
+                }),
+                IkChain::LookAt { entity, target, .. } => Some(Bound::LookAt {
+                    entity: find(entity)?,
+                    target: find_target(target)?,
+                }),
+            })
+            .collect();
+    }
+
+    // Turns the chains' joints in `local`, which are the entities
+    // `hierarchy` was made from in the same order
+    pub fn apply(&self, hierarchy: &SceneHierarchy, local: &mut [Transform]) {
+        for (chain, bound) in self.chains.iter().zip(&self.bound) {
+            let Some(bound) = bound else {
+                continue;
+            };
+            let weight = chain.weight().clamp(0.0, 1.0);
+            if weight == 0.0 {
+                continue;
+            }
+            // Again for every chain, an earlier one may have moved this
+            // one's joints or target
+            let world = hierarchy.world_transforms(local);
+            match *bound {
+                Bound::TwoBone { joints, target, pole } => {
+                    let [root, middle, end] = joints;
+                    let target = target.position(&world);
+                    let pole = pole.map(|pole| pole.position(&world));
+                    let (root_turn, middle_turn) = two_bone([world[root], world[middle], world[end]], target, pole);
+                    turn(&mut local[root], root_turn, weight);
+                    turn(&mut local[middle], middle_turn, weight);
+                }
+                Bound::LookAt { entity, target } => {
+                    let IkChain::LookAt { max_angle, .. } = chain else {
+                        continue;
+                    };
+                    let from = world[entity].forward();
+                    let Some(to) = (target.position(&world) - world[entity].translation).try_normalize() else {
+                        continue;
+                    };
+                    let mut aim = Quat::from_rotation_arc(from, to);
+                    if let Some(max_angle) = max_angle {
+                        let (axis, angle) = aim.to_axis_angle();
+                        aim = Quat::from_axis_angle(axis, angle.min(max_angle.to_radians()));
+                    }
+                    // In the entity's own space, where its local rotation
+                    // gets turned
+                    let rotation = world[entity].rotation;
+                    turn(&mut local[entity], rotation.inverse() * aim * rotation, weight);
+                }
+            }
+        }
+    }
+}
+
+// `weight` of the way to `rotation` turned by `by`, in its own space
+fn turn(transform: &mut Transform, by: Quat, weight: f32) {
+    let solved = (transform.rotation * by).normalize();
+    transform.rotation = transform.rotation.slerp(solved, weight);
+}
+
+// How much the root and the middle joint have to turn, each in its own
+// space, for the end to reach `target`. Solved with the triangle the three
+// joints make, from https://theorangeduck.com/page/simple-two-joint
+fn two_bone(joints: [Transform; 3], target: Vec3, pole: Option<Vec3>) -> (Quat, Quat) {
+    let [a, b, c] = joints.map(|joint| joint.translation);
+    let (a_rotation, b_rotation) = (joints[0].rotation, joints[1].rotation);
+    let length_ab = (b - a).length();
+    let length_cb = (b - c).length();
+    if length_ab < EPSILON || length_cb < EPSILON {
+        return (Quat::IDENTITY, Quat::IDENTITY);
+    }
+    let min = (length_ab - length_cb).abs() + EPSILON;
+    let length_at = (target - a).length().clamp(min, (length_ab + length_cb - EPSILON).max(min));
+
+    let angle = |u: Vec3, v: Vec3| u.normalize_or_zero().dot(v.normalize_or_zero()).clamp(-1.0, 1.0).acos();
+    // The angle between sides x and y of a triangle, across from `opposite`
+    let cosine_rule = |opposite: f32, x: f32, y: f32| {
+        ((opposite * opposite - x * x - y * y) / (-2.0 * x * y)).clamp(-1.0, 1.0).acos()
+    };
+    let ac_ab_0 = angle(c - a, b - a);
+    let ba_bc_0 = angle(a - b, c - b);
+    let ac_at_0 = angle(c - a, target - a);
+    let ac_ab_1 = cosine_rule(length_cb, length_ab, length_at);
+    let ba_bc_1 = cosine_rule(length_at, length_ab, length_cb);
+
+    // The plane the chain bends in. A straight chain has none, so it bends
+    // towards the pole, or any which way and gets turned to the pole below.
+    let bend = (c - a)
+        .cross(b - a)
+        .try_normalize()
+        .or_else(|| pole.and_then(|pole| (c - a).cross(pole - a).try_normalize()))
+        .unwrap_or_else(|| (c - a).normalize_or_zero().any_orthonormal_vector());
+    let reach = (c - a).cross(target - a).try_normalize().unwrap_or(bend);
+    let r0 = Quat::from_axis_angle(a_rotation.inverse() * bend, ac_ab_1 - ac_ab_0);
+    let r1 = Quat::from_axis_angle(b_rotation.inverse() * bend, ba_bc_1 - ba_bc_0);
+    let r2 = Quat::from_axis_angle(a_rotation.inverse() * reach, ac_at_0);
+    let mut root_turn = r2 * r0;
+
+    // Spins the solved chain around the line to the target until the middle
+    // joint is on the pole's side
+    if let Some(pole) = pole {
+        let axis = (target - a).normalize_or_zero();
+        let turned = a_rotation * root_turn;
+        let middle = turned * a_rotation.inverse() * (b - a);
+        let flatten = |v: Vec3| v - axis * v.dot(axis);
+        let (from, to) = (flatten(middle), flatten(pole - a));
+        if from.length_squared() > EPSILON && to.length_squared() > EPSILON {
+            let spin = from.cross(to).dot(axis).atan2(from.dot(to));
+            let spin = Quat::from_axis_angle(axis, spin);
+            root_turn *= turned.inverse() * spin * turned;
+        }
+    }
+    (root_turn, r1)
+}
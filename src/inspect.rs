@@ -0,0 +1,426 @@
+use std::sync::Arc;
+
+use glam::{EulerRot, Quat};
+
+use crate::particles::{Curve, Gradient, ParticleBlend, ParticleEffect, SpawnShape, SubEmitter, SubEmitterTrigger};
+use crate::scene::{
+    EmissiveMaterial, Gloss, LightmapContents, LightmappedMaterial, MaterialRef, Mirror, ParallaxMaterial,
+    ParallaxSettings, SceneEntity, SceneLight, SceneTransform, ToonMaterial, ToonOutline,
+};
+use crate::trail::Trail;
+
+// Reflection-lite: a type the editor's inspector can show and edit, field by
+// field. The scene's components and what they're made of implement it, so
+// anything built out of them only has to list its fields.
+pub trait Inspect {
+    // Widgets editing it in place, true when anything changed
+    fn inspect(&mut self, ui: &mut egui::Ui) -> bool;
+}
+
+// Lays `add`'s fields out as a two column grid of names and values
+pub fn fields(ui: &mut egui::Ui, id: impl std::hash::Hash, add: impl FnOnce(&mut egui::Ui) -> bool) -> bool {
+    egui::Grid::new(id).num_columns(2).show(ui, add).inner
+}
+
+// One row of fields()
+pub fn field(ui: &mut egui::Ui, name: &str, value: &mut dyn Inspect) -> bool {
+    ui.label(name);
+    let changed = value.inspect(ui);
+    ui.end_row();
+    changed
+}
+
+// A row for something that can't be edited here, like a file path
+fn read_only(ui: &mut egui::Ui, name: &str, value: impl ToString) {
+    ui.label(name);
+    ui.label(value.to_string());
+    ui.end_row();
+}
+
+impl Inspect for f32 {
+    fn inspect(&mut self, ui: &mut egui::Ui) -> bool {
+        ui.add(egui::DragValue::new(self).speed(0.01)).changed()
+    }
+}
+
+impl Inspect for u32 {
+    fn inspect(&mut self, ui: &mut egui::Ui) -> bool {
+        ui.add(egui::DragValue::new(self)).changed()
+    }
+}
+
+impl Inspect for bool {
+    fn inspect(&mut self, ui: &mut egui::Ui) -> bool {
+        ui.checkbox(self, "").changed()
+    }
+}
+
+impl<const N: usize> Inspect for [f32; N] {
+    fn inspect(&mut self, ui: &mut egui::Ui) -> bool {
+        ui.horizontal(|ui| self.iter_mut().fold(false, |changed, value| value.inspect(ui) | changed)).inner
+    }
+}
+
+impl Inspect for SceneTransform {
+    fn inspect(&mut self, ui: &mut egui::Ui) -> bool {
+        fields(ui, "transform", |ui| {
+            let mut changed = field(ui, "Translation", &mut self.translation);
+            // Euler angles in degrees are easier to type than a quaternion
+            let rotation = Quat::from_array(self.rotation).normalize();
+            let (y, x, z) = rotation.to_euler(EulerRot::YXZ);
+            let mut degrees = [x, y, z].map(f32::to_degrees);
+            if field(ui, "Rotation", &mut degrees) {
+                let [x, y, z] = degrees.map(f32::to_radians);
+                self.rotation = Quat::from_euler(EulerRot::YXZ, y, x, z).to_array();
+                changed = true;
+            }
+            changed | field(ui, "Scale", &mut self.scale)
+        })
+    }
+}
+
+impl Inspect for Mirror {
+    fn inspect(&mut self, ui: &mut egui::Ui) -> bool {
+        fields(ui, "mirror", |ui| {
+            ui.label("Reflectivity");
+            let changed = ui.add(egui::Slider::new(&mut self.reflectivity, 0.0..=1.0)).changed();
+            ui.end_row();
+            changed
+        })
+    }
+}
+
+impl Inspect for Gloss {
+    fn inspect(&mut self, ui: &mut egui::Ui) -> bool {
+        fields(ui, "gloss", |ui| {
+            ui.label("Reflectivity");
+            let changed = ui.add(egui::Slider::new(&mut self.reflectivity, 0.0..=1.0)).changed();
+            ui.end_row();
+            changed
+        })
+    }
+}
+
+impl Inspect for ParallaxSettings {
+    fn inspect(&mut self, ui: &mut egui::Ui) -> bool {
+        fields(ui, "parallax_settings", |ui| {
+            field(ui, "Depth", &mut self.depth)
+                | field(ui, "Min steps", &mut self.min_steps)
+                | field(ui, "Max steps", &mut self.max_steps)
+                | field(ui, "Self shadowing", &mut self.self_shadowing)
+        })
+    }
+}
+
+impl Inspect for ParallaxMaterial {
+    fn inspect(&mut self, ui: &mut egui::Ui) -> bool {
+        fields(ui, "parallax", |ui| {
+            read_only(ui, "Texture", self.texture.display());
+            read_only(ui, "Height", self.height.display());
+            false
+        });
+        self.settings.inspect(ui)
+    }
+}
+
+impl Inspect for EmissiveMaterial {
+    fn inspect(&mut self, ui: &mut egui::Ui) -> bool {
+        fields(ui, "emissive", |ui| {
+            let texture = self.texture.as_ref().map_or("default".to_string(), |path| path.display().to_string());
+            read_only(ui, "Texture", texture);
+            let emissive = self.emissive_texture.as_ref().map_or("none".to_string(), |path| path.display().to_string());
+            read_only(ui, "Emissive texture", emissive);
+            field(ui, "Color", &mut self.color)
+        })
+    }
+}
+
+impl Inspect for ToonOutline {
+    fn inspect(&mut self, ui: &mut egui::Ui) -> bool {
+        fields(ui, "toon_outline", |ui| field(ui, "Width", &mut self.width) | field(ui, "Color", &mut self.color))
+    }
+}
+
+impl Inspect for ToonMaterial {
+    fn inspect(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = fields(ui, "toon", |ui| {
+            let texture = self.texture.as_ref().map_or("default".to_string(), |path| path.display().to_string());
+            read_only(ui, "Texture", texture);
+            let mut outlined = self.outline.is_some();
+            let changed = field(ui, "Bands", &mut self.bands)
+                | field(ui, "Softness", &mut self.softness)
+                | field(ui, "Rim color", &mut self.rim_color)
+                | field(ui, "Rim width", &mut self.rim_width)
+                | field(ui, "Outline", &mut outlined);
+            if outlined != self.outline.is_some() {
+                self.outline = outlined.then(ToonOutline::default);
+            }
+            changed
+        });
+        if let Some(outline) = &mut self.outline {
+            changed |= outline.inspect(ui);
+        }
+        changed
+    }
+}
+
+impl Inspect for LightmapContents {
+    fn inspect(&mut self, ui: &mut egui::Ui) -> bool {
+        ui.horizontal(|ui| {
+            ui.selectable_value(self, LightmapContents::Irradiance, "Irradiance").changed()
+                | ui.selectable_value(self, LightmapContents::AmbientOcclusion, "Ambient occlusion").changed()
+        })
+        .inner
+    }
+}
+
+impl Inspect for LightmappedMaterial {
+    fn inspect(&mut self, ui: &mut egui::Ui) -> bool {
+        fields(ui, "lightmapped", |ui| {
+            let texture = self.texture.as_ref().map_or("default".to_string(), |path| path.display().to_string());
+            read_only(ui, "Texture", texture);
+            read_only(ui, "Lightmap", self.lightmap.display());
+            field(ui, "Contents", &mut self.contents) | field(ui, "Intensity", &mut self.intensity)
+        })
+    }
+}
+
+impl Inspect for MaterialRef {
+    fn inspect(&mut self, ui: &mut egui::Ui) -> bool {
+        match self {
+            MaterialRef::Default => {
+                ui.label("Default texture");
+                false
+            }
+            MaterialRef::Texture(path) => {
+                ui.label(format!("Texture {}", path.display()));
+                false
+            }
+            MaterialRef::Parallax(material) => material.inspect(ui),
+            MaterialRef::Emissive(material) => material.inspect(ui),
+            MaterialRef::Toon(material) => material.inspect(ui),
+            MaterialRef::Lightmapped(material) => material.inspect(ui),
+        }
+    }
+}
+
+impl Inspect for SceneLight {
+    fn inspect(&mut self, ui: &mut egui::Ui) -> bool {
+        fields(ui, "light", |ui| match self {
+            SceneLight::Directional { direction, color, intensity } => {
+                field(ui, "Direction", direction) | field(ui, "Color", color) | field(ui, "Intensity", intensity)
+            }
+            SceneLight::Point { position, color, intensity, range } => {
+                field(ui, "Position", position)
+                    | field(ui, "Color", color)
+                    | field(ui, "Intensity", intensity)
+                    | field(ui, "Range", range)
+            }
+        })
+    }
+}
+
+impl Inspect for SceneEntity {
+    fn inspect(&mut self, ui: &mut egui::Ui) -> bool {
+        ui.heading(&self.name);
+        let mut changed = self.transform.inspect(ui);
+        ui.separator();
+        ui.label("Material");
+        changed |= self.material.inspect(ui);
+        if let Some(mirror) = &mut self.mirror {
+            ui.separator();
+            ui.label("Mirror");
+            changed |= mirror.inspect(ui);
+        }
+        if let Some(gloss) = &mut self.gloss {
+            ui.separator();
+            ui.label("Gloss");
+            changed |= gloss.inspect(ui);
+        }
+        if let Some(trail) = &mut self.trail {
+            ui.separator();
+            ui.label("Trail");
+            changed |= trail.inspect(ui);
+        }
+        changed
+    }
+}
+
+// One row per key, with buttons for adding one after the last and taking
+// any of them away. Keys dragged past their neighbours get sorted back.
+fn keys<T: Inspect + Clone>(ui: &mut egui::Ui, keys: &mut Vec<(f32, T)>, default: T) -> bool {
+    let mut changed = false;
+    let mut removed = None;
+    for (index, (age, value)) in keys.iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+            changed |= ui.add(egui::DragValue::new(age).speed(0.01).clamp_range(0.0..=1.0)).changed();
+            changed |= value.inspect(ui);
+            if ui.small_button("x").clicked() {
+                removed = Some(index);
+            }
+        });
+    }
+    if let Some(index) = removed {
+        keys.remove(index);
+        changed = true;
+    }
+    if ui.small_button("Add key").clicked() {
+        let value = keys.last().map_or(default, |(_, value)| value.clone());
+        keys.push((1.0, value));
+        changed = true;
+    }
+    if changed {
+        keys.sort_by(|a, b| a.0.total_cmp(&b.0));
+    }
+    changed
+}
+
+impl Inspect for Curve {
+    fn inspect(&mut self, ui: &mut egui::Ui) -> bool {
+        ui.vertical(|ui| keys(ui, &mut self.0, 0.0)).inner
+    }
+}
+
+impl Inspect for Gradient {
+    fn inspect(&mut self, ui: &mut egui::Ui) -> bool {
+        ui.vertical(|ui| keys(ui, &mut self.0, [1.0; 4])).inner
+    }
+}
+
+impl Inspect for SpawnShape {
+    fn inspect(&mut self, ui: &mut egui::Ui) -> bool {
+        let name = match self {
+            SpawnShape::Point => "Point",
+            SpawnShape::Sphere { .. } => "Sphere",
+            SpawnShape::Box { .. } => "Box",
+            SpawnShape::Cone { .. } => "Cone",
+        };
+        ui.vertical(|ui| {
+            let mut changed = false;
+            egui::ComboBox::from_id_source("spawn_shape").selected_text(name).show_ui(ui, |ui| {
+                let shapes = [
+                    SpawnShape::Point,
+                    SpawnShape::Sphere { radius: 0.5 },
+                    SpawnShape::Box { size: [1.0; 3] },
+                    SpawnShape::Cone { angle: 25.0, radius: 0.0 },
+                ];
+                for (shape, label) in shapes.into_iter().zip(["Point", "Sphere", "Box", "Cone"]) {
+                    if ui.selectable_label(name == label, label).clicked() && name != label {
+                        *self = shape;
+                        changed = true;
+                    }
+                }
+            });
+            changed
+                | match self {
+                    SpawnShape::Point => false,
+                    SpawnShape::Sphere { radius } => fields(ui, "sphere", |ui| field(ui, "Radius", radius)),
+                    SpawnShape::Box { size } => fields(ui, "box", |ui| field(ui, "Size", size)),
+                    SpawnShape::Cone { angle, radius } => {
+                        fields(ui, "cone", |ui| field(ui, "Angle", angle) | field(ui, "Radius", radius))
+                    }
+                }
+        })
+        .inner
+    }
+}
+
+impl Inspect for ParticleBlend {
+    fn inspect(&mut self, ui: &mut egui::Ui) -> bool {
+        ui.horizontal(|ui| {
+            ui.selectable_value(self, ParticleBlend::Alpha, "Alpha").changed()
+                | ui.selectable_value(self, ParticleBlend::Additive, "Additive").changed()
+        })
+        .inner
+    }
+}
+
+impl Inspect for SubEmitter {
+    fn inspect(&mut self, ui: &mut egui::Ui) -> bool {
+        let changed = fields(ui, "sub_emitter", |ui| {
+            ui.label("Trigger");
+            let changed = ui
+                .horizontal(|ui| {
+                    ui.selectable_value(&mut self.trigger, SubEmitterTrigger::Birth, "Birth").changed()
+                        | ui.selectable_value(&mut self.trigger, SubEmitterTrigger::Death, "Death").changed()
+                })
+                .inner;
+            ui.end_row();
+            changed | field(ui, "Inherit velocity", &mut self.inherit_velocity)
+        });
+        // Only copied when an emitter it set off is still playing the old one
+        changed | Arc::make_mut(&mut self.effect).inspect(ui)
+    }
+}
+
+impl Inspect for ParticleEffect {
+    fn inspect(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = fields(ui, "particle_effect", |ui| {
+            field(ui, "Rate", &mut self.rate)
+                | field(ui, "Burst", &mut self.burst)
+                | field(ui, "Duration", &mut self.duration)
+                | field(ui, "Looping", &mut self.looping)
+                | field(ui, "Max particles", &mut self.max_particles)
+                | field(ui, "Lifetime", &mut self.lifetime)
+                | field(ui, "Shape", &mut self.shape)
+                | field(ui, "Speed", &mut self.speed)
+                | field(ui, "Spin", &mut self.spin)
+                | field(ui, "Gravity", &mut self.gravity)
+                | field(ui, "Drag", &mut self.drag)
+                | field(ui, "Size", &mut self.size)
+                | field(ui, "Color", &mut self.color)
+                | field(ui, "Blend", &mut self.blend)
+        });
+        fields(ui, "particle_texture", |ui| {
+            let texture = self.texture.as_ref().map_or("default".to_string(), |path| path.display().to_string());
+            read_only(ui, "Texture", texture);
+            false
+        });
+        let mut removed = None;
+        for (index, sub) in self.sub_emitters.iter_mut().enumerate() {
+            // Each one's fields need their own ids
+            ui.push_id(index, |ui| {
+                egui::CollapsingHeader::new(format!("Sub-emitter {}", index + 1)).show(ui, |ui| {
+                    changed |= sub.inspect(ui);
+                    if ui.button("Remove").clicked() {
+                        removed = Some(index);
+                    }
+                });
+            });
+        }
+        if let Some(index) = removed {
+            self.sub_emitters.remove(index);
+            changed = true;
+        }
+        if ui.button("Add sub-emitter").clicked() {
+            self.sub_emitters.push(SubEmitter {
+                trigger: SubEmitterTrigger::Death,
+                effect: Arc::new(ParticleEffect {
+                    looping: false,
+                    rate: 0.0,
+                    burst: 10,
+                    ..Default::default()
+                }),
+                inherit_velocity: 0.0,
+            });
+            changed = true;
+        }
+        changed
+    }
+}
+
+impl Inspect for Trail {
+    fn inspect(&mut self, ui: &mut egui::Ui) -> bool {
+        fields(ui, "trail", |ui| {
+            let changed = field(ui, "Lifetime", &mut self.lifetime)
+                | field(ui, "Min distance", &mut self.min_distance)
+                | field(ui, "Width", &mut self.width)
+                | field(ui, "Alpha", &mut self.alpha)
+                | field(ui, "Color", &mut self.color)
+                | field(ui, "Blend", &mut self.blend);
+            let texture = self.texture.as_ref().map_or("default".to_string(), |path| path.display().to_string());
+            read_only(ui, "Texture", texture);
+            changed | field(ui, "Texture length", &mut self.texture_length) | field(ui, "Scroll", &mut self.scroll)
+        })
+    }
+}
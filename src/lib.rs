@@ -1,99 +1,207 @@
-mod texture;
-
-use cgmath::prelude::*;
+pub mod animation;
+pub mod animation_graph;
+pub mod app;
+pub mod assets;
+#[cfg(all(feature = "audio", not(target_arch = "wasm32")))]
+pub mod audio;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod bench;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod bake;
+pub mod bind_group_cache;
+pub mod bloom;
+pub mod bookmarks;
+pub mod bounds;
+pub mod bvh;
+pub mod buffer_pool;
+pub mod clipboard;
+#[cfg(feature = "editor")]
+pub mod console;
+pub mod crowd;
+pub mod crt;
+pub mod cursor;
+pub mod debug;
+pub mod draw;
+#[cfg(all(feature = "dylib", not(target_arch = "wasm32")))]
+pub mod dylib;
+pub mod emissive;
+#[cfg(feature = "ecs")]
+pub mod ecs;
+#[cfg(feature = "editor")]
+pub mod editor;
+#[cfg(feature = "editor")]
+pub mod egui_renderer;
+pub mod events;
+pub mod exposure;
+pub mod filters;
+pub mod fog;
+pub mod foliage;
+pub mod frame;
+pub mod gizmo;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod golden;
+pub mod gltf;
+pub mod grid;
+pub mod highlight;
+pub mod ik;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod hot_reload;
+#[cfg(feature = "editor")]
+pub mod inspect;
+pub mod lightmap;
+pub mod lines;
+pub mod logging;
+pub mod marquee;
+pub mod mesh;
+pub mod mirror;
+pub mod noise;
+pub mod outline;
+pub mod paint;
+pub mod parallax;
+pub mod particles;
+pub mod pixel_art;
+pub mod plugin;
+pub mod pointer;
+pub mod probe;
+pub mod profiler;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod readback;
+pub mod render_graph;
+pub mod scene;
+pub mod screen;
+#[cfg(feature = "rhai")]
+pub mod script;
+pub mod settings;
+pub mod shaders;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod shadertoy;
+pub mod shake;
+pub mod simplify;
+pub mod sky;
+pub mod sprite;
+pub mod stereo;
+pub mod streaming;
+pub mod terrain;
+pub mod text;
+pub mod text_input;
+pub mod texture;
+pub mod tonemap;
+pub mod toon;
+pub mod touch;
+pub mod trail;
+pub mod transform;
+pub mod tween;
+pub mod ui;
+pub mod upload;
+pub mod vertex;
+pub mod viewports;
+pub mod water;
+pub mod widgets;
+pub mod window;
+
+#[cfg(not(feature = "ecs"))]
+use animation::AnimationPlayer;
+#[cfg(not(feature = "ecs"))]
+use ik::IkRig;
+use app::{App, ClearMode, Exposure, RenderContext, RenderSettings, Setup};
+use assets::{AssetId, Assets, Handle, Model};
+use bookmarks::CameraBookmarks;
+use bounds::{Aabb, Frustum, Ray};
+use bvh::Bvh;
+use buffer_pool::{MeshAllocation, MeshPool};
+use clipboard::Clipboard;
+#[cfg(feature = "editor")]
+use console::Console;
+use cursor::{Cursor, CustomCursor};
+use bind_group_cache::{BindGroupCache, CachedBinding, ResourceId};
+use events::EventBus;
+use exposure::{AutoExposure, EyeAdaptation};
+use fog::{FogSettings, VolumetricFog};
+use frame::FrameGlobals;
+#[cfg(not(feature = "ecs"))]
+use gizmo::{Gizmo, GizmoMode, TransformEdited};
+#[cfg(not(feature = "ecs"))]
+use marquee::{Marquee, MarqueeSelected};
+use grid::Grid;
+use highlight::Highlight;
+use lines::LineBatch;
+use mirror::{Mirrors, MAX_MIRRORS};
+use outline::{Outline, OutlineSettings};
+use parallax::Parallax;
+use pixel_art::{PixelArt, PixelArtSettings};
+use bloom::{Bloom, BloomSettings};
+use crt::{Crt, CrtSettings};
+use emissive::Emissive;
+use toon::Toon;
+use lightmap::Lightmap;
+use probe::ReflectionProbes;
+use plugin::{Plugin, PluginContext, SceneChanges};
+use profiler::Profiler;
+use draw::{DrawCommand, DrawList, DrawResources, MeshBuffers, StaticBundle};
+use render_graph::{RenderGraph, TransientPool, TransientTexture};
+use glam::{Mat4, Quat, Vec3};
+use pointer::Pointer;
+use screen::Screen;
+use settings::{DisplaySettings, GraphicsSettings, SETTINGS_PATH};
+use shaders::Shaders;
+use sky::{Lighting, Sky};
+use viewports::ViewportCameras;
+use sprite::SpriteBatch;
+use stereo::{Stereo, StereoSettings};
+use text::{Fonts, GlyphAtlas};
+use text_input::TextInput;
+use tonemap::Tonemap;
+use touch::{Gesture, Touches};
+use scene::{Gloss, MaterialRef, MeshRef, Mirror, Scene, SceneCamera, SceneEntity, SceneLight, SceneTransform};
+#[cfg(not(feature = "ecs"))]
+use scene::SceneHierarchy;
+use transform::Transform;
+use tween::{Easing, Tween, TweenHandle, Tweens};
+use upload::{AppUploader, Uploader};
+use vertex::{VertexLayout, VertexLightmapUv, VertexPosUv};
+use window::WindowConfig;
+use tracing::Instrument;
 use wgpu::util::DeviceExt;
 use winit::{
     event::*,
     event_loop::{ControlFlow, EventLoop},
     window::Window,
-    window::WindowBuilder,
 };
 
-#[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
-struct Vertex {
-    position: [f32; 3],
-    tex_coords: [f32; 2],
-}
-
+// What the mesh pipeline and the mesh pool use, see vertex.rs for the others
+type Vertex = VertexPosUv;
 
-impl Vertex {
-    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
-        wgpu::VertexBufferLayout {
-            array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &[
-                wgpu::VertexAttribute {
-                    offset: 0,
-                    shader_location: 0,
-                    format: wgpu::VertexFormat::Float32x3,
-                },
-                wgpu::VertexAttribute {
-                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
-                    shader_location: 1,
-                    format: wgpu::VertexFormat::Float32x2,
-                },
-            ],
-        }
+vertex_layout! {
+    step_mode: Instance,
+    struct InstanceRaw {
+        // A mat4 takes up 4 vertex slots as it is technically 4 vec4s, we'll
+        // have to reassemble the mat4 in the shader. Locations 0 to 4 are
+        // left for the vertex attributes.
+        #[location(5)] model: [[f32; 4]; 4],
     }
 }
 
-struct Instance {
-    position: cgmath::Vector3<f32>,
-    rotation: cgmath::Quaternion<f32>,
-}
-
-#[repr(C)]
-#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-struct InstanceRaw {
-    model: [[f32; 4]; 4],
-}
-
-impl Instance {
-    fn to_raw(&self) -> InstanceRaw {
-        InstanceRaw {
-            model: (cgmath::Matrix4::from_translation(self.position) * cgmath::Matrix4::from(self.rotation)).into(),
+impl From<&Transform> for InstanceRaw {
+    fn from(transform: &Transform) -> Self {
+        Self {
+            model: transform.matrix().to_cols_array_2d(),
         }
     }
 }
 
-impl InstanceRaw {
-    fn desc() -> wgpu::VertexBufferLayout<'static> {
-        use std::mem;
-        wgpu::VertexBufferLayout {
-            array_stride: mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
-            // We need to switch from using a step mode of Vertex to Instance
-            // This means that our shaders will only change to use the next
-            // instance when the shader starts processing a new instance
-            step_mode: wgpu::VertexStepMode::Instance,
-            attributes: &[
-                // A mat4 takes up 4 vertex slots as it is technically 4 vec4s. We need to define a slot
-                // for each vec4. We'll have to reassemble the mat4 in the shader.
-                wgpu::VertexAttribute {
-                    offset: 0,
-                    // While our vertex shader only uses locations 0, and 1 now, in later tutorials we'll
-                    // be using 2, 3, and 4, for Vertex. We'll start at slot 5 not conflict with them later
-                    shader_location: 5,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
-                    shader_location: 6,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
-                    shader_location: 7,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
-                    shader_location: 8,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-            ],
-        }
+fn create_instance_buffer(device: &wgpu::Device, instances: &[Transform]) -> wgpu::Buffer {
+    let mut instance_data = instances.iter().map(InstanceRaw::from).collect::<Vec<_>>();
+    // Empty scenes still need something to bind
+    if instance_data.is_empty() {
+        instance_data.push(bytemuck::Zeroable::zeroed());
     }
+    device.create_buffer_init(
+        &wgpu::util::BufferInitDescriptor {
+            label: Some("Instance Buffer"),
+            contents: bytemuck::cast_slice(&instance_data),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        }
+    )
 }
 
 // Counter clockwise so that they dont get culled! Top, bottom left, bottom right. // TODO: Check this.
@@ -120,7 +228,7 @@ const VERTICES: &[Vertex] = &[
 // assumes every polygon is a tri with 3 vertices
 #[rustfmt::skip]
 #[allow(clippy::identity_op)]
-const INDICES: &[u16] = &[
+const INDICES: &[u32] = &[
     // 0, 1, 4,
     // 1, 2, 4,
     // 2, 3, 4,
@@ -131,31 +239,88 @@ const INDICES: &[u16] = &[
     1+4, 0+4, 2+4,
 ];
 
-#[rustfmt::skip]
-pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
-    1.0, 0.0, 0.0, 0.0,
-    0.0, 1.0, 0.0, 0.0,
-    0.0, 0.0, 0.5, 0.0,
-    0.0, 0.0, 0.5, 1.0,
-);
- 
+// The quad as a Mesh, for things that work on the CPU like lightmap baking.
+// Both of its squares use the whole texture, so in the lightmap they get a
+// half each, the upright one on the left.
+pub(crate) fn quad_geometry() -> mesh::Mesh {
+    let lightmap_coords = VERTICES
+        .iter()
+        .enumerate()
+        .map(|(i, v)| {
+            let half = if i < 4 { 0.0 } else { 0.5 };
+            let uv = mesh::inset(glam::Vec2::from(v.tex_coords));
+            [half + uv.x * 0.5, uv.y]
+        })
+        .collect();
+    mesh::Mesh::new(VERTICES.iter().map(|v| v.position).collect(), INDICES.to_vec())
+        .with_tex_coords(VERTICES.iter().map(|v| v.tex_coords).collect())
+        .with_lightmap_coords(lightmap_coords)
+}
 
+#[derive(Clone)]
 struct Camera {
-    eye: cgmath::Point3<f32>,
-    target: cgmath::Point3<f32>,
-    up: cgmath::Vector3<f32>,
+    eye: Vec3,
+    target: Vec3,
+    up: Vec3,
     aspect: f32,
     fovy: f32,
     znear: f32,
     zfar: f32,
+    // RenderSettings::draw_distance, pulling the far plane in without
+    // changing the scene's camera
+    draw_distance: f32,
+    // RenderSettings::camera_shake, on top of where the camera is looking
+    // from. Only the view moves, not the eye and target the controller steers.
+    shake: Transform,
 }
 
 impl Camera {
-    fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
-        let view = cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up);
-        let proj = cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar);
+    fn from_scene(camera: &SceneCamera, aspect: f32) -> Self {
+        Self {
+            eye: camera.eye.into(),
+            target: camera.target.into(),
+            up: camera.up.into(),
+            aspect,
+            fovy: camera.fovy,
+            znear: camera.znear,
+            zfar: camera.zfar,
+            draw_distance: f32::INFINITY,
+            shake: Transform::IDENTITY,
+        }
+    }
+
+    fn to_scene(&self) -> SceneCamera {
+        SceneCamera {
+            eye: self.eye.into(),
+            target: self.target.into(),
+            up: self.up.into(),
+            fovy: self.fovy,
+            znear: self.znear,
+            zfar: self.zfar,
+        }
+    }
+
+    fn transform(&self) -> Transform {
+        Transform::looking_at(self.eye, self.target, self.up) * self.shake
+    }
 
-        OPENGL_TO_WGPU_MATRIX * proj * view
+    // Nothing past here gets drawn, or survives culling
+    fn far(&self) -> f32 {
+        self.zfar.min(self.draw_distance).max(self.znear * 2.0)
+    }
+
+    fn build_view_projection_matrix(&self) -> Mat4 {
+        let view = self.transform().view_matrix();
+        // glam already maps depth to 0..1 like wgpu wants
+        let proj = Mat4::perspective_rh(self.fovy.to_radians(), self.aspect, self.znear, self.far());
+
+        proj * view
+    }
+
+    // From the camera through `cursor` (physical pixels) when it's drawn
+    // into `viewport`, see Ray::from_screen()
+    fn unproject(&self, viewport: [u32; 4], cursor: glam::Vec2) -> Option<Ray> {
+        Ray::from_screen(self.build_view_projection_matrix(), viewport, cursor)
     }
 }
 
@@ -164,21 +329,26 @@ impl Camera {
 // This is so we can store this in a buffer
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 struct CameraUniform {
-    // We can't use cgmath with bytemuck directly so we'll have
-    // to convert the Matrix4 into a 4x4 f32 array
+    // We can't use glam with bytemuck directly so we'll have
+    // to convert the Mat4 into a 4x4 f32 array
     view_proj: [[f32; 4]; 4],
+    // Where the camera is, w is unused. Most shaders leave it out of their
+    // CameraUniform, it's for the ones that need the view direction.
+    eye: [f32; 4],
 }
 
 impl CameraUniform {
     fn new() -> Self {
-        // use cgmath::SquareMatrix;
         Self {
-            view_proj: cgmath::Matrix4::identity().into(),
+            view_proj: Mat4::IDENTITY.to_cols_array_2d(),
+            eye: [0.0, 0.0, 0.0, 1.0],
         }
     }
 
     fn update_view_proj(&mut self, camera: &Camera) {
-        self.view_proj = camera.build_view_projection_matrix().into();
+        self.view_proj = camera.build_view_projection_matrix().to_cols_array_2d();
+        // Where it's really looking from, shaken
+        self.eye = camera.transform().translation.extend(1.0).to_array();
     }
 }
 
@@ -276,11 +446,32 @@ impl CameraController {
         }
     }
 
+    // Pinching zooms towards the target, twisting orbits around it and
+    // dragging turns the camera like the arrow keys do
+    fn apply_gesture(&self, gesture: &Gesture, camera: &mut Camera) {
+        let forward = camera.target - camera.eye;
+        let distance = forward.length();
+        if distance <= f32::EPSILON {
+            return;
+        }
+        let forward_norm = forward / distance;
+
+        let distance = (distance / gesture.pinch).max(self.speed);
+        let orbit = Quat::from_axis_angle(camera.up.normalize(), gesture.rotate);
+        camera.eye = camera.target - orbit * forward_norm * distance;
+
+        let forward_norm = (camera.target - camera.eye).normalize();
+        let right = forward_norm.cross(camera.up).normalize();
+        let relative_up = right.cross(forward_norm);
+        // Dragging by the whole screen turns by roughly the field of view
+        let turn = distance * 0.003;
+        camera.target += (-right * gesture.pan.x + relative_up * gesture.pan.y) * turn;
+    }
+
     fn update_camera(&self, camera: &mut Camera) {
-        // use cgmath::InnerSpace;
         let forward = camera.target - camera.eye;
         let forward_norm = forward.normalize();
-        let forward_mag = forward.magnitude();
+        let forward_mag = forward.length();
 
         // Prevents glitching when camera gets too close to the
         // center of the scene.
@@ -305,7 +496,7 @@ impl CameraController {
 
         // Redo radius calc in case the fowrard/backward is pressed.
         // let forward = camera.target - camera.eye;
-        // let forward_mag = forward.magnitude();
+        // let forward_mag = forward.length();
 
         if self.move_right {
             // Rescale the distance between the target and eye so 
@@ -348,38 +539,378 @@ impl CameraController {
 }
  
 
+// Draw lists longer than this get recorded on multiple threads
+#[cfg(not(target_arch = "wasm32"))]
+const PARALLEL_DRAW_THRESHOLD: usize = 512;
+
 const NUM_INSTANCES_PER_ROW: u32 = 10;
-const INSTANCE_DISPLACEMENT: cgmath::Vector3<f32> = cgmath::Vector3::new(NUM_INSTANCES_PER_ROW as f32 * 0.5, 0.0, NUM_INSTANCES_PER_ROW as f32 * 0.5);
+const INSTANCE_DISPLACEMENT: Vec3 = Vec3::new(NUM_INSTANCES_PER_ROW as f32 * 0.5, 0.0, NUM_INSTANCES_PER_ROW as f32 * 0.5);
+
+// F5 saves the current scene here and F9 loads it back
+const SCENE_PATH: &str = "scene.ron";
+// Ctrl and a number key saves a camera bookmark here
+const BOOKMARKS_PATH: &str = "bookmarks.ron";
+// F8 writes the frames puffin kept here
+#[cfg(all(feature = "puffin", not(target_arch = "wasm32")))]
+const PUFFIN_PATH: &str = "profile.puffin";
+
+// The grid of quads we start with when there's no scene file to load
+fn demo_scene() -> Scene {
+    let entities = (0..NUM_INSTANCES_PER_ROW).flat_map(|z| {
+        (0..NUM_INSTANCES_PER_ROW).map(move |x| {
+            let position = Vec3::new(x as f32, 0.0, z as f32) - INSTANCE_DISPLACEMENT;
+
+            let rotation = if position == Vec3::ZERO {
+                // this is needed so an object at (0, 0, 0) won't get scaled to zero
+                // as Quaternions can effect scale if they're not created correctly
+                Quat::IDENTITY
+            } else {
+                Quat::from_axis_angle(position.normalize(), 45f32.to_radians())
+            };
+
+            SceneEntity {
+                name: format!("quad_{}_{}", x, z),
+                parent: None,
+                transform: SceneTransform::from(&Transform::from_translation(position).with_rotation(rotation)),
+                mesh: MeshRef::Quad,
+                material: MaterialRef::Default,
+                mirror: None,
+                gloss: None,
+                trail: None,
+            }
+        })
+    }).collect();
+
+    Scene {
+        entities,
+        ..Default::default()
+    }
+}
+
+struct SceneMesh {
+    source: MeshRef,
+    model: Option<Handle<Model>>,
+}
+
+struct Material {
+    source: MaterialRef,
+    texture: Handle<texture::Texture>,
+    maps: MaterialMaps,
+    id: ResourceId,
+}
+
+// What a material has besides its texture
+enum MaterialMaps {
+    Plain,
+    // The height map and settings, see parallax.rs
+    Parallax(Handle<texture::Texture>, wgpu::Buffer),
+    // The emissive texture and colour, see emissive.rs
+    Emissive(Handle<texture::Texture>, wgpu::Buffer),
+    // The bands, rim and outline, see toon.rs. Whether it has an outline
+    // drawn.
+    Toon(wgpu::Buffer, bool),
+    // The lightmap and what's in it, see lightmap.rs
+    Lightmapped(Handle<texture::Texture>, wgpu::Buffer),
+}
+
+impl Material {
+    // Which of the scene passes' pipelines draw it, one draw each
+    fn pipelines(&self) -> impl Iterator<Item = u32> {
+        let (pipeline, outline) = match self.maps {
+            MaterialMaps::Plain => (0, None),
+            MaterialMaps::Parallax(..) => (parallax::PIPELINE, None),
+            MaterialMaps::Emissive(..) => (emissive::PIPELINE, None),
+            MaterialMaps::Toon(_, outlined) => (toon::PIPELINE, outlined.then_some(toon::OUTLINE_PIPELINE)),
+            MaterialMaps::Lightmapped(..) => (lightmap::PIPELINE, None),
+        };
+        std::iter::once(pipeline).chain(outline)
+    }
+
+    fn textures(&self) -> impl Iterator<Item = Handle<texture::Texture>> {
+        let map = match self.maps {
+            MaterialMaps::Plain | MaterialMaps::Toon(..) => None,
+            MaterialMaps::Parallax(texture, _)
+            | MaterialMaps::Emissive(texture, _)
+            | MaterialMaps::Lightmapped(texture, _) => Some(texture),
+        };
+        std::iter::once(self.texture).chain(map)
+    }
+}
+
+// A run of instances in the instance buffer sharing a mesh and material
+struct SceneBatch {
+    mesh: usize,
+    material: usize,
+    instances: std::ops::Range<u32>,
+}
+
+// An instance that reflects the rest of the scene, see mirror.rs
+struct SceneMirror {
+    instance: u32,
+    mesh: usize,
+    mirror: Mirror,
+}
+
+// An instance that reflects the closest reflection probe, see probe.rs
+struct SceneGlossy {
+    instance: u32,
+    mesh: usize,
+    gloss: Gloss,
+}
+
+// Every mesh in the pool with the scene's instances, what scene draws index
+// into with DrawCommand::mesh
+fn scene_mesh_buffers<'a>(mesh_pool: &'a MeshPool, instance_buffer: &'a wgpu::Buffer) -> Vec<MeshBuffers<'a>> {
+    mesh_pool
+        .index_buffers()
+        .into_iter()
+        .map(|(index_buffer, index_format)| MeshBuffers {
+            vertex_buffer: mesh_pool.vertex_buffer().slice(..),
+            instance_buffer: instance_buffer.slice(..),
+            streams: mesh_pool.stream_buffers().map(|buffer| buffer.slice(..)).collect(),
+            index_buffer: index_buffer.slice(..),
+            index_format,
+        })
+        .collect()
+}
+
+
+fn create_render_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    shader_source: &str,
+    // Extra attribute streams from the mesh pool, see MeshPool::add_stream()
+    streams: &[wgpu::VertexBufferLayout],
+) -> wgpu::RenderPipeline {
+    let mut buffers = vec![Vertex::desc(), InstanceRaw::desc()];
+    buffers.extend_from_slice(streams);
+
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Shader"),
+        source: wgpu::ShaderSource::Wgsl(shader_source.into()),
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Render Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            // buffers: &[Vertex::desc()],
+            buffers: &buffers,
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: color_format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw, // Counter clockwise
+            cull_mode: None,//Some(wgpu::Face::Back),
+            // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
+            polygon_mode: wgpu::PolygonMode::Fill,
+            // Requires Features::DEPTH_CLIP_CONTROL
+            unclipped_depth: false,
+            // Requires Features::CONSERVATIVE_RASTERIZATION
+            conservative: false,
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less, // 1.
+            stencil: wgpu::StencilState::default(), // 2.
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
 
+// Where frames end up
+enum Target {
+    Surface(wgpu::Surface),
+    // No window, for benchmarks and tests. Frames stay in the texture.
+    Offscreen(texture::Texture),
+}
+
+impl Target {
+    fn offscreen(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Self {
+        Target::Offscreen(texture::Texture::create_render_target(
+            device,
+            (config.width, config.height),
+            config.format,
+            "Offscreen Target",
+        ))
+    }
+}
 
 struct State {
-    surface: wgpu::Surface,
+    target: Target,
+    adapter_info: wgpu::AdapterInfo,
     device: wgpu::Device,
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
-    size: winit::dpi::PhysicalSize<u32>,
+    // Surface size and DPI scale, 2D work happens in its logical pixels
+    screen: Screen,
+    render_pipeline_layout: wgpu::PipelineLayout,
     render_pipeline: wgpu::RenderPipeline,
-    vertex_buffer: wgpu::Buffer,
-    index_buffer: wgpu::Buffer, 
-    num_indices: u32,
-    diffuse_bind_group: wgpu::BindGroup,
-    diffuse_texture: texture::Texture,
+    shaders: Shaders,
+    // Every mesh lives in one shared vertex and index buffer
+    mesh_pool: MeshPool,
+    quad_mesh: MeshAllocation,
+    bind_group_cache: BindGroupCache,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    texture_bind_group_layout_id: ResourceId,
+    assets: Assets,
+    // Material 0 is the default one, the rest come from loaded scenes
+    materials: Vec<Material>,
+    scene: Scene,
+    scene_meshes: Vec<SceneMesh>,
+    batches: Vec<SceneBatch>,
+    mirrors: Vec<SceneMirror>,
+    glossy: Vec<SceneGlossy>,
     camera: Camera,
     camera_uniform: CameraUniform,
     camera_buffer: wgpu::Buffer,
-    camera_bind_group: wgpu::BindGroup,
+    camera_buffer_id: ResourceId,
+    // Time, resolution and mouse for every pipeline
+    frame: FrameGlobals,
+    camera_bind_group_layout: wgpu::BindGroupLayout,
+    camera_bind_group_layout_id: ResourceId,
     camera_controller: CameraController,
-    instances: Vec<Instance>,
+    // Eye and target easing towards a newly loaded scene's camera or a
+    // bookmark, and the camera to end up with
+    camera_tween: Option<(TweenHandle<Vec3>, TweenHandle<Vec3>, SceneCamera)>,
+    bookmarks: CameraBookmarks,
+    tweens: Tweens,
+    last_update: instant::Instant,
+    // Scene animations, and where each scene entity ended up in `instances`
+    #[cfg(not(feature = "ecs"))]
+    animations: Vec<AnimationPlayer>,
+    #[cfg(not(feature = "ecs"))]
+    ik: IkRig,
+    // Entities with a trail and where they've been. Their Trail is read from
+    // the scene every frame, so the inspector can change it.
+    #[cfg(not(feature = "ecs"))]
+    trails: Vec<(usize, trail::TrailPoints)>,
+    #[cfg(not(feature = "ecs"))]
+    entity_instances: Vec<u32>,
+    // The scene's parents as indices, for posing it every frame
+    #[cfg(not(feature = "ecs"))]
+    hierarchy: SceneHierarchy,
+    instances: Vec<Transform>,
     instance_buffer: wgpu::Buffer,
-    depth_texture: texture::Texture,
+    // Instances pose() moved, uploaded at the start of the next render()
+    #[cfg(not(feature = "ecs"))]
+    moved_instances: Vec<u32>,
+    // Over the world bounds of every instance, object i is instances[i]
+    scene_bvh: Bvh,
+    // Per-frame draws, rebuilt every frame
+    draw_list: DrawList,
+    // The scene never moves, so it is recorded once into a bundle
+    static_geometry: StaticBundle,
+    transient_pool: TransientPool,
+    uploader: Uploader,
+    // Set by the app every frame
+    settings: RenderSettings,
+    // The scene is drawn in this, Texture::HDR_FORMAT unless the GPU can't
+    // render to it
+    hdr_format: wgpu::TextureFormat,
+    // Exposes and tonemaps the scene onto the surface
+    tonemap: Tonemap,
+    // None without compute shaders
+    eye_adaptation: Option<EyeAdaptation>,
+    fog: Option<VolumetricFog>,
+    outline: Outline,
+    pixel_art: PixelArt,
+    crt: Crt,
+    sky: Sky,
+    // Draws the scene again for every mirror in view
+    mirror_passes: Mirrors,
+    // Cubemaps of the scene from its reflection probes, for glossy entities
+    probes: ReflectionProbes,
+    // One for each of RenderSettings::viewports
+    viewport_cameras: ViewportCameras,
+    parallax: Parallax,
+    emissive: Emissive,
+    toon: Toon,
+    lightmap: Lightmap,
+    // None without multiview
+    stereo: Option<Stereo>,
+    highlight: Highlight,
+    bloom: Bloom,
+    // Whether eye adaptation ran last frame, it starts over when it didn't
+    adapting: bool,
+    // Holds the last frame while the main pass is preserving it
+    accumulation: Option<(texture::Texture, (u32, u32))>,
+    // 2D overlay in logical pixels, drawn after the scene
+    sprites: SpriteBatch,
+    // What apps draw text with, see App::overlay()
+    fonts: Fonts,
+    glyph_atlas: GlyphAtlas,
+    // World space lines, drawn over the scene
+    lines: LineBatch,
+    grid: Grid,
+    // Effects apps spawn through Setup::particles, and what draws them
+    particles: particles::Particles,
+    particle_renderer: particles::ParticleRenderer,
+    trail_renderer: trail::TrailRenderer,
+    // The camera's view projection when F4 was pressed, its frustum stays
+    // put while flying around to see what it covers
+    frozen_frustum: Option<Mat4>,
+    // What the window was last set up with, None until the first frame
+    applied_display: Option<DisplaySettings>,
+    profiler: Profiler,
+    white_texture: Handle<texture::Texture>,
+    // Clicking an entity selects it, and the gizmo on it edits its transform.
+    // Edits wait in `edits` until run() can hand them to subscribers.
+    #[cfg(not(feature = "ecs"))]
+    selected: Option<usize>,
+    #[cfg(not(feature = "ecs"))]
+    gizmo: Gizmo,
+    #[cfg(not(feature = "ecs"))]
+    edits: Vec<TransformEdited>,
+    // Shift dragging selects everything inside a rectangle instead. What it
+    // picked waits in `marquee_selected` the same way.
+    #[cfg(not(feature = "ecs"))]
+    marquee: Marquee,
+    #[cfg(not(feature = "ecs"))]
+    marquee_selected: Option<MarqueeSelected>,
+    cursor: Cursor,
+    clipboard: Clipboard,
+    modifiers: ModifiersState,
+    // Drawn by the editor plugin, the commands that need the renderer are
+    // run here, see run_command()
+    #[cfg(feature = "editor")]
+    console: Console,
+    // Set by plugins, see PluginContext::paused
+    paused: bool,
+    // Takes over the keyboard while something wants typed text
+    text_input: TextInput,
+    touches: Touches,
+    pointer: Pointer,
+    // Entities live here instead of the static scene, and get extracted into
+    // the draw list every frame
+    #[cfg(feature = "ecs")]
+    ecs: ecs::EcsWorld,
+    #[cfg(feature = "ecs")]
+    instance_capacity: usize,
 }
 
 impl State {
     // Creating some of the wgpu types requires async code
     async fn new(window: &Window) -> Self {
-        let size = window.inner_size();
-        let camera_controller = CameraController::new(0.03);
-
         // The instance is a handle to our GPU
         // Backends::all => Vulkan + Metal + DX12 + Browser WebGPU
         let instance = wgpu::Instance::new(wgpu::Backends::all());
@@ -392,11 +923,56 @@ impl State {
             })
             .await
             .unwrap();
+        let format = surface.get_supported_formats(&adapter)[0];
+        let mut state =
+            Self::with_target(&adapter, Some(surface), format, window.inner_size(), window.scale_factor()).await;
+        // Only windows read the settings file, golden images shouldn't
+        // depend on it
+        if std::path::Path::new(SETTINGS_PATH).exists() {
+            match GraphicsSettings::load(SETTINGS_PATH) {
+                Ok(settings) => settings.apply(&mut state.settings),
+                Err(e) => tracing::error!("Failed to load graphics settings: {:?}", e),
+            }
+        }
+        state
+    }
+
+    // Renders into a texture instead of a window. None if there's no GPU to
+    // render with.
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn headless(size: winit::dpi::PhysicalSize<u32>) -> Option<Self> {
+        let instance = wgpu::Instance::new(wgpu::Backends::all());
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                compatible_surface: None,
+                force_fallback_adapter: false,
+            })
+            .await?;
+        Some(Self::with_target(&adapter, None, wgpu::TextureFormat::Rgba8UnormSrgb, size, 1.0).await)
+    }
+
+    // Everything past picking an adapter, `surface` is None to render
+    // offscreen
+    async fn with_target(
+        adapter: &wgpu::Adapter,
+        surface: Option<wgpu::Surface>,
+        format: wgpu::TextureFormat,
+        size: winit::dpi::PhysicalSize<u32>,
+        scale_factor: f64,
+    ) -> Self {
+        let camera_controller = CameraController::new(0.03);
 
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
-                    features: wgpu::Features::empty(),
+                    // Timestamps are only for the profiler, multiview for
+                    // stereo and border clamping for textures that ask for
+                    // it, so it's fine to go without them
+                    features: adapter.features()
+                        & (wgpu::Features::TIMESTAMP_QUERY
+                            | wgpu::Features::MULTIVIEW
+                            | wgpu::Features::ADDRESS_MODE_CLAMP_TO_BORDER),
                     // WebGL doesn't support all of wgpu's features, so if
                     // we're building for the web we'll have to disable some.
                     limits: if cfg!(target_arch = "wasm32") {
@@ -413,15 +989,31 @@ impl State {
 
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface.get_supported_formats(&adapter)[0],
+            format,
             width: size.width,
             height: size.height,
             present_mode: wgpu::PresentMode::Fifo, // vsync
         };
 
-        surface.configure(&device, &config);
-        let diffuse_bytes = include_bytes!("dot32.png");
-        let diffuse_texture = texture::Texture::from_bytes(&device, &queue, diffuse_bytes, "happy-tree.png").unwrap();
+        // WebGL can't always render to float textures, in which case the
+        // scene is just as bright as the surface can show
+        let hdr_format = if adapter
+            .get_texture_format_features(texture::Texture::HDR_FORMAT)
+            .allowed_usages
+            .contains(wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING)
+        {
+            texture::Texture::HDR_FORMAT
+        } else {
+            config.format
+        };
+
+        let target = match surface {
+            Some(surface) => {
+                surface.configure(&device, &config);
+                Target::Surface(surface)
+            }
+            None => Target::offscreen(&device, &config),
+        };
 
         let texture_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             entries: &[
@@ -447,35 +1039,20 @@ impl State {
             label: Some("texture_bind_group_layout"),
         });
 
-        let diffuse_bind_group = device.create_bind_group(
-            &wgpu::BindGroupDescriptor {
-                layout: &texture_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                    }
-                ],
-                label: Some("diffuse_bind_group"),
-            }
-        );
-
         let camera = Camera {
             // position the camera one unit up and 2 units back
             // +z is out of the screen
-            eye: (0.0, 0.0, 2.0).into(),
+            eye: Vec3::new(0.0, 0.0, 2.0),
             // have it look at the origin
-            target: (0.0, 0.0, 0.0).into(),
+            target: Vec3::ZERO,
             // which way is "up"
-            up: cgmath::Vector3::unit_y(),
+            up: Vec3::Y,
             aspect: config.width as f32 / config.height as f32,
             fovy: 45.0,
             znear: 0.1,
             zfar: 100.0,
+            draw_distance: f32::INFINITY,
+            shake: Transform::IDENTITY,
         };
     
         let mut camera_uniform = CameraUniform::new();
@@ -505,21 +1082,14 @@ impl State {
             label: Some("camera_bind_group_layout"),
         });
 
-        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &camera_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: camera_buffer.as_entire_binding(),
-                }
-            ],
-            label: Some("camera_bind_group"),
-        });
+        // Bind groups are created on demand through the cache, so we only need
+        // ids for everything that goes into them.
+        let mut bind_group_cache = BindGroupCache::new();
+        let texture_bind_group_layout_id = bind_group_cache.register();
+        let camera_buffer_id = bind_group_cache.register();
+        let camera_bind_group_layout_id = bind_group_cache.register();
 
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
-        });
+        let frame = FrameGlobals::new(&device);
 
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
@@ -527,253 +1097,2925 @@ impl State {
                 bind_group_layouts: &[
                     &texture_bind_group_layout,
                     &camera_bind_group_layout,    
+                    frame.layout(),
                 ],
                 push_constant_ranges: &[],
             });
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                // buffers: &[Vertex::desc()],
-                buffers: &[Vertex::desc(), InstanceRaw::desc()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw, // Counter clockwise
-                cull_mode: None,//Some(wgpu::Face::Back),
-                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-                polygon_mode: wgpu::PolygonMode::Fill,
-                // Requires Features::DEPTH_CLIP_CONTROL
-                unclipped_depth: false,
-                // Requires Features::CONSERVATIVE_RASTERIZATION
-                conservative: false,
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: texture::Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less, // 1.
-                stencil: wgpu::StencilState::default(), // 2.
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-        });
-
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
-            contents: bytemuck::cast_slice(VERTICES),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
-        let index_buffer = device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Index Buffer"),
-                contents: bytemuck::cast_slice(INDICES),
-                usage: wgpu::BufferUsages::INDEX,
+        let mut mesh_pool = MeshPool::new(
+            &device,
+            "Mesh Pool",
+            std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
+            1024,
+            4096,
+        );
+        let quad_mesh = mesh_pool.allocate(&device, &queue, VERTICES, INDICES);
+        // Where meshes are in their lightmaps, only read by the lightmap pipeline
+        let lightmap_stream = mesh_pool.add_stream::<VertexLightmapUv>(&device);
+        mesh_pool.write_stream(&queue, lightmap_stream, &quad_mesh, &quad_geometry().vertices::<VertexLightmapUv>());
+
+        // Decoded in the background, a placeholder gets drawn until it's ready
+        let mut assets = Assets::new(&device, &queue, &mut mesh_pool, lightmap_stream);
+        let diffuse_texture = assets.load_texture_from_bytes("dot32.png", include_bytes!("dot32.png"));
+        let profiler = Profiler::new(&device, &queue);
+        // For sprites that are just a colour
+        let white_texture = assets.load_texture_from_bytes("white.png", include_bytes!("white.png"));
+        let shaders = Shaders::load(&mut assets);
+
+        // Edits to the files these were embedded from get picked up while running
+        #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+        {
+            assets.watch_file(diffuse_texture.id(), concat!(env!("CARGO_MANIFEST_DIR"), "/src/dot32.png"));
+            if let Err(e) = assets.watch(concat!(env!("CARGO_MANIFEST_DIR"), "/src")) {
+                tracing::warn!("Hot reloading is disabled: {:?}", e);
             }
+        }
+
+        let render_pipeline = create_render_pipeline(
+            &device,
+            &render_pipeline_layout,
+            hdr_format,
+            &shaders.source(&assets, shaders.mesh).expect("embedded shaders are always loaded"),
+            &mesh_pool.stream_layouts(),
         );
-        let num_indices = INDICES.len() as u32;
         
-        let instances = (0..NUM_INSTANCES_PER_ROW).flat_map(|z| {
-            (0..NUM_INSTANCES_PER_ROW).map(move |x| {
-                let position = cgmath::Vector3 { x: x as f32, y: 0.0, z: z as f32 } - INSTANCE_DISPLACEMENT;
-
-                let rotation = if position.is_zero() {
-                    // this is needed so an object at (0, 0, 0) won't get scaled to zero
-                    // as Quaternions can effect scale if they're not created correctly
-                    cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_z(), cgmath::Deg(0.0))
-                } else {
-                    cgmath::Quaternion::from_axis_angle(position.normalize(), cgmath::Deg(45.0))
-                };
-
-                Instance {
-                    position, rotation,
-                }
-            })
-        }).collect::<Vec<_>>();
-
-        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-        let instance_buffer = device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Instance Buffer"),
-                contents: bytemuck::cast_slice(&instance_data),
-                usage: wgpu::BufferUsages::VERTEX,
-            }
+        let parallax = Parallax::new(
+            &device,
+            hdr_format,
+            &camera_bind_group_layout,
+            frame.layout(),
+            &mut bind_group_cache,
+            &shaders.source(&assets, shaders.parallax).expect("embedded shaders are always loaded"),
+            &mesh_pool.stream_layouts(),
+        );
+        let emissive = Emissive::new(
+            &device,
+            hdr_format,
+            &camera_bind_group_layout,
+            frame.layout(),
+            &mut bind_group_cache,
+            &shaders.source(&assets, shaders.emissive).expect("embedded shaders are always loaded"),
+            &mesh_pool.stream_layouts(),
+        );
+        let toon = Toon::new(
+            &device,
+            hdr_format,
+            &camera_bind_group_layout,
+            frame.layout(),
+            parallax.light_layout(),
+            &mut bind_group_cache,
+            &shaders.source(&assets, shaders.toon).expect("embedded shaders are always loaded"),
+            &mesh_pool.stream_layouts(),
+        );
+        let lightmap = Lightmap::new(
+            &device,
+            hdr_format,
+            &camera_bind_group_layout,
+            frame.layout(),
+            parallax.light_layout(),
+            &mut bind_group_cache,
+            &shaders.source(&assets, shaders.lightmap).expect("embedded shaders are always loaded"),
+            &mesh_pool.stream_layouts(),
+        );
+        let highlight = Highlight::new(
+            &device,
+            hdr_format,
+            &camera_bind_group_layout,
+            frame.layout(),
+            &shaders.source(&assets, shaders.highlight).expect("embedded shaders are always loaded"),
+            &mesh_pool.stream_layouts(),
+        );
+        let stereo = device.features().contains(wgpu::Features::MULTIVIEW).then(|| {
+            Stereo::new(
+                &device,
+                hdr_format,
+                &texture_bind_group_layout,
+                &camera_bind_group_layout,
+                frame.layout(),
+                &shaders.source(&assets, shaders.stereo).expect("embedded shaders are always loaded"),
+                &mesh_pool.stream_layouts(),
+            )
+        });
+        
+        let default_material = Material {
+            source: MaterialRef::Default,
+            texture: diffuse_texture,
+            maps: MaterialMaps::Plain,
+            id: bind_group_cache.register(),
+        };
+        // Filled in by apply_scene()
+        let instance_buffer = create_instance_buffer(&device, &[]);
+
+        let sprites = SpriteBatch::new(
+            &device,
+            config.format,
+            &texture_bind_group_layout,
+            frame.layout(),
+            &shaders.source(&assets, shaders.sprite).expect("embedded shaders are always loaded"),
+        );
+        let glyph_atlas = GlyphAtlas::new(&device, &mut assets);
+        let tonemap = Tonemap::new(
+            &device,
+            config.format,
+            frame.layout(),
+            &shaders.source(&assets, shaders.tonemap).expect("embedded shaders are always loaded"),
+        );
+        let compute = adapter.get_downlevel_capabilities().flags.contains(wgpu::DownlevelFlags::COMPUTE_SHADERS);
+        let eye_adaptation = compute.then(|| {
+            EyeAdaptation::new(
+                &device,
+                &shaders.source(&assets, shaders.exposure).expect("embedded shaders are always loaded"),
+            )
+        });
+        let fog = compute.then(|| {
+            VolumetricFog::new(
+                &device,
+                hdr_format,
+                &shaders.source(&assets, shaders.fog).expect("embedded shaders are always loaded"),
+            )
+        });
+        let outline = Outline::new(
+            &device,
+            hdr_format,
+            &shaders.source(&assets, shaders.outline).expect("embedded shaders are always loaded"),
+        );
+        let pixel_art = PixelArt::new(
+            &device,
+            config.format,
+            &shaders.source(&assets, shaders.pixel_art).expect("embedded shaders are always loaded"),
+        );
+        let crt = Crt::new(
+            &device,
+            config.format,
+            &shaders.source(&assets, shaders.crt).expect("embedded shaders are always loaded"),
+        );
+        let bloom = Bloom::new(
+            &device,
+            hdr_format,
+            &shaders.source(&assets, shaders.bloom).expect("embedded shaders are always loaded"),
+        );
+        let sky = Sky::new(
+            &device,
+            hdr_format,
+            &camera_bind_group_layout,
+            &shaders.source(&assets, shaders.sky).expect("embedded shaders are always loaded"),
+        );
+        let mirror_passes = Mirrors::new(
+            &device,
+            hdr_format,
+            &camera_bind_group_layout,
+            frame.layout(),
+            &shaders.source(&assets, shaders.mirror).expect("embedded shaders are always loaded"),
+        );
+        let probes = ReflectionProbes::new(
+            &device,
+            hdr_format,
+            &camera_bind_group_layout,
+            &shaders.source(&assets, shaders.probe).expect("embedded shaders are always loaded"),
+        );
+        let lines = LineBatch::new(
+            &device,
+            hdr_format,
+            &camera_bind_group_layout,
+            frame.layout(),
+            &shaders.source(&assets, shaders.lines).expect("embedded shaders are always loaded"),
+        );
+        let grid = Grid::new(
+            &device,
+            hdr_format,
+            frame.layout(),
+            &shaders.source(&assets, shaders.grid).expect("embedded shaders are always loaded"),
+        );
+        let particle_renderer = particles::ParticleRenderer::new(
+            &device,
+            &queue,
+            hdr_format,
+            &camera_bind_group_layout,
+            frame.layout(),
+            &shaders.source(&assets, shaders.particles).expect("embedded shaders are always loaded"),
+            &mut bind_group_cache,
+        );
+        let trail_renderer = trail::TrailRenderer::new(
+            &device,
+            &queue,
+            hdr_format,
+            &camera_bind_group_layout,
+            frame.layout(),
+            &shaders.source(&assets, shaders.trails).expect("embedded shaders are always loaded"),
+            &mut bind_group_cache,
         );
 
-        let depth_texture = texture::Texture::create_depth_texture(&device, &config, "depth_texture");
-
+        // Attachments like the depth buffer are allocated by the render graph
+        let transient_pool = TransientPool::new();
 
-        Self {
-            surface,
+        let mut state = Self {
+            target,
+            adapter_info: adapter.get_info(),
             device,
             queue,
             config,
+            render_pipeline_layout,
             render_pipeline,
-            vertex_buffer,
-            num_indices,
-            index_buffer,
-            size,
-            diffuse_bind_group,
-            diffuse_texture,
+            shaders,
+            mesh_pool,
+            quad_mesh,
+            screen: Screen::new(size, scale_factor),
+            bind_group_cache,
+            texture_bind_group_layout,
+            texture_bind_group_layout_id,
+            materials: vec![default_material],
+            assets,
+            scene: Scene::default(),
+            scene_meshes: Vec::new(),
+            batches: Vec::new(),
+            mirrors: Vec::new(),
+            glossy: Vec::new(),
             camera,
             camera_uniform,
             camera_buffer,
-            camera_bind_group,
+            camera_buffer_id,
+            frame,
+            camera_bind_group_layout,
+            camera_bind_group_layout_id,
             camera_controller,
-            instances,
+            camera_tween: None,
+            bookmarks: CameraBookmarks::default(),
+            tweens: Tweens::new(),
+            last_update: instant::Instant::now(),
+            #[cfg(not(feature = "ecs"))]
+            animations: Vec::new(),
+            #[cfg(not(feature = "ecs"))]
+            ik: IkRig::default(),
+            #[cfg(not(feature = "ecs"))]
+            trails: Vec::new(),
+            #[cfg(not(feature = "ecs"))]
+            entity_instances: Vec::new(),
+            #[cfg(not(feature = "ecs"))]
+            hierarchy: SceneHierarchy::default(),
+            instances: Vec::new(),
             instance_buffer,
-            depth_texture,
+            #[cfg(not(feature = "ecs"))]
+            moved_instances: Vec::new(),
+            scene_bvh: Bvh::default(),
+            draw_list: DrawList::new(),
+            static_geometry: StaticBundle::new(),
+            transient_pool,
+            uploader: Uploader::new(),
+            settings: RenderSettings::default(),
+            hdr_format,
+            tonemap,
+            eye_adaptation,
+            fog,
+            outline,
+            pixel_art,
+            crt,
+            sky,
+            mirror_passes,
+            probes,
+            viewport_cameras: ViewportCameras::new(),
+            parallax,
+            emissive,
+            toon,
+            lightmap,
+            stereo,
+            highlight,
+            bloom,
+            adapting: false,
+            accumulation: None,
+            sprites,
+            fonts: Fonts::new(),
+            glyph_atlas,
+            lines,
+            grid,
+            particles: particles::Particles::new(),
+            particle_renderer,
+            trail_renderer,
+            frozen_frustum: None,
+            applied_display: None,
+            profiler,
+            white_texture,
+            #[cfg(not(feature = "ecs"))]
+            selected: None,
+            #[cfg(not(feature = "ecs"))]
+            gizmo: Gizmo::new(),
+            #[cfg(not(feature = "ecs"))]
+            edits: Vec::new(),
+            #[cfg(not(feature = "ecs"))]
+            marquee: Marquee::new(),
+            #[cfg(not(feature = "ecs"))]
+            marquee_selected: None,
+            cursor: Cursor::new(),
+            clipboard: Clipboard::new(),
+            #[cfg(feature = "editor")]
+            console: Console::new(),
+            paused: false,
+            modifiers: ModifiersState::empty(),
+            text_input: TextInput::new(),
+            touches: Touches::new(),
+            pointer: Pointer::new(),
+            #[cfg(feature = "ecs")]
+            ecs: ecs::EcsWorld::new(),
+            #[cfg(feature = "ecs")]
+            instance_capacity: 1,
+        };
+
+        let scene = if std::path::Path::new(SCENE_PATH).exists() {
+            Scene::load(SCENE_PATH).unwrap_or_else(|e| {
+                tracing::error!("Failed to load scene, using the demo scene instead: {:?}", e);
+                demo_scene()
+            })
+        } else {
+            demo_scene()
+        };
+        #[cfg(feature = "ecs")]
+        tracing::warn!("Built with the ecs feature: {}", ecs::UNSUPPORTED);
+        state.apply_scene(scene);
+        if std::path::Path::new(BOOKMARKS_PATH).exists() {
+            match CameraBookmarks::load(BOOKMARKS_PATH) {
+                Ok(bookmarks) => state.bookmarks = bookmarks,
+                Err(e) => tracing::error!("Failed to load camera bookmarks: {:?}", e),
+            }
         }
+        state
     }
 
     fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        let _span = tracing::info_span!("resize", width = new_size.width, height = new_size.height).entered();
         if new_size.width > 0 && new_size.height > 0 {
-            self.size = new_size;
+            self.screen.physical_size = new_size;
             self.config.width = new_size.width;
             self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
-            self.depth_texture = texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
+            match &self.target {
+                Target::Surface(surface) => surface.configure(&self.device, &self.config),
+                Target::Offscreen(_) => self.target = Target::offscreen(&self.device, &self.config),
+            }
+            self.camera.aspect = self.screen.aspect();
+            // A window dragged to a new size keeps it when the settings are
+            // saved, instead of going back next time
+            let display = &mut self.settings.display;
+            if display.resolution.is_some() && !display.fullscreen {
+                display.resolution = Some((new_size.width, new_size.height));
+                if let Some(applied) = &mut self.applied_display {
+                    applied.resolution = display.resolution;
+                }
+            }
         }
     }
 
-    fn input(&mut self, event: &WindowEvent) -> bool {
-        self.camera_controller.process_events(event)
+    // Catches the window and the surface up with settings.display
+    fn apply_display(&mut self, window: &Window) {
+        let display = self.settings.display;
+        if self.applied_display.replace(display) == Some(display) {
+            return;
+        }
+        if self.config.present_mode != display.present_mode() {
+            self.config.present_mode = display.present_mode();
+            if let Target::Surface(surface) = &self.target {
+                surface.configure(&self.device, &self.config);
+            }
+        }
+        if window.fullscreen().is_some() != display.fullscreen {
+            window.set_fullscreen(display.fullscreen.then_some(winit::window::Fullscreen::Borderless(None)));
+        }
+        // Coming out of fullscreen goes back to the resolution too
+        if let (Some((width, height)), false) = (display.resolution, display.fullscreen) {
+            if window.inner_size() != winit::dpi::PhysicalSize::new(width, height) {
+                window.set_inner_size(winit::dpi::PhysicalSize::new(width, height));
+            }
+        }
     }
 
-    fn update(&mut self) {
-        self.camera_controller.update_camera(&mut self.camera);
-        self.camera_uniform.update_view_proj(&self.camera);
-        self.queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
+    // The scene goes onto the clipboard as RON, handy for pasting into a
+    // scene file or another running copy
+    fn copy_scene(&mut self) {
+        self.scene.camera = self.camera.to_scene();
+        match self.scene.to_ron() {
+            Ok(text) => self.clipboard.set_text(text),
+            Err(e) => tracing::error!("Failed to copy the scene: {:?}", e),
+        }
     }
 
-    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let output = self.surface.get_current_texture()?;
-        let view = output
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
-
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Render Encoder"),
-            });
-
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            // r: 0.1,
-                            // g: 0.2,
-                            // b: 0.3,
-                            // a: 1.0,
-                            r: 0.013,
-                            g: 0.013,
-                            b: 0.013,
-                            a: 1.013,
-                        }),
-                        store: true,
-                    },
-                })],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_texture.view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: true,
-                    }),
-                    stencil_ops: None,
-                }),
-            });
+    // Pasting an image saves it next to the scene and adds it as a textured
+    // quad, pasting RON replaces the scene and pasting a path loads that file
+    fn paste(&mut self) {
+        if let Some(image) = self.clipboard.image() {
+            let millis = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|time| time.as_millis())
+                .unwrap_or_default();
+            let path = std::path::PathBuf::from("pasted").join(format!("paste_{}.png", millis));
+            let saved = std::fs::create_dir_all("pasted").map_err(anyhow::Error::from).and_then(|_| Ok(image.save(&path)?));
+            match saved {
+                Ok(()) => self.file_dropped(&path),
+                Err(e) => tracing::error!("Failed to save the pasted image: {:?}", e),
+            }
+            return;
+        }
 
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
-            render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-            render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-            // render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
-            render_pass.draw_indexed(0..self.num_indices, 0, 0..self.instances.len() as _);
+        let Some(text) = self.clipboard.text() else {
+            return;
+        };
+        let path = std::path::Path::new(text.trim());
+        if path.is_file() {
+            self.file_dropped(path);
+            return;
         }
+        match Scene::from_ron(&text) {
+            Ok(scene) => self.apply_scene(scene),
+            Err(e) => tracing::warn!("Clipboard doesn't hold a scene: {:?}", e),
+        }
+    }
 
-        // submit will accept anything that implements IntoIter
-        self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+    // Dropped models and images get added to the scene in front of the
+    // camera, dropped scene files replace the current scene
+    fn file_dropped(&mut self, path: &std::path::Path) {
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        let (mesh, material) = match extension.as_str() {
+            "obj" | "gltf" | "glb" => (MeshRef::Model(path.to_path_buf()), MaterialRef::Default),
+            "png" | "jpg" | "jpeg" => (MeshRef::Quad, MaterialRef::Texture(path.to_path_buf())),
+            "ron" | "json" => {
+                match Scene::load(path) {
+                    Ok(scene) => self.apply_scene(scene),
+                    Err(e) => tracing::error!("Failed to load dropped scene: {:?}", e),
+                }
+                return;
+            }
+            _ => {
+                tracing::warn!("Don't know how to load dropped file {}", path.display());
+                return;
+            }
+        };
 
-        Ok(())
+        let mut scene = self.scene.clone();
+        // Otherwise applying the scene would snap the camera back
+        scene.camera = self.camera.to_scene();
+        let position = self.camera.eye + (self.camera.target - self.camera.eye).normalize_or_zero() * 2.0;
+        scene.entities.push(SceneEntity {
+            name: path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default(),
+            parent: None,
+            transform: SceneTransform::from(&Transform::from_translation(position)),
+            mesh,
+            material,
+            mirror: None,
+            gloss: None,
+            trail: None,
+        });
+        tracing::info!("Loading dropped file {}", path.display());
+        self.apply_scene(scene);
     }
-}
-
-pub async fn run() {
-    env_logger::init();
-    let event_loop = EventLoop::new();
-    let window = WindowBuilder::new().build(&event_loop).unwrap();
 
-    let mut state = State::new(&window).await;
+    // Moving to a display with a different DPI changes how many physical
+    // pixels a logical one covers, the surface gets resized to match
+    fn rescale(&mut self, scale_factor: f64, new_size: winit::dpi::PhysicalSize<u32>) {
+        tracing::info!("Scale factor changed to {}", scale_factor);
+        self.screen.scale_factor = scale_factor;
+        self.resize(new_size);
+    }
 
-    event_loop.run(move |event, _, control_flow| {
-        match event {
-            Event::RedrawRequested(window_id) if window_id == window.id() => {
-                state.update();
-                match state.render() {
-                    Ok(_) => {}
-                    // Reconfigure the surface if lost
-                    Err(wgpu::SurfaceError::Lost) => state.resize(state.size),
-                    // The system is out of memory, we should probably quit
-                    Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
-                    // All other errors (Outdated, Timeout) should be resolved by the next frame
-                    Err(e) => eprintln!("{:?}", e),
+    fn input(&mut self, event: &WindowEvent) -> bool {
+        self.cursor.handle_event(event, &self.screen);
+        if self.pointer.handle_event(event, &self.screen) {
+            return true;
+        }
+        if self.touches.handle_event(event, &self.screen) {
+            return true;
+        }
+        if let WindowEvent::ModifiersChanged(modifiers) = event {
+            self.modifiers = *modifiers;
+        }
+        // Cmd on macOS
+        let shortcut = self.modifiers.ctrl() || self.modifiers.logo();
+        if !shortcut && self.text_input.handle_event(event) {
+            return true;
+        }
+        if let WindowEvent::KeyboardInput {
+            input: KeyboardInput {
+                state: ElementState::Pressed,
+                virtual_keycode: Some(keycode),
+                ..
+            },
+            ..
+        } = event
+        {
+            match keycode {
+                VirtualKeyCode::C if shortcut => {
+                    self.copy_scene();
+                    return true;
                 }
-            }
-            Event::MainEventsCleared => {
-                // RedrawRequested will only trigger once, unless we manually
-                // request it.
-                window.request_redraw();
-            }
-            Event::WindowEvent {
-                ref event,
-                window_id,
-            } if window_id == window.id() => {
-                if !state.input(event) {
-                    // UPDATED!
-                    match event {
-                        WindowEvent::CloseRequested
-                        | WindowEvent::KeyboardInput {
-                            input:
-                                KeyboardInput {
-                                    state: ElementState::Pressed,
-                                    virtual_keycode: Some(VirtualKeyCode::Escape),
-                                    ..
-                                },
-                            ..
-                        } => *control_flow = ControlFlow::Exit,
-                        WindowEvent::Resized(physical_size) => {
-                            state.resize(*physical_size);
-                        }
-                        WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                            state.resize(**new_inner_size);
-                        }
-                        _ => {}
+                VirtualKeyCode::V if shortcut => {
+                    self.paste();
+                    return true;
+                }
+                VirtualKeyCode::F5 => {
+                    self.scene.camera = self.camera.to_scene();
+                    match self.scene.save(SCENE_PATH) {
+                        Ok(()) => tracing::info!("Saved scene to {}", SCENE_PATH),
+                        Err(e) => tracing::error!("Failed to save scene: {:?}", e),
+                    }
+                    return true;
+                }
+                // Swaps the system cursor for a texture drawn by the sprite batch
+                VirtualKeyCode::F2 => {
+                    let custom = match self.cursor.custom() {
+                        Some(_) => None,
+                        None => Some(CustomCursor {
+                            texture: self.materials[0].texture,
+                            size: glam::Vec2::splat(32.0),
+                            hotspot: glam::Vec2::splat(16.0),
+                        }),
+                    };
+                    self.cursor.set_custom(custom);
+                    return true;
+                }
+                VirtualKeyCode::G => {
+                    self.settings.grid = !self.settings.grid;
+                    return true;
+                }
+                VirtualKeyCode::H => {
+                    self.settings.hover_highlight = !self.settings.hover_highlight;
+                    return true;
+                }
+                VirtualKeyCode::N => {
+                    self.settings.dither = !self.settings.dither;
+                    return true;
+                }
+                VirtualKeyCode::O => {
+                    self.settings.outline = match self.settings.outline {
+                        Some(_) => None,
+                        None => Some(OutlineSettings::default()),
+                    };
+                    return true;
+                }
+                VirtualKeyCode::P => {
+                    self.settings.pixel_art = match self.settings.pixel_art {
+                        Some(_) => None,
+                        None => Some(PixelArtSettings::default()),
+                    };
+                    return true;
+                }
+                VirtualKeyCode::T => {
+                    self.settings.crt = match self.settings.crt {
+                        Some(_) => None,
+                        None => Some(CrtSettings::default()),
+                    };
+                    return true;
+                }
+                VirtualKeyCode::B => {
+                    self.settings.bloom = match self.settings.bloom {
+                        Some(_) => None,
+                        None => Some(BloomSettings::default()),
+                    };
+                    return true;
+                }
+                VirtualKeyCode::F6 => {
+                    self.settings.profiler = !self.settings.profiler;
+                    return true;
+                }
+                // Holds on to the frame on screen and logs its timings
+                VirtualKeyCode::F7 => {
+                    self.profiler.toggle_freeze();
+                    self.settings.profiler |= self.profiler.is_frozen();
+                    return true;
+                }
+                #[cfg(all(feature = "puffin", not(target_arch = "wasm32")))]
+                VirtualKeyCode::F8 => {
+                    match self.profiler.save_puffin(PUFFIN_PATH) {
+                        Ok(()) => tracing::info!("Saved profile to {}, open it with puffin_viewer", PUFFIN_PATH),
+                        Err(e) => tracing::error!("Failed to save profile: {:?}", e),
                     }
+                    return true;
+                }
+                VirtualKeyCode::F3 => {
+                    self.settings.debug_volumes = !self.settings.debug_volumes;
+                    return true;
+                }
+                VirtualKeyCode::F4 => {
+                    self.frozen_frustum = match self.frozen_frustum {
+                        Some(_) => None,
+                        None => Some(self.camera.build_view_projection_matrix()),
+                    };
+                    // No point freezing it without seeing it
+                    self.settings.debug_volumes |= self.frozen_frustum.is_some();
+                    return true;
+                }
+                VirtualKeyCode::F9 => {
+                    match Scene::load(SCENE_PATH) {
+                        Ok(scene) => {
+                            // Glide over to the saved camera instead of jumping
+                            let (eye, target) = (self.camera.eye, self.camera.target);
+                            self.apply_scene(scene);
+                            self.camera.eye = eye;
+                            self.camera.target = target;
+                            self.glide_camera(self.scene.camera.clone());
+                        }
+                        Err(e) => tracing::error!("Failed to load scene: {:?}", e),
+                    }
+                    return true;
+                }
+                VirtualKeyCode::F10 => {
+                    self.settings.exposure = match self.settings.exposure {
+                        Exposure::Fixed(_) | Exposure::Manual(_) => Exposure::Auto(AutoExposure::default()),
+                        Exposure::Auto(_) => Exposure::Fixed(1.0),
+                    };
+                    if self.eye_adaptation.is_none() {
+                        tracing::warn!("Automatic exposure needs compute shaders, staying at a fixed exposure");
+                    }
+                    return true;
+                }
+                // Not an F key, shadertoy mode takes screenshots with F12
+                VirtualKeyCode::V => {
+                    self.settings.stereo = match self.settings.stereo {
+                        Some(_) => None,
+                        None => Some(StereoSettings::default()),
+                    };
+                    if self.stereo.is_none() {
+                        tracing::warn!("Stereo needs multiview, which this GPU doesn't have");
+                    }
+                    return true;
+                }
+                VirtualKeyCode::F11 => {
+                    self.settings.fog = match self.settings.fog {
+                        Some(_) => None,
+                        None => Some(FogSettings::default()),
+                    };
+                    if self.fog.is_none() {
+                        tracing::warn!("Volumetric fog needs compute shaders, leaving it out");
+                    }
+                    return true;
+                }
+                key if shortcut => {
+                    if let Some(slot) = bookmarks::slot_for_key(*key) {
+                        self.bookmarks.set(slot, self.camera.to_scene());
+                        match self.bookmarks.save(BOOKMARKS_PATH) {
+                            Ok(()) => tracing::info!("Saved camera bookmark {}", slot + 1),
+                            Err(e) => tracing::error!("Failed to save camera bookmarks: {:?}", e),
+                        }
+                        return true;
+                    }
+                }
+                _ => {}
+            }
+        }
+        #[cfg(not(feature = "ecs"))]
+        if self.gizmo_input(event) {
+            return true;
+        }
+        if let WindowEvent::KeyboardInput {
+            input: KeyboardInput {
+                state: ElementState::Pressed,
+                virtual_keycode: Some(keycode),
+                ..
+            },
+            ..
+        } = event
+        {
+            if let Some(slot) = bookmarks::slot_for_key(*keycode) {
+                match self.bookmarks.get(slot) {
+                    Some(camera) => self.glide_camera(camera.clone()),
+                    None => tracing::info!("No camera bookmark {}, Ctrl+{} saves one", slot + 1, slot + 1),
+                }
+                return true;
+            }
+        }
+        self.camera_controller.process_events(event)
+    }
+
+    // Eases the camera over to `to` instead of jumping there
+    fn glide_camera(&mut self, to: SceneCamera) {
+        let end = Camera::from_scene(&to, self.camera.aspect);
+        self.camera_tween = Some((
+            self.tweens.start(Tween::new(self.camera.eye, end.eye, 0.5).easing(Easing::CubicInOut)),
+            self.tweens.start(Tween::new(self.camera.target, end.target, 0.5).easing(Easing::CubicInOut)),
+            to,
+        ));
+    }
+
+    fn scene_mesh(&mut self, source: &MeshRef) -> usize {
+        if let Some(index) = self.scene_meshes.iter().position(|m| m.source == *source) {
+            return index;
+        }
+        let model = match source {
+            MeshRef::Model(path) => Some(self.assets.load_model(path)),
+            MeshRef::Quad | MeshRef::Cube => None,
+        };
+        self.scene_meshes.push(SceneMesh {
+            source: source.clone(),
+            model,
+        });
+        self.scene_meshes.len() - 1
+    }
+
+    fn material(&mut self, source: &MaterialRef) -> usize {
+        if let Some(index) = self.materials.iter().position(|m| m.source == *source) {
+            return index;
+        }
+        let (texture, maps) = match source {
+            MaterialRef::Default => (self.materials[0].texture, MaterialMaps::Plain),
+            MaterialRef::Texture(path) => (self.assets.load_streamed_texture(path), MaterialMaps::Plain),
+            MaterialRef::Parallax(material) => {
+                // Heights are data, not colours
+                let height = self.assets.load_streamed_texture_with(
+                    &material.height,
+                    texture::SamplerOptions::mipmapped(),
+                    texture::ColorSpace::Linear,
+                );
+                let buffer = Parallax::create_material_buffer(&self.device, &material.settings);
+                (self.assets.load_streamed_texture(&material.texture), MaterialMaps::Parallax(height, buffer))
+            }
+            MaterialRef::Emissive(material) => {
+                let texture = match &material.texture {
+                    Some(path) => self.assets.load_streamed_texture(path),
+                    None => self.materials[0].texture,
+                };
+                // Without a texture the whole surface glows the same
+                let emissive = match &material.emissive_texture {
+                    Some(path) => self.assets.load_streamed_texture(path),
+                    None => self.white_texture,
+                };
+                let buffer = Emissive::create_material_buffer(&self.device, material.color);
+                (texture, MaterialMaps::Emissive(emissive, buffer))
+            }
+            MaterialRef::Toon(material) => {
+                let texture = match &material.texture {
+                    Some(path) => self.assets.load_streamed_texture(path),
+                    None => self.materials[0].texture,
+                };
+                let buffer = Toon::create_material_buffer(&self.device, material);
+                let outlined = material.outline.is_some_and(|outline| outline.width > 0.0);
+                (texture, MaterialMaps::Toon(buffer, outlined))
+            }
+            MaterialRef::Lightmapped(material) => {
+                let texture = match &material.texture {
+                    Some(path) => self.assets.load_streamed_texture(path),
+                    None => self.materials[0].texture,
+                };
+                let lightmap = self.assets.load_streamed_texture(&material.lightmap);
+                let buffer = Lightmap::create_material_buffer(&self.device, material);
+                (texture, MaterialMaps::Lightmapped(lightmap, buffer))
+            }
+        };
+        self.materials.push(Material {
+            source: source.clone(),
+            texture,
+            maps,
+            id: self.bind_group_cache.register(),
+        });
+        self.materials.len() - 1
+    }
+
+    // Replaces whatever is being drawn with the scene's entities
+    fn apply_scene(&mut self, scene: Scene) {
+        self.camera = Camera::from_scene(&scene.camera, self.camera.aspect);
+        if let Some(camera) = scene.exposure {
+            self.settings.exposure = Exposure::Manual(camera);
+        }
+        let (znear, zfar) = (scene.camera.znear, scene.camera.zfar);
+        self.probes.set(&self.device, &self.camera_bind_group_layout, &scene.probes, znear, zfar);
+
+        // Drawn from the world every frame instead, see extract_draws()
+        #[cfg(feature = "ecs")]
+        {
+            if !scene.animations.is_empty() || !scene.ik.is_empty() {
+                tracing::warn!(
+                    "The scene's {} animations and {} IK chains won't play, {}",
+                    scene.animations.len(),
+                    scene.ik.len(),
+                    ecs::UNSUPPORTED
+                );
+            }
+            self.ecs.spawn_scene(&scene);
+        }
+        #[cfg(not(feature = "ecs"))]
+        self.batch_instances(&scene);
+        self.scene = scene;
+        #[cfg(not(feature = "ecs"))]
+        {
+            self.scene_bvh = Bvh::build(&self.instance_bounds());
+            // Entity indices mean something else in the new scene
+            self.selected = None;
+            self.gizmo.end_drag();
+        }
+
+        // The old bundle was recorded with the old instance buffer
+        self.static_geometry.invalidate();
+        self.build_static_draws();
+    }
+
+    // Instances get sorted by mesh, material and distance from the camera so
+    // every batch is a contiguous range of the instance buffer.
+    #[cfg(not(feature = "ecs"))]
+    fn batch_instances(&mut self, scene: &Scene) {
+        self.hierarchy = SceneHierarchy::new(&scene.entities);
+        let local = scene.entities.iter().map(|e| Transform::from(&e.transform)).collect::<Vec<_>>();
+        let world = self.hierarchy.world_transforms(&local);
+        let mut entities = Vec::with_capacity(scene.entities.len());
+        for (index, (entity, instance)) in scene.entities.iter().zip(world).enumerate() {
+            let mesh = self.scene_mesh(&entity.mesh);
+            let material = self.material(&entity.material);
+            let distance = self.camera.eye.distance(instance.translation);
+            entities.push((mesh, material, distance, index, instance));
+        }
+        entities.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.cmp(&b.1)).then(a.2.total_cmp(&b.2)));
+
+        self.batches.clear();
+        for (index, &(mesh, material, ..)) in entities.iter().enumerate() {
+            let index = index as u32;
+            match self.batches.last_mut() {
+                Some(batch) if batch.mesh == mesh && batch.material == material => batch.instances.end = index + 1,
+                _ => self.batches.push(SceneBatch {
+                    mesh,
+                    material,
+                    instances: index..index + 1,
+                }),
+            }
+        }
+
+        self.entity_instances = vec![0; entities.len()];
+        self.mirrors.clear();
+        self.glossy.clear();
+        for (instance, &(mesh, _, _, entity, _)) in entities.iter().enumerate() {
+            self.entity_instances[entity] = instance as u32;
+            if let Some(mirror) = scene.entities[entity].mirror {
+                self.mirrors.push(SceneMirror {
+                    instance: instance as u32,
+                    mesh,
+                    mirror,
+                });
+            }
+            if let Some(gloss) = scene.entities[entity].gloss {
+                self.glossy.push(SceneGlossy {
+                    instance: instance as u32,
+                    mesh,
+                    gloss,
+                });
+            }
+        }
+        self.instances = entities.into_iter().map(|(.., instance)| instance).collect();
+        self.instance_buffer = create_instance_buffer(&self.device, &self.instances);
+        self.moved_instances.clear();
+
+        self.animations = scene
+            .animations
+            .iter()
+            .map(|clip| {
+                let mut player = AnimationPlayer::new(std::rc::Rc::new(clip.clone()));
+                player.bind(&scene.entities);
+                player
+            })
+            .collect();
+        self.ik = IkRig::new(scene.ik.clone());
+        self.ik.bind(&scene.entities);
+        self.trails = (scene.entities.iter().enumerate())
+            .filter(|(_, entity)| entity.trail.is_some())
+            .map(|(index, _)| (index, trail::TrailPoints::new()))
+            .collect();
+    }
+
+    // Adds where every trail's entity is now to its points
+    #[cfg(not(feature = "ecs"))]
+    fn record_trails(&mut self, dt: f32) {
+        for (entity, points) in &mut self.trails {
+            let Some(trail) = self.scene.entities.get(*entity).and_then(|entity| entity.trail.as_ref()) else {
+                continue;
+            };
+            let world = self.instances[self.entity_instances[*entity] as usize];
+            points.record(trail, world.translation, dt);
+        }
+    }
+
+    // Poses the scene with every playing animation, then the IK chains, and
+    // rewrites the instance buffer. Bundles keep pointing at the same buffer,
+    // so they stay valid.
+    #[cfg(not(feature = "ecs"))]
+    fn animate(&mut self, dt: f32) {
+        if self.animations.is_empty() && self.ik.is_empty() {
+            return;
+        }
+        let mut local = self.scene.entities.iter().map(|e| Transform::from(&e.transform)).collect::<Vec<_>>();
+        for player in &mut self.animations {
+            player.update(dt);
+            player.apply(&mut local);
+        }
+        self.ik.apply(&self.hierarchy, &mut local);
+        self.pose(&local);
+    }
+
+    // Moves every instance to where its entity is with these local
+    // transforms, keeping the draws the same. Only the ones that moved get
+    // uploaded.
+    #[cfg(not(feature = "ecs"))]
+    fn pose(&mut self, local: &[Transform]) {
+        let before = self.moved_instances.len();
+        for (entity, world) in self.hierarchy.world_transforms(local).into_iter().enumerate() {
+            let instance = self.entity_instances[entity];
+            if self.instances[instance as usize] != world {
+                self.instances[instance as usize] = world;
+                self.moved_instances.push(instance);
+            }
+        }
+        if self.moved_instances.len() > before {
+            let bounds = self.instance_bounds();
+            self.scene_bvh.refit(&bounds);
+        }
+    }
+
+    // Uploads what pose() moved since the last frame, a run of neighbouring
+    // instances at a time
+    #[cfg(not(feature = "ecs"))]
+    fn upload_moved_instances(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let mut moved = std::mem::take(&mut self.moved_instances);
+        moved.sort_unstable();
+        moved.dedup();
+        for run in moved.chunk_by(|a, b| a + 1 == *b) {
+            let (first, last) = (run[0] as usize, run[run.len() - 1] as usize);
+            let raw = self.instances[first..=last].iter().map(InstanceRaw::from).collect::<Vec<_>>();
+            let offset = (first * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress;
+            self.uploader.write(&self.device, encoder, &self.instance_buffer, offset, &raw);
+        }
+        // Keeps its capacity for next frame
+        moved.clear();
+        self.moved_instances = moved;
+    }
+
+    // What lights the scene. With the ecs feature that's the world's Light
+    // components, as of the last extract.
+    fn lights(&self) -> &[SceneLight] {
+        #[cfg(feature = "ecs")]
+        return self.ecs.lights();
+        #[cfg(not(feature = "ecs"))]
+        &self.scene.lights
+    }
+
+    // What the scene is drawn at, see RenderSettings::pixel_art
+    fn scene_size(&self) -> (u32, u32) {
+        match &self.settings.pixel_art {
+            Some(pixel_art) => pixel_art.size(),
+            None => (self.config.width, self.config.height),
+        }
+    }
+
+    // Where a point in logical pixels is in the scene, in the scene's
+    // pixels. The same as physical pixels unless in pixel art mode.
+    fn scene_point(&self, point: glam::Vec2) -> glam::Vec2 {
+        let point = self.screen.to_physical(point);
+        match &self.settings.pixel_art {
+            Some(pixel_art) => pixel_art.to_picture((self.config.width, self.config.height), point),
+            None => point,
+        }
+    }
+
+    // Where the cursor is over the scene, None when it's outside the window
+    // or over pixel art mode's black bars
+    fn scene_cursor(&self) -> Option<glam::Vec2> {
+        let cursor = self.scene_point(self.cursor.position()?);
+        let (width, height) = self.scene_size();
+        let size = glam::Vec2::new(width as f32, height as f32);
+        (cursor.cmpge(glam::Vec2::ZERO).all() && cursor.cmplt(size).all()).then_some(cursor)
+    }
+
+    // Which of RenderSettings::viewports the cursor is over
+    fn hovered_viewport(&self) -> Option<usize> {
+        self.settings.viewport_at(self.scene_size(), self.scene_cursor()?)
+    }
+
+    // The camera the mouse works through and where it's drawn. That's the
+    // hovered viewport's when there are viewports, and none when the cursor
+    // is between them.
+    fn input_camera(&self) -> Option<(Camera, app::PassRegion)> {
+        if self.settings.viewports.is_empty() {
+            return Some((self.camera.clone(), self.settings.main_pass.region));
+        }
+        let viewport = &self.settings.viewports[self.hovered_viewport()?];
+        let region = viewport.region();
+        let aspect = region.aspect(self.scene_size());
+        Some((Camera::from_scene(&viewport.camera, aspect), region))
+    }
+
+    // From the camera through the cursor, None when the cursor is outside
+    // the window or the viewport being drawn into
+    fn cursor_ray(&self) -> Option<Ray> {
+        let cursor = self.scene_cursor()?;
+        let (camera, region) = self.input_camera()?;
+        camera.unproject(region.pixels(self.scene_size()), cursor)
+    }
+
+    // Every entity at least partly inside a rectangle on screen, corners in
+    // logical pixels, seen through the camera the mouse works through
+    #[cfg(not(feature = "ecs"))]
+    fn marquee_select(&self, min: glam::Vec2, max: glam::Vec2) -> Vec<usize> {
+        let Some((camera, region)) = self.input_camera() else {
+            return Vec::new();
+        };
+        let viewport = region.pixels(self.scene_size());
+        // Only the part of it over the viewport, the frustum would reach
+        // past the viewport's edges otherwise
+        let [x, y, width, height] = viewport.map(|v| v as f32);
+        let min = self.scene_point(min).max(glam::Vec2::new(x, y));
+        let max = self.scene_point(max).min(glam::Vec2::new(x + width, y + height));
+        if min.cmpge(max).any() {
+            return Vec::new();
+        }
+        let frustum = Frustum::from_screen_rect(camera.build_view_projection_matrix(), viewport, min, max);
+        let mut inside = vec![false; self.instances.len()];
+        self.scene_bvh.query_frustum(&frustum, |instance| inside[instance] = true);
+        (0..self.entity_instances.len()).filter(|&entity| inside[self.entity_instances[entity] as usize]).collect()
+    }
+
+    // Clicking picks an entity, shift dragging picks everything inside a
+    // rectangle, dragging a gizmo handle edits it and Z, X and C switch
+    // between moving, rotating and scaling. The number keys stay camera
+    // bookmarks.
+    #[cfg(not(feature = "ecs"))]
+    fn gizmo_input(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } if self.modifiers.shift() => {
+                let Some(cursor) = self.cursor.position() else {
+                    return false;
+                };
+                self.marquee.begin(cursor);
+                self.selected = None;
+                true
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Released,
+                button: MouseButton::Left,
+                ..
+            } if self.marquee.is_dragging() => {
+                if let Some((min, max)) = self.marquee.end() {
+                    let entities = self.marquee_select(min, max);
+                    self.marquee_selected = Some(MarqueeSelected { entities });
+                }
+                true
+            }
+            WindowEvent::CursorMoved { .. } if self.marquee.is_dragging() => {
+                if let Some(cursor) = self.cursor.position() {
+                    self.marquee.drag(cursor);
+                }
+                false
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } => {
+                let (Some(ray), Some((camera, _))) = (self.cursor_ray(), self.input_camera()) else {
+                    return false;
+                };
+                if let Some(entity) = self.selected {
+                    let world = self.instances[self.entity_instances[entity] as usize];
+                    if self.gizmo.begin_drag(&ray, &world, camera.eye) {
+                        return true;
+                    }
+                }
+                self.selected = self.scene_bvh.cast_ray(&ray, |_, distance| Some(distance)).and_then(|(instance, _)| {
+                    self.entity_instances.iter().position(|&i| i as usize == instance)
+                });
+                true
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Released,
+                button: MouseButton::Left,
+                ..
+            } if self.gizmo.is_dragging() => {
+                self.gizmo.end_drag();
+                true
+            }
+            WindowEvent::CursorMoved { .. } if self.gizmo.is_dragging() => {
+                let (Some(entity), Some(ray)) = (self.selected, self.cursor_ray()) else {
+                    return false;
+                };
+                if let Some(world) = self.gizmo.drag(&ray) {
+                    self.move_entity(entity, world);
+                }
+                false
+            }
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(key @ (VirtualKeyCode::Z | VirtualKeyCode::X | VirtualKeyCode::C)),
+                    ..
+                },
+                ..
+            } if self.selected.is_some() && !self.gizmo.is_dragging() => {
+                self.gizmo.mode = match key {
+                    VirtualKeyCode::Z => GizmoMode::Translate,
+                    VirtualKeyCode::X => GizmoMode::Rotate,
+                    _ => GizmoMode::Scale,
+                };
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Puts a scene entity at `world`, working out what its local transform
+    // has to be under its parent
+    #[cfg(not(feature = "ecs"))]
+    fn move_entity(&mut self, entity: usize, world: Transform) {
+        let local = match self.hierarchy.parent(entity) {
+            Some(parent) => self.instances[self.entity_instances[parent] as usize].inverse() * world,
+            None => world,
+        };
+        self.scene.entities[entity].transform = (&local).into();
+        self.transform_changed(entity);
+    }
+
+    // Poses the scene after `entity`'s local transform was changed and lets
+    // the app know
+    #[cfg(not(feature = "ecs"))]
+    fn transform_changed(&mut self, entity: usize) {
+        let local = self.scene.entities.iter().map(|e| Transform::from(&e.transform)).collect::<Vec<_>>();
+        self.pose(&local);
+        self.edits.push(TransformEdited {
+            entity,
+            transform: local[entity],
+        });
+    }
+
+    // Builds everything drawn from self.scene again, after entities were
+    // added or their materials changed. Entity indices stay the same.
+    fn rebuild_scene(&mut self) {
+        // A material can't change, tweaking one makes another, so the ones
+        // nothing uses any more go. The default stays, the cursor uses it.
+        let used = |material: &Material| self.scene.entities.iter().any(|e| e.material == material.source);
+        let keep = self.materials.iter().enumerate().map(|(i, m)| i == 0 || used(m)).collect::<Vec<_>>();
+        let mut keep = keep.into_iter();
+        self.materials.retain(|_| keep.next().unwrap());
+
+        #[cfg(feature = "ecs")]
+        self.ecs.spawn_scene(&self.scene);
+        #[cfg(not(feature = "ecs"))]
+        {
+            let scene = self.scene.clone();
+            self.batch_instances(&scene);
+            self.scene_bvh = Bvh::build(&self.instance_bounds());
+        }
+        self.static_geometry.invalidate();
+        self.build_static_draws();
+    }
+
+    // Shows what plugins did to the scene
+    fn apply_scene_changes(&mut self, changes: SceneChanges) {
+        #[cfg(feature = "ecs")]
+        self.ecs.set_lights(&self.scene.lights);
+        if changes.rebuilt {
+            self.rebuild_scene();
+        } else if changes.moved {
+            #[cfg(feature = "ecs")]
+            self.ecs.spawn_scene(&self.scene);
+            #[cfg(not(feature = "ecs"))]
+            {
+                let local = self.scene.entities.iter().map(|e| Transform::from(&e.transform)).collect::<Vec<_>>();
+                self.pose(&local);
+            }
+        }
+    }
+
+    // Runs whatever was typed into the console since last time
+    #[cfg(feature = "editor")]
+    fn run_console(&mut self) {
+        for line in self.console.take_submitted() {
+            self.run_command(&line);
+        }
+    }
+
+    // Runs a line typed into the console. The commands that need the
+    // renderer are here, the rest were registered with the console.
+    #[cfg(feature = "editor")]
+    fn run_command(&mut self, line: &str) {
+        tracing::info!(target: "console", "> {}", line);
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        let Some((&name, args)) = words.split_first() else {
+            return;
+        };
+        let result = match name {
+            "help" => Ok(self.console.help()),
+            "set" => self.set_command(args),
+            "spawn" => self.spawn_command(args),
+            "reload" => self.reload_command(args),
+            "settings" => self.settings_command(args),
+            "quality" => self.quality_command(args),
+            "probes" => self.probes_command(args),
+            "shake" => self.shake_command(args),
+            _ => match self.console.run(name, args) {
+                Some(result) => result,
+                None => Err(anyhow::anyhow!("No command called {}, try help", name)),
+            },
+        };
+        match result {
+            Ok(output) if !output.is_empty() => tracing::info!(target: "console", "{}", output),
+            Ok(_) => {}
+            Err(e) => tracing::warn!(target: "console", "{:#}", e),
+        }
+    }
+
+    #[cfg(feature = "editor")]
+    fn set_command(&mut self, args: &[&str]) -> anyhow::Result<String> {
+        let Some((&name, value)) = args.split_first() else {
+            anyhow::bail!("set takes a setting and a value");
+        };
+        match name {
+            "clear_color" => {
+                let rgb = value.iter().map(|v| v.parse::<f64>()).collect::<Result<Vec<_>, _>>()?;
+                let [r, g, b] = rgb[..] else {
+                    anyhow::bail!("clear_color takes red, green and blue from 0 to 1");
+                };
+                self.settings.main_pass.clear = ClearMode::Color(wgpu::Color { r, g, b, a: 1.0 });
+            }
+            "grid" => self.settings.grid = console::switch(value)?,
+            "dither" => self.settings.dither = console::switch(value)?,
+            "bloom" => self.settings.bloom = console::switch(value)?.then(BloomSettings::default),
+            "hover_highlight" => self.settings.hover_highlight = console::switch(value)?,
+            "debug_volumes" => self.settings.debug_volumes = console::switch(value)?,
+            "profiler" => self.settings.profiler = console::switch(value)?,
+            _ => anyhow::bail!("No setting called {}", name),
+        }
+        Ok(String::new())
+    }
+
+    // Adds an entity a few units in front of the camera
+    #[cfg(feature = "editor")]
+    fn spawn_command(&mut self, args: &[&str]) -> anyhow::Result<String> {
+        let (mesh, kind) = match args {
+            ["cube"] => (MeshRef::Cube, "cube"),
+            ["quad"] => (MeshRef::Quad, "quad"),
+            [path] if assets::is_model(path.as_ref()) => (MeshRef::Model(path.into()), "model"),
+            _ => anyhow::bail!("spawn takes cube, quad or an .obj, .gltf or .glb file"),
+        };
+        let name = (1..)
+            .map(|n| format!("{} {}", kind, n))
+            .find(|name| self.scene.entities.iter().all(|e| e.name != *name))
+            .unwrap();
+        let forward = (self.camera.target - self.camera.eye).normalize_or_zero();
+        let mut scene = self.scene.clone();
+        // Where the camera is now, not where the scene started it
+        scene.camera = self.camera.to_scene();
+        scene.entities.push(SceneEntity {
+            name: name.clone(),
+            parent: None,
+            transform: SceneTransform {
+                translation: (self.camera.eye + forward * 3.0).into(),
+                ..Default::default()
+            },
+            mesh,
+            material: MaterialRef::Default,
+            mirror: None,
+            gloss: None,
+            trail: None,
+        });
+        self.apply_scene(scene);
+        #[cfg(not(feature = "ecs"))]
+        {
+            self.selected = Some(self.scene.entities.len() - 1);
+        }
+        Ok(format!("Spawned {}", name))
+    }
+
+    #[cfg(feature = "editor")]
+    fn reload_command(&mut self, args: &[&str]) -> anyhow::Result<String> {
+        let ids = match args {
+            ["shaders"] => self.shaders.all().iter().map(|shader| shader.id()).collect::<Vec<_>>(),
+            ["assets"] => self.assets.list().iter().map(|asset| asset.id).collect(),
+            _ => anyhow::bail!("reload takes shaders or assets"),
+        };
+        for &id in &ids {
+            self.assets.reload(id);
+        }
+        Ok(format!("Reloading {} assets", ids.len()))
+    }
+
+    #[cfg(feature = "editor")]
+    fn settings_command(&mut self, args: &[&str]) -> anyhow::Result<String> {
+        match args {
+            ["save"] => {
+                GraphicsSettings::from_render_settings(&self.settings).save(SETTINGS_PATH)?;
+                Ok(format!("Saved graphics settings to {}", SETTINGS_PATH))
+            }
+            ["load"] => {
+                GraphicsSettings::load(SETTINGS_PATH)?.apply(&mut self.settings);
+                Ok(format!("Loaded graphics settings from {}", SETTINGS_PATH))
+            }
+            _ => anyhow::bail!("settings takes save or load"),
+        }
+    }
+
+    #[cfg(feature = "editor")]
+    fn quality_command(&mut self, args: &[&str]) -> anyhow::Result<String> {
+        let [name] = args else {
+            anyhow::bail!("quality takes low, medium, high or ultra");
+        };
+        let preset = name.parse::<settings::QualityPreset>()?;
+        preset.apply(&mut self.settings);
+        Ok(format!("Switched to {:?} quality", preset))
+    }
+
+    #[cfg(feature = "editor")]
+    fn probes_command(&mut self, args: &[&str]) -> anyhow::Result<String> {
+        let ["capture"] = args else {
+            anyhow::bail!("probes takes capture");
+        };
+        self.probes.capture();
+        Ok(format!("Capturing {} reflection probes", self.probes.len()))
+    }
+
+    #[cfg(feature = "editor")]
+    fn shake_command(&mut self, args: &[&str]) -> anyhow::Result<String> {
+        let [amount] = args else {
+            anyhow::bail!("shake takes an amount from 0 to 1");
+        };
+        let amount = amount.parse::<f32>()?;
+        self.settings.camera_shake.add_shake(amount);
+        Ok(format!("Camera trauma at {:.2}", self.settings.camera_shake.trauma()))
+    }
+
+    // World bounds of every instance, in the instance buffer's order
+    #[cfg(not(feature = "ecs"))]
+    fn instance_bounds(&self) -> Vec<Aabb> {
+        let mut bounds = vec![Aabb::EMPTY; self.instances.len()];
+        for batch in &self.batches {
+            let aabb = self.mesh_aabb(&self.scene_meshes[batch.mesh]);
+            for index in batch.instances.clone() {
+                bounds[index as usize] = aabb.transformed(self.instances[index as usize].matrix());
+            }
+        }
+        bounds
+    }
+
+    // Roughly how many pixels across `bounds` ends up on screen
+    fn screen_coverage(&self, bounds: Aabb) -> f32 {
+        let radius = bounds.half_extents().length();
+        let distance = (self.camera.eye.distance(bounds.center()) - radius).max(self.camera.znear);
+        let half_height = distance * (self.camera.fovy.to_radians() * 0.5).tan();
+        radius / half_height * self.scene_size().1 as f32
+    }
+
+    // Tells the texture streamer how big every visible instance is on screen,
+    // assuming its texture is stretched over it once
+    #[cfg(not(feature = "ecs"))]
+    fn request_texture_detail(&mut self) {
+        let frustum = Frustum::from_view_proj(self.camera.build_view_projection_matrix());
+        for batch in &self.batches {
+            let aabb = self.mesh_aabb(&self.scene_meshes[batch.mesh]);
+            let material = &self.materials[batch.material];
+            for index in batch.instances.clone() {
+                let bounds = aabb.transformed(self.instances[index as usize].matrix());
+                if frustum.intersects_aabb(&bounds) {
+                    let pixels = self.screen_coverage(bounds);
+                    for texture in material.textures() {
+                        self.assets.request_texture(texture, pixels);
+                    }
+                }
+            }
+        }
+    }
+
+    fn mesh_allocations(&self, mesh: &SceneMesh) -> Vec<MeshAllocation> {
+        let model = match (&mesh.source, mesh.model) {
+            (MeshRef::Quad, _) => return vec![self.quad_mesh.clone()],
+            (MeshRef::Cube, _) => self.assets.cube(),
+            (MeshRef::Model(_), Some(handle)) => self.assets.model(handle),
+            (MeshRef::Model(_), None) => return Vec::new(),
+        };
+        model.meshes.iter().map(|m| m.mesh.clone()).collect()
+    }
+
+    // In model space. Empty while the model is still loading.
+    fn mesh_aabb(&self, mesh: &SceneMesh) -> Aabb {
+        match (&mesh.source, mesh.model) {
+            (MeshRef::Quad, _) => Aabb::from_points(VERTICES.iter().map(|v| Vec3::from(v.position))),
+            (MeshRef::Cube, _) => self.assets.cube().aabb,
+            (MeshRef::Model(_), Some(handle)) => self.assets.model(handle).aabb,
+            (MeshRef::Model(_), None) => Aabb::EMPTY,
+        }
+    }
+
+    // Returns how many seconds passed since the last tick, which step()
+    // then moves everything on by
+    fn tick(&mut self) -> f32 {
+        let now = instant::Instant::now();
+        let dt = (now - self.last_update).as_secs_f32();
+        self.last_update = now;
+        // Paused by a plugin, like the editor's pause button
+        if self.paused {
+            0.0
+        } else {
+            dt
+        }
+    }
+
+    // Returns how many seconds passed since the last update, for bench.rs
+    #[cfg(not(target_arch = "wasm32"))]
+    fn update(&mut self) -> f32 {
+        let dt = self.tick();
+        self.step(dt);
+        dt
+    }
+
+    // What plugins get to see and change, see plugin.rs
+    fn plugin_context(&mut self, dt: f32) -> PluginContext<'_> {
+        // The world's lights are the ones that count, apply_scene_changes()
+        // puts back whatever plugins changed
+        #[cfg(feature = "ecs")]
+        {
+            self.scene.lights = self.ecs.lights().to_vec();
+        }
+        #[cfg(not(feature = "ecs"))]
+        let selection = Some((&mut self.selected, &mut self.gizmo.mode));
+        #[cfg(feature = "ecs")]
+        let selection = None;
+        PluginContext {
+            device: &self.device,
+            queue: &self.queue,
+            settings: &mut self.settings,
+            assets: &mut self.assets,
+            scene: &mut self.scene,
+            scene_changes: SceneChanges::default(),
+            selection,
+            screen: self.screen,
+            clipboard: &mut self.clipboard,
+            #[cfg(feature = "editor")]
+            console: &mut self.console,
+            paused: &mut self.paused,
+            camera: self.camera.to_scene(),
+            frame: *self.frame.uniform(),
+            dt,
+        }
+    }
+
+    // Moves everything on by `dt` seconds, usually what tick() gave back.
+    // Stepping by 0 keeps animations and the
+    // frame time where they are, for rendering the same image every time.
+    fn step(&mut self, dt: f32) {
+        let pool_generation = self.mesh_pool.generation();
+        let mut meshes_changed = false;
+        self.assets.set_texture_budget(self.settings.texture_budget);
+        self.assets.set_max_anisotropy(self.settings.max_anisotropy);
+        for id in self.assets.update(&self.device, &self.queue, &mut self.mesh_pool) {
+            self.sprites.texture_changed(id, &mut self.bind_group_cache);
+            self.particle_renderer.texture_changed(id, &mut self.bind_group_cache);
+            self.trail_renderer.texture_changed(id, &mut self.bind_group_cache);
+            for material in self.materials.iter_mut().filter(|m| m.textures().any(|t| t.id() == id)) {
+                // The bind group and the bundle recorded with it still point
+                // at the placeholder
+                material.id = self.bind_group_cache.recreated(material.id);
+                self.static_geometry.invalidate();
+            }
+            if self.scene_meshes.iter().any(|m| m.model.map(|h| h.id()) == Some(id)) {
+                meshes_changed = true;
+            }
+            self.shader_changed(id);
+        }
+        self.particles.update(dt, &mut self.assets);
+        // Loading models can grow the mesh pool, which recreates its buffers
+        if self.mesh_pool.generation() != pool_generation {
+            self.static_geometry.invalidate();
+        }
+        if meshes_changed {
+            self.build_static_draws();
+            // Loaded models have different bounds than the placeholder
+            #[cfg(not(feature = "ecs"))]
+            {
+                let bounds = self.instance_bounds();
+                self.scene_bvh.refit(&bounds);
+            }
+        }
+
+        self.tweens.update(dt);
+        self.frame.update(dt, self.scene_size(), self.scene_cursor());
+        #[cfg(not(feature = "ecs"))]
+        self.animate(dt);
+        #[cfg(not(feature = "ecs"))]
+        self.record_trails(dt);
+        #[cfg(feature = "ecs")]
+        self.ecs.record_trails(dt);
+
+        // Gameplay code moves the camera by changing the world's Camera
+        #[cfg(feature = "ecs")]
+        if let Some(camera) = self.ecs.moved_camera() {
+            self.camera = Camera::from_scene(&camera, self.camera.aspect);
+            self.camera_tween = None;
+        }
+        let gesture = self.touches.take_gesture();
+        if self.settings.viewports.is_empty() {
+            self.camera_controller.update_camera(&mut self.camera);
+            if !gesture.is_none() {
+                self.camera_controller.apply_gesture(&gesture, &mut self.camera);
+            }
+        } else if let Some(index) = self.hovered_viewport() {
+            // Split up, the controller moves whichever camera the cursor is
+            // over. Only the direction matters here, the aspect is left alone.
+            let viewport = &mut self.settings.viewports[index];
+            let mut camera = Camera::from_scene(&viewport.camera, 1.0);
+            self.camera_controller.update_camera(&mut camera);
+            if !gesture.is_none() {
+                self.camera_controller.apply_gesture(&gesture, &mut camera);
+            }
+            viewport.camera = camera.to_scene();
+        }
+        if let Some((eye, target, end)) = &self.camera_tween {
+            match (self.tweens.value(*eye), self.tweens.value(*target)) {
+                (Some(eye), Some(target)) => {
+                    self.camera.eye = eye;
+                    self.camera.target = target;
+                }
+                _ => {
+                    self.camera = Camera::from_scene(end, self.camera.aspect);
+                    self.camera_tween = None;
+                }
+            }
+        }
+        // After the controller and tweens, so the shake goes on top of them
+        // instead of being steered away
+        self.settings.camera_shake.update(dt);
+        self.camera.shake = self.settings.camera_shake.offset();
+        // Only the main pass's viewport shows the camera
+        self.camera.aspect = self.settings.main_pass.region.aspect(self.scene_size());
+        self.camera.draw_distance = self.settings.draw_distance.unwrap_or(f32::INFINITY);
+        #[cfg(feature = "ecs")]
+        self.ecs.set_camera(self.camera.to_scene());
+        // Uploaded through the staging belt at the start of render()
+        self.camera_uniform.update_view_proj(&self.camera);
+        #[cfg(not(feature = "ecs"))]
+        self.request_texture_detail();
+    }
+
+    // Swaps in pipelines built from the edited shader. If the new source
+    // doesn't compile we keep drawing with the old pipeline.
+    fn shader_changed(&mut self, id: AssetId) {
+        let Shaders {
+            mesh,
+            sprite,
+            lines,
+            grid,
+            particles,
+            trails,
+            sky,
+            mirror,
+            probe,
+            parallax,
+            emissive,
+            toon,
+            lightmap,
+            stereo,
+            highlight,
+            bloom,
+            exposure,
+            fog,
+            outline,
+            pixel_art,
+            crt,
+            ..
+        } = self.shaders;
+        let Some(shader) = self.shaders.all().into_iter().find(|shader| shader.id() == id) else {
+            return;
+        };
+        let Some(source) = self.shaders.source(&self.assets, shader) else {
+            return;
+        };
+        let source = source.as_str();
+
+        if shader == mesh {
+            if let Some(pipeline) = shaders::try_create(&self.device, || {
+                create_render_pipeline(
+                    &self.device,
+                    &self.render_pipeline_layout,
+                    self.hdr_format,
+                    source,
+                    &self.mesh_pool.stream_layouts(),
+                )
+            }) {
+                self.render_pipeline = pipeline;
+                self.static_geometry.invalidate();
+            }
+        } else if shader == sprite {
+            self.sprites.reload_shader(&self.device, source);
+        } else if shader == lines {
+            self.lines.reload_shader(&self.device, source);
+        } else if shader == grid {
+            self.grid.reload_shader(&self.device, source);
+        } else if shader == particles {
+            self.particle_renderer.reload_shader(&self.device, source);
+        } else if shader == trails {
+            self.trail_renderer.reload_shader(&self.device, source);
+        } else if shader == sky {
+            self.sky.reload_shader(&self.device, source);
+        } else if shader == mirror {
+            self.mirror_passes.reload_shader(&self.device, source);
+        } else if shader == probe {
+            self.probes.reload_shader(&self.device, source);
+        } else if shader == parallax {
+            self.parallax.reload_shader(&self.device, source, &self.mesh_pool.stream_layouts());
+            self.static_geometry.invalidate();
+        } else if shader == emissive {
+            self.emissive.reload_shader(&self.device, source, &self.mesh_pool.stream_layouts());
+            self.static_geometry.invalidate();
+        } else if shader == toon {
+            self.toon.reload_shader(&self.device, source, &self.mesh_pool.stream_layouts());
+            self.static_geometry.invalidate();
+        } else if shader == lightmap {
+            self.lightmap.reload_shader(&self.device, source, &self.mesh_pool.stream_layouts());
+            self.static_geometry.invalidate();
+        } else if shader == highlight {
+            self.highlight.reload_shader(&self.device, source, &self.mesh_pool.stream_layouts());
+        } else if shader == stereo {
+            if let Some(stereo) = &mut self.stereo {
+                stereo.reload_shader(&self.device, source, &self.mesh_pool.stream_layouts());
+            }
+        } else if shader == bloom {
+            self.bloom.reload_shader(&self.device, source);
+        } else if shader == exposure {
+            if let Some(eye_adaptation) = &mut self.eye_adaptation {
+                eye_adaptation.reload_shader(&self.device, source);
+            }
+        } else if shader == outline {
+            self.outline.reload_shader(&self.device, source);
+        } else if shader == pixel_art {
+            self.pixel_art.reload_shader(&self.device, source);
+        } else if shader == crt {
+            self.crt.reload_shader(&self.device, source);
+        } else if shader == fog {
+            if let Some(volumetric_fog) = &mut self.fog {
+                volumetric_fog.reload_shader(&self.device, source);
+            }
+        } else {
+            self.tonemap.reload_shader(&self.device, source);
+        }
+    }
+
+    // For when the shader constants or the mesh pool's streams changed
+    fn rebuild_pipelines(&mut self) {
+        for shader in self.shaders.all() {
+            self.shader_changed(shader.id());
+        }
+    }
+
+    // One draw per instance and mesh, sorted front to back from where the
+    // camera was when the scene got applied. Instances that end up next to
+    // each other get merged back into one draw.
+    fn build_static_draws(&mut self) {
+        let mut static_draws = DrawList::new();
+        for batch in &self.batches {
+            let meshes = self.mesh_allocations(&self.scene_meshes[batch.mesh]);
+            for index in batch.instances.clone() {
+                let position = self.instances[index as usize].translation;
+                let depth = self.camera.eye.distance(position);
+                for mesh in &meshes {
+                    for pipeline in self.materials[batch.material].pipelines() {
+                        static_draws.push(DrawCommand {
+                            pipeline,
+                            material: batch.material as u32,
+                            // Every mesh lives in the shared mesh pool
+                            // buffers, one set per index format
+                            mesh: mesh.buffers_index(),
+                            depth,
+                            indices: mesh.indices(),
+                            base_vertex: mesh.base_vertex(),
+                            instances: index..index + 1,
+                        });
+                    }
+                }
+            }
+        }
+        static_draws.sort_and_batch();
+        self.static_geometry.update(&static_draws);
+    }
+
+    // Pulls every MeshRenderer out of the world and turns it into draws.
+    // Like apply_scene() the instances get sorted by mesh, material and
+    // distance first, so sort_and_batch can merge them back into few draws.
+    #[cfg(feature = "ecs")]
+    fn extract_draws(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let extracted = self.ecs.extract().to_vec();
+
+        let mut objects = Vec::with_capacity(extracted.len());
+        for object in &extracted {
+            let mesh = self.scene_mesh(&object.mesh);
+            let material = self.material(&object.material);
+            let instance = object.transform;
+            let depth = self.camera.eye.distance(instance.translation);
+            objects.push((mesh, material, depth, instance, object.mirror, object.gloss));
+        }
+        objects.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.cmp(&b.1)).then(a.2.total_cmp(&b.2)));
+
+        self.mirrors.clear();
+        self.glossy.clear();
+        for (index, (mesh, material, depth, _, mirror, gloss)) in objects.iter().enumerate() {
+            let index = index as u32;
+            if let Some(mirror) = *mirror {
+                self.mirrors.push(SceneMirror {
+                    instance: index,
+                    mesh: *mesh,
+                    mirror,
+                });
+            }
+            if let Some(gloss) = *gloss {
+                self.glossy.push(SceneGlossy {
+                    instance: index,
+                    mesh: *mesh,
+                    gloss,
+                });
+            }
+            for allocation in self.mesh_allocations(&self.scene_meshes[*mesh]) {
+                for pipeline in self.materials[*material].pipelines() {
+                    self.draw_list.push(DrawCommand {
+                        pipeline,
+                        material: *material as u32,
+                        mesh: allocation.buffers_index(),
+                        depth: *depth,
+                        indices: allocation.indices(),
+                        base_vertex: allocation.base_vertex(),
+                        instances: index..index + 1,
+                    });
+                }
+            }
+        }
+
+        // Built from scratch since the objects come out in a different order
+        // every frame, which refitting can't keep up with
+        let bounds = objects
+            .iter()
+            .map(|(mesh, _, _, instance, ..)| self.mesh_aabb(&self.scene_meshes[*mesh]).transformed(instance.matrix()))
+            .collect::<Vec<_>>();
+        self.scene_bvh = Bvh::build(&bounds);
+
+        let frustum = Frustum::from_view_proj(self.camera.build_view_projection_matrix());
+        for ((_, material, ..), bounds) in objects.iter().zip(&bounds) {
+            if frustum.intersects_aabb(bounds) {
+                let pixels = self.screen_coverage(*bounds);
+                for texture in self.materials[*material].textures() {
+                    self.assets.request_texture(texture, pixels);
+                }
+            }
+        }
+
+        self.instances = objects.into_iter().map(|(_, _, _, instance, ..)| instance).collect();
+        if self.instances.len() > self.instance_capacity {
+            self.instance_buffer = create_instance_buffer(&self.device, &self.instances);
+            self.instance_capacity = self.instances.len();
+        } else if !self.instances.is_empty() {
+            let raw = self.instances.iter().map(InstanceRaw::from).collect::<Vec<_>>();
+            self.uploader.write(&self.device, encoder, &self.instance_buffer, 0, &raw);
+        }
+    }
+
+    // Picks the closest mirrors in view and works out their cameras, and
+    // returns the draws of their surfaces with the mirror's slot as the
+    // material
+    fn prepare_mirrors(&mut self, encoder: &mut wgpu::CommandEncoder) -> Vec<DrawCommand> {
+        let view_proj = self.camera.build_view_projection_matrix();
+        let frustum = Frustum::from_view_proj(view_proj);
+        let mut visible = self
+            .mirrors
+            .iter()
+            .filter_map(|mirror| {
+                let world = self.instances[mirror.instance as usize];
+                let bounds = self.mesh_aabb(&self.scene_meshes[mirror.mesh]).transformed(world.matrix());
+                frustum.intersects_aabb(&bounds).then(|| (bounds.center().distance(self.camera.eye), mirror))
+            })
+            .collect::<Vec<_>>();
+        visible.sort_by(|a, b| a.0.total_cmp(&b.0));
+        visible.truncate(MAX_MIRRORS);
+
+        let placed = visible
+            .iter()
+            .map(|(_, mirror)| (self.instances[mirror.instance as usize], mirror.mirror))
+            .collect::<Vec<_>>();
+        let eye = self.camera.eye;
+        self.mirror_passes.prepare(&self.device, encoder, &mut self.uploader, eye, view_proj, &placed);
+
+        let mut surfaces = Vec::new();
+        for (slot, (depth, mirror)) in visible.iter().enumerate() {
+            for mesh in self.mesh_allocations(&self.scene_meshes[mirror.mesh]) {
+                surfaces.push(DrawCommand {
+                    pipeline: 0,
+                    material: slot as u32,
+                    mesh: mesh.buffers_index(),
+                    depth: *depth,
+                    indices: mesh.indices(),
+                    base_vertex: mesh.base_vertex(),
+                    instances: mirror.instance..mirror.instance + 1,
+                });
+            }
+        }
+        surfaces
+    }
+
+    // Gives every glossy instance in view the probe it reflects, and returns
+    // the draws of their surfaces with their slot as the material
+    fn prepare_glossy(&mut self, encoder: &mut wgpu::CommandEncoder) -> Vec<DrawCommand> {
+        let frustum = Frustum::from_view_proj(self.camera.build_view_projection_matrix());
+        let mut visible = Vec::new();
+        for glossy in &self.glossy {
+            let world = self.instances[glossy.instance as usize];
+            let bounds = self.mesh_aabb(&self.scene_meshes[glossy.mesh]).transformed(world.matrix());
+            if !frustum.intersects_aabb(&bounds) {
+                continue;
+            }
+            if let Some(probe) = self.probes.nearest(bounds.center()) {
+                visible.push((bounds.center().distance(self.camera.eye), probe, glossy));
+            }
+        }
+        let slots = visible.iter().map(|&(_, probe, glossy)| (probe, glossy.gloss)).collect::<Vec<_>>();
+        self.probes.prepare(&self.device, encoder, &mut self.uploader, &slots);
+
+        let mut surfaces = Vec::new();
+        for (slot, (depth, _, glossy)) in visible.iter().enumerate() {
+            for mesh in self.mesh_allocations(&self.scene_meshes[glossy.mesh]) {
+                surfaces.push(DrawCommand {
+                    pipeline: 0,
+                    material: slot as u32,
+                    mesh: mesh.buffers_index(),
+                    depth: *depth,
+                    indices: mesh.indices(),
+                    base_vertex: mesh.base_vertex(),
+                    instances: glossy.instance..glossy.instance + 1,
+                });
+            }
+        }
+        surfaces
+    }
+
+    // Graphs of where GPU memory goes, next to the frames in Tracy
+    #[cfg(feature = "tracy")]
+    fn plot_memory(&self) {
+        let Some(client) = tracy_client::Client::running() else {
+            return;
+        };
+        let megabytes = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+        let (used, capacity) = self.mesh_pool.memory();
+        client.plot(tracy_client::plot_name!("mesh pool used (MB)"), megabytes(used));
+        client.plot(tracy_client::plot_name!("mesh pool size (MB)"), megabytes(capacity));
+        let instance_bytes = (self.instances.len() * std::mem::size_of::<InstanceRaw>()) as u64;
+        client.plot(tracy_client::plot_name!("instances (MB)"), megabytes(instance_bytes));
+        client.plot(tracy_client::plot_name!("instances"), self.instances.len() as f64);
+        let (textures, _) = self.assets.texture_memory();
+        client.plot(tracy_client::plot_name!("streamed textures (MB)"), megabytes(textures));
+    }
+
+    fn render(&mut self, app: &mut dyn App, plugins: &mut [Box<dyn Plugin>]) -> Result<(), wgpu::SurfaceError> {
+        let _scope = profiler::scope("render");
+        let (output, view) = match &self.target {
+            Target::Surface(surface) => {
+                let output = surface.get_current_texture()?;
+                let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+                (Some(output), view)
+            }
+            Target::Offscreen(texture) => (None, texture.texture.create_view(&wgpu::TextureViewDescriptor::default())),
+        };
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Render Encoder"),
+            });
+
+        self.uploader.write(&self.device, &mut encoder, &self.camera_buffer, 0, &[self.camera_uniform]);
+        self.frame.upload(&self.device, &mut encoder, &mut self.uploader);
+        #[cfg(not(feature = "ecs"))]
+        self.upload_moved_instances(&mut encoder);
+
+        // Anything that moves gets pushed here each frame
+        self.draw_list.clear();
+        #[cfg(feature = "ecs")]
+        self.extract_draws(&mut encoder);
+        self.draw_list.sort_and_batch();
+
+        let material_bind_groups = self
+            .materials
+            .iter()
+            .map(|material| {
+                let texture = self.assets.texture(material.texture);
+                match &material.maps {
+                    MaterialMaps::Plain => {}
+                    MaterialMaps::Parallax(height, buffer) => {
+                        return self.parallax.material_bind_group(
+                            &self.device,
+                            &mut self.bind_group_cache,
+                            material.id,
+                            texture,
+                            self.assets.texture(*height),
+                            buffer,
+                        );
+                    }
+                    MaterialMaps::Emissive(emissive, buffer) => {
+                        return self.emissive.material_bind_group(
+                            &self.device,
+                            &mut self.bind_group_cache,
+                            material.id,
+                            texture,
+                            self.assets.texture(*emissive),
+                            buffer,
+                        );
+                    }
+                    MaterialMaps::Toon(buffer, _) => {
+                        return self.toon.material_bind_group(
+                            &self.device,
+                            &mut self.bind_group_cache,
+                            material.id,
+                            texture,
+                            buffer,
+                        );
+                    }
+                    MaterialMaps::Lightmapped(lightmap, buffer) => {
+                        return self.lightmap.material_bind_group(
+                            &self.device,
+                            &mut self.bind_group_cache,
+                            material.id,
+                            texture,
+                            self.assets.texture(*lightmap),
+                            buffer,
+                        );
+                    }
+                }
+                self.bind_group_cache.get_or_create(
+                    &self.device,
+                    Some("diffuse_bind_group"),
+                    self.texture_bind_group_layout_id,
+                    &self.texture_bind_group_layout,
+                    &[
+                        CachedBinding {
+                            binding: 0,
+                            id: material.id,
+                            resource: wgpu::BindingResource::TextureView(&texture.view),
+                        },
+                        CachedBinding {
+                            binding: 1,
+                            id: material.id,
+                            resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                        },
+                    ],
+                )
+            })
+            .collect::<Vec<_>>();
+        let camera_bind_group = self.bind_group_cache.get_or_create(
+            &self.device,
+            Some("camera_bind_group"),
+            self.camera_bind_group_layout_id,
+            &self.camera_bind_group_layout,
+            &[CachedBinding {
+                binding: 0,
+                id: self.camera_buffer_id,
+                resource: self.camera_buffer.as_entire_binding(),
+            }],
+        );
+
+        self.glyph_atlas.begin_frame();
+        self.pointer.captured = false;
+        app.overlay(&mut app::Overlay {
+            sprites: &mut self.sprites,
+            fonts: &self.fonts,
+            atlas: &mut self.glyph_atlas,
+            screen: self.screen,
+            white_texture: self.white_texture,
+            pointer: &mut self.pointer,
+        });
+        self.pointer.end_frame();
+        self.glyph_atlas.upload(&self.queue, &self.assets);
+        if self.settings.profiler {
+            let width = (self.screen.logical_size().x - 16.0).clamp(0.0, 480.0);
+            for sprite in self.profiler.overlay(self.white_texture, glam::Vec2::splat(8.0), width) {
+                self.sprites.push(sprite);
+            }
+        }
+        #[cfg(not(feature = "ecs"))]
+        for sprite in self.marquee.sprites(self.white_texture) {
+            self.sprites.push(sprite);
+        }
+        // The custom cursor goes on top of every other sprite
+        if let Some(sprite) = self.cursor.sprite() {
+            self.sprites.push(sprite);
+        }
+        self.sprites.set_nearest_by_default(self.settings.pixel_art.is_some());
+        self.sprites.prepare(&self.device, &mut encoder, &mut self.uploader, &self.screen);
+        #[cfg(not(feature = "ecs"))]
+        if let Some(entity) = self.selected {
+            let world = self.instances[self.entity_instances[entity] as usize];
+            // Sized for the camera it gets dragged through
+            let eye = self.input_camera().map_or(self.camera.eye, |(camera, _)| camera.eye);
+            self.gizmo.draw(&mut self.lines, &world, eye);
+        }
+        // Stereo takes over from the main camera and the viewports, when
+        // the GPU can do it
+        let scene_size = self.scene_size();
+        let cursor_ray = self.cursor_ray();
+        let stereo = match (self.settings.stereo, &mut self.stereo) {
+            (Some(settings), Some(stereo)) if self.settings.draw_scene => {
+                let camera = self.camera.to_scene();
+                stereo.prepare(&self.device, &mut encoder, &mut self.uploader, &camera, &settings, scene_size);
+                true
+            }
+            _ => false,
+        };
+        let split = !self.settings.viewports.is_empty() && !stereo;
+        // Mirrors, fog, outlines and the grid only work from the one main
+        // camera
+        let single_camera = !split && !stereo;
+        if self.settings.grid && single_camera {
+            self.grid.upload(
+                &self.device,
+                &mut encoder,
+                &mut self.uploader,
+                self.camera.build_view_projection_matrix(),
+                self.camera.eye,
+                self.camera.far(),
+            );
+            // The grid lies flat, so the Y axis is a line
+            let top = Vec3::Y * self.camera.far();
+            self.lines.line(-top, top, [0.3, 0.85, 0.3, 1.0], false);
+        }
+        if self.settings.debug_volumes {
+            for light in self.lights() {
+                debug::light(light);
+            }
+            if let Some(view_proj) = self.frozen_frustum {
+                debug::frustum(view_proj, [1.0, 0.85, 0.1, 1.0]);
+            }
+        }
+        debug::flush(&mut self.lines);
+        self.lines.prepare(&self.device, &mut encoder, &mut self.uploader);
+        // Sorted for the main camera, split viewports make do with its order
+        self.particle_renderer.prepare(
+            &self.device,
+            &mut encoder,
+            &mut self.uploader,
+            &mut self.bind_group_cache,
+            &self.particles,
+            &self.assets,
+            self.camera.eye,
+        );
+        #[cfg(not(feature = "ecs"))]
+        {
+            let entities = &self.scene.entities;
+            let trails = (self.trails.iter())
+                .filter_map(|(entity, points)| Some((entities.get(*entity)?.trail.as_ref()?, points)));
+            let (uploader, cache) = (&mut self.uploader, &mut self.bind_group_cache);
+            self.trail_renderer.prepare(&self.device, &mut encoder, uploader, cache, &mut self.assets, trails);
+        }
+        #[cfg(feature = "ecs")]
+        {
+            let mut query = self.ecs.world.query::<&ecs::TrailEmitter>();
+            let trails = query.iter(&self.ecs.world).map(|emitter| (&emitter.trail, &emitter.points));
+            let (uploader, cache) = (&mut self.uploader, &mut self.bind_group_cache);
+            self.trail_renderer.prepare(&self.device, &mut encoder, uploader, cache, &mut self.assets, trails);
+        }
+        let sprite_bind_groups = self.sprites.bind_groups(
+            &self.device,
+            &self.assets,
+            &mut self.bind_group_cache,
+            self.texture_bind_group_layout_id,
+            &self.texture_bind_group_layout,
+        );
+
+        // The scene is drawn in HDR and tonemapped onto the surface at the
+        // end. Preserving the last frame needs that texture to outlive the
+        // frame, otherwise the render graph hands out a fresh one.
+        let main_ops = self.settings.main_pass;
+        let size = scene_size;
+        let surface_size = (self.config.width, self.config.height);
+        // Where pixel art mode's picture goes on the surface
+        let pixel_art_rect = self.settings.pixel_art.map(|settings| settings.rect(surface_size));
+        if main_ops.clear == ClearMode::PreserveLastFrame {
+            if self.accumulation.as_ref().is_none_or(|(_, current)| *current != size) {
+                let target = texture::Texture::create_render_target(&self.device, size, self.hdr_format, "Accumulation Texture");
+                self.accumulation = Some((target, size));
+            }
+        } else {
+            self.accumulation = None;
+        }
+
+        self.viewport_cameras.prepare(
+            &self.device,
+            &mut encoder,
+            &mut self.uploader,
+            &self.camera_bind_group_layout,
+            &self.settings.viewports,
+            size,
+        );
+        // Each viewport only draws what its camera can see
+        let viewport_draws = match split && self.settings.draw_scene {
+            true => {
+                let commands = [self.static_geometry.commands(), self.draw_list.commands()];
+                self.viewport_cameras.cull(&self.scene_bvh, &commands)
+            }
+            false => Vec::new(),
+        };
+
+        // Mirrors see the scene, which isn't there when the app draws
+        // everything itself
+        let mirror_surfaces = match self.settings.draw_scene && single_camera {
+            true => self.prepare_mirrors(&mut encoder),
+            false => Vec::new(),
+        };
+        // Reflection probes get captured once everything they'd see has
+        // loaded, and glossy surfaces show them after that
+        let capture_probes = self.settings.draw_scene && self.assets.pending() == 0 && self.probes.take_capture();
+        let glossy_surfaces = match self.settings.draw_scene && single_camera && self.probes.is_captured() {
+            true => self.prepare_glossy(&mut encoder),
+            false => Vec::new(),
+        };
+        // The scene's draws, seen again from every mirror's camera and every
+        // probe's faces. The main pass holds on to the draw list and static
+        // bundle, so they're copied. Reflections and cubemap faces turn
+        // triangles around, which would show toon outlines' front faces over
+        // their objects, so those are left out.
+        let reflected = if mirror_surfaces.is_empty() && !capture_probes {
+            Vec::new()
+        } else {
+            let commands = self.static_geometry.commands().iter().chain(self.draw_list.commands());
+            commands.filter(|command| command.pipeline != toon::OUTLINE_PIPELINE).cloned().collect()
+        };
+
+        // Copied for the same reason
+        let stereo_draws = match stereo {
+            true => self.static_geometry.commands().iter().chain(self.draw_list.commands()).cloned().collect(),
+            false => Vec::new(),
+        };
+
+        // The draws of whatever the cursor is over, by the bounds it has in
+        // the scene's BVH, and the viewport it's seen through
+        let hovered = match self.settings.hover_highlight && self.settings.draw_scene && !stereo {
+            true => self.cursor_ray().and_then(|ray| self.scene_bvh.cast_ray(&ray, |_, distance| Some(distance))),
+            false => None,
+        };
+        let highlighted = match hovered {
+            Some((instance, _)) => [self.static_geometry.commands(), self.draw_list.commands()]
+                .iter()
+                .flat_map(|commands| Highlight::commands(commands, instance as u32))
+                .collect(),
+            None => Vec::new(),
+        };
+        let hovered_viewport = self.hovered_viewport().filter(|_| split);
+
+        // With automatic exposure the exposure written here is a placeholder,
+        // the exposure pass copies the real one over it on the GPU
+        let auto_exposure = match self.settings.exposure {
+            Exposure::Auto(settings) => self.eye_adaptation.as_ref().map(|eye_adaptation| (eye_adaptation, settings)),
+            Exposure::Fixed(_) | Exposure::Manual(_) => None,
+        };
+        let exposure = match self.settings.exposure {
+            Exposure::Fixed(exposure) => exposure,
+            Exposure::Manual(camera) => camera.exposure(),
+            Exposure::Auto(_) => 1.0,
+        };
+        let (tonemapping, dither) = (self.settings.tonemapping, self.settings.dither);
+        self.tonemap.upload(&self.device, &mut encoder, &mut self.uploader, exposure, tonemapping, dither);
+        if let Some((eye_adaptation, settings)) = auto_exposure {
+            let delta_time = self.frame.uniform().delta_time;
+            eye_adaptation.upload(&self.device, &mut encoder, &mut self.uploader, &settings, delta_time, !self.adapting);
+        }
+        self.adapting = auto_exposure.is_some();
+        if let Some(settings) = &self.settings.sky {
+            self.sky.upload(&self.device, &mut encoder, &mut self.uploader, settings);
+        }
+        let lighting = match self.settings.sky {
+            Some(_) => self.sky.lighting(),
+            None => Lighting::default(),
+        };
+        // The scene's sun, when it has one, as the direction towards it and
+        // its colour
+        let scene_sun = self.lights().iter().find_map(|light| match *light {
+            SceneLight::Directional { direction, .. } => {
+                Some((-Vec3::from(direction), light.radiance(self.scene.light_units)))
+            }
+            SceneLight::Point { .. } => None,
+        });
+        // Parallax materials' self-shadows fall away from it too, and toon
+        // materials are lit by it, otherwise by the sun the sky (or the
+        // default lighting) has
+        let scene_lighting = match scene_sun {
+            Some((sun_direction, sun_color)) => Lighting {
+                sun_direction,
+                sun_color,
+                ..lighting
+            },
+            None => lighting,
+        };
+        self.parallax.upload(&self.device, &mut encoder, &mut self.uploader, scene_lighting);
+        let fog = self.settings.fog.filter(|_| single_camera);
+        let fog = fog.and_then(|settings| self.fog.as_ref().map(|fog| (fog, settings)));
+        if let Some((volumetric_fog, mut settings)) = fog {
+            // The scene's sun lights the fog when it has one
+            if let Some((direction, color)) = scene_sun {
+                settings.sun_direction = direction;
+                settings.sun_color = color;
+            } else if self.settings.sky.is_some() {
+                // Otherwise the sky's, so the shafts line up with its sun
+                settings.sun_direction = lighting.sun_direction;
+                settings.sun_color = lighting.sun_color;
+                settings.ambient = lighting.ambient;
+            }
+            let (eye, view_proj) = (self.camera.eye, self.camera.build_view_projection_matrix());
+            volumetric_fog.upload(&self.device, &mut encoder, &mut self.uploader, &settings, eye, view_proj);
+        }
+        let outline = self.settings.outline.filter(|_| single_camera);
+        if let Some(settings) = &outline {
+            let (eye, view_proj) = (self.camera.eye, self.camera.build_view_projection_matrix());
+            self.outline.upload(&self.device, &mut encoder, &mut self.uploader, settings, eye, view_proj);
+        }
+        if let Some(settings) = &self.settings.bloom {
+            self.bloom.upload(&self.device, &mut encoder, &mut self.uploader, settings);
+        }
+        match &self.settings.crt {
+            Some(settings) => {
+                let rect = pixel_art_rect.unwrap_or([0, 0, surface_size.0, surface_size.1]);
+                let rows = self.settings.pixel_art.map(|pixel_art| pixel_art.size().1);
+                self.crt.upload(&self.device, &mut encoder, &mut self.uploader, settings, rect, surface_size, rows);
+            }
+            None => self.crt.release(),
+        }
+
+        let viewport_cameras = self.viewport_cameras.cameras();
+        // Made before the graph, the app's passes can hold on to it
+        let app_uploader = AppUploader::new(&self.device, std::mem::take(&mut self.uploader));
+        let mut graph = RenderGraph::new();
+        graph.import("surface", &view);
+        let scene_target = match &self.accumulation {
+            Some((target, _)) => {
+                graph.import("accumulation", &target.view);
+                "accumulation"
+            }
+            None => {
+                graph.create_texture("hdr", TransientTexture::new(self.hdr_format));
+                "hdr"
+            }
+        };
+        // The pool notices the new surface size after a resize and reallocates
+        graph.create_texture("depth", TransientTexture::new(texture::Texture::DEPTH_FORMAT));
+
+        graph.add_pass("main").writes(&[scene_target, "depth"]).execute(|encoder, resources| {
+            let draw_resources = DrawResources {
+                globals: vec![(1, &camera_bind_group), (2, self.frame.bind_group()), (3, self.parallax.light())],
+                pipelines: vec![
+                    &self.render_pipeline,
+                    self.parallax.pipeline(),
+                    self.emissive.pipeline(),
+                    self.toon.pipeline(),
+                    self.toon.outline_pipeline(),
+                    self.lightmap.pipeline(),
+                ],
+                materials: material_bind_groups.iter().map(|group| &**group).collect(),
+                meshes: scene_mesh_buffers(&self.mesh_pool, &self.instance_buffer),
+            };
+
+            let bundle_desc = wgpu::RenderBundleEncoderDescriptor {
+                label: Some("Main Pass Bundle"),
+                color_formats: &[Some(self.hdr_format)],
+                depth_stencil: Some(wgpu::RenderBundleDepthStencil {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_read_only: false,
+                    stencil_read_only: true,
+                }),
+                sample_count: 1,
+                multiview: None,
+            };
+            let static_bundle = self.static_geometry.bundle(&self.device, &bundle_desc, 0, &draw_resources);
+
+            // Big scenes get recorded into render bundles across threads, the
+            // bundles have to exist before the pass that executes them.
+            #[cfg(not(target_arch = "wasm32"))]
+            let bundles = (self.draw_list.len() >= PARALLEL_DRAW_THRESHOLD)
+                .then(|| self.draw_list.draw_parallel(&self.device, &bundle_desc, 0, &draw_resources));
+            #[cfg(target_arch = "wasm32")]
+            let bundles: Option<Vec<wgpu::RenderBundle>> = None;
+
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: resources.view(scene_target),
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: match main_ops.clear {
+                            ClearMode::Color(color) => wgpu::LoadOp::Clear(color),
+                            ClearMode::PreserveLastFrame => wgpu::LoadOp::Load,
+                        },
+                        store: main_ops.store,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: resources.view("depth"),
+                    depth_ops: Some(main_ops.depth),
+                    stencil_ops: None,
+                }),
+            });
+
+            // Stereo draws the scene in a pass of its own
+            if !self.settings.draw_scene || stereo {
+                return;
+            }
+            if split {
+                for (camera, draws) in viewport_cameras.iter().zip(&viewport_draws) {
+                    camera.region.apply(&mut render_pass, size);
+                    let camera_bind_group = &camera.camera_bind_group;
+                    let draw_resources = DrawResources {
+                        globals: vec![(1, camera_bind_group), (2, self.frame.bind_group()), (3, self.parallax.light())],
+                        pipelines: vec![
+                            &self.render_pipeline,
+                            self.parallax.pipeline(),
+                            self.emissive.pipeline(),
+                            self.toon.pipeline(),
+                            self.toon.outline_pipeline(),
+                            self.lightmap.pipeline(),
+                        ],
+                        materials: material_bind_groups.iter().map(|group| &**group).collect(),
+                        meshes: scene_mesh_buffers(&self.mesh_pool, &self.instance_buffer),
+                    };
+                    draw::draw_commands(draws, &mut render_pass, 0, &draw_resources);
+                }
+                return;
+            }
+            main_ops.region.apply(&mut render_pass, size);
+            render_pass.execute_bundles(std::iter::once(static_bundle));
+            match &bundles {
+                Some(bundles) => render_pass.execute_bundles(bundles.iter()),
+                None => self.draw_list.draw(&mut render_pass, 0, &draw_resources),
+            }
+        });
+
+        // Behind everything, filling in what the main pass left at the far
+        // plane. App passes drawing into the scene still come after it.
+        if self.settings.sky.is_some() && !stereo {
+            graph.add_pass("sky").writes(&[scene_target, "depth"]).execute(|encoder, resources| {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Sky Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: resources.view(scene_target),
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: resources.view("depth"),
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        }),
+                        stencil_ops: None,
+                    }),
+                });
+                if split {
+                    for camera in viewport_cameras {
+                        camera.region.apply(&mut render_pass, size);
+                        self.sky.draw(&mut render_pass, &camera.camera_bind_group);
+                    }
+                    return;
+                }
+                main_ops.region.apply(&mut render_pass, size);
+                self.sky.draw(&mut render_pass, &camera_bind_group);
+            });
+        }
+
+        if !mirror_surfaces.is_empty() {
+            let clear = match main_ops.clear {
+                ClearMode::Color(color) => color,
+                ClearMode::PreserveLastFrame => wgpu::Color::BLACK,
+            };
+            self.mirror_passes.declare(&mut graph, self.hdr_format);
+            let (mirror_passes, sky) = (&self.mirror_passes, self.settings.sky.map(|_| &self.sky));
+            let (reflected, material_bind_groups) = (&reflected, &material_bind_groups);
+            let (render_pipeline, parallax, emissive) = (&self.render_pipeline, &self.parallax, &self.emissive);
+            let (toon, lightmap) = (&self.toon, &self.lightmap);
+            let frame = &self.frame;
+            let (mesh_pool, instance_buffer) = (&self.mesh_pool, &self.instance_buffer);
+            for slot in 0..mirror_surfaces.len().min(MAX_MIRRORS) {
+                graph
+                    .add_pass(Mirrors::PASSES[slot])
+                    .writes(&[Mirrors::TEXTURES[slot], Mirrors::DEPTHS[slot]])
+                    .execute(move |encoder, resources| {
+                        let mut render_pass = Mirrors::reflection_pass(encoder, resources, slot, clear);
+                        let camera = mirror_passes.camera(slot);
+                        let draw_resources = DrawResources {
+                            globals: vec![(1, camera), (2, frame.bind_group()), (3, parallax.light())],
+                            pipelines: vec![
+                                render_pipeline,
+                                parallax.pipeline(),
+                                emissive.pipeline(),
+                                toon.pipeline(),
+                                toon.outline_pipeline(),
+                                lightmap.pipeline(),
+                            ],
+                            materials: material_bind_groups.iter().map(|group| &**group).collect(),
+                            meshes: scene_mesh_buffers(mesh_pool, instance_buffer),
+                        };
+                        draw::draw_commands(reflected, &mut render_pass, 0, &draw_resources);
+                        if let Some(sky) = sky {
+                            sky.draw(&mut render_pass, camera);
+                        }
+                    });
+            }
+            mirror_passes.add_pass(
+                &mut graph,
+                &self.device,
+                scene_target,
+                &camera_bind_group,
+                self.frame.bind_group(),
+                mirror_surfaces,
+                scene_mesh_buffers(mesh_pool, instance_buffer),
+            );
+        }
+
+        self.probes.declare(&mut graph);
+        if capture_probes {
+            let clear = match main_ops.clear {
+                ClearMode::Color(color) => color,
+                ClearMode::PreserveLastFrame => wgpu::Color::BLACK,
+            };
+            let (probes, sky) = (&self.probes, self.settings.sky.map(|_| &self.sky));
+            let (reflected, material_bind_groups) = (&reflected, &material_bind_groups);
+            let (render_pipeline, parallax, emissive) = (&self.render_pipeline, &self.parallax, &self.emissive);
+            let (toon, lightmap) = (&self.toon, &self.lightmap);
+            let frame = &self.frame;
+            let (mesh_pool, instance_buffer) = (&self.mesh_pool, &self.instance_buffer);
+            graph.add_pass("probe_capture").writes(&[ReflectionProbes::TEXTURE]).execute(move |encoder, _| {
+                for probe in 0..probes.len() {
+                    for face in 0..6 {
+                        let mut render_pass = probes.face_pass(encoder, probe, face, clear);
+                        let camera = probes.camera(probe, face);
+                        let draw_resources = DrawResources {
+                            globals: vec![(1, camera), (2, frame.bind_group()), (3, parallax.light())],
+                            pipelines: vec![
+                                render_pipeline,
+                                parallax.pipeline(),
+                                emissive.pipeline(),
+                                toon.pipeline(),
+                                toon.outline_pipeline(),
+                                lightmap.pipeline(),
+                            ],
+                            materials: material_bind_groups.iter().map(|group| &**group).collect(),
+                            meshes: scene_mesh_buffers(mesh_pool, instance_buffer),
+                        };
+                        draw::draw_commands(reflected, &mut render_pass, 0, &draw_resources);
+                        if let Some(sky) = sky {
+                            sky.draw(&mut render_pass, camera);
+                        }
+                    }
+                }
+            });
+        }
+        if !glossy_surfaces.is_empty() {
+            self.probes.add_pass(
+                &mut graph,
+                scene_target,
+                &camera_bind_group,
+                glossy_surfaces,
+                scene_mesh_buffers(&self.mesh_pool, &self.instance_buffer),
+            );
+        }
+
+        if self.settings.grid && single_camera {
+            graph.add_pass("grid").writes(&[scene_target, "depth"]).execute(|encoder, resources| {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Grid Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: resources.view(scene_target),
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: resources.view("depth"),
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        }),
+                        stencil_ops: None,
+                    }),
+                });
+                main_ops.region.apply(&mut render_pass, size);
+                self.grid.draw(&mut render_pass, self.frame.bind_group());
+            });
+        }
+
+        // Over the scene and anything drawn into it, under the lines
+        if !highlighted.is_empty() {
+            graph.add_pass("highlight").writes(&[scene_target, "depth"]).execute(|encoder, resources| {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Highlight Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: resources.view(scene_target),
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: resources.view("depth"),
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        }),
+                        stencil_ops: None,
+                    }),
+                });
+                let (region, camera) = match hovered_viewport.map(|index| &viewport_cameras[index]) {
+                    Some(viewport) => (viewport.region, &viewport.camera_bind_group),
+                    None => (main_ops.region, &*camera_bind_group),
+                };
+                region.apply(&mut render_pass, size);
+                let meshes = scene_mesh_buffers(&self.mesh_pool, &self.instance_buffer);
+                self.highlight.draw(&mut render_pass, &highlighted, camera, self.frame.bind_group(), meshes);
+            });
+        }
+
+        if let (true, Some(stereo_passes)) = (stereo, &self.stereo) {
+            let clear = match main_ops.clear {
+                ClearMode::Color(color) => color,
+                ClearMode::PreserveLastFrame => wgpu::Color::BLACK,
+            };
+            stereo_passes.add_pass(
+                &mut graph,
+                scene_target,
+                self.frame.bind_group(),
+                stereo_draws,
+                material_bind_groups.iter().map(|group| &**group).collect(),
+                scene_mesh_buffers(&self.mesh_pool, &self.instance_buffer),
+                clear,
+            );
+        }
+
+        // Trails then particles, both blended over the scene
+        if (self.trail_renderer.has_draws() || self.particle_renderer.has_draws()) && !stereo {
+            graph.add_pass("effects").writes(&[scene_target, "depth"]).execute(|encoder, resources| {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Effects Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: resources.view(scene_target),
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: resources.view("depth"),
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        }),
+                        stencil_ops: None,
+                    }),
+                });
+                if split {
+                    for camera in viewport_cameras {
+                        camera.region.apply(&mut render_pass, size);
+                        let camera = &camera.camera_bind_group;
+                        self.trail_renderer.draw(&mut render_pass, camera, self.frame.bind_group());
+                        self.particle_renderer.draw(&mut render_pass, camera, self.frame.bind_group());
+                    }
+                    return;
+                }
+                main_ops.region.apply(&mut render_pass, size);
+                self.trail_renderer.draw(&mut render_pass, &camera_bind_group, self.frame.bind_group());
+                self.particle_renderer.draw(&mut render_pass, &camera_bind_group, self.frame.bind_group());
+            });
+        }
+
+        if self.lines.has_draws() && !stereo {
+            graph.add_pass("lines").writes(&[scene_target, "depth"]).execute(|encoder, resources| {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Line Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: resources.view(scene_target),
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: resources.view("depth"),
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        }),
+                        stencil_ops: None,
+                    }),
+                });
+                if split {
+                    for camera in viewport_cameras {
+                        camera.region.apply(&mut render_pass, size);
+                        self.lines.draw(&mut render_pass, &camera.camera_bind_group, self.frame.bind_group());
+                    }
+                    return;
+                }
+                main_ops.region.apply(&mut render_pass, size);
+                self.lines.draw(&mut render_pass, &camera_bind_group, self.frame.bind_group());
+            });
+        }
+
+        // Reading the scene and depth makes it wait for everything the app
+        // draws into them as well
+        let outlined = match outline {
+            Some(_) => {
+                graph.create_texture("outlined", TransientTexture::new(self.hdr_format));
+                graph.add_pass("outline").reads(&[scene_target, "depth"]).writes(&["outlined"]).execute(
+                    |encoder, resources| {
+                        let (scene, depth) = (resources.view(scene_target), resources.view("depth"));
+                        self.outline.run(&self.device, encoder, scene, depth, resources.view("outlined"));
+                    },
+                );
+                "outlined"
+            }
+            None => scene_target,
+        };
+        let tonemap_source = match fog {
+            Some((volumetric_fog, _)) => {
+                graph.create_texture("fogged", TransientTexture::new(self.hdr_format));
+                graph.add_pass("fog").reads(&[outlined, "depth"]).writes(&["fogged"]).execute(
+                    |encoder, resources| {
+                        let (scene, depth) = (resources.view(outlined), resources.view("depth"));
+                        volumetric_fog.run(&self.device, encoder, scene, depth, resources.view("fogged"));
+                    },
+                );
+                "fogged"
+            }
+            None => outlined,
+        };
+        // After the fog, so its light shafts can glow too
+        let tonemap_source = match self.settings.bloom {
+            Some(_) => {
+                self.bloom.add_pass(&mut graph, &self.device, tonemap_source, "bloomed");
+                "bloomed"
+            }
+            None => tonemap_source,
+        };
+
+        if let Some((eye_adaptation, _)) = auto_exposure {
+            graph.add_pass("exposure").reads(&[tonemap_source]).execute(|encoder, resources| {
+                let view = resources.view(tonemap_source);
+                eye_adaptation.run(&self.device, encoder, view, size, self.tonemap.exposure_buffer(), 0);
+            });
+        }
+
+        // In pixel art mode the scene is tonemapped at its own small size
+        // first, and only then scaled up onto the surface. The CRT effect
+        // puts it on the surface itself, in pixel art mode or not.
+        let tonemap_target = match self.settings.pixel_art.is_some() || self.settings.crt.is_some() {
+            true => {
+                graph.create_texture("pixels", TransientTexture::new(self.config.format));
+                "pixels"
+            }
+            false => "surface",
+        };
+        graph.add_pass("tonemap").reads(&[tonemap_source]).writes(&[tonemap_target]).execute(|encoder, resources| {
+            let source = self.tonemap.bind_source(&self.device, resources.view(tonemap_source));
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Tonemap Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: resources.view(tonemap_target),
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            self.tonemap.draw(&mut render_pass, &source, self.frame.bind_group());
+        });
+        if self.settings.crt.is_some() {
+            graph.add_pass("crt").reads(&["pixels"]).writes(&["surface"]).execute(|encoder, resources| {
+                self.crt.run(&self.device, encoder, resources.view("pixels"), resources.view("surface"));
+            });
+        } else if let Some(rect) = &pixel_art_rect {
+            graph.add_pass("pixel_art").reads(&["pixels"]).writes(&["surface"]).execute(|encoder, resources| {
+                self.pixel_art.run(&self.device, encoder, resources.view("pixels"), resources.view("surface"), *rect);
+            });
+        }
+
+        let context = RenderContext {
+            device: &self.device,
+            queue: &self.queue,
+            uploader: &app_uploader,
+            surface_format: self.config.format,
+            scene_target,
+            hdr_format: self.hdr_format,
+            surface_size,
+            scene_size: size,
+            camera_position: self.camera.eye,
+            view_proj: self.camera.build_view_projection_matrix(),
+            camera_bind_group: &camera_bind_group,
+            viewports: viewport_cameras,
+            cursor_ray,
+            frame_bind_group: self.frame.bind_group(),
+            scene_bvh: &self.scene_bvh,
+            lighting,
+            sky: self.settings.sky.is_some().then_some(&self.sky),
+        };
+        let app_scope = profiler::scope("app render");
+        app.render(&mut graph, context);
+        drop(app_scope);
+
+        if self.sprites.has_draws() {
+            graph.add_pass("sprites").writes(&["surface"]).execute(|encoder, resources| {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Sprite Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: resources.view("surface"),
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                });
+                self.settings.sprite_pass.apply(&mut render_pass, surface_size);
+                self.sprites.draw(&mut render_pass, &sprite_bind_groups, self.frame.bind_group());
+            });
+        }
+
+        // Over everything else, sprites included
+        let plugin_scope = profiler::scope("plugin render");
+        for plugin in plugins.iter_mut() {
+            plugin.render(&mut graph, context);
+        }
+        drop(plugin_scope);
+
+        // Transient textures follow the scene's size, which is only
+        // different from the surface's in pixel art mode
+        let graph_scope = profiler::scope("render graph");
+        graph
+            .execute(
+                &self.device,
+                &mut self.transient_pool,
+                &mut encoder,
+                size,
+                self.profiler.gpu_timer(),
+            )
+            .expect("Failed to execute render graph");
+        drop(graph_scope);
+        if let Some(timer) = self.profiler.gpu_timer() {
+            timer.resolve(&mut encoder);
+        }
+        self.bind_group_cache.end_frame();
+
+        // What the app uploaded goes first, its passes are in `encoder`
+        let (uploader, app_uploads) = app_uploader.finish();
+        self.uploader = uploader;
+        self.uploader.finish();
+        self.queue.submit([app_uploads, encoder.finish()]);
+        if let Some(timer) = self.profiler.gpu_timer() {
+            timer.submitted();
+        }
+        self.uploader.recall();
+        if let Some(output) = output {
+            let _scope = profiler::scope("present");
+            output.present();
+        }
+
+        Ok(())
+    }
+}
+
+pub async fn run(config: WindowConfig) {
+    run_app(config, ()).await
+}
+
+// Like run(), with the app's hooks called along the way. See
+// plugin::AppBuilder for adding plugins too.
+pub async fn run_app(config: WindowConfig, app: impl App) {
+    run_with_plugins(config, app, Vec::new()).await
+}
+
+pub(crate) async fn run_with_plugins(config: WindowConfig, mut app: impl App, mut plugins: Vec<Box<dyn Plugin>>) {
+    logging::init();
+    #[cfg(feature = "puffin")]
+    puffin::set_scopes_on(true);
+    let event_loop = EventLoop::new();
+    let window = config.builder().build(&event_loop).unwrap();
+
+    let mut state = State::new(&window).instrument(tracing::info_span!("init")).await;
+    let mut events = EventBus::new();
+    let mut constants = state.shaders.constants.clone();
+    let stream_count = state.mesh_pool.stream_count();
+    let mut setup = Setup {
+        events: &mut events,
+        settings: &mut state.settings,
+        device: &state.device,
+        queue: &state.queue,
+        surface_format: state.config.format,
+        hdr_format: state.hdr_format,
+        camera_bind_group_layout: &state.camera_bind_group_layout,
+        frame_bind_group_layout: state.frame.layout(),
+        shader_constants: &mut constants,
+        mesh_pool: &mut state.mesh_pool,
+        fonts: &mut state.fonts,
+        assets: &mut state.assets,
+        particles: &state.particles,
+        #[cfg(feature = "editor")]
+        console: &mut state.console,
+    };
+    for plugin in &mut plugins {
+        plugin.setup(&mut setup);
+    }
+    app.init(&mut setup);
+    if constants != state.shaders.constants || stream_count != state.mesh_pool.stream_count() {
+        state.shaders.constants = constants;
+        state.rebuild_pipelines();
+    }
+
+    event_loop.run(move |event, _, control_flow| {
+        match event {
+            Event::RedrawRequested(window_id) if window_id == window.id() => {
+                let _span = tracing::info_span!("frame", index = state.frame.uniform().frame).entered();
+                let dt = state.tick();
+                {
+                    let _scope = profiler::scope("plugins");
+                    let mut context = state.plugin_context(dt);
+                    for plugin in &mut plugins {
+                        plugin.pre_update(&mut context);
+                    }
+                    let changes = context.scene_changes;
+                    state.apply_scene_changes(changes);
+                    #[cfg(feature = "editor")]
+                    state.run_console();
+                }
+                {
+                    let _scope = profiler::scope("update");
+                    state.step(dt);
+                }
+                {
+                    let _scope = profiler::scope("app update");
+                    app.update(&mut state.settings, dt);
+                }
+                match state.render(&mut app, &mut plugins) {
+                    Ok(_) => {}
+                    // Reconfigure the surface if lost
+                    Err(wgpu::SurfaceError::Lost) => state.resize(state.screen.physical_size),
+                    // The system is out of memory, we should probably quit
+                    Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
+                    // All other errors (Outdated, Timeout) should be resolved by the next frame
+                    Err(e) => tracing::warn!("Failed to get the next frame: {:?}", e),
+                }
+                let mut context = state.plugin_context(dt);
+                for plugin in &mut plugins {
+                    plugin.post_render(&mut context);
+                }
+                let changes = context.scene_changes;
+                state.apply_scene_changes(changes);
+                state.profiler.end_frame(&state.device);
+                #[cfg(feature = "tracy")]
+                state.plot_memory();
+            }
+            Event::MainEventsCleared => {
+                state.cursor.apply(&window);
+                state.apply_display(&window);
+                state.text_input.apply(&window);
+                // RedrawRequested will only trigger once, unless we manually
+                // request it.
+                window.request_redraw();
+            }
+            Event::WindowEvent {
+                ref event,
+                window_id,
+            } if window_id == window.id() => {
+                let taken = plugins.iter_mut().any(|plugin| plugin.on_event(event)) || state.input(event);
+                match event {
+                    // The window's size and closing can't be kept from the
+                    // engine, whoever else used them
+                    WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+                    WindowEvent::Resized(physical_size) => {
+                        state.resize(*physical_size);
+                    }
+                    WindowEvent::ScaleFactorChanged { scale_factor, new_inner_size } => {
+                        state.rescale(*scale_factor, **new_inner_size);
+                    }
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::Escape),
+                                ..
+                            },
+                        ..
+                    } if !taken => *control_flow = ControlFlow::Exit,
+                    WindowEvent::DroppedFile(path) if !taken => {
+                        state.file_dropped(path);
+                    }
+                    _ => {}
+                }
+                // Subscribers hear about everything, after the engine has
+                // dealt with it
+                events.emit_window_event(event, &state.screen);
+                #[cfg(not(feature = "ecs"))]
+                for edit in std::mem::take(&mut state.edits) {
+                    events.emit(&edit);
+                }
+                #[cfg(not(feature = "ecs"))]
+                if let Some(selected) = state.marquee_selected.take() {
+                    events.emit(&selected);
                 }
             }
             _ => {}
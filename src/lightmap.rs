@@ -0,0 +1,195 @@
+use std::rc::Rc;
+
+use wgpu::util::DeviceExt;
+
+use crate::bind_group_cache::{BindGroupCache, CachedBinding, ResourceId};
+use crate::scene::{LightmapContents, LightmappedMaterial};
+use crate::texture::Texture;
+
+// Where the lightmap pipeline goes in the scene passes' DrawResources, after
+// the toon pipelines
+pub const PIPELINE: u32 = 5;
+
+// Irradiance is stored divided by this, so light brighter than white (a
+// surface facing the sun with the sky on top) fits in an 8 bit image
+pub const IRRADIANCE_SCALE: f32 = 2.0;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightmapUniform {
+    intensity: f32,
+    // 1 for ambient occlusion, 0 for irradiance
+    occlusion: f32,
+    _padding: [f32; 2],
+}
+
+impl From<&LightmappedMaterial> for LightmapUniform {
+    fn from(material: &LightmappedMaterial) -> Self {
+        let (scale, occlusion) = match material.contents {
+            LightmapContents::Irradiance => (IRRADIANCE_SCALE, 0.0),
+            LightmapContents::AmbientOcclusion => (1.0, 1.0),
+        };
+        Self {
+            intensity: material.intensity.max(0.0) * scale,
+            occlusion,
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+// Surfaces lit by light worked out ahead of time (see bake.rs) and stored in
+// a texture, the lightmap, read through a second set of texture coordinates
+// where no two triangles overlap. Those come from the mesh pool's lightmap
+// stream (VertexLightmapUv), so only this pipeline has to know about them.
+//
+// A lightmap holds either all the light reaching the surface, which then
+// replaces the realtime lighting, or only how much of the sky it sees, which
+// darkens the ambient light while the sun is still done every frame like
+// toon.rs does, normals from screen space derivatives and all.
+//
+// Lightmapped materials are drawn with their own pipeline (PIPELINE in the
+// draw commands) and material bind group, and need Parallax::light() bound
+// to group 3 next to the camera and frame.
+pub struct Lightmap {
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    material_layout: wgpu::BindGroupLayout,
+    material_layout_id: ResourceId,
+}
+
+impl Lightmap {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        camera_layout: &wgpu::BindGroupLayout,
+        frame_layout: &wgpu::BindGroupLayout,
+        // Parallax::light_layout()
+        light_layout: &wgpu::BindGroupLayout,
+        bind_group_cache: &mut BindGroupCache,
+        shader_source: &str,
+        // Extra attribute streams from the mesh pool, which have to include
+        // the lightmap coordinates
+        streams: &[wgpu::VertexBufferLayout],
+    ) -> Self {
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let sampler_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        let material_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("lightmap_material_bind_group_layout"),
+            entries: &[
+                texture_entry(0),
+                sampler_entry(1),
+                // The lightmap
+                texture_entry(2),
+                sampler_entry(3),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Lightmap Pipeline Layout"),
+            bind_group_layouts: &[&material_layout, camera_layout, frame_layout, light_layout],
+            push_constant_ranges: &[],
+        });
+
+        Self {
+            pipeline: crate::create_render_pipeline(device, &layout, color_format, shader_source, streams),
+            layout,
+            color_format,
+            material_layout,
+            material_layout_id: bind_group_cache.register(),
+        }
+    }
+
+    // Called when the shader file changed or the mesh pool got new streams,
+    // keeps the old pipeline if the new source doesn't compile
+    pub fn reload_shader(&mut self, device: &wgpu::Device, shader_source: &str, streams: &[wgpu::VertexBufferLayout]) {
+        if let Some(pipeline) = crate::shaders::try_create(device, || {
+            crate::create_render_pipeline(device, &self.layout, self.color_format, shader_source, streams)
+        }) {
+            self.pipeline = pipeline;
+        }
+    }
+
+    // The settings of one material, for material_bind_group()
+    pub fn create_material_buffer(device: &wgpu::Device, material: &LightmappedMaterial) -> wgpu::Buffer {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Lightmap Material Buffer"),
+            contents: bytemuck::bytes_of(&LightmapUniform::from(material)),
+            usage: wgpu::BufferUsages::UNIFORM,
+        })
+    }
+
+    // Group 0 for a lightmapped material. `id` stands in for all of it, so it
+    // has to be recreated when either texture is.
+    pub fn material_bind_group(
+        &self,
+        device: &wgpu::Device,
+        bind_group_cache: &mut BindGroupCache,
+        id: ResourceId,
+        diffuse: &Texture,
+        lightmap: &Texture,
+        buffer: &wgpu::Buffer,
+    ) -> Rc<wgpu::BindGroup> {
+        bind_group_cache.get_or_create(
+            device,
+            Some("lightmap_material_bind_group"),
+            self.material_layout_id,
+            &self.material_layout,
+            &[
+                CachedBinding {
+                    binding: 0,
+                    id,
+                    resource: wgpu::BindingResource::TextureView(&diffuse.view),
+                },
+                CachedBinding {
+                    binding: 1,
+                    id,
+                    resource: wgpu::BindingResource::Sampler(&diffuse.sampler),
+                },
+                CachedBinding {
+                    binding: 2,
+                    id,
+                    resource: wgpu::BindingResource::TextureView(&lightmap.view),
+                },
+                CachedBinding {
+                    binding: 3,
+                    id,
+                    resource: wgpu::BindingResource::Sampler(&lightmap.sampler),
+                },
+                CachedBinding {
+                    binding: 4,
+                    id,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+        )
+    }
+
+    pub fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+}
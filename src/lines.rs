@@ -0,0 +1,174 @@
+use std::ops::Range;
+
+use glam::Vec3;
+
+use crate::texture::Texture;
+use crate::upload::Uploader;
+use crate::vertex::VertexLayout;
+
+crate::vertex_layout! {
+    #[derive(Debug)]
+    struct LineVertex {
+        #[location(0)] position: [f32; 3],
+        #[location(4)] color: [f32; 4],
+    }
+}
+
+// Collects coloured lines in world space over a frame and draws them after
+// the scene, either depth tested against it or on top of everything.
+//
+// Every frame: line() as much as you like, prepare() and then draw() into a
+// pass with the scene's depth buffer attached.
+pub struct LineBatch {
+    // Depth tested, then on top
+    pipelines: [wgpu::RenderPipeline; 2],
+    layout: wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    vertex_buffer: wgpu::Buffer,
+    // In vertices
+    capacity: usize,
+    lines: [Vec<LineVertex>; 2],
+    // What prepare() left for each pipeline, in vertices
+    ranges: [Range<u32>; 2],
+}
+
+impl LineBatch {
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        camera_layout: &wgpu::BindGroupLayout,
+        frame_layout: &wgpu::BindGroupLayout,
+        shader_source: &str,
+    ) -> Self {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Line Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, frame_layout],
+            push_constant_ranges: &[],
+        });
+        let pipelines = Self::create_pipelines(device, &layout, color_format, shader_source);
+
+        let capacity = 1024;
+        Self {
+            pipelines,
+            layout,
+            color_format,
+            vertex_buffer: Self::create_vertex_buffer(device, capacity),
+            capacity,
+            lines: [Vec::new(), Vec::new()],
+            ranges: [0..0, 0..0],
+        }
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        shader_source: &str,
+    ) -> [wgpu::RenderPipeline; 2] {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Line Shader"),
+            source: wgpu::ShaderSource::Wgsl(shader_source.into()),
+        });
+        [(wgpu::CompareFunction::Less, "Line Pipeline"), (wgpu::CompareFunction::Always, "Line Overlay Pipeline")].map(
+            |(depth_compare, label)| {
+                device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some(label),
+                    layout: Some(layout),
+                    vertex: wgpu::VertexState {
+                        module: &shader,
+                        entry_point: "vs_main",
+                        buffers: &[LineVertex::desc()],
+                    },
+                    fragment: Some(wgpu::FragmentState {
+                        module: &shader,
+                        entry_point: "fs_main",
+                        targets: &[Some(wgpu::ColorTargetState {
+                            format: color_format,
+                            blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                            write_mask: wgpu::ColorWrites::ALL,
+                        })],
+                    }),
+                    primitive: wgpu::PrimitiveState {
+                        topology: wgpu::PrimitiveTopology::LineList,
+                        ..Default::default()
+                    },
+                    // Lines read the scene's depth but never write it, so
+                    // they don't hide each other
+                    depth_stencil: Some(wgpu::DepthStencilState {
+                        format: Texture::DEPTH_FORMAT,
+                        depth_write_enabled: false,
+                        depth_compare,
+                        stencil: wgpu::StencilState::default(),
+                        bias: wgpu::DepthBiasState::default(),
+                    }),
+                    multisample: wgpu::MultisampleState::default(),
+                    multiview: None,
+                })
+            },
+        )
+    }
+
+    // Called when the shader file changed, keeps the old pipelines if the
+    // new source doesn't compile
+    pub fn reload_shader(&mut self, device: &wgpu::Device, shader_source: &str) {
+        if let Some(pipelines) = crate::shaders::try_create(device, || {
+            Self::create_pipelines(device, &self.layout, self.color_format, shader_source)
+        }) {
+            self.pipelines = pipelines;
+        }
+    }
+
+    fn create_vertex_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Line Vertex Buffer"),
+            size: (capacity * std::mem::size_of::<LineVertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    // `on_top` lines show through the scene instead of being hidden by it
+    pub fn line(&mut self, a: Vec3, b: Vec3, color: [f32; 4], on_top: bool) {
+        self.lines[on_top as usize].extend([a, b].map(|position| LineVertex {
+            position: position.into(),
+            color,
+        }));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.iter().all(Vec::is_empty)
+    }
+
+    // Writes this frame's lines into the vertex buffer and clears them
+    pub fn prepare(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, uploader: &mut Uploader) {
+        let [tested, on_top] = &mut self.lines;
+        let count = tested.len() + on_top.len();
+        if count > self.capacity {
+            self.capacity = count.next_power_of_two();
+            self.vertex_buffer = Self::create_vertex_buffer(device, self.capacity);
+        }
+        self.ranges = [0..tested.len() as u32, tested.len() as u32..count as u32];
+        tested.append(on_top);
+        if !tested.is_empty() {
+            uploader.write(device, encoder, &self.vertex_buffer, 0, tested);
+        }
+        tested.clear();
+    }
+
+    // Whether prepare() left anything to draw
+    pub fn has_draws(&self) -> bool {
+        self.ranges.iter().any(|range| !range.is_empty())
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera: &'a wgpu::BindGroup, frame: &'a wgpu::BindGroup) {
+        render_pass.set_bind_group(0, camera, &[]);
+        render_pass.set_bind_group(1, frame, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        for (pipeline, range) in self.pipelines.iter().zip(&self.ranges) {
+            if !range.is_empty() {
+                render_pass.set_pipeline(pipeline);
+                render_pass.draw(range.clone(), 0..1);
+            }
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::filter::{EnvFilter, Targets};
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+
+// A log line kept around for showing on screen
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogLine {
+    pub level: Level,
+    pub target: String,
+    // The message followed by any other fields, `key=value`
+    pub message: String,
+}
+
+// How many lines recent_lines() remembers
+const MAX_RECENT_LINES: usize = 256;
+
+static RECENT: Mutex<VecDeque<LogLine>> = Mutex::new(VecDeque::new());
+
+// The last few hundred lines logged, oldest first. Info and up, except for
+// the chatty graphics and windowing crates which only get warnings in,
+// whatever RUST_LOG says.
+pub fn recent_lines() -> Vec<LogLine> {
+    RECENT.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).iter().cloned().collect()
+}
+
+// Sets up tracing for the whole program: printed to stderr as filtered by
+// RUST_LOG (errors only when it isn't set), and kept for recent_lines().
+// Records from crates still on the `log` crate, like wgpu, come along too.
+// Does nothing if something already set up a subscriber.
+pub fn init() {
+    let printed = tracing_subscriber::fmt::layer()
+        .with_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("error")));
+    let recent = RecentLines.with_filter(
+        Targets::new()
+            .with_targets(["wgpu_core", "wgpu_hal", "naga", "winit"].map(|target| (target, Level::WARN)))
+            .with_default(Level::INFO),
+    );
+    let _ = tracing_subscriber::registry().with(printed).with(recent).try_init();
+}
+
+struct RecentLines;
+
+impl<S: Subscriber> Layer<S> for RecentLines {
+    fn on_event(&self, event: &Event<'_>, _context: Context<'_, S>) {
+        let mut message = MessageVisitor(String::new());
+        event.record(&mut message);
+        let line = LogLine {
+            level: *event.metadata().level(),
+            target: event.metadata().target().to_string(),
+            message: message.0,
+        };
+        let mut recent = RECENT.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if recent.len() == MAX_RECENT_LINES {
+            recent.pop_front();
+        }
+        recent.push_back(line);
+    }
+}
+
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        // Where records from the log crate came from, already in the metadata
+        if field.name().starts_with("log.") {
+            return;
+        }
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+        let _ = if field.name() == "message" {
+            write!(self.0, "{:?}", value)
+        } else {
+            write!(self.0, "{}={:?}", field.name(), value)
+        };
+    }
+}
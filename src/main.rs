@@ -1,5 +1,73 @@
-use learning_wgpu::run;
+use learning_wgpu::{app::App, plugin::AppBuilder, window::WindowConfig};
+
+// Every app gets the plugins the build was made with.
+// `cargo run --features audio` plays the sounds apps ask for, see audio.rs,
+// and `--features editor` adds the editor, see editor.rs
+fn builder<A: App>(config: WindowConfig, app: A) -> AppBuilder<A> {
+    let builder = AppBuilder::new(config, app);
+    #[cfg(feature = "editor")]
+    let builder = builder.add_plugin(learning_wgpu::editor::Editor::new());
+    #[cfg(all(feature = "audio", not(target_arch = "wasm32")))]
+    let builder = builder.add_plugin(learning_wgpu::audio::Audio::new());
+    builder
+}
 
 fn main() {
-    pollster::block_on(run());
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(arg) = std::env::args().nth(1) {
+        // `cargo run -- --bench scene.ron` prints frame times as JSON, see bench.rs
+        if arg == "--bench" {
+            learning_wgpu::logging::init();
+            let report = learning_wgpu::bench::BenchConfig::from_args(std::env::args().skip(2))
+                .and_then(|config| pollster::block_on(learning_wgpu::bench::bench(&config)));
+            match report {
+                Ok(report) => println!("{}", serde_json::to_string_pretty(&report).unwrap()),
+                Err(e) => {
+                    eprintln!("Benchmark failed: {:?}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        // `cargo run --release -- --bake-lightmaps scene.ron` bakes the scene's
+        // lightmaps, see bake.rs
+        if arg == "--bake-lightmaps" {
+            learning_wgpu::logging::init();
+            let written = learning_wgpu::bake::BakeConfig::from_args(std::env::args().skip(2))
+                .and_then(|config| learning_wgpu::bake::bake_lightmaps(&config));
+            match written {
+                Ok(written) => println!("Baked {} lightmaps", written.len()),
+                Err(e) => {
+                    eprintln!("Baking failed: {:?}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        // `cargo run -- --terrain` flies over generated hills, see terrain.rs
+        if arg == "--terrain" {
+            let app = learning_wgpu::terrain::TerrainApp::default();
+            return pollster::block_on(builder(WindowConfig::new("terrain"), app).run());
+        }
+        // `cargo run --features rhai -- --script toys/spin.rhai` runs a script
+        // over the usual scene, see script.rs
+        #[cfg(feature = "rhai")]
+        if arg == "--script" {
+            let path = std::env::args().nth(2).expect("--script needs a .rhai file");
+            let plugin = learning_wgpu::script::ScriptPlugin::new().with_script(path);
+            return pollster::block_on(builder(WindowConfig::default(), ()).add_plugin(plugin).run());
+        }
+        // `cargo run --features dylib -- --dylib target/debug/libgame.so` runs
+        // game logic that reloads when it's rebuilt, see dylib.rs
+        #[cfg(feature = "dylib")]
+        if arg == "--dylib" {
+            let path = std::env::args().nth(2).expect("--dylib needs a library");
+            let app = learning_wgpu::dylib::DylibApp::new(path);
+            return pollster::block_on(builder(WindowConfig::new("game"), app).run());
+        }
+        // `cargo run -- toys/plasma.wgsl` runs just that shader, see shadertoy.rs
+        let app = learning_wgpu::shadertoy::Shadertoy::new(arg);
+        return pollster::block_on(builder(WindowConfig::new("shadertoy"), app).run());
+    }
+    pollster::block_on(builder(WindowConfig::default(), ()).run());
 }
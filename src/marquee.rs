@@ -0,0 +1,77 @@
+use glam::Vec2;
+
+use crate::assets::Handle;
+use crate::sprite::Sprite;
+use crate::texture::Texture;
+
+// Sent when a marquee drag ends, with every scene entity whose bounds are
+// at least partly inside the rectangle. Like culling, an entity just outside
+// one of its corners can still count. Never sent with the ecs feature.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MarqueeSelected {
+    pub entities: Vec<usize>,
+}
+
+// How far the cursor has to move before a drag counts, so a shaky click
+// doesn't select a sliver
+const MIN_SIZE: f32 = 4.0;
+
+const FILL: [f32; 4] = [0.3, 0.6, 1.0, 0.15];
+const BORDER: [f32; 4] = [0.3, 0.6, 1.0, 0.9];
+
+// A rectangle dragged out across the window to select everything in it,
+// in logical pixels like sprites
+#[derive(Default)]
+pub struct Marquee {
+    // Where the drag started and where the cursor is now
+    drag: Option<(Vec2, Vec2)>,
+}
+
+impl Marquee {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn begin(&mut self, point: Vec2) {
+        self.drag = Some((point, point));
+    }
+
+    pub fn drag(&mut self, point: Vec2) {
+        if let Some((_, end)) = &mut self.drag {
+            *end = point;
+        }
+    }
+
+    // Stops dragging, giving back the rectangle if it got big enough
+    pub fn end(&mut self) -> Option<(Vec2, Vec2)> {
+        let rect = self.rect();
+        self.drag = None;
+        rect.filter(|(min, max)| (*max - *min).max_element() >= MIN_SIZE)
+    }
+
+    pub fn is_dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    // The min and max corners, whichever way it was dragged
+    pub fn rect(&self) -> Option<(Vec2, Vec2)> {
+        self.drag.map(|(start, end)| (start.min(end), start.max(end)))
+    }
+
+    // A see-through fill with a one pixel border, drawn with a plain white
+    // texture
+    pub fn sprites(&self, white: Handle<Texture>) -> Vec<Sprite> {
+        let Some((min, max)) = self.rect() else {
+            return Vec::new();
+        };
+        let size = max - min;
+        let border = |position: Vec2, size: Vec2| Sprite::new(white, position, size.max(Vec2::ONE)).with_color(BORDER);
+        vec![
+            Sprite::new(white, min, size).with_color(FILL),
+            border(min, Vec2::new(size.x, 1.0)),
+            border(Vec2::new(min.x, max.y - 1.0), Vec2::new(size.x, 1.0)),
+            border(min, Vec2::new(1.0, size.y)),
+            border(Vec2::new(max.x - 1.0, min.y), Vec2::new(1.0, size.y)),
+        ]
+    }
+}
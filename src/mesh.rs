@@ -0,0 +1,352 @@
+use glam::{Vec2, Vec3};
+
+use crate::bounds::{Aabb, BoundingSphere, Ray};
+use crate::vertex::{VertexLightmapUv, VertexPosNormalTangentUvColor, VertexPosNormalUv, VertexPosUv};
+
+// Where a ray hit a mesh, see Mesh::raycast()
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MeshHit {
+    // Along the ray, in multiples of its direction
+    pub distance: f32,
+    pub triangle: usize,
+    // Blended from the hit triangle's corners, zero without tex coords
+    pub tex_coords: Vec2,
+}
+
+// A mesh on the CPU, one Vec per attribute so it's easy to build up and
+// change. Attributes other than positions are either empty or have one entry
+// per position. Turn it into one of the vertex layouts with `vertices()` once
+// it's ready to upload.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Mesh {
+    pub positions: Vec<[f32; 3]>,
+    pub tex_coords: Vec<[f32; 2]>,
+    pub normals: Vec<[f32; 3]>,
+    // w is 1 or -1 depending on which way the bitangent points
+    pub tangents: Vec<[f32; 4]>,
+    pub colors: Vec<[f32; 4]>,
+    // A second set of texture coordinates for the lightmap, where no two
+    // triangles may overlap. Falls back to tex_coords when empty, which is
+    // fine for meshes that don't reuse parts of their texture.
+    pub lightmap_coords: Vec<[f32; 2]>,
+    pub indices: Vec<u32>,
+}
+
+impl Mesh {
+    pub fn new(positions: Vec<[f32; 3]>, indices: Vec<u32>) -> Self {
+        Self {
+            positions,
+            indices,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_tex_coords(mut self, tex_coords: Vec<[f32; 2]>) -> Self {
+        self.tex_coords = tex_coords;
+        self
+    }
+
+    pub fn with_lightmap_coords(mut self, lightmap_coords: Vec<[f32; 2]>) -> Self {
+        self.lightmap_coords = lightmap_coords;
+        self
+    }
+
+    // Where vertex `i` is in the lightmap, from whichever set of texture
+    // coordinates it has
+    pub fn lightmap_coord(&self, i: usize) -> Vec2 {
+        let coords = if self.lightmap_coords.is_empty() { &self.tex_coords } else { &self.lightmap_coords };
+        coords.get(i).copied().map_or(Vec2::ZERO, Vec2::from)
+    }
+
+    fn triangles(&self) -> impl Iterator<Item = [usize; 3]> + '_ {
+        self.indices.chunks_exact(3).map(|t| [t[0] as usize, t[1] as usize, t[2] as usize])
+    }
+
+    // Smooth normals, averaged over the triangles sharing each vertex and
+    // weighted by their area. Edges where the vertices are split (like the
+    // cube's) stay sharp.
+    pub fn recompute_normals(&mut self) {
+        let mut sums = vec![Vec3::ZERO; self.positions.len()];
+        for [a, b, c] in self.triangles() {
+            let [pa, pb, pc] = [a, b, c].map(|i| Vec3::from(self.positions[i]));
+            // The cross product's length is twice the area
+            let normal = (pb - pa).cross(pc - pa);
+            for i in [a, b, c] {
+                sums[i] += normal;
+            }
+        }
+        self.normals = sums.iter().map(|sum| sum.normalize_or_zero().into()).collect();
+    }
+
+    // Every triangle gets its own three vertices with the triangle's normal,
+    // for a faceted look
+    pub fn recompute_flat_normals(&mut self) {
+        fn unweld<T: Copy>(values: &[T], indices: &[u32]) -> Vec<T> {
+            if values.is_empty() {
+                return Vec::new();
+            }
+            indices.iter().map(|&i| values[i as usize]).collect()
+        }
+        self.positions = unweld(&self.positions, &self.indices);
+        self.tex_coords = unweld(&self.tex_coords, &self.indices);
+        self.lightmap_coords = unweld(&self.lightmap_coords, &self.indices);
+        self.tangents = unweld(&self.tangents, &self.indices);
+        self.colors = unweld(&self.colors, &self.indices);
+        self.indices = (0..self.positions.len() as u32).collect();
+
+        self.normals = self
+            .positions
+            .chunks_exact(3)
+            .flat_map(|t| {
+                let [a, b, c] = [t[0], t[1], t[2]].map(Vec3::from);
+                let normal: [f32; 3] = (b - a).cross(c - a).normalize_or_zero().into();
+                [normal; 3]
+            })
+            .collect();
+    }
+
+    // Tangents pointing along +u, for normal mapping. Needs normals, and
+    // without UVs there's no telling which way the texture goes so any
+    // perpendicular direction is used.
+    pub fn recompute_tangents(&mut self) {
+        if self.normals.len() != self.positions.len() {
+            self.recompute_normals();
+        }
+        let normals = self.normals.iter().map(|n| Vec3::from(*n)).collect::<Vec<_>>();
+        if self.tex_coords.len() != self.positions.len() {
+            self.tangents = normals.iter().map(|n| n.any_orthonormal_vector().extend(1.0).into()).collect();
+            return;
+        }
+
+        // Lengyel's method: the tangent and bitangent of each triangle are
+        // where u and v go across it, summed up per vertex
+        let mut tangents = vec![Vec3::ZERO; self.positions.len()];
+        let mut bitangents = vec![Vec3::ZERO; self.positions.len()];
+        for [a, b, c] in self.triangles() {
+            let [pa, pb, pc] = [a, b, c].map(|i| Vec3::from(self.positions[i]));
+            let [ua, ub, uc] = [a, b, c].map(|i| Vec2::from(self.tex_coords[i]));
+            let (edge1, edge2) = (pb - pa, pc - pa);
+            let (duv1, duv2) = (ub - ua, uc - ua);
+            let det = duv1.x * duv2.y - duv2.x * duv1.y;
+            if det.abs() <= f32::EPSILON {
+                continue;
+            }
+            let tangent = (edge1 * duv2.y - edge2 * duv1.y) / det;
+            let bitangent = (edge2 * duv1.x - edge1 * duv2.x) / det;
+            for i in [a, b, c] {
+                tangents[i] += tangent;
+                bitangents[i] += bitangent;
+            }
+        }
+
+        self.tangents = normals
+            .iter()
+            .zip(tangents.iter().zip(&bitangents))
+            .map(|(&normal, (&tangent, &bitangent))| {
+                // Made perpendicular to the normal (Gram-Schmidt)
+                let tangent = (tangent - normal * normal.dot(tangent))
+                    .try_normalize()
+                    .unwrap_or_else(|| normal.any_orthonormal_vector());
+                let handedness = if normal.cross(tangent).dot(bitangent) < 0.0 { -1.0 } else { 1.0 };
+                tangent.extend(handedness).into()
+            })
+            .collect();
+    }
+
+    // Fills in normals and tangents if the mesh came without them
+    pub fn generate_missing(&mut self) {
+        if self.normals.len() != self.positions.len() {
+            self.recompute_normals();
+        }
+        if self.tangents.len() != self.positions.len() {
+            self.recompute_tangents();
+        }
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        Aabb::from_points(self.positions.iter().map(|p| Vec3::from(*p)))
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        BoundingSphere::from_points(&self.positions.iter().map(|p| Vec3::from(*p)).collect::<Vec<_>>())
+    }
+
+    // The nearest triangle `ray` hits, with the ray in the mesh's own space
+    // (Ray::transformed() by the inverse of its transform's matrix). Tests
+    // every triangle, fine for the odd click but not thousands of rays.
+    pub fn raycast(&self, ray: &Ray) -> Option<MeshHit> {
+        let mut nearest: Option<MeshHit> = None;
+        for (triangle, [a, b, c]) in self.triangles().enumerate() {
+            let [pa, pb, pc] = [a, b, c].map(|i| Vec3::from(self.positions[i]));
+            let Some((distance, weights)) = ray.intersect_triangle(pa, pb, pc) else {
+                continue;
+            };
+            if nearest.is_some_and(|hit| hit.distance <= distance) {
+                continue;
+            }
+            let tex_coords = if self.tex_coords.is_empty() {
+                Vec2::ZERO
+            } else {
+                let [ta, tb, tc] = [a, b, c].map(|i| Vec2::from(self.tex_coords[i]));
+                ta * (1.0 - weights.x - weights.y) + tb * weights.x + tc * weights.y
+            };
+            nearest = Some(MeshHit {
+                distance,
+                triangle,
+                tex_coords,
+            });
+        }
+        nearest
+    }
+
+    pub fn vertices<V: FromMesh>(&self) -> Vec<V> {
+        (0..self.positions.len()).map(|i| V::from_mesh(self, i)).collect()
+    }
+
+    // A cube of the given size around the origin, with flat faces and each
+    // face showing the whole texture
+    pub fn cube(size: f32) -> Self {
+        #[rustfmt::skip]
+        const FACES: [([f32; 3], [f32; 3], [f32; 3]); 6] = [
+            // normal, right, up
+            ([ 1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+            ([-1.0, 0.0, 0.0], [0.0, 0.0,  1.0], [0.0, 1.0, 0.0]),
+            ([0.0,  1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0]),
+            ([0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0,  1.0]),
+            ([0.0, 0.0,  1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+            ([0.0, 0.0, -1.0], [-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+        ];
+
+        let mut mesh = Self::default();
+        for (face, (normal, right, up)) in FACES.into_iter().enumerate() {
+            let base = mesh.positions.len() as u32;
+            let (normal, right, up) = (Vec3::from(normal), Vec3::from(right), Vec3::from(up));
+            // Every face uses the whole texture, in the lightmap they get a
+            // cell each of a 3 by 2 grid
+            let cell = Vec2::new((face % 3) as f32 / 3.0, (face / 3) as f32 / 2.0);
+            for (u, v) in [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0)] {
+                let (x, y) = (u - 0.5, 0.5 - v);
+                mesh.positions.push(((normal * 0.5 + right * x + up * y) * size).into());
+                mesh.tex_coords.push([u, v]);
+                mesh.lightmap_coords.push((cell + inset(Vec2::new(u, v)) / Vec2::new(3.0, 2.0)).into());
+            }
+            mesh.indices.extend_from_slice(&[base + 2, base + 3, base + 1, base + 1, base, base + 2]);
+        }
+        // Faces don't share vertices, so the normals come out flat
+        mesh.recompute_normals();
+        mesh.recompute_tangents();
+        mesh
+    }
+
+    // A flat square on the XZ plane facing up, split into `subdivisions`
+    // squares along each side
+    pub fn plane(size: f32, subdivisions: u32) -> Self {
+        let n = subdivisions.max(1);
+        let mut mesh = Self::default();
+        for z in 0..=n {
+            for x in 0..=n {
+                let (u, v) = (x as f32 / n as f32, z as f32 / n as f32);
+                mesh.positions.push([(u - 0.5) * size, 0.0, (v - 0.5) * size]);
+                mesh.tex_coords.push([u, v]);
+            }
+        }
+        for z in 0..n {
+            for x in 0..n {
+                let a = z * (n + 1) + x;
+                let b = a + n + 1;
+                mesh.indices.extend_from_slice(&[a, b, a + 1, a + 1, b, b + 1]);
+            }
+        }
+        mesh.recompute_normals();
+        mesh.recompute_tangents();
+        mesh
+    }
+
+    // A sphere made of `sectors` slices around and `stacks` from pole to
+    // pole, with the texture wrapped around it like a globe
+    pub fn uv_sphere(radius: f32, sectors: u32, stacks: u32) -> Self {
+        let (sectors, stacks) = (sectors.max(3), stacks.max(2));
+        let mut mesh = Self::default();
+        for stack in 0..=stacks {
+            let v = stack as f32 / stacks as f32;
+            let polar = v * std::f32::consts::PI;
+            for sector in 0..=sectors {
+                let u = sector as f32 / sectors as f32;
+                let azimuth = u * std::f32::consts::TAU;
+                let direction = Vec3::new(polar.sin() * azimuth.cos(), polar.cos(), -polar.sin() * azimuth.sin());
+                mesh.positions.push((direction * radius).into());
+                // Averaging would leave a crease along the seam where u wraps
+                mesh.normals.push(direction.into());
+                mesh.tex_coords.push([u, v]);
+            }
+        }
+        for stack in 0..stacks {
+            for sector in 0..sectors {
+                let a = stack * (sectors + 1) + sector;
+                let b = a + sectors + 1;
+                // The stacks at the poles are fans, their other half would
+                // have no area
+                if stack != 0 {
+                    mesh.indices.extend_from_slice(&[a, b, a + 1]);
+                }
+                if stack != stacks - 1 {
+                    mesh.indices.extend_from_slice(&[a + 1, b, b + 1]);
+                }
+            }
+        }
+        mesh.recompute_tangents();
+        mesh
+    }
+}
+
+// Pulls texture coordinates from 0 to 1 in a little from the edges, so
+// lightmap cells next to each other don't bleed into one another when
+// they're filtered
+pub(crate) fn inset(uv: Vec2) -> Vec2 {
+    const MARGIN: f32 = 0.03;
+    uv * (1.0 - 2.0 * MARGIN) + MARGIN
+}
+
+// Vertex layouts that can be filled in from a Mesh
+pub trait FromMesh {
+    fn from_mesh(mesh: &Mesh, i: usize) -> Self;
+}
+
+impl FromMesh for VertexPosUv {
+    fn from_mesh(mesh: &Mesh, i: usize) -> Self {
+        Self {
+            position: mesh.positions[i],
+            tex_coords: mesh.tex_coords.get(i).copied().unwrap_or_default(),
+        }
+    }
+}
+
+impl FromMesh for VertexPosNormalUv {
+    fn from_mesh(mesh: &Mesh, i: usize) -> Self {
+        Self {
+            position: mesh.positions[i],
+            normal: mesh.normals.get(i).copied().unwrap_or_default(),
+            tex_coords: mesh.tex_coords.get(i).copied().unwrap_or_default(),
+        }
+    }
+}
+
+impl FromMesh for VertexPosNormalTangentUvColor {
+    fn from_mesh(mesh: &Mesh, i: usize) -> Self {
+        Self {
+            position: mesh.positions[i],
+            normal: mesh.normals.get(i).copied().unwrap_or_default(),
+            tangent: mesh.tangents.get(i).copied().unwrap_or([1.0, 0.0, 0.0, 1.0]),
+            tex_coords: mesh.tex_coords.get(i).copied().unwrap_or_default(),
+            color: mesh.colors.get(i).copied().unwrap_or([1.0; 4]),
+        }
+    }
+}
+
+impl FromMesh for VertexLightmapUv {
+    fn from_mesh(mesh: &Mesh, i: usize) -> Self {
+        Self {
+            lightmap_coords: mesh.lightmap_coord(i).into(),
+        }
+    }
+}
@@ -0,0 +1,368 @@
+use glam::{Mat4, Vec3};
+use wgpu::util::DeviceExt;
+
+use crate::draw::{DrawCommand, DrawResources, MeshBuffers};
+use crate::render_graph::{GraphResources, RenderGraph, TransientTexture};
+use crate::scene::Mirror;
+use crate::texture::Texture;
+use crate::transform::Transform;
+use crate::upload::Uploader;
+use crate::vertex::VertexLayout;
+use crate::water::clip_to_plane;
+use crate::{CameraUniform, InstanceRaw, Vertex};
+
+// Mirrors drawn at once, the closest ones get them
+pub const MAX_MIRRORS: usize = 4;
+
+// How far in front of a mirror things start showing up in it. Leaves out the
+// mirror itself, and what's lying flat on it.
+const CLIP_MARGIN: f32 = 0.01;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct MirrorUniform {
+    reflectivity: f32,
+    _padding: [f32; 3],
+}
+
+// One mirror's worth of GPU state, reused by whichever mirror gets it
+struct Slot {
+    camera: (wgpu::Buffer, wgpu::BindGroup),
+    buffer: wgpu::Buffer,
+}
+
+// Planar reflections for scene entities tagged with a Mirror. Every frame
+// each visible mirror gets the scene drawn into a texture from the camera
+// flipped through its plane (like Water's reflection, without the waves),
+// and then its surface is drawn again over itself with that texture on it.
+//
+// The scene passes go through the render graph like any other render to
+// texture: declare() the textures, draw into them in a pass started with
+// reflection_pass() using camera(), then add_pass() for the surfaces.
+pub struct Mirrors {
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    slots: Vec<Slot>,
+    // How many slots the last prepare() filled
+    active: usize,
+}
+
+impl Mirrors {
+    // Names of the transient textures and passes in the render graph, one
+    // per slot
+    pub const TEXTURES: [&'static str; MAX_MIRRORS] = ["mirror_0", "mirror_1", "mirror_2", "mirror_3"];
+    pub const DEPTHS: [&'static str; MAX_MIRRORS] =
+        ["mirror_depth_0", "mirror_depth_1", "mirror_depth_2", "mirror_depth_3"];
+    pub const PASSES: [&'static str; MAX_MIRRORS] =
+        ["mirror_scene_0", "mirror_scene_1", "mirror_scene_2", "mirror_scene_3"];
+
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        camera_layout: &wgpu::BindGroupLayout,
+        frame_layout: &wgpu::BindGroupLayout,
+        shader_source: &str,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("mirror_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Mirror Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, frame_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let slots = (0..MAX_MIRRORS)
+            .map(|_| {
+                let camera_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("mirror_camera"),
+                    size: std::mem::size_of::<CameraUniform>() as wgpu::BufferAddress,
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                });
+                let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("mirror_camera"),
+                    layout: camera_layout,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: camera_buffer.as_entire_binding(),
+                    }],
+                });
+                let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Mirror Buffer"),
+                    contents: bytemuck::bytes_of(&MirrorUniform {
+                        reflectivity: 1.0,
+                        _padding: [0.0; 3],
+                    }),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                });
+                Slot {
+                    camera: (camera_buffer, camera_bind_group),
+                    buffer,
+                }
+            })
+            .collect();
+
+        Self {
+            pipeline: Self::create_pipeline(device, &layout, color_format, shader_source),
+            layout,
+            color_format,
+            bind_group_layout,
+            sampler,
+            slots,
+            active: 0,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        shader_source: &str,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Mirror Shader"),
+            source: wgpu::ShaderSource::Wgsl(shader_source.into()),
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Mirror Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[Vertex::desc(), InstanceRaw::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    // Over the surface's own colour by its reflectivity
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::COLOR,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            // Only where the surface itself ended up in front
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+
+    // Called when the shader file changed, keeps the old pipeline if the new
+    // source doesn't compile
+    pub fn reload_shader(&mut self, device: &wgpu::Device, shader_source: &str) {
+        if let Some(pipeline) = crate::shaders::try_create(device, || {
+            Self::create_pipeline(device, &self.layout, self.color_format, shader_source)
+        }) {
+            self.pipeline = pipeline;
+        }
+    }
+
+    // Works out the mirrored cameras for up to MAX_MIRRORS mirrors, each at
+    // the world transform it's drawn with. Slot i goes to mirrors[i], the
+    // rest are left out. Returns how many slots are in use.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        uploader: &mut Uploader,
+        eye: Vec3,
+        view_proj: Mat4,
+        mirrors: &[(Transform, Mirror)],
+    ) -> usize {
+        self.active = mirrors.len().min(MAX_MIRRORS);
+        for (slot, (transform, mirror)) in self.slots.iter().zip(mirrors) {
+            let (reflected, reflected_eye) = reflected_camera(view_proj, eye, transform);
+            let camera = CameraUniform {
+                view_proj: reflected.to_cols_array_2d(),
+                eye: reflected_eye.extend(1.0).to_array(),
+            };
+            uploader.write(device, encoder, &slot.camera.0, 0, &[camera]);
+            let uniform = MirrorUniform {
+                reflectivity: mirror.reflectivity.clamp(0.0, 1.0),
+                _padding: [0.0; 3],
+            };
+            uploader.write(device, encoder, &slot.buffer, 0, &[uniform]);
+        }
+        self.active
+    }
+
+    // In place of the main camera for drawing the scene into slot `slot`'s
+    // texture
+    pub fn camera(&self, slot: usize) -> &wgpu::BindGroup {
+        &self.slots[slot].camera.1
+    }
+
+    // Adds the textures of the slots in use to the graph, as big as the
+    // scene since they get sampled pixel for pixel
+    pub fn declare(&self, graph: &mut RenderGraph, color_format: wgpu::TextureFormat) {
+        for slot in 0..self.active {
+            graph.create_texture(Self::TEXTURES[slot], TransientTexture::new(color_format));
+            graph.create_texture(Self::DEPTHS[slot], TransientTexture::new(Texture::DEPTH_FORMAT));
+        }
+    }
+
+    // Starts a pass that clears slot `slot`'s texture to `clear` for the
+    // scene to be drawn into, the graph pass it's in has to write
+    // TEXTURES[slot] and DEPTHS[slot]
+    pub fn reflection_pass<'p>(
+        encoder: &'p mut wgpu::CommandEncoder,
+        resources: &'p GraphResources,
+        slot: usize,
+        clear: wgpu::Color,
+    ) -> wgpu::RenderPass<'p> {
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(Self::PASSES[slot]),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: resources.view(Self::TEXTURES[slot]),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(clear),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: resources.view(Self::DEPTHS[slot]),
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        })
+    }
+
+    // The mirrors' surfaces, drawn into `scene_target` over what the main
+    // pass drew of them. `surfaces` are draws of each mirror's mesh with the
+    // slot as the material, out of `meshes` like the scene's draws.
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_pass<'g>(
+        &'g self,
+        graph: &mut RenderGraph<'g>,
+        device: &'g wgpu::Device,
+        scene_target: &'static str,
+        camera: &'g wgpu::BindGroup,
+        frame: &'g wgpu::BindGroup,
+        surfaces: Vec<DrawCommand>,
+        meshes: Vec<MeshBuffers<'g>>,
+    ) {
+        graph.add_pass("mirrors").reads(&Self::TEXTURES[..self.active]).writes(&[scene_target, "depth"]).execute(
+            move |encoder, resources| {
+                let bind_groups = (0..self.active)
+                    .map(|slot| {
+                        device.create_bind_group(&wgpu::BindGroupDescriptor {
+                            label: Some("mirror_bind_group"),
+                            layout: &self.bind_group_layout,
+                            entries: &[
+                                wgpu::BindGroupEntry {
+                                    binding: 0,
+                                    resource: self.slots[slot].buffer.as_entire_binding(),
+                                },
+                                wgpu::BindGroupEntry {
+                                    binding: 1,
+                                    resource: wgpu::BindingResource::TextureView(resources.view(Self::TEXTURES[slot])),
+                                },
+                                wgpu::BindGroupEntry {
+                                    binding: 2,
+                                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                                },
+                            ],
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Mirror Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: resources.view(scene_target),
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: resources.view("depth"),
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        }),
+                        stencil_ops: None,
+                    }),
+                });
+                let draw_resources = DrawResources {
+                    globals: vec![(0, camera), (1, frame)],
+                    pipelines: vec![&self.pipeline],
+                    materials: bind_groups.iter().collect(),
+                    meshes,
+                };
+                crate::draw::draw_commands(&surfaces, &mut render_pass, 2, &draw_resources);
+            },
+        );
+    }
+}
+
+// `view_proj` seen in a mirror at `transform`: the world flipped through the
+// mirror's plane, and everything on the far side of it (what's behind the
+// mirror) clipped away. Mirrors work from both sides. Also returns where the
+// eye ends up, behind the mirror.
+fn reflected_camera(view_proj: Mat4, eye: Vec3, transform: &Transform) -> (Mat4, Vec3) {
+    let mut normal = (transform.rotation * Vec3::Z).normalize();
+    if normal.dot(eye - transform.translation) < 0.0 {
+        normal = -normal;
+    }
+    let d = -normal.dot(transform.translation);
+    // p - 2 (n · p + d) n
+    let reflect = |axis: Vec3| axis - 2.0 * normal.dot(axis) * normal;
+    let mirror = Mat4::from_cols(
+        reflect(Vec3::X).extend(0.0),
+        reflect(Vec3::Y).extend(0.0),
+        reflect(Vec3::Z).extend(0.0),
+        (-2.0 * d * normal).extend(1.0),
+    );
+    (clip_to_plane(view_proj * mirror, normal.extend(d - CLIP_MARGIN)), mirror.transform_point3(eye))
+}
@@ -0,0 +1,66 @@
+use glam::Vec2;
+
+// Procedural noise for things that should look random without repeating,
+// like terrain heights, where grass grows and camera shake. Everything here
+// is the same for the same input and seed, so it can be evaluated wherever
+// it's needed without keeping state.
+
+// Between 0 and 1, the same for the same lattice point, salt and seed. The
+// salt picks one of several unrelated numbers for the same point, e.g. an x
+// and a y offset.
+pub fn hash(x: i32, z: i32, salt: u32, seed: u32) -> f32 {
+    let mut h = (x as u32).wrapping_mul(0x27d4_eb2d)
+        ^ (z as u32).wrapping_mul(0x1656_67b1)
+        ^ salt.wrapping_mul(0x85eb_ca6b)
+        ^ seed.wrapping_mul(0x9e37_79b9);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x2c1b_3c6d);
+    h ^= h >> 12;
+    h = h.wrapping_mul(0x297a_2d39);
+    h ^= h >> 15;
+    (h & 0xffff) as f32 / 65535.0
+}
+
+// Between 0 and 1, blended smoothly between random values on every whole
+// number corner
+pub fn value_noise(point: Vec2, seed: u32) -> f32 {
+    let cell = point.floor();
+    let t = point - cell;
+    // Smoothstep so the cells don't show as creases
+    let t = t * t * (Vec2::splat(3.0) - 2.0 * t);
+    let (x, z) = (cell.x as i32, cell.y as i32);
+    let corner = |x, z| hash(x, z, 0, seed);
+    let top = corner(x, z) + (corner(x + 1, z) - corner(x, z)) * t.x;
+    let bottom = corner(x, z + 1) + (corner(x + 1, z + 1) - corner(x, z + 1)) * t.x;
+    top + (bottom - top) * t.y
+}
+
+// Several octaves of value noise, each twice as detailed and half as tall
+pub fn fbm(point: Vec2, seed: u32) -> f32 {
+    let mut sum = 0.0;
+    let mut amplitude = 0.5;
+    let mut point = point;
+    for octave in 0..6 {
+        sum += value_noise(point, seed.wrapping_add(octave)) * amplitude;
+        point *= 2.0;
+        amplitude *= 0.5;
+    }
+    sum
+}
+
+// One dimensional Perlin noise, roughly between -1 and 1. It's 0 on every
+// whole number and curves smoothly between them, with a new random slope at
+// each, so it never sits still for long or jumps.
+pub fn perlin(x: f32, seed: u32) -> f32 {
+    let cell = x.floor();
+    let t = x - cell;
+    let i = cell as i32;
+    // A slope between -1 and 1 at each end, carried over to t
+    let gradient = |i| hash(i, 0, 0, seed) * 2.0 - 1.0;
+    let a = gradient(i) * t;
+    let b = gradient(i + 1) * (t - 1.0);
+    // Perlin's quintic fade, smooth down to the second derivative
+    let fade = t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+    // A single octave only reaches about half way, scaled back up to 1
+    (a + (b - a) * fade) * 2.0
+}
@@ -0,0 +1,220 @@
+use glam::{Mat4, Vec3};
+
+use crate::upload::Uploader;
+
+// How outlines look, see RenderSettings::outline in app.rs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutlineSettings {
+    // In pixels, how far apart the samples either side of an edge are
+    pub thickness: f32,
+    // The alpha is how strongly it covers the scene
+    pub color: [f32; 4],
+    // How much further away, as a fraction of the distance, the other side
+    // of an edge has to be. Smaller finds more edges, down to objects
+    // resting on each other.
+    pub depth_threshold: f32,
+    // How far apart the surfaces' normals have to be, from 0 (any angle at
+    // all) to 2 (facing opposite ways). Finds creases where the depth
+    // doesn't jump, like the edges of a cube.
+    pub normal_threshold: f32,
+}
+
+impl Default for OutlineSettings {
+    fn default() -> Self {
+        Self {
+            thickness: 1.0,
+            color: [0.0, 0.0, 0.0, 1.0],
+            depth_threshold: 0.05,
+            normal_threshold: 0.4,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct OutlineParams {
+    inverse_view_proj: [[f32; 4]; 4],
+    color: [f32; 4],
+    eye: [f32; 3],
+    thickness: f32,
+    depth_threshold: f32,
+    normal_threshold: f32,
+    _padding: [f32; 2],
+}
+
+// Draws lines along the edges in the scene, found from the depth buffer
+// alone (see shaders/outline.wgsl). Where the depth jumps there's an
+// object's silhouette, and where the surface turns sharply there's a crease,
+// so it works on everything on screen at once without drawing anything
+// twice, unlike outlining single objects with the stencil buffer. Normals
+// are worked out from the depth too, there's no normal buffer to read.
+pub struct Outline {
+    layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::RenderPipeline,
+    hdr_format: wgpu::TextureFormat,
+    params: wgpu::Buffer,
+}
+
+impl Outline {
+    pub fn new(device: &wgpu::Device, hdr_format: wgpu::TextureFormat, shader_source: &str) -> Self {
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("outline_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // The scene
+                texture_entry(1),
+                // Its depth buffer
+                texture_entry(2),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Outline Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let params = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Outline Params"),
+            size: std::mem::size_of::<OutlineParams>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            pipeline: Self::create_pipeline(device, &pipeline_layout, hdr_format, shader_source),
+            layout,
+            pipeline_layout,
+            hdr_format,
+            params,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        hdr_format: wgpu::TextureFormat,
+        shader_source: &str,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Outline Shader"),
+            source: wgpu::ShaderSource::Wgsl(shader_source.into()),
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Outline Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: hdr_format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+
+    // Keeps the old pipeline if the new source doesn't compile
+    pub fn reload_shader(&mut self, device: &wgpu::Device, shader_source: &str) {
+        if let Some(pipeline) = crate::shaders::try_create(device, || {
+            Self::create_pipeline(device, &self.pipeline_layout, self.hdr_format, shader_source)
+        }) {
+            self.pipeline = pipeline;
+        }
+    }
+
+    // Once a frame before run(), with the camera the scene is drawn from
+    pub fn upload(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        uploader: &mut Uploader,
+        settings: &OutlineSettings,
+        eye: Vec3,
+        view_proj: Mat4,
+    ) {
+        let params = OutlineParams {
+            inverse_view_proj: view_proj.inverse().to_cols_array_2d(),
+            color: settings.color,
+            eye: eye.into(),
+            thickness: settings.thickness.max(0.0),
+            depth_threshold: settings.depth_threshold.max(0.0),
+            normal_threshold: settings.normal_threshold.max(0.0),
+            _padding: [0.0; 2],
+        };
+        uploader.write(device, encoder, &self.params, 0, &[params]);
+    }
+
+    // Draws `scene` with its edges outlined into `target`, all three the
+    // same size
+    pub fn run(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        scene: &wgpu::TextureView,
+        depth: &wgpu::TextureView,
+        target: &wgpu::TextureView,
+    ) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("outline_bind_group"),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.params.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(scene),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(depth),
+                },
+            ],
+        });
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Outline Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,318 @@
+use glam::Vec2;
+
+use crate::render_graph::RenderGraph;
+use crate::texture::Texture;
+use crate::upload::AppUploader;
+use crate::vertex::VertexLayout;
+
+const SOURCE: &str = include_str!("shaders/paint.wgsl");
+
+// What a brush does to the texture
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum BrushMode {
+    // Lays its colour down over what's there, by its alpha
+    #[default]
+    Paint,
+    // Takes the texture back towards transparent black, by the brush's
+    // strength alone
+    Erase,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Brush {
+    pub color: [f32; 4],
+    // In uv, 0 to 1 across the texture, so it stays round on whatever the
+    // texture is stretched over
+    pub radius: f32,
+    // From 0 (fades out all the way from the middle) to 1 (a hard edge)
+    pub hardness: f32,
+    // How much each dab covers, strokes build up where dabs overlap
+    pub opacity: f32,
+    // How far apart the dabs along a stroke are, as a fraction of the radius
+    pub spacing: f32,
+    pub mode: BrushMode,
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Self {
+            color: [1.0; 4],
+            radius: 0.02,
+            hardness: 0.5,
+            opacity: 0.5,
+            spacing: 0.25,
+            mode: BrushMode::Paint,
+        }
+    }
+}
+
+impl Brush {
+    pub fn new(color: [f32; 4], radius: f32) -> Self {
+        Self {
+            color,
+            radius,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_hardness(mut self, hardness: f32) -> Self {
+        self.hardness = hardness;
+        self
+    }
+
+    pub fn with_opacity(mut self, opacity: f32) -> Self {
+        self.opacity = opacity;
+        self
+    }
+
+    pub fn with_spacing(mut self, spacing: f32) -> Self {
+        self.spacing = spacing;
+        self
+    }
+
+    pub fn with_mode(mut self, mode: BrushMode) -> Self {
+        self.mode = mode;
+        self
+    }
+}
+
+crate::vertex_layout! {
+    step_mode: Instance,
+    #[derive(Debug, PartialEq)]
+    struct Dab {
+        #[location(0)] center: [f32; 2],
+        #[location(1)] radius: f32,
+        #[location(2)] hardness: f32,
+        #[location(3)] color: [f32; 4],
+    }
+}
+
+// A texture that gets painted into while running, for splat maps, decals
+// and drawing programs. Strokes are collected over a frame as dabs, round
+// stamps of the brush, and drawn into the texture all at once by a small
+// render pass of their own, blended over what was painted before. Nothing
+// goes back to the CPU, so it's as cheap as drawing a few quads.
+//
+// Every frame: dab() and stroke() as the mouse moves, prepare() them, then
+// add_pass() and read the texture under the same name in passes that draw
+// with it, so they wait for the paint to go down.
+pub struct Canvas {
+    texture: Texture,
+    size: (u32, u32),
+    format: wgpu::TextureFormat,
+    layout: wgpu::PipelineLayout,
+    paint_pipeline: wgpu::RenderPipeline,
+    erase_pipeline: wgpu::RenderPipeline,
+    instance_buffer: wgpu::Buffer,
+    // In dabs
+    capacity: usize,
+    // Queued since the last prepare(), in order, each with whether it
+    // erases
+    dabs: Vec<(Dab, BrushMode)>,
+    clear: Option<[f32; 4]>,
+    // What prepare() uploaded for this frame's pass: runs of dabs in the
+    // instance buffer that share a mode, and the colour to clear to first
+    runs: Vec<(BrushMode, std::ops::Range<u32>)>,
+    clearing: Option<[f32; 4]>,
+}
+
+impl Canvas {
+    // Starts out filled with `clear`
+    pub fn new(device: &wgpu::Device, size: (u32, u32), format: wgpu::TextureFormat, clear: [f32; 4]) -> Self {
+        let size = (size.0.max(1), size.1.max(1));
+        let texture = Texture::create_render_target(device, size, format, "Canvas Texture");
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Canvas Pipeline Layout"),
+            bind_group_layouts: &[],
+            push_constant_ranges: &[],
+        });
+        let (paint_pipeline, erase_pipeline) = Self::create_pipelines(device, &layout, format, SOURCE);
+
+        let capacity = 64;
+        Self {
+            texture,
+            size,
+            format,
+            layout,
+            paint_pipeline,
+            erase_pipeline,
+            instance_buffer: Self::create_instance_buffer(device, capacity),
+            capacity,
+            dabs: Vec::new(),
+            clear: Some(clear),
+            runs: Vec::new(),
+            clearing: None,
+        }
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        format: wgpu::TextureFormat,
+        shader_source: &str,
+    ) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Paint Shader"),
+            source: wgpu::ShaderSource::Wgsl(shader_source.into()),
+        });
+        let create = |label, blend| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[Dab::desc()],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: Some(blend),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        // Erasing scales everything down by the dab's alpha, colour included
+        let fade = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::Zero,
+            dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+            operation: wgpu::BlendOperation::Add,
+        };
+        let erase = wgpu::BlendState {
+            color: fade,
+            alpha: fade,
+        };
+        (create("Paint Pipeline", wgpu::BlendState::ALPHA_BLENDING), create("Erase Pipeline", erase))
+    }
+
+    // For apps watching their own copy of paint.wgsl, keeps the old
+    // pipelines if the new source doesn't compile
+    pub fn reload_shader(&mut self, device: &wgpu::Device, shader_source: &str) {
+        if let Some((paint, erase)) = crate::shaders::try_create(device, || {
+            Self::create_pipelines(device, &self.layout, self.format, shader_source)
+        }) {
+            self.paint_pipeline = paint;
+            self.erase_pipeline = erase;
+        }
+    }
+
+    fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Canvas Dab Buffer"),
+            size: (capacity * std::mem::size_of::<Dab>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    // What it's painted into, to bind wherever it's drawn with
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    // Stamps the brush once at `uv`
+    pub fn dab(&mut self, uv: Vec2, brush: &Brush) {
+        let color = match brush.mode {
+            BrushMode::Paint => brush.color,
+            // Only the alpha matters to the erase pipeline's blending
+            BrushMode::Erase => [0.0, 0.0, 0.0, 1.0],
+        };
+        let dab = Dab {
+            center: uv.into(),
+            radius: brush.radius.max(0.0),
+            hardness: brush.hardness.clamp(0.0, 1.0),
+            color: [color[0], color[1], color[2], color[3] * brush.opacity.clamp(0.0, 1.0)],
+        };
+        self.dabs.push((dab, brush.mode));
+    }
+
+    // Dabs from `from` to `to`, leaving out `from` itself so a stroke made
+    // of one call per mouse movement doesn't stamp twice where they meet.
+    // Start it with dab().
+    pub fn stroke(&mut self, from: Vec2, to: Vec2, brush: &Brush) {
+        let step = (brush.radius * brush.spacing).max(1e-4);
+        let count = (from.distance(to) / step).ceil().min(4096.0) as u32;
+        for i in 1..=count {
+            self.dab(from.lerp(to, i as f32 / count as f32), brush);
+        }
+    }
+
+    // Fills the whole texture with `color`, before anything painted since
+    pub fn clear(&mut self, color: [f32; 4]) {
+        self.clear = Some(color);
+        self.dabs.clear();
+    }
+
+    // Uploads what was painted since the last frame, call once a frame
+    // before add_pass()
+    pub fn prepare(&mut self, device: &wgpu::Device, uploader: &AppUploader) {
+        self.clearing = self.clear.take();
+        self.runs.clear();
+        if self.dabs.is_empty() {
+            return;
+        }
+        if self.dabs.len() > self.capacity {
+            self.capacity = self.dabs.len().next_power_of_two();
+            self.instance_buffer = Self::create_instance_buffer(device, self.capacity);
+        }
+        let mut instances = Vec::with_capacity(self.dabs.len());
+        for (i, (dab, mode)) in self.dabs.drain(..).enumerate() {
+            let i = i as u32;
+            match self.runs.last_mut() {
+                Some((run_mode, run)) if *run_mode == mode => run.end = i + 1,
+                _ => self.runs.push((mode, i..i + 1)),
+            }
+            instances.push(dab);
+        }
+        uploader.write(&self.instance_buffer, 0, &instances);
+    }
+
+    // Puts the texture in the graph as `name`, along with a pass painting
+    // into it when prepare() had anything to paint. Passes reading `name`
+    // see the paint.
+    pub fn add_pass<'g>(&'g self, graph: &mut RenderGraph<'g>, name: &'static str) {
+        graph.import(name, &self.texture.view);
+        if self.runs.is_empty() && self.clearing.is_none() {
+            return;
+        }
+        graph.add_pass("paint").writes(&[name]).execute(move |encoder, resources| {
+            let load = match self.clearing {
+                Some([r, g, b, a]) => wgpu::LoadOp::Clear(wgpu::Color {
+                    r: r as f64,
+                    g: g as f64,
+                    b: b as f64,
+                    a: a as f64,
+                }),
+                None => wgpu::LoadOp::Load,
+            };
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Paint Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: resources.view(name),
+                    resolve_target: None,
+                    ops: wgpu::Operations { load, store: true },
+                })],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+            for (mode, dabs) in &self.runs {
+                render_pass.set_pipeline(match mode {
+                    BrushMode::Paint => &self.paint_pipeline,
+                    BrushMode::Erase => &self.erase_pipeline,
+                });
+                render_pass.draw(0..6, dabs.clone());
+            }
+        });
+    }
+}
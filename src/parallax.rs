@@ -0,0 +1,229 @@
+use std::rc::Rc;
+
+use wgpu::util::DeviceExt;
+
+use crate::bind_group_cache::{BindGroupCache, CachedBinding, ResourceId};
+use crate::scene::ParallaxSettings;
+use crate::sky::{Lighting, LightingUniform};
+use crate::texture::Texture;
+use crate::upload::Uploader;
+
+// Where the parallax pipeline goes in the scene passes' DrawResources, after
+// the plain mesh pipeline
+pub const PIPELINE: u32 = 1;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ParallaxUniform {
+    depth: f32,
+    min_steps: f32,
+    max_steps: f32,
+    self_shadowing: u32,
+}
+
+impl From<ParallaxSettings> for ParallaxUniform {
+    fn from(settings: ParallaxSettings) -> Self {
+        let min_steps = settings.min_steps.max(1);
+        Self {
+            depth: settings.depth.max(0.0),
+            min_steps: min_steps as f32,
+            max_steps: settings.max_steps.max(min_steps) as f32,
+            self_shadowing: settings.self_shadowing as u32,
+        }
+    }
+}
+
+// Parallax occlusion mapping for materials with a height map. The surface
+// stays flat, but every pixel walks the view ray down through the height
+// map until it hits it and shows the texture from there, so bricks and
+// cobbles look like they stick out without any extra geometry. With
+// self-shadowing on, a second walk from there towards the sun finds out
+// whether a bump is in the way.
+//
+// Meshes don't carry tangents, the shader works out which way the texture
+// runs across the surface from screen space derivatives of its position and
+// texture coordinates instead.
+//
+// Parallax materials are drawn with their own pipeline (PIPELINE in the
+// draw commands) and material bind group, and need light() bound to group 3
+// next to the camera and frame. Toon materials (see toon.rs) light
+// themselves from the same group.
+pub struct Parallax {
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    material_layout: wgpu::BindGroupLayout,
+    material_layout_id: ResourceId,
+    light_layout: wgpu::BindGroupLayout,
+    light: (wgpu::Buffer, wgpu::BindGroup),
+}
+
+impl Parallax {
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        camera_layout: &wgpu::BindGroupLayout,
+        frame_layout: &wgpu::BindGroupLayout,
+        bind_group_cache: &mut BindGroupCache,
+        shader_source: &str,
+        // Extra attribute streams from the mesh pool, like the mesh pipeline
+        streams: &[wgpu::VertexBufferLayout],
+    ) -> Self {
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let uniform_entry = |binding, visibility| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let material_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("parallax_material_bind_group_layout"),
+            entries: &[
+                texture_entry(0),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                texture_entry(2),
+                uniform_entry(3, wgpu::ShaderStages::FRAGMENT),
+            ],
+        });
+        let light_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("parallax_light_bind_group_layout"),
+            entries: &[uniform_entry(0, wgpu::ShaderStages::FRAGMENT)],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Parallax Pipeline Layout"),
+            bind_group_layouts: &[&material_layout, camera_layout, frame_layout, &light_layout],
+            push_constant_ranges: &[],
+        });
+
+        let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Parallax Light Buffer"),
+            contents: bytemuck::bytes_of(&LightingUniform::from(Lighting::default())),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("parallax_light_bind_group"),
+            layout: &light_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: light_buffer.as_entire_binding(),
+            }],
+        });
+
+        Self {
+            pipeline: crate::create_render_pipeline(device, &layout, color_format, shader_source, streams),
+            layout,
+            color_format,
+            material_layout,
+            material_layout_id: bind_group_cache.register(),
+            light_layout,
+            light: (light_buffer, light_bind_group),
+        }
+    }
+
+    // Called when the shader file changed or the mesh pool got new streams,
+    // keeps the old pipeline if the new source doesn't compile
+    pub fn reload_shader(&mut self, device: &wgpu::Device, shader_source: &str, streams: &[wgpu::VertexBufferLayout]) {
+        if let Some(pipeline) = crate::shaders::try_create(device, || {
+            crate::create_render_pipeline(device, &self.layout, self.color_format, shader_source, streams)
+        }) {
+            self.pipeline = pipeline;
+        }
+    }
+
+    // The settings of one material, for material_bind_group(). They can't
+    // change, a material with other settings is a different material.
+    pub fn create_material_buffer(device: &wgpu::Device, settings: &ParallaxSettings) -> wgpu::Buffer {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Parallax Material Buffer"),
+            contents: bytemuck::bytes_of(&ParallaxUniform::from(*settings)),
+            usage: wgpu::BufferUsages::UNIFORM,
+        })
+    }
+
+    // Group 0 for a parallax material. `id` stands in for all of it, so it
+    // has to be recreated when either texture is.
+    pub fn material_bind_group(
+        &self,
+        device: &wgpu::Device,
+        bind_group_cache: &mut BindGroupCache,
+        id: ResourceId,
+        texture: &Texture,
+        height: &Texture,
+        buffer: &wgpu::Buffer,
+    ) -> Rc<wgpu::BindGroup> {
+        bind_group_cache.get_or_create(
+            device,
+            Some("parallax_material_bind_group"),
+            self.material_layout_id,
+            &self.material_layout,
+            &[
+                CachedBinding {
+                    binding: 0,
+                    id,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                CachedBinding {
+                    binding: 1,
+                    id,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+                CachedBinding {
+                    binding: 2,
+                    id,
+                    resource: wgpu::BindingResource::TextureView(&height.view),
+                },
+                CachedBinding {
+                    binding: 3,
+                    id,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+        )
+    }
+
+    // The sun in `lighting` casts the self-shadows
+    pub fn upload(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        uploader: &mut Uploader,
+        lighting: Lighting,
+    ) {
+        let lighting = Lighting {
+            sun_direction: lighting.sun_direction.normalize_or_zero(),
+            ..lighting
+        };
+        uploader.write(device, encoder, &self.light.0, 0, &[LightingUniform::from(lighting)]);
+    }
+
+    pub fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+
+    pub fn light(&self) -> &wgpu::BindGroup {
+        &self.light.1
+    }
+
+    // For other pipelines that bind light() too
+    pub fn light_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.light_layout
+    }
+}
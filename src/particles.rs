@@ -0,0 +1,818 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+
+use anyhow::Result;
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+
+use crate::assets::{AssetId, Assets, Handle};
+use crate::bind_group_cache::{BindGroupCache, CachedBinding, ResourceId};
+use crate::texture::Texture;
+use crate::transform::Transform;
+use crate::upload::Uploader;
+use crate::vertex::VertexLayout;
+
+// A value over a particle's life, as (age, value) keys in order with the age
+// going from 0 when it's born to 1 when it dies. It's a straight line from
+// one key to the next, and flat past the first and last.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Curve(pub Vec<(f32, f32)>);
+
+impl Curve {
+    pub fn constant(value: f32) -> Self {
+        Self(vec![(0.0, value)])
+    }
+
+    pub fn linear(from: f32, to: f32) -> Self {
+        Self(vec![(0.0, from), (1.0, to)])
+    }
+
+    // 0 with no keys at all
+    pub fn sample(&self, age: f32) -> f32 {
+        sample_keys(&self.0, age, |a, b, t| a + (b - a) * t).unwrap_or(0.0)
+    }
+}
+
+// A colour over a particle's life, keyed like Curve. Alpha is the last of
+// the four, and with additive blending it dims the colour instead.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Gradient(pub Vec<(f32, [f32; 4])>);
+
+impl Gradient {
+    pub fn constant(color: [f32; 4]) -> Self {
+        Self(vec![(0.0, color)])
+    }
+
+    pub fn linear(from: [f32; 4], to: [f32; 4]) -> Self {
+        Self(vec![(0.0, from), (1.0, to)])
+    }
+
+    // White with no keys at all
+    pub fn sample(&self, age: f32) -> [f32; 4] {
+        let lerp = |a: [f32; 4], b: [f32; 4], t: f32| std::array::from_fn(|i| a[i] + (b[i] - a[i]) * t);
+        sample_keys(&self.0, age, lerp).unwrap_or([1.0; 4])
+    }
+}
+
+fn sample_keys<T: Copy>(keys: &[(f32, T)], age: f32, lerp: impl Fn(T, T, f32) -> T) -> Option<T> {
+    let first = keys.first()?;
+    match keys.partition_point(|(at, _)| *at <= age) {
+        0 => Some(first.1),
+        next if next == keys.len() => Some(keys[next - 1].1),
+        next => {
+            let (a, b) = (keys[next - 1], keys[next]);
+            Some(lerp(a.1, b.1, (age - a.0) / (b.0 - a.0)))
+        }
+    }
+}
+
+// Where particles start and which way they fly off, around the emitter
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SpawnShape {
+    // Right at the emitter, in every direction
+    Point,
+    // Anywhere inside, away from the middle
+    Sphere { radius: f32 },
+    // Anywhere inside, up along the emitter's Y axis
+    Box { size: [f32; 3] },
+    // Anywhere on a flat disc, up and out by at most `angle` degrees from
+    // the emitter's Y axis
+    Cone { angle: f32, radius: f32 },
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ParticleBlend {
+    // Smoke, dust and leaves
+    #[default]
+    Alpha,
+    // Fire, sparks and magic, brighter where they overlap
+    Additive,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SubEmitterTrigger {
+    Birth,
+    Death,
+}
+
+// Another effect set off where each particle is born or dies, like the
+// sparks a firework bursts into. It plays one cycle and is gone. Shared, so
+// setting it off doesn't copy the whole effect for every particle, and an Arc
+// since effects are loaded on the asset threads.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SubEmitter {
+    pub trigger: SubEmitterTrigger,
+    pub effect: Arc<ParticleEffect>,
+    // How much of the particle's velocity its particles start with
+    #[serde(default)]
+    pub inherit_velocity: f32,
+}
+
+// Everything about how an effect looks, kept as data so it can live in a
+// RON file, load as an asset and be changed while it plays:
+//
+//     (
+//         rate: 40.0,
+//         lifetime: (0.6, 1.2),
+//         shape: Cone(angle: 20.0, radius: 0.1),
+//         speed: (2.0, 3.0),
+//         gravity: (0.0, 1.0, 0.0),
+//         size: [(0.0, 0.1), (0.3, 0.4), (1.0, 0.0)],
+//         color: [(0.0, (4.0, 2.0, 0.5, 1.0)), (1.0, (1.0, 0.1, 0.0, 0.0))],
+//         blend: Additive,
+//     )
+//
+// Anything left out keeps its default. Ranges like lifetime are a low and a
+// high end that each particle picks a value between.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ParticleEffect {
+    // Particles a second while it's emitting
+    pub rate: f32,
+    // Particles all at once at the start of every cycle
+    pub burst: u32,
+    // Seconds one cycle of emitting lasts
+    pub duration: f32,
+    // Starts another cycle whenever one ends, until the emitter is stopped
+    pub looping: bool,
+    // No more are spawned while this many are alive
+    pub max_particles: u32,
+    // In seconds
+    pub lifetime: [f32; 2],
+    pub shape: SpawnShape,
+    pub speed: [f32; 2],
+    // Degrees a second, negative turns the other way
+    pub spin: [f32; 2],
+    pub gravity: [f32; 3],
+    // How quickly particles slow down, 0 never does
+    pub drag: f32,
+    // How wide particles are over their life, in world units
+    pub size: Curve,
+    // Values past 1 glow once bloom is on
+    pub color: Gradient,
+    pub blend: ParticleBlend,
+    // Loaded through the asset manager. A soft round dot when left out.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub texture: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sub_emitters: Vec<SubEmitter>,
+}
+
+impl Default for ParticleEffect {
+    fn default() -> Self {
+        Self {
+            rate: 20.0,
+            burst: 0,
+            duration: 1.0,
+            looping: true,
+            max_particles: 1000,
+            lifetime: [1.0, 2.0],
+            shape: SpawnShape::Point,
+            speed: [1.0, 2.0],
+            spin: [0.0, 0.0],
+            gravity: [0.0; 3],
+            drag: 0.0,
+            size: Curve::constant(0.2),
+            color: Gradient::linear([1.0; 4], [1.0, 1.0, 1.0, 0.0]),
+            blend: ParticleBlend::Alpha,
+            texture: None,
+            sub_emitters: Vec::new(),
+        }
+    }
+}
+
+impl ParticleEffect {
+    pub fn from_ron(text: &str) -> Result<Self> {
+        Ok(ron::from_str(text)?)
+    }
+
+    pub fn to_ron(&self) -> Result<String> {
+        Ok(ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?)
+    }
+
+    // Its texture and every sub-emitter's, all the way down
+    fn textures<'a>(&'a self, out: &mut Vec<&'a Path>) {
+        out.extend(self.texture.as_deref());
+        for sub in &self.sub_emitters {
+            sub.effect.textures(out);
+        }
+    }
+}
+
+// Between 0 and 1, from a seed that moves on every call (xorshift)
+struct Random(u32);
+
+impl Random {
+    fn unit(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        (self.0 >> 8) as f32 / (1 << 24) as f32
+    }
+
+    fn range(&mut self, [low, high]: [f32; 2]) -> f32 {
+        low + (high - low) * self.unit()
+    }
+
+    fn direction(&mut self) -> Vec3 {
+        let y = self.unit() * 2.0 - 1.0;
+        let angle = self.unit() * std::f32::consts::TAU;
+        let across = (1.0 - y * y).max(0.0).sqrt();
+        Vec3::new(across * angle.cos(), y, across * angle.sin())
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Particle {
+    position: Vec3,
+    velocity: Vec3,
+    age: f32,
+    lifetime: f32,
+    // In radians
+    rotation: f32,
+    spin: f32,
+}
+
+#[derive(Clone)]
+enum Source {
+    Asset(Handle<ParticleEffect>),
+    // A sub-emitter's, shared with the effect that set it off
+    Inline(Arc<ParticleEffect>),
+}
+
+impl Source {
+    // None while the asset is still loading
+    fn effect<'a>(&'a self, assets: &'a Assets) -> Option<&'a ParticleEffect> {
+        match self {
+            Self::Asset(handle) => assets.effect(*handle),
+            Self::Inline(effect) => Some(effect),
+        }
+    }
+}
+
+// A sub-emitter waiting to be started once every emitter has stepped
+struct Spawn {
+    effect: Arc<ParticleEffect>,
+    position: Vec3,
+    velocity: Vec3,
+}
+
+struct Emitter {
+    id: EmitterId,
+    source: Source,
+    transform: Transform,
+    // Added to the velocity particles start with
+    velocity: Vec3,
+    // Seconds into the current cycle
+    time: f32,
+    // Fractions of a particle left over from earlier frames
+    owed: f32,
+    started: bool,
+    emitting: bool,
+    // Sub-emitters play one cycle whatever their effect says
+    once: bool,
+    particles: Vec<Particle>,
+}
+
+impl Emitter {
+    fn new(id: EmitterId, source: Source, transform: Transform) -> Self {
+        Self {
+            id,
+            source,
+            transform,
+            velocity: Vec3::ZERO,
+            time: 0.0,
+            owed: 0.0,
+            started: false,
+            emitting: true,
+            once: false,
+            particles: Vec::new(),
+        }
+    }
+
+    // Still loading, still emitting or with particles left
+    fn is_alive(&self) -> bool {
+        !self.started || self.emitting || !self.particles.is_empty()
+    }
+
+    fn step(&mut self, effect: &ParticleEffect, dt: f32, random: &mut Random, spawns: &mut Vec<Spawn>) {
+        let gravity = Vec3::from(effect.gravity);
+        let drag = (1.0 - effect.drag * dt).max(0.0);
+        let mut died = Vec::new();
+        self.particles.retain_mut(|particle| {
+            particle.age += dt;
+            if particle.age >= particle.lifetime {
+                died.push(*particle);
+                return false;
+            }
+            particle.velocity = (particle.velocity + gravity * dt) * drag;
+            particle.position += particle.velocity * dt;
+            particle.rotation += particle.spin * dt;
+            true
+        });
+        sub_emitters(effect, SubEmitterTrigger::Death, &died, spawns);
+
+        if !self.emitting {
+            return;
+        }
+        let mut count = 0;
+        if !self.started {
+            self.started = true;
+            count += effect.burst;
+        }
+        self.time += dt;
+        self.owed += effect.rate.max(0.0) * dt;
+        count += self.owed as u32;
+        self.owed = self.owed.fract();
+        if self.time >= effect.duration {
+            if self.once || !effect.looping || effect.duration <= 0.0 {
+                self.emitting = false;
+            } else {
+                self.time %= effect.duration;
+                count += effect.burst;
+            }
+        }
+
+        let room = (effect.max_particles as usize).saturating_sub(self.particles.len());
+        let born = (0..(count as usize).min(room)).map(|_| self.emit(effect, random)).collect::<Vec<_>>();
+        sub_emitters(effect, SubEmitterTrigger::Birth, &born, spawns);
+        self.particles.extend(born);
+    }
+
+    fn emit(&self, effect: &ParticleEffect, random: &mut Random) -> Particle {
+        let (offset, direction) = match effect.shape {
+            SpawnShape::Point => (Vec3::ZERO, random.direction()),
+            SpawnShape::Sphere { radius } => {
+                let direction = random.direction();
+                // The cube root spreads them evenly through the volume
+                // instead of bunching them up in the middle
+                (direction * radius * random.unit().cbrt(), direction)
+            }
+            SpawnShape::Box { size } => {
+                let offset = Vec3::new(random.unit(), random.unit(), random.unit()) - Vec3::splat(0.5);
+                (offset * Vec3::from(size), Vec3::Y)
+            }
+            SpawnShape::Cone { angle, radius } => {
+                let around = random.unit() * std::f32::consts::TAU;
+                let distance = radius * random.unit().sqrt();
+                let offset = Vec3::new(around.cos(), 0.0, around.sin()) * distance;
+                // Evenly over the cap of a sphere, not bunched at the tip
+                let y = 1.0 - random.unit() * (1.0 - angle.to_radians().cos());
+                let out = (1.0 - y * y).max(0.0).sqrt();
+                let around = random.unit() * std::f32::consts::TAU;
+                (offset, Vec3::new(out * around.cos(), y, out * around.sin()))
+            }
+        };
+        Particle {
+            position: self.transform.matrix().transform_point3(offset),
+            velocity: self.transform.rotation * direction * random.range(effect.speed) + self.velocity,
+            age: 0.0,
+            lifetime: random.range(effect.lifetime).max(0.001),
+            rotation: random.unit() * std::f32::consts::TAU,
+            spin: random.range(effect.spin).to_radians(),
+        }
+    }
+}
+
+fn sub_emitters(effect: &ParticleEffect, trigger: SubEmitterTrigger, particles: &[Particle], spawns: &mut Vec<Spawn>) {
+    for sub in effect.sub_emitters.iter().filter(|sub| sub.trigger == trigger) {
+        for particle in particles {
+            spawns.push(Spawn {
+                effect: sub.effect.clone(),
+                position: particle.position,
+                velocity: particle.velocity * sub.inherit_velocity,
+            });
+        }
+    }
+}
+
+// One particle as the shader gets it
+crate::vertex_layout! {
+    step_mode: Instance,
+    #[derive(Debug)]
+    struct ParticleInstance {
+        #[location(0)] position: [f32; 3],
+        #[location(1)] size: f32,
+        #[location(2)] color: [f32; 4],
+        #[location(3)] rotation: f32,
+    }
+}
+
+// Particles drawn the same way, with the same texture
+struct DrawBatch {
+    blend: ParticleBlend,
+    texture: Option<Handle<Texture>>,
+    instances: Vec<ParticleInstance>,
+}
+
+// One play of an effect, for moving or stopping it later
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct EmitterId(u64);
+
+struct Simulation {
+    next_id: u64,
+    emitters: Vec<Emitter>,
+    random: Random,
+    // Every texture an effect has asked for, by path
+    textures: HashMap<PathBuf, Handle<Texture>>,
+}
+
+// Plays particle effects loaded through Assets::load_effect(). Cloning it is
+// cheap and every clone plays into the same scene, so apps keep the one
+// Setup hands them:
+//
+//     self.particles = setup.particles.clone();
+//     self.fire = setup.assets.load_effect("assets/fire.ron");
+//
+// then whenever:
+//
+//     let id = self.particles.spawn(self.fire, Transform::from_translation(position));
+//
+// Effects are looked up again every frame, so edits from hot reloading or
+// the editor's effect panel show up in emitters that are already playing.
+#[derive(Clone)]
+pub struct Particles {
+    simulation: Rc<RefCell<Simulation>>,
+}
+
+impl Default for Particles {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Particles {
+    pub fn new() -> Self {
+        Self {
+            simulation: Rc::new(RefCell::new(Simulation {
+                next_id: 0,
+                emitters: Vec::new(),
+                random: Random(0x2545_f491),
+                textures: HashMap::new(),
+            })),
+        }
+    }
+
+    // Starts emitting straight away, or as soon as the effect has loaded
+    pub fn spawn(&self, effect: Handle<ParticleEffect>, transform: Transform) -> EmitterId {
+        let mut simulation = self.simulation.borrow_mut();
+        simulation.next_id += 1;
+        let id = EmitterId(simulation.next_id);
+        simulation.emitters.push(Emitter::new(id, Source::Asset(effect), transform));
+        id
+    }
+
+    // Particles already out stay where they are, new ones start from here
+    pub fn set_transform(&self, id: EmitterId, transform: Transform) {
+        if let Some(emitter) = self.simulation.borrow_mut().emitters.iter_mut().find(|emitter| emitter.id == id) {
+            emitter.transform = transform;
+        }
+    }
+
+    // Stops spawning particles and lets the ones out there live out their
+    // lives, the emitter is gone once they have
+    pub fn stop(&self, id: EmitterId) {
+        if let Some(emitter) = self.simulation.borrow_mut().emitters.iter_mut().find(|emitter| emitter.id == id) {
+            emitter.started = true;
+            emitter.emitting = false;
+        }
+    }
+
+    // Gone straight away, particles and all
+    pub fn remove(&self, id: EmitterId) {
+        self.simulation.borrow_mut().emitters.retain(|emitter| emitter.id != id);
+    }
+
+    pub fn clear(&self) {
+        self.simulation.borrow_mut().emitters.clear();
+    }
+
+    // Still emitting or with particles left. Effects that don't loop end on
+    // their own.
+    pub fn is_alive(&self, id: EmitterId) -> bool {
+        self.simulation.borrow().emitters.iter().any(|emitter| emitter.id == id)
+    }
+
+    // Particles alive across every emitter
+    pub fn count(&self) -> usize {
+        self.simulation.borrow().emitters.iter().map(|emitter| emitter.particles.len()).sum()
+    }
+
+    // Moves every particle on by `dt` seconds and spawns new ones. Called
+    // every frame after the assets are updated.
+    pub(crate) fn update(&self, dt: f32, assets: &mut Assets) {
+        let mut simulation = self.simulation.borrow_mut();
+        let Simulation {
+            next_id,
+            emitters,
+            random,
+            textures,
+        } = &mut *simulation;
+
+        // Textures get loaded before anything borrows the effects out of
+        // the assets
+        let mut paths = Vec::new();
+        for emitter in emitters.iter() {
+            if let Some(effect) = emitter.source.effect(assets) {
+                effect.textures(&mut paths);
+            }
+        }
+        let missing = paths.into_iter().filter(|path| !textures.contains_key(*path)).map(Path::to_path_buf);
+        for path in missing.collect::<Vec<_>>() {
+            textures.insert(path.clone(), assets.load_texture(path));
+        }
+
+        let mut spawns = Vec::new();
+        for emitter in emitters.iter_mut() {
+            // Cloned so the emitter isn't borrowed while it steps
+            let source = emitter.source.clone();
+            if let Some(effect) = source.effect(assets) {
+                emitter.step(effect, dt, random, &mut spawns);
+            }
+        }
+        emitters.retain(Emitter::is_alive);
+        for spawn in spawns {
+            *next_id += 1;
+            let transform = Transform::from_translation(spawn.position);
+            let mut emitter = Emitter::new(EmitterId(*next_id), Source::Inline(spawn.effect), transform);
+            emitter.velocity = spawn.velocity;
+            emitter.once = true;
+            emitters.push(emitter);
+        }
+    }
+
+    // Every particle grouped by how it's drawn, alpha blended ones first and
+    // each group sorted furthest from `eye` first
+    fn batches(&self, assets: &Assets, eye: Vec3) -> Vec<DrawBatch> {
+        let simulation = self.simulation.borrow();
+        let mut batches: Vec<DrawBatch> = Vec::new();
+        for emitter in &simulation.emitters {
+            let Some(effect) = emitter.source.effect(assets) else {
+                continue;
+            };
+            let texture = effect.texture.as_ref().and_then(|path| simulation.textures.get(path).copied());
+            let index = match batches.iter().position(|b| b.blend == effect.blend && b.texture == texture) {
+                Some(index) => index,
+                None => {
+                    batches.push(DrawBatch {
+                        blend: effect.blend,
+                        texture,
+                        instances: Vec::new(),
+                    });
+                    batches.len() - 1
+                }
+            };
+            batches[index].instances.extend(emitter.particles.iter().map(|particle| {
+                let age = particle.age / particle.lifetime;
+                ParticleInstance {
+                    position: particle.position.into(),
+                    size: effect.size.sample(age),
+                    color: effect.color.sample(age),
+                    rotation: particle.rotation,
+                }
+            }));
+        }
+        for batch in &mut batches {
+            let distance = |instance: &ParticleInstance| Vec3::from(instance.position).distance_squared(eye);
+            batch.instances.sort_by(|a, b| distance(b).total_cmp(&distance(a)));
+        }
+        batches.sort_by_key(|batch| batch.blend == ParticleBlend::Additive);
+        batches
+    }
+}
+
+// Draws the particles as quads turned to face the camera, after the scene
+// and before the lines. Particles test against the depth buffer but don't
+// write to it, so they don't cut holes in each other.
+pub struct ParticleRenderer {
+    // Alpha and additive
+    pipelines: [wgpu::RenderPipeline; 2],
+    layout: wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    bind_group_layout: wgpu::BindGroupLayout,
+    layout_id: ResourceId,
+    // For effects without a texture of their own
+    dot: Texture,
+    // Every texture drawn with so far, None for the dot
+    texture_ids: HashMap<Option<AssetId>, ResourceId>,
+    instance_buffer: wgpu::Buffer,
+    instance_capacity: usize,
+    // Blend, texture and the instances drawn with them, from the last prepare()
+    draws: Vec<(ParticleBlend, Rc<wgpu::BindGroup>, std::ops::Range<u32>)>,
+}
+
+impl ParticleRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color_format: wgpu::TextureFormat,
+        camera_layout: &wgpu::BindGroupLayout,
+        frame_layout: &wgpu::BindGroupLayout,
+        shader_source: &str,
+        cache: &mut BindGroupCache,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("particle_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Particle Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, frame_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        // White, fading out from the middle
+        let dot = image::RgbaImage::from_fn(32, 32, |x, y| {
+            let distance = glam::Vec2::new(x as f32 - 15.5, y as f32 - 15.5).length() / 16.0;
+            let alpha = (1.0 - distance).clamp(0.0, 1.0);
+            image::Rgba([255, 255, 255, (alpha * alpha * 255.0) as u8])
+        });
+        let dot = Texture::from_image(device, queue, &image::DynamicImage::ImageRgba8(dot), Some("particle_dot"))
+            .expect("Failed to create the particle texture");
+
+        Self {
+            pipelines: Self::create_pipelines(device, &layout, color_format, shader_source),
+            layout,
+            color_format,
+            bind_group_layout,
+            layout_id: cache.register(),
+            dot,
+            texture_ids: HashMap::new(),
+            instance_buffer: Self::create_instance_buffer(device, 1),
+            instance_capacity: 1,
+            draws: Vec::new(),
+        }
+    }
+
+    fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Particle Instance Buffer"),
+            size: (capacity * std::mem::size_of::<ParticleInstance>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        shader_source: &str,
+    ) -> [wgpu::RenderPipeline; 2] {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Particle Shader"),
+            source: wgpu::ShaderSource::Wgsl(shader_source.into()),
+        });
+        let additive = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::SrcAlpha,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+        [
+            wgpu::BlendState::ALPHA_BLENDING,
+            wgpu::BlendState {
+                color: additive,
+                alpha: additive,
+            },
+        ]
+        .map(|blend| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Particle Pipeline"),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[ParticleInstance::desc()],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: color_format,
+                        blend: Some(blend),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: Texture::DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        })
+    }
+
+    // Keeps the old pipelines if the new source doesn't compile
+    pub fn reload_shader(&mut self, device: &wgpu::Device, shader_source: &str) {
+        if let Some(pipelines) = crate::shaders::try_create(device, || {
+            Self::create_pipelines(device, &self.layout, self.color_format, shader_source)
+        }) {
+            self.pipelines = pipelines;
+        }
+    }
+
+    // Call when a texture gets reloaded, so its bind group is rebuilt
+    pub fn texture_changed(&mut self, id: AssetId, cache: &mut BindGroupCache) {
+        if let Some(resource) = self.texture_ids.get_mut(&Some(id)) {
+            *resource = cache.recreated(*resource);
+        }
+    }
+
+    // Uploads every particle, sorted for a camera at `eye`
+    #[allow(clippy::too_many_arguments)]
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        uploader: &mut Uploader,
+        cache: &mut BindGroupCache,
+        particles: &Particles,
+        assets: &Assets,
+        eye: Vec3,
+    ) {
+        let batches = particles.batches(assets, eye);
+        let count = batches.iter().map(|batch| batch.instances.len()).sum::<usize>();
+        if count > self.instance_capacity {
+            self.instance_capacity = count.next_power_of_two();
+            self.instance_buffer = Self::create_instance_buffer(device, self.instance_capacity);
+        }
+        self.draws.clear();
+        let mut first = 0;
+        for batch in batches.iter().filter(|batch| !batch.instances.is_empty()) {
+            let offset = (first * std::mem::size_of::<ParticleInstance>()) as wgpu::BufferAddress;
+            uploader.write(device, encoder, &self.instance_buffer, offset, &batch.instances);
+            let key = batch.texture.map(|handle| handle.id());
+            let id = *self.texture_ids.entry(key).or_insert_with(|| cache.register());
+            let texture = batch.texture.map_or(&self.dot, |handle| assets.texture(handle));
+            let bind_group = cache.get_or_create(
+                device,
+                Some("particle_bind_group"),
+                self.layout_id,
+                &self.bind_group_layout,
+                &[
+                    CachedBinding {
+                        binding: 0,
+                        id,
+                        resource: wgpu::BindingResource::TextureView(&texture.view),
+                    },
+                    CachedBinding {
+                        binding: 1,
+                        id,
+                        resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                    },
+                ],
+            );
+            let last = first + batch.instances.len();
+            self.draws.push((batch.blend, bind_group, first as u32..last as u32));
+            first = last;
+        }
+    }
+
+    pub fn has_draws(&self) -> bool {
+        !self.draws.is_empty()
+    }
+
+    // Expects the camera at group 0 and the frame at group 1
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera: &'a wgpu::BindGroup,
+        frame: &'a wgpu::BindGroup,
+    ) {
+        render_pass.set_bind_group(0, camera, &[]);
+        render_pass.set_bind_group(1, frame, &[]);
+        render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+        for (blend, bind_group, instances) in &self.draws {
+            render_pass.set_pipeline(&self.pipelines[*blend as usize]);
+            render_pass.set_bind_group(2, bind_group, &[]);
+            render_pass.draw(0..6, instances.clone());
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use glam::Vec2;
+
+// How the small picture is scaled up to fill the window
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PixelScaling {
+    // The biggest whole multiple that fits, so every pixel comes out the
+    // same size, with black bars around what's left. When the window is
+    // smaller than the picture it falls back to Fit.
+    #[default]
+    Integer,
+    // As big as fits while keeping its shape, some pixels a screen pixel
+    // wider than others
+    Fit,
+    // Fills the whole window, squashed if it's a different shape
+    Stretch,
+}
+
+// How pixel art mode looks, see RenderSettings::pixel_art in app.rs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PixelArtSettings {
+    // What the scene is drawn at, in pixels
+    pub resolution: (u32, u32),
+    pub scaling: PixelScaling,
+}
+
+impl Default for PixelArtSettings {
+    fn default() -> Self {
+        Self {
+            resolution: (320, 180),
+            scaling: PixelScaling::Integer,
+        }
+    }
+}
+
+impl PixelArtSettings {
+    // Never zero, which no texture can be
+    pub fn size(&self) -> (u32, u32) {
+        (self.resolution.0.max(1), self.resolution.1.max(1))
+    }
+
+    // Where the picture goes on a `target` sized surface, as x, y, width and
+    // height in pixels
+    pub fn rect(&self, target: (u32, u32)) -> [u32; 4] {
+        let (width, height) = self.size();
+        let scale = match self.scaling {
+            PixelScaling::Stretch => return [0, 0, target.0, target.1],
+            PixelScaling::Integer if target.0 >= width && target.1 >= height => {
+                (target.0 / width).min(target.1 / height) as f32
+            }
+            _ => (target.0 as f32 / width as f32).min(target.1 as f32 / height as f32),
+        };
+        let size = ((width as f32 * scale) as u32).max(1).min(target.0);
+        let size = (size, ((height as f32 * scale) as u32).max(1).min(target.1));
+        [(target.0 - size.0) / 2, (target.1 - size.1) / 2, size.0, size.1]
+    }
+
+    // Where `point` (in physical pixels on a `target` sized surface) is in
+    // the picture, in its pixels. Points over the black bars come out past
+    // its edges.
+    pub fn to_picture(&self, target: (u32, u32), point: Vec2) -> Vec2 {
+        let [x, y, width, height] = self.rect(target).map(|v| v as f32);
+        let (columns, rows) = self.size();
+        (point - Vec2::new(x, y)) / Vec2::new(width, height) * Vec2::new(columns as f32, rows as f32)
+    }
+}
+
+// Blows the scene, drawn small by the rest of the renderer, up onto the
+// surface with nearest neighbour sampling so its pixels stay hard edged
+// blocks instead of getting smeared into each other. The scaling only
+// decides the viewport the one triangle is drawn into.
+pub struct PixelArt {
+    layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::RenderPipeline,
+    surface_format: wgpu::TextureFormat,
+    sampler: wgpu::Sampler,
+}
+
+impl PixelArt {
+    pub fn new(device: &wgpu::Device, surface_format: wgpu::TextureFormat, shader_source: &str) -> Self {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("pixel_art_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Pixel Art Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Pixel Art Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            pipeline: Self::create_pipeline(device, &pipeline_layout, surface_format, shader_source),
+            layout,
+            pipeline_layout,
+            surface_format,
+            sampler,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        surface_format: wgpu::TextureFormat,
+        shader_source: &str,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Pixel Art Shader"),
+            source: wgpu::ShaderSource::Wgsl(shader_source.into()),
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Pixel Art Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: surface_format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+
+    // Keeps the old pipeline if the new source doesn't compile
+    pub fn reload_shader(&mut self, device: &wgpu::Device, shader_source: &str) {
+        if let Some(pipeline) = crate::shaders::try_create(device, || {
+            Self::create_pipeline(device, &self.pipeline_layout, self.surface_format, shader_source)
+        }) {
+            self.pipeline = pipeline;
+        }
+    }
+
+    // Draws `picture`, already tonemapped, into `rect` (see
+    // PixelArtSettings::rect()) of `target` and clears the rest to black
+    pub fn run(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        picture: &wgpu::TextureView,
+        target: &wgpu::TextureView,
+        rect: [u32; 4],
+    ) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("pixel_art_bind_group"),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(picture),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        });
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Pixel Art Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        let [x, y, width, height] = rect.map(|v| v as f32);
+        render_pass.set_viewport(x, y, width, height, 0.0, 1.0);
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
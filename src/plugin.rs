@@ -0,0 +1,114 @@
+use winit::event::WindowEvent;
+
+use crate::app::{App, RenderContext, RenderSettings, Setup};
+use crate::assets::Assets;
+use crate::clipboard::Clipboard;
+#[cfg(feature = "editor")]
+use crate::console::Console;
+use crate::frame::FrameUniform;
+use crate::gizmo::GizmoMode;
+use crate::render_graph::RenderGraph;
+use crate::scene::{Scene, SceneCamera};
+use crate::screen::Screen;
+use crate::window::WindowConfig;
+
+// What a plugin changed in the scene, beyond the values themselves. Lights
+// are read every frame, so they need nothing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SceneChanges {
+    // Transforms, the instances just have to move
+    pub moved: bool,
+    // Entities were spawned or their materials changed, so everything drawn
+    // from the scene has to be built again
+    pub rebuilt: bool,
+}
+
+// What plugins get to work with every frame. The engine catches up with
+// whatever pre_update() did to the scene once every plugin has had a go, as
+// long as it's been told through `scene_changes`.
+pub struct PluginContext<'a> {
+    pub device: &'a wgpu::Device,
+    pub queue: &'a wgpu::Queue,
+    pub settings: &'a mut RenderSettings,
+    pub assets: &'a mut Assets,
+    pub scene: &'a mut Scene,
+    pub scene_changes: SceneChanges,
+    // The picked entity and what the gizmo on it does. None with the ecs
+    // feature, which has neither.
+    pub selection: Option<(&'a mut Option<usize>, &'a mut GizmoMode)>,
+    pub screen: Screen,
+    pub clipboard: &'a mut Clipboard,
+    // The drop-down console. Lines typed into it are run once every plugin's
+    // pre_update() has had a go.
+    #[cfg(feature = "editor")]
+    pub console: &'a mut Console,
+    // Stops time for animations, tweens and the app from the next frame on,
+    // like the editor's pause button. The camera still moves.
+    pub paused: &'a mut bool,
+    // Where the main camera was last drawn from
+    pub camera: SceneCamera,
+    // The last frame's uniform, see frame.rs. Its `frame` counts the frames
+    // drawn so far.
+    pub frame: FrameUniform,
+    // Seconds since the last frame, 0 while paused
+    pub dt: f32,
+}
+
+// A feature packaged up so any app can opt into it, like physics or audio.
+// Plugins get the same setup as App::init() and hooks around the app's own,
+// called in the order the plugins were added.
+pub trait Plugin: 'static {
+    // Once before the first frame, before the app's init()
+    fn setup(&mut self, _setup: &mut Setup) {}
+
+    // Every frame before the engine moves things on (animations, particles,
+    // the camera) and before the app's update()
+    fn pre_update(&mut self, _context: &mut PluginContext) {}
+
+    // Passes of the plugin's own, like App::render(). They go after the
+    // app's passes and the sprites, on top of everything else.
+    fn render<'g>(&'g mut self, _graph: &mut RenderGraph<'g>, _context: RenderContext<'g>) {}
+
+    // Every frame once it's been submitted and presented. Changes to the
+    // scene here show up next frame.
+    fn post_render(&mut self, _context: &mut PluginContext) {}
+
+    // Every window event, before the engine sees it. True keeps it from the
+    // engine and the plugins after this one, like a UI that's being clicked
+    // on. Subscribers to engine events still hear about it, and the engine
+    // still resizes, rescales and closes the window whatever comes back.
+    fn on_event(&mut self, _event: &WindowEvent) -> bool {
+        false
+    }
+}
+
+// run_app() with plugins added:
+//
+//     AppBuilder::new(WindowConfig::default(), app)
+//         .add_plugin(Physics::default())
+//         .run()
+//         .await
+pub struct AppBuilder<A: App> {
+    config: WindowConfig,
+    app: A,
+    plugins: Vec<Box<dyn Plugin>>,
+}
+
+impl<A: App> AppBuilder<A> {
+    pub fn new(config: WindowConfig, app: A) -> Self {
+        Self {
+            config,
+            app,
+            plugins: Vec::new(),
+        }
+    }
+
+    pub fn add_plugin(mut self, plugin: impl Plugin) -> Self {
+        self.plugins.push(Box::new(plugin));
+        self
+    }
+
+    pub async fn run(self) {
+        crate::run_with_plugins(self.config, self.app, self.plugins).await
+    }
+}
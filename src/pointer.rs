@@ -0,0 +1,68 @@
+use glam::Vec2;
+use winit::event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent};
+
+use crate::screen::Screen;
+
+// The mouse as the overlay sees it. Presses and releases are kept until the
+// frame's overlay has run, so a click that's over between two frames still
+// gets noticed.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Pointer {
+    // Logical pixels, None while the mouse is outside the window
+    pub position: Option<Vec2>,
+    // The left button
+    pub down: bool,
+    pub pressed: bool,
+    pub released: bool,
+    // Lines scrolled this frame, positive away from the user
+    pub scroll: f32,
+    // Set by the overlay when the mouse is over something it drew. Presses
+    // and scrolling don't reach the scene while it is, so clicking a button
+    // doesn't select what's behind it.
+    pub captured: bool,
+}
+
+impl Pointer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Returns true when the overlay took the event
+    pub fn handle_event(&mut self, event: &WindowEvent, screen: &Screen) -> bool {
+        match event {
+            WindowEvent::CursorMoved { position, .. } => self.position = Some(screen.to_logical(*position)),
+            WindowEvent::CursorLeft { .. } => self.position = None,
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => {
+                let pressed = *state == ElementState::Pressed;
+                self.pressed |= pressed;
+                self.released |= !pressed;
+                self.down = pressed;
+                // Releases always go through, or a camera drag that started
+                // on the scene would never end
+                return pressed && self.captured;
+            }
+            WindowEvent::MouseInput { state, .. } => return *state == ElementState::Pressed && self.captured,
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.scroll += match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / 20.0,
+                };
+                return self.captured;
+            }
+            _ => {}
+        }
+        false
+    }
+
+    // Forgets this frame's presses, releases and scrolling, after the
+    // overlay's seen them
+    pub fn end_frame(&mut self) {
+        self.pressed = false;
+        self.released = false;
+        self.scroll = 0.0;
+    }
+}
@@ -0,0 +1,485 @@
+use glam::{Mat4, Vec3};
+use wgpu::util::DeviceExt;
+
+use crate::draw::{DrawCommand, DrawResources, MeshBuffers};
+use crate::render_graph::RenderGraph;
+use crate::scene::{Gloss, ReflectionProbe};
+use crate::texture::Texture;
+use crate::upload::Uploader;
+use crate::vertex::VertexLayout;
+use crate::{CameraUniform, InstanceRaw, Vertex};
+
+// Where each cube face looks and which way is up on it, in the order a
+// cubemap's layers go: +x, -x, +y, -y, +z, -z. Cubemaps are laid out
+// left-handed, so the faces are seen through left-handed cameras.
+const FACES: [(Vec3, Vec3); 6] = [
+    (Vec3::X, Vec3::Y),
+    (Vec3::NEG_X, Vec3::Y),
+    (Vec3::Y, Vec3::NEG_Z),
+    (Vec3::NEG_Y, Vec3::Z),
+    (Vec3::Z, Vec3::Y),
+    (Vec3::NEG_Z, Vec3::Y),
+];
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GlossUniform {
+    // The probe's, for correcting the reflection
+    position: [f32; 3],
+    reflectivity: f32,
+    extents: [f32; 3],
+    _padding: f32,
+}
+
+// One probe's cubemap, and what it takes to draw the scene into it
+struct Probe {
+    position: Vec3,
+    extents: Vec3,
+    resolution: u32,
+    // All six faces, for sampling
+    cube: wgpu::TextureView,
+    // Each face on its own, to draw into
+    faces: Vec<wgpu::TextureView>,
+    // Shared by the faces, they're drawn one after the other
+    depth: wgpu::TextureView,
+    cameras: Vec<(wgpu::Buffer, wgpu::BindGroup)>,
+}
+
+// Reflections for glossy scene entities of any shape, from reflection probes
+// placed around the scene. A probe draws the scene around it into a cubemap
+// once, when the scene is loaded (or whenever capture() asks for it again),
+// and every frame each glossy entity in view is drawn again over itself with
+// the closest probe's cubemap on it, like a mirror is with its reflection.
+//
+// Reflections off a cubemap are right for things far away. A probe with
+// extents is a box around it, usually the room it's in, and directions
+// reflected inside the box are bent to where they'd hit its walls, which
+// lines up nearby walls and floors in the reflection.
+//
+// Capturing goes through the render graph as one pass writing TEXTURE,
+// drawing each face in a pass started with face_pass() using camera().
+// Then prepare() and add_pass() for the surfaces, which reads TEXTURE.
+pub struct ReflectionProbes {
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    probes: Vec<Probe>,
+    // Whether the probes should be captured next frame, and whether they
+    // have been since they were made
+    dirty: bool,
+    captured: bool,
+    // A uniform buffer for each glossy surface, grown as needed, and the
+    // bind groups the last prepare() made with the first few
+    slots: Vec<wgpu::Buffer>,
+    bind_groups: Vec<wgpu::BindGroup>,
+}
+
+impl ReflectionProbes {
+    // Stands in for every probe's cubemap in the render graph
+    pub const TEXTURE: &'static str = "reflection_probes";
+
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        camera_layout: &wgpu::BindGroupLayout,
+        shader_source: &str,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("probe_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Probe Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            pipeline: Self::create_pipeline(device, &layout, color_format, shader_source),
+            layout,
+            color_format,
+            bind_group_layout,
+            sampler,
+            probes: Vec::new(),
+            dirty: false,
+            captured: false,
+            slots: Vec::new(),
+            bind_groups: Vec::new(),
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        shader_source: &str,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Probe Shader"),
+            source: wgpu::ShaderSource::Wgsl(shader_source.into()),
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Probe Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[Vertex::desc(), InstanceRaw::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    // Over the surface's own colour by how reflective it is
+                    // at that angle
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::COLOR,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            // Only where the surface itself ended up in front
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+
+    // Called when the shader file changed, keeps the old pipeline if the new
+    // source doesn't compile
+    pub fn reload_shader(&mut self, device: &wgpu::Device, shader_source: &str) {
+        if let Some(pipeline) = crate::shaders::try_create(device, || {
+            Self::create_pipeline(device, &self.layout, self.color_format, shader_source)
+        }) {
+            self.pipeline = pipeline;
+        }
+    }
+
+    // Replaces the probes with the scene's, captured next frame. Faces see
+    // from `znear` to `zfar` like the scene's camera does.
+    pub fn set(
+        &mut self,
+        device: &wgpu::Device,
+        camera_layout: &wgpu::BindGroupLayout,
+        probes: &[ReflectionProbe],
+        znear: f32,
+        zfar: f32,
+    ) {
+        self.probes = probes
+            .iter()
+            .map(|probe| {
+                let position = Vec3::from(probe.position);
+                let resolution = probe.resolution.clamp(1, device.limits().max_texture_dimension_2d);
+                let size = wgpu::Extent3d {
+                    width: resolution,
+                    height: resolution,
+                    depth_or_array_layers: 6,
+                };
+                let texture = device.create_texture(&wgpu::TextureDescriptor {
+                    label: Some("Reflection Probe"),
+                    size,
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: self.color_format,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                });
+                let cube = texture.create_view(&wgpu::TextureViewDescriptor {
+                    dimension: Some(wgpu::TextureViewDimension::Cube),
+                    ..Default::default()
+                });
+                let faces = (0..6)
+                    .map(|layer| {
+                        texture.create_view(&wgpu::TextureViewDescriptor {
+                            dimension: Some(wgpu::TextureViewDimension::D2),
+                            base_array_layer: layer,
+                            array_layer_count: std::num::NonZeroU32::new(1),
+                            ..Default::default()
+                        })
+                    })
+                    .collect();
+                let depth = device
+                    .create_texture(&wgpu::TextureDescriptor {
+                        label: Some("Reflection Probe Depth"),
+                        size: wgpu::Extent3d {
+                            depth_or_array_layers: 1,
+                            ..size
+                        },
+                        mip_level_count: 1,
+                        sample_count: 1,
+                        dimension: wgpu::TextureDimension::D2,
+                        format: Texture::DEPTH_FORMAT,
+                        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                    })
+                    .create_view(&wgpu::TextureViewDescriptor::default());
+
+                let projection = Mat4::perspective_lh(std::f32::consts::FRAC_PI_2, 1.0, znear, zfar);
+                let cameras = FACES
+                    .iter()
+                    .map(|&(forward, up)| {
+                        let camera = CameraUniform {
+                            view_proj: (projection * Mat4::look_at_lh(position, position + forward, up))
+                                .to_cols_array_2d(),
+                            eye: position.extend(1.0).to_array(),
+                        };
+                        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                            label: Some("probe_camera"),
+                            contents: bytemuck::bytes_of(&camera),
+                            usage: wgpu::BufferUsages::UNIFORM,
+                        });
+                        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                            label: Some("probe_camera"),
+                            layout: camera_layout,
+                            entries: &[wgpu::BindGroupEntry {
+                                binding: 0,
+                                resource: buffer.as_entire_binding(),
+                            }],
+                        });
+                        (buffer, bind_group)
+                    })
+                    .collect();
+
+                Probe {
+                    position,
+                    extents: Vec3::from(probe.extents).max(Vec3::ZERO),
+                    resolution,
+                    cube,
+                    faces,
+                    depth,
+                    cameras,
+                }
+            })
+            .collect();
+        self.dirty = true;
+        self.captured = false;
+    }
+
+    // Draws the scene into every probe again next frame, after something in
+    // it changed
+    pub fn capture(&mut self) {
+        self.dirty = true;
+    }
+
+    // Whether the probes are waiting to be captured. Clears it, the capture
+    // pass has to be added this frame.
+    pub fn take_capture(&mut self) -> bool {
+        let capture = self.dirty && !self.probes.is_empty();
+        self.dirty = false;
+        self.captured |= capture;
+        capture
+    }
+
+    // Whether there's anything to reflect yet
+    pub fn is_captured(&self) -> bool {
+        self.captured
+    }
+
+    pub fn len(&self) -> usize {
+        self.probes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.probes.is_empty()
+    }
+
+    // The probe a surface around `point` should reflect: the smallest box
+    // it's inside, or failing that the closest probe
+    pub fn nearest(&self, point: Vec3) -> Option<usize> {
+        let volume = |probe: &Probe| probe.extents.x * probe.extents.y * probe.extents.z;
+        let inside = self
+            .probes
+            .iter()
+            .enumerate()
+            .filter(|(_, probe)| volume(probe) > 0.0 && (point - probe.position).abs().cmple(probe.extents).all())
+            .min_by(|(_, a), (_, b)| volume(a).total_cmp(&volume(b)));
+        inside.map(|(index, _)| index).or_else(|| {
+            self.probes
+                .iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| a.position.distance(point).total_cmp(&b.position.distance(point)))
+                .map(|(index, _)| index)
+        })
+    }
+
+    // In place of the main camera for drawing face `face` of probe `probe`
+    pub fn camera(&self, probe: usize, face: usize) -> &wgpu::BindGroup {
+        &self.probes[probe].cameras[face].1
+    }
+
+    // Puts TEXTURE in the graph, when there are probes
+    pub fn declare<'g>(&'g self, graph: &mut RenderGraph<'g>) {
+        if let Some(probe) = self.probes.first() {
+            graph.import(Self::TEXTURE, &probe.cube);
+        }
+    }
+
+    // Starts a pass that clears face `face` of probe `probe` to `clear` for
+    // the scene to be drawn into, the graph pass it's in has to write TEXTURE
+    pub fn face_pass<'p>(
+        &'p self,
+        encoder: &'p mut wgpu::CommandEncoder,
+        probe: usize,
+        face: usize,
+        clear: wgpu::Color,
+    ) -> wgpu::RenderPass<'p> {
+        let probe = &self.probes[probe];
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Probe Capture Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &probe.faces[face],
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(clear),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &probe.depth,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: false,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        let size = probe.resolution as f32;
+        render_pass.set_viewport(0.0, 0.0, size, size, 0.0, 1.0);
+        render_pass.set_scissor_rect(0, 0, probe.resolution, probe.resolution);
+        render_pass
+    }
+
+    // Sets up the glossy surfaces in view, each with the probe it reflects.
+    // Slot i goes to surfaces[i], which add_pass()'s draws use as their
+    // material.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        uploader: &mut Uploader,
+        surfaces: &[(usize, Gloss)],
+    ) {
+        while self.slots.len() < surfaces.len() {
+            self.slots.push(device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Gloss Buffer"),
+                size: std::mem::size_of::<GlossUniform>() as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }));
+        }
+        self.bind_groups.clear();
+        for (buffer, &(probe, gloss)) in self.slots.iter().zip(surfaces) {
+            let probe = &self.probes[probe];
+            let uniform = GlossUniform {
+                position: probe.position.to_array(),
+                reflectivity: gloss.reflectivity.clamp(0.0, 1.0),
+                extents: probe.extents.to_array(),
+                _padding: 0.0,
+            };
+            uploader.write(device, encoder, buffer, 0, &[uniform]);
+            self.bind_groups.push(device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("probe_bind_group"),
+                layout: &self.bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&probe.cube),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                ],
+            }));
+        }
+    }
+
+    // The glossy surfaces, drawn into `scene_target` over what the main pass
+    // drew of them. `surfaces` are draws of each one's mesh with its slot as
+    // the material, out of `meshes` like the scene's draws.
+    pub fn add_pass<'g>(
+        &'g self,
+        graph: &mut RenderGraph<'g>,
+        scene_target: &'static str,
+        camera: &'g wgpu::BindGroup,
+        surfaces: Vec<DrawCommand>,
+        meshes: Vec<MeshBuffers<'g>>,
+    ) {
+        graph.add_pass("glossy").reads(&[Self::TEXTURE]).writes(&[scene_target, "depth"]).execute(
+            move |encoder, resources| {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Glossy Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: resources.view(scene_target),
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: resources.view("depth"),
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        }),
+                        stencil_ops: None,
+                    }),
+                });
+                let draw_resources = DrawResources {
+                    globals: vec![(0, camera)],
+                    pipelines: vec![&self.pipeline],
+                    materials: self.bind_groups.iter().collect(),
+                    meshes,
+                };
+                crate::draw::draw_commands(&surfaces, &mut render_pass, 1, &draw_resources);
+            },
+        );
+    }
+}
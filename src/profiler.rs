@@ -0,0 +1,434 @@
+use std::cell::RefCell;
+use std::marker::PhantomData;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+
+use glam::Vec2;
+
+use crate::assets::Handle;
+use crate::sprite::Sprite;
+use crate::texture::Texture;
+
+// One timed piece of a frame. Times are in milliseconds from the start of the
+// frame (CPU) or the first timed pass (GPU).
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProfileScope {
+    pub name: &'static str,
+    // How many scopes this one is nested in
+    pub depth: u32,
+    pub start: f32,
+    pub duration: f32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FrameProfile {
+    pub cpu: Vec<ProfileScope>,
+    // Render graph passes. Empty if the GPU can't do timestamp queries.
+    pub gpu: Vec<ProfileScope>,
+    // From the start of this frame to the start of the next, in milliseconds
+    pub frame_time: f32,
+}
+
+impl FrameProfile {
+    // Every scope on its own line, indented by how deeply it's nested
+    pub fn log(&self) {
+        tracing::info!("Frame took {:.2} ms", self.frame_time);
+        for scope in &self.cpu {
+            tracing::info!("{:indent$}{}: {:.3} ms", "", scope.name, scope.duration, indent = 2 + scope.depth as usize * 2);
+        }
+        if !self.gpu.is_empty() {
+            tracing::info!("GPU passes");
+            for scope in &self.gpu {
+                tracing::info!("  {}: {:.3} ms", scope.name, scope.duration);
+            }
+        }
+    }
+}
+
+struct Recorder {
+    frame_start: instant::Instant,
+    scopes: Vec<ProfileScope>,
+    // Scopes that haven't ended yet
+    open: usize,
+}
+
+thread_local! {
+    static RECORDER: RefCell<Recorder> = RefCell::new(Recorder {
+        frame_start: instant::Instant::now(),
+        scopes: Vec::new(),
+        open: 0,
+    });
+}
+
+// Times everything until it's dropped, scopes started meanwhile are nested
+// inside it. Only scopes on the thread that renders show up in the overlay.
+// With the `puffin` or `tracy` features they're sent there as well, from any
+// thread.
+//
+//     let _scope = profiler::scope("physics");
+#[track_caller]
+pub fn scope(name: &'static str) -> ScopeGuard {
+    #[cfg(feature = "puffin")]
+    let puffin = puffin::are_scopes_on().then(|| puffin_scope(name, std::panic::Location::caller()));
+    #[cfg(feature = "tracy")]
+    let tracy = tracy_client::Client::running().map(|client| {
+        let location = std::panic::Location::caller();
+        client.span_alloc(Some(name), "", location.file(), location.line(), 0)
+    });
+    let index = RECORDER.with(|recorder| {
+        let mut recorder = recorder.borrow_mut();
+        let start = recorder.frame_start.elapsed().as_secs_f32() * 1000.0;
+        let depth = recorder.open as u32;
+        recorder.open += 1;
+        recorder.scopes.push(ProfileScope {
+            name,
+            depth,
+            start,
+            duration: 0.0,
+        });
+        recorder.scopes.len() - 1
+    });
+    ScopeGuard {
+        index,
+        #[cfg(feature = "puffin")]
+        _puffin: puffin,
+        #[cfg(feature = "tracy")]
+        _tracy: tracy,
+        _not_send: PhantomData,
+    }
+}
+
+// puffin wants every scope registered once, which its macros do with a
+// static per call site. Names aren't known at compile time here, so the ids
+// are looked up by name and call site instead.
+#[cfg(feature = "puffin")]
+fn puffin_scope(name: &'static str, location: &'static std::panic::Location<'static>) -> puffin::ProfilerScope {
+    use std::collections::HashMap;
+
+    thread_local! {
+        static SCOPE_IDS: RefCell<HashMap<(&'static str, &'static str, u32), puffin::ScopeId>> = RefCell::new(HashMap::new());
+    }
+    let id = SCOPE_IDS.with(|ids| {
+        *ids.borrow_mut().entry((name, location.file(), location.line())).or_insert_with(|| {
+            puffin::ThreadProfiler::call(|profiler| {
+                profiler.register_named_scope(name, "", puffin::short_file_name(location.file()), location.line())
+            })
+        })
+    });
+    puffin::ProfilerScope::new(id, "")
+}
+
+pub struct ScopeGuard {
+    index: usize,
+    #[cfg(feature = "puffin")]
+    _puffin: Option<puffin::ProfilerScope>,
+    #[cfg(feature = "tracy")]
+    _tracy: Option<tracy_client::Span>,
+    // Has to be dropped on the thread it was made on
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for ScopeGuard {
+    fn drop(&mut self) {
+        RECORDER.with(|recorder| {
+            let mut recorder = recorder.borrow_mut();
+            let now = recorder.frame_start.elapsed().as_secs_f32() * 1000.0;
+            recorder.open = recorder.open.saturating_sub(1);
+            if let Some(scope) = recorder.scopes.get_mut(self.index) {
+                scope.duration = now - scope.start;
+            }
+        });
+    }
+}
+
+// This thread's scopes since the last call, and how long that was
+fn finish_frame() -> (Vec<ProfileScope>, f32) {
+    RECORDER.with(|recorder| {
+        let mut recorder = recorder.borrow_mut();
+        let frame_time = recorder.frame_start.elapsed().as_secs_f32() * 1000.0;
+        recorder.frame_start = instant::Instant::now();
+        (std::mem::take(&mut recorder.scopes), frame_time)
+    })
+}
+
+// A frame's timestamps on their way back
+struct InFlight {
+    passes: Vec<&'static str>,
+    #[cfg(feature = "tracy")]
+    spans: Vec<Option<tracy_client::GpuSpan>>,
+    mapped: Receiver<Result<(), wgpu::BufferAsyncError>>,
+}
+
+// Most passes a frame can time, anything past that goes untimed
+const MAX_PASSES: u32 = 64;
+
+// Timestamps written around render graph passes. The results take a frame
+// or two to come back, frames recorded while they're on their way aren't
+// timed.
+pub struct GpuTimer {
+    queries: wgpu::QuerySet,
+    readback_buffer: wgpu::Buffer,
+    // Nanoseconds per tick
+    period: f32,
+    // Passes timed this frame
+    passes: Vec<&'static str>,
+    in_flight: Option<InFlight>,
+    // Tracy needs a GPU timestamp to line its clock up with, so this is made
+    // once the first ones come back. Passes before that go without.
+    #[cfg(feature = "tracy")]
+    tracy: Option<tracy_client::GpuContext>,
+    #[cfg(feature = "tracy")]
+    spans: Vec<Option<tracy_client::GpuSpan>>,
+}
+
+impl GpuTimer {
+    // None when the device wasn't created with timestamp queries
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+        let size = (MAX_PASSES * 2) as wgpu::BufferAddress * std::mem::size_of::<u64>() as wgpu::BufferAddress;
+        Some(Self {
+            queries: device.create_query_set(&wgpu::QuerySetDescriptor {
+                label: Some("Pass Timestamps"),
+                ty: wgpu::QueryType::Timestamp,
+                count: MAX_PASSES * 2,
+            }),
+            readback_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Timestamp Readback Buffer"),
+                size,
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            period: queue.get_timestamp_period(),
+            passes: Vec::new(),
+            in_flight: None,
+            #[cfg(feature = "tracy")]
+            tracy: None,
+            #[cfg(feature = "tracy")]
+            spans: Vec::new(),
+        })
+    }
+
+    // Around each pass, begin_pass() before and end_pass() after
+    pub fn begin_pass(&mut self, encoder: &mut wgpu::CommandEncoder, name: &'static str) {
+        if self.in_flight.is_some() || self.passes.len() as u32 >= MAX_PASSES {
+            return;
+        }
+        encoder.write_timestamp(&self.queries, self.passes.len() as u32 * 2);
+        self.passes.push(name);
+        #[cfg(feature = "tracy")]
+        if let Some(context) = &self.tracy {
+            self.spans.push(context.span_alloc(name, "", file!(), line!()).ok());
+        }
+    }
+
+    pub fn end_pass(&mut self, encoder: &mut wgpu::CommandEncoder, name: &'static str) {
+        if self.in_flight.is_some() || self.passes.last() != Some(&name) {
+            return;
+        }
+        encoder.write_timestamp(&self.queries, self.passes.len() as u32 * 2 - 1);
+        #[cfg(feature = "tracy")]
+        if let Some(Some(span)) = self.spans.last_mut() {
+            span.end_zone();
+        }
+    }
+
+    // Copies this frame's timestamps somewhere they can be read from, last
+    // thing before the encoder is finished
+    pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        if self.in_flight.is_some() || self.passes.is_empty() {
+            return;
+        }
+        encoder.resolve_query_set(&self.queries, 0..self.passes.len() as u32 * 2, &self.readback_buffer, 0);
+    }
+
+    // After submitting, starts reading the timestamps back
+    pub fn submitted(&mut self) {
+        if self.in_flight.is_some() || self.passes.is_empty() {
+            return;
+        }
+        let (sender, receiver) = mpsc::channel();
+        self.readback_buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.in_flight = Some(InFlight {
+            passes: std::mem::take(&mut self.passes),
+            #[cfg(feature = "tracy")]
+            spans: std::mem::take(&mut self.spans),
+            mapped: receiver,
+        });
+    }
+
+    // The pass timings once they're back, without waiting for them
+    pub fn collect(&mut self, device: &wgpu::Device) -> Option<Vec<ProfileScope>> {
+        device.poll(wgpu::Maintain::Poll);
+        let result = match self.in_flight.as_ref()?.mapped.try_recv() {
+            Ok(result) => result,
+            Err(TryRecvError::Empty) => return None,
+            Err(TryRecvError::Disconnected) => Err(wgpu::BufferAsyncError),
+        };
+        let in_flight = self.in_flight.take()?;
+        if result.is_err() {
+            tracing::warn!("Failed to read GPU timestamps back");
+            return None;
+        }
+
+        let slice = self.readback_buffer.slice(..);
+        let timestamps = bytemuck::cast_slice::<u8, u64>(&slice.get_mapped_range())[..in_flight.passes.len() * 2].to_vec();
+        self.readback_buffer.unmap();
+        #[cfg(feature = "tracy")]
+        self.send_to_tracy(&in_flight.spans, &timestamps);
+        let to_ms = |ticks: u64| ticks as f32 * self.period / 1_000_000.0;
+        let first = timestamps[0];
+        Some(
+            in_flight
+                .passes
+                .into_iter()
+                .zip(timestamps.chunks_exact(2))
+                .map(|(name, pair)| ProfileScope {
+                    name,
+                    depth: 0,
+                    start: to_ms(pair[0].saturating_sub(first)),
+                    duration: to_ms(pair[1].saturating_sub(pair[0])),
+                })
+                .collect(),
+        )
+    }
+
+    #[cfg(feature = "tracy")]
+    fn send_to_tracy(&mut self, spans: &[Option<tracy_client::GpuSpan>], timestamps: &[u64]) {
+        if self.tracy.is_none() {
+            self.tracy = tracy_client::Client::running().and_then(|client| {
+                client
+                    .new_gpu_context(Some("wgpu"), tracy_client::GpuContextType::Invalid, timestamps[0] as i64, self.period)
+                    .ok()
+            });
+        }
+        for (span, pair) in spans.iter().zip(timestamps.chunks_exact(2)) {
+            if let Some(span) = span {
+                span.upload_timestamp_start(pair[0] as i64);
+                span.upload_timestamp_end(pair[1] as i64);
+            }
+        }
+    }
+}
+
+// What the overlay's full width stands for, two frames at 60 fps
+const OVERLAY_SPAN: f32 = 1000.0 / 30.0;
+const ROW_HEIGHT: f32 = 12.0;
+const ROW_GAP: f32 = 2.0;
+const PALETTE: [[f32; 4]; 6] = [
+    [0.35, 0.65, 0.95, 1.0],
+    [0.95, 0.6, 0.25, 1.0],
+    [0.45, 0.85, 0.45, 1.0],
+    [0.85, 0.4, 0.75, 1.0],
+    [0.95, 0.85, 0.3, 1.0],
+    [0.4, 0.85, 0.85, 1.0],
+];
+
+// Collects each frame's CPU scopes and GPU pass timings, and draws the last
+// one as bars in a corner: CPU scopes in rows by how nested they are, then
+// GPU passes. Freezing keeps a frame on screen to look at.
+pub struct Profiler {
+    gpu: Option<GpuTimer>,
+    last: FrameProfile,
+    frozen: bool,
+    // Recent and slowest frames, for save_puffin()
+    #[cfg(feature = "puffin")]
+    puffin_frames: puffin::GlobalFrameView,
+}
+
+impl Profiler {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        Self {
+            gpu: GpuTimer::new(device, queue),
+            last: FrameProfile::default(),
+            frozen: false,
+            #[cfg(feature = "puffin")]
+            puffin_frames: puffin::GlobalFrameView::default(),
+        }
+    }
+
+    pub fn gpu_timer(&mut self) -> Option<&mut GpuTimer> {
+        self.gpu.as_mut()
+    }
+
+    // The last finished frame, or the frozen one
+    pub fn last_frame(&self) -> &FrameProfile {
+        &self.last
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    // Stops taking new frames and logs the one being kept, or picks back up
+    pub fn toggle_freeze(&mut self) {
+        self.frozen = !self.frozen;
+        if self.frozen {
+            self.last.log();
+        }
+    }
+
+    // Once a frame, after everything for it has been submitted
+    pub fn end_frame(&mut self, device: &wgpu::Device) {
+        #[cfg(feature = "puffin")]
+        puffin::GlobalProfiler::lock().new_frame();
+        #[cfg(feature = "tracy")]
+        if let Some(client) = tracy_client::Client::running() {
+            client.frame_mark();
+        }
+        let (cpu, frame_time) = finish_frame();
+        let gpu = self.gpu.as_mut().and_then(|timer| timer.collect(device));
+        if self.frozen {
+            return;
+        }
+        self.last.cpu = cpu;
+        self.last.frame_time = frame_time;
+        if let Some(gpu) = gpu {
+            self.last.gpu = gpu;
+        }
+    }
+
+    // Writes the frames puffin is holding on to, recent ones and the slowest
+    // so far, to a file puffin_viewer can open
+    #[cfg(all(feature = "puffin", not(target_arch = "wasm32")))]
+    pub fn save_puffin(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        self.puffin_frames.lock().write(&mut file)
+    }
+
+    // Bars for the last frame, with the top left corner at `origin` and
+    // `width` logical pixels standing for two 60 fps frames
+    pub fn overlay(&self, texture: Handle<Texture>, origin: Vec2, width: f32) -> Vec<Sprite> {
+        let FrameProfile { cpu, gpu, frame_time } = &self.last;
+        let cpu_rows = cpu.iter().map(|scope| scope.depth + 1).max().unwrap_or(0);
+        let rows = 1 + cpu_rows + !gpu.is_empty() as u32;
+        let height = rows as f32 * (ROW_HEIGHT + ROW_GAP) + ROW_GAP;
+        let scale = width / OVERLAY_SPAN;
+        let bar = |row: u32, start: f32, duration: f32, color: [f32; 4]| {
+            let position = origin + Vec2::new(start * scale, ROW_GAP + row as f32 * (ROW_HEIGHT + ROW_GAP));
+            // At least a pixel, so nothing disappears entirely
+            let size = Vec2::new((duration * scale).clamp(1.0, width - start * scale), ROW_HEIGHT);
+            Sprite::new(texture, position, size).with_color(color)
+        };
+
+        let background = if self.frozen { [0.2, 0.05, 0.05, 0.75] } else { [0.0, 0.0, 0.0, 0.6] };
+        let mut sprites = vec![Sprite::new(texture, origin, Vec2::new(width, height)).with_color(background)];
+        // The whole frame, red when over budget
+        let over_budget = *frame_time > 1000.0 / 60.0;
+        let frame_color = if over_budget { [0.9, 0.3, 0.25, 1.0] } else { [0.6, 0.6, 0.6, 1.0] };
+        sprites.push(bar(0, 0.0, frame_time.min(OVERLAY_SPAN), frame_color));
+        for (i, scope) in cpu.iter().filter(|scope| scope.start < OVERLAY_SPAN).enumerate() {
+            sprites.push(bar(1 + scope.depth, scope.start, scope.duration, PALETTE[i % PALETTE.len()]));
+        }
+        for (i, scope) in gpu.iter().filter(|scope| scope.start < OVERLAY_SPAN).enumerate() {
+            sprites.push(bar(1 + cpu_rows, scope.start, scope.duration, PALETTE[(i + 3) % PALETTE.len()]));
+        }
+        // A tick where a 60 fps frame ends
+        sprites.push(
+            Sprite::new(texture, origin + Vec2::new(width / 2.0, 0.0), Vec2::new(1.0, height)).with_color([1.0, 1.0, 1.0, 0.5]),
+        );
+        sprites
+    }
+}
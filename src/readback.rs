@@ -0,0 +1,120 @@
+use anyhow::{anyhow, bail, Context, Result};
+use image::RgbaImage;
+
+// Copying things back from the GPU, the other direction to upload.rs. Both
+// functions submit a copy and then wait for the GPU to get through it and
+// everything submitted before, so they're for screenshots, tools and tests
+// rather than something to do every frame.
+//
+// wgpu doesn't remember how big buffers and textures are, so that has to be
+// passed in, and the source needs to have been created with COPY_SRC.
+
+// `count` values of T starting `offset` bytes into `buffer`. The offset has
+// to be a multiple of 4.
+pub fn read_buffer<T: bytemuck::Pod>(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    buffer: &wgpu::Buffer,
+    offset: wgpu::BufferAddress,
+    count: usize,
+) -> Result<Vec<T>> {
+    if !offset.is_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT) {
+        bail!("buffers can only be read from multiples of {} bytes in", wgpu::COPY_BUFFER_ALIGNMENT);
+    }
+    let size = (count * std::mem::size_of::<T>()) as wgpu::BufferAddress;
+    if size == 0 {
+        return Ok(Vec::new());
+    }
+    // Copies have to be a multiple of 4 bytes long too, the extra is cut off
+    // again below
+    let copy_size = size.div_ceil(wgpu::COPY_BUFFER_ALIGNMENT) * wgpu::COPY_BUFFER_ALIGNMENT;
+    let staging = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Readback Buffer"),
+        size: copy_size,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Readback Encoder"),
+    });
+    encoder.copy_buffer_to_buffer(buffer, offset, &staging, 0, copy_size);
+    queue.submit(Some(encoder.finish()));
+
+    map_and_wait(device, &staging)?;
+    // The mapped bytes aren't necessarily aligned for T
+    let values = bytemuck::pod_collect_to_vec(&staging.slice(..).get_mapped_range()[..size as usize]);
+    staging.unmap();
+    Ok(values)
+}
+
+// The first mip level of a 2D texture, which has to be one of the 8 bit RGBA
+// or BGRA formats. BGRA is swizzled so the image always comes out as RGBA.
+pub fn read_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    format: wgpu::TextureFormat,
+    size: (u32, u32),
+) -> Result<RgbaImage> {
+    let bgra = match format {
+        wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+        wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+        _ => bail!("reading back {:?} textures isn't supported", format),
+    };
+    let (width, height) = size;
+    // Rows in the buffer have to start on a 256 byte boundary
+    let unpadded_row = width * 4;
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    let padded_row = unpadded_row.div_ceil(align) * align;
+    let staging = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Texture Readback Buffer"),
+        size: (padded_row * height) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Texture Readback Encoder"),
+    });
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &staging,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(padded_row),
+                rows_per_image: None,
+            },
+        },
+        wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+    );
+    queue.submit(Some(encoder.finish()));
+
+    map_and_wait(device, &staging)?;
+    let mut pixels = Vec::with_capacity((unpadded_row * height) as usize);
+    for row in staging.slice(..).get_mapped_range().chunks(padded_row as usize) {
+        pixels.extend_from_slice(&row[..unpadded_row as usize]);
+    }
+    staging.unmap();
+    if bgra {
+        for pixel in pixels.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+    }
+    RgbaImage::from_raw(width, height, pixels).ok_or_else(|| anyhow!("readback was the wrong size"))
+}
+
+fn map_and_wait(device: &wgpu::Device, buffer: &wgpu::Buffer) -> Result<()> {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+        let _ = sender.send(result);
+    });
+    device.poll(wgpu::Maintain::Wait);
+    receiver
+        .recv()
+        .context("the device was lost before the readback finished")?
+        .context("mapping the readback buffer")
+}
@@ -0,0 +1,372 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+
+use crate::profiler::GpuTimer;
+
+// How big a transient texture should be. Most attachments follow the surface,
+// so they get reallocated automatically when the window is resized.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TextureSize {
+    Surface,
+    // Surface size divided by n, e.g. half resolution bloom targets
+    Divided(u32),
+    Fixed(u32, u32),
+}
+
+impl TextureSize {
+    fn resolve(self, surface: (u32, u32)) -> (u32, u32) {
+        match self {
+            TextureSize::Surface => surface,
+            TextureSize::Divided(n) => ((surface.0 / n).max(1), (surface.1 / n).max(1)),
+            TextureSize::Fixed(width, height) => (width, height),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TransientTexture {
+    pub size: TextureSize,
+    pub format: wgpu::TextureFormat,
+    pub usage: wgpu::TextureUsages,
+    pub sample_count: u32,
+}
+
+impl TransientTexture {
+    pub fn new(format: wgpu::TextureFormat) -> Self {
+        Self {
+            size: TextureSize::Surface,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            sample_count: 1,
+        }
+    }
+}
+
+enum Resource<'g> {
+    // Owned by someone else, e.g. the surface texture for this frame
+    Imported(&'g wgpu::TextureView),
+    Transient(TransientTexture),
+}
+
+type PassFn<'g> = Box<dyn FnOnce(&mut wgpu::CommandEncoder, &GraphResources) + 'g>;
+
+struct Pass<'g> {
+    name: &'static str,
+    reads: Vec<&'static str>,
+    writes: Vec<&'static str>,
+    run: PassFn<'g>,
+}
+
+// A frame's worth of passes. Passes declare which resources they read and
+// write, the graph works out an order that satisfies those dependencies and
+// hands out transient attachments from a pool that outlives the frame.
+#[derive(Default)]
+pub struct RenderGraph<'g> {
+    resources: HashMap<&'static str, Resource<'g>>,
+    passes: Vec<Pass<'g>>,
+}
+
+pub struct PassBuilder<'a, 'g> {
+    graph: &'a mut RenderGraph<'g>,
+    name: &'static str,
+    reads: Vec<&'static str>,
+    writes: Vec<&'static str>,
+}
+
+impl<'a, 'g> PassBuilder<'a, 'g> {
+    pub fn reads(mut self, names: &[&'static str]) -> Self {
+        self.reads.extend_from_slice(names);
+        self
+    }
+
+    pub fn writes(mut self, names: &[&'static str]) -> Self {
+        self.writes.extend_from_slice(names);
+        self
+    }
+
+    pub fn execute(self, run: impl FnOnce(&mut wgpu::CommandEncoder, &GraphResources) + 'g) {
+        self.graph.passes.push(Pass {
+            name: self.name,
+            reads: self.reads,
+            writes: self.writes,
+            run: Box::new(run),
+        });
+    }
+}
+
+impl<'g> RenderGraph<'g> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn import(&mut self, name: &'static str, view: &'g wgpu::TextureView) {
+        self.resources.insert(name, Resource::Imported(view));
+    }
+
+    pub fn create_texture(&mut self, name: &'static str, desc: TransientTexture) {
+        self.resources.insert(name, Resource::Transient(desc));
+    }
+
+    pub fn add_pass<'a>(&'a mut self, name: &'static str) -> PassBuilder<'a, 'g> {
+        PassBuilder {
+            graph: self,
+            name,
+            reads: Vec::new(),
+            writes: Vec::new(),
+        }
+    }
+
+    // Works out the pass order from the order passes were added in. A pass
+    // that reads a resource runs after whichever pass wrote it before it was
+    // added, and one that writes it runs after every pass added before it
+    // that read or wrote it. So a pass reading a texture a later pass
+    // overwrites still sees the earlier contents.
+    fn schedule(&self) -> Result<Vec<usize>> {
+        let count = self.passes.len();
+        let mut edges = vec![Vec::new(); count];
+        let mut in_degree = vec![0; count];
+
+        // The last pass to write each resource so far, and the passes that
+        // have read it since
+        let mut last_writer: HashMap<&'static str, usize> = HashMap::new();
+        let mut readers: HashMap<&'static str, Vec<usize>> = HashMap::new();
+        for (index, pass) in self.passes.iter().enumerate() {
+            for &name in &pass.reads {
+                // Read after write
+                if let Some(&writer) = last_writer.get(name) {
+                    edges[writer].push(index);
+                }
+                readers.entry(name).or_default().push(index);
+            }
+            for &name in &pass.writes {
+                // Write after write
+                if let Some(&writer) = last_writer.get(name) {
+                    edges[writer].push(index);
+                }
+                // Write after read
+                for reader in readers.remove(name).unwrap_or_default() {
+                    edges[reader].push(index);
+                }
+                last_writer.insert(name, index);
+            }
+        }
+        for (index, targets) in edges.iter_mut().enumerate() {
+            // A pass that reads and writes the same resource doesn't wait on
+            // itself
+            targets.retain(|&target| target != index);
+            targets.sort_unstable();
+            targets.dedup();
+            for &target in targets.iter() {
+                in_degree[target] += 1;
+            }
+        }
+
+        // Kahn's algorithm, always picking the earliest added pass that is
+        // ready so the order stays stable from frame to frame.
+        let mut order = Vec::with_capacity(count);
+        let mut ready = (0..count).filter(|&i| in_degree[i] == 0).collect::<Vec<_>>();
+        while let Some(pos) = ready.iter().enumerate().min_by_key(|(_, &i)| i).map(|(pos, _)| pos) {
+            let pass = ready.swap_remove(pos);
+            order.push(pass);
+            for &target in &edges[pass] {
+                in_degree[target] -= 1;
+                if in_degree[target] == 0 {
+                    ready.push(target);
+                }
+            }
+        }
+
+        if order.len() != count {
+            let stuck = (0..count)
+                .filter(|i| !order.contains(i))
+                .map(|i| self.passes[i].name)
+                .collect::<Vec<_>>();
+            bail!("render graph has a dependency cycle between passes {:?}", stuck);
+        }
+
+        Ok(order)
+    }
+
+    // The names of the passes in the order execute() will run them
+    pub fn pass_order(&self) -> Result<Vec<&'static str>> {
+        Ok(self.schedule()?.into_iter().map(|index| self.passes[index].name).collect())
+    }
+
+    pub fn execute(
+        mut self,
+        device: &wgpu::Device,
+        pool: &mut TransientPool,
+        encoder: &mut wgpu::CommandEncoder,
+        surface_size: (u32, u32),
+        // Times each pass on the GPU, when given
+        mut timer: Option<&mut GpuTimer>,
+    ) -> Result<()> {
+        for pass in &self.passes {
+            for name in pass.reads.iter().chain(&pass.writes) {
+                if !self.resources.contains_key(name) {
+                    bail!("pass {:?} uses unknown resource {:?}", pass.name, name);
+                }
+            }
+        }
+
+        let order = self.schedule()?;
+
+        // The first and last position in the schedule each transient is used
+        // at. Attachments with lifetimes that don't overlap can share memory.
+        let mut lifetimes: Vec<(&'static str, TransientTexture, usize, usize)> = Vec::new();
+        for (step, &index) in order.iter().enumerate() {
+            let pass = &self.passes[index];
+            for &name in pass.reads.iter().chain(&pass.writes) {
+                if let Some(Resource::Transient(desc)) = self.resources.get(name) {
+                    match lifetimes.iter_mut().find(|(n, ..)| *n == name) {
+                        Some(lifetime) => lifetime.3 = step,
+                        None => lifetimes.push((name, *desc, step, step)),
+                    }
+                }
+            }
+        }
+
+        pool.begin_frame();
+        let mut allocations = HashMap::new();
+        for (name, desc, first, last) in lifetimes {
+            let slot = pool.acquire(device, name, desc, surface_size, first, last);
+            allocations.insert(name, slot);
+        }
+
+        let mut views = HashMap::new();
+        for (&name, resource) in &self.resources {
+            match resource {
+                Resource::Imported(view) => {
+                    views.insert(name, *view);
+                }
+                Resource::Transient(_) => {
+                    if let Some(&slot) = allocations.get(name) {
+                        views.insert(name, &pool.textures[slot].view);
+                    }
+                }
+            }
+        }
+
+        let mut passes = self.passes.drain(..).map(Some).collect::<Vec<_>>();
+        for index in order {
+            if let Some(Pass { name, reads, writes, run }) = passes[index].take() {
+                let _span = tracing::debug_span!("pass", name = name).entered();
+                let resources = GraphResources {
+                    views: &views,
+                    pass: name,
+                    reads: &reads,
+                    writes: &writes,
+                };
+                if let Some(timer) = timer.as_deref_mut() {
+                    timer.begin_pass(encoder, name);
+                }
+                run(encoder, &resources);
+                if let Some(timer) = timer.as_deref_mut() {
+                    timer.end_pass(encoder, name);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// What a pass gets to look up its textures with, only the ones it declared
+pub struct GraphResources<'a> {
+    views: &'a HashMap<&'static str, &'a wgpu::TextureView>,
+    pass: &'static str,
+    reads: &'a [&'static str],
+    writes: &'a [&'static str],
+}
+
+impl<'a> GraphResources<'a> {
+    pub fn view(&self, name: &str) -> &'a wgpu::TextureView {
+        // Anything else could be used out of order, the schedule doesn't know
+        // about it
+        if !self.reads.contains(&name) && !self.writes.contains(&name) {
+            panic!("render graph pass {:?} uses {:?} without reading or writing it", self.pass, name);
+        }
+        self.views[name]
+    }
+}
+
+struct PooledTexture {
+    desc: TransientTexture,
+    extent: (u32, u32),
+    // Last schedule step this texture is in use for during the current frame
+    busy_until: Option<usize>,
+    used_this_frame: bool,
+    view: wgpu::TextureView,
+}
+
+// Physical textures backing the graph's transient resources. Kept between
+// frames so we only allocate when the surface size or the passes change.
+#[derive(Default)]
+pub struct TransientPool {
+    textures: Vec<PooledTexture>,
+}
+
+impl TransientPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Anything that wasn't needed last frame (old sizes after a resize,
+    // removed passes) gets freed before handing out this frame's textures.
+    fn begin_frame(&mut self) {
+        self.textures.retain(|t| t.used_this_frame);
+        for texture in &mut self.textures {
+            texture.busy_until = None;
+            texture.used_this_frame = false;
+        }
+    }
+
+    fn acquire(
+        &mut self,
+        device: &wgpu::Device,
+        label: &str,
+        desc: TransientTexture,
+        surface_size: (u32, u32),
+        first: usize,
+        last: usize,
+    ) -> usize {
+        let extent = desc.size.resolve(surface_size);
+        let free = self.textures.iter().position(|t| {
+            t.desc == desc && t.extent == extent && t.busy_until.is_none_or(|until| until < first)
+        });
+
+        let slot = match free {
+            Some(slot) => slot,
+            None => {
+                let texture = device.create_texture(&wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size: wgpu::Extent3d {
+                        width: extent.0,
+                        height: extent.1,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: desc.sample_count,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: desc.format,
+                    usage: desc.usage,
+                });
+                let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+                self.textures.push(PooledTexture {
+                    desc,
+                    extent,
+                    busy_until: None,
+                    used_this_frame: false,
+                    view,
+                });
+                self.textures.len() - 1
+            }
+        };
+
+        let texture = &mut self.textures[slot];
+        texture.busy_until = Some(last);
+        texture.used_this_frame = true;
+        slot
+    }
+}
@@ -0,0 +1,516 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::animation::AnimationClip;
+use crate::exposure::{CameraExposure, DAYLIGHT_LUX};
+use crate::ik::IkChain;
+use crate::trail::Trail;
+use crate::transform::Transform;
+
+// Scenes as plain data, so demo scenes can be written by hand in RON (or
+// JSON) instead of being built up in Rust code.
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SceneTransform {
+    pub translation: [f32; 3],
+    // Quaternion as x, y, z, w
+    pub rotation: [f32; 4],
+    pub scale: [f32; 3],
+}
+
+impl Default for SceneTransform {
+    fn default() -> Self {
+        Self {
+            translation: [0.0; 3],
+            rotation: [0.0, 0.0, 0.0, 1.0],
+            scale: [1.0; 3],
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MeshRef {
+    Quad,
+    Cube,
+    // An .obj, .gltf or .glb file, loaded through the asset manager
+    Model(PathBuf),
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum MaterialRef {
+    // The texture the crate ships with
+    #[default]
+    Default,
+    Texture(PathBuf),
+    // A texture with a height map giving it depth, see parallax.rs
+    Parallax(ParallaxMaterial),
+    // A texture that gives off light, see emissive.rs
+    Emissive(EmissiveMaterial),
+    // Lit in flat bands like a cartoon, see toon.rs
+    Toon(ToonMaterial),
+    // Lit by light baked into a texture ahead of time, see lightmap.rs
+    Lightmapped(LightmappedMaterial),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EmissiveMaterial {
+    // The texture the crate ships with when left out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub texture: Option<PathBuf>,
+    // Added on top of the texture. It's in HDR units, anything over 1 glows
+    // when bloom is on.
+    pub color: [f32; 3],
+    // Multiplies the colour, for only some parts of the surface glowing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emissive_texture: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ParallaxMaterial {
+    pub texture: PathBuf,
+    // Greyscale, white is the top of the surface and black the bottom
+    pub height: PathBuf,
+    #[serde(default)]
+    pub settings: ParallaxSettings,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ParallaxSettings {
+    // How far below the surface black in the height map is, in texture
+    // coordinates (so 0.05 is a twentieth of the texture's width)
+    pub depth: f32,
+    // Steps through the height map, looking straight at the surface uses
+    // the fewest and grazing angles the most
+    pub min_steps: u32,
+    pub max_steps: u32,
+    // Whether bumps shadow what's behind them from the sun
+    pub self_shadowing: bool,
+}
+
+impl Default for ParallaxSettings {
+    fn default() -> Self {
+        Self {
+            depth: 0.05,
+            min_steps: 8,
+            max_steps: 32,
+            self_shadowing: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ToonMaterial {
+    // The texture the crate ships with when left out
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub texture: Option<PathBuf>,
+    // How many steps the light goes in from the unlit side to facing the
+    // sun, 1 is lit or not
+    pub bands: u32,
+    // How much of each step blends into the next, 0 for hard edges
+    pub softness: f32,
+    // Added around the silhouette, where the surface turns away from the
+    // camera. Black for none.
+    pub rim_color: [f32; 3],
+    // How far in from the silhouette the rim reaches, from 0 to 1
+    pub rim_width: f32,
+    // Drawn as a copy of the mesh turned inside out and made a bit bigger
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outline: Option<ToonOutline>,
+}
+
+impl Default for ToonMaterial {
+    fn default() -> Self {
+        Self {
+            texture: None,
+            bands: 3,
+            softness: 0.05,
+            rim_color: [0.3; 3],
+            rim_width: 0.3,
+            outline: Some(ToonOutline::default()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ToonOutline {
+    // In world units
+    pub width: f32,
+    pub color: [f32; 3],
+}
+
+impl Default for ToonOutline {
+    fn default() -> Self {
+        Self {
+            width: 0.02,
+            color: [0.0; 3],
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LightmappedMaterial {
+    // The texture the crate ships with when left out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub texture: Option<PathBuf>,
+    // Read through the mesh's lightmap coordinates. Written by
+    // `--bake-lightmaps`, see bake.rs.
+    pub lightmap: PathBuf,
+    #[serde(default)]
+    pub contents: LightmapContents,
+    // Multiplies the lightmap, for making baked light brighter or dimmer
+    // without baking again
+    #[serde(default = "default_lightmap_intensity")]
+    pub intensity: f32,
+}
+
+fn default_lightmap_intensity() -> f32 {
+    1.0
+}
+
+// What's in a lightmap, and so what's left for the shader to work out
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum LightmapContents {
+    // All the light reaching the surface, from the sky and the sun with its
+    // shadows. The sun can't move once it's baked.
+    #[default]
+    Irradiance,
+    // How much of the sky each point can see. Only darkens the ambient
+    // light, the sun is still worked out every frame.
+    AmbientOcclusion,
+}
+
+// Makes an entity reflect the rest of the scene, in the plane its local x
+// and y axes lie in (the one the quad is in). See mirror.rs.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Mirror {
+    // 1 for a mirror, less for something like a polished floor that still
+    // shows its own texture
+    pub reflectivity: f32,
+}
+
+impl Default for Mirror {
+    fn default() -> Self {
+        Self { reflectivity: 1.0 }
+    }
+}
+
+// Makes an entity reflect the closest reflection probe, over its own colour.
+// Unlike a mirror it can be any shape, but only sees the scene as it was
+// when the probe captured it. See probe.rs.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Gloss {
+    // How much of the reflection shows looking straight at the surface,
+    // more of it does at grazing angles
+    pub reflectivity: f32,
+}
+
+impl Default for Gloss {
+    fn default() -> Self {
+        Self { reflectivity: 0.25 }
+    }
+}
+
+// A point the scene gets captured from into a cubemap, for glossy entities
+// around it to reflect
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReflectionProbe {
+    pub position: [f32; 3],
+    // Half the size of the room or area the probe is in, centred on it.
+    // Reflections inside it are corrected to hit its walls instead of
+    // treating everything as infinitely far away. Zero leaves that out.
+    #[serde(default)]
+    pub extents: [f32; 3],
+    // Of each cube face, in pixels
+    #[serde(default = "default_probe_resolution")]
+    pub resolution: u32,
+}
+
+fn default_probe_resolution() -> u32 {
+    128
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SceneEntity {
+    pub name: String,
+    // Name of another entity, the transform is then relative to that one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+    #[serde(default)]
+    pub transform: SceneTransform,
+    pub mesh: MeshRef,
+    #[serde(default)]
+    pub material: MaterialRef,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirror: Option<Mirror>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gloss: Option<Gloss>,
+    // Leaves a ribbon behind it as it moves, see trail.rs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trail: Option<Trail>,
+}
+
+// What the intensities of a scene's lights are measured in
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum LightUnits {
+    // Plain multipliers of the colour, 1 being about as bright as the sun
+    #[default]
+    Relative,
+    // Lux (the illuminance they give) for directional lights, lumens (the
+    // light they give off altogether) for point lights. A clear day's sun
+    // is around 100000 lux, an overcast one 1000 and a 60 W bulb 800 lm.
+    Physical,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SceneLight {
+    // The intensities are in the scene's light_units
+    Directional {
+        direction: [f32; 3],
+        color: [f32; 3],
+        intensity: f32,
+    },
+    Point {
+        position: [f32; 3],
+        color: [f32; 3],
+        intensity: f32,
+        range: f32,
+    },
+}
+
+impl SceneLight {
+    // The colour times the intensity in the renderer's units, where 1 is a
+    // clear day's sun. For point lights it's how much light reaches a
+    // surface a metre away, falling off with the square of the distance.
+    pub fn radiance(&self, units: LightUnits) -> glam::Vec3 {
+        let (color, intensity) = match *self {
+            SceneLight::Directional { color, intensity, .. } | SceneLight::Point { color, intensity, .. } => {
+                (glam::Vec3::from(color), intensity)
+            }
+        };
+        let intensity = match (units, self) {
+            (LightUnits::Relative, _) => intensity,
+            (LightUnits::Physical, SceneLight::Directional { .. }) => intensity / DAYLIGHT_LUX,
+            // Spread evenly over the sphere, lumens become candela, which
+            // gives that many lux a metre away
+            (LightUnits::Physical, SceneLight::Point { .. }) => {
+                intensity / (4.0 * std::f32::consts::PI) / DAYLIGHT_LUX
+            }
+        };
+        color * intensity
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SceneCamera {
+    pub eye: [f32; 3],
+    pub target: [f32; 3],
+    pub up: [f32; 3],
+    pub fovy: f32,
+    pub znear: f32,
+    pub zfar: f32,
+}
+
+impl Default for SceneCamera {
+    fn default() -> Self {
+        Self {
+            eye: [0.0, 0.0, 2.0],
+            target: [0.0, 0.0, 0.0],
+            up: [0.0, 1.0, 0.0],
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+        }
+    }
+}
+
+impl SceneCamera {
+    // What the camera sees with `aspect` (width over height) as the shape of
+    // the picture, for Ray::from_screen() and bounds::world_to_screen()
+    pub fn view_proj(&self, aspect: f32) -> glam::Mat4 {
+        crate::Camera::from_scene(self, aspect).build_view_projection_matrix()
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Scene {
+    #[serde(default)]
+    pub camera: SceneCamera,
+    #[serde(default)]
+    pub entities: Vec<SceneEntity>,
+    #[serde(default)]
+    pub lights: Vec<SceneLight>,
+    // Played on a loop once the scene is loaded, except with the ecs feature
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub animations: Vec<AnimationClip>,
+    // Solved every frame after the animations, see ik.rs. Not with the ecs
+    // feature either.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ik: Vec<IkChain>,
+    #[serde(default, skip_serializing_if = "is_relative")]
+    pub light_units: LightUnits,
+    // The camera settings the scene was lit for, RenderSettings::exposure
+    // switches to them when it's loaded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exposure: Option<CameraExposure>,
+    // Captured when the scene is loaded, see probe.rs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub probes: Vec<ReflectionProbe>,
+}
+
+fn is_relative(units: &LightUnits) -> bool {
+    *units == LightUnits::Relative
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+}
+
+// Which entity every entity's parent is, found by name once rather than
+// every time the scene is posed. Missing parents and cycles are warned about
+// here, and the entities treated as roots.
+#[derive(Clone, Debug, Default)]
+pub struct SceneHierarchy {
+    parents: Vec<Option<usize>>,
+    // Every entity after its parent
+    order: Vec<usize>,
+}
+
+impl SceneHierarchy {
+    pub fn new(entities: &[SceneEntity]) -> Self {
+        let mut names = HashMap::with_capacity(entities.len());
+        for (index, entity) in entities.iter().enumerate() {
+            names.entry(entity.name.as_str()).or_insert(index);
+        }
+        let mut parents = entities
+            .iter()
+            .map(|entity| {
+                let parent = entity.parent.as_ref()?;
+                let index = names.get(parent.as_str()).copied();
+                if index.is_none() {
+                    tracing::warn!("Scene entity {} has a missing parent {}", entity.name, parent);
+                }
+                index
+            })
+            .collect::<Vec<_>>();
+
+        #[derive(Clone, Copy, PartialEq)]
+        enum Visit {
+            No,
+            Walking,
+            Done,
+        }
+        let mut visits = vec![Visit::No; entities.len()];
+        let mut order = Vec::with_capacity(entities.len());
+        for start in 0..entities.len() {
+            // Walk up until we reach a root or something already ordered
+            let mut chain: Vec<usize> = Vec::new();
+            let mut next = Some(start);
+            while let Some(index) = next {
+                match visits[index] {
+                    Visit::Done => break,
+                    Visit::Walking => {
+                        let last = *chain.last().expect("only entities on the chain are walking");
+                        tracing::warn!("Scene entity {} is its own ancestor", entities[last].name);
+                        parents[last] = None;
+                        break;
+                    }
+                    Visit::No => {
+                        visits[index] = Visit::Walking;
+                        chain.push(index);
+                        next = parents[index];
+                    }
+                }
+            }
+            for &index in chain.iter().rev() {
+                visits[index] = Visit::Done;
+                order.push(index);
+            }
+        }
+        Self { parents, order }
+    }
+
+    pub fn parent(&self, entity: usize) -> Option<usize> {
+        self.parents.get(entity).copied().flatten()
+    }
+
+    // Every entity's transform with its parents applied, from local
+    // transforms in the same order as the entities
+    pub fn world_transforms(&self, local: &[Transform]) -> Vec<Transform> {
+        let mut world = vec![Transform::IDENTITY; self.parents.len()];
+        for &index in &self.order {
+            world[index] = match self.parents[index] {
+                Some(parent) => world[parent] * local[index],
+                None => local[index],
+            };
+        }
+        world
+    }
+}
+
+impl Scene {
+    // Every entity's transform with its parents applied, in the same order as
+    // `entities`. Missing parents and cycles are ignored with a warning.
+    pub fn world_transforms(&self) -> Vec<Transform> {
+        let local = self.entities.iter().map(|e| Transform::from(&e.transform)).collect::<Vec<_>>();
+        self.world_transforms_from(&local)
+    }
+
+    // Same as world_transforms(), but with the local transforms swapped out,
+    // e.g. for ones an animation has posed. Posing every frame should keep a
+    // SceneHierarchy around instead.
+    pub fn world_transforms_from(&self, local: &[Transform]) -> Vec<Transform> {
+        SceneHierarchy::new(&self.entities).world_transforms(local)
+    }
+
+    // .json files are read as JSON, anything else as RON
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        if is_json(path) {
+            let scene: Self = serde_json::from_str(&text)?;
+            scene.validate()?;
+            Ok(scene)
+        } else {
+            Self::from_ron(&text)
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let text = if is_json(path) {
+            serde_json::to_string_pretty(self)?
+        } else {
+            self.to_ron()?
+        };
+        std::fs::write(path, text).with_context(|| format!("writing {}", path.display()))?;
+        Ok(())
+    }
+
+    pub fn from_ron(text: &str) -> Result<Self> {
+        let scene: Self = ron::from_str(text)?;
+        scene.validate()?;
+        Ok(scene)
+    }
+
+    // Catches what would otherwise only go wrong while drawing, like an
+    // animation track with too few values for its keyframes
+    pub fn validate(&self) -> Result<()> {
+        for clip in &self.animations {
+            clip.validate()?;
+        }
+        Ok(())
+    }
+
+    pub fn to_ron(&self) -> Result<String> {
+        Ok(ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?)
+    }
+}
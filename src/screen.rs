@@ -0,0 +1,46 @@
+use glam::{Mat4, Vec2};
+use winit::dpi::{PhysicalPosition, PhysicalSize};
+
+// The surface's size along with the window's DPI scale factor. 2D things
+// (UI, text, cursors) are laid out in logical pixels so they keep the same
+// size on screen whatever the display's DPI, and only get turned into
+// physical pixels when rendering.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Screen {
+    pub physical_size: PhysicalSize<u32>,
+    pub scale_factor: f64,
+}
+
+impl Screen {
+    pub fn new(physical_size: PhysicalSize<u32>, scale_factor: f64) -> Self {
+        Self {
+            physical_size,
+            scale_factor,
+        }
+    }
+
+    pub fn logical_size(&self) -> Vec2 {
+        let size = self.physical_size.to_logical::<f32>(self.scale_factor);
+        Vec2::new(size.width, size.height)
+    }
+
+    pub fn aspect(&self) -> f32 {
+        self.physical_size.width as f32 / self.physical_size.height.max(1) as f32
+    }
+
+    pub fn to_logical(&self, position: PhysicalPosition<f64>) -> Vec2 {
+        let position = position.to_logical::<f32>(self.scale_factor);
+        Vec2::new(position.x, position.y)
+    }
+
+    pub fn to_physical(&self, position: Vec2) -> Vec2 {
+        position * self.scale_factor as f32
+    }
+
+    // Maps logical pixels to clip space, with (0, 0) in the top left corner
+    // and y going down like window coordinates do
+    pub fn ui_projection(&self) -> Mat4 {
+        let size = self.logical_size();
+        Mat4::orthographic_rh(0.0, size.x, size.y, 0.0, -1.0, 1.0)
+    }
+}
@@ -0,0 +1,371 @@
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use glam::{EulerRot, Quat};
+use rhai::{Array, CallFnOptions, Dynamic, Engine, EvalAltResult, Scope, AST};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::hot_reload::FileWatcher;
+use crate::plugin::{Plugin, PluginContext, SceneChanges};
+use crate::scene::{MaterialRef, MeshRef, Scene, SceneEntity, SceneLight, SceneTransform};
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+// The scene as scripts see it while they run
+#[derive(Default)]
+struct ScriptWorld {
+    scene: Scene,
+    changes: SceneChanges,
+}
+
+struct Script {
+    path: PathBuf,
+    // None while the file doesn't compile
+    ast: Option<AST>,
+    // Whether the top level has run since the file was (re)loaded
+    started: bool,
+    // Stops update() being called again after it failed, until the file is
+    // saved again
+    failed: bool,
+}
+
+// Rhai scripts driving the scene. A script's top level runs when it's loaded
+// and every time its file is saved, and `fn update(time, dt)` in it, if
+// there is one, runs every frame:
+//
+//     spawn_entity("spinner", "cube", 0.0, 2.0, 0.0);
+//
+//     fn update(time, dt) {
+//         set_rotation("spinner", 0.0, time * 90.0, 0.0);
+//     }
+//
+// Numbers are f32 and the functions below only take floats, so write 2.0 and
+// not 2. Entities are found by name, lights by their index in the scene.
+//
+// spawn_entity(name, mesh, x, y, z) adds a "cube", "quad" or .obj, .gltf or
+// .glb file.
+// When there already is an entity with that name it's moved (and given the
+// mesh) instead, so reloading a script doesn't pile up copies.
+// position(name), set_position, translate, set_rotation (Euler angles in
+// degrees) and set_scale move things around and entity_names() lists them.
+// set_emissive_color and set_parallax_depth tweak materials, and
+// set_light_color and set_light_intensity the lights. Materials are rebuilt
+// when they change, which is fine for tweaking them but not for animating
+// them every frame.
+pub struct Scripts {
+    engine: Engine,
+    world: Rc<RefCell<ScriptWorld>>,
+    scripts: Vec<Script>,
+    // Seconds the scripts have been running, standing still while paused
+    time: f32,
+    // The directories scripts were loaded from
+    #[cfg(not(target_arch = "wasm32"))]
+    watchers: Vec<(PathBuf, FileWatcher)>,
+}
+
+impl Default for Scripts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scripts {
+    pub fn new() -> Self {
+        let world = Rc::new(RefCell::new(ScriptWorld::default()));
+        let mut engine = Engine::new();
+        register_api(&mut engine, &world);
+        Self {
+            engine,
+            world,
+            scripts: Vec::new(),
+            time: 0.0,
+            #[cfg(not(target_arch = "wasm32"))]
+            watchers: Vec::new(),
+        }
+    }
+
+    // Adds a script, which starts running next frame and reloads whenever
+    // its file changes. One that doesn't compile is logged and picked up
+    // once it's been fixed.
+    pub fn load(&mut self, path: impl AsRef<Path>) {
+        let path = path.as_ref();
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        #[cfg(not(target_arch = "wasm32"))]
+        self.watch_dir(&path);
+        let ast = self.compile(&path);
+        self.scripts.push(Script {
+            path,
+            ast,
+            started: false,
+            failed: false,
+        });
+    }
+
+    // Runs every script on `scene`, giving back what they changed
+    pub fn update(&mut self, scene: &mut Scene, dt: f32) -> SceneChanges {
+        if self.scripts.is_empty() {
+            return SceneChanges::default();
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        self.reload_changed();
+        self.time += dt;
+
+        self.world.borrow_mut().scene = std::mem::take(scene);
+        for script in &mut self.scripts {
+            let Some(ast) = &script.ast else {
+                continue;
+            };
+            let mut scope = Scope::new();
+            if !script.started {
+                script.started = true;
+                if let Err(e) = self.engine.run_ast_with_scope(&mut scope, ast) {
+                    tracing::warn!("{} failed: {}", script.path.display(), e);
+                    script.failed = true;
+                }
+            }
+            let has_update = ast.iter_functions().any(|f| f.name == "update" && f.params.len() == 2);
+            if script.failed || !has_update {
+                continue;
+            }
+            // The top level already ran when the script was loaded
+            let options = CallFnOptions::new().eval_ast(false);
+            let args = (self.time, dt);
+            if let Err(e) = self.engine.call_fn_with_options::<Dynamic>(options, &mut scope, ast, "update", args) {
+                tracing::warn!("{} failed: {}", script.path.display(), e);
+                script.failed = true;
+            }
+        }
+        let mut world = self.world.borrow_mut();
+        *scene = std::mem::take(&mut world.scene);
+        std::mem::take(&mut world.changes)
+    }
+
+    fn compile(&self, path: &Path) -> Option<AST> {
+        match self.engine.compile_file(path.to_path_buf()) {
+            Ok(ast) => Some(ast),
+            Err(e) => {
+                tracing::warn!("{} doesn't compile: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn watch_dir(&mut self, path: &Path) {
+        let dir = path.parent().map(PathBuf::from).unwrap_or_default();
+        if self.watchers.iter().any(|(watched, _)| *watched == dir) {
+            return;
+        }
+        match FileWatcher::new(&dir) {
+            Ok(watcher) => self.watchers.push((dir, watcher)),
+            Err(e) => tracing::warn!("Script hot reloading is disabled: {:?}", e),
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn reload_changed(&mut self) {
+        let changed = self.watchers.iter().flat_map(|(_, watcher)| watcher.changed_files()).collect::<Vec<_>>();
+        for index in 0..self.scripts.len() {
+            if !changed.contains(&self.scripts[index].path) {
+                continue;
+            }
+            tracing::info!("Reloading {}", self.scripts[index].path.display());
+            let ast = self.compile(&self.scripts[index].path);
+            let script = &mut self.scripts[index];
+            script.ast = ast;
+            script.started = false;
+            script.failed = false;
+        }
+    }
+}
+
+// What a change to an entity means for drawing it
+#[derive(Clone, Copy)]
+enum Change {
+    Moved,
+    Rebuilt,
+}
+
+// Runs `edit` on the entity called `name`, noting the change
+fn edit_entity<T>(
+    world: &RefCell<ScriptWorld>,
+    name: &str,
+    change: Change,
+    edit: impl FnOnce(&mut SceneEntity) -> ScriptResult<T>,
+) -> ScriptResult<T> {
+    let world = &mut *world.borrow_mut();
+    let entity = world.scene.entities.iter_mut().find(|e| e.name == name);
+    let result = edit(entity.ok_or_else(|| format!("No entity called {}", name))?)?;
+    match change {
+        Change::Moved => world.changes.moved = true,
+        Change::Rebuilt => world.changes.rebuilt = true,
+    }
+    Ok(result)
+}
+
+// The same for the light at `index`
+fn edit_light(world: &RefCell<ScriptWorld>, index: i64, edit: impl FnOnce(&mut SceneLight)) -> ScriptResult<()> {
+    let mut world = world.borrow_mut();
+    let light = usize::try_from(index).ok().and_then(|index| world.scene.lights.get_mut(index));
+    edit(light.ok_or_else(|| format!("No light {}", index))?);
+    Ok(())
+}
+
+fn register_api(engine: &mut Engine, world: &Rc<RefCell<ScriptWorld>>) {
+    let w = world.clone();
+    engine.register_fn("spawn_entity", move |name: &str, mesh: &str, x: f32, y: f32, z: f32| -> ScriptResult<()> {
+        let mesh = match mesh {
+            "cube" => MeshRef::Cube,
+            "quad" => MeshRef::Quad,
+            path if crate::assets::is_model(path.as_ref()) => MeshRef::Model(path.into()),
+            _ => return Err(format!("Can't spawn {}, it takes cube, quad or an .obj, .gltf or .glb file", mesh).into()),
+        };
+        let world = &mut *w.borrow_mut();
+        match world.scene.entities.iter_mut().find(|e| e.name == name) {
+            Some(entity) => {
+                entity.transform.translation = [x, y, z];
+                if entity.mesh != mesh {
+                    entity.mesh = mesh;
+                    world.changes.rebuilt = true;
+                }
+                world.changes.moved = true;
+            }
+            None => {
+                world.scene.entities.push(SceneEntity {
+                    name: name.to_string(),
+                    parent: None,
+                    transform: SceneTransform {
+                        translation: [x, y, z],
+                        ..Default::default()
+                    },
+                    mesh,
+                    material: MaterialRef::Default,
+                    mirror: None,
+                    gloss: None,
+                    trail: None,
+                });
+                world.changes.rebuilt = true;
+            }
+        }
+        Ok(())
+    });
+
+    let w = world.clone();
+    engine.register_fn("entity_names", move || -> Array {
+        w.borrow().scene.entities.iter().map(|e| Dynamic::from(e.name.clone())).collect()
+    });
+
+    let w = world.clone();
+    engine.register_fn("position", move |name: &str| -> ScriptResult<Array> {
+        let world = w.borrow();
+        let entity = world.scene.entities.iter().find(|e| e.name == name);
+        let translation = entity.ok_or_else(|| format!("No entity called {}", name))?.transform.translation;
+        Ok(translation.iter().map(|&v| Dynamic::from_float(v)).collect())
+    });
+
+    let w = world.clone();
+    engine.register_fn("set_position", move |name: &str, x: f32, y: f32, z: f32| {
+        edit_entity(&w, name, Change::Moved, |entity| {
+            entity.transform.translation = [x, y, z];
+            Ok(())
+        })
+    });
+
+    let w = world.clone();
+    engine.register_fn("translate", move |name: &str, x: f32, y: f32, z: f32| {
+        edit_entity(&w, name, Change::Moved, |entity| {
+            let [tx, ty, tz] = &mut entity.transform.translation;
+            (*tx, *ty, *tz) = (*tx + x, *ty + y, *tz + z);
+            Ok(())
+        })
+    });
+
+    let w = world.clone();
+    engine.register_fn("set_rotation", move |name: &str, x: f32, y: f32, z: f32| {
+        edit_entity(&w, name, Change::Moved, |entity| {
+            let [x, y, z] = [x, y, z].map(f32::to_radians);
+            // The same order the editor's inspector uses
+            entity.transform.rotation = Quat::from_euler(EulerRot::YXZ, y, x, z).to_array();
+            Ok(())
+        })
+    });
+
+    let w = world.clone();
+    engine.register_fn("set_scale", move |name: &str, x: f32, y: f32, z: f32| {
+        edit_entity(&w, name, Change::Moved, |entity| {
+            entity.transform.scale = [x, y, z];
+            Ok(())
+        })
+    });
+
+    let w = world.clone();
+    engine.register_fn("set_emissive_color", move |name: &str, r: f32, g: f32, b: f32| {
+        edit_entity(&w, name, Change::Rebuilt, |entity| match &mut entity.material {
+            MaterialRef::Emissive(material) => {
+                material.color = [r, g, b];
+                Ok(())
+            }
+            _ => Err(format!("{} doesn't have an emissive material", name).into()),
+        })
+    });
+
+    let w = world.clone();
+    engine.register_fn("set_parallax_depth", move |name: &str, depth: f32| {
+        edit_entity(&w, name, Change::Rebuilt, |entity| match &mut entity.material {
+            MaterialRef::Parallax(material) => {
+                material.settings.depth = depth;
+                Ok(())
+            }
+            _ => Err(format!("{} doesn't have a parallax material", name).into()),
+        })
+    });
+
+    let w = world.clone();
+    engine.register_fn("set_light_color", move |index: i64, r: f32, g: f32, b: f32| {
+        edit_light(&w, index, |light| match light {
+            SceneLight::Directional { color, .. } | SceneLight::Point { color, .. } => *color = [r, g, b],
+        })
+    });
+
+    let w = world.clone();
+    engine.register_fn("set_light_intensity", move |index: i64, value: f32| {
+        edit_light(&w, index, |light| match light {
+            SceneLight::Directional { intensity, .. } | SceneLight::Point { intensity, .. } => *intensity = value,
+        })
+    });
+}
+
+// Runs rhai scripts over the scene, see main.rs
+//
+//     AppBuilder::new(WindowConfig::new("script"), ())
+//         .add_plugin(ScriptPlugin::new().with_script("toys/spin.rhai"))
+pub struct ScriptPlugin {
+    scripts: Scripts,
+}
+
+impl Default for ScriptPlugin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScriptPlugin {
+    pub fn new() -> Self {
+        Self { scripts: Scripts::new() }
+    }
+
+    pub fn with_script(mut self, path: impl AsRef<Path>) -> Self {
+        self.scripts.load(path);
+        self
+    }
+}
+
+impl Plugin for ScriptPlugin {
+    // Before animations, which pose the scene from what scripts left
+    fn pre_update(&mut self, context: &mut PluginContext) {
+        let changes = self.scripts.update(context.scene, context.dt);
+        context.scene_changes.moved |= changes.moved;
+        context.scene_changes.rebuilt |= changes.rebuilt;
+    }
+}
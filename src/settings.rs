@@ -0,0 +1,191 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::app::{Exposure, RenderSettings, Tonemapping};
+use crate::exposure::AutoExposure;
+use crate::streaming::DEFAULT_BUDGET;
+use crate::texture::{DEFAULT_ANISOTROPY, MAX_ANISOTROPY};
+
+// Read at startup when it's there, the console's `settings save` writes it
+pub const SETTINGS_PATH: &str = "settings.toml";
+
+// How the window and the surface are set up. Part of RenderSettings, so apps
+// can change it while running like everything else there, the window catches
+// up before the next frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DisplaySettings {
+    // The window's inner size in physical pixels, None leaves it at whatever
+    // size it is. Ignored while fullscreen.
+    pub resolution: Option<(u32, u32)>,
+    // Borderless, covering the monitor the window is on
+    pub fullscreen: bool,
+    // Waits for the display so frames never tear. Without it frames are shown
+    // as soon as they're done, on platforms that let us.
+    pub vsync: bool,
+}
+
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        Self {
+            resolution: None,
+            fullscreen: false,
+            vsync: true,
+        }
+    }
+}
+
+impl DisplaySettings {
+    pub fn present_mode(&self) -> wgpu::PresentMode {
+        if self.vsync {
+            wgpu::PresentMode::Fifo
+        } else {
+            // Immediate, or mailbox, or Fifo when there's nothing else
+            wgpu::PresentMode::AutoNoVsync
+        }
+    }
+}
+
+// Which post effects are on. Their own settings, like bloom's threshold, are
+// left to the app.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PostEffects {
+    pub bloom: bool,
+    pub fog: bool,
+    pub dither: bool,
+    pub auto_exposure: bool,
+    pub tonemapping: Tonemapping,
+}
+
+impl Default for PostEffects {
+    fn default() -> Self {
+        Self::from(&RenderSettings::default())
+    }
+}
+
+impl From<&RenderSettings> for PostEffects {
+    fn from(settings: &RenderSettings) -> Self {
+        Self {
+            bloom: settings.bloom.is_some(),
+            fog: settings.fog.is_some(),
+            dither: settings.dither,
+            auto_exposure: matches!(settings.exposure, Exposure::Auto(_)),
+            tonemapping: settings.tonemapping,
+        }
+    }
+}
+
+// The graphics options a player would pick in a settings menu, as a TOML
+// file:
+//
+//     [display]
+//     resolution = [1920, 1080]
+//     fullscreen = false
+//     vsync = true
+//
+//     [post]
+//     bloom = true
+//     fog = false
+//     dither = true
+//     auto_exposure = false
+//     tonemapping = "aces"
+//
+// Anything left out keeps its default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GraphicsSettings {
+    pub display: DisplaySettings,
+    pub post: PostEffects,
+}
+
+impl GraphicsSettings {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        Ok(toml::from_str(&text)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let text = toml::to_string(self)?;
+        std::fs::write(path, text).with_context(|| format!("writing {}", path.display()))?;
+        Ok(())
+    }
+
+    // What `settings` has on right now, to save()
+    pub fn from_render_settings(settings: &RenderSettings) -> Self {
+        Self {
+            display: settings.display,
+            post: PostEffects::from(settings),
+        }
+    }
+
+    // Switches effects on and off in `settings`. Ones that were on already
+    // keep their settings.
+    pub fn apply(&self, settings: &mut RenderSettings) {
+        settings.display = self.display;
+        let post = &self.post;
+        settings.bloom = post.bloom.then(|| settings.bloom.unwrap_or_default());
+        settings.fog = post.fog.then(|| settings.fog.unwrap_or_default());
+        settings.dither = post.dither;
+        settings.exposure = match (post.auto_exposure, settings.exposure) {
+            (true, Exposure::Fixed(_) | Exposure::Manual(_)) => Exposure::Auto(AutoExposure::default()),
+            (false, Exposure::Auto(_)) => Exposure::Fixed(1.0),
+            (_, exposure) => exposure,
+        };
+        settings.tonemapping = post.tonemapping;
+    }
+}
+
+// One call to trade looks for speed, for a settings menu's "quality" option.
+// Turns effects on and off and sets how much streamed texture detail,
+// anisotropic filtering and draw distance there is, the passes pick up the
+// new settings next frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QualityPreset {
+    // No bloom, fog or anisotropic filtering, blurrier textures and things
+    // disappear past 50 units
+    Low,
+    // Bloom, and draw distance out to 150 units
+    Medium,
+    // Bloom and fog, with all the texture detail the default budget allows
+    #[default]
+    High,
+    // Everything with twice the texture budget and the most anisotropic
+    // filtering
+    Ultra,
+}
+
+impl QualityPreset {
+    pub const ALL: [Self; 4] = [Self::Low, Self::Medium, Self::High, Self::Ultra];
+
+    // Effects that were on already keep their settings
+    pub fn apply(self, settings: &mut RenderSettings) {
+        let (bloom, fog, texture_budget, anisotropy, draw_distance) = match self {
+            Self::Low => (false, false, DEFAULT_BUDGET / 4, 1, Some(50.0)),
+            Self::Medium => (true, false, DEFAULT_BUDGET / 2, 4, Some(150.0)),
+            Self::High => (true, true, DEFAULT_BUDGET, DEFAULT_ANISOTROPY, None),
+            Self::Ultra => (true, true, DEFAULT_BUDGET * 2, MAX_ANISOTROPY, None),
+        };
+        settings.bloom = bloom.then(|| settings.bloom.unwrap_or_default());
+        settings.fog = fog.then(|| settings.fog.unwrap_or_default());
+        settings.texture_budget = texture_budget;
+        settings.max_anisotropy = anisotropy;
+        settings.draw_distance = draw_distance;
+    }
+}
+
+impl std::str::FromStr for QualityPreset {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|preset| format!("{:?}", preset).eq_ignore_ascii_case(name))
+            .ok_or_else(|| anyhow::anyhow!("expected low, medium, high or ultra"))
+    }
+}
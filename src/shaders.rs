@@ -0,0 +1,251 @@
+use crate::assets::{Assets, Handle, Shader};
+
+// Every pipeline gets its own WGSL file in src/shaders/. They're embedded so
+// the binary works on its own, and in debug builds the files are watched so
+// edits show up without restarting.
+pub struct Shaders {
+    pub mesh: Handle<Shader>,
+    pub sprite: Handle<Shader>,
+    // Exposes the HDR scene onto the surface
+    pub tonemap: Handle<Shader>,
+    // Eye adaptation's compute passes
+    pub exposure: Handle<Shader>,
+    // Volumetric fog's compute passes and composite
+    pub fog: Handle<Shader>,
+    // Bloom's downsample and upsample chain
+    pub bloom: Handle<Shader>,
+    pub sky: Handle<Shader>,
+    // Draws mirrors' reflections over them
+    pub mirror: Handle<Shader>,
+    // Draws reflection probes' cubemaps over glossy surfaces
+    pub probe: Handle<Shader>,
+    // Scene meshes with parallax materials
+    pub parallax: Handle<Shader>,
+    // Scene meshes with emissive materials
+    pub emissive: Handle<Shader>,
+    // Scene meshes with toon materials, and their outlines
+    pub toon: Handle<Shader>,
+    // Scene meshes with baked lightmaps
+    pub lightmap: Handle<Shader>,
+    // The scene for both eyes at once, and putting them together
+    pub stereo: Handle<Shader>,
+    // Tints the object under the cursor
+    pub highlight: Handle<Shader>,
+    pub lines: Handle<Shader>,
+    pub grid: Handle<Shader>,
+    pub particles: Handle<Shader>,
+    pub trails: Handle<Shader>,
+    // Edge detection over the scene's depth
+    pub outline: Handle<Shader>,
+    // Scales pixel art mode's small picture up onto the surface
+    pub pixel_art: Handle<Shader>,
+    // The CRT screen at the end of the post chain
+    pub crt: Handle<Shader>,
+    // Baked into all of the above whenever their pipelines get built
+    pub constants: ShaderConstants,
+}
+
+impl Shaders {
+    pub fn load(assets: &mut Assets) -> Self {
+        let mut add = |name: &str, source: &'static str| {
+            let shader = assets.add_shader(name, source);
+            #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+            assets.watch_file(
+                shader.id(),
+                std::path::Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders")).join(name),
+            );
+            shader
+        };
+        Self {
+            mesh: add("mesh.wgsl", include_str!("shaders/mesh.wgsl")),
+            sprite: add("sprite.wgsl", include_str!("shaders/sprite.wgsl")),
+            tonemap: add("tonemap.wgsl", include_str!("shaders/tonemap.wgsl")),
+            exposure: add("exposure.wgsl", include_str!("shaders/exposure.wgsl")),
+            fog: add("fog.wgsl", include_str!("shaders/fog.wgsl")),
+            bloom: add("bloom.wgsl", include_str!("shaders/bloom.wgsl")),
+            sky: add("sky.wgsl", include_str!("shaders/sky.wgsl")),
+            mirror: add("mirror.wgsl", include_str!("shaders/mirror.wgsl")),
+            probe: add("probe.wgsl", include_str!("shaders/probe.wgsl")),
+            parallax: add("parallax.wgsl", include_str!("shaders/parallax.wgsl")),
+            emissive: add("emissive.wgsl", include_str!("shaders/emissive.wgsl")),
+            toon: add("toon.wgsl", include_str!("shaders/toon.wgsl")),
+            lightmap: add("lightmap.wgsl", include_str!("shaders/lightmap.wgsl")),
+            stereo: add("stereo.wgsl", include_str!("shaders/stereo.wgsl")),
+            highlight: add("highlight.wgsl", include_str!("shaders/highlight.wgsl")),
+            lines: add("lines.wgsl", include_str!("shaders/lines.wgsl")),
+            grid: add("grid.wgsl", include_str!("shaders/grid.wgsl")),
+            particles: add("particles.wgsl", include_str!("shaders/particles.wgsl")),
+            trails: add("trails.wgsl", include_str!("shaders/trails.wgsl")),
+            outline: add("outline.wgsl", include_str!("shaders/outline.wgsl")),
+            pixel_art: add("pixel_art.wgsl", include_str!("shaders/pixel_art.wgsl")),
+            crt: add("crt.wgsl", include_str!("shaders/crt.wgsl")),
+            constants: ShaderConstants::new(),
+        }
+    }
+
+    // Every one of them, for rebuilding them all at once
+    pub fn all(&self) -> [Handle<Shader>; 22] {
+        [
+            self.mesh,
+            self.sprite,
+            self.tonemap,
+            self.exposure,
+            self.fog,
+            self.bloom,
+            self.sky,
+            self.mirror,
+            self.probe,
+            self.parallax,
+            self.emissive,
+            self.toon,
+            self.lightmap,
+            self.stereo,
+            self.highlight,
+            self.lines,
+            self.grid,
+            self.particles,
+            self.trails,
+            self.outline,
+            self.pixel_art,
+            self.crt,
+        ]
+    }
+
+    // The source of one of our shaders with the constants filled in
+    pub fn source(&self, assets: &Assets, shader: Handle<Shader>) -> Option<String> {
+        assets.shader(shader).map(|shader| self.constants.apply(&shader.source))
+    }
+}
+
+// Something that can be written as a WGSL literal
+pub trait ShaderValue {
+    fn to_wgsl(&self) -> String;
+}
+
+impl ShaderValue for u32 {
+    fn to_wgsl(&self) -> String {
+        format!("{}u", self)
+    }
+}
+
+impl ShaderValue for i32 {
+    fn to_wgsl(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl ShaderValue for f32 {
+    fn to_wgsl(&self) -> String {
+        // Debug always keeps the decimal point, so 1.0 doesn't turn into an int
+        format!("{:?}", self)
+    }
+}
+
+impl ShaderValue for bool {
+    fn to_wgsl(&self) -> String {
+        self.to_string()
+    }
+}
+
+// Values for a shader's `override` constants, so the same shader can be built
+// with different limits like MAX_LIGHTS. wgpu 0.13 can't set overrides on the
+// pipeline, so they're substituted into the source instead: each
+//
+//     override MAX_LIGHTS: u32 = 4u;
+//
+// line turns into a module scope `let` holding either the value set here or
+// the default from the shader.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ShaderConstants {
+    values: Vec<(String, String)>,
+}
+
+impl ShaderConstants {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, name: &str, value: impl ShaderValue) -> Self {
+        self.set(name, value);
+        self
+    }
+
+    pub fn set(&mut self, name: &str, value: impl ShaderValue) {
+        let value = value.to_wgsl();
+        match self.values.iter_mut().find(|(n, _)| n == name) {
+            Some((_, v)) => *v = value,
+            None => self.values.push((name.to_string(), value)),
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.values.retain(|(n, _)| n != name);
+    }
+
+    // The WGSL literal a constant was set to
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    pub fn apply(&self, source: &str) -> String {
+        let mut out = String::with_capacity(source.len());
+        for line in source.lines() {
+            match self.substitute(line) {
+                Some(line) => out.push_str(&line),
+                None => out.push_str(line),
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    // Rewrites `[@id(n)] override NAME[: type] [= default];`, anything else
+    // is left alone
+    fn substitute(&self, line: &str) -> Option<String> {
+        let indent = &line[..line.len() - line.trim_start().len()];
+        let mut rest = line.trim();
+        // Pipeline constant ids mean nothing once it's a plain constant
+        if let Some(attribute) = rest.strip_prefix("@id(") {
+            rest = attribute.split_once(')')?.1.trim_start();
+        }
+        let declaration = rest.strip_prefix("override")?;
+        if !declaration.starts_with(char::is_whitespace) {
+            return None;
+        }
+        let (declaration, comment) = declaration.split_once(';')?;
+        let (name_and_type, default) = match declaration.split_once('=') {
+            Some((left, default)) => (left, Some(default.trim())),
+            None => (declaration, None),
+        };
+        let name = name_and_type.split(':').next()?.trim();
+        let ty = name_and_type.split_once(':').map(|(_, ty)| ty.trim());
+
+        let value = match (self.get(name), default) {
+            (Some(value), _) => value,
+            (None, Some(default)) => default,
+            (None, None) => {
+                tracing::error!("Shader constant {} has no default and wasn't set", name);
+                return None;
+            }
+        };
+        Some(match ty {
+            Some(ty) => format!("{}let {}: {} = {};{}", indent, name, ty, value, comment),
+            None => format!("{}let {} = {};{}", indent, name, value, comment),
+        })
+    }
+}
+
+// Builds a pipeline from shader source that may have just been edited.
+// Returns None and logs why if it doesn't compile, so the caller can keep
+// drawing with the old one.
+pub fn try_create<T>(device: &wgpu::Device, create: impl FnOnce() -> T) -> Option<T> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let pipeline = create();
+    match pollster::block_on(device.pop_error_scope()) {
+        None => Some(pipeline),
+        Some(error) => {
+            tracing::error!("Shader failed to compile, keeping the old one: {}", error);
+            None
+        }
+    }
+}
@@ -0,0 +1,92 @@
+// Bloom, see bloom.rs. Every step is one triangle covering its target, the
+// source texture is sampled with bilinear filtering so each tap averages
+// four texels.
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    // (0, 0), (2, 0), (0, 2) in uv space, the parts off screen get clipped
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+
+struct Bloom {
+    // Brightness where things start glowing
+    threshold: f32,
+    // How far below the threshold the glow fades in
+    knee: f32,
+    // How much of the glow gets added to the scene
+    intensity: f32,
+};
+@group(1) @binding(0)
+var<uniform> bloom: Bloom;
+
+// A box of 16 texels around uv, in four bilinear taps
+fn box(uv: vec2<f32>) -> vec3<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_source));
+    return (textureSample(t_source, s_source, uv - texel).rgb
+        + textureSample(t_source, s_source, uv + vec2<f32>(texel.x, -texel.y)).rgb
+        + textureSample(t_source, s_source, uv + vec2<f32>(-texel.x, texel.y)).rgb
+        + textureSample(t_source, s_source, uv + texel).rgb) * 0.25;
+}
+
+// Halves the scene and keeps only what's over the threshold. The curve has
+// a soft knee below the threshold, so things don't pop into glowing.
+@fragment
+fn fs_prefilter(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = max(box(in.uv), vec3<f32>(0.0));
+    let brightness = max(color.r, max(color.g, color.b));
+    let knee = max(bloom.knee, 0.00001);
+    var soft = clamp(brightness - bloom.threshold + knee, 0.0, 2.0 * knee);
+    soft = soft * soft / (4.0 * knee);
+    let contribution = max(soft, brightness - bloom.threshold) / max(brightness, 0.00001);
+    return vec4<f32>(color * contribution, 1.0);
+}
+
+// Halves the level above
+@fragment
+fn fs_downsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(box(in.uv), 1.0);
+}
+
+// Spreads the level below out with a 3x3 tent filter. Added on top of the
+// level being drawn into by the pipeline's blending.
+@fragment
+fn fs_upsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_source));
+    var color = textureSample(t_source, s_source, in.uv).rgb * 4.0;
+    color = color + textureSample(t_source, s_source, in.uv + vec2<f32>(-texel.x, 0.0)).rgb * 2.0;
+    color = color + textureSample(t_source, s_source, in.uv + vec2<f32>(texel.x, 0.0)).rgb * 2.0;
+    color = color + textureSample(t_source, s_source, in.uv + vec2<f32>(0.0, -texel.y)).rgb * 2.0;
+    color = color + textureSample(t_source, s_source, in.uv + vec2<f32>(0.0, texel.y)).rgb * 2.0;
+    color = color + textureSample(t_source, s_source, in.uv - texel).rgb;
+    color = color + textureSample(t_source, s_source, in.uv + texel).rgb;
+    color = color + textureSample(t_source, s_source, in.uv + vec2<f32>(-texel.x, texel.y)).rgb;
+    color = color + textureSample(t_source, s_source, in.uv + vec2<f32>(texel.x, -texel.y)).rgb;
+    return vec4<f32>(color / 16.0, 1.0);
+}
+
+// The scene itself is in group 2 for the last step, where the glow from the
+// biggest level gets added to it
+@group(2) @binding(0)
+var t_scene: texture_2d<f32>;
+
+@fragment
+fn fs_composite(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Same size as the target, so every pixel reads exactly one texel
+    let scene = textureLoad(t_scene, vec2<i32>(in.clip_position.xy), 0);
+    let glow = textureSample(t_source, s_source, in.uv).rgb;
+    return vec4<f32>(scene.rgb + glow * bloom.intensity, scene.a);
+}
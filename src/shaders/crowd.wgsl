@@ -0,0 +1,101 @@
+// Characters with their animations baked into a texture, see crowd.rs.
+// Every vertex of every frame is already posed, so all the vertex shader
+// does is look up where its vertex is at the instance's time.
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Time, resolution and mouse, the same for every pipeline
+struct Frame {
+    time: f32,
+    delta_time: f32,
+    resolution: vec2<f32>,
+    mouse: vec2<f32>,
+    frame: u32,
+};
+@group(1) @binding(0)
+var<uniform> frame: Frame;
+
+struct Crowd {
+    // Vertices in one frame of the baked mesh
+    vertex_count: u32,
+    // Texels across the animation texture, the frames wrap onto the next
+    // row when they run off the end of one
+    width: u32,
+    // Towards the sun
+    sun_direction: vec3<f32>,
+    sun_color: vec3<f32>,
+    ambient: vec3<f32>,
+};
+@group(2) @binding(0)
+var<uniform> crowd: Crowd;
+// Frame after frame, the position and then the normal of every vertex
+@group(2) @binding(1)
+var t_animation: texture_2d<f32>;
+@group(2) @binding(2)
+var t_diffuse: texture_2d<f32>;
+@group(2) @binding(3)
+var s_diffuse: sampler;
+
+struct VertexInput {
+    @builtin(vertex_index) index: u32,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+struct InstanceInput {
+    @location(1) model_0: vec4<f32>,
+    @location(2) model_1: vec4<f32>,
+    @location(3) model_2: vec4<f32>,
+    @location(4) model_3: vec4<f32>,
+    // The clip's first frame, how many it has, frames a second and which
+    // frame the instance was on at time 0
+    @location(5) animation: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) normal: vec3<f32>,
+};
+
+fn texel(frame_index: u32, vertex: u32, which: u32) -> vec3<f32> {
+    let i = (frame_index * crowd.vertex_count + vertex) * 2u + which;
+    return textureLoad(t_animation, vec2<i32>(i32(i % crowd.width), i32(i / crowd.width)), 0).xyz;
+}
+
+@vertex
+fn vs_main(vertex: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let model = mat4x4<f32>(instance.model_0, instance.model_1, instance.model_2, instance.model_3);
+    let first = u32(instance.animation.x);
+    let frames = instance.animation.y;
+
+    // Between the two frames either side of now, the last one blending back
+    // into the first since clips loop
+    let at = instance.animation.w + frame.time * instance.animation.z;
+    let at = at - floor(at / frames) * frames;
+    let a = u32(at);
+    let b = (a + 1u) % u32(frames);
+    let t = fract(at);
+    let position = mix(texel(first + a, vertex.index, 0u), texel(first + b, vertex.index, 0u), t);
+    let normal = mix(texel(first + a, vertex.index, 1u), texel(first + b, vertex.index, 1u), t);
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * model * vec4<f32>(position, 1.0);
+    out.tex_coords = vertex.tex_coords;
+    // Fine for the uniform scales crowds are usually placed with
+    out.normal = (model * vec4<f32>(normal, 0.0)).xyz;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let albedo = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    let n = normalize(in.normal);
+    let diffuse = max(dot(n, crowd.sun_direction), 0.0) * crowd.sun_color;
+    // Facing down gets less of the sky
+    let ambient = crowd.ambient * (0.75 + 0.25 * n.y);
+    return vec4<f32>(albedo.rgb * (diffuse + ambient), albedo.a);
+}
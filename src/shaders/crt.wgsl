@@ -0,0 +1,98 @@
+// An old CRT screen, see crt.rs. The picture is bent outwards, read one
+// line at a time with dark gaps between the lines, darkened into the red,
+// green and blue stripes of the aperture grille, given a soft glow and laid
+// over what's left of the last frame.
+
+struct Crt {
+    // Where the picture is on the surface, x, y, width and height in 0..1
+    rect: vec4<f32>,
+    lines: f32,
+    curvature: f32,
+    scanlines: f32,
+    mask: f32,
+    glow: f32,
+    persistence: f32,
+};
+@group(0) @binding(0)
+var<uniform> crt: Crt;
+@group(0) @binding(1)
+var t_picture: texture_2d<f32>;
+@group(0) @binding(2)
+var t_history: texture_2d<f32>;
+@group(0) @binding(3)
+var s_linear: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // 0 to 1 across the surface
+    @location(0) uv: vec2<f32>,
+}
+
+struct FragmentOutput {
+    @location(0) surface: vec4<f32>,
+    // The same again, for next frame's persistence
+    @location(1) history: vec4<f32>,
+}
+
+let PI: f32 = 3.14159265;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    // (0, 0), (2, 0), (0, 2) in uv space, the parts off screen get clipped
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+    // From the middle of the picture out to its edges at -1 and 1, pushed
+    // further out the further from the middle it is, so the picture bulges
+    let centered = (in.uv - crt.rect.xy) / crt.rect.zw * 2.0 - 1.0;
+    let bent = centered * (1.0 + crt.curvature * centered.yx * centered.yx);
+    let uv = bent * 0.5 + 0.5;
+
+    // Every line is read from the picture along its middle, and is brightest
+    // there, fading towards the gaps either side
+    let scan = uv.y * crt.lines;
+    let row = (floor(scan) + 0.5) / crt.lines;
+    var color = textureSample(t_picture, s_linear, vec2<f32>(uv.x, row)).rgb;
+    let beam = mix(1.0, sin(fract(scan) * PI), crt.scanlines);
+
+    // Each screen pixel shows one of red, green or blue brightest
+    var grille = vec3<f32>(1.0 - crt.mask);
+    grille[u32(in.clip_position.x) % 3u] = 1.0;
+
+    // Light from the surrounding picture, squared so mostly bright parts
+    // spread
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_picture));
+    var offsets = array<vec2<f32>, 8>(
+        vec2<f32>(-2.0, 0.0),
+        vec2<f32>(2.0, 0.0),
+        vec2<f32>(0.0, -2.0),
+        vec2<f32>(0.0, 2.0),
+        vec2<f32>(-1.5, -1.5),
+        vec2<f32>(1.5, -1.5),
+        vec2<f32>(-1.5, 1.5),
+        vec2<f32>(1.5, 1.5),
+    );
+    var around = vec3<f32>(0.0);
+    for (var i = 0; i < 8; i = i + 1) {
+        let tap = textureSample(t_picture, s_linear, uv + offsets[i] * texel).rgb;
+        around = around + tap * tap;
+    }
+    color = color * beam * grille + around / 8.0 * crt.glow;
+
+    // A thin dark border where the bent picture ends
+    let edge = min(min(uv.x, 1.0 - uv.x), min(uv.y, 1.0 - uv.y));
+    color = color * smoothstep(0.0, 0.005, edge);
+
+    let last = textureSample(t_history, s_linear, in.uv).rgb;
+    let screen = vec4<f32>(max(color, last * crt.persistence), 1.0);
+    var out: FragmentOutput;
+    out.surface = screen;
+    out.history = screen;
+    return out;
+}
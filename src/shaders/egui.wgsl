@@ -0,0 +1,61 @@
+// egui's triangles, see egui_renderer.rs. Positions are in logical pixels
+// and colours are sRGB with premultiplied alpha.
+
+struct Globals {
+    projection: mat4x4<f32>,
+};
+@group(1) @binding(0)
+var<uniform> globals: Globals;
+
+struct VertexInput {
+    @location(0) position: vec2<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) color: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+fn linear_from_srgb(srgb: vec3<f32>) -> vec3<f32> {
+    let cutoff = srgb < vec3<f32>(0.04045);
+    let lower = srgb / vec3<f32>(12.92);
+    let higher = pow((srgb + vec3<f32>(0.055)) / vec3<f32>(1.055), vec3<f32>(2.4));
+    return select(higher, lower, cutoff);
+}
+
+fn srgb_from_linear(rgb: vec3<f32>) -> vec3<f32> {
+    let cutoff = rgb < vec3<f32>(0.0031308);
+    let lower = rgb * vec3<f32>(12.92);
+    let higher = vec3<f32>(1.055) * pow(rgb, vec3<f32>(1.0 / 2.4)) - vec3<f32>(0.055);
+    return select(higher, lower, cutoff);
+}
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = globals.projection * vec4<f32>(in.position, 0.0, 1.0);
+    out.tex_coords = in.tex_coords;
+    out.color = vec4<f32>(linear_from_srgb(in.color.rgb), in.color.a);
+    return out;
+}
+
+@group(0) @binding(0)
+var t_egui: texture_2d<f32>;
+@group(0) @binding(1)
+var s_egui: sampler;
+
+// Onto an sRGB surface, which converts the linear colour itself
+@fragment
+fn fs_linear(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color * textureSample(t_egui, s_egui, in.tex_coords);
+}
+
+// Onto a surface that takes sRGB values as they are
+@fragment
+fn fs_gamma(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = in.color * textureSample(t_egui, s_egui, in.tex_coords);
+    return vec4<f32>(srgb_from_linear(color.rgb), color.a);
+}
@@ -0,0 +1,100 @@
+// Eye adaptation, see exposure.rs: a histogram of how bright the HDR scene
+// is, then a single workgroup that averages it and eases the exposure
+// towards that average
+
+struct Params {
+    // log2 of the darkest and brightest luminance the histogram covers
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    // How quickly exposure follows the scene getting brighter and darker
+    speed_up: f32,
+    speed_down: f32,
+    // In stops, added on top of what the average calls for
+    compensation: f32,
+    delta_time: f32,
+};
+
+struct Exposure {
+    // What exposure is adapted to, 0 until the first frame has been seen
+    luminance: f32,
+    // What the tonemap pass multiplies the scene by
+    exposure: f32,
+};
+
+@group(0) @binding(0)
+var t_scene: texture_2d<f32>;
+@group(0) @binding(1)
+var<storage, read_write> histogram: array<atomic<u32>, 256>;
+@group(0) @binding(2)
+var<storage, read_write> exposure: Exposure;
+@group(0) @binding(3)
+var<uniform> params: Params;
+
+// Anything darker goes in bin 0, which the average leaves out so black
+// backgrounds don't blow up the exposure
+let EPSILON: f32 = 0.005;
+// Middle grey, what the average brightness ends up as
+let KEY: f32 = 0.18;
+
+var<workgroup> local_bins: array<atomic<u32>, 256>;
+var<workgroup> weighted: array<f32, 256>;
+
+fn bin(color: vec3<f32>) -> u32 {
+    let luminance = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+    if (luminance < EPSILON) {
+        return 0u;
+    }
+    let t = clamp((log2(luminance) - params.min_log_luminance) / params.log_luminance_range, 0.0, 1.0);
+    return u32(t * 254.0 + 1.0);
+}
+
+// One thread per pixel, counted into the workgroup's bins first so only 256
+// atomics per workgroup hit the shared histogram
+@compute @workgroup_size(16, 16)
+fn build_histogram(@builtin(global_invocation_id) id: vec3<u32>, @builtin(local_invocation_index) index: u32) {
+    atomicStore(&local_bins[index], 0u);
+    workgroupBarrier();
+    let size = textureDimensions(t_scene);
+    let position = vec2<i32>(id.xy);
+    if (position.x < size.x && position.y < size.y) {
+        atomicAdd(&local_bins[bin(textureLoad(t_scene, position, 0).rgb)], 1u);
+    }
+    workgroupBarrier();
+    atomicAdd(&histogram[index], atomicLoad(&local_bins[index]));
+}
+
+// One thread per bin
+@compute @workgroup_size(256)
+fn average(@builtin(local_invocation_index) index: u32) {
+    let count = atomicLoad(&histogram[index]);
+    // Empty again for next frame
+    atomicStore(&histogram[index], 0u);
+    weighted[index] = f32(count) * f32(index);
+    workgroupBarrier();
+    for (var stride = 128u; stride > 0u; stride = stride >> 1u) {
+        if (index < stride) {
+            weighted[index] = weighted[index] + weighted[index + stride];
+        }
+        workgroupBarrier();
+    }
+    if (index != 0u) {
+        return;
+    }
+
+    // Thread 0's count is the pixels too dark to matter
+    let size = textureDimensions(t_scene);
+    let lit = f32(size.x * size.y) - f32(count);
+    var average_luminance = exp2(params.min_log_luminance);
+    if (lit > 0.0) {
+        let mean_bin = weighted[0] / lit;
+        average_luminance = exp2((mean_bin - 1.0) / 254.0 * params.log_luminance_range + params.min_log_luminance);
+    }
+
+    var luminance = average_luminance;
+    if (exposure.luminance > 0.0) {
+        let speed = select(params.speed_down, params.speed_up, average_luminance > exposure.luminance);
+        luminance = exposure.luminance + (average_luminance - exposure.luminance) * (1.0 - exp(-params.delta_time * speed));
+    }
+    exposure.luminance = luminance;
+    exposure.exposure = KEY / luminance * exp2(params.compensation);
+}
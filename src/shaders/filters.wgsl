@@ -0,0 +1,99 @@
+// Image filters as compute kernels, see filters.rs. Each one reads the source
+// texture with textureLoad and writes one pixel of the target per invocation.
+// The target's format is swapped for the one the filters were created with.
+
+struct Params {
+    // Which way a blur pass goes, (1, 0) or (0, 1)
+    direction: vec2<i32>,
+    radius: i32,
+    sigma: f32,
+    // How strongly sharpen() sharpens
+    amount: f32,
+};
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var t_target: texture_storage_2d<rgba16float, write>;
+@group(0) @binding(2)
+var<uniform> params: Params;
+
+// Pixels past the edge repeat the closest one inside
+fn load(position: vec2<i32>) -> vec4<f32> {
+    let size = textureDimensions(t_source);
+    return textureLoad(t_source, clamp(position, vec2<i32>(0), size - 1), 0);
+}
+
+// Threads past the edge of targets that aren't a multiple of 8 wide
+fn outside(position: vec2<i32>) -> bool {
+    let size = textureDimensions(t_target);
+    return position.x >= size.x || position.y >= size.y;
+}
+
+// One direction of a separable Gaussian blur
+@compute @workgroup_size(8, 8)
+fn blur(@builtin(global_invocation_id) id: vec3<u32>) {
+    let position = vec2<i32>(id.xy);
+    if (outside(position)) {
+        return;
+    }
+    var color = vec4<f32>(0.0);
+    var total = 0.0;
+    for (var i = -params.radius; i <= params.radius; i = i + 1) {
+        let x = f32(i);
+        let weight = exp(-x * x / (2.0 * params.sigma * params.sigma));
+        color = color + load(position + params.direction * i) * weight;
+        total = total + weight;
+    }
+    textureStore(t_target, position, color / total);
+}
+
+// Half the size, each pixel the average of the four it covers
+@compute @workgroup_size(8, 8)
+fn downsample(@builtin(global_invocation_id) id: vec3<u32>) {
+    let position = vec2<i32>(id.xy);
+    if (outside(position)) {
+        return;
+    }
+    let source = position * 2;
+    let color = load(source) + load(source + vec2<i32>(1, 0)) + load(source + vec2<i32>(0, 1)) + load(source + vec2<i32>(1, 1));
+    textureStore(t_target, position, color * 0.25);
+}
+
+// Pushes each pixel away from the average of its neighbours
+@compute @workgroup_size(8, 8)
+fn sharpen(@builtin(global_invocation_id) id: vec3<u32>) {
+    let position = vec2<i32>(id.xy);
+    if (outside(position)) {
+        return;
+    }
+    let center = load(position);
+    let neighbours = load(position + vec2<i32>(1, 0)) + load(position - vec2<i32>(1, 0))
+        + load(position + vec2<i32>(0, 1)) + load(position - vec2<i32>(0, 1));
+    let sharpened = center.rgb + (center.rgb - neighbours.rgb * 0.25) * params.amount;
+    textureStore(t_target, position, vec4<f32>(max(sharpened, vec3<f32>(0.0)), center.a));
+}
+
+fn luminance(position: vec2<i32>) -> f32 {
+    return dot(load(position).rgb, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+// Edge strength from the brightness gradient, as grey
+@compute @workgroup_size(8, 8)
+fn sobel(@builtin(global_invocation_id) id: vec3<u32>) {
+    let position = vec2<i32>(id.xy);
+    if (outside(position)) {
+        return;
+    }
+    let top_left = luminance(position + vec2<i32>(-1, -1));
+    let top = luminance(position + vec2<i32>(0, -1));
+    let top_right = luminance(position + vec2<i32>(1, -1));
+    let left = luminance(position + vec2<i32>(-1, 0));
+    let right = luminance(position + vec2<i32>(1, 0));
+    let bottom_left = luminance(position + vec2<i32>(-1, 1));
+    let bottom = luminance(position + vec2<i32>(0, 1));
+    let bottom_right = luminance(position + vec2<i32>(1, 1));
+    let gx = (top_right + 2.0 * right + bottom_right) - (top_left + 2.0 * left + bottom_left);
+    let gy = (bottom_left + 2.0 * bottom + bottom_right) - (top_left + 2.0 * top + top_right);
+    textureStore(t_target, position, vec4<f32>(vec3<f32>(length(vec2<f32>(gx, gy))), 1.0));
+}
@@ -0,0 +1,174 @@
+// Volumetric fog, see fog.rs. The view frustum is cut into a grid of froxels
+// (frustum voxels), thinner near the camera. `scatter` works out how much
+// light each one sends towards the camera, `integrate` adds them up front to
+// back, and the composite pass fogs the scene with the running total at
+// each pixel's depth.
+
+struct Fog {
+    inverse_view_proj: mat4x4<f32>,
+    view_proj: mat4x4<f32>,
+    eye: vec3<f32>,
+    // Distance of the first and last slice from the eye
+    near: f32,
+    // Towards the sun
+    sun_direction: vec3<f32>,
+    far: f32,
+    sun_color: vec3<f32>,
+    // How much of the light fog takes out per world unit at base_height
+    density: f32,
+    // Light reaching the fog from the sky, all around
+    ambient: vec3<f32>,
+    // How quickly it thins out above base_height
+    height_falloff: f32,
+    base_height: f32,
+    // Henyey-Greenstein g, towards 1 scatters more light forward
+    anisotropy: f32,
+};
+@group(0) @binding(0)
+var<uniform> fog: Fog;
+
+// Froxels across, down and deep, matching GRID in fog.rs
+let GRID: vec3<u32> = vec3<u32>(160u, 90u, 64u);
+let PI: f32 = 3.14159265;
+// Steps towards the sun checking for anything in the way
+let SHADOW_STEPS: i32 = 8;
+
+// Distance from the eye of the front of slice `z`, which can be fractional
+fn slice_distance(z: f32) -> f32 {
+    return fog.near * pow(fog.far / fog.near, z / f32(GRID.z));
+}
+
+// The other way around, 0 to 1 across the slices
+fn slice_coordinate(along: f32) -> f32 {
+    return log(max(along, fog.near) / fog.near) / log(fog.far / fog.near);
+}
+
+fn world_position(uv: vec2<f32>, depth: f32) -> vec3<f32> {
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let world = fog.inverse_view_proj * ndc;
+    return world.xyz / world.w;
+}
+
+fn phase(cos_theta: f32) -> f32 {
+    let g = fog.anisotropy;
+    let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+    return (1.0 - g * g) / (4.0 * PI * pow(denominator, 1.5));
+}
+
+// --- Compute passes ---
+
+@group(0) @binding(1)
+var t_depth: texture_2d<f32>;
+// Scattered light in rgb and extinction in a, per froxel
+@group(0) @binding(2)
+var<storage, read_write> froxels: array<vec4<f32>>;
+// The running totals as half floats, scattered light so far and what's
+// left of the scene behind, copied into a 3D texture afterwards
+@group(0) @binding(3)
+var<storage, read_write> integrated: array<vec2<u32>>;
+
+fn froxel_index(id: vec3<u32>) -> u32 {
+    return (id.z * GRID.y + id.y) * GRID.x + id.x;
+}
+
+// Whether the sun reaches `position`. There are no shadow maps, so this
+// walks towards the sun through the depth buffer: once the walk ends up
+// behind something on screen, that something is in the way. Only what's on
+// screen casts light shafts this way, which is mostly all that shows anyway.
+fn sun_visibility(position: vec3<f32>, step: f32) -> f32 {
+    let size = vec2<f32>(textureDimensions(t_depth));
+    for (var i = 1; i <= SHADOW_STEPS; i = i + 1) {
+        let marched = position + fog.sun_direction * step * f32(i);
+        let clip = fog.view_proj * vec4<f32>(marched, 1.0);
+        if (clip.w <= 0.0) {
+            break;
+        }
+        let ndc = clip.xyz / clip.w;
+        let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+        if (any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0))) {
+            break;
+        }
+        let scene_depth = textureLoad(t_depth, vec2<i32>(uv * (size - 1.0)), 0).r;
+        if (ndc.z > scene_depth) {
+            return 0.0;
+        }
+    }
+    return 1.0;
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn scatter(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (any(id >= GRID)) {
+        return;
+    }
+    let uv = (vec2<f32>(id.xy) + 0.5) / vec2<f32>(GRID.xy);
+    let direction = normalize(world_position(uv, 1.0) - fog.eye);
+    let along = slice_distance(f32(id.z) + 0.5);
+    let position = fog.eye + direction * along;
+
+    // Thickest low down, thinning out exponentially with height
+    let density = fog.density * exp(-fog.height_falloff * max(position.y - fog.base_height, 0.0));
+    let thickness = slice_distance(f32(id.z) + 1.0) - slice_distance(f32(id.z));
+    let sun = fog.sun_color * phase(dot(-direction, -fog.sun_direction)) * sun_visibility(position, thickness * 2.0);
+    let light = (sun + fog.ambient / (4.0 * PI)) * density;
+    froxels[froxel_index(id)] = vec4<f32>(light, density);
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn integrate(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (any(id.xy >= GRID.xy)) {
+        return;
+    }
+    var light = vec3<f32>(0.0);
+    var transmittance = 1.0;
+    for (var z = 0u; z < GRID.z; z = z + 1u) {
+        let index = froxel_index(vec3<u32>(id.xy, z));
+        let froxel = froxels[index];
+        let thickness = slice_distance(f32(z) + 1.0) - slice_distance(f32(z));
+        let extinction = max(froxel.a, 0.000001);
+        let slice_transmittance = exp(-extinction * thickness);
+        // Light scattered all through the slice, dimmed by the fog in front
+        // of it within the slice too (Hillaire's energy conserving version)
+        light = light + transmittance * froxel.rgb * (1.0 - slice_transmittance) / extinction;
+        transmittance = transmittance * slice_transmittance;
+        integrated[index] = vec2<u32>(pack2x16float(light.rg), pack2x16float(vec2<f32>(light.b, transmittance)));
+    }
+}
+
+// --- Composite pass ---
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    // (0, 0), (2, 0), (0, 2) in uv space, the parts off screen get clipped
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    return out;
+}
+
+@group(1) @binding(0)
+var t_scene: texture_2d<f32>;
+@group(1) @binding(1)
+var t_scene_depth: texture_2d<f32>;
+@group(1) @binding(2)
+var t_volume: texture_3d<f32>;
+@group(1) @binding(3)
+var s_volume: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let uv = in.clip_position.xy / vec2<f32>(textureDimensions(t_scene));
+    let scene = textureLoad(t_scene, pixel, 0);
+    let depth = textureLoad(t_scene_depth, pixel, 0).r;
+    // The sky is as far away as the fog goes
+    let seen = min(distance(world_position(uv, depth), fog.eye), fog.far);
+    // Every texel holds the totals to the back of its slice
+    let w = slice_coordinate(seen) - 0.5 / f32(GRID.z);
+    let volume = textureSampleLevel(t_volume, s_volume, vec3<f32>(uv, w), 0.0);
+    return vec4<f32>(scene.rgb * volume.a + volume.rgb, scene.a);
+}
@@ -0,0 +1,122 @@
+// Grass scattered over the terrain, see foliage.rs. Every tuft is a quad
+// that turns to face the camera, with the blades cut out of it here and bent
+// by the wind.
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Time, resolution and mouse, the same for every pipeline
+struct Frame {
+    time: f32,
+    delta_time: f32,
+    resolution: vec2<f32>,
+    mouse: vec2<f32>,
+    frame: u32,
+};
+@group(1) @binding(0)
+var<uniform> frame: Frame;
+
+struct Foliage {
+    eye: vec3<f32>,
+    // Tufts start shrinking away at fade_start and are gone by fade_end
+    fade_start: f32,
+    // Which way the wind blows, as long as how far it pushes the tips
+    wind: vec2<f32>,
+    fade_end: f32,
+    // Towards the sun
+    sun_direction: vec3<f32>,
+    sun_color: vec3<f32>,
+    ambient: vec3<f32>,
+};
+@group(2) @binding(0)
+var<uniform> foliage: Foliage;
+
+struct VertexInput {
+    // x from -0.5 to 0.5 across the tuft, y from 0 at the ground to 1
+    @location(0) corner: vec2<f32>,
+};
+
+struct TuftInput {
+    @location(1) position: vec3<f32>,
+    // Width and height
+    @location(2) size: vec2<f32>,
+    // Between 0 and 1, different for every tuft
+    @location(3) seed: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) seed: f32,
+    @location(2) fade: f32,
+};
+
+@vertex
+fn vs_main(vertex: VertexInput, tuft: TuftInput) -> VertexOutput {
+    let to_eye = foliage.eye - tuft.position;
+    let distance = length(to_eye);
+    let fade = 1.0 - smoothstep(foliage.fade_start, foliage.fade_end, distance);
+
+    // Turning around the up axis only, so the blades stay upright
+    let across = vec2<f32>(to_eye.x, to_eye.z);
+    let facing = select(vec2<f32>(0.0, 1.0), normalize(across), length(across) > 0.0001);
+    let right = vec3<f32>(facing.y, 0.0, -facing.x);
+
+    // Gusts roll across the field in the wind's direction, each tuft a bit
+    // out of step. The tips move most and the roots not at all.
+    let phase = dot(tuft.position.xz, normalize(foliage.wind + vec2<f32>(0.0001))) * 0.35;
+    let gust = sin(frame.time * 1.7 - phase + tuft.seed * 6.28) * 0.6 + sin(frame.time * 3.1 - phase * 2.3) * 0.4;
+    let bend = vertex.corner.y * vertex.corner.y;
+    let sway = foliage.wind * (0.6 + 0.4 * gust) * bend;
+
+    // Far away tufts shrink into the ground instead of popping out
+    let size = tuft.size * fade;
+    var world = tuft.position + right * vertex.corner.x * size.x + vec3<f32>(0.0, vertex.corner.y * size.y, 0.0);
+    world = world + vec3<f32>(sway.x, -length(sway) * 0.3, sway.y) * size.y;
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(world, 1.0);
+    out.uv = vec2<f32>(vertex.corner.x + 0.5, vertex.corner.y);
+    out.seed = tuft.seed;
+    out.fade = fade;
+    return out;
+}
+
+let ROOT: vec3<f32> = vec3<f32>(0.05, 0.12, 0.03);
+let TIP: vec3<f32> = vec3<f32>(0.3, 0.45, 0.12);
+let DRY: vec3<f32> = vec3<f32>(0.45, 0.42, 0.18);
+let BLADES: i32 = 5;
+
+fn hash(n: f32) -> f32 {
+    return fract(sin(n * 127.1) * 43758.5453);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Some of the tuft's blades cover this point, each a thin wedge leaning
+    // a bit to one side
+    var covered = false;
+    for (var i = 0; i < BLADES; i = i + 1) {
+        let n = in.seed * 17.0 + f32(i);
+        let height = 0.55 + 0.45 * hash(n);
+        let lean = (hash(n + 0.5) - 0.5) * 0.5;
+        let root = (f32(i) + 0.5) / f32(BLADES) + (hash(n + 0.25) - 0.5) * 0.15;
+        let center = root + lean * in.uv.y;
+        let width = 0.09 * (1.0 - in.uv.y / height);
+        covered = covered || (in.uv.y < height && abs(in.uv.x - center) < width);
+    }
+    // Fading tufts lose pixels in a fixed pattern rather than blending, so
+    // they can still be drawn without sorting
+    let dither = fract(52.9829189 * fract(dot(in.clip_position.xy, vec2<f32>(0.06711056, 0.00583715))));
+    if (!covered || in.fade < dither) {
+        discard;
+    }
+
+    let albedo = mix(ROOT, mix(TIP, DRY, in.seed * in.seed * 0.6), in.uv.y);
+    // Lit as if facing up, blades that thin light up from both sides
+    let diffuse = max(foliage.sun_direction.y, 0.0) * (0.6 + 0.4 * in.uv.y) * foliage.sun_color;
+    return vec4<f32>(albedo * (diffuse + foliage.ambient), 1.0);
+}
@@ -0,0 +1,96 @@
+// An endless grid on the ground (y = 0) that fades out with distance, with
+// the X axis in red and the Z axis in blue. Every pixel casts a ray from the
+// camera and draws whatever part of the grid it hits, writing the hit's depth
+// so the scene can cover it.
+
+struct GridUniform {
+    view_proj: mat4x4<f32>,
+    inverse_view_proj: mat4x4<f32>,
+    eye: vec3<f32>,
+    // Lines fade out completely this far from the camera
+    fade_distance: f32,
+};
+@group(0) @binding(0)
+var<uniform> grid: GridUniform;
+
+// Time, resolution and mouse, the same for every pipeline
+struct Frame {
+    time: f32,
+    delta_time: f32,
+    resolution: vec2<f32>,
+    mouse: vec2<f32>,
+    frame: u32,
+};
+@group(1) @binding(0)
+var<uniform> frame: Frame;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.ndc = vec2<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+    out.clip_position = vec4<f32>(out.ndc, 0.0, 1.0);
+    return out;
+}
+
+fn unproject(ndc: vec2<f32>, depth: f32) -> vec3<f32> {
+    let world = grid.inverse_view_proj * vec4<f32>(ndc, depth, 1.0);
+    return world.xyz / world.w;
+}
+
+// How much of a grid line with `spacing` covers this pixel, `pixel` being
+// fwidth(position). That's taken in fs_main since the GLSL backend puts
+// helpers like this one in the vertex shader too, where fwidth doesn't exist.
+fn grid_lines(position: vec2<f32>, pixel: vec2<f32>, spacing: f32) -> f32 {
+    let coord = position / spacing;
+    let width = pixel / spacing;
+    let distance = abs(fract(coord - 0.5) - 0.5) / width;
+    return 1.0 - min(min(distance.x, distance.y), 1.0);
+}
+
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    @builtin(frag_depth) depth: f32,
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+    let near = unproject(in.ndc, 0.0);
+    let far = unproject(in.ndc, 1.0);
+    let t = -near.y / (far.y - near.y);
+    let hit = near + (far - near) * t;
+    let position = hit.xz;
+
+    let width = fwidth(position);
+
+    // Thin lines every unit, stronger ones every ten
+    let small = grid_lines(position, width, 1.0);
+    let large = grid_lines(position, width, 10.0);
+    var color = vec4<f32>(vec3<f32>(0.35), max(small * 0.4, large * 0.8));
+
+    if (abs(position.y) < width.y) {
+        color = vec4<f32>(0.9, 0.2, 0.2, 1.0);
+    }
+    if (abs(position.x) < width.x) {
+        color = vec4<f32>(0.25, 0.45, 1.0, 1.0);
+    }
+
+    let fade = 1.0 - smoothstep(grid.fade_distance * 0.25, grid.fade_distance, distance(hit, grid.eye));
+    color.a = color.a * fade;
+
+    let clip = grid.view_proj * vec4<f32>(hit, 1.0);
+    var out: FragmentOutput;
+    out.color = color;
+    out.depth = clip.z / clip.w;
+    // Rays going up or away never reach the ground
+    if (t <= 0.0 || out.depth > 1.0) {
+        out.color = vec4<f32>(0.0);
+        out.depth = 1.0;
+    }
+    return out;
+}
@@ -0,0 +1,42 @@
+// Hover highlighting, see highlight.rs. The scene mesh under the cursor
+// drawn again on top of itself in a flat colour, which gets blended over it.
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+};
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// The alpha is how much of it covers the mesh
+struct Highlight {
+    color: vec4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> highlight: Highlight;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+}
+
+@vertex
+fn vs_main(model: VertexInput, instance: InstanceInput) -> @builtin(position) vec4<f32> {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    return camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return highlight.color;
+}
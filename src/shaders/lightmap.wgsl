@@ -0,0 +1,98 @@
+// Lightmapped surfaces, see lightmap.rs. The lightmap is read through the
+// mesh's second set of texture coordinates and either replaces the lighting
+// or, when it only holds ambient occlusion, darkens the ambient light under
+// the realtime sun.
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+struct LightmapInput {
+    @location(9) lightmap_coords: vec2<f32>,
+};
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    eye: vec4<f32>,
+};
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct Lightmap {
+    // Already multiplied by the scale irradiance is stored at
+    intensity: f32,
+    // 1 for ambient occlusion, 0 for irradiance
+    occlusion: f32,
+};
+@group(0) @binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(0) @binding(1)
+var s_diffuse: sampler;
+@group(0) @binding(2)
+var t_lightmap: texture_2d<f32>;
+@group(0) @binding(3)
+var s_lightmap: sampler;
+@group(0) @binding(4)
+var<uniform> lightmap: Lightmap;
+
+// The same as parallax.wgsl's
+struct Light {
+    // Towards the sun
+    sun_direction: vec3<f32>,
+    sun_color: vec3<f32>,
+    ambient: vec3<f32>,
+};
+@group(3) @binding(0)
+var<uniform> light: Light;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) lightmap_coords: vec2<f32>,
+    @location(2) world_position: vec3<f32>,
+    // From the surface to the camera
+    @location(3) to_eye: vec3<f32>,
+};
+
+@vertex
+fn vs_main(model: VertexInput, instance: InstanceInput, baked: LightmapInput) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+    out.lightmap_coords = baked.lightmap_coords;
+    out.world_position = world_position.xyz;
+    out.to_eye = camera.eye.xyz - world_position.xyz;
+    out.clip_position = camera.view_proj * world_position;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    let baked = textureSample(t_lightmap, s_lightmap, in.lightmap_coords).rgb * lightmap.intensity;
+
+    // The triangle's own normal, turned towards the camera since both sides
+    // of a surface get drawn. Only the realtime sun needs it, irradiance
+    // lightmaps have the sun baked in already.
+    let face = normalize(cross(dpdx(in.world_position), dpdy(in.world_position)));
+    let normal = select(-face, face, dot(face, in.to_eye) >= 0.0);
+    let realtime = max(dot(normal, light.sun_direction), 0.0) * light.sun_color + baked * light.ambient;
+
+    let lighting = mix(baked, realtime, lightmap.occlusion);
+    return vec4<f32>(color.rgb * lighting, color.a);
+}
@@ -0,0 +1,41 @@
+// Coloured lines in world space, for gizmos and debug drawing
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Time, resolution and mouse, the same for every pipeline
+struct Frame {
+    time: f32,
+    delta_time: f32,
+    resolution: vec2<f32>,
+    mouse: vec2<f32>,
+    frame: u32,
+};
+@group(1) @binding(0)
+var<uniform> frame: Frame;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(4) color: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+}
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(in.position, 1.0);
+    out.color = in.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
@@ -13,6 +13,17 @@ struct CameraUniform {
 @group(1) @binding(0) 
 var<uniform> camera: CameraUniform;
 
+// Time, resolution and mouse, the same for every pipeline
+struct Frame {
+    time: f32,
+    delta_time: f32,
+    resolution: vec2<f32>,
+    mouse: vec2<f32>,
+    frame: u32,
+};
+@group(2) @binding(0)
+var<uniform> frame: Frame;
+
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
@@ -0,0 +1,68 @@
+// Mirrors, see mirror.rs. Drawn over the mirror's own surface after the main
+// pass, showing what the mirrored camera saw at the same place on screen.
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Time, resolution and mouse, the same for every pipeline
+struct Frame {
+    time: f32,
+    delta_time: f32,
+    resolution: vec2<f32>,
+    mouse: vec2<f32>,
+    frame: u32,
+};
+@group(1) @binding(0)
+var<uniform> frame: Frame;
+
+struct Mirror {
+    // How much of the reflection covers the surface's own colour
+    reflectivity: f32,
+};
+@group(2) @binding(0)
+var<uniform> mirror: Mirror;
+@group(2) @binding(1)
+var t_reflection: texture_2d<f32>;
+@group(2) @binding(2)
+var s_reflection: sampler;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+@vertex
+fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    var out: VertexOutput;
+    // Worked out exactly like mesh.wgsl does, so the depth matches what the
+    // main pass left and the equal test passes
+    out.clip_position = camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let uv = in.clip_position.xy / frame.resolution;
+    let reflection = textureSample(t_reflection, s_reflection, uv).rgb;
+    return vec4<f32>(reflection, mirror.reflectivity);
+}
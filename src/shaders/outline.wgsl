@@ -0,0 +1,101 @@
+// Outlines found in the depth buffer, see outline.rs. Every pixel compares
+// itself with four neighbours `thickness` pixels away diagonally: a big
+// jump in distance is an object's silhouette, a big change in normal is a
+// crease. Either one draws the outline colour over the scene.
+
+struct Outline {
+    inverse_view_proj: mat4x4<f32>,
+    color: vec4<f32>,
+    eye: vec3<f32>,
+    thickness: f32,
+    // As a fraction of the distance from the eye
+    depth_threshold: f32,
+    // 1 - cos of the angle between the normals
+    normal_threshold: f32,
+};
+@group(0) @binding(0)
+var<uniform> outline: Outline;
+@group(0) @binding(1)
+var t_scene: texture_2d<f32>;
+@group(0) @binding(2)
+var t_scene_depth: texture_2d<f32>;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    // (0, 0), (2, 0), (0, 2) in uv space, the parts off screen get clipped
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    return out;
+}
+
+// Where the surface seen through `pixel` is in the world
+fn world_position(pixel: vec2<i32>) -> vec3<f32> {
+    let size = vec2<i32>(textureDimensions(t_scene_depth));
+    let pixel = clamp(pixel, vec2<i32>(0), size - 1);
+    let depth = textureLoad(t_scene_depth, pixel, 0).r;
+    let uv = (vec2<f32>(pixel) + 0.5) / vec2<f32>(size);
+    let clip = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let world = outline.inverse_view_proj * clip;
+    return world.xyz / world.w;
+}
+
+// From the neighbours on each side that are closest in depth, so pixels on
+// an object's edge don't get a normal half way to whatever is behind it
+fn world_normal(pixel: vec2<i32>) -> vec3<f32> {
+    let center = world_position(pixel);
+    let left = world_position(pixel - vec2<i32>(1, 0));
+    let right = world_position(pixel + vec2<i32>(1, 0));
+    let up = world_position(pixel - vec2<i32>(0, 1));
+    let down = world_position(pixel + vec2<i32>(0, 1));
+    let eye = outline.eye;
+    let dx = select(right - center, center - left, abs(distance(left, eye) - distance(center, eye))
+        < abs(distance(right, eye) - distance(center, eye)));
+    let dy = select(down - center, center - up, abs(distance(up, eye) - distance(center, eye))
+        < abs(distance(down, eye) - distance(center, eye)));
+    return normalize(cross(dy, dx));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let scene = textureLoad(t_scene, pixel, 0);
+
+    let position = world_position(pixel);
+    let normal = world_normal(pixel);
+    let distance_to_eye = distance(position, outline.eye);
+    // Surfaces seen at a glancing angle get further away quickly from one
+    // pixel to the next without there being an edge, so they need a bigger
+    // jump before it counts
+    let facing = abs(dot(normal, normalize(outline.eye - position)));
+    let depth_threshold = outline.depth_threshold * distance_to_eye / max(facing, 0.1);
+
+    let step = i32(max(round(outline.thickness), 1.0));
+    var offsets = array<vec2<i32>, 4>(
+        vec2<i32>(-step, -step),
+        vec2<i32>(step, -step),
+        vec2<i32>(-step, step),
+        vec2<i32>(step, step),
+    );
+    var edge = 0.0;
+    for (var i = 0; i < 4; i = i + 1) {
+        let neighbour = pixel + offsets[i];
+        // Only the nearer side of a silhouette gets the line, otherwise it'd
+        // be drawn on whatever is behind as well
+        let jump = distance_to_eye - distance(world_position(neighbour), outline.eye);
+        let silhouette = smoothstep(depth_threshold, depth_threshold * 1.5, -jump);
+        // Across a silhouette the normals are of two different things, which
+        // would put a second line on the far side
+        let crease = 1.0 - dot(normal, world_normal(neighbour));
+        let creased = smoothstep(outline.normal_threshold, outline.normal_threshold * 1.5, crease)
+            * f32(abs(jump) < depth_threshold);
+        edge = max(edge, max(silhouette, creased));
+    }
+
+    let amount = edge * outline.color.a;
+    return vec4<f32>(mix(scene.rgb, outline.color.rgb, amount), scene.a);
+}
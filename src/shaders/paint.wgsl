@@ -0,0 +1,50 @@
+// Brush dabs painted into a canvas texture, see paint.rs. Every dab is a
+// square around its center, drawn as a round spot that fades out towards
+// its edge, blended over what's already there.
+
+struct DabInput {
+    // 0 to 1 across the texture, (0, 0) its first texel
+    @location(0) center: vec2<f32>,
+    @location(1) radius: f32,
+    @location(2) hardness: f32,
+    // The alpha is how much the dab covers at its middle
+    @location(3) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // -1 to 1 across the dab
+    @location(0) offset: vec2<f32>,
+    @location(1) hardness: f32,
+    @location(2) color: vec4<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32, dab: DabInput) -> VertexOutput {
+    // Two triangles, corners picked from the index
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(-1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+    );
+    let offset = corners[index];
+    let uv = dab.center + offset * dab.radius;
+
+    var out: VertexOutput;
+    // Texture rows go down, clip space y up
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.offset = offset;
+    out.hardness = dab.hardness;
+    out.color = dab.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let distance = length(in.offset);
+    let coverage = 1.0 - smoothstep(min(in.hardness, 0.999), 1.0, distance);
+    return vec4<f32>(in.color.rgb, in.color.a * coverage);
+}
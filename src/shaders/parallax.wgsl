@@ -0,0 +1,187 @@
+// Parallax occlusion mapping, see parallax.rs. Drawn like mesh.wgsl, except
+// the texture coordinates first get walked along the view ray down into the
+// height map.
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    eye: vec4<f32>,
+};
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct Parallax {
+    // How far below the top black in the height map is, in texture
+    // coordinates
+    depth: f32,
+    min_steps: f32,
+    max_steps: f32,
+    self_shadowing: u32,
+};
+@group(0) @binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(0) @binding(1)
+var s_diffuse: sampler;
+@group(0) @binding(2)
+var t_height: texture_2d<f32>;
+@group(0) @binding(3)
+var<uniform> parallax: Parallax;
+
+// Shared with toon.wgsl, the sun's colour and the ambient light aren't used
+// here
+struct Light {
+    // Towards the sun
+    sun_direction: vec3<f32>,
+    sun_color: vec3<f32>,
+    ambient: vec3<f32>,
+};
+@group(3) @binding(0)
+var<uniform> light: Light;
+
+// How dark the surface gets where it's fully in its own shadow
+let SHADOW: f32 = 0.35;
+// How far under a bump (in the height map's 0 to 1) the shadow gets to full
+// strength, smaller is sharper
+let PENUMBRA: f32 = 0.1;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) world_position: vec3<f32>,
+    // From the surface to the camera
+    @location(2) to_eye: vec3<f32>,
+};
+
+@vertex
+fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+    out.world_position = world_position.xyz;
+    out.to_eye = camera.eye.xyz - world_position.xyz;
+    out.clip_position = camera.view_proj * world_position;
+    return out;
+}
+
+// How far below the top of the surface the height map is at `uv`, from 0 to
+// 1. The gradients are the ones at the pixel's own texture coordinates, so
+// the mip doesn't jump around where neighbouring pixels walk different
+// distances.
+fn depth_at(uv: vec2<f32>, dx: vec2<f32>, dy: vec2<f32>) -> f32 {
+    // Loaded as linear, so the heights come out as they were painted
+    let height = textureSampleGrad(t_height, s_diffuse, uv, dx, dy).r;
+    return 1.0 - height;
+}
+
+// How much of the sun reaches the height map at `uv`, `depth` down. Walks
+// from there towards the sun (`to_sun` in tangent space) and keeps the
+// furthest the height map rises above the ray, counting for less the further
+// away it is so shadows get soft edges.
+fn sun_visibility(uv: vec2<f32>, depth: f32, to_sun: vec3<f32>, dx: vec2<f32>, dy: vec2<f32>) -> f32 {
+    if (to_sun.z <= 0.0) {
+        return 0.0;
+    }
+    let steps = mix(parallax.max_steps, parallax.min_steps, to_sun.z);
+    let step_depth = depth / steps;
+    let uv_step = to_sun.xy / to_sun.z * parallax.depth * step_depth;
+
+    var occlusion = 0.0;
+    var i = 1.0;
+    loop {
+        if (i >= steps) {
+            break;
+        }
+        let ray_depth = depth - step_depth * i;
+        let above = ray_depth - depth_at(uv + uv_step * i, dx, dy);
+        occlusion = max(occlusion, above * (1.0 - i / steps));
+        i = i + 1.0;
+    }
+    return 1.0 - clamp(occlusion / PENUMBRA, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let dx = dpdx(in.tex_coords);
+    let dy = dpdy(in.tex_coords);
+
+    // The tangent frame, with the tangent and bitangent pointing the way u
+    // and v grow across the surface. Worked out from how the position and
+    // texture coordinates change between neighbouring pixels, after
+    // Christian Schüler's "Normal Mapping Without Precomputed Tangents".
+    let dpx = dpdx(in.world_position);
+    let dpy = dpdy(in.world_position);
+    let normal = normalize(cross(dpx, dpy));
+    let dpy_perp = cross(dpy, normal);
+    let dpx_perp = cross(normal, dpx);
+    var tangent = dpy_perp * dx.x + dpx_perp * dy.x;
+    var bitangent = dpy_perp * dx.y + dpx_perp * dy.y;
+    let scale = inverseSqrt(max(max(dot(tangent, tangent), dot(bitangent, bitangent)), 1e-20));
+    tangent = tangent * scale;
+    bitangent = bitangent * scale;
+    let to_eye = normalize(in.to_eye);
+    // Whichever side is being looked at is the top
+    let facing = select(-normal, normal, dot(normal, to_eye) >= 0.0);
+    let view = vec3<f32>(dot(tangent, to_eye), dot(bitangent, to_eye), dot(facing, to_eye));
+
+    // Step down through the height map until the ray is under it. Grazing
+    // angles cross more of the texture, so they take more steps.
+    let steps = mix(parallax.max_steps, parallax.min_steps, clamp(view.z, 0.0, 1.0));
+    let step_depth = 1.0 / steps;
+    let uv_step = view.xy / max(view.z, 0.05) * parallax.depth * step_depth;
+    var uv = in.tex_coords;
+    var ray_depth = 0.0;
+    var surface_depth = depth_at(uv, dx, dy);
+    var previous_depth = surface_depth;
+    var i = 0.0;
+    loop {
+        if (ray_depth >= surface_depth || i >= steps) {
+            break;
+        }
+        previous_depth = surface_depth;
+        uv = uv - uv_step;
+        ray_depth = ray_depth + step_depth;
+        surface_depth = depth_at(uv, dx, dy);
+        i = i + 1.0;
+    }
+
+    // The height map was crossed somewhere between the last two steps,
+    // assume it's a straight line in between
+    var depth = ray_depth;
+    if (i > 0.0) {
+        let after = surface_depth - ray_depth;
+        let before = previous_depth - (ray_depth - step_depth);
+        let weight = clamp(after / (after - before), 0.0, 1.0);
+        uv = uv + uv_step * weight;
+        depth = ray_depth - step_depth * weight;
+    }
+
+    var color = textureSampleGrad(t_diffuse, s_diffuse, uv, dx, dy);
+    if (parallax.self_shadowing != 0u) {
+        let to_sun = normalize(vec3<f32>(
+            dot(tangent, light.sun_direction),
+            dot(bitangent, light.sun_direction),
+            dot(facing, light.sun_direction),
+        ));
+        let visibility = sun_visibility(uv, depth, to_sun, dx, dy);
+        color = vec4<f32>(color.rgb * mix(SHADOW, 1.0, visibility), color.a);
+    }
+    return color;
+}
@@ -0,0 +1,81 @@
+// Particles as quads turned to face the camera, see particles.rs. There's
+// no vertex buffer, each quad's corners come from the vertex index.
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    eye: vec4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Time, resolution and mouse, the same for every pipeline
+struct Frame {
+    time: f32,
+    delta_time: f32,
+    resolution: vec2<f32>,
+    mouse: vec2<f32>,
+    frame: u32,
+};
+@group(1) @binding(0)
+var<uniform> frame: Frame;
+
+@group(2) @binding(0)
+var t_particle: texture_2d<f32>;
+@group(2) @binding(1)
+var s_particle: sampler;
+
+struct InstanceInput {
+    @builtin(vertex_index) index: u32,
+    @location(0) position: vec3<f32>,
+    @location(1) size: f32,
+    @location(2) color: vec4<f32>,
+    // Radians around the line to the camera
+    @location(3) rotation: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+@vertex
+fn vs_main(in: InstanceInput) -> VertexOutput {
+    // Two triangles, from -0.5 to 0.5 on both axes
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-0.5, -0.5),
+        vec2<f32>(0.5, -0.5),
+        vec2<f32>(0.5, 0.5),
+        vec2<f32>(-0.5, -0.5),
+        vec2<f32>(0.5, 0.5),
+        vec2<f32>(-0.5, 0.5),
+    );
+    let corner = corners[in.index];
+
+    // Facing the eye rather than lying flat on the screen, so particles
+    // don't swing around when the camera turns
+    let to_eye = normalize(camera.eye.xyz - in.position);
+    var right = cross(vec3<f32>(0.0, 1.0, 0.0), to_eye);
+    // Straight above or below, any direction across will do
+    if (dot(right, right) < 0.0001) {
+        right = vec3<f32>(1.0, 0.0, 0.0);
+    }
+    right = normalize(right);
+    let up = cross(to_eye, right);
+
+    let c = cos(in.rotation);
+    let s = sin(in.rotation);
+    let turned = vec2<f32>(corner.x * c - corner.y * s, corner.x * s + corner.y * c);
+    let position = in.position + (right * turned.x + up * turned.y) * in.size;
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(position, 1.0);
+    out.tex_coords = vec2<f32>(corner.x + 0.5, 0.5 - corner.y);
+    out.color = in.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(t_particle, s_particle, in.tex_coords) * in.color;
+}
@@ -0,0 +1,29 @@
+// Scales the small picture pixel art mode draws up onto the surface, see
+// pixel_art.rs. The sampler is nearest neighbour, so every pixel of the
+// picture turns into a hard edged block.
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+@group(0) @binding(0)
+var t_picture: texture_2d<f32>;
+@group(0) @binding(1)
+var s_picture: sampler;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    // (0, 0), (2, 0), (0, 2) in uv space, the parts outside the viewport get
+    // clipped
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(t_picture, s_picture, in.uv);
+}
@@ -0,0 +1,96 @@
+// Glossy surfaces, see probe.rs. Drawn over the surface after the main pass,
+// showing the closest reflection probe's cubemap in the direction the view
+// bounces off it, more of it at grazing angles.
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    eye: vec4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct Gloss {
+    // Where the probe is, and half the size of the box around it
+    position: vec3<f32>,
+    // How much of the reflection covers the surface looking straight at it
+    reflectivity: f32,
+    extents: vec3<f32>,
+};
+@group(1) @binding(0)
+var<uniform> gloss: Gloss;
+@group(1) @binding(1)
+var t_probe: texture_cube<f32>;
+@group(1) @binding(2)
+var s_probe: sampler;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    // From the surface to the camera
+    @location(1) to_eye: vec3<f32>,
+};
+
+@vertex
+fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+
+    var out: VertexOutput;
+    // Worked out exactly like mesh.wgsl does, so the depth matches what the
+    // main pass left and the equal test passes
+    out.clip_position = camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+    out.world_position = world_position.xyz;
+    out.to_eye = camera.eye.xyz - world_position.xyz;
+    return out;
+}
+
+// Which way to look up the cubemap for a reflection leaving `position` in
+// `direction`. Inside the probe's box that's towards where the reflection
+// hits the box's walls, as seen from the probe. Without a box, or outside
+// it, everything is taken to be infinitely far away.
+fn parallax_corrected(position: vec3<f32>, direction: vec3<f32>) -> vec3<f32> {
+    let box_min = gloss.position - gloss.extents;
+    let box_max = gloss.position + gloss.extents;
+    if (any(gloss.extents <= vec3<f32>(0.0)) || any(position < box_min) || any(position > box_max)) {
+        return direction;
+    }
+    // How far along the ray each pair of walls is, the one ahead of it is
+    // the further one, and the closest of those is where it leaves the box
+    let exits = max((box_max - position) / direction, (box_min - position) / direction);
+    let distance = min(min(exits.x, exits.y), exits.z);
+    return position + direction * distance - gloss.position;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // The triangle's own normal, turned towards the camera like toon.wgsl's
+    let view = normalize(in.to_eye);
+    let face = normalize(cross(dpdx(in.world_position), dpdy(in.world_position)));
+    let normal = select(-face, face, dot(face, view) >= 0.0);
+
+    let direction = parallax_corrected(in.world_position, reflect(-view, normal));
+    let reflection = textureSample(t_probe, s_probe, direction).rgb;
+
+    // Schlick's approximation of the Fresnel term, with the reflectivity as
+    // how much is reflected head on
+    let grazing = pow(1.0 - max(dot(normal, view), 0.0), 5.0);
+    let fresnel = gloss.reflectivity + (1.0 - gloss.reflectivity) * grazing;
+    return vec4<f32>(reflection, fresnel);
+}
@@ -0,0 +1,23 @@
+// Goes in front of the shader given to Shadertoy. That shader has to define
+//
+//     fn main_image(frag_coord: vec2<f32>) -> vec4<f32>
+//
+// which gets called for every pixel, frag_coord is in physical pixels from
+// the top left corner.
+
+struct Frame {
+    time: f32,
+    delta_time: f32,
+    resolution: vec2<f32>,
+    mouse: vec2<f32>,
+    frame: u32,
+};
+@group(0) @binding(0)
+var<uniform> frame: Frame;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    // One triangle that covers the whole screen
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+}
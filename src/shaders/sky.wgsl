@@ -0,0 +1,79 @@
+// The Preetham sky, see sky.rs. A cube around the camera, pushed out to the
+// far plane, with the sky's colour worked out per pixel from the direction
+// it's looked at in.
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Perez's distribution function has five coefficients, a to e, one set each
+// for luminance (Y) and chromaticity (x and y), kept in the three lanes
+struct Sky {
+    a: vec3<f32>,
+    // Scales the sky's luminance into the scene's HDR units
+    intensity: f32,
+    b: vec3<f32>,
+    // Cosine of the sun disk's angular radius
+    sun_size: f32,
+    c: vec3<f32>,
+    d: vec3<f32>,
+    e: vec3<f32>,
+    // Zenith Y, x and y, already divided by the distribution at the zenith
+    zenith: vec3<f32>,
+    // Towards the sun
+    sun_direction: vec3<f32>,
+    sun_color: vec3<f32>,
+};
+@group(1) @binding(0)
+var<uniform> sky: Sky;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) direction: vec3<f32>,
+};
+
+@vertex
+fn vs_main(@location(0) position: vec3<f32>) -> VertexOutput {
+    var out: VertexOutput;
+    // w of 0 leaves out the camera's position, so the sky is infinitely far
+    // away. z of w puts it on the far plane, behind everything else.
+    let clip = camera.view_proj * vec4<f32>(position, 0.0);
+    out.clip_position = clip.xyww;
+    out.direction = position;
+    return out;
+}
+
+fn perez(cos_theta: f32, gamma: f32, cos_gamma: f32) -> vec3<f32> {
+    return (1.0 + sky.a * exp(sky.b / cos_theta)) * (1.0 + sky.c * exp(sky.d * gamma) + sky.e * cos_gamma * cos_gamma);
+}
+
+fn xyy_to_rgb(xyy: vec3<f32>) -> vec3<f32> {
+    let y = xyy.x;
+    let xyz = vec3<f32>(xyy.y / xyy.z * y, y, (1.0 - xyy.y - xyy.z) / xyy.z * y);
+    return vec3<f32>(
+        3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z,
+        -0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z,
+        0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z,
+    );
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let direction = normalize(in.direction);
+    // The model only covers the sky, below the horizon it carries on with the
+    // horizon's colour, darkening towards the ground
+    let above = vec3<f32>(direction.x, max(direction.y, 0.001), direction.z);
+    let view = normalize(above);
+    let cos_gamma = clamp(dot(view, sky.sun_direction), -1.0, 1.0);
+    let gamma = acos(cos_gamma);
+    let xyy = sky.zenith * perez(view.y, gamma, cos_gamma);
+    var color = max(xyy_to_rgb(xyy), vec3<f32>(0.0)) * sky.intensity;
+    color = color * mix(1.0, 0.3, smoothstep(0.0, -0.2, direction.y));
+
+    // The sun itself, softened at the edge
+    let disk = smoothstep(sky.sun_size - 0.00002, sky.sun_size, dot(direction, sky.sun_direction));
+    color = color + sky.sun_color * disk * 20.0;
+    return vec4<f32>(color, 1.0);
+}
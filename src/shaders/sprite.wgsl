@@ -0,0 +1,49 @@
+// Textured, tinted quads in logical pixels, drawn on top of the scene
+
+struct Globals {
+    projection: mat4x4<f32>,
+};
+@group(1) @binding(0)
+var<uniform> globals: Globals;
+
+// Time, resolution and mouse, the same for every pipeline
+struct Frame {
+    time: f32,
+    delta_time: f32,
+    resolution: vec2<f32>,
+    mouse: vec2<f32>,
+    frame: u32,
+};
+@group(2) @binding(0)
+var<uniform> frame: Frame;
+
+struct VertexInput {
+    @location(0) position: vec2<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) color: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = globals.projection * vec4<f32>(in.position, 0.0, 1.0);
+    out.tex_coords = in.tex_coords;
+    out.color = in.color;
+    return out;
+}
+
+@group(0) @binding(0)
+var t_sprite: texture_2d<f32>;
+@group(0) @binding(1)
+var s_sprite: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(t_sprite, s_sprite, in.tex_coords) * in.color;
+}
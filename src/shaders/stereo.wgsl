@@ -0,0 +1,94 @@
+// Stereo rendering, see stereo.rs. The scene part is mesh.wgsl drawn into
+// both eyes' layers at once, view_index says which one a vertex is for.
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+// Left eye first
+struct StereoCamera {
+    view_proj: array<mat4x4<f32>, 2>,
+};
+@group(1) @binding(0)
+var<uniform> camera: StereoCamera;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+@vertex
+fn vs_main(model: VertexInput, instance: InstanceInput, @builtin(view_index) view: i32) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+    out.clip_position = camera.view_proj[view] * model_matrix * vec4<f32>(model.position, 1.0);
+    return out;
+}
+
+@group(0) @binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(0) @binding(1)
+var s_diffuse: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
+}
+
+// Putting the two eyes together into the scene target, with one triangle
+// covering it
+
+struct CompositeOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_composite(@builtin(vertex_index) index: u32) -> CompositeOutput {
+    // (0, 0), (2, 0), (0, 2) in uv space, the parts off screen get clipped
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: CompositeOutput;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+// Bindings of their own, so they don't clash with the material's
+@group(0) @binding(2)
+var t_eyes: texture_2d_array<f32>;
+@group(0) @binding(3)
+var s_eyes: sampler;
+
+// The left eye on the left half, the right eye on the right
+@fragment
+fn fs_side_by_side(in: CompositeOutput) -> @location(0) vec4<f32> {
+    let right = i32(in.uv.x >= 0.5);
+    let uv = vec2<f32>(fract(in.uv.x * 2.0), in.uv.y);
+    return textureSample(t_eyes, s_eyes, uv, right);
+}
+
+// Red and cyan glasses. The left eye's brightness goes into red, the right
+// eye's colour into green and blue, which keeps some colour without the two
+// eyes seeing each other much.
+@fragment
+fn fs_anaglyph(in: CompositeOutput) -> @location(0) vec4<f32> {
+    let left = textureSample(t_eyes, s_eyes, in.uv, 0).rgb;
+    let right = textureSample(t_eyes, s_eyes, in.uv, 1).rgb;
+    let brightness = dot(left, vec3<f32>(0.299, 0.587, 0.114));
+    return vec4<f32>(brightness, right.g, right.b, 1.0);
+}
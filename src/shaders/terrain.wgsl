@@ -0,0 +1,123 @@
+// Heightmap terrain, see terrain.rs. Every chunk of the quadtree is the same
+// grid mesh, moved and scaled into place by its instance and lifted by the
+// heightmap here.
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct Terrain {
+    // World position of the heightmap's first texel at height 0
+    origin: vec3<f32>,
+    // Width and depth in world units
+    size: f32,
+    // What a height of 1 comes out as
+    height: f32,
+    // The most the height changes per world unit, for sizing skirts
+    max_slope: f32,
+    // Quads along each side of a chunk
+    resolution: f32,
+    // Towards the sun
+    sun_direction: vec3<f32>,
+    sun_color: vec3<f32>,
+    // Light from the sky reaching a surface facing up
+    ambient: vec3<f32>,
+};
+@group(1) @binding(0)
+var t_height: texture_2d<f32>;
+@group(1) @binding(1)
+var<uniform> terrain: Terrain;
+// Painted layers, grass, rock and dirt in red, green and blue, alpha how
+// much they cover. See Terrain::splat_map().
+@group(1) @binding(2)
+var t_splat: texture_2d<f32>;
+@group(1) @binding(3)
+var s_splat: sampler;
+
+struct VertexInput {
+    // 0 to 1 across the chunk
+    @location(0) position: vec2<f32>,
+    // 1 for the skirt hanging down from the chunk's edges
+    @location(1) skirt: f32,
+};
+
+struct ChunkInput {
+    // Where the chunk starts and how much of the terrain it covers, both 0
+    // to 1 across the whole terrain
+    @location(2) offset: vec2<f32>,
+    @location(3) scale: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) normal: vec3<f32>,
+    // 0 at the bottom of the heightmap's range, 1 at the top
+    @location(1) height: f32,
+    // 0 to 1 across the whole terrain
+    @location(2) uv: vec2<f32>,
+};
+
+// Bilinear, done by hand since 32 bit float textures can't be filtered
+// everywhere
+fn height_at(uv: vec2<f32>) -> f32 {
+    let size = vec2<f32>(textureDimensions(t_height));
+    let texel = clamp(uv * (size - 1.0), vec2<f32>(0.0), size - 1.0);
+    let base = vec2<i32>(floor(texel));
+    let last = vec2<i32>(size) - 1;
+    let t = fract(texel);
+    let h00 = textureLoad(t_height, base, 0).r;
+    let h10 = textureLoad(t_height, min(base + vec2<i32>(1, 0), last), 0).r;
+    let h01 = textureLoad(t_height, min(base + vec2<i32>(0, 1), last), 0).r;
+    let h11 = textureLoad(t_height, min(base + vec2<i32>(1, 1), last), 0).r;
+    return mix(mix(h00, h10, t.x), mix(h01, h11, t.x), t.y);
+}
+
+fn normal_at(uv: vec2<f32>) -> vec3<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_height));
+    let dx = height_at(uv + vec2<f32>(texel.x, 0.0)) - height_at(uv - vec2<f32>(texel.x, 0.0));
+    let dz = height_at(uv + vec2<f32>(0.0, texel.y)) - height_at(uv - vec2<f32>(0.0, texel.y));
+    let step = 2.0 * texel * terrain.size;
+    return normalize(vec3<f32>(-dx * terrain.height / step.x, 1.0, -dz * terrain.height / step.y));
+}
+
+@vertex
+fn vs_main(vertex: VertexInput, chunk: ChunkInput) -> VertexOutput {
+    let uv = chunk.offset + vertex.position * chunk.scale;
+    let height = height_at(uv);
+    var world = terrain.origin + vec3<f32>(uv.x * terrain.size, height * terrain.height, uv.y * terrain.size);
+    // Where a bigger neighbour meets this chunk its edge can be off by as
+    // much as the height changes over one of its quads, so the skirt reaches
+    // down that far to cover the crack
+    let cell = chunk.scale * terrain.size / terrain.resolution;
+    world.y = world.y - vertex.skirt * (cell * 2.0 * terrain.max_slope + 0.01);
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(world, 1.0);
+    out.normal = normal_at(uv);
+    out.height = height;
+    out.uv = uv;
+    return out;
+}
+
+let GRASS: vec3<f32> = vec3<f32>(0.13, 0.26, 0.06);
+let ROCK: vec3<f32> = vec3<f32>(0.25, 0.23, 0.21);
+let SNOW: vec3<f32> = vec3<f32>(0.8, 0.82, 0.85);
+let DIRT: vec3<f32> = vec3<f32>(0.3, 0.2, 0.12);
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let normal = normalize(in.normal);
+    // Steep slopes are bare rock, high and flat enough ones get snow
+    var albedo = mix(ROCK, GRASS, smoothstep(0.7, 0.85, normal.y));
+    albedo = mix(albedo, SNOW, smoothstep(0.7, 0.8, in.height) * smoothstep(0.6, 0.75, normal.y));
+    // Painted layers on top, weighted by how much of each went down
+    let splat = textureSample(t_splat, s_splat, in.uv);
+    let painted = (splat.r * GRASS + splat.g * ROCK + splat.b * DIRT) / max(splat.r + splat.g + splat.b, 0.0001);
+    albedo = mix(albedo, painted, splat.a);
+    let diffuse = max(dot(normal, terrain.sun_direction), 0.0) * terrain.sun_color;
+    // Less of the sky's light reaches surfaces facing sideways
+    let ambient = terrain.ambient * (0.5 + 0.5 * normal.y);
+    return vec4<f32>(albedo * (diffuse + ambient), 1.0);
+}
@@ -0,0 +1,100 @@
+// Brings the HDR scene onto the screen with one triangle that covers all of
+// it: scaled by the exposure, then squeezed into 0 to 1 by a curve
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    // (0, 0), (2, 0), (0, 2) in uv space, the parts off screen get clipped
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    return out;
+}
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+
+// Time, resolution and mouse, the same for every pipeline
+struct Frame {
+    time: f32,
+    delta_time: f32,
+    resolution: vec2<f32>,
+    mouse: vec2<f32>,
+    frame: u32,
+};
+@group(1) @binding(0)
+var<uniform> frame: Frame;
+
+struct Tonemap {
+    // Written by the eye adaptation pass when exposure is automatic
+    exposure: f32,
+    // 0 clamps, 1 is Reinhard and 2 ACES, see Tonemapping in app.rs
+    curve: u32,
+    dither: u32,
+    // Whether the target turns what's written into sRGB itself
+    srgb: u32,
+};
+@group(2) @binding(0)
+var<uniform> tonemap: Tonemap;
+
+// Narkowicz's fit of the ACES filmic curve
+fn aces(x: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((x * (a * x + b)) / (x * (c * x + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+fn linear_to_srgb(c: vec3<f32>) -> vec3<f32> {
+    return select(1.055 * pow(c, vec3<f32>(1.0 / 2.4)) - 0.055, c * 12.92, c <= vec3<f32>(0.0031308));
+}
+
+fn srgb_to_linear(c: vec3<f32>) -> vec3<f32> {
+    return select(pow((c + 0.055) / 1.055, vec3<f32>(2.4)), c / 12.92, c <= vec3<f32>(0.04045));
+}
+
+// Jimenez's interleaved gradient noise, 0 to 1. Close enough to blue noise
+// that the dithering doesn't look like a pattern, without needing a texture.
+fn interleaved_gradient_noise(pixel: vec2<f32>) -> f32 {
+    return fract(52.9829189 * fract(dot(pixel, vec2<f32>(0.06711056, 0.00583715))));
+}
+
+// Moves `color` by up to half a step of an 8 bit target either way, so
+// gradients get rounded up in some pixels and down in others instead of in
+// bands. The steps are even in the target's encoding, which is sRGB for
+// sRGB targets.
+fn dither(color: vec3<f32>, pixel: vec2<f32>) -> vec3<f32> {
+    let noise = (interleaved_gradient_noise(pixel) - 0.5) / 255.0;
+    if (tonemap.srgb != 0u) {
+        return srgb_to_linear(max(linear_to_srgb(color) + noise, vec3<f32>(0.0)));
+    }
+    return max(color + noise, vec3<f32>(0.0));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Same size as the target, so every pixel reads exactly one texel
+    let source = textureLoad(t_source, vec2<i32>(in.clip_position.xy), 0);
+    let color = max(source.rgb * tonemap.exposure, vec3<f32>(0.0));
+    var mapped: vec3<f32>;
+    switch (tonemap.curve) {
+        case 1u: {
+            mapped = color / (color + 1.0);
+        }
+        case 2u: {
+            mapped = aces(color);
+        }
+        default: {
+            mapped = min(color, vec3<f32>(1.0));
+        }
+    }
+    if (tonemap.dither != 0u) {
+        mapped = dither(mapped, in.clip_position.xy);
+    }
+    return vec4<f32>(mapped, source.a);
+}
@@ -0,0 +1,129 @@
+// Toon shading, see toon.rs. Lit by the sun like terrain.wgsl, except the
+// light gets rounded into a few flat bands, with a rim of light around the
+// silhouette. vs_outline and fs_outline draw the inverted hull outline.
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    eye: vec4<f32>,
+};
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct Toon {
+    rim_color: vec3<f32>,
+    // From 0 to 1, how far in from the silhouette the rim reaches
+    rim_width: f32,
+    outline_color: vec3<f32>,
+    // In world units
+    outline_width: f32,
+    bands: f32,
+    softness: f32,
+};
+@group(0) @binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(0) @binding(1)
+var s_diffuse: sampler;
+@group(0) @binding(2)
+var<uniform> toon: Toon;
+
+// The same as parallax.wgsl's
+struct Light {
+    // Towards the sun
+    sun_direction: vec3<f32>,
+    sun_color: vec3<f32>,
+    ambient: vec3<f32>,
+};
+@group(3) @binding(0)
+var<uniform> light: Light;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) world_position: vec3<f32>,
+    // From the surface to the camera
+    @location(2) to_eye: vec3<f32>,
+};
+
+fn model_matrix(instance: InstanceInput) -> mat4x4<f32> {
+    return mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+}
+
+@vertex
+fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let world_position = model_matrix(instance) * vec4<f32>(model.position, 1.0);
+
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+    out.world_position = world_position.xyz;
+    out.to_eye = camera.eye.xyz - world_position.xyz;
+    out.clip_position = camera.view_proj * world_position;
+    return out;
+}
+
+// Each band starts where the one before ends, `softness` of the way in
+fn banded(light: f32) -> f32 {
+    let scaled = light * toon.bands;
+    let blend = smoothstep(0.0, max(toon.softness, 0.0001), fract(scaled));
+    return (floor(scaled) + blend) / toon.bands;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+
+    // The triangle's own normal, turned towards the camera since both sides
+    // of a surface get drawn
+    let face = normalize(cross(dpdx(in.world_position), dpdy(in.world_position)));
+    let to_eye = normalize(in.to_eye);
+    let normal = select(-face, face, dot(face, to_eye) >= 0.0);
+
+    let diffuse = banded(max(dot(normal, light.sun_direction), 0.0)) * light.sun_color;
+    // Stepped as well, so it has a hard edge like the bands
+    let edge = 1.0 - dot(normal, to_eye);
+    let rim = smoothstep(1.0 - toon.rim_width, 1.0 - toon.rim_width + max(toon.softness, 0.0001), edge)
+        * f32(toon.rim_width > 0.0) * toon.rim_color;
+    return vec4<f32>(color.rgb * (diffuse + light.ambient) + rim, color.a);
+}
+
+@vertex
+fn vs_outline(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let transform = model_matrix(instance);
+    let world_position = transform * vec4<f32>(model.position, 1.0);
+    // Out from the object's origin, in world space so a stretched object's
+    // outline is still the same width all round
+    let outwards = world_position.xyz - transform[3].xyz;
+    let grown = select(
+        world_position.xyz,
+        world_position.xyz + normalize(outwards) * toon.outline_width,
+        length(outwards) > 0.0
+    );
+
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+    out.world_position = grown;
+    out.to_eye = camera.eye.xyz - grown;
+    out.clip_position = camera.view_proj * vec4<f32>(grown, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_outline(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(toon.outline_color, 1.0);
+}
@@ -0,0 +1,67 @@
+// Trail ribbons, see trail.rs. Both sides of the ribbon arrive at the point
+// they're on, and get pushed out across it here, at right angles to both the
+// ribbon and the line to the camera.
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    eye: vec4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Time, resolution and mouse, the same for every pipeline
+struct Frame {
+    time: f32,
+    delta_time: f32,
+    resolution: vec2<f32>,
+    mouse: vec2<f32>,
+    frame: u32,
+};
+@group(1) @binding(0)
+var<uniform> frame: Frame;
+
+@group(2) @binding(0)
+var t_trail: texture_2d<f32>;
+@group(2) @binding(1)
+var s_trail: sampler;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) direction: vec3<f32>,
+    @location(2) offset: f32,
+    @location(3) tex_coords: vec2<f32>,
+    @location(4) color: vec4<f32>,
+    @location(5) scroll: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    let to_eye = normalize(camera.eye.xyz - in.position);
+    var across = cross(in.direction, to_eye);
+    // Heading straight at the camera, or a lone point with no direction.
+    // It's seen end on then, any direction across will do.
+    if (dot(across, across) < 0.000001) {
+        across = cross(vec3<f32>(0.0, 1.0, 0.0), to_eye);
+    }
+    if (dot(across, across) < 0.000001) {
+        across = vec3<f32>(1.0, 0.0, 0.0);
+    }
+    let position = in.position + normalize(across) * in.offset;
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(position, 1.0);
+    out.tex_coords = vec2<f32>(in.tex_coords.x - frame.time * in.scroll, in.tex_coords.y);
+    out.color = in.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(t_trail, s_trail, in.tex_coords) * in.color;
+}
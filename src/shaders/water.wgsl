@@ -0,0 +1,135 @@
+// A water surface, see water.rs. Gerstner waves move the vertices, and the
+// surface blends between the reflection and refraction passes' renders of
+// the scene depending on how steeply it's looked at.
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Time, resolution and mouse, the same for every pipeline
+struct Frame {
+    time: f32,
+    delta_time: f32,
+    resolution: vec2<f32>,
+    mouse: vec2<f32>,
+    frame: u32,
+};
+@group(1) @binding(0)
+var<uniform> frame: Frame;
+
+struct Water {
+    // For turning the refraction pass's depth back into world positions
+    inverse_view_proj: mat4x4<f32>,
+    eye: vec3<f32>,
+    level: f32,
+    // Direction in xy, then wavelength and steepness
+    waves: array<vec4<f32>, 4>,
+    wave_count: u32,
+    // Towards the sun
+    sun_direction: vec3<f32>,
+    sun_color: vec3<f32>,
+    ambient: vec3<f32>,
+};
+@group(2) @binding(0)
+var<uniform> water: Water;
+@group(2) @binding(1)
+var t_reflection: texture_2d<f32>;
+@group(2) @binding(2)
+var t_refraction: texture_2d<f32>;
+@group(2) @binding(3)
+// Bound as a plain float texture, GL can't read depth textures any other
+// way without a comparison
+var t_refraction_depth: texture_2d<f32>;
+@group(2) @binding(4)
+var s_scene: sampler;
+
+struct VertexInput {
+    @location(0) position: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+};
+
+let PI: f32 = 3.14159265;
+let GRAVITY: f32 = 9.8;
+
+@vertex
+fn vs_main(vertex: VertexInput) -> VertexOutput {
+    let grid = vertex.position;
+    var position = vec3<f32>(grid.x, water.level, grid.y);
+    var tangent = vec3<f32>(1.0, 0.0, 0.0);
+    var binormal = vec3<f32>(0.0, 0.0, 1.0);
+    for (var i = 0u; i < water.wave_count; i = i + 1u) {
+        let wave = water.waves[i];
+        let direction = normalize(wave.xy);
+        let k = 2.0 * PI / wave.z;
+        // Deep water waves travel faster the longer they are
+        let speed = sqrt(GRAVITY / k);
+        let f = k * (dot(direction, grid) - speed * frame.time);
+        let steepness = wave.w;
+        let amplitude = steepness / k;
+        // Points move in circles, bunching up under the crests
+        position = position + vec3<f32>(
+            direction.x * amplitude * cos(f),
+            amplitude * sin(f),
+            direction.y * amplitude * cos(f),
+        );
+        tangent = tangent + vec3<f32>(
+            -direction.x * direction.x * steepness * sin(f),
+            direction.x * steepness * cos(f),
+            -direction.x * direction.y * steepness * sin(f),
+        );
+        binormal = binormal + vec3<f32>(
+            -direction.x * direction.y * steepness * sin(f),
+            direction.y * steepness * cos(f),
+            -direction.y * direction.y * steepness * sin(f),
+        );
+    }
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(position, 1.0);
+    out.world_position = position;
+    out.normal = normalize(cross(binormal, tangent));
+    return out;
+}
+
+let DEEP: vec3<f32> = vec3<f32>(0.01, 0.05, 0.08);
+// How much of each colour is left per world unit of water it goes through,
+// red goes first
+let ABSORPTION: vec3<f32> = vec3<f32>(0.45, 0.12, 0.08);
+// How far the waves bend what's seen through and in them, in screen space
+let DISTORTION: f32 = 0.03;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let normal = normalize(in.normal);
+    let view = normalize(water.eye - in.world_position);
+    let screen_uv = in.clip_position.xy / frame.resolution;
+    let offset = normal.xz * DISTORTION;
+
+    let reflection = textureSample(t_reflection, s_scene, screen_uv + offset).rgb;
+
+    // How deep the water is behind this point, from where the refraction
+    // pass hit the ground. Nothing there at all counts as very deep.
+    let refraction_uv = clamp(screen_uv + offset, vec2<f32>(0.0), vec2<f32>(1.0));
+    let depth_size = vec2<f32>(textureDimensions(t_refraction_depth));
+    let depth = textureLoad(t_refraction_depth, vec2<i32>(refraction_uv * (depth_size - 1.0)), 0).r;
+    let ndc = vec4<f32>(refraction_uv.x * 2.0 - 1.0, 1.0 - refraction_uv.y * 2.0, depth, 1.0);
+    let behind = water.inverse_view_proj * ndc;
+    let thickness = select(1000.0, distance(behind.xyz / behind.w, in.world_position), depth < 1.0);
+    let transmitted = exp(-ABSORPTION * thickness);
+    // Deep water is the colour of the light scattered back out of it
+    let scattered = DEEP * (water.sun_color * max(water.sun_direction.y, 0.0) + water.ambient);
+    let seen_through = textureSample(t_refraction, s_scene, refraction_uv).rgb;
+    let refraction = seen_through * transmitted + scattered * (1.0 - transmitted);
+
+    // Schlick's approximation, water reflects about 2% looking straight down
+    let fresnel = 0.02 + 0.98 * pow(1.0 - max(dot(normal, view), 0.0), 5.0);
+    let specular = pow(max(dot(reflect(-water.sun_direction, normal), view), 0.0), 256.0) * 4.0 * water.sun_color;
+    return vec4<f32>(mix(refraction, reflection, fresnel) + specular, 1.0);
+}
@@ -0,0 +1,234 @@
+use std::cell::Cell;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use winit::event::VirtualKeyCode;
+
+use crate::app::{App, RenderContext, Setup};
+use crate::events::KeyInput;
+use crate::hot_reload::FileWatcher;
+use crate::readback;
+use crate::render_graph::RenderGraph;
+use crate::shaders;
+
+const PRELUDE: &str = include_str!("shaders/shadertoy.wgsl");
+const ENTRY_POINT: &str = "
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    return main_image(position.xy);
+}
+";
+
+// Runs a single fragment shader over the whole window instead of the scene,
+// for playing around with shaders. It gets the frame uniform (see frame.rs
+// and shaders/shadertoy.wgsl), reloads whenever the file is saved and F12
+// saves a screenshot to screenshots/.
+//
+//     run_app(WindowConfig::new("shadertoy"), Shadertoy::new("toys/plasma.wgsl"))
+pub struct Shadertoy {
+    path: PathBuf,
+    watcher: Option<FileWatcher>,
+    format: wgpu::TextureFormat,
+    layout: Option<wgpu::PipelineLayout>,
+    // None until the shader compiles for the first time
+    pipeline: Option<wgpu::RenderPipeline>,
+    screenshot_requested: Rc<Cell<bool>>,
+    capture: Option<Capture>,
+}
+
+// A screenshot being drawn, read back at the start of the next frame
+struct Capture {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    size: (u32, u32),
+}
+
+impl Shadertoy {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        Self {
+            path: path.canonicalize().unwrap_or(path),
+            watcher: None,
+            format: wgpu::TextureFormat::Bgra8UnormSrgb,
+            layout: None,
+            pipeline: None,
+            screenshot_requested: Rc::new(Cell::new(false)),
+            capture: None,
+        }
+    }
+
+    fn load(&mut self, device: &wgpu::Device) {
+        let Some(layout) = &self.layout else {
+            return;
+        };
+        let source = match std::fs::read_to_string(&self.path) {
+            Ok(source) => source,
+            Err(e) => {
+                tracing::error!("Couldn't read {}: {}", self.path.display(), e);
+                return;
+            }
+        };
+        // Line numbers in errors count the prelude too
+        tracing::info!(
+            "Compiling {}, it starts at line {} of the shader",
+            self.path.display(),
+            PRELUDE.lines().count() + 1
+        );
+        let source = format!("{}{}{}", PRELUDE, source, ENTRY_POINT);
+        let format = self.format;
+        let pipeline = shaders::try_create(device, || {
+            let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Shadertoy Shader"),
+                source: wgpu::ShaderSource::Wgsl(source.into()),
+            });
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Shadertoy Pipeline"),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: &module,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &module,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        });
+        if pipeline.is_some() {
+            self.pipeline = pipeline;
+        }
+    }
+
+    fn start_capture(&mut self, device: &wgpu::Device, size: (u32, u32)) {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Screenshot Texture"),
+            size: wgpu::Extent3d {
+                width: size.0,
+                height: size.1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        });
+        self.capture = Some(Capture {
+            view: texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            texture,
+            size,
+        });
+    }
+
+    // Reads back the screenshot drawn last frame and writes it out
+    fn finish_capture(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let Some(capture) = self.capture.take() else {
+            return;
+        };
+        let image = match readback::read_texture(device, queue, &capture.texture, self.format, capture.size) {
+            Ok(image) => image,
+            Err(e) => {
+                tracing::error!("Failed to read the screenshot back: {:?}", e);
+                return;
+            }
+        };
+
+        let millis = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|time| time.as_millis())
+            .unwrap_or_default();
+        let path = PathBuf::from("screenshots").join(format!("shadertoy_{}.png", millis));
+        // Encoding a big png takes a moment, keep it off the render loop
+        std::thread::spawn(move || {
+            let saved = std::fs::create_dir_all("screenshots")
+                .map_err(anyhow::Error::from)
+                .and_then(|_| Ok(image.save(&path)?));
+            match saved {
+                Ok(()) => tracing::info!("Saved a screenshot to {}", path.display()),
+                Err(e) => tracing::error!("Failed to save the screenshot: {:?}", e),
+            }
+        });
+    }
+}
+
+impl App for Shadertoy {
+    fn init(&mut self, setup: &mut Setup) {
+        setup.settings.draw_scene = false;
+        self.format = setup.surface_format;
+        self.layout = Some(setup.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadertoy Pipeline Layout"),
+            bind_group_layouts: &[setup.frame_bind_group_layout],
+            push_constant_ranges: &[],
+        }));
+        self.load(setup.device);
+
+        let watched = self.path.parent().map(PathBuf::from).unwrap_or_default();
+        match FileWatcher::new(&watched) {
+            Ok(watcher) => self.watcher = Some(watcher),
+            Err(e) => tracing::warn!("Hot reloading is disabled: {:?}", e),
+        }
+
+        let requested = self.screenshot_requested.clone();
+        setup.events.subscribe(move |input: &KeyInput| {
+            if input.key == VirtualKeyCode::F12 && input.pressed {
+                requested.set(true);
+            }
+        });
+    }
+
+    fn render<'g>(&'g mut self, graph: &mut RenderGraph<'g>, context: RenderContext<'g>) {
+        if self.watcher.as_ref().is_some_and(|watcher| watcher.changed_files().contains(&self.path)) {
+            self.load(context.device);
+        }
+        // Last frame has been submitted by now
+        self.finish_capture(context.device, context.queue);
+        if self.screenshot_requested.take() && self.pipeline.is_some() {
+            self.start_capture(context.device, context.surface_size);
+        }
+
+        let this: &'g Self = self;
+        let Some(pipeline) = &this.pipeline else {
+            return;
+        };
+        let draw = move |encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView| {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Shadertoy Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, context.frame_bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        };
+
+        graph.add_pass("shadertoy").writes(&["surface"]).execute(move |encoder, resources| {
+            draw(encoder, resources.view("surface"));
+        });
+
+        // Drawn again into a texture we're allowed to copy from, the surface
+        // can only be rendered to
+        if let Some(capture) = &this.capture {
+            graph.import("screenshot", &capture.view);
+            graph.add_pass("screenshot").writes(&["screenshot"]).execute(move |encoder, resources| {
+                draw(encoder, resources.view("screenshot"));
+            });
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use glam::{EulerRot, Quat, Vec3};
+
+use crate::noise::perlin;
+use crate::transform::Transform;
+
+// Camera shake driven by trauma: hits, explosions and the like add_shake(),
+// which piles up to at most 1 and wears off over time. The camera is turned
+// and moved by noise scaled by the square of the trauma, so small knocks
+// stay subtle and big ones are violent, and it all settles down smoothly.
+//
+// The main camera's is RenderSettings::camera_shake. It's applied to the
+// view after the camera controller has moved the camera, without moving the
+// camera itself, so the controller carries on from where it was.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraShake {
+    // How far the camera turns at full trauma, in radians, around its own
+    // x (pitch), y (yaw) and z (roll) axes
+    pub max_angle: Vec3,
+    // How far it moves at full trauma, in world units along its own axes
+    pub max_offset: Vec3,
+    // How quickly it shakes, in noise cycles per second
+    pub frequency: f32,
+    // How much trauma wears off every second
+    pub recovery: f32,
+    trauma: f32,
+    // Seconds since the shaking started, for the noise
+    time: f32,
+}
+
+impl Default for CameraShake {
+    fn default() -> Self {
+        Self {
+            max_angle: Vec3::new(0.05, 0.05, 0.1),
+            max_offset: Vec3::new(0.1, 0.1, 0.0),
+            frequency: 15.0,
+            recovery: 1.0,
+            trauma: 0.0,
+            time: 0.0,
+        }
+    }
+}
+
+impl CameraShake {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_angle(mut self, max_angle: Vec3) -> Self {
+        self.max_angle = max_angle;
+        self
+    }
+
+    pub fn with_max_offset(mut self, max_offset: Vec3) -> Self {
+        self.max_offset = max_offset;
+        self
+    }
+
+    pub fn with_frequency(mut self, frequency: f32) -> Self {
+        self.frequency = frequency;
+        self
+    }
+
+    pub fn with_recovery(mut self, recovery: f32) -> Self {
+        self.recovery = recovery;
+        self
+    }
+
+    // Adds trauma, from 0 for nothing to 1 for as much as the camera shakes
+    pub fn add_shake(&mut self, amount: f32) {
+        self.trauma = (self.trauma + amount.max(0.0)).min(1.0);
+    }
+
+    pub fn trauma(&self) -> f32 {
+        self.trauma
+    }
+
+    // Stops shaking straight away
+    pub fn stop(&mut self) {
+        self.trauma = 0.0;
+    }
+
+    // Moves the noise on and lets trauma wear off, once a frame
+    pub fn update(&mut self, dt: f32) {
+        self.trauma = (self.trauma - self.recovery.max(0.0) * dt).max(0.0);
+        // Starts over while still, so the time never gets big enough to
+        // lose precision
+        self.time = if self.trauma > 0.0 { self.time + dt } else { 0.0 };
+    }
+
+    // How far the camera is thrown off right now, in its own space
+    pub fn offset(&self) -> Transform {
+        if self.trauma <= 0.0 {
+            return Transform::IDENTITY;
+        }
+        let shake = self.trauma * self.trauma;
+        let t = self.time * self.frequency;
+        // Every axis follows noise of its own
+        let noise = |seed| Vec3::new(perlin(t, seed), perlin(t, seed + 1), perlin(t, seed + 2));
+        let angles = self.max_angle * noise(0) * shake;
+        let offset = self.max_offset * noise(3) * shake;
+        let rotation = Quat::from_euler(EulerRot::YXZ, angles.y, angles.x, angles.z);
+        Transform::from_translation(offset).with_rotation(rotation)
+    }
+}
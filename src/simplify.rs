@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+
+use glam::{DVec3, Vec3};
+
+// Mesh simplification by edge collapse, the way meshoptimizer does it: every
+// collapse moves one vertex onto a neighbour, picking the ones that change the
+// surface the least first. The cost comes from the error quadrics of the
+// planes around each vertex (Garland and Heckbert).
+//
+// Vertices are never moved, only merged into each other, so the result still
+// indexes into the original vertex buffer and UVs stay where they were.
+// Vertices on UV seams and open edges are left alone so the outline and
+// texture layout survive.
+
+// The sum of squared distances to a set of planes, as a symmetric 4x4 matrix
+#[derive(Clone, Copy, Debug, Default)]
+struct Quadric {
+    a2: f64,
+    ab: f64,
+    ac: f64,
+    ad: f64,
+    b2: f64,
+    bc: f64,
+    bd: f64,
+    c2: f64,
+    cd: f64,
+    d2: f64,
+}
+
+impl Quadric {
+    fn from_plane(normal: DVec3, d: f64, weight: f64) -> Self {
+        let (a, b, c) = (normal.x, normal.y, normal.z);
+        Self {
+            a2: a * a * weight,
+            ab: a * b * weight,
+            ac: a * c * weight,
+            ad: a * d * weight,
+            b2: b * b * weight,
+            bc: b * c * weight,
+            bd: b * d * weight,
+            c2: c * c * weight,
+            cd: c * d * weight,
+            d2: d * d * weight,
+        }
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        self.a2 += other.a2;
+        self.ab += other.ab;
+        self.ac += other.ac;
+        self.ad += other.ad;
+        self.b2 += other.b2;
+        self.bc += other.bc;
+        self.bd += other.bd;
+        self.c2 += other.c2;
+        self.cd += other.cd;
+        self.d2 += other.d2;
+    }
+
+    fn error(&self, p: DVec3) -> f64 {
+        let (x, y, z) = (p.x, p.y, p.z);
+        let error = self.a2 * x * x + 2.0 * self.ab * x * y + 2.0 * self.ac * x * z + 2.0 * self.ad * x
+            + self.b2 * y * y + 2.0 * self.bc * y * z + 2.0 * self.bd * y
+            + self.c2 * z * z + 2.0 * self.cd * z
+            + self.d2;
+        error.abs()
+    }
+}
+
+fn triangle_normal(positions: &[DVec3], triangle: [u32; 3]) -> DVec3 {
+    let [a, b, c] = triangle.map(|i| positions[i as usize]);
+    (b - a).cross(c - a)
+}
+
+// Reduces `indices` to at most `target_index_count` indices if that can be done
+// without moving the surface further than `max_error`, which is relative to
+// the mesh's size (0.01 is 1% of its extent). Returns fewer triangles than
+// asked for when the error limit gets in the way.
+pub fn simplify(positions: &[[f32; 3]], indices: &[u32], target_index_count: usize, max_error: f32) -> Vec<u32> {
+    let points = positions.iter().map(|p| Vec3::from(*p).as_dvec3()).collect::<Vec<_>>();
+    let mut triangles = indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect::<Vec<_>>();
+
+    let (min, max) = points
+        .iter()
+        .fold((DVec3::splat(f64::MAX), DVec3::splat(f64::MIN)), |(min, max), p| (min.min(*p), max.max(*p)));
+    let extent = (max - min).max_element().max(f64::EPSILON);
+    let error_limit = (max_error as f64 * extent).powi(2);
+
+    let locked = locked_vertices(positions, &triangles);
+
+    let mut quadrics = vec![Quadric::default(); points.len()];
+    for &triangle in &triangles {
+        let normal = triangle_normal(&points, triangle);
+        let area = normal.length();
+        if area <= f64::EPSILON {
+            continue;
+        }
+        let normal = normal / area;
+        let d = -normal.dot(points[triangle[0] as usize]);
+        let quadric = Quadric::from_plane(normal, d, area);
+        for i in triangle {
+            quadrics[i as usize].add(&quadric);
+        }
+    }
+
+    let mut remap = (0..points.len() as u32).collect::<Vec<_>>();
+    while triangles.len() * 3 > target_index_count {
+        let mut adjacency = vec![Vec::new(); points.len()];
+        for (t, triangle) in triangles.iter().enumerate() {
+            for &i in triangle {
+                adjacency[i as usize].push(t);
+            }
+        }
+
+        // Moving `from` onto `to` costs however far `to` is from the planes
+        // around `from`
+        let mut collapses = Vec::new();
+        for triangle in &triangles {
+            for k in 0..3 {
+                for (from, to) in [(triangle[k], triangle[(k + 1) % 3]), (triangle[(k + 1) % 3], triangle[k])] {
+                    if !locked[from as usize] {
+                        let cost = quadrics[from as usize].error(points[to as usize]);
+                        collapses.push((cost, from, to));
+                    }
+                }
+            }
+        }
+        collapses.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        // Each pass only collapses edges that don't touch each other, so the
+        // adjacency stays valid for the whole pass
+        let to_remove = triangles.len() - target_index_count / 3;
+        let mut removed = 0;
+        let mut touched = vec![false; points.len()];
+        for (cost, from, to) in collapses {
+            if cost > error_limit || removed >= to_remove {
+                break;
+            }
+            if touched[from as usize] || touched[to as usize] {
+                continue;
+            }
+            if flips(&points, &triangles, &adjacency[from as usize], from, to) {
+                continue;
+            }
+
+            remap[from as usize] = to;
+            let quadric = quadrics[from as usize];
+            quadrics[to as usize].add(&quadric);
+            for &t in &adjacency[from as usize] {
+                if triangles[t].contains(&to) {
+                    removed += 1;
+                }
+                for i in triangles[t] {
+                    touched[i as usize] = true;
+                }
+            }
+        }
+        if removed == 0 {
+            break;
+        }
+
+        for triangle in &mut triangles {
+            *triangle = triangle.map(|i| remap[i as usize]);
+        }
+        triangles.retain(|&[a, b, c]| a != b && b != c && c != a);
+    }
+
+    triangles.into_iter().flatten().collect()
+}
+
+// Whether moving `from` onto `to` would turn any remaining triangle around
+fn flips(points: &[DVec3], triangles: &[[u32; 3]], around: &[usize], from: u32, to: u32) -> bool {
+    around.iter().any(|&t| {
+        let triangle = triangles[t];
+        if triangle.contains(&to) {
+            // Goes away with the collapse
+            return false;
+        }
+        let before = triangle_normal(points, triangle);
+        let after = triangle_normal(points, triangle.map(|i| if i == from { to } else { i }));
+        before.dot(after) <= 0.0
+    })
+}
+
+// Vertices sharing a position with another one (UV or normal seams) and ones
+// on open edges
+fn locked_vertices(positions: &[[f32; 3]], triangles: &[[u32; 3]]) -> Vec<bool> {
+    let key = |i: u32| positions[i as usize].map(f32::to_bits);
+    let mut welded = HashMap::new();
+    for i in 0..positions.len() as u32 {
+        *welded.entry(key(i)).or_insert(0) += 1;
+    }
+    let mut locked = (0..positions.len() as u32).map(|i| welded[&key(i)] > 1).collect::<Vec<_>>();
+
+    // Edges by position, so seams don't count as open edges
+    let mut edges = HashMap::new();
+    for triangle in triangles {
+        for k in 0..3 {
+            let (a, b) = (key(triangle[k]), key(triangle[(k + 1) % 3]));
+            *edges.entry(if a < b { (a, b) } else { (b, a) }).or_insert(0) += 1;
+        }
+    }
+    for triangle in triangles {
+        for k in 0..3 {
+            let (a, b) = (triangle[k], triangle[(k + 1) % 3]);
+            let (ka, kb) = (key(a), key(b));
+            if edges[&if ka < kb { (ka, kb) } else { (kb, ka) }] == 1 {
+                locked[a as usize] = true;
+                locked[b as usize] = true;
+            }
+        }
+    }
+    locked
+}
+
+// Drops the vertices `indices` doesn't use any more and renumbers it to match
+pub fn compact<V: Copy>(vertices: &[V], indices: &mut [u32]) -> Vec<V> {
+    let mut remap = vec![u32::MAX; vertices.len()];
+    let mut compacted = Vec::new();
+    for index in indices {
+        let slot = &mut remap[*index as usize];
+        if *slot == u32::MAX {
+            *slot = compacted.len() as u32;
+            compacted.push(vertices[*index as usize]);
+        }
+        *index = *slot;
+    }
+    compacted
+}
+
+// Successively simpler versions of a mesh, each with about half the triangles
+// of the one before. Stops early once simplifying doesn't get far.
+pub fn generate_lods<V: Copy>(
+    vertices: &[V],
+    indices: &[u32],
+    position: impl Fn(&V) -> [f32; 3],
+    levels: usize,
+    max_error: f32,
+) -> Vec<(Vec<V>, Vec<u32>)> {
+    let positions = vertices.iter().map(position).collect::<Vec<_>>();
+    let mut lods = Vec::new();
+    let mut previous = indices.len();
+    for level in 1..=levels {
+        let target = (indices.len() >> level) / 3 * 3;
+        let mut reduced = simplify(&positions, indices, target, max_error);
+        // Not worth another draw's worth of memory
+        if reduced.is_empty() || reduced.len() * 10 > previous * 8 {
+            break;
+        }
+        previous = reduced.len();
+        let vertices = compact(vertices, &mut reduced);
+        lods.push((vertices, reduced));
+    }
+    lods
+}
@@ -0,0 +1,423 @@
+use std::f32::consts::{FRAC_PI_2, PI};
+
+use glam::Vec3;
+use wgpu::util::DeviceExt;
+
+use crate::texture::Texture;
+use crate::upload::Uploader;
+
+// How the sky looks, see RenderSettings::sky in app.rs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SkySettings {
+    // Towards the sun. Below the horizon there's no sunlight, and the sky
+    // stays as it looks at sunset.
+    pub sun_direction: Vec3,
+    // How hazy the air is, from 2 for a clear day to 10 or so for a murky
+    // one
+    pub turbidity: f32,
+    // Scales the sky, the sun and the ambient light they give into the
+    // scene's HDR units
+    pub intensity: f32,
+}
+
+impl Default for SkySettings {
+    fn default() -> Self {
+        Self {
+            sun_direction: Vec3::new(0.4, 0.8, 0.3).normalize(),
+            turbidity: 2.5,
+            intensity: 0.05,
+        }
+    }
+}
+
+// The light for shading things outdoors. Comes from the sky when there is
+// one, see RenderContext::lighting.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Lighting {
+    // Towards the sun
+    pub sun_direction: Vec3,
+    pub sun_color: Vec3,
+    // Light from the whole sky reaching a surface facing up
+    pub ambient: Vec3,
+}
+
+impl Default for Lighting {
+    fn default() -> Self {
+        Self {
+            sun_direction: Vec3::new(0.4, 0.8, 0.3).normalize(),
+            sun_color: Vec3::ONE,
+            ambient: Vec3::splat(0.2),
+        }
+    }
+}
+
+// Lighting as it's laid out at the end of the terrain's, foliage's and
+// water's uniforms, three vec3s padded to 16 bytes each
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct LightingUniform {
+    sun_direction: [f32; 3],
+    _padding0: f32,
+    sun_color: [f32; 3],
+    _padding1: f32,
+    ambient: [f32; 3],
+    _padding2: f32,
+}
+
+impl From<Lighting> for LightingUniform {
+    fn from(lighting: Lighting) -> Self {
+        Self {
+            sun_direction: lighting.sun_direction.into(),
+            _padding0: 0.0,
+            sun_color: lighting.sun_color.into(),
+            _padding1: 0.0,
+            ambient: lighting.ambient.into(),
+            _padding2: 0.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct SkyUniform {
+    a: [f32; 3],
+    intensity: f32,
+    b: [f32; 3],
+    sun_size: f32,
+    c: [f32; 3],
+    _padding0: f32,
+    d: [f32; 3],
+    _padding1: f32,
+    e: [f32; 3],
+    _padding2: f32,
+    zenith: [f32; 3],
+    _padding3: f32,
+    sun_direction: [f32; 3],
+    _padding4: f32,
+    sun_color: [f32; 3],
+    _padding5: f32,
+}
+
+// Angular radius of the sun, in radians
+const SUN_RADIUS: f32 = 0.0047;
+// How bright the sun is next to the sky at the zenith, before the air dims
+// and reddens it
+const SUN_BRIGHTNESS: f32 = 25.0;
+
+// Preetham, Shirley and Smits' analytic daylight model ("A Practical
+// Analytic Model for Daylight", 1999) for a given sun and turbidity: the
+// Perez coefficients and the zenith's colour, for luminance Y and
+// chromaticity x and y.
+struct Model {
+    coefficients: [Vec3; 5],
+    zenith: Vec3,
+}
+
+impl Model {
+    fn new(sun_direction: Vec3, turbidity: f32) -> Self {
+        let t = turbidity;
+        // The model only holds with the sun above the horizon
+        let theta_s = sun_direction.y.clamp(-1.0, 1.0).acos().min(FRAC_PI_2 - 0.01);
+        let coefficients = [
+            Vec3::new(0.1787 * t - 1.4630, -0.0193 * t - 0.2592, -0.0167 * t - 0.2608),
+            Vec3::new(-0.3554 * t + 0.4275, -0.0665 * t + 0.0008, -0.0950 * t + 0.0092),
+            Vec3::new(-0.0227 * t + 5.3251, -0.0004 * t + 0.2125, -0.0079 * t + 0.2102),
+            Vec3::new(0.1206 * t - 2.5771, -0.0641 * t - 0.8989, -0.0441 * t - 1.6537),
+            Vec3::new(-0.0670 * t + 0.3703, -0.0033 * t + 0.0452, -0.0109 * t + 0.0529),
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let cubic = |c: [f32; 4]| ((c[0] * theta_s + c[1]) * theta_s + c[2]) * theta_s + c[3];
+        let x = t * t * cubic([0.00166, -0.00375, 0.00209, 0.0])
+            + t * cubic([-0.02903, 0.06377, -0.03202, 0.00394])
+            + cubic([0.11693, -0.21196, 0.06052, 0.25886]);
+        let y = t * t * cubic([0.00275, -0.00610, 0.00317, 0.0])
+            + t * cubic([-0.04214, 0.08970, -0.04153, 0.00516])
+            + cubic([0.15346, -0.26756, 0.06670, 0.26688]);
+
+        let mut model = Self {
+            coefficients,
+            zenith: Vec3::new(luminance, x, y),
+        };
+        // Divided through by the distribution at the zenith here, so the
+        // shader doesn't have to
+        model.zenith /= model.perez(1.0, theta_s);
+        model
+    }
+
+    fn perez(&self, cos_theta: f32, gamma: f32) -> Vec3 {
+        let [a, b, c, d, e] = self.coefficients;
+        let cos_gamma = gamma.cos();
+        (Vec3::ONE + a * (b / cos_theta).exp()) * (Vec3::ONE + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+    }
+
+    // The same as the shader, without the sun disk
+    fn radiance(&self, direction: Vec3, sun_direction: Vec3) -> Vec3 {
+        let view = Vec3::new(direction.x, direction.y.max(0.001), direction.z).normalize();
+        let gamma = view.dot(sun_direction).clamp(-1.0, 1.0).acos();
+        xyy_to_rgb(self.zenith * self.perez(view.y, gamma)).max(Vec3::ZERO)
+    }
+}
+
+fn xyy_to_rgb(xyy: Vec3) -> Vec3 {
+    let (luminance, x, y) = (xyy.x, xyy.y, xyy.z);
+    let xyz = Vec3::new(x / y * luminance, luminance, (1.0 - x - y) / y * luminance);
+    Vec3::new(
+        3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z,
+        -0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z,
+        0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z,
+    )
+}
+
+// What's left of sunlight after coming through the air, per channel.
+// Rayleigh scattering by the air takes out blue the most, haze (more of it
+// the higher the turbidity) takes out a bit of everything, and both more
+// with the sun low and its light going through more air.
+fn sun_transmittance(sun_direction: Vec3, turbidity: f32) -> Vec3 {
+    let elevation = sun_direction.y.clamp(-1.0, 1.0).asin().to_degrees();
+    if elevation <= 0.0 {
+        return Vec3::ZERO;
+    }
+    // Kasten and Young's relative air mass
+    let zenith_angle = 90.0 - elevation;
+    let air_mass = 1.0 / (zenith_angle.to_radians().cos() + 0.50572 * (96.07995 - zenith_angle).powf(-1.6364));
+    // Optical depths straight up at 650, 550 and 440 nm
+    let rayleigh = Vec3::new(0.044, 0.097, 0.24);
+    let haze = (0.04608 * turbidity - 0.04586) * Vec3::new(0.65f32.powf(-1.3), 0.55f32.powf(-1.3), 0.44f32.powf(-1.3));
+    let transmittance = (-(rayleigh + haze) * air_mass).exp();
+    // Fading out as the sun sets rather than dropping to nothing at once
+    transmittance * (elevation / 2.0).min(1.0)
+}
+
+// The sky as an analytic model of daylight (Preetham), lit by the sun from
+// any direction. It's drawn behind everything that was drawn before it (see
+// shaders/sky.wgsl), and works out the sun's colour and the ambient light the
+// sky gives, for lighting the rest of the scene to match.
+//
+// draw() works with any camera bind group, so reflections can have the sky
+// in them too.
+pub struct Sky {
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    lighting: Lighting,
+    // What lighting was worked out for, it only changes when these do
+    settings: Option<SkySettings>,
+}
+
+impl Sky {
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        camera_layout: &wgpu::BindGroupLayout,
+        shader_source: &str,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("sky_bind_group_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Sky Buffer"),
+            size: std::mem::size_of::<SkyUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("sky_bind_group"),
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Sky Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        // Corners of a cube around the camera, how big doesn't matter
+        let vertices = (0..8)
+            .map(|i| {
+                let corner = |bit: u32| if i & bit == 0 { -1.0f32 } else { 1.0 };
+                [corner(1), corner(2), corner(4)]
+            })
+            .collect::<Vec<_>>();
+        let indices: [u16; 36] = [
+            0, 1, 3, 0, 3, 2, 4, 6, 7, 4, 7, 5, 0, 4, 5, 0, 5, 1, 2, 3, 7, 2, 7, 6, 0, 2, 6, 0, 6, 4, 1, 5, 7, 1, 7, 3,
+        ];
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Sky Vertex Buffer"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Sky Index Buffer"),
+            contents: bytemuck::cast_slice(&indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        Self {
+            pipeline: Self::create_pipeline(device, &layout, color_format, shader_source),
+            layout,
+            color_format,
+            buffer,
+            bind_group,
+            vertex_buffer,
+            index_buffer,
+            lighting: Lighting::default(),
+            settings: None,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        shader_source: &str,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Sky Shader"),
+            source: wgpu::ShaderSource::Wgsl(shader_source.into()),
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Sky Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x3],
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            // Seen from inside
+            primitive: wgpu::PrimitiveState::default(),
+            // Only where nothing has been drawn yet, which is where the depth
+            // buffer is still cleared to the far plane
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+
+    // Called when the shader file changed, keeps the old pipeline if the new
+    // source doesn't compile
+    pub fn reload_shader(&mut self, device: &wgpu::Device, shader_source: &str) {
+        if let Some(pipeline) = crate::shaders::try_create(device, || {
+            Self::create_pipeline(device, &self.layout, self.color_format, shader_source)
+        }) {
+            self.pipeline = pipeline;
+        }
+    }
+
+    // Once a frame before draw(). Only does any work when the settings
+    // changed since last time.
+    pub fn upload(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        uploader: &mut Uploader,
+        settings: &SkySettings,
+    ) {
+        if self.settings.as_ref() == Some(settings) {
+            return;
+        }
+        self.settings = Some(*settings);
+
+        let sun_direction = settings.sun_direction.normalize_or_zero();
+        let model = Model::new(sun_direction, settings.turbidity);
+        let sun_color = sun_transmittance(sun_direction, settings.turbidity) * SUN_BRIGHTNESS * settings.intensity;
+        self.lighting = Lighting {
+            sun_direction,
+            sun_color,
+            ambient: ambient(&model, sun_direction) * settings.intensity,
+        };
+
+        let [a, b, c, d, e] = model.coefficients.map(|coefficient| coefficient.to_array());
+        let uniform = SkyUniform {
+            a,
+            intensity: settings.intensity,
+            b,
+            sun_size: SUN_RADIUS.cos(),
+            c,
+            _padding0: 0.0,
+            d,
+            _padding1: 0.0,
+            e,
+            _padding2: 0.0,
+            zenith: model.zenith.into(),
+            _padding3: 0.0,
+            sun_direction: sun_direction.into(),
+            _padding4: 0.0,
+            sun_color: sun_color.into(),
+            _padding5: 0.0,
+        };
+        uploader.write(device, encoder, &self.buffer, 0, &[uniform]);
+    }
+
+    // The sun and ambient light from the last upload()
+    pub fn lighting(&self) -> Lighting {
+        self.lighting
+    }
+
+    // Into a pass with a depth buffer, after the opaque things it should be
+    // behind
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera: &'a wgpu::BindGroup) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        render_pass.draw_indexed(0..36, 0, 0..1);
+    }
+}
+
+// The light the sky gives a surface facing up: its radiance averaged over
+// directions spread the way a diffuse surface weighs them, more of them
+// overhead than near the horizon
+fn ambient(model: &Model, sun_direction: Vec3) -> Vec3 {
+    const RINGS: u32 = 8;
+    const SEGMENTS: u32 = 16;
+    let mut sum = Vec3::ZERO;
+    for ring in 0..RINGS {
+        for segment in 0..SEGMENTS {
+            let u = (ring as f32 + 0.5) / RINGS as f32;
+            let angle = (segment as f32 + 0.5) / SEGMENTS as f32 * 2.0 * PI;
+            let radius = u.sqrt();
+            let direction = Vec3::new(radius * angle.cos(), (1.0 - u).sqrt(), radius * angle.sin());
+            sum += model.radiance(direction, sun_direction);
+        }
+    }
+    sum / (RINGS * SEGMENTS) as f32
+}
@@ -0,0 +1,382 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::rc::Rc;
+
+use glam::{Mat4, Vec2};
+
+use crate::assets::{AssetId, Assets, Handle};
+use crate::bind_group_cache::{BindGroupCache, CachedBinding, ResourceId};
+use crate::screen::Screen;
+use crate::texture::Texture;
+use crate::upload::Uploader;
+use crate::vertex::VertexLayout;
+
+// A textured rectangle in logical pixels, (0, 0) is the top left corner of
+// the window
+#[derive(Clone, Copy, Debug)]
+pub struct Sprite {
+    pub texture: Handle<Texture>,
+    pub position: Vec2,
+    pub size: Vec2,
+    // Which part of the texture to show, min and max corners in 0..1
+    pub uv: [Vec2; 2],
+    pub color: [f32; 4],
+    pub filter: SpriteFilter,
+}
+
+// How a sprite's texture is sampled when it's drawn bigger or smaller than
+// the texture is
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum SpriteFilter {
+    // Nearest in pixel art mode (RenderSettings::pixel_art), so sprites keep
+    // hard edged pixels like the scene, and the texture's own sampler
+    // otherwise
+    #[default]
+    Auto,
+    // Always the texture's own sampler
+    Texture,
+    // Always nearest, blocky when scaled up
+    Nearest,
+}
+
+impl Sprite {
+    pub fn new(texture: Handle<Texture>, position: Vec2, size: Vec2) -> Self {
+        Self {
+            texture,
+            position,
+            size,
+            uv: [Vec2::ZERO, Vec2::ONE],
+            color: [1.0; 4],
+            filter: SpriteFilter::Auto,
+        }
+    }
+
+    pub fn with_uv(mut self, min: Vec2, max: Vec2) -> Self {
+        self.uv = [min, max];
+        self
+    }
+
+    pub fn with_color(mut self, color: [f32; 4]) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn with_filter(mut self, filter: SpriteFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+}
+
+crate::vertex_layout! {
+    #[derive(Debug)]
+    struct SpriteVertex {
+        #[location(0)] position: [f32; 2],
+        #[location(1)] tex_coords: [f32; 2],
+        #[location(2)] color: [f32; 4],
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct SpriteGlobals {
+    projection: [[f32; 4]; 4],
+}
+
+// Collects sprites over a frame and draws them in submission order, later
+// sprites on top. Neighbouring sprites with the same texture and filter
+// share a draw.
+//
+// Every frame: push() sprites, prepare() them, grab bind_groups() and then
+// draw() into a pass on the surface.
+pub struct SpriteBatch {
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    globals_buffer: wgpu::Buffer,
+    globals_bind_group: wgpu::BindGroup,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    // In sprites
+    capacity: usize,
+    sprites: Vec<Sprite>,
+    // Runs of sprites that share a texture and whether it's sampled nearest,
+    // in index buffer elements
+    runs: Vec<(Handle<Texture>, bool, Range<u32>)>,
+    texture_ids: HashMap<(AssetId, bool), ResourceId>,
+    // For SpriteFilter::Nearest, and Auto in pixel art mode
+    nearest_sampler: wgpu::Sampler,
+    nearest_by_default: bool,
+}
+
+impl SpriteBatch {
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        texture_layout: &wgpu::BindGroupLayout,
+        frame_layout: &wgpu::BindGroupLayout,
+        shader_source: &str,
+    ) -> Self {
+        use wgpu::util::DeviceExt;
+
+        let globals_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("sprite_globals_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let globals_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Sprite Globals"),
+            contents: bytemuck::cast_slice(&[SpriteGlobals {
+                projection: Mat4::IDENTITY.to_cols_array_2d(),
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let globals_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("sprite_globals"),
+            layout: &globals_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: globals_buffer.as_entire_binding(),
+            }],
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Sprite Pipeline Layout"),
+            bind_group_layouts: &[texture_layout, &globals_layout, frame_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = Self::create_pipeline(device, &layout, color_format, shader_source);
+
+        let capacity = 64;
+        Self {
+            pipeline,
+            layout,
+            color_format,
+            globals_buffer,
+            globals_bind_group,
+            vertex_buffer: Self::create_vertex_buffer(device, capacity),
+            index_buffer: Self::create_index_buffer(device, capacity),
+            capacity,
+            sprites: Vec::new(),
+            runs: Vec::new(),
+            texture_ids: HashMap::new(),
+            nearest_sampler: device.create_sampler(&wgpu::SamplerDescriptor {
+                label: Some("Sprite Nearest Sampler"),
+                address_mode_u: wgpu::AddressMode::ClampToEdge,
+                address_mode_v: wgpu::AddressMode::ClampToEdge,
+                mag_filter: wgpu::FilterMode::Nearest,
+                min_filter: wgpu::FilterMode::Nearest,
+                ..Default::default()
+            }),
+            nearest_by_default: false,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        shader_source: &str,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Sprite Shader"),
+            source: wgpu::ShaderSource::Wgsl(shader_source.into()),
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Sprite Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[SpriteVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            // Sprites always go on top, no depth testing
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+
+    // Called when the shader file changed, keeps the old pipeline if the new
+    // source doesn't compile
+    pub fn reload_shader(&mut self, device: &wgpu::Device, shader_source: &str) {
+        if let Some(pipeline) = crate::shaders::try_create(device, || {
+            Self::create_pipeline(device, &self.layout, self.color_format, shader_source)
+        }) {
+            self.pipeline = pipeline;
+        }
+    }
+
+    fn create_vertex_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Sprite Vertex Buffer"),
+            size: (capacity * 4 * std::mem::size_of::<SpriteVertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    // Every sprite is two triangles, so the indices never change
+    fn create_index_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        use wgpu::util::DeviceExt;
+        let indices = (0..capacity as u32)
+            .flat_map(|i| [0, 1, 2, 2, 1, 3].map(|index| i * 4 + index))
+            .collect::<Vec<u32>>();
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Sprite Index Buffer"),
+            contents: bytemuck::cast_slice(&indices),
+            usage: wgpu::BufferUsages::INDEX,
+        })
+    }
+
+    pub fn push(&mut self, sprite: Sprite) {
+        self.sprites.push(sprite);
+    }
+
+    pub fn len(&self) -> usize {
+        self.sprites.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sprites.is_empty()
+    }
+
+    // What SpriteFilter::Auto means, nearest in pixel art mode
+    pub fn set_nearest_by_default(&mut self, nearest: bool) {
+        self.nearest_by_default = nearest;
+    }
+
+    // Call when a texture gets reloaded, so its bind groups are rebuilt
+    pub fn texture_changed(&mut self, id: AssetId, cache: &mut BindGroupCache) {
+        for nearest in [false, true] {
+            if let Some(resource) = self.texture_ids.get_mut(&(id, nearest)) {
+                *resource = cache.recreated(*resource);
+            }
+        }
+    }
+
+    // Writes this frame's sprites into the vertex buffer and clears them
+    pub fn prepare(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, uploader: &mut Uploader, screen: &Screen) {
+        let globals = SpriteGlobals {
+            projection: screen.ui_projection().to_cols_array_2d(),
+        };
+        uploader.write(device, encoder, &self.globals_buffer, 0, &[globals]);
+
+        if self.sprites.len() > self.capacity {
+            self.capacity = self.sprites.len().next_power_of_two();
+            self.vertex_buffer = Self::create_vertex_buffer(device, self.capacity);
+            self.index_buffer = Self::create_index_buffer(device, self.capacity);
+        }
+
+        self.runs.clear();
+        let mut vertices = Vec::with_capacity(self.sprites.len() * 4);
+        for (i, sprite) in self.sprites.drain(..).enumerate() {
+            let [uv_min, uv_max] = sprite.uv;
+            let min = sprite.position;
+            let max = sprite.position + sprite.size;
+            vertices.extend([
+                (min, uv_min),
+                (Vec2::new(max.x, min.y), Vec2::new(uv_max.x, uv_min.y)),
+                (Vec2::new(min.x, max.y), Vec2::new(uv_min.x, uv_max.y)),
+                (max, uv_max),
+            ].map(|(position, tex_coords)| SpriteVertex {
+                position: position.into(),
+                tex_coords: tex_coords.into(),
+                color: sprite.color,
+            }));
+
+            let nearest = match sprite.filter {
+                SpriteFilter::Auto => self.nearest_by_default,
+                SpriteFilter::Texture => false,
+                SpriteFilter::Nearest => true,
+            };
+            let indices = i as u32 * 6..i as u32 * 6 + 6;
+            match self.runs.last_mut() {
+                Some((texture, run_nearest, run)) if *texture == sprite.texture && *run_nearest == nearest => {
+                    run.end = indices.end
+                }
+                _ => self.runs.push((sprite.texture, nearest, indices)),
+            }
+        }
+        uploader.write(device, encoder, &self.vertex_buffer, 0, &vertices);
+    }
+
+    // One bind group per run prepared this frame, pass them on to draw()
+    pub fn bind_groups(
+        &mut self,
+        device: &wgpu::Device,
+        assets: &Assets,
+        cache: &mut BindGroupCache,
+        layout_id: ResourceId,
+        layout: &wgpu::BindGroupLayout,
+    ) -> Vec<Rc<wgpu::BindGroup>> {
+        self.runs
+            .iter()
+            .map(|(handle, nearest, _)| {
+                let id = *self.texture_ids.entry((handle.id(), *nearest)).or_insert_with(|| cache.register());
+                let texture = assets.texture(*handle);
+                let sampler = if *nearest { &self.nearest_sampler } else { &texture.sampler };
+                cache.get_or_create(
+                    device,
+                    Some("sprite_bind_group"),
+                    layout_id,
+                    layout,
+                    &[
+                        CachedBinding {
+                            binding: 0,
+                            id,
+                            resource: wgpu::BindingResource::TextureView(&texture.view),
+                        },
+                        CachedBinding {
+                            binding: 1,
+                            id,
+                            resource: wgpu::BindingResource::Sampler(sampler),
+                        },
+                    ],
+                )
+            })
+            .collect()
+    }
+
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        bind_groups: &'a [Rc<wgpu::BindGroup>],
+        frame: &'a wgpu::BindGroup,
+    ) {
+        if self.runs.is_empty() {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(1, &self.globals_bind_group, &[]);
+        render_pass.set_bind_group(2, frame, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        for ((_, _, indices), bind_group) in self.runs.iter().zip(bind_groups) {
+            render_pass.set_bind_group(0, bind_group, &[]);
+            render_pass.draw_indexed(indices.clone(), 0, 0..1);
+        }
+    }
+
+    // Whether prepare() left anything to draw
+    pub fn has_draws(&self) -> bool {
+        !self.runs.is_empty()
+    }
+}
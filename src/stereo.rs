@@ -0,0 +1,406 @@
+use std::num::NonZeroU32;
+
+use glam::{Mat4, Vec3};
+use wgpu::util::DeviceExt;
+
+use crate::draw::{DrawCommand, DrawResources, MeshBuffers};
+use crate::render_graph::RenderGraph;
+use crate::scene::SceneCamera;
+use crate::texture::Texture;
+use crate::transform::Transform;
+use crate::upload::Uploader;
+use crate::vertex::VertexLayout;
+use crate::{InstanceRaw, Vertex};
+
+// How the two eyes end up on screen
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StereoOutput {
+    // Next to each other, left eye on the left, for cross-eyed viewing and
+    // headsets that take a side by side image
+    SideBySide,
+    // On top of each other for red and cyan glasses
+    #[default]
+    Anaglyph,
+}
+
+// See RenderSettings::stereo in app.rs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StereoSettings {
+    // How far apart the eyes are, in world units. About 6.4cm for people
+    // when a unit is a metre.
+    pub eye_separation: f32,
+    pub output: StereoOutput,
+}
+
+impl Default for StereoSettings {
+    fn default() -> Self {
+        Self {
+            eye_separation: 0.064,
+            output: StereoOutput::default(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct StereoCameraUniform {
+    // Left eye first
+    view_proj: [[[f32; 4]; 4]; 2],
+}
+
+// Where both eyes are drawn, one layer each
+struct Eyes {
+    size: (u32, u32),
+    color: wgpu::TextureView,
+    depth: wgpu::TextureView,
+    // For the composite
+    bind_group: wgpu::BindGroup,
+}
+
+// Draws the scene for a left and a right eye in one go with multiview: the
+// draws are recorded once into a pass with two array layers, and the GPU
+// runs the vertex shader for both, view_index picking the eye's camera. The
+// eyes are side by side (parallel, not turned in), eye_separation apart.
+//
+// For now they're put back together in the scene target, next to each
+// other or as an anaglyph, and everything after the scene (fog, bloom,
+// tonemapping) works on that. A headset would take the layers as they are
+// instead.
+//
+// Needs Features::MULTIVIEW, which WebGL and most GL drivers don't have.
+// Only plain materials are drawn so far.
+pub struct Stereo {
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    side_by_side: wgpu::RenderPipeline,
+    anaglyph: wgpu::RenderPipeline,
+    composite_layout: wgpu::PipelineLayout,
+    eyes_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    camera: (wgpu::Buffer, wgpu::BindGroup),
+    eyes: Option<Eyes>,
+    output: StereoOutput,
+}
+
+impl Stereo {
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        // Group 0 of the mesh pipeline, a plain material's texture
+        texture_layout: &wgpu::BindGroupLayout,
+        camera_layout: &wgpu::BindGroupLayout,
+        frame_layout: &wgpu::BindGroupLayout,
+        shader_source: &str,
+        // Extra attribute streams from the mesh pool, like the mesh pipeline
+        streams: &[wgpu::VertexBufferLayout],
+    ) -> Self {
+        let eyes_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("stereo_eyes_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Stereo Pipeline Layout"),
+            bind_group_layouts: &[texture_layout, camera_layout, frame_layout],
+            push_constant_ranges: &[],
+        });
+        let composite_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Stereo Composite Pipeline Layout"),
+            bind_group_layouts: &[&eyes_layout],
+            push_constant_ranges: &[],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Stereo Camera Buffer"),
+            contents: bytemuck::bytes_of(&StereoCameraUniform {
+                view_proj: [Mat4::IDENTITY.to_cols_array_2d(); 2],
+            }),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("stereo_camera_bind_group"),
+            layout: camera_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            }],
+        });
+
+        let [pipeline, side_by_side, anaglyph] =
+            Self::create_pipelines(device, &layout, &composite_layout, color_format, shader_source, streams);
+        Self {
+            pipeline,
+            layout,
+            color_format,
+            side_by_side,
+            anaglyph,
+            composite_layout,
+            eyes_layout,
+            sampler,
+            camera: (camera_buffer, camera_bind_group),
+            eyes: None,
+            output: StereoOutput::default(),
+        }
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        composite_layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        shader_source: &str,
+        streams: &[wgpu::VertexBufferLayout],
+    ) -> [wgpu::RenderPipeline; 3] {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Stereo Shader"),
+            source: wgpu::ShaderSource::Wgsl(shader_source.into()),
+        });
+        let mut buffers = vec![Vertex::desc(), InstanceRaw::desc()];
+        buffers.extend_from_slice(streams);
+        let color_target = [Some(wgpu::ColorTargetState {
+            format: color_format,
+            blend: Some(wgpu::BlendState::REPLACE),
+            write_mask: wgpu::ColorWrites::ALL,
+        })];
+
+        // Like the mesh pipeline, with both eyes drawn at once
+        let scene = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Stereo Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &buffers,
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &color_target,
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: NonZeroU32::new(2),
+        });
+        let composite = |label, entry_point| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(composite_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_composite",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point,
+                    targets: &color_target,
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        [
+            scene,
+            composite("Stereo Side By Side Pipeline", "fs_side_by_side"),
+            composite("Stereo Anaglyph Pipeline", "fs_anaglyph"),
+        ]
+    }
+
+    // Called when the shader file changed or the mesh pool got new streams,
+    // keeps the old pipelines if the new source doesn't compile
+    pub fn reload_shader(&mut self, device: &wgpu::Device, shader_source: &str, streams: &[wgpu::VertexBufferLayout]) {
+        let (layout, composite_layout) = (&self.layout, &self.composite_layout);
+        if let Some([pipeline, side_by_side, anaglyph]) = crate::shaders::try_create(device, || {
+            Self::create_pipelines(device, layout, composite_layout, self.color_format, shader_source, streams)
+        }) {
+            self.pipeline = pipeline;
+            self.side_by_side = side_by_side;
+            self.anaglyph = anaglyph;
+        }
+    }
+
+    // Works out both eyes' cameras from `camera`, which sits right between
+    // them, and makes the eye textures for a `target` sized scene target
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        uploader: &mut Uploader,
+        camera: &SceneCamera,
+        settings: &StereoSettings,
+        target: (u32, u32),
+    ) {
+        // Side by side each eye gets half the width
+        let size = match settings.output {
+            StereoOutput::SideBySide => ((target.0 / 2).max(1), target.1.max(1)),
+            StereoOutput::Anaglyph => (target.0.max(1), target.1.max(1)),
+        };
+        if self.eyes.as_ref().is_none_or(|eyes| eyes.size != size) {
+            self.eyes = Some(self.create_eyes(device, size));
+        }
+        self.output = settings.output;
+
+        let view = Transform::looking_at(camera.eye.into(), camera.target.into(), camera.up.into()).view_matrix();
+        let aspect = size.0 as f32 / size.1 as f32;
+        let proj = Mat4::perspective_rh(camera.fovy.to_radians(), aspect, camera.znear, camera.zfar);
+        // Moving an eye left moves the world right in front of it
+        let half = settings.eye_separation * 0.5;
+        let eye = |offset: f32| (proj * Mat4::from_translation(Vec3::X * offset) * view).to_cols_array_2d();
+        let uniform = StereoCameraUniform {
+            view_proj: [eye(half), eye(-half)],
+        };
+        uploader.write(device, encoder, &self.camera.0, 0, &[uniform]);
+    }
+
+    fn create_eyes(&self, device: &wgpu::Device, size: (u32, u32)) -> Eyes {
+        let layers = |label, format, usage| {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: size.0,
+                    height: size.1,
+                    depth_or_array_layers: 2,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage,
+            });
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::D2Array),
+                ..Default::default()
+            })
+        };
+        let color = layers(
+            "Stereo Eyes",
+            self.color_format,
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        );
+        let depth = layers("Stereo Depth", Texture::DEPTH_FORMAT, wgpu::TextureUsages::RENDER_ATTACHMENT);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("stereo_eyes_bind_group"),
+            layout: &self.eyes_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&color),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        });
+        Eyes {
+            size,
+            color,
+            depth,
+            bind_group,
+        }
+    }
+
+    // Adds a "stereo" pass drawing `commands` for both eyes and putting them
+    // together over all of `target`. Needs prepare() first. Only the ones
+    // for the mesh pipeline (pipeline 0) get drawn.
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_pass<'g>(
+        &'g self,
+        graph: &mut RenderGraph<'g>,
+        target: &'static str,
+        frame: &'g wgpu::BindGroup,
+        mut commands: Vec<DrawCommand>,
+        materials: Vec<&'g wgpu::BindGroup>,
+        meshes: Vec<MeshBuffers<'g>>,
+        clear: wgpu::Color,
+    ) {
+        let Some(eyes) = &self.eyes else {
+            return;
+        };
+        commands.retain(|command| command.pipeline == 0);
+        graph.import("stereo_eyes", &eyes.color);
+        graph.import("stereo_depth", &eyes.depth);
+        graph.add_pass("stereo").writes(&["stereo_eyes", "stereo_depth", target]).execute(move |encoder, resources| {
+            {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Stereo Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: resources.view("stereo_eyes"),
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(clear),
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: resources.view("stereo_depth"),
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(1.0),
+                            store: false,
+                        }),
+                        stencil_ops: None,
+                    }),
+                });
+                let draw_resources = DrawResources {
+                    globals: vec![(1, &self.camera.1), (2, frame)],
+                    pipelines: vec![&self.pipeline],
+                    materials,
+                    meshes,
+                };
+                crate::draw::draw_commands(&commands, &mut render_pass, 0, &draw_resources);
+            }
+
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Stereo Composite Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: resources.view(target),
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_pipeline(match self.output {
+                StereoOutput::SideBySide => &self.side_by_side,
+                StereoOutput::Anaglyph => &self.anaglyph,
+            });
+            render_pass.set_bind_group(0, &eyes.bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        });
+    }
+}
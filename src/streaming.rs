@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::assets::AssetId;
+use crate::texture::{ColorSpace, Texture};
+
+// What streamed textures may take up on the GPU, RenderSettings starts out
+// with this too
+pub const DEFAULT_BUDGET: u64 = 256 * 1024 * 1024;
+// Mips this size and smaller are always on the GPU, so a streamed texture
+// has at least a blurry version of itself to show from the moment it loads
+const TAIL_SIZE: u32 = 64;
+// Bytes of mips uploaded per frame before the rest waits for the next one.
+// The texture that goes over still gets its upload, however big.
+const UPLOAD_BYTES_PER_FRAME: u64 = 16 * 1024 * 1024;
+
+// The full mip chain of an image, halving until 1x1. Done on the loading
+// threads, since resizing big textures takes a while.
+pub fn generate_mips(image: image::RgbaImage) -> Vec<image::RgbaImage> {
+    let mut mips = vec![image];
+    loop {
+        let last = mips.last().expect("starts with the image");
+        let (width, height) = last.dimensions();
+        if width <= 1 && height <= 1 {
+            return mips;
+        }
+        let next = image::imageops::resize(
+            last,
+            (width / 2).max(1),
+            (height / 2).max(1),
+            image::imageops::FilterType::Triangle,
+        );
+        mips.push(next);
+    }
+}
+
+fn mip_bytes(mip: &image::RgbaImage) -> u64 {
+    mip.width() as u64 * mip.height() as u64 * 4
+}
+
+struct StreamedTexture {
+    label: String,
+    // Every mip, 0 being the full size one. The GPU only has `resident` and
+    // everything smaller.
+    mips: Vec<image::RgbaImage>,
+    resident: usize,
+    // The first mip that counts as the tail
+    tail: usize,
+    // The most pixels across it covered on screen during `requested_frame`
+    requested: f32,
+    requested_frame: u64,
+    sampler: Arc<wgpu::Sampler>,
+    color_space: ColorSpace,
+}
+
+impl StreamedTexture {
+    // Bytes on the GPU with `top` as the largest mip
+    fn bytes(&self, top: usize) -> u64 {
+        self.mips[top..].iter().map(mip_bytes).sum()
+    }
+
+    // The mip whose size is closest to covering `pixels` without going under
+    fn mip_for(&self, pixels: f32) -> usize {
+        let size = self.mips[0].width().max(self.mips[0].height()) as f32;
+        let level = (size / pixels.max(1.0)).log2().floor().max(0.0) as usize;
+        level.min(self.tail)
+    }
+
+    fn upload(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Texture {
+        let mips = &self.mips[self.resident..];
+        Texture::from_mips(device, queue, mips, &self.label, self.sampler.clone(), self.color_space)
+    }
+}
+
+// Keeps only the mips of each texture that are actually needed on the GPU.
+// Textures start out with just their smallest mips, then get sharper one mip
+// a frame as long as something asks for them with request(). When everything
+// wanted doesn't fit in the budget, the textures that went longest without a
+// request give up their biggest mips first.
+//
+// All mips stay in memory on the CPU side, so nothing has to be read from
+// disk again when a texture comes back into view. Changing which mips are
+// resident makes a new texture, so bind groups using it have to be rebuilt,
+// see Assets::update().
+pub struct TextureStreamer {
+    textures: HashMap<AssetId, StreamedTexture>,
+    // In bytes
+    budget: u64,
+    frame: u64,
+}
+
+impl TextureStreamer {
+    pub fn new(budget: u64) -> Self {
+        Self {
+            textures: HashMap::new(),
+            budget,
+            frame: 0,
+        }
+    }
+
+    pub fn set_budget(&mut self, budget: u64) {
+        self.budget = budget;
+    }
+
+    // What `id` gets sampled with from its next upload on, the texture
+    // already on the GPU needs it swapping in too
+    pub fn set_sampler(&mut self, id: AssetId, sampler: Arc<wgpu::Sampler>) {
+        if let Some(texture) = self.textures.get_mut(&id) {
+            texture.sampler = sampler;
+        }
+    }
+
+    // Bytes of texture on the GPU, and how many there may be
+    pub fn memory(&self) -> (u64, u64) {
+        let used = self.textures.values().map(|texture| texture.bytes(texture.resident)).sum();
+        (used, self.budget)
+    }
+
+    // Starts streaming a freshly loaded texture, or one that got reloaded.
+    // Returns it with only its tail resident.
+    #[allow(clippy::too_many_arguments)]
+    pub fn insert(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        id: AssetId,
+        mips: Vec<image::RgbaImage>,
+        label: &str,
+        sampler: Arc<wgpu::Sampler>,
+        color_space: ColorSpace,
+    ) -> Texture {
+        let tail = mips
+            .iter()
+            .position(|mip| mip.width().max(mip.height()) <= TAIL_SIZE)
+            .unwrap_or(mips.len() - 1);
+        let texture = StreamedTexture {
+            label: label.to_string(),
+            mips,
+            resident: tail,
+            tail,
+            requested: 0.0,
+            requested_frame: 0,
+            sampler,
+            color_space,
+        };
+        let uploaded = texture.upload(device, queue);
+        self.textures.insert(id, texture);
+        uploaded
+    }
+
+    // The texture covers about `pixels` across on screen this frame, e.g.
+    // the size of an object using it. Asking again keeps the biggest.
+    pub fn request(&mut self, id: AssetId, pixels: f32) {
+        let Some(texture) = self.textures.get_mut(&id) else {
+            return;
+        };
+        if texture.requested_frame != self.frame {
+            texture.requested_frame = self.frame;
+            texture.requested = 0.0;
+        }
+        texture.requested = texture.requested.max(pixels);
+    }
+
+    // Once a frame. Works out what fits in the budget from last frame's
+    // requests and uploads towards it, returning the textures that changed.
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> Vec<(AssetId, Texture)> {
+        let frame = self.frame;
+        self.frame += 1;
+
+        // Textures nobody asked for keep what they have, but go first when
+        // something has to give
+        let mut wanted = self
+            .textures
+            .iter()
+            .map(|(id, texture)| {
+                let mip = if texture.requested_frame == frame {
+                    texture.mip_for(texture.requested)
+                } else {
+                    texture.resident
+                };
+                (*id, mip)
+            })
+            .collect::<HashMap<_, _>>();
+
+        let mut total: u64 = wanted.iter().map(|(id, mip)| self.textures[id].bytes(*mip)).sum();
+        while total > self.budget {
+            let evict = wanted
+                .iter()
+                .filter(|(id, mip)| **mip < self.textures[*id].tail)
+                .min_by(|(a, a_mip), (b, b_mip)| {
+                    let (a, b) = (&self.textures[*a], &self.textures[*b]);
+                    // Longest unused first, then whichever frees the most
+                    a.requested_frame
+                        .cmp(&b.requested_frame)
+                        .then(mip_bytes(&b.mips[**b_mip]).cmp(&mip_bytes(&a.mips[**a_mip])))
+                })
+                .map(|(id, _)| *id);
+            let Some(id) = evict else {
+                // Everything is down to its tail already
+                break;
+            };
+            let mip = wanted.get_mut(&id).expect("picked from wanted");
+            total -= mip_bytes(&self.textures[&id].mips[*mip]);
+            *mip += 1;
+        }
+
+        // The most visible textures get sharper first
+        let mut order = wanted.into_iter().collect::<Vec<_>>();
+        order.sort_by(|(a, _), (b, _)| self.textures[b].requested.total_cmp(&self.textures[a].requested));
+
+        let mut changed = Vec::new();
+        let mut uploaded = 0;
+        for (id, mip) in order {
+            let texture = self.textures.get_mut(&id).expect("came from textures");
+            if mip > texture.resident {
+                // Dropping mips frees memory straight away
+                texture.resident = mip;
+            } else if mip < texture.resident && uploaded < UPLOAD_BYTES_PER_FRAME {
+                // One mip at a time, so it sharpens gradually instead of
+                // stalling on the whole chain
+                texture.resident -= 1;
+                uploaded += texture.bytes(texture.resident);
+            } else {
+                continue;
+            }
+            changed.push((id, texture.upload(device, queue)));
+        }
+        changed
+    }
+}
@@ -0,0 +1,873 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use anyhow::{ensure, Result};
+use glam::{Mat4, Vec2, Vec3};
+use wgpu::util::DeviceExt;
+use winit::event::MouseButton;
+
+use crate::app::{App, ClearMode, RenderContext, Setup};
+use crate::bounds::{Aabb, Frustum, Ray};
+use crate::events::MouseInput;
+use crate::foliage::{DensityMap, Foliage, FoliageConfig};
+use crate::noise::fbm;
+use crate::paint::{Brush, BrushMode, Canvas};
+use crate::render_graph::RenderGraph;
+use crate::sky::{Lighting, LightingUniform, SkySettings};
+use crate::texture::Texture;
+use crate::upload::AppUploader;
+use crate::vertex::VertexLayout;
+use crate::water::{Water, WaterConfig};
+
+const SOURCE: &str = include_str!("shaders/terrain.wgsl");
+
+crate::vertex_layout! {
+    #[derive(Debug)]
+    struct TerrainVertex {
+        #[location(0)] position: [f32; 2],
+        #[location(1)] skirt: f32,
+    }
+}
+
+crate::vertex_layout! {
+    step_mode: Instance,
+    #[derive(Debug, PartialEq)]
+    struct ChunkInstance {
+        #[location(2)] offset: [f32; 2],
+        #[location(3)] scale: f32,
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct TerrainUniform {
+    origin: [f32; 3],
+    size: f32,
+    height: f32,
+    max_slope: f32,
+    resolution: f32,
+    _padding: f32,
+    lighting: LightingUniform,
+}
+
+// Heights from 0 to 1 on a grid, row by row
+#[derive(Clone, Debug)]
+pub struct Heightmap {
+    width: u32,
+    depth: u32,
+    heights: Vec<f32>,
+}
+
+impl Heightmap {
+    pub fn new(width: u32, depth: u32, heights: Vec<f32>) -> Result<Self> {
+        ensure!(width >= 2 && depth >= 2, "heightmaps need at least 2x2 heights");
+        ensure!(heights.len() == (width * depth) as usize, "expected {} heights", width * depth);
+        Ok(Self { width, depth, heights })
+    }
+
+    // Greyscale, black being the lowest. 16 bit images keep their precision.
+    pub fn from_image(image: &image::DynamicImage) -> Result<Self> {
+        let luma = image.to_luma16();
+        let heights = luma.pixels().map(|p| p.0[0] as f32 / u16::MAX as f32).collect();
+        Self::new(luma.width(), luma.height(), heights)
+    }
+
+    // Rolling hills from layered value noise, `size` heights square
+    pub fn generate(size: u32, seed: u32) -> Self {
+        let mut heights = Vec::with_capacity((size * size) as usize);
+        for z in 0..size {
+            for x in 0..size {
+                let point = Vec2::new(x as f32, z as f32) / size as f32 * 4.0;
+                heights.push(fbm(point, seed));
+            }
+        }
+        // Stretched to fill 0 to 1
+        let (min, max) = min_max(&heights);
+        for height in &mut heights {
+            *height = (*height - min) / (max - min).max(f32::EPSILON);
+        }
+        Self::new(size, size, heights).expect("generated heightmaps have the right size")
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.depth)
+    }
+
+    pub fn get(&self, x: u32, z: u32) -> f32 {
+        self.heights[(z.min(self.depth - 1) * self.width + x.min(self.width - 1)) as usize]
+    }
+
+    // Bilinear between the heights, `uv` going from 0 to 1 across the map.
+    // Matches what the shader does.
+    pub fn sample(&self, uv: Vec2) -> f32 {
+        let size = Vec2::new((self.width - 1) as f32, (self.depth - 1) as f32);
+        let texel = (uv * size).clamp(Vec2::ZERO, size);
+        let (x, z) = (texel.x as u32, texel.y as u32);
+        let t = texel - texel.floor();
+        let top = self.get(x, z) + (self.get(x + 1, z) - self.get(x, z)) * t.x;
+        let bottom = self.get(x, z + 1) + (self.get(x + 1, z + 1) - self.get(x, z + 1)) * t.x;
+        top + (bottom - top) * t.y
+    }
+
+    fn create_texture(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> wgpu::Texture {
+        device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some("Heightmap"),
+                size: wgpu::Extent3d {
+                    width: self.width,
+                    height: self.depth,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::R32Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+            },
+            bytemuck::cast_slice(&self.heights),
+        )
+    }
+}
+
+fn min_max(values: &[f32]) -> (f32, f32) {
+    values.iter().fold((f32::MAX, f32::MIN), |(min, max), v| (min.min(*v), max.max(*v)))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TerrainConfig {
+    // World position of the heightmap's first corner at height 0
+    pub origin: Vec3,
+    // Width and depth in world units, the terrain is square
+    pub size: f32,
+    // What a height of 1 comes out as
+    pub height: f32,
+    // Quads along each side of a chunk, whatever its size
+    pub chunk_resolution: u32,
+    // How often the quadtree may split. The smallest chunks are
+    // size / 2^max_depth across.
+    pub max_depth: u32,
+    // Chunks split when the camera gets closer than this many times their
+    // size, higher keeps more detail further away
+    pub lod_distance: f32,
+}
+
+impl Default for TerrainConfig {
+    fn default() -> Self {
+        Self {
+            origin: Vec3::new(-64.0, -8.0, -64.0),
+            size: 128.0,
+            height: 16.0,
+            chunk_resolution: 32,
+            max_depth: 5,
+            lod_distance: 1.5,
+        }
+    }
+}
+
+// What can be painted onto the terrain's splat map
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TerrainLayer {
+    Grass,
+    Rock,
+    Dirt,
+}
+
+impl TerrainLayer {
+    // The splat map colour that paints it, see Terrain::splat_map()
+    pub fn color(self) -> [f32; 4] {
+        match self {
+            Self::Grass => [1.0, 0.0, 0.0, 1.0],
+            Self::Rock => [0.0, 1.0, 0.0, 1.0],
+            Self::Dirt => [0.0, 0.0, 1.0, 1.0],
+        }
+    }
+}
+
+// A heightmap drawn as a quadtree of chunks. Chunks near the camera split
+// into four smaller ones with the same number of quads, so detail falls off
+// with distance while the triangle count stays about the same wherever the
+// camera is. All chunks share one grid mesh and only differ in where they
+// are and how big, so the whole terrain is a single instanced draw.
+//
+// Where a chunk meets a bigger one their edges don't line up exactly. Each
+// chunk has a skirt hanging down from its edges that fills those cracks.
+//
+// On top of the colours it picks from slope and height, layers painted into
+// its splat map show through, see splat_map().
+//
+// Every frame: prepare() with the camera, add the splat map's pass, then
+// draw() into a pass with the scene's depth buffer that reads SPLAT_MAP,
+// like TerrainApp does.
+pub struct Terrain {
+    config: TerrainConfig,
+    heightmap: Heightmap,
+    // Lowest and highest height under each node of the quadtree, one grid
+    // per depth, for culling and measuring distances to the real surface
+    ranges: Vec<Vec<(f32, f32)>>,
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    uniform: TerrainUniform,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    index_count: u32,
+    instance_buffer: wgpu::Buffer,
+    // In chunks
+    capacity: usize,
+    chunks: Vec<ChunkInstance>,
+    splat_map: Canvas,
+}
+
+impl Terrain {
+    // What the splat map is called in the render graph
+    pub const SPLAT_MAP: &'static str = "terrain_splat_map";
+
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color_format: wgpu::TextureFormat,
+        camera_layout: &wgpu::BindGroupLayout,
+        heightmap: Heightmap,
+        config: TerrainConfig,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("terrain_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        // A texel for every height, with nothing painted yet
+        let splat_map = Canvas::new(device, heightmap.size(), wgpu::TextureFormat::Rgba8Unorm, [0.0; 4]);
+        let height_texture = heightmap.create_texture(device, queue);
+        let height_view = height_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let uniform = TerrainUniform {
+            origin: config.origin.into(),
+            size: config.size,
+            height: config.height,
+            max_slope: max_slope(&heightmap, &config),
+            resolution: config.chunk_resolution as f32,
+            _padding: 0.0,
+            lighting: Lighting::default().into(),
+        };
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Terrain Buffer"),
+            contents: bytemuck::bytes_of(&uniform),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("terrain_bind_group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&height_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&splat_map.texture().view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&splat_map.texture().sampler),
+                },
+            ],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Terrain Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let (vertices, indices) = chunk_mesh(config.chunk_resolution);
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Terrain Vertex Buffer"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Terrain Index Buffer"),
+            contents: bytemuck::cast_slice(&indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        let capacity = 64;
+        Self {
+            ranges: height_ranges(&heightmap, config.max_depth),
+            config,
+            heightmap,
+            pipeline: Self::create_pipeline(device, &layout, color_format, SOURCE),
+            layout,
+            color_format,
+            uniform,
+            uniform_buffer,
+            bind_group,
+            vertex_buffer,
+            index_buffer,
+            index_count: indices.len() as u32,
+            instance_buffer: Self::create_instance_buffer(device, capacity),
+            capacity,
+            chunks: Vec::new(),
+            splat_map,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        shader_source: &str,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Terrain Shader"),
+            source: wgpu::ShaderSource::Wgsl(shader_source.into()),
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Terrain Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[TerrainVertex::desc(), ChunkInstance::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            // Skirts get seen from both sides
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+
+    // For apps watching their own copy of terrain.wgsl, keeps the old
+    // pipeline if the new source doesn't compile
+    pub fn reload_shader(&mut self, device: &wgpu::Device, shader_source: &str) {
+        if let Some(pipeline) = crate::shaders::try_create(device, || {
+            Self::create_pipeline(device, &self.layout, self.color_format, shader_source)
+        }) {
+            self.pipeline = pipeline;
+        }
+    }
+
+    fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Terrain Chunk Buffer"),
+            size: (capacity * std::mem::size_of::<ChunkInstance>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    pub fn config(&self) -> &TerrainConfig {
+        &self.config
+    }
+
+    pub fn heightmap(&self) -> &Heightmap {
+        &self.heightmap
+    }
+
+    // The ground's height at a point in the world, None off the edges
+    pub fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+        let uv = (Vec2::new(x, z) - Vec2::new(self.config.origin.x, self.config.origin.z)) / self.config.size;
+        if uv.cmplt(Vec2::ZERO).any() || uv.cmpgt(Vec2::ONE).any() {
+            return None;
+        }
+        Some(self.config.origin.y + self.heightmap.sample(uv) * self.config.height)
+    }
+
+    // Where the ground is along `ray`, the first place it dips under the
+    // surface. Steps along it half a height apart and then narrows down on
+    // the crossing, so it can miss ridges thinner than that.
+    pub fn raycast(&self, ray: &Ray) -> Option<Vec3> {
+        let bounds = self.node_bounds(0, 0, 0);
+        let start = ray.intersect_aabb(&bounds)?;
+        let length = ray.direction.length();
+        if length == 0.0 {
+            return None;
+        }
+        let (width, depth) = self.heightmap.size();
+        let spacing = self.config.size / (width.max(depth) - 1) as f32;
+        let step = spacing * 0.5 / length;
+        let steps = ((bounds.max - bounds.min).length() / (spacing * 0.5)).ceil() as u32 + 1;
+        let below = |t: f32| {
+            let point = ray.at(t);
+            self.height_at(point.x, point.z).map(|height| point.y <= height)
+        };
+
+        let mut last = start;
+        for i in 0..=steps {
+            let t = start + i as f32 * step;
+            match below(t) {
+                // Off the edge, having come in through the top or a side
+                None if i > 0 => return None,
+                Some(true) if i == 0 => return Some(ray.at(t)),
+                Some(true) => {
+                    let (mut above, mut under) = (last, t);
+                    for _ in 0..16 {
+                        let middle = (above + under) * 0.5;
+                        if below(middle) == Some(true) {
+                            under = middle;
+                        } else {
+                            above = middle;
+                        }
+                    }
+                    return Some(ray.at(under));
+                }
+                _ => last = t,
+            }
+        }
+        None
+    }
+
+    // Where a point in the world is on the splat map, 0 to 1 across it
+    pub fn splat_uv(&self, point: Vec3) -> Vec2 {
+        (Vec2::new(point.x, point.z) - Vec2::new(self.config.origin.x, self.config.origin.z)) / self.config.size
+    }
+
+    // Paint TerrainLayer::color() into it to show that layer, mixed with
+    // whatever else was painted there. Erasing brings back the colours from
+    // slope and height. Brush radiuses are in terrain sizes, see splat_uv().
+    pub fn splat_map(&self) -> &Canvas {
+        &self.splat_map
+    }
+
+    pub fn splat_map_mut(&mut self) -> &mut Canvas {
+        &mut self.splat_map
+    }
+
+    // How many chunks the last prepare() picked
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    // Walks the quadtree from `eye` and uploads the chunks to draw, along
+    // with what was painted into the splat map. Chunks outside of what
+    // `view_proj` sees are left out.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        uploader: &AppUploader,
+        eye: Vec3,
+        view_proj: Mat4,
+        lighting: &Lighting,
+    ) {
+        self.uniform.lighting = (*lighting).into();
+        uploader.write(&self.uniform_buffer, 0, &[self.uniform]);
+        self.splat_map.prepare(device, uploader);
+
+        let frustum = Frustum::from_view_proj(view_proj);
+        self.chunks.clear();
+        let mut chunks = std::mem::take(&mut self.chunks);
+        self.select(&mut chunks, eye, &frustum, 0, 0, 0);
+        self.chunks = chunks;
+
+        if self.chunks.len() > self.capacity {
+            self.capacity = self.chunks.len().next_power_of_two();
+            self.instance_buffer = Self::create_instance_buffer(device, self.capacity);
+        }
+        if !self.chunks.is_empty() {
+            uploader.write(&self.instance_buffer, 0, &self.chunks);
+        }
+    }
+
+    // World bounds of node (x, z) at `depth`
+    fn node_bounds(&self, depth: u32, x: u32, z: u32) -> Aabb {
+        let nodes = 1 << depth;
+        let scale = self.config.size / nodes as f32;
+        let (low, high) = self.ranges[depth as usize][(z * nodes + x) as usize];
+        let origin = self.config.origin;
+        Aabb::new(
+            origin + Vec3::new(x as f32 * scale, low * self.config.height, z as f32 * scale),
+            origin + Vec3::new((x + 1) as f32 * scale, high * self.config.height, (z + 1) as f32 * scale),
+        )
+    }
+
+    fn select(&self, chunks: &mut Vec<ChunkInstance>, eye: Vec3, frustum: &Frustum, depth: u32, x: u32, z: u32) {
+        let bounds = self.node_bounds(depth, x, z);
+        if !frustum.intersects_aabb(&bounds) {
+            return;
+        }
+        let nodes = 1 << depth;
+        let size = self.config.size / nodes as f32;
+        let distance = eye.clamp(bounds.min, bounds.max).distance(eye);
+        if depth < self.config.max_depth && distance < size * self.config.lod_distance {
+            for (dx, dz) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                self.select(chunks, eye, frustum, depth + 1, x * 2 + dx, z * 2 + dz);
+            }
+            return;
+        }
+        let scale = 1.0 / nodes as f32;
+        chunks.push(ChunkInstance {
+            offset: [x as f32 * scale, z as f32 * scale],
+            scale,
+        });
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera: &'a wgpu::BindGroup) {
+        if self.chunks.is_empty() {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(0..self.index_count, 0, 0..self.chunks.len() as u32);
+    }
+}
+
+// The steepest the heightmap gets between neighbouring heights, in world
+// units up per unit across
+fn max_slope(heightmap: &Heightmap, config: &TerrainConfig) -> f32 {
+    let (width, depth) = heightmap.size();
+    let spacing = config.size / (width.max(depth) - 1) as f32;
+    let mut steepest: f32 = 0.0;
+    for z in 0..depth {
+        for x in 0..width {
+            let height = heightmap.get(x, z);
+            steepest = steepest
+                .max((heightmap.get(x + 1, z) - height).abs())
+                .max((heightmap.get(x, z + 1) - height).abs());
+        }
+    }
+    steepest * config.height / spacing
+}
+
+// For each depth of the quadtree, the lowest and highest height under each
+// of its nodes. The deepest level is measured, the others merge the four
+// nodes below them.
+fn height_ranges(heightmap: &Heightmap, max_depth: u32) -> Vec<Vec<(f32, f32)>> {
+    let (width, depth) = heightmap.size();
+    let nodes = 1u32 << max_depth;
+    let mut deepest = Vec::with_capacity((nodes * nodes) as usize);
+    for z in 0..nodes {
+        for x in 0..nodes {
+            // Including the heights on the edges both neighbours share
+            let x_range = (x * (width - 1) / nodes)..=((x + 1) * (width - 1)).div_ceil(nodes);
+            let z_range = (z * (depth - 1) / nodes)..=((z + 1) * (depth - 1)).div_ceil(nodes);
+            let mut range = (f32::MAX, f32::MIN);
+            for hz in z_range {
+                for hx in x_range.clone() {
+                    let height = heightmap.get(hx, hz);
+                    range = (range.0.min(height), range.1.max(height));
+                }
+            }
+            deepest.push(range);
+        }
+    }
+
+    let mut ranges = vec![deepest];
+    for level in (0..max_depth).rev() {
+        let nodes = 1u32 << level;
+        let below = ranges.last().expect("starts with the deepest");
+        let merged = (0..nodes * nodes)
+            .map(|i| {
+                let (x, z) = (i % nodes, i / nodes);
+                [(0, 0), (1, 0), (0, 1), (1, 1)]
+                    .iter()
+                    .map(|(dx, dz)| below[((z * 2 + dz) * nodes * 2 + x * 2 + dx) as usize])
+                    .fold((f32::MAX, f32::MIN), |a, b| (a.0.min(b.0), a.1.max(b.1)))
+            })
+            .collect();
+        ranges.push(merged);
+    }
+    ranges.reverse();
+    ranges
+}
+
+// A grid of `resolution` quads each way from 0 to 1, with a skirt around the
+// edges: a copy of every edge vertex that the shader drops down, joined to
+// the edge by a strip of quads.
+fn chunk_mesh(resolution: u32) -> (Vec<TerrainVertex>, Vec<u32>) {
+    let row = resolution + 1;
+    let mut vertices = Vec::new();
+    for z in 0..row {
+        for x in 0..row {
+            vertices.push(TerrainVertex {
+                position: [x as f32 / resolution as f32, z as f32 / resolution as f32],
+                skirt: 0.0,
+            });
+        }
+    }
+    let mut indices = Vec::new();
+    for z in 0..resolution {
+        for x in 0..resolution {
+            let i = z * row + x;
+            indices.extend([i, i + row, i + 1, i + 1, i + row, i + row + 1]);
+        }
+    }
+
+    // Around the edge in order, back to where it started
+    let edge = (0..resolution)
+        .chain((0..resolution).map(|z| z * row + resolution))
+        .chain((1..=resolution).rev().map(|x| resolution * row + x))
+        .chain((1..=resolution).rev().map(|z| z * row))
+        .collect::<Vec<_>>();
+    let first_skirt = vertices.len() as u32;
+    for &i in &edge {
+        vertices.push(TerrainVertex {
+            skirt: 1.0,
+            ..vertices[i as usize]
+        });
+    }
+    for (n, (&a, &b)) in edge.iter().zip(edge.iter().cycle().skip(1)).enumerate() {
+        let n = n as u32;
+        let (skirt_a, skirt_b) = (first_skirt + n, first_skirt + (n + 1) % edge.len() as u32);
+        indices.extend([a, b, skirt_b, a, skirt_b, skirt_a]);
+    }
+    (vertices, indices)
+}
+
+// Flying around generated hills, `cargo run -- --terrain`. The scene is left
+// out, WASD and the mouse move the camera as usual. Holding the left mouse
+// button paints dirt where it points, the right one wipes it off again.
+pub struct TerrainApp {
+    config: TerrainConfig,
+    seed: u32,
+    terrain: Option<Terrain>,
+    water: Option<Water>,
+    foliage: Option<Foliage>,
+    // Which button is held, set by the MouseInput subscription
+    painting: Rc<Cell<Option<BrushMode>>>,
+    // Where the stroke got to last frame, on the splat map
+    last_paint: Option<Vec2>,
+}
+
+// In world units
+const BRUSH_RADIUS: f32 = 2.5;
+
+// Under the water, and behind the terrain if the sky gets turned off
+const SKY: wgpu::Color = wgpu::Color {
+    r: 0.35,
+    g: 0.5,
+    b: 0.75,
+    a: 1.0,
+};
+
+impl TerrainApp {
+    pub fn new(config: TerrainConfig, seed: u32) -> Self {
+        Self {
+            config,
+            seed,
+            terrain: None,
+            water: None,
+            foliage: None,
+            painting: Rc::new(Cell::new(None)),
+            last_paint: None,
+        }
+    }
+
+    // Carries the stroke on to wherever the mouse points now
+    fn paint(&mut self, ray: Option<Ray>) {
+        let Some(terrain) = &mut self.terrain else {
+            return;
+        };
+        let Some(mode) = self.painting.get() else {
+            self.last_paint = None;
+            return;
+        };
+        let Some(uv) = ray.and_then(|ray| terrain.raycast(&ray)).map(|point| terrain.splat_uv(point)) else {
+            self.last_paint = None;
+            return;
+        };
+        let brush = Brush::new(TerrainLayer::Dirt.color(), BRUSH_RADIUS / self.config.size)
+            .with_opacity(0.3)
+            .with_mode(mode);
+        match self.last_paint {
+            Some(last) => terrain.splat_map_mut().stroke(last, uv, &brush),
+            None => terrain.splat_map_mut().dab(uv, &brush),
+        }
+        self.last_paint = Some(uv);
+    }
+}
+
+impl Default for TerrainApp {
+    fn default() -> Self {
+        Self::new(TerrainConfig::default(), 1)
+    }
+}
+
+impl App for TerrainApp {
+    fn init(&mut self, setup: &mut Setup) {
+        setup.settings.draw_scene = false;
+        setup.settings.main_pass.clear = ClearMode::Color(SKY);
+        setup.settings.sky = Some(SkySettings::default());
+        self.terrain = Some(Terrain::new(
+            setup.device,
+            setup.queue,
+            setup.hdr_format,
+            setup.camera_bind_group_layout,
+            Heightmap::generate(512, self.seed),
+            self.config,
+        ));
+        // Filling the valleys up to a bit under a third of the way
+        let level = self.config.origin.y + self.config.height * 0.3;
+        self.water = Some(Water::new(
+            setup.device,
+            setup.hdr_format,
+            setup.camera_bind_group_layout,
+            setup.frame_bind_group_layout,
+            WaterConfig {
+                level,
+                center: Vec2::new(self.config.origin.x, self.config.origin.z) + Vec2::splat(self.config.size * 0.5),
+                size: self.config.size,
+                ..Default::default()
+            },
+        ));
+        let painting = self.painting.clone();
+        setup.events.subscribe(move |input: &MouseInput| {
+            let mode = match input.button {
+                MouseButton::Left => BrushMode::Paint,
+                MouseButton::Right => BrushMode::Erase,
+                _ => return,
+            };
+            if input.pressed {
+                painting.set(Some(mode));
+            } else if painting.get() == Some(mode) {
+                painting.set(None);
+            }
+        });
+
+        let terrain = self.terrain.as_ref().expect("just made");
+        self.foliage = Some(Foliage::new(
+            setup.device,
+            setup.hdr_format,
+            setup.camera_bind_group_layout,
+            setup.frame_bind_group_layout,
+            terrain,
+            &DensityMap::from_terrain(terrain, level),
+            FoliageConfig {
+                seed: self.seed,
+                ..Default::default()
+            },
+        ));
+    }
+
+    fn render<'g>(&'g mut self, graph: &mut RenderGraph<'g>, context: RenderContext<'g>) {
+        self.paint(context.cursor_ray);
+        let Some(terrain) = &mut self.terrain else {
+            return;
+        };
+        let lighting = &context.lighting;
+        terrain.prepare(context.device, context.uploader, context.camera_position, context.view_proj, lighting);
+        let terrain = &*terrain;
+        terrain.splat_map().add_pass(graph, Terrain::SPLAT_MAP);
+        if let Some(foliage) = &mut self.foliage {
+            foliage.prepare(context.uploader, context.camera_position, context.view_proj, lighting);
+        }
+        let foliage = self.foliage.as_ref();
+        let scene_target = context.scene_target;
+        graph
+            .add_pass("terrain")
+            .reads(&[Terrain::SPLAT_MAP])
+            .writes(&[scene_target, "depth"])
+            .execute(move |encoder, resources| {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Terrain Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: resources.view(scene_target),
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: resources.view("depth"),
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        }),
+                        stencil_ops: None,
+                    }),
+                });
+                terrain.draw(&mut render_pass, context.camera_bind_group);
+                if let Some(foliage) = foliage {
+                    foliage.draw(&mut render_pass, context.camera_bind_group, context.frame_bind_group);
+                }
+            });
+
+        let Some(water) = &mut self.water else {
+            return;
+        };
+        water.prepare(context.uploader, context.camera_position, context.view_proj, lighting);
+        let water = &*water;
+        water.declare(graph, context.hdr_format);
+        graph
+            .add_pass("water_reflection")
+            .reads(&[Terrain::SPLAT_MAP])
+            .writes(&[Water::REFLECTION, Water::REFLECTION_DEPTH])
+            .execute(move |encoder, resources| {
+                let mut render_pass = Water::reflection_pass(encoder, resources, SKY);
+                terrain.draw(&mut render_pass, water.reflection_camera());
+                if let Some(sky) = context.sky {
+                    sky.draw(&mut render_pass, water.reflection_camera());
+                }
+            });
+        graph
+            .add_pass("water_refraction")
+            .reads(&[Terrain::SPLAT_MAP])
+            .writes(&[Water::REFRACTION, Water::REFRACTION_DEPTH])
+            .execute(move |encoder, resources| {
+                let mut render_pass = Water::refraction_pass(encoder, resources, SKY);
+                terrain.draw(&mut render_pass, water.refraction_camera());
+            });
+        water.add_pass(graph, context.device, scene_target, context.camera_bind_group, context.frame_bind_group);
+    }
+}
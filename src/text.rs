@@ -0,0 +1,942 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use glam::Vec2;
+use rustybuzz::ttf_parser;
+use unicode_bidi::BidiInfo;
+use unicode_script::{Script, UnicodeScript};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::assets::{Assets, Handle};
+use crate::sprite::{Sprite, SpriteBatch, SpriteFilter};
+use crate::texture::Texture;
+
+// Glyphs are rasterized into one texture this many pixels across
+const ATLAS_SIZE: u32 = 1024;
+
+// One face from a TrueType or OpenType file. rustybuzz shapes text with it,
+// ab_glyph turns its outlines into pixels and colour bitmap fonts (emoji)
+// are drawn from their PNGs.
+pub struct Font {
+    name: String,
+    data: Vec<u8>,
+    index: u32,
+}
+
+impl Font {
+    pub fn from_bytes(name: impl Into<String>, data: Vec<u8>) -> Result<Self> {
+        let name = name.into();
+        ttf_parser::Face::parse(&data, 0).with_context(|| format!("parsing {}", name))?;
+        Ok(Self { name, data, index: 0 })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let data = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        Self::from_bytes(path.display().to_string(), data)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // Parsed again each time, the face borrows the data
+    fn face(&self) -> rustybuzz::Face<'_> {
+        rustybuzz::Face::from_slice(&self.data, self.index).expect("checked in from_bytes()")
+    }
+}
+
+// A run of text drawn one way. What it leaves out comes from the TextStyle
+// it's drawn with.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TextSpan {
+    pub text: String,
+    pub color: Option<[f32; 4]>,
+    // Logical pixels to the em
+    pub size: Option<f32>,
+    pub bold: bool,
+}
+
+impl TextSpan {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_color(mut self, color: [f32; 4]) -> Self {
+        self.color = Some(color);
+        self
+    }
+
+    pub fn with_size(mut self, size: f32) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    pub fn with_bold(mut self, bold: bool) -> Self {
+        self.bold = bold;
+        self
+    }
+}
+
+// Spans laid out one after the other as one text, so lines wrap and right to
+// left runs reorder across them
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RichText {
+    pub spans: Vec<TextSpan>,
+}
+
+impl RichText {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(mut self, span: impl Into<TextSpan>) -> Self {
+        self.spans.push(span.into());
+        self
+    }
+
+    // Rich text written out in a string, for text that comes from data
+    // files or translations:
+    //
+    //     Press [b]E[/b] to [color=#ffcc00]open[/color] the [size=32]door[/size]
+    //
+    // Colours are sRGB hex like colour pickers give them, with or without
+    // alpha. Tags nest, and `[[` is a `[`. Anything else in brackets is kept
+    // as it is.
+    pub fn markup(source: &str) -> Self {
+        // Which tag each style was opened with, the style outside every tag
+        // is at the bottom
+        let mut styles = vec![("", TextSpan::default())];
+        let mut spans = Vec::new();
+        let mut text = String::new();
+        let mut rest = source;
+        while let Some(open) = rest.find('[') {
+            text.push_str(&rest[..open]);
+            rest = &rest[open..];
+            if let Some(after) = rest.strip_prefix("[[") {
+                text.push('[');
+                rest = after;
+                continue;
+            }
+            let Some(close) = rest.find(']') else {
+                break;
+            };
+            let tag = &rest[1..close];
+            let (_, current) = styles.last().expect("the outermost style is never closed");
+            let mut style = current.clone();
+            let opened = match tag.split_once('=') {
+                None if tag == "b" => {
+                    style.bold = true;
+                    Some("b")
+                }
+                Some(("color", hex)) => parse_color(hex).map(|color| {
+                    style.color = Some(color);
+                    "color"
+                }),
+                Some(("size", size)) => size.trim().parse().ok().map(|size| {
+                    style.size = Some(size);
+                    "size"
+                }),
+                _ => None,
+            };
+            let closed = tag
+                .strip_prefix('/')
+                .and_then(|name| styles.iter().skip(1).rposition(|(opened, _)| *opened == name));
+            if opened.is_none() && closed.is_none() {
+                text.push_str(&rest[..=close]);
+                rest = &rest[close + 1..];
+                continue;
+            }
+            if !text.is_empty() {
+                let (_, current) = styles.last().expect("the outermost style is never closed");
+                spans.push(TextSpan {
+                    text: std::mem::take(&mut text),
+                    ..current.clone()
+                });
+            }
+            match (opened, closed) {
+                (Some(name), _) => styles.push((name, style)),
+                // Closes anything opened inside it too
+                (None, Some(index)) => styles.truncate(index + 1),
+                (None, None) => unreachable!(),
+            }
+            rest = &rest[close + 1..];
+        }
+        text.push_str(rest);
+        if !text.is_empty() {
+            let (_, current) = styles.last().expect("the outermost style is never closed");
+            spans.push(TextSpan { text, ..current.clone() });
+        }
+        Self { spans }
+    }
+}
+
+impl From<&str> for RichText {
+    fn from(text: &str) -> Self {
+        Self::new().push(TextSpan::new(text))
+    }
+}
+
+impl From<String> for RichText {
+    fn from(text: String) -> Self {
+        Self::new().push(TextSpan::new(text))
+    }
+}
+
+impl From<&str> for TextSpan {
+    fn from(text: &str) -> Self {
+        Self::new(text)
+    }
+}
+
+impl From<TextSpan> for RichText {
+    fn from(span: TextSpan) -> Self {
+        Self::new().push(span)
+    }
+}
+
+// "#rrggbb" or "#rrggbbaa" in sRGB, to the linear colour sprites take
+fn parse_color(hex: &str) -> Option<[f32; 4]> {
+    let hex = hex.trim().strip_prefix('#')?;
+    if !matches!(hex.len(), 6 | 8) || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok();
+    let alpha = if hex.len() == 8 { channel(3)? } else { 255 };
+    let linear = |c: u8| {
+        let c = c as f32 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    Some([linear(channel(0)?), linear(channel(1)?), linear(channel(2)?), alpha as f32 / 255.0])
+}
+
+// Where lines go within the text's width: max_width when there is one, the
+// widest line's otherwise
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Align {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+// How text is drawn where its spans don't say otherwise, and how it's laid
+// out
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextStyle {
+    // Logical pixels to the em
+    pub size: f32,
+    pub color: [f32; 4],
+    // Lines wrap between words to stay narrower than this, and words too
+    // long for a line of their own between graphemes
+    pub max_width: Option<f32>,
+    pub align: Align,
+    // Multiplies the font's own line height
+    pub line_spacing: f32,
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        Self {
+            size: 16.0,
+            color: [1.0; 4],
+            max_width: None,
+            align: Align::Left,
+            line_spacing: 1.0,
+        }
+    }
+}
+
+impl TextStyle {
+    pub fn new(size: f32) -> Self {
+        Self {
+            size,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_color(mut self, color: [f32; 4]) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn with_max_width(mut self, max_width: f32) -> Self {
+        self.max_width = Some(max_width);
+        self
+    }
+
+    pub fn with_align(mut self, align: Align) -> Self {
+        self.align = align;
+        self
+    }
+
+    pub fn with_line_spacing(mut self, line_spacing: f32) -> Self {
+        self.line_spacing = line_spacing;
+        self
+    }
+}
+
+// A glyph placed by Fonts::layout()
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PositionedGlyph {
+    // Which of the fonts it comes from
+    pub font: usize,
+    pub glyph: u16,
+    // Where the pen is on the baseline, in logical pixels from the layout's
+    // top left corner
+    pub position: Vec2,
+    // How far the pen moves on after it
+    pub advance: f32,
+    // The byte in TextLayout::text the glyph's grapheme starts at, and the
+    // span that's in
+    pub cluster: usize,
+    pub span: usize,
+    pub size: f32,
+    pub color: [f32; 4],
+    // Bold without a bold font, drawn twice a little apart
+    pub embolden: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LineMetrics {
+    // The bytes of TextLayout::text on the line, without the spaces or
+    // newline it was broken at
+    pub range: Range<usize>,
+    // Which of TextLayout::glyphs are on it
+    pub glyphs: Range<usize>,
+    // From the layout's top left corner, after alignment
+    pub left: f32,
+    pub top: f32,
+    pub width: f32,
+    pub height: f32,
+    pub baseline: f32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TextLayout {
+    // Every span's text one after the other
+    pub text: String,
+    pub glyphs: Vec<PositionedGlyph>,
+    pub lines: Vec<LineMetrics>,
+    // How wide (max_width, if there was one) and tall it is together
+    pub size: Vec2,
+}
+
+impl TextLayout {
+    // The glyph under `point`, which is relative to the layout's top left
+    // corner like the glyphs are. Its cluster and span tell what was hit.
+    pub fn hit_test(&self, point: Vec2) -> Option<&PositionedGlyph> {
+        let line = self.lines.iter().find(|line| (line.top..line.top + line.height).contains(&point.y))?;
+        self.glyphs[line.glyphs.clone()]
+            .iter()
+            .find(|glyph| (glyph.position.x..glyph.position.x + glyph.advance).contains(&point.x))
+    }
+
+    // The line `index` (a byte of `text`) is on, for putting a caret there
+    pub fn line_of(&self, index: usize) -> Option<&LineMetrics> {
+        self.lines.iter().find(|line| line.range.contains(&index) || line.range.end == index)
+    }
+}
+
+// A fallback chain. Every grapheme (a character along with its combining
+// marks, or a whole emoji sequence) comes from the first font that has
+// glyphs for all of it, so e.g. a Latin font followed by a CJK one and an
+// emoji one covers most text:
+//
+//     fonts.add(Font::load("fonts/NotoSans-Regular.ttf")?);
+//     fonts.add(Font::load("fonts/NotoSansCJK-Regular.ttc")?);
+//     fonts.add(Font::load("fonts/NotoColorEmoji.ttf")?);
+//
+// Whatever none of them have comes out as the first font's missing glyph.
+// Bold text has a chain of its own, add_bold() fills it. Until it's got
+// something bold text is drawn twice over with the regular fonts.
+#[derive(Default)]
+pub struct Fonts {
+    fonts: Vec<Font>,
+    // Indices into `fonts`, in fallback order
+    regular: Vec<usize>,
+    bold: Vec<usize>,
+}
+
+// A piece of a paragraph shaped in one go: one font, size, direction and
+// script
+struct Item {
+    range: Range<usize>,
+    font: usize,
+    size: f32,
+    rtl: bool,
+    embolden: bool,
+}
+
+// Shaped, but not yet placed on a line
+struct ShapedGlyph {
+    glyph: u16,
+    cluster: usize,
+    offset: Vec2,
+    advance: f32,
+}
+
+impl Fonts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Goes to the end of the chain, after every font added before it
+    pub fn add(&mut self, font: Font) {
+        tracing::info!("Added font {}", font.name);
+        self.regular.push(self.fonts.len());
+        self.fonts.push(font);
+    }
+
+    // The same for the chain bold text uses
+    pub fn add_bold(&mut self, font: Font) {
+        tracing::info!("Added bold font {}", font.name);
+        self.bold.push(self.fonts.len());
+        self.fonts.push(font);
+    }
+
+    pub fn load(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        self.add(Font::load(path)?);
+        Ok(())
+    }
+
+    pub fn load_bold(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        self.add_bold(Font::load(path)?);
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.regular.is_empty()
+    }
+
+    // Any font, regular or bold, by the index glyphs refer to it by
+    pub fn get(&self, index: usize) -> Option<&Font> {
+        self.fonts.get(index)
+    }
+
+    // How tall a line of `size` text is, from the first font
+    pub fn line_height(&self, size: f32) -> f32 {
+        self.regular.first().map_or(size, |&font| {
+            let face = self.fonts[font].face();
+            let height = face.ascender() - face.descender() + face.line_gap();
+            height as f32 * size / face.units_per_em() as f32
+        })
+    }
+
+    fn ascent(&self, size: f32) -> f32 {
+        self.regular.first().map_or(size, |&font| {
+            let face = self.fonts[font].face();
+            face.ascender() as f32 * size / face.units_per_em() as f32
+        })
+    }
+
+    // Shapes and places `text`. Lines break at newlines, and between words
+    // past `style.max_width`. Right to left scripts come out right to left,
+    // mixed with left to right ones by the Unicode bidi algorithm, and every
+    // run of one script in one font is shaped on its own so ligatures,
+    // joining and combining marks work.
+    pub fn layout(&self, text: &RichText, style: &TextStyle) -> TextLayout {
+        let mut layout = TextLayout::default();
+        let mut spans = Vec::new();
+        for span in &text.spans {
+            let start = layout.text.len();
+            layout.text.push_str(&span.text);
+            spans.push((start..layout.text.len(), span));
+        }
+        if self.is_empty() {
+            return layout;
+        }
+        let text_str = layout.text.as_str();
+        let span_at = |index: usize| span_at(&spans, index);
+        let faces = self.fonts.iter().map(Font::face).collect::<Vec<_>>();
+        let bidi = BidiInfo::new(text_str, None);
+        let mut glyphs = Vec::new();
+        let mut lines = Vec::new();
+        let mut top = 0.0;
+        for paragraph in &bidi.paragraphs {
+            // Paragraphs end with the newline that ended them
+            let end = paragraph.range.start + text_str[paragraph.range.clone()].trim_end_matches(['\n', '\r']).len();
+            let items = self.itemize(&faces, text_str, &spans, &bidi.levels, paragraph.range.start..end, style);
+            let shaped = items.iter().map(|item| shape(&faces[item.font], text_str, item)).collect::<Vec<_>>();
+            for range in break_lines(text_str, paragraph.range.start..end, &shaped, style.max_width) {
+                // The spaces it was broken at would only push it off center
+                let range = range.start..range.start + text_str[range.clone()].trim_end().len();
+                let first = glyphs.len();
+                let mut x = 0.0;
+                let mut size = None::<f32>;
+                if !range.is_empty() {
+                    let (levels, runs) = bidi.visual_runs(paragraph, range.clone());
+                    for run in runs {
+                        let rtl = levels[run.start].is_rtl();
+                        let mut order = (0..items.len())
+                            .filter(|&i| items[i].range.start < run.end && run.start < items[i].range.end)
+                            .collect::<Vec<_>>();
+                        if rtl {
+                            order.reverse();
+                        }
+                        for i in order {
+                            let item = &items[i];
+                            size = Some(size.map_or(item.size, |size| size.max(item.size)));
+                            for glyph in shaped[i].iter().filter(|glyph| run.contains(&glyph.cluster)) {
+                                let span = span_at(glyph.cluster);
+                                glyphs.push(PositionedGlyph {
+                                    font: item.font,
+                                    glyph: glyph.glyph,
+                                    position: Vec2::new(x, 0.0) + glyph.offset,
+                                    advance: glyph.advance,
+                                    cluster: glyph.cluster,
+                                    span,
+                                    size: item.size,
+                                    color: text.spans[span].color.unwrap_or(style.color),
+                                    embolden: item.embolden,
+                                });
+                                x += glyph.advance;
+                            }
+                        }
+                    }
+                }
+                // Empty lines are as tall as the text around them would be
+                let size = size.unwrap_or_else(|| {
+                    spans.get(span_at(range.start)).and_then(|(_, span)| span.size).unwrap_or(style.size)
+                });
+                let baseline = top + self.ascent(size);
+                for glyph in &mut glyphs[first..] {
+                    glyph.position.y += baseline;
+                }
+                let height = self.line_height(size) * style.line_spacing;
+                lines.push(LineMetrics {
+                    range,
+                    glyphs: first..glyphs.len(),
+                    left: 0.0,
+                    top,
+                    width: x,
+                    height,
+                    baseline,
+                });
+                top += height;
+            }
+        }
+
+        let width = style.max_width.unwrap_or_else(|| lines.iter().map(|line| line.width).fold(0.0, f32::max));
+        for line in &mut lines {
+            line.left = match style.align {
+                Align::Left => 0.0,
+                Align::Center => (width - line.width) / 2.0,
+                Align::Right => width - line.width,
+            };
+            for glyph in &mut glyphs[line.glyphs.clone()] {
+                glyph.position.x += line.left;
+            }
+        }
+        layout.glyphs = glyphs;
+        layout.lines = lines;
+        layout.size = Vec2::new(width, top);
+        layout
+    }
+
+    // Splits a paragraph into pieces that can be shaped together: the same
+    // direction, font, size and script. Characters belonging to every
+    // script, like spaces and punctuation, go along with whatever's around
+    // them. Colours don't matter here, so Arabic keeps joining across them.
+    fn itemize(
+        &self,
+        faces: &[rustybuzz::Face],
+        text: &str,
+        spans: &[(Range<usize>, &TextSpan)],
+        levels: &[unicode_bidi::Level],
+        paragraph: Range<usize>,
+        style: &TextStyle,
+    ) -> Vec<Item> {
+        let mut items: Vec<(Item, Option<Script>)> = Vec::new();
+        for (start, grapheme) in text[paragraph.clone()].grapheme_indices(true) {
+            let start = paragraph.start + start;
+            let end = start + grapheme.len();
+            let (_, span) = spans[span_at(spans, start)];
+            let embolden = span.bold && self.bold.is_empty();
+            let chain = if span.bold && !embolden { &self.bold } else { &self.regular };
+            // Failing that, the base character without whatever's been added
+            // on (a skin tone, say) beats a missing glyph
+            let base = &grapheme[..grapheme.chars().next().map_or(0, char::len_utf8)];
+            let font = chain
+                .iter()
+                .find(|&&font| covers(&faces[font], grapheme))
+                .or_else(|| chain.iter().find(|&&font| covers(&faces[font], base)))
+                .copied()
+                .unwrap_or(chain[0]);
+            let size = span.size.unwrap_or(style.size);
+            let rtl = levels[start].is_rtl();
+            let script = grapheme
+                .chars()
+                .map(|c| c.script())
+                .find(|script| !matches!(script, Script::Common | Script::Inherited | Script::Unknown));
+            match items.last_mut() {
+                Some((item, last_script))
+                    if item.font == font
+                        && item.size == size
+                        && item.rtl == rtl
+                        && item.embolden == embolden
+                        && (script.is_none() || last_script.is_none() || script == *last_script) =>
+                {
+                    item.range.end = end;
+                    *last_script = last_script.or(script);
+                }
+                _ => items.push((Item { range: start..end, font, size, rtl, embolden }, script)),
+            }
+        }
+        items.into_iter().map(|(item, _)| item).collect()
+    }
+}
+
+// Which span the byte `index` of the laid out text is in. The last one
+// starting at or before it, so empty spans in front of it don't count.
+fn span_at(spans: &[(Range<usize>, &TextSpan)], index: usize) -> usize {
+    spans.iter().rposition(|(range, _)| range.start <= index).unwrap_or(0)
+}
+
+fn shape(face: &rustybuzz::Face, text: &str, item: &Item) -> Vec<ShapedGlyph> {
+    let mut buffer = rustybuzz::UnicodeBuffer::new();
+    buffer.push_str(&text[item.range.clone()]);
+    buffer.set_direction(if item.rtl { rustybuzz::Direction::RightToLeft } else { rustybuzz::Direction::LeftToRight });
+    // Picks the script from the text
+    buffer.guess_segment_properties();
+    let shaped = rustybuzz::shape(face, &[], buffer);
+    let scale = item.size / face.units_per_em() as f32;
+    shaped
+        .glyph_infos()
+        .iter()
+        .zip(shaped.glyph_positions())
+        .map(|(info, position)| ShapedGlyph {
+            glyph: info.glyph_id as u16,
+            cluster: item.range.start + info.cluster as usize,
+            offset: Vec2::new(position.x_offset as f32, -position.y_offset as f32) * scale,
+            advance: position.x_advance as f32 * scale,
+        })
+        .collect()
+}
+
+// Where the paragraph's lines go, greedily fitting as many words on each as
+// there's room for. Line break opportunities are the Unicode ones, so CJK
+// breaks between characters and nothing breaks before punctuation.
+fn break_lines(
+    text: &str,
+    paragraph: Range<usize>,
+    shaped: &[Vec<ShapedGlyph>],
+    max_width: Option<f32>,
+) -> Vec<Range<usize>> {
+    let Some(max_width) = max_width else {
+        return vec![paragraph];
+    };
+    // How wide each byte's glyphs are, shaping doesn't care about lines
+    let mut widths = vec![0.0; paragraph.len()];
+    for glyph in shaped.iter().flatten() {
+        widths[glyph.cluster - paragraph.start] += glyph.advance;
+    }
+    // Without the spaces it would be broken at
+    let width = |range: Range<usize>| {
+        let end = range.start + text[range.clone()].trim_end().len();
+        widths[range.start - paragraph.start..end - paragraph.start].iter().sum::<f32>()
+    };
+
+    let mut lines = Vec::new();
+    let mut start = paragraph.start;
+    // The last place the current line could end
+    let mut fits = start;
+    for (offset, _) in unicode_linebreak::linebreaks(&text[paragraph.clone()]) {
+        let end = paragraph.start + offset;
+        if width(start..end) <= max_width {
+            fits = end;
+            continue;
+        }
+        if fits > start {
+            lines.push(start..fits);
+            start = fits;
+        }
+        // A word that's too long for a line of its own
+        while width(start..end) > max_width {
+            let mut cut = start;
+            for (index, grapheme) in text[start..end].grapheme_indices(true) {
+                let next = start + index + grapheme.len();
+                if cut > start && width(start..next) > max_width {
+                    break;
+                }
+                cut = next;
+            }
+            if cut == end {
+                break;
+            }
+            lines.push(start..cut);
+            start = cut;
+        }
+        fits = end;
+    }
+    if start < paragraph.end || lines.is_empty() {
+        lines.push(start..paragraph.end);
+    }
+    lines
+}
+
+// Whether the font has a glyph for everything in `grapheme` that's drawn
+fn covers(face: &rustybuzz::Face, grapheme: &str) -> bool {
+    grapheme.chars().filter(|&c| !is_invisible(c)).all(|c| face.glyph_index(c).is_some())
+}
+
+// Joiners and variation selectors steer how the characters around them are
+// drawn, fonts don't need glyphs of their own for them
+fn is_invisible(c: char) -> bool {
+    matches!(
+        c,
+        '\u{200c}' | '\u{200d}' | '\u{fe00}'..='\u{fe0f}' | '\u{e0020}'..='\u{e007f}' | '\u{e0100}'..='\u{e01ef}'
+    )
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct GlyphKey {
+    font: usize,
+    glyph: u16,
+    // The f32 size in physical pixels to the em, as bits
+    size: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct AtlasGlyph {
+    // Where it is in the atlas, in pixels
+    min: [u32; 2],
+    size: [u32; 2],
+    // From the pen position to the glyph's top left corner, in physical
+    // pixels
+    offset: Vec2,
+    // Colour bitmaps keep their own colours, only outlines get tinted
+    color: bool,
+}
+
+// Every glyph that's been drawn, rasterized once at the size it's drawn at
+// and packed into rows of one texture. When it fills up it's emptied the next
+// frame and whatever's still drawn gets rasterized again.
+pub struct GlyphAtlas {
+    texture: Handle<Texture>,
+    pixels: image::RgbaImage,
+    // None for glyphs with nothing to draw, like spaces
+    glyphs: HashMap<GlyphKey, Option<AtlasGlyph>>,
+    // Where the next glyph goes, and how tall the row it's on is so far
+    cursor: [u32; 2],
+    row_height: u32,
+    full: bool,
+    // Rows of pixels written since the last upload()
+    dirty: Option<Range<u32>>,
+}
+
+impl GlyphAtlas {
+    pub fn new(device: &wgpu::Device, assets: &mut Assets) -> Self {
+        let size = wgpu::Extent3d {
+            width: ATLAS_SIZE,
+            height: ATLAS_SIZE,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("glyph_atlas"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = Arc::new(device.create_sampler(&wgpu::SamplerDescriptor {
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        }));
+        let texture = assets.insert_texture("glyph_atlas", Texture { texture, view, sampler });
+        Self {
+            texture,
+            pixels: image::RgbaImage::new(ATLAS_SIZE, ATLAS_SIZE),
+            glyphs: HashMap::new(),
+            cursor: [0, 0],
+            row_height: 0,
+            full: false,
+            dirty: Some(0..ATLAS_SIZE),
+        }
+    }
+
+    // Called before anything's drawn each frame
+    pub fn begin_frame(&mut self) {
+        if std::mem::take(&mut self.full) {
+            tracing::debug!("Glyph atlas is full, starting over");
+            self.pixels.fill(0);
+            self.glyphs.clear();
+            self.cursor = [0, 0];
+            self.row_height = 0;
+            self.dirty = Some(0..ATLAS_SIZE);
+        }
+    }
+
+    // Sprites for every glyph of `layout`, its top left corner at `position`
+    // in logical pixels. Glyphs are rasterized for `scale_factor` so they
+    // stay sharp on high DPI displays.
+    pub fn draw(
+        &mut self,
+        fonts: &Fonts,
+        layout: &TextLayout,
+        position: Vec2,
+        scale_factor: f32,
+        sprites: &mut SpriteBatch,
+    ) {
+        for glyph in &layout.glyphs {
+            let size = glyph.size * scale_factor;
+            let key = GlyphKey {
+                font: glyph.font,
+                glyph: glyph.glyph,
+                size: size.to_bits(),
+            };
+            let Some(atlas_glyph) = self.glyph(fonts, key) else {
+                continue;
+            };
+            // Snapped to whole pixels, the glyph was rasterized on them
+            let pen = ((position + glyph.position) * scale_factor).round();
+            let min = Vec2::new(atlas_glyph.min[0] as f32, atlas_glyph.min[1] as f32);
+            let extent = Vec2::new(atlas_glyph.size[0] as f32, atlas_glyph.size[1] as f32);
+            let color = if atlas_glyph.color { [1.0, 1.0, 1.0, glyph.color[3]] } else { glyph.color };
+            // Drawn one texel to a pixel anyway, and left alone in pixel art
+            // mode so text stays as it was
+            let sprite = Sprite::new(self.texture, (pen + atlas_glyph.offset) / scale_factor, extent / scale_factor)
+                .with_uv(min / ATLAS_SIZE as f32, (min + extent) / ATLAS_SIZE as f32)
+                .with_color(color)
+                .with_filter(SpriteFilter::Texture);
+            sprites.push(sprite);
+            if glyph.embolden {
+                let shift = (size / 24.0).max(1.0).round() / scale_factor;
+                sprites.push(Sprite {
+                    position: sprite.position + Vec2::new(shift, 0.0),
+                    ..sprite
+                });
+            }
+        }
+    }
+
+    fn glyph(&mut self, fonts: &Fonts, key: GlyphKey) -> Option<AtlasGlyph> {
+        if let Some(glyph) = self.glyphs.get(&key) {
+            return *glyph;
+        }
+        let Some((image, offset, color)) = fonts.get(key.font).and_then(|font| rasterize(font, key)) else {
+            self.glyphs.insert(key, None);
+            return None;
+        };
+        let (width, height) = image.dimensions();
+        // A pixel of padding keeps neighbours from bleeding in when sampling
+        if self.cursor[0] + width + 1 > ATLAS_SIZE {
+            self.cursor = [0, self.cursor[1] + self.row_height + 1];
+            self.row_height = 0;
+        }
+        if width + 1 > ATLAS_SIZE || self.cursor[1] + height + 1 > ATLAS_SIZE {
+            // Not cached, it might fit once the atlas has been emptied
+            self.full = true;
+            return None;
+        }
+        let [x, y] = self.cursor;
+        image::imageops::replace(&mut self.pixels, &image, x as i64, y as i64);
+        self.cursor[0] += width + 1;
+        self.row_height = self.row_height.max(height);
+        self.dirty = Some(match self.dirty.take() {
+            Some(rows) => rows.start.min(y)..rows.end.max(y + height),
+            None => y..y + height,
+        });
+        let glyph = AtlasGlyph {
+            min: [x, y],
+            size: [width, height],
+            offset,
+            color,
+        };
+        self.glyphs.insert(key, Some(glyph));
+        Some(glyph)
+    }
+
+    // Copies the rows glyphs were added to since last time to the GPU
+    pub fn upload(&mut self, queue: &wgpu::Queue, assets: &Assets) {
+        let Some(rows) = self.dirty.take() else {
+            return;
+        };
+        let row_bytes = 4 * ATLAS_SIZE as usize;
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &assets.texture(self.texture).texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x: 0, y: rows.start, z: 0 },
+            },
+            &self.pixels.as_raw()[rows.start as usize * row_bytes..rows.end as usize * row_bytes],
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(4 * ATLAS_SIZE),
+                rows_per_image: None,
+            },
+            wgpu::Extent3d {
+                width: ATLAS_SIZE,
+                height: rows.end - rows.start,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
+
+// A glyph's pixels, where they go from the pen position and whether they're
+// in colour
+type Rasterized = (image::RgbaImage, Vec2, bool);
+
+// None for glyphs without any pixels
+fn rasterize(font: &Font, key: GlyphKey) -> Option<Rasterized> {
+    let size = f32::from_bits(key.size);
+    let glyph_id = ttf_parser::GlyphId(key.glyph);
+    match rasterize_bitmap(font, glyph_id, size) {
+        Ok(Some(bitmap)) => return Some(bitmap),
+        Ok(None) => {}
+        Err(e) => tracing::warn!("Failed to decode glyph {} of {}: {:?}", key.glyph, font.name, e),
+    }
+
+    use ab_glyph::Font as _;
+    let outlines = ab_glyph::FontRef::try_from_slice_and_index(&font.data, font.index).ok()?;
+    // ab_glyph's scale is the height from descender to ascender, not the em
+    let scale = size * outlines.height_unscaled() / outlines.units_per_em()?;
+    let outline = outlines.outline_glyph(ab_glyph::GlyphId(key.glyph).with_scale(scale))?;
+    let bounds = outline.px_bounds();
+    let mut image = image::RgbaImage::new(bounds.width() as u32, bounds.height() as u32);
+    outline.draw(|x, y, coverage| {
+        if let Some(pixel) = image.get_pixel_mut_checked(x, y) {
+            *pixel = image::Rgba([255, 255, 255, (coverage * 255.0).round() as u8]);
+        }
+    });
+    (image.width() > 0 && image.height() > 0).then(|| (image, Vec2::new(bounds.min.x, bounds.min.y), false))
+}
+
+// Colour emoji fonts keep their glyphs as PNGs at a few sizes, the one
+// closest to `size` gets scaled to it
+fn rasterize_bitmap(font: &Font, glyph_id: ttf_parser::GlyphId, size: f32) -> Result<Option<Rasterized>> {
+    let face = font.face();
+    let Some(bitmap) = face.glyph_raster_image(glyph_id, size.round().clamp(1.0, u16::MAX as f32) as u16) else {
+        return Ok(None);
+    };
+    if bitmap.format != ttf_parser::RasterImageFormat::PNG {
+        return Ok(None);
+    }
+    let image = image::load_from_memory_with_format(bitmap.data, image::ImageFormat::Png)?.into_rgba8();
+    let scale = size / bitmap.pixels_per_em.max(1) as f32;
+    let width = (image.width() as f32 * scale).round() as u32;
+    let height = (image.height() as f32 * scale).round() as u32;
+    if width == 0 || height == 0 {
+        return Err(anyhow!("scaled down to nothing"));
+    }
+    let image = image::imageops::resize(&image, width, height, image::imageops::FilterType::Triangle);
+    // The bitmap's origin is its bottom left corner, with y going up
+    let offset = Vec2::new(bitmap.x as f32 * scale, -(bitmap.y as f32 * scale) - height as f32);
+    Ok(Some((image, offset, true)))
+}
@@ -0,0 +1,165 @@
+use glam::Vec2;
+use winit::dpi::LogicalPosition;
+use winit::event::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent};
+use winit::window::Window;
+
+// Typed text and editing keys turned into a stream of events, for consoles,
+// text fields and the like. Characters arrive already composed: with an IME
+// the platform draws the composition itself, all we do is tell it where the
+// caret is with set_ime_position() so the candidate window shows up there.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TextEvent {
+    Insert(String),
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Home,
+    End,
+    // Enter
+    Submit,
+}
+
+#[derive(Default)]
+pub struct TextInput {
+    active: bool,
+    events: Vec<TextEvent>,
+    // Where the caret is in logical pixels, so IME popups line up with it
+    ime_position: Option<Vec2>,
+    ime_dirty: bool,
+}
+
+impl TextInput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    // While inactive, typing is left alone for camera controls and shortcuts
+    pub fn set_active(&mut self, active: bool) {
+        self.active = active;
+        if !active {
+            self.events.clear();
+        }
+    }
+
+    pub fn set_ime_position(&mut self, position: Vec2) {
+        self.ime_dirty |= self.ime_position != Some(position);
+        self.ime_position = Some(position);
+    }
+
+    // Returns true if the event was used up as text input
+    pub fn handle_event(&mut self, event: &WindowEvent) -> bool {
+        if !self.active {
+            return false;
+        }
+        match event {
+            WindowEvent::ReceivedCharacter(c) => {
+                match c {
+                    // Editing keys are handled from KeyboardInput below
+                    '\u{8}' | '\u{7f}' | '\r' | '\n' => {}
+                    c if c.is_control() => {}
+                    c => match self.events.last_mut() {
+                        Some(TextEvent::Insert(text)) => text.push(*c),
+                        _ => self.events.push(TextEvent::Insert(c.to_string())),
+                    },
+                }
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(keycode),
+                    ..
+                },
+                ..
+            } => {
+                let event = match keycode {
+                    VirtualKeyCode::Back => TextEvent::Backspace,
+                    VirtualKeyCode::Delete => TextEvent::Delete,
+                    VirtualKeyCode::Left => TextEvent::Left,
+                    VirtualKeyCode::Right => TextEvent::Right,
+                    VirtualKeyCode::Home => TextEvent::Home,
+                    VirtualKeyCode::End => TextEvent::End,
+                    VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => TextEvent::Submit,
+                    // Escape and the function keys still reach everything else
+                    VirtualKeyCode::Escape => return false,
+                    keycode if (VirtualKeyCode::F1..=VirtualKeyCode::F24).contains(keycode) => return false,
+                    _ => return true,
+                };
+                self.events.push(event);
+                true
+            }
+            // Swallow releases too, so held movement keys don't get stuck
+            WindowEvent::KeyboardInput { .. } => true,
+            _ => false,
+        }
+    }
+
+    // Everything typed since the last call
+    pub fn drain(&mut self) -> impl Iterator<Item = TextEvent> + '_ {
+        self.events.drain(..)
+    }
+
+    pub fn apply(&mut self, window: &Window) {
+        if let (true, Some(position)) = (self.ime_dirty, self.ime_position) {
+            window.set_ime_position(LogicalPosition::new(position.x, position.y));
+            self.ime_dirty = false;
+        }
+    }
+}
+
+// A single line of editable text with a caret, fed from TextInput
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TextBuffer {
+    pub text: String,
+    // Byte offset, always on a char boundary
+    caret: usize,
+}
+
+impl TextBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn caret(&self) -> usize {
+        self.caret
+    }
+
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.caret = 0;
+    }
+
+    // Returns the line when Submit comes through, and clears the buffer
+    pub fn apply(&mut self, event: &TextEvent) -> Option<String> {
+        let previous = self.text[..self.caret].chars().next_back().map_or(0, char::len_utf8);
+        let next = self.text[self.caret..].chars().next().map_or(0, char::len_utf8);
+        match event {
+            TextEvent::Insert(text) => {
+                self.text.insert_str(self.caret, text);
+                self.caret += text.len();
+            }
+            TextEvent::Backspace if previous > 0 => {
+                self.caret -= previous;
+                self.text.remove(self.caret);
+            }
+            TextEvent::Delete if next > 0 => {
+                self.text.remove(self.caret);
+            }
+            TextEvent::Left => self.caret -= previous,
+            TextEvent::Right => self.caret += next,
+            TextEvent::Home => self.caret = 0,
+            TextEvent::End => self.caret = self.text.len(),
+            TextEvent::Submit => {
+                self.caret = 0;
+                return Some(std::mem::take(&mut self.text));
+            }
+            TextEvent::Backspace | TextEvent::Delete => {}
+        }
+        None
+    }
+}
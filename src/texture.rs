@@ -1,10 +1,162 @@
+use std::collections::HashMap;
+use std::num::NonZeroU8;
+use std::sync::Arc;
+
 use image::GenericImageView;
 use anyhow::*;
 
+// The most anisotropic filtering samplers take
+pub const MAX_ANISOTROPY: u8 = 16;
+// What mipmapped textures get unless told otherwise. Keeps floors and walls
+// seen at a glancing angle sharp, for next to nothing on anything recent.
+pub const DEFAULT_ANISOTROPY: u8 = 8;
+
+// `max` as samplers take it, the power of two at or below it up to
+// MAX_ANISOTROPY. 1 and below turn it off. GPUs without anisotropic
+// filtering (some WebGL ones) quietly go without.
+pub fn anisotropy_clamp(max: u8) -> Option<NonZeroU8> {
+    let max = max.min(MAX_ANISOTROPY);
+    if max <= 1 {
+        return None;
+    }
+    NonZeroU8::new(1 << (7 - max.leading_zeros()))
+}
+
+// What the numbers in a texture stand for. Colours someone painted, like
+// albedo maps, are sRGB and get turned back into linear light as they're
+// sampled. Data like normal, roughness and height maps is linear already, and
+// comes out darker and bent when read as sRGB.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    #[default]
+    Srgb,
+    Linear,
+}
+
+impl ColorSpace {
+    pub fn format(self) -> wgpu::TextureFormat {
+        match self {
+            ColorSpace::Srgb => wgpu::TextureFormat::Rgba8UnormSrgb,
+            ColorSpace::Linear => wgpu::TextureFormat::Rgba8Unorm,
+        }
+    }
+}
+
+// How a texture gets sampled, picked when it's loaded (see
+// Assets::load_texture_with()). Textures asking for the same options share
+// one sampler, see SamplerCache.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SamplerOptions {
+    // Close up, Nearest keeps pixel art blocky
+    pub mag_filter: wgpu::FilterMode,
+    // Far away
+    pub min_filter: wgpu::FilterMode,
+    // Between mips, for textures that have them
+    pub mipmap_filter: wgpu::FilterMode,
+    // Outside 0 to 1. Repeat tiles, MirrorRepeat tiles without seams and
+    // ClampToBorder shows border_color.
+    pub address_mode_u: wgpu::AddressMode,
+    pub address_mode_v: wgpu::AddressMode,
+    // Needs Features::ADDRESS_MODE_CLAMP_TO_BORDER, without it clamping to
+    // the border clamps to the edge instead
+    pub border_color: Option<wgpu::SamplerBorderColor>,
+    // The most anisotropic filtering it gets, RenderSettings::max_anisotropy
+    // caps it further
+    pub anisotropy: u8,
+}
+
+impl Default for SamplerOptions {
+    // How textures that aren't mipmapped have always been sampled
+    fn default() -> Self {
+        Self {
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            border_color: None,
+            anisotropy: 1,
+        }
+    }
+}
+
+impl SamplerOptions {
+    // Trilinear, with as much anisotropic filtering as the settings allow.
+    // What streamed textures get unless they're told otherwise.
+    pub fn mipmapped() -> Self {
+        Self {
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            anisotropy: MAX_ANISOTROPY,
+            ..Default::default()
+        }
+    }
+
+    // Blocky close up, for pixel art
+    pub fn nearest() -> Self {
+        Self {
+            mag_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_address_mode(mut self, mode: wgpu::AddressMode) -> Self {
+        self.address_mode_u = mode;
+        self.address_mode_v = mode;
+        self
+    }
+
+    pub fn with_border(mut self, color: wgpu::SamplerBorderColor) -> Self {
+        self = self.with_address_mode(wgpu::AddressMode::ClampToBorder);
+        self.border_color = Some(color);
+        self
+    }
+
+    fn descriptor(&self, device: &wgpu::Device) -> wgpu::SamplerDescriptor<'static> {
+        let border = device.features().contains(wgpu::Features::ADDRESS_MODE_CLAMP_TO_BORDER);
+        let address_mode = |mode| match mode {
+            wgpu::AddressMode::ClampToBorder if !border => wgpu::AddressMode::ClampToEdge,
+            mode => mode,
+        };
+        wgpu::SamplerDescriptor {
+            address_mode_u: address_mode(self.address_mode_u),
+            address_mode_v: address_mode(self.address_mode_v),
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: self.mag_filter,
+            min_filter: self.min_filter,
+            mipmap_filter: self.mipmap_filter,
+            anisotropy_clamp: anisotropy_clamp(self.anisotropy),
+            border_color: self.border_color.filter(|_| border),
+            ..Default::default()
+        }
+    }
+}
+
+// One sampler per set of SamplerOptions, so textures sampled the same way
+// don't each make their own
+#[derive(Default)]
+pub struct SamplerCache {
+    samplers: HashMap<SamplerOptions, Arc<wgpu::Sampler>>,
+}
+
+impl SamplerCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&mut self, device: &wgpu::Device, options: SamplerOptions) -> Arc<wgpu::Sampler> {
+        self.samplers
+            .entry(options)
+            .or_insert_with(|| Arc::new(device.create_sampler(&options.descriptor(device))))
+            .clone()
+    }
+}
+
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
-    pub sampler: wgpu::Sampler,
+    // Shared with every other texture sampled the same way
+    pub sampler: Arc<wgpu::Sampler>,
 }
 
 impl Texture {
@@ -23,6 +175,20 @@ impl Texture {
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
         label: Option<&str>
+    ) -> Result<Self> {
+        let sampler = Arc::new(device.create_sampler(&SamplerOptions::default().descriptor(device)));
+        Self::from_image_with_sampler(device, queue, img, label, sampler, ColorSpace::Srgb)
+    }
+
+    // from_image() with a sampler from a SamplerCache, and linear data as
+    // well as colours
+    pub fn from_image_with_sampler(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+        sampler: Arc<wgpu::Sampler>,
+        color_space: ColorSpace,
     ) -> Result<Self> {
         let rgba = img.to_rgba8();
         let dimensions = img.dimensions();
@@ -39,7 +205,7 @@ impl Texture {
                 mip_level_count: 1,
                 sample_count: 1,
                 dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                format: color_space.format(),
                 usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
             }
         );
@@ -61,22 +227,66 @@ impl Texture {
         );
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(
-            &wgpu::SamplerDescriptor {
-                address_mode_u: wgpu::AddressMode::ClampToEdge,
-                address_mode_v: wgpu::AddressMode::ClampToEdge,
-                address_mode_w: wgpu::AddressMode::ClampToEdge,
-                mag_filter: wgpu::FilterMode::Linear,
-                min_filter: wgpu::FilterMode::Nearest,
-                mipmap_filter: wgpu::FilterMode::Nearest,
-                ..Default::default()
-            }
-        );
         
         Ok(Self { texture, view, sampler })
     }
 
+    // A mipmapped texture from a chain of images, each half the size of the
+    // one before. SamplerOptions::mipmapped() samples it trilinearly so
+    // distant surfaces don't shimmer.
+    pub fn from_mips(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        mips: &[image::RgbaImage],
+        label: &str,
+        sampler: Arc<wgpu::Sampler>,
+        color_space: ColorSpace,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: mips[0].width(),
+            height: mips[0].height(),
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: mips.len() as u32,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: color_space.format(),
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        });
+
+        for (level, mip) in mips.iter().enumerate() {
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level: level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                mip,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(4 * mip.width()),
+                    rows_per_image: std::num::NonZeroU32::new(mip.height()),
+                },
+                wgpu::Extent3d {
+                    width: mip.width(),
+                    height: mip.height(),
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self { texture, view, sampler }
+    }
+
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float; // 1.
+    // The scene is drawn in this before tonemapping, so it can go past 1
+    pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
     
     pub fn create_depth_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, label: &str) -> Self {
         let size = wgpu::Extent3d { // 2.
@@ -113,6 +323,44 @@ impl Texture {
             }
         );
 
-        Self { texture, view, sampler }
+        Self { texture, view, sampler: Arc::new(sampler) }
+    }
+
+    // Something to render into and sample from afterwards, e.g. an
+    // offscreen copy of the scene
+    pub fn create_render_target(
+        device: &wgpu::Device,
+        size: (u32, u32),
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: size.0,
+                height: size.1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self { texture, view, sampler: Arc::new(sampler) }
     }
 }
@@ -0,0 +1,190 @@
+use crate::app::Tonemapping;
+use crate::upload::Uploader;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct TonemapUniform {
+    exposure: f32,
+    curve: u32,
+    dither: u32,
+    // Whether the target encodes to sRGB when it's written, which is where
+    // the dithering has to happen
+    srgb: u32,
+}
+
+// Draws the HDR scene onto the render target with a fullscreen triangle,
+// exposed and tonemapped on the way (see shaders/tonemap.wgsl). The scene
+// texture goes in group 0, the frame uniform in group 1 and the exposure and
+// curve in group 2.
+//
+// Smooth gradients like the sky and fog come out in visible bands once
+// they're squeezed into 8 bits, so unless it's turned off a little noise
+// (under one step of the target's precision) is added on the way out, which
+// breaks them up.
+pub struct Tonemap {
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    source_layout: wgpu::BindGroupLayout,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Tonemap {
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        frame_layout: &wgpu::BindGroupLayout,
+        shader_source: &str,
+    ) -> Self {
+        let source_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("tonemap_source_bind_group_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    // Read with textureLoad, no sampler involved
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                },
+                count: None,
+            }],
+        });
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Tonemap Buffer"),
+            size: std::mem::size_of::<TonemapUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let uniform_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("tonemap_bind_group_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("tonemap_bind_group"),
+            layout: &uniform_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Tonemap Pipeline Layout"),
+            bind_group_layouts: &[&source_layout, frame_layout, &uniform_layout],
+            push_constant_ranges: &[],
+        });
+        Self {
+            pipeline: Self::create_pipeline(device, &layout, format, shader_source),
+            layout,
+            format,
+            source_layout,
+            buffer,
+            bind_group,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        format: wgpu::TextureFormat,
+        shader_source: &str,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Tonemap Shader"),
+            source: wgpu::ShaderSource::Wgsl(shader_source.into()),
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Tonemap Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+
+    // Called when the shader file changed, keeps the old pipeline if the new
+    // source doesn't compile
+    pub fn reload_shader(&mut self, device: &wgpu::Device, shader_source: &str) {
+        if let Some(pipeline) = crate::shaders::try_create(device, || {
+            Self::create_pipeline(device, &self.layout, self.format, shader_source)
+        }) {
+            self.pipeline = pipeline;
+        }
+    }
+
+    // With automatic exposure, `exposure` is only a placeholder that the eye
+    // adaptation pass overwrites later in the same encoder
+    pub fn upload(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        uploader: &mut Uploader,
+        exposure: f32,
+        tonemapping: Tonemapping,
+        dither: bool,
+    ) {
+        let uniform = TonemapUniform {
+            exposure,
+            curve: tonemapping as u32,
+            dither: dither as u32,
+            srgb: self.format.describe().srgb as u32,
+        };
+        uploader.write(device, encoder, &self.buffer, 0, &[uniform]);
+    }
+
+    // Where the exposure lives, the first f32 of it
+    pub fn exposure_buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    // The scene for group 0. It's a transient texture of the render graph,
+    // so this gets made again every frame.
+    pub fn bind_source(&self, device: &wgpu::Device, source: &wgpu::TextureView) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("tonemap_source_bind_group"),
+            layout: &self.source_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(source),
+            }],
+        })
+    }
+
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        source: &'a wgpu::BindGroup,
+        frame: &'a wgpu::BindGroup,
+    ) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, source, &[]);
+        render_pass.set_bind_group(1, frame, &[]);
+        render_pass.set_bind_group(2, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
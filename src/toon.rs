@@ -0,0 +1,246 @@
+use std::rc::Rc;
+
+use wgpu::util::DeviceExt;
+
+use crate::bind_group_cache::{BindGroupCache, CachedBinding, ResourceId};
+use crate::scene::ToonMaterial;
+use crate::texture::Texture;
+use crate::vertex::VertexLayout;
+use crate::{InstanceRaw, Vertex};
+
+// Where the toon pipelines go in the scene passes' DrawResources, after the
+// mesh, parallax and emissive pipelines. The outline comes after the
+// surfaces so it only fills in around them.
+pub const PIPELINE: u32 = 3;
+pub const OUTLINE_PIPELINE: u32 = 4;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ToonUniform {
+    rim_color: [f32; 3],
+    rim_width: f32,
+    outline_color: [f32; 3],
+    outline_width: f32,
+    bands: f32,
+    softness: f32,
+    _padding: [f32; 2],
+}
+
+impl From<&ToonMaterial> for ToonUniform {
+    fn from(material: &ToonMaterial) -> Self {
+        let outline = material.outline.unwrap_or_default();
+        Self {
+            rim_color: material.rim_color.map(|c| c.max(0.0)),
+            rim_width: material.rim_width.clamp(0.0, 1.0),
+            outline_color: outline.color,
+            outline_width: outline.width.max(0.0),
+            bands: material.bands.max(1) as f32,
+            softness: material.softness.clamp(0.0, 1.0),
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+// Cartoon style shading. Instead of getting gradually darker as it turns
+// away from the sun, the light on a surface is rounded into a few flat
+// bands, with a rim of light around the silhouette. Like parallax.rs the
+// normals come from screen space derivatives, so every triangle is lit flat.
+//
+// Outlines are the inverted hull trick: the mesh is drawn a second time,
+// pushed out a little and with only its back faces showing, so all that's
+// left of it is a border around the object. Meshes don't carry normals, so
+// it grows out from the object's origin, which works for closed meshes built
+// around it (cubes, spheres, most models) but not flat ones like the quad.
+//
+// Toon materials are drawn with their own pipelines (PIPELINE and
+// OUTLINE_PIPELINE in the draw commands) and material bind group, and need
+// Parallax::light() bound to group 3 next to the camera and frame.
+pub struct Toon {
+    pipeline: wgpu::RenderPipeline,
+    outline_pipeline: wgpu::RenderPipeline,
+    layout: wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    material_layout: wgpu::BindGroupLayout,
+    material_layout_id: ResourceId,
+}
+
+impl Toon {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        camera_layout: &wgpu::BindGroupLayout,
+        frame_layout: &wgpu::BindGroupLayout,
+        // Parallax::light_layout()
+        light_layout: &wgpu::BindGroupLayout,
+        bind_group_cache: &mut BindGroupCache,
+        shader_source: &str,
+        // Extra attribute streams from the mesh pool, like the mesh pipeline
+        streams: &[wgpu::VertexBufferLayout],
+    ) -> Self {
+        let material_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("toon_material_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                // The outline's width is needed in the vertex shader
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Toon Pipeline Layout"),
+            bind_group_layouts: &[&material_layout, camera_layout, frame_layout, light_layout],
+            push_constant_ranges: &[],
+        });
+        let (pipeline, outline_pipeline) =
+            Self::create_pipelines(device, &layout, color_format, shader_source, streams);
+
+        Self {
+            pipeline,
+            outline_pipeline,
+            layout,
+            color_format,
+            material_layout,
+            material_layout_id: bind_group_cache.register(),
+        }
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        shader_source: &str,
+        streams: &[wgpu::VertexBufferLayout],
+    ) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+        let pipeline = crate::create_render_pipeline(device, layout, color_format, shader_source, streams);
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Toon Outline Shader"),
+            source: wgpu::ShaderSource::Wgsl(shader_source.into()),
+        });
+        let mut buffers = vec![Vertex::desc(), InstanceRaw::desc()];
+        buffers.extend_from_slice(streams);
+        let outline_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Toon Outline Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_outline",
+                buffers: &buffers,
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_outline",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            // Only the far side of the grown mesh, which the object itself
+            // covers everywhere but around its edges
+            primitive: wgpu::PrimitiveState {
+                cull_mode: Some(wgpu::Face::Front),
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        (pipeline, outline_pipeline)
+    }
+
+    // Called when the shader file changed or the mesh pool got new streams,
+    // keeps the old pipelines if the new source doesn't compile
+    pub fn reload_shader(&mut self, device: &wgpu::Device, shader_source: &str, streams: &[wgpu::VertexBufferLayout]) {
+        if let Some((pipeline, outline_pipeline)) = crate::shaders::try_create(device, || {
+            Self::create_pipelines(device, &self.layout, self.color_format, shader_source, streams)
+        }) {
+            self.pipeline = pipeline;
+            self.outline_pipeline = outline_pipeline;
+        }
+    }
+
+    // The settings of one material, for material_bind_group(). They can't
+    // change, a material with other settings is a different material.
+    pub fn create_material_buffer(device: &wgpu::Device, material: &ToonMaterial) -> wgpu::Buffer {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Toon Material Buffer"),
+            contents: bytemuck::bytes_of(&ToonUniform::from(material)),
+            usage: wgpu::BufferUsages::UNIFORM,
+        })
+    }
+
+    // Group 0 for a toon material, its surface and outline both. `id` stands
+    // in for all of it, so it has to be recreated when the texture is.
+    pub fn material_bind_group(
+        &self,
+        device: &wgpu::Device,
+        bind_group_cache: &mut BindGroupCache,
+        id: ResourceId,
+        texture: &Texture,
+        buffer: &wgpu::Buffer,
+    ) -> Rc<wgpu::BindGroup> {
+        bind_group_cache.get_or_create(
+            device,
+            Some("toon_material_bind_group"),
+            self.material_layout_id,
+            &self.material_layout,
+            &[
+                CachedBinding {
+                    binding: 0,
+                    id,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                CachedBinding {
+                    binding: 1,
+                    id,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+                CachedBinding {
+                    binding: 2,
+                    id,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+        )
+    }
+
+    pub fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+
+    pub fn outline_pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.outline_pipeline
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+
+use glam::Vec2;
+use winit::event::{TouchPhase, WindowEvent};
+
+use crate::screen::Screen;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TouchPoint {
+    pub phase: TouchPhase,
+    // Logical pixels
+    pub start: Vec2,
+    pub position: Vec2,
+}
+
+// What the fingers did since the gesture was last taken. One finger drags
+// pan, two fingers pinch and twist.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Gesture {
+    // Logical pixels moved by a single finger, or by the middle of two
+    pub pan: Vec2,
+    // Above 1 when the fingers moved apart
+    pub pinch: f32,
+    // Radians, counter clockwise on screen
+    pub rotate: f32,
+}
+
+impl Default for Gesture {
+    fn default() -> Self {
+        Self {
+            pan: Vec2::ZERO,
+            pinch: 1.0,
+            rotate: 0.0,
+        }
+    }
+}
+
+impl Gesture {
+    pub fn is_none(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+// Every finger currently on the screen, by the id the platform gave it
+#[derive(Default)]
+pub struct Touches {
+    points: HashMap<u64, TouchPoint>,
+    gesture: Gesture,
+}
+
+impl Touches {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, id: u64) -> Option<&TouchPoint> {
+        self.points.get(&id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u64, &TouchPoint)> {
+        self.points.iter().map(|(id, point)| (*id, point))
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    pub fn handle_event(&mut self, event: &WindowEvent, screen: &Screen) -> bool {
+        let touch = match event {
+            WindowEvent::Touch(touch) => touch,
+            _ => return false,
+        };
+        let position = screen.to_logical(touch.location);
+
+        // Compare the two finger layout before and after this one moved
+        let before = self.pair();
+        match touch.phase {
+            TouchPhase::Started => {
+                self.points.insert(touch.id, TouchPoint { phase: touch.phase, start: position, position });
+            }
+            TouchPhase::Moved => {
+                let Some(point) = self.points.get_mut(&touch.id) else {
+                    return true;
+                };
+                let delta = position - point.position;
+                point.phase = touch.phase;
+                point.position = position;
+                if self.points.len() == 1 {
+                    self.gesture.pan += delta;
+                }
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                self.points.remove(&touch.id);
+                return true;
+            }
+        }
+
+        if let (Some((a0, b0)), Some((a1, b1))) = (before, self.pair()) {
+            let (from, to) = (b0 - a0, b1 - a1);
+            if from.length() > f32::EPSILON && to.length() > f32::EPSILON {
+                self.gesture.pinch *= to.length() / from.length();
+                // Screen y points down, so flip to get counter clockwise
+                self.gesture.rotate -= from.angle_between(to);
+                self.gesture.pan += (a1 + b1 - a0 - b0) / 2.0;
+            }
+        }
+        true
+    }
+
+    // The first two fingers, in a stable order
+    fn pair(&self) -> Option<(Vec2, Vec2)> {
+        if self.points.len() != 2 {
+            return None;
+        }
+        let mut ids = self.points.keys().copied().collect::<Vec<_>>();
+        ids.sort_unstable();
+        Some((self.points[&ids[0]].position, self.points[&ids[1]].position))
+    }
+
+    // Hands out everything gathered since the last call
+    pub fn take_gesture(&mut self) -> Gesture {
+        std::mem::take(&mut self.gesture)
+    }
+}
@@ -0,0 +1,477 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+
+use crate::assets::{AssetId, Assets, Handle};
+use crate::bind_group_cache::{BindGroupCache, CachedBinding, ResourceId};
+use crate::particles::{Curve, ParticleBlend};
+use crate::texture::{ColorSpace, SamplerOptions, Texture};
+use crate::upload::Uploader;
+use crate::vertex::VertexLayout;
+
+// Leaves a ribbon behind an entity as it moves, like a sword swing or the
+// streak behind a rocket. The ribbon turns to face the camera along its
+// length, and fades out over `lifetime` seconds:
+//
+//     trail: Some((
+//         lifetime: 0.5,
+//         width: [(0.0, 0.3), (1.0, 0.0)],
+//         alpha: [(0.0, 1.0), (1.0, 0.0)],
+//         color: (2.0, 1.2, 0.4, 1.0),
+//         blend: Additive,
+//     )),
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Trail {
+    // Seconds a point stays on the ribbon
+    pub lifetime: f32,
+    // How far the entity moves before another point is added. Smaller is
+    // smoother around corners but makes more triangles.
+    pub min_distance: f32,
+    // Across the ribbon in world units, from the newest point (0) to the
+    // oldest (1)
+    pub width: Curve,
+    // Multiplies the colour's alpha, keyed the same way
+    pub alpha: Curve,
+    // Values past 1 glow once bloom is on
+    pub color: [f32; 4],
+    pub blend: ParticleBlend,
+    // Loaded through the asset manager, with U along the ribbon and V across
+    // it. A strip fading out towards both edges when left out.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub texture: Option<PathBuf>,
+    // World units one repeat of the texture covers, 0 stretches it once over
+    // the whole ribbon
+    pub texture_length: f32,
+    // Repeats a second the texture moves back along the ribbon
+    pub scroll: f32,
+}
+
+impl Default for Trail {
+    fn default() -> Self {
+        Self {
+            lifetime: 0.5,
+            min_distance: 0.1,
+            width: Curve::linear(0.2, 0.0),
+            alpha: Curve::linear(1.0, 0.0),
+            color: [1.0; 4],
+            blend: ParticleBlend::Alpha,
+            texture: None,
+            texture_length: 0.0,
+            scroll: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct TrailPoint {
+    position: Vec3,
+    age: f32,
+}
+
+// Where a trail's entity has been recently, newest first, recorded every
+// frame with record()
+#[derive(Clone, Debug, Default)]
+pub struct TrailPoints {
+    points: VecDeque<TrailPoint>,
+}
+
+impl TrailPoints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Ages every point by `dt` seconds, drops the ones older than the
+    // trail's lifetime and moves the newest to `position`
+    pub fn record(&mut self, trail: &Trail, position: Vec3, dt: f32) {
+        for point in &mut self.points {
+            point.age += dt;
+        }
+        while self.points.back().is_some_and(|point| point.age > trail.lifetime) {
+            self.points.pop_back();
+        }
+        // The head follows the entity until it's far enough from the last
+        // point left behind, then stays behind too and a new one takes over.
+        // A lone point stays where the entity started moving from.
+        let moved = |from: &TrailPoint| position.distance(from.position) >= trail.min_distance;
+        match (self.points.get(1), self.points.len()) {
+            (Some(last), _) if !moved(last) => {
+                self.points[0] = TrailPoint { position, age: 0.0 };
+            }
+            (None, 1) if !moved(&self.points[0]) => self.points[0].age = 0.0,
+            _ => self.points.push_front(TrailPoint { position, age: 0.0 }),
+        }
+    }
+
+    // Starts over, e.g. after teleporting the entity
+    pub fn clear(&mut self) {
+        self.points.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+}
+
+// One side of the ribbon at one point. Which way is across depends on the
+// camera, so the shader pushes it out sideways.
+crate::vertex_layout! {
+    #[derive(Debug)]
+    struct TrailVertex {
+        #[location(0)] position: [f32; 3],
+        // Along the ribbon, towards the older points
+        #[location(1)] direction: [f32; 3],
+        // Half the width, negative on one side
+        #[location(2)] offset: f32,
+        #[location(3)] tex_coords: [f32; 2],
+        #[location(4)] color: [f32; 4],
+        // Repeats a second U moves by
+        #[location(5)] scroll: f32,
+    }
+}
+
+// Ribbons drawn the same way, with the same texture
+struct RibbonBatch {
+    blend: ParticleBlend,
+    texture: Option<Handle<Texture>>,
+    vertices: Vec<TrailVertex>,
+    indices: Vec<u32>,
+}
+
+// Draws Trail ribbons, rebuilt from their points every frame. Like the
+// particles they test against the depth buffer without writing to it.
+pub struct TrailRenderer {
+    // Alpha and additive
+    pipelines: [wgpu::RenderPipeline; 2],
+    layout: wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    bind_group_layout: wgpu::BindGroupLayout,
+    layout_id: ResourceId,
+    // For trails without a texture of their own
+    strip: Texture,
+    textures: HashMap<PathBuf, Handle<Texture>>,
+    // Every texture drawn with so far, None for the strip
+    texture_ids: HashMap<Option<AssetId>, ResourceId>,
+    vertex_buffer: wgpu::Buffer,
+    vertex_capacity: usize,
+    index_buffer: wgpu::Buffer,
+    index_capacity: usize,
+    // Blend, texture and the indices drawn with them, from the last prepare()
+    draws: Vec<(ParticleBlend, Rc<wgpu::BindGroup>, std::ops::Range<u32>)>,
+}
+
+impl TrailRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color_format: wgpu::TextureFormat,
+        camera_layout: &wgpu::BindGroupLayout,
+        frame_layout: &wgpu::BindGroupLayout,
+        shader_source: &str,
+        cache: &mut BindGroupCache,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("trail_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Trail Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, frame_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        // White, fading out towards the top and bottom edges
+        let strip = image::RgbaImage::from_fn(1, 32, |_, y| {
+            let distance = ((y as f32 - 15.5) / 16.0).abs();
+            image::Rgba([255, 255, 255, ((1.0 - distance * distance) * 255.0) as u8])
+        });
+        let strip = Texture::from_image(device, queue, &image::DynamicImage::ImageRgba8(strip), Some("trail_strip"))
+            .expect("Failed to create the trail texture");
+
+        Self {
+            pipelines: Self::create_pipelines(device, &layout, color_format, shader_source),
+            layout,
+            color_format,
+            bind_group_layout,
+            layout_id: cache.register(),
+            strip,
+            textures: HashMap::new(),
+            texture_ids: HashMap::new(),
+            vertex_buffer: Self::create_buffer(device, "Trail Vertex Buffer", wgpu::BufferUsages::VERTEX, 1),
+            vertex_capacity: 1,
+            index_buffer: Self::create_buffer(device, "Trail Index Buffer", wgpu::BufferUsages::INDEX, 1),
+            index_capacity: 1,
+            draws: Vec::new(),
+        }
+    }
+
+    fn create_buffer(device: &wgpu::Device, label: &str, usage: wgpu::BufferUsages, size: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: size as wgpu::BufferAddress,
+            usage: usage | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        shader_source: &str,
+    ) -> [wgpu::RenderPipeline; 2] {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Trail Shader"),
+            source: wgpu::ShaderSource::Wgsl(shader_source.into()),
+        });
+        let additive = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::SrcAlpha,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+        [
+            wgpu::BlendState::ALPHA_BLENDING,
+            wgpu::BlendState {
+                color: additive,
+                alpha: additive,
+            },
+        ]
+        .map(|blend| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Trail Pipeline"),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[TrailVertex::desc()],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: color_format,
+                        blend: Some(blend),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    // Ribbons are seen from both sides as they twist
+                    cull_mode: None,
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: Texture::DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        })
+    }
+
+    // Keeps the old pipelines if the new source doesn't compile
+    pub fn reload_shader(&mut self, device: &wgpu::Device, shader_source: &str) {
+        if let Some(pipelines) = crate::shaders::try_create(device, || {
+            Self::create_pipelines(device, &self.layout, self.color_format, shader_source)
+        }) {
+            self.pipelines = pipelines;
+        }
+    }
+
+    // Call when a texture gets reloaded, so its bind group is rebuilt
+    pub fn texture_changed(&mut self, id: AssetId, cache: &mut BindGroupCache) {
+        if let Some(resource) = self.texture_ids.get_mut(&Some(id)) {
+            *resource = cache.recreated(*resource);
+        }
+    }
+
+    // Builds every trail's ribbon and uploads them. Alpha blended trails are
+    // drawn before additive ones.
+    pub fn prepare<'a>(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        uploader: &mut Uploader,
+        cache: &mut BindGroupCache,
+        assets: &mut Assets,
+        trails: impl IntoIterator<Item = (&'a Trail, &'a TrailPoints)>,
+    ) {
+        // Grouped by how they're drawn
+        let mut batches: Vec<RibbonBatch> = Vec::new();
+        for (trail, points) in trails {
+            if points.len() < 2 {
+                continue;
+            }
+            let texture = trail.texture.as_ref().map(|path| {
+                *self.textures.entry(path.clone()).or_insert_with(|| {
+                    // Repeating along the ribbon for texture_length and scroll
+                    let sampler = SamplerOptions {
+                        address_mode_u: wgpu::AddressMode::Repeat,
+                        min_filter: wgpu::FilterMode::Linear,
+                        ..Default::default()
+                    };
+                    assets.load_texture_with(path, sampler, ColorSpace::Srgb)
+                })
+            });
+            let index = match batches.iter().position(|b| b.blend == trail.blend && b.texture == texture) {
+                Some(index) => index,
+                None => {
+                    batches.push(RibbonBatch {
+                        blend: trail.blend,
+                        texture,
+                        vertices: Vec::new(),
+                        indices: Vec::new(),
+                    });
+                    batches.len() - 1
+                }
+            };
+            let batch = &mut batches[index];
+            ribbon(trail, &points.points, &mut batch.vertices, &mut batch.indices);
+        }
+        batches.sort_by_key(|batch| batch.blend == ParticleBlend::Additive);
+
+        let vertex_count = batches.iter().map(|batch| batch.vertices.len()).sum::<usize>();
+        let index_count = batches.iter().map(|batch| batch.indices.len()).sum::<usize>();
+        if vertex_count > self.vertex_capacity {
+            self.vertex_capacity = vertex_count.next_power_of_two();
+            let size = self.vertex_capacity * std::mem::size_of::<TrailVertex>();
+            self.vertex_buffer = Self::create_buffer(device, "Trail Vertex Buffer", wgpu::BufferUsages::VERTEX, size);
+        }
+        if index_count > self.index_capacity {
+            self.index_capacity = index_count.next_power_of_two();
+            let size = self.index_capacity * std::mem::size_of::<u32>();
+            self.index_buffer = Self::create_buffer(device, "Trail Index Buffer", wgpu::BufferUsages::INDEX, size);
+        }
+
+        self.draws.clear();
+        let (mut first_vertex, mut first_index) = (0, 0);
+        for RibbonBatch {
+            blend,
+            texture,
+            vertices,
+            mut indices,
+        } in batches
+        {
+            // Every batch's indices start from 0, they're moved past the
+            // batches before it in the shared buffer
+            for index in &mut indices {
+                *index += first_vertex as u32;
+            }
+            let offset = (first_vertex * std::mem::size_of::<TrailVertex>()) as wgpu::BufferAddress;
+            uploader.write(device, encoder, &self.vertex_buffer, offset, &vertices);
+            let offset = (first_index * std::mem::size_of::<u32>()) as wgpu::BufferAddress;
+            uploader.write(device, encoder, &self.index_buffer, offset, &indices);
+
+            let key = texture.map(|handle| handle.id());
+            let id = *self.texture_ids.entry(key).or_insert_with(|| cache.register());
+            let texture = texture.map_or(&self.strip, |handle| assets.texture(handle));
+            let bind_group = cache.get_or_create(
+                device,
+                Some("trail_bind_group"),
+                self.layout_id,
+                &self.bind_group_layout,
+                &[
+                    CachedBinding {
+                        binding: 0,
+                        id,
+                        resource: wgpu::BindingResource::TextureView(&texture.view),
+                    },
+                    CachedBinding {
+                        binding: 1,
+                        id,
+                        resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                    },
+                ],
+            );
+            let last = first_index + indices.len();
+            self.draws.push((blend, bind_group, first_index as u32..last as u32));
+            first_vertex += vertices.len();
+            first_index = last;
+        }
+    }
+
+    pub fn has_draws(&self) -> bool {
+        !self.draws.is_empty()
+    }
+
+    // Expects the camera at group 0 and the frame at group 1
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera: &'a wgpu::BindGroup,
+        frame: &'a wgpu::BindGroup,
+    ) {
+        render_pass.set_bind_group(0, camera, &[]);
+        render_pass.set_bind_group(1, frame, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        for (blend, bind_group, indices) in &self.draws {
+            render_pass.set_pipeline(&self.pipelines[*blend as usize]);
+            render_pass.set_bind_group(2, bind_group, &[]);
+            render_pass.draw_indexed(indices.clone(), 0, 0..1);
+        }
+    }
+}
+
+// Two vertices at every point and two triangles between each pair of them
+fn ribbon(trail: &Trail, points: &VecDeque<TrailPoint>, vertices: &mut Vec<TrailVertex>, indices: &mut Vec<u32>) {
+    let length = points.iter().zip(points.iter().skip(1)).map(|(a, b)| a.position.distance(b.position)).sum::<f32>();
+    let repeat = if trail.texture_length > 0.0 { trail.texture_length } else { length.max(f32::EPSILON) };
+    let first = vertices.len() as u32;
+    let mut along = 0.0;
+    for (index, point) in points.iter().enumerate() {
+        if index > 0 {
+            along += points[index - 1].position.distance(point.position);
+        }
+        // Halfway between the segments either side, so corners don't pinch
+        let before = points.get(index.wrapping_sub(1)).map_or(point.position, |p| p.position);
+        let after = points.get(index + 1).map_or(point.position, |p| p.position);
+        let direction = (after - before).normalize_or_zero();
+        let age = (point.age / trail.lifetime.max(f32::EPSILON)).min(1.0);
+        let half_width = trail.width.sample(age) * 0.5;
+        let mut color = trail.color;
+        color[3] *= trail.alpha.sample(age);
+        for (offset, v) in [(-half_width, 1.0), (half_width, 0.0)] {
+            vertices.push(TrailVertex {
+                position: point.position.into(),
+                direction: direction.into(),
+                offset,
+                tex_coords: [along / repeat, v],
+                color,
+                scroll: trail.scroll,
+            });
+        }
+    }
+    for segment in 0..points.len() as u32 - 1 {
+        let i = first + segment * 2;
+        indices.extend_from_slice(&[i, i + 1, i + 2, i + 2, i + 1, i + 3]);
+    }
+}
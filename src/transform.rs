@@ -0,0 +1,170 @@
+use glam::{Mat4, Quat, Vec3};
+
+use crate::scene::SceneTransform;
+
+// Where something is, which way it faces and how big it is. Instances,
+// cameras and scene entities all use this, and child transforms are stored
+// relative to their parent so moving the parent carries them along.
+#[cfg_attr(feature = "ecs", derive(bevy_ecs::component::Component))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Transform {
+    pub const IDENTITY: Self = Self {
+        translation: Vec3::ZERO,
+        rotation: Quat::IDENTITY,
+        scale: Vec3::ONE,
+    };
+
+    pub fn from_translation(translation: Vec3) -> Self {
+        Self {
+            translation,
+            ..Self::IDENTITY
+        }
+    }
+
+    pub fn from_rotation(rotation: Quat) -> Self {
+        Self {
+            rotation,
+            ..Self::IDENTITY
+        }
+    }
+
+    pub fn from_scale(scale: Vec3) -> Self {
+        Self {
+            scale,
+            ..Self::IDENTITY
+        }
+    }
+
+    // Breaks a matrix back down, shearing gets lost
+    pub fn from_matrix(matrix: Mat4) -> Self {
+        let (scale, rotation, translation) = matrix.to_scale_rotation_translation();
+        Self {
+            translation,
+            rotation,
+            scale,
+        }
+    }
+
+    pub fn with_translation(mut self, translation: Vec3) -> Self {
+        self.translation = translation;
+        self
+    }
+
+    pub fn with_rotation(mut self, rotation: Quat) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn with_scale(mut self, scale: Vec3) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    // Scale first, then rotate, then translate
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.translation)
+    }
+
+    // For cameras, takes world space into the transform's local space
+    pub fn view_matrix(&self) -> Mat4 {
+        self.matrix().inverse()
+    }
+
+    // -Z is forward, like the camera looks down -Z
+    pub fn forward(&self) -> Vec3 {
+        self.rotation * Vec3::NEG_Z
+    }
+
+    pub fn right(&self) -> Vec3 {
+        self.rotation * Vec3::X
+    }
+
+    pub fn up(&self) -> Vec3 {
+        self.rotation * Vec3::Y
+    }
+
+    // A transform at `eye` turned so forward() points at `target`
+    pub fn looking_at(eye: Vec3, target: Vec3, up: Vec3) -> Self {
+        Self::from_translation(eye).look_at(target, up)
+    }
+
+    pub fn look_at(self, target: Vec3, up: Vec3) -> Self {
+        self.look_to(target - self.translation, up)
+    }
+
+    pub fn look_to(mut self, direction: Vec3, up: Vec3) -> Self {
+        let forward = direction.normalize_or_zero();
+        if forward == Vec3::ZERO {
+            return self;
+        }
+        // Looking straight along `up` would leave right undefined
+        let right = forward.cross(up).try_normalize().unwrap_or_else(|| forward.any_orthonormal_vector());
+        let up = right.cross(forward);
+        self.rotation = Quat::from_mat3(&glam::Mat3::from_cols(right, up, -forward));
+        self
+    }
+
+    // `self` is the parent, `child` is relative to it. The result is the
+    // child in the parent's space.
+    pub fn mul_transform(&self, child: &Transform) -> Self {
+        Self {
+            translation: self.transform_point(child.translation),
+            rotation: self.rotation * child.rotation,
+            scale: self.scale * child.scale,
+        }
+    }
+
+    pub fn transform_point(&self, point: Vec3) -> Vec3 {
+        self.rotation * (self.scale * point) + self.translation
+    }
+
+    pub fn inverse(&self) -> Self {
+        let rotation = self.rotation.inverse();
+        let scale = self.scale.recip();
+        Self {
+            translation: rotation * (-self.translation) * scale,
+            rotation,
+            scale,
+        }
+    }
+}
+
+impl std::ops::Mul for Transform {
+    type Output = Transform;
+
+    fn mul(self, child: Transform) -> Transform {
+        self.mul_transform(&child)
+    }
+}
+
+impl From<&SceneTransform> for Transform {
+    fn from(transform: &SceneTransform) -> Self {
+        Self {
+            translation: transform.translation.into(),
+            rotation: Quat::from_array(transform.rotation).normalize(),
+            scale: transform.scale.into(),
+        }
+    }
+}
+
+impl From<&Transform> for SceneTransform {
+    fn from(transform: &Transform) -> Self {
+        Self {
+            translation: transform.translation.into(),
+            rotation: transform.rotation.into(),
+            scale: transform.scale.into(),
+        }
+    }
+}
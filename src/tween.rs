@@ -0,0 +1,361 @@
+use std::any::Any;
+use std::marker::PhantomData;
+
+use glam::{Quat, Vec2, Vec3, Vec4};
+
+use crate::transform::Transform;
+
+// Easing curves, all of them take t in 0..1 and return 0 at 0 and 1 at 1.
+// See https://easings.net for what they look like.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Easing {
+    #[default]
+    Linear,
+    QuadIn,
+    QuadOut,
+    QuadInOut,
+    CubicIn,
+    CubicOut,
+    CubicInOut,
+    SineIn,
+    SineOut,
+    SineInOut,
+    ExpoIn,
+    ExpoOut,
+    // Overshoots a little before settling
+    BackOut,
+    ElasticOut,
+    BounceOut,
+}
+
+impl Easing {
+    pub fn apply(self, t: f32) -> f32 {
+        use std::f32::consts::PI;
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::QuadIn => t * t,
+            Easing::QuadOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Easing::QuadInOut => {
+                if t < 0.5 {
+                    2.0 * t * t
+                } else {
+                    1.0 - (-2.0 * t + 2.0).powi(2) / 2.0
+                }
+            }
+            Easing::CubicIn => t * t * t,
+            Easing::CubicOut => 1.0 - (1.0 - t).powi(3),
+            Easing::CubicInOut => {
+                if t < 0.5 {
+                    4.0 * t * t * t
+                } else {
+                    1.0 - (-2.0 * t + 2.0).powi(3) / 2.0
+                }
+            }
+            Easing::SineIn => 1.0 - (t * PI / 2.0).cos(),
+            Easing::SineOut => (t * PI / 2.0).sin(),
+            Easing::SineInOut => -((PI * t).cos() - 1.0) / 2.0,
+            Easing::ExpoIn => {
+                if t == 0.0 {
+                    0.0
+                } else {
+                    2f32.powf(10.0 * t - 10.0)
+                }
+            }
+            Easing::ExpoOut => {
+                if t == 1.0 {
+                    1.0
+                } else {
+                    1.0 - 2f32.powf(-10.0 * t)
+                }
+            }
+            Easing::BackOut => {
+                let c1 = 1.70158;
+                let c3 = c1 + 1.0;
+                1.0 + c3 * (t - 1.0).powi(3) + c1 * (t - 1.0).powi(2)
+            }
+            Easing::ElasticOut => {
+                if t == 0.0 || t == 1.0 {
+                    t
+                } else {
+                    2f32.powf(-10.0 * t) * ((t * 10.0 - 0.75) * (2.0 * PI / 3.0)).sin() + 1.0
+                }
+            }
+            Easing::BounceOut => {
+                let n1 = 7.5625;
+                let d1 = 2.75;
+                if t < 1.0 / d1 {
+                    n1 * t * t
+                } else if t < 2.0 / d1 {
+                    let t = t - 1.5 / d1;
+                    n1 * t * t + 0.75
+                } else if t < 2.5 / d1 {
+                    let t = t - 2.25 / d1;
+                    n1 * t * t + 0.9375
+                } else {
+                    let t = t - 2.625 / d1;
+                    n1 * t * t + 0.984375
+                }
+            }
+        }
+    }
+}
+
+// Anything that can be blended between two values
+pub trait Lerp: Copy + 'static {
+    fn lerp(self, to: Self, t: f32) -> Self;
+}
+
+impl Lerp for f32 {
+    fn lerp(self, to: Self, t: f32) -> Self {
+        self + (to - self) * t
+    }
+}
+
+impl Lerp for Vec2 {
+    fn lerp(self, to: Self, t: f32) -> Self {
+        Vec2::lerp(self, to, t)
+    }
+}
+
+impl Lerp for Vec3 {
+    fn lerp(self, to: Self, t: f32) -> Self {
+        Vec3::lerp(self, to, t)
+    }
+}
+
+// Colors as linear RGBA
+impl Lerp for Vec4 {
+    fn lerp(self, to: Self, t: f32) -> Self {
+        Vec4::lerp(self, to, t)
+    }
+}
+
+impl Lerp for [f32; 4] {
+    fn lerp(self, to: Self, t: f32) -> Self {
+        Vec4::from(self).lerp(Vec4::from(to), t).into()
+    }
+}
+
+impl Lerp for wgpu::Color {
+    fn lerp(self, to: Self, t: f32) -> Self {
+        let t = t as f64;
+        wgpu::Color {
+            r: self.r + (to.r - self.r) * t,
+            g: self.g + (to.g - self.g) * t,
+            b: self.b + (to.b - self.b) * t,
+            a: self.a + (to.a - self.a) * t,
+        }
+    }
+}
+
+impl Lerp for Quat {
+    fn lerp(self, to: Self, t: f32) -> Self {
+        self.slerp(to, t)
+    }
+}
+
+impl Lerp for Transform {
+    fn lerp(self, to: Self, t: f32) -> Self {
+        Transform {
+            translation: self.translation.lerp(to.translation, t),
+            rotation: self.rotation.slerp(to.rotation, t),
+            scale: self.scale.lerp(to.scale, t),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Repeat {
+    Once,
+    Loop,
+    // Plays forwards then backwards, forever
+    PingPong,
+}
+
+// A value moving from one place to another over `duration` seconds
+pub struct Tween<T: Lerp> {
+    from: T,
+    to: T,
+    duration: f32,
+    delay: f32,
+    easing: Easing,
+    repeat: Repeat,
+    on_complete: Option<Box<dyn FnOnce(T)>>,
+}
+
+impl<T: Lerp> Tween<T> {
+    pub fn new(from: T, to: T, duration: f32) -> Self {
+        Self {
+            from,
+            to,
+            duration,
+            delay: 0.0,
+            easing: Easing::Linear,
+            repeat: Repeat::Once,
+            on_complete: None,
+        }
+    }
+
+    pub fn easing(mut self, easing: Easing) -> Self {
+        self.easing = easing;
+        self
+    }
+
+    // Seconds to wait before starting
+    pub fn delay(mut self, delay: f32) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    pub fn repeat(mut self, repeat: Repeat) -> Self {
+        self.repeat = repeat;
+        self
+    }
+
+    // Called with the final value once a Repeat::Once tween finishes
+    pub fn on_complete(mut self, callback: impl FnOnce(T) + 'static) -> Self {
+        self.on_complete = Some(Box::new(callback));
+        self
+    }
+}
+
+// Refers to a tween started on Tweens, and remembers what type it animates
+pub struct TweenHandle<T> {
+    id: u64,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for TweenHandle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for TweenHandle<T> {}
+
+impl<T> PartialEq for TweenHandle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<T> Eq for TweenHandle<T> {}
+
+impl<T> std::fmt::Debug for TweenHandle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("TweenHandle").field(&self.id).finish()
+    }
+}
+
+// Type erased so one manager can run tweens of every type
+trait AnyTween {
+    // Returns true once the tween has finished
+    fn advance(&mut self, dt: f32) -> bool;
+    fn complete(&mut self);
+    fn as_any(&self) -> &dyn Any;
+}
+
+struct Running<T: Lerp> {
+    tween: Tween<T>,
+    elapsed: f32,
+    value: T,
+}
+
+impl<T: Lerp> AnyTween for Running<T> {
+    fn advance(&mut self, dt: f32) -> bool {
+        self.elapsed += dt;
+        let tween = &self.tween;
+        let time = (self.elapsed - tween.delay).max(0.0);
+        let duration = tween.duration.max(f32::EPSILON);
+        let (t, finished) = match tween.repeat {
+            Repeat::Once => ((time / duration).min(1.0), time >= duration),
+            Repeat::Loop => ((time / duration).fract(), false),
+            Repeat::PingPong => {
+                let t = (time / duration) % 2.0;
+                (if t > 1.0 { 2.0 - t } else { t }, false)
+            }
+        };
+        self.value = tween.from.lerp(tween.to, tween.easing.apply(t));
+        finished
+    }
+
+    fn complete(&mut self) {
+        if let Some(callback) = self.tween.on_complete.take() {
+            callback(self.value);
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+// Keeps every running tween and advances them all from State::update().
+// Read the current value back with `value()` each frame.
+#[derive(Default)]
+pub struct Tweens {
+    running: Vec<(u64, Box<dyn AnyTween>)>,
+    next_id: u64,
+}
+
+impl Tweens {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn start<T: Lerp>(&mut self, tween: Tween<T>) -> TweenHandle<T> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let value = tween.from;
+        self.running.push((id, Box::new(Running { tween, elapsed: 0.0, value })));
+        TweenHandle {
+            id,
+            _marker: PhantomData,
+        }
+    }
+
+    // None once the tween has finished (or been stopped)
+    pub fn value<T: Lerp>(&self, handle: TweenHandle<T>) -> Option<T> {
+        self.running
+            .iter()
+            .find(|(id, _)| *id == handle.id)
+            .and_then(|(_, tween)| tween.as_any().downcast_ref::<Running<T>>())
+            .map(|running| running.value)
+    }
+
+    pub fn is_running<T>(&self, handle: TweenHandle<T>) -> bool {
+        self.running.iter().any(|(id, _)| *id == handle.id)
+    }
+
+    // Drops the tween without calling its completion callback
+    pub fn stop<T>(&mut self, handle: TweenHandle<T>) {
+        self.running.retain(|(id, _)| *id != handle.id);
+    }
+
+    pub fn len(&self) -> usize {
+        self.running.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.running.is_empty()
+    }
+
+    // Moves every tween on by `dt` seconds, running completion callbacks for
+    // the ones that finished
+    pub fn update(&mut self, dt: f32) {
+        let mut finished = Vec::new();
+        let mut index = 0;
+        while index < self.running.len() {
+            if self.running[index].1.advance(dt) {
+                finished.push(self.running.remove(index).1);
+            } else {
+                index += 1;
+            }
+        }
+        // Callbacks run after the list is consistent again
+        for mut tween in finished {
+            tween.complete();
+        }
+    }
+}
@@ -0,0 +1,643 @@
+use std::collections::HashMap;
+
+use glam::Vec2;
+
+use crate::app::Overlay;
+use crate::assets::Handle;
+use crate::text::{Fonts, RichText, TextLayout, TextStyle};
+use crate::texture::Texture;
+
+// A rectangle in logical pixels, (0, 0) is the top left corner of the window
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct UiRect {
+    pub min: Vec2,
+    pub size: Vec2,
+}
+
+impl UiRect {
+    pub fn new(min: Vec2, size: Vec2) -> Self {
+        Self { min, size }
+    }
+
+    pub fn max(&self) -> Vec2 {
+        self.min + self.size
+    }
+
+    pub fn contains(&self, point: Vec2) -> bool {
+        point.cmpge(self.min).all() && point.cmplt(self.max()).all()
+    }
+
+    // Shrunk by `edges` on every side, never below nothing
+    pub fn inset(&self, edges: Edges) -> Self {
+        let min = self.min + Vec2::new(edges.left, edges.top);
+        let size = self.size - Vec2::new(edges.left + edges.right, edges.top + edges.bottom);
+        Self::new(min, size.max(Vec2::ZERO))
+    }
+}
+
+// Space on each side of a node's contents, in logical pixels
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Edges {
+    pub left: f32,
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+}
+
+impl Edges {
+    pub fn all(value: f32) -> Self {
+        Self::symmetric(value, value)
+    }
+
+    pub fn symmetric(horizontal: f32, vertical: f32) -> Self {
+        Self {
+            left: horizontal,
+            top: vertical,
+            right: horizontal,
+            bottom: vertical,
+        }
+    }
+
+    fn total(&self) -> Vec2 {
+        Vec2::new(self.left + self.right, self.top + self.bottom)
+    }
+}
+
+// How big a node is along one axis
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Size {
+    // As big as its contents and padding
+    #[default]
+    Auto,
+    Pixels(f32),
+    // Of the space inside the parent's padding
+    Fraction(f32),
+}
+
+// Which way a node's children go
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Direction {
+    Row,
+    #[default]
+    Column,
+}
+
+// Where children go across the direction they're laid out in
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AlignItems {
+    Start,
+    Center,
+    End,
+    // Auto sized children fill the parent across
+    #[default]
+    Stretch,
+}
+
+// Where children go along the direction they're laid out in, when there's
+// room left over and none of them grow into it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Justify {
+    #[default]
+    Start,
+    Center,
+    End,
+    SpaceBetween,
+}
+
+// A point of the parent a node is pinned to instead of taking its turn in
+// the parent's layout, like a HUD's corners. The same point of the node
+// goes there.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor {
+    // From the top left corner, in fractions of the size
+    fn fraction(self) -> Vec2 {
+        use Anchor::*;
+        let x = match self {
+            TopLeft | Left | BottomLeft => 0.0,
+            Top | Center | Bottom => 0.5,
+            TopRight | Right | BottomRight => 1.0,
+        };
+        let y = match self {
+            TopLeft | Top | TopRight => 0.0,
+            Left | Center | Right => 0.5,
+            BottomLeft | Bottom | BottomRight => 1.0,
+        };
+        Vec2::new(x, y)
+    }
+}
+
+// What's drawn inside a node's padding, on top of its background
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Content {
+    #[default]
+    None,
+    // Wraps at the node's width when it's narrower than the text
+    Text(RichText, TextStyle),
+    // Stretched over the node, `size` is what Size::Auto makes it
+    Image {
+        texture: Handle<Texture>,
+        size: Vec2,
+        color: [f32; 4],
+    },
+}
+
+// One box of a Ui. Everything about how it's laid out and drawn is here, the
+// Ui keeps where it ended up.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Node {
+    pub width: Size,
+    pub height: Size,
+    pub padding: Edges,
+    pub direction: Direction,
+    // Between children, in logical pixels
+    pub gap: f32,
+    pub align: AlignItems,
+    pub justify: Justify,
+    // How much of the parent's leftover room along its direction this node
+    // takes, shared out between the children that grow by these weights
+    pub grow: f32,
+    // Pinned to a point of the parent, moved by the offset, instead of
+    // being laid out with its siblings
+    pub anchor: Option<(Anchor, Vec2)>,
+    pub background: Option<[f32; 4]>,
+    pub content: Content,
+    // Hidden nodes take no room and aren't drawn, nor are their children
+    pub visible: bool,
+}
+
+impl Default for Node {
+    fn default() -> Self {
+        Self {
+            width: Size::Auto,
+            height: Size::Auto,
+            padding: Edges::default(),
+            direction: Direction::Column,
+            gap: 0.0,
+            align: AlignItems::Stretch,
+            justify: Justify::Start,
+            grow: 0.0,
+            anchor: None,
+            background: None,
+            content: Content::None,
+            visible: true,
+        }
+    }
+}
+
+impl Node {
+    pub fn row() -> Self {
+        Self {
+            direction: Direction::Row,
+            ..Default::default()
+        }
+    }
+
+    pub fn column() -> Self {
+        Self::default()
+    }
+
+    pub fn text(text: impl Into<RichText>, style: TextStyle) -> Self {
+        Self {
+            content: Content::Text(text.into(), style),
+            ..Default::default()
+        }
+    }
+
+    pub fn image(texture: Handle<Texture>, size: Vec2) -> Self {
+        Self {
+            content: Content::Image {
+                texture,
+                size,
+                color: [1.0; 4],
+            },
+            ..Default::default()
+        }
+    }
+
+    pub fn with_size(mut self, width: Size, height: Size) -> Self {
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    pub fn with_padding(mut self, padding: Edges) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    pub fn with_gap(mut self, gap: f32) -> Self {
+        self.gap = gap;
+        self
+    }
+
+    pub fn with_align(mut self, align: AlignItems) -> Self {
+        self.align = align;
+        self
+    }
+
+    pub fn with_justify(mut self, justify: Justify) -> Self {
+        self.justify = justify;
+        self
+    }
+
+    pub fn with_grow(mut self, grow: f32) -> Self {
+        self.grow = grow;
+        self
+    }
+
+    pub fn with_anchor(mut self, anchor: Anchor, offset: Vec2) -> Self {
+        self.anchor = Some((anchor, offset));
+        self
+    }
+
+    pub fn with_background(mut self, color: [f32; 4]) -> Self {
+        self.background = Some(color);
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId(u64);
+
+struct Slot {
+    node: Node,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    rect: UiRect,
+    // The text laid out at the width it was last asked for, kept until the
+    // node's changed
+    text: Option<(f32, TextLayout)>,
+}
+
+// A tree of nodes that stays around between frames, laid out like CSS
+// flexbox cut down to what HUDs and menus need: rows and columns, padding,
+// gaps, growing into leftover room and anchoring to the parent's corners.
+// A lighter alternative to egui, drawn with the sprite and text batches.
+//
+//     let hud = ui.add(ui.root(), Node::row().with_anchor(Anchor::TopRight, Vec2::new(-8.0, 8.0)));
+//     let score = ui.add(hud, Node::text("Score: 0", TextStyle::new(24.0)));
+//
+// then in App::overlay(), after changing whatever's changed through
+// node_mut():
+//
+//     ui.draw(overlay);
+pub struct Ui {
+    nodes: HashMap<NodeId, Slot>,
+    root: NodeId,
+    next_id: u64,
+}
+
+impl Default for Ui {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ui {
+    // Just the root, which covers the whole window
+    pub fn new() -> Self {
+        let root = NodeId(0);
+        let mut nodes = HashMap::new();
+        nodes.insert(
+            root,
+            Slot {
+                node: Node::default(),
+                parent: None,
+                children: Vec::new(),
+                rect: UiRect::default(),
+                text: None,
+            },
+        );
+        Self { nodes, root, next_id: 1 }
+    }
+
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    // After the parent's other children. Nodes added to a removed parent go
+    // nowhere.
+    pub fn add(&mut self, parent: NodeId, node: Node) -> NodeId {
+        let id = NodeId(self.next_id);
+        self.next_id += 1;
+        let Some(parent_slot) = self.nodes.get_mut(&parent) else {
+            return id;
+        };
+        parent_slot.children.push(id);
+        self.nodes.insert(
+            id,
+            Slot {
+                node,
+                parent: Some(parent),
+                children: Vec::new(),
+                rect: UiRect::default(),
+                text: None,
+            },
+        );
+        id
+    }
+
+    // Along with all of its children. The root stays, its children go.
+    pub fn remove(&mut self, id: NodeId) {
+        let Some(slot) = self.nodes.get_mut(&id) else {
+            return;
+        };
+        let children = std::mem::take(&mut slot.children);
+        for child in children {
+            self.remove_subtree(child);
+        }
+        if id == self.root {
+            return;
+        }
+        if let Some(slot) = self.nodes.remove(&id) {
+            if let Some(parent) = slot.parent.and_then(|parent| self.nodes.get_mut(&parent)) {
+                parent.children.retain(|child| *child != id);
+            }
+        }
+    }
+
+    fn remove_subtree(&mut self, id: NodeId) {
+        if let Some(slot) = self.nodes.remove(&id) {
+            for child in slot.children {
+                self.remove_subtree(child);
+            }
+        }
+    }
+
+    pub fn node(&self, id: NodeId) -> Option<&Node> {
+        self.nodes.get(&id).map(|slot| &slot.node)
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> Option<&mut Node> {
+        let slot = self.nodes.get_mut(&id)?;
+        // It's probably about to change
+        slot.text = None;
+        Some(&mut slot.node)
+    }
+
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.nodes.get(&id)?.parent
+    }
+
+    pub fn children(&self, id: NodeId) -> &[NodeId] {
+        self.nodes.get(&id).map_or(&[], |slot| &slot.children)
+    }
+
+    // Moves the node after its siblings, so it's drawn over them and found
+    // first by node_at()
+    pub fn raise(&mut self, id: NodeId) {
+        let Some(parent) = self.parent(id).and_then(|parent| self.nodes.get_mut(&parent)) else {
+            return;
+        };
+        parent.children.retain(|child| *child != id);
+        parent.children.push(id);
+    }
+
+    // Where the node was put by the last layout()
+    pub fn rect(&self, id: NodeId) -> Option<UiRect> {
+        self.nodes.get(&id).map(|slot| slot.rect)
+    }
+
+    // Whether the node and every parent it has are visible
+    pub fn is_visible(&self, id: NodeId) -> bool {
+        let mut id = Some(id);
+        while let Some(slot) = id.and_then(|id| self.nodes.get(&id)) {
+            if !slot.node.visible {
+                return false;
+            }
+            id = slot.parent;
+        }
+        true
+    }
+
+    // The visible node drawn on top at `point`, from the last layout(). Nodes
+    // without a background or content are see-through, so the root doesn't
+    // swallow every click.
+    pub fn node_at(&self, point: Vec2) -> Option<NodeId> {
+        self.node_at_in(self.root, point)
+    }
+
+    fn node_at_in(&self, id: NodeId, point: Vec2) -> Option<NodeId> {
+        let slot = self.nodes.get(&id)?;
+        if !slot.node.visible {
+            return None;
+        }
+        // Later children are drawn over earlier ones
+        if let Some(hit) = slot.children.iter().rev().find_map(|&child| self.node_at_in(child, point)) {
+            return Some(hit);
+        }
+        let solid = slot.node.background.is_some() || slot.node.content != Content::None;
+        (solid && slot.rect.contains(point)).then_some(id)
+    }
+
+    // Places every node in a `size` window, in logical pixels
+    pub fn layout(&mut self, size: Vec2, fonts: &Fonts) {
+        self.arrange(self.root, UiRect::new(Vec2::ZERO, size), fonts);
+    }
+
+    // layout() for the overlay's window, then draws every visible node,
+    // children over their parents
+    pub fn draw(&mut self, overlay: &mut Overlay) {
+        self.layout(overlay.size(), overlay.fonts());
+        self.draw_node(self.root, overlay);
+    }
+
+    fn draw_node(&self, id: NodeId, overlay: &mut Overlay) {
+        let Some(slot) = self.nodes.get(&id) else {
+            return;
+        };
+        if !slot.node.visible {
+            return;
+        }
+        if let Some(color) = slot.node.background {
+            overlay.rect(slot.rect.min, slot.rect.size, color);
+        }
+        let inner = slot.rect.inset(slot.node.padding);
+        match &slot.node.content {
+            Content::None => {}
+            Content::Text(..) => {
+                if let Some((_, layout)) = &slot.text {
+                    overlay.draw_layout(layout, inner.min);
+                }
+            }
+            Content::Image { texture, color, .. } => {
+                overlay.sprite(crate::sprite::Sprite::new(*texture, inner.min, inner.size).with_color(*color));
+            }
+        }
+        for &child in &slot.children {
+            self.draw_node(child, overlay);
+        }
+    }
+
+    // How big the node would like to be with `available` room inside its
+    // parent's padding
+    fn measure(&mut self, id: NodeId, available: Vec2, fonts: &Fonts) -> Vec2 {
+        let Some(slot) = self.nodes.get(&id) else {
+            return Vec2::ZERO;
+        };
+        let node = &slot.node;
+        if !node.visible {
+            return Vec2::ZERO;
+        }
+        let fixed = |size: Size, available: f32| match size {
+            Size::Auto => None,
+            Size::Pixels(pixels) => Some(pixels),
+            Size::Fraction(fraction) => Some(fraction * available),
+        };
+        let fixed = [fixed(node.width, available.x), fixed(node.height, available.y)];
+        if let [Some(width), Some(height)] = fixed {
+            return Vec2::new(width, height);
+        }
+        let padding = node.padding.total();
+        let inner = Vec2::new(fixed[0].unwrap_or(available.x), fixed[1].unwrap_or(available.y)) - padding;
+        let inner = inner.max(Vec2::ZERO);
+        let direction = node.direction;
+        let gap = node.gap;
+        let children = slot.children.clone();
+        let content = match &node.content {
+            Content::None => Vec2::ZERO,
+            Content::Text(..) => {
+                // Not max_width, which the layout's size is when there is one
+                let layout = self.text_layout(id, inner.x, fonts);
+                Vec2::new(layout.lines.iter().map(|line| line.width).fold(0.0, f32::max), layout.size.y)
+            }
+            Content::Image { size, .. } => *size,
+        };
+
+        // Anchored children don't take up room
+        let mut main = 0.0;
+        let mut cross: f32 = 0.0;
+        let mut count = 0_usize;
+        for child in children {
+            let in_flow = self.nodes.get(&child).is_some_and(|slot| slot.node.visible && slot.node.anchor.is_none());
+            if !in_flow {
+                continue;
+            }
+            let size = self.measure(child, inner, fonts);
+            let (child_main, child_cross) = split(direction, size);
+            main += child_main;
+            cross = cross.max(child_cross);
+            count += 1;
+        }
+        main += gap * count.saturating_sub(1) as f32;
+        let children = join(direction, main, cross);
+        let auto = content.max(children) + padding;
+        Vec2::new(fixed[0].unwrap_or(auto.x), fixed[1].unwrap_or(auto.y))
+    }
+
+    // Puts the node at `rect` and lays out its children inside it
+    fn arrange(&mut self, id: NodeId, rect: UiRect, fonts: &Fonts) {
+        let Some(slot) = self.nodes.get_mut(&id) else {
+            return;
+        };
+        slot.rect = rect;
+        let node = slot.node.clone();
+        let children = slot.children.clone();
+        let inner = rect.inset(node.padding);
+        if let Content::Text(..) = node.content {
+            self.text_layout(id, inner.size.x, fonts);
+        }
+
+        let mut in_flow = Vec::new();
+        for &child in &children {
+            let Some(child_node) = self.nodes.get(&child).map(|slot| &slot.node) else {
+                continue;
+            };
+            if !child_node.visible {
+                continue;
+            }
+            let (grow, anchor, sizes) = (child_node.grow, child_node.anchor, [child_node.width, child_node.height]);
+            let size = self.measure(child, inner.size, fonts);
+            match anchor {
+                Some((anchor, offset)) => {
+                    let fraction = anchor.fraction();
+                    let min = inner.min + inner.size * fraction - size * fraction + offset;
+                    self.arrange(child, UiRect::new(min, size), fonts);
+                }
+                None => in_flow.push((child, size, grow, sizes)),
+            }
+        }
+
+        let (inner_main, inner_cross) = split(node.direction, inner.size);
+        let gaps = node.gap * in_flow.len().saturating_sub(1) as f32;
+        let used = in_flow.iter().map(|(_, size, ..)| split(node.direction, *size).0).sum::<f32>() + gaps;
+        let free = (inner_main - used).max(0.0);
+        let total_grow = in_flow.iter().map(|(_, _, grow, _)| grow.max(0.0)).sum::<f32>();
+        let (mut position, spacing) = match node.justify {
+            _ if total_grow > 0.0 => (0.0, node.gap),
+            Justify::Start => (0.0, node.gap),
+            Justify::Center => (free / 2.0, node.gap),
+            Justify::End => (free, node.gap),
+            Justify::SpaceBetween if in_flow.len() > 1 => (0.0, node.gap + free / (in_flow.len() - 1) as f32),
+            Justify::SpaceBetween => (0.0, node.gap),
+        };
+        for (child, size, grow, sizes) in in_flow {
+            let (mut main, mut cross) = split(node.direction, size);
+            if total_grow > 0.0 {
+                main += free * grow.max(0.0) / total_grow;
+            }
+            // Only what sizes itself stretches
+            let cross_auto = match node.direction {
+                Direction::Row => sizes[1] == Size::Auto,
+                Direction::Column => sizes[0] == Size::Auto,
+            };
+            let offset = match node.align {
+                AlignItems::Stretch if cross_auto => {
+                    cross = inner_cross;
+                    0.0
+                }
+                AlignItems::Start | AlignItems::Stretch => 0.0,
+                AlignItems::Center => (inner_cross - cross) / 2.0,
+                AlignItems::End => inner_cross - cross,
+            };
+            let min = inner.min + join(node.direction, position, offset);
+            self.arrange(child, UiRect::new(min, join(node.direction, main, cross)), fonts);
+            position += main + spacing;
+        }
+    }
+
+    // The node's text wrapped at `width`, laid out again only when the width
+    // or the node changed
+    fn text_layout(&mut self, id: NodeId, width: f32, fonts: &Fonts) -> &TextLayout {
+        let slot = self.nodes.get_mut(&id).expect("only called for nodes that exist");
+        let stale = !matches!(&slot.text, Some((laid_out, _)) if *laid_out == width);
+        if stale {
+            let layout = match &slot.node.content {
+                Content::Text(text, style) => {
+                    let max_width = style.max_width.map_or(width, |max_width| max_width.min(width));
+                    fonts.layout(text, &style.with_max_width(max_width))
+                }
+                _ => TextLayout::default(),
+            };
+            slot.text = Some((width, layout));
+        }
+        &slot.text.as_ref().expect("just laid out").1
+    }
+}
+
+// Along the direction and across it
+fn split(direction: Direction, size: Vec2) -> (f32, f32) {
+    match direction {
+        Direction::Row => (size.x, size.y),
+        Direction::Column => (size.y, size.x),
+    }
+}
+
+fn join(direction: Direction, main: f32, cross: f32) -> Vec2 {
+    match direction {
+        Direction::Row => Vec2::new(main, cross),
+        Direction::Column => Vec2::new(cross, main),
+    }
+}
@@ -0,0 +1,107 @@
+use std::cell::RefCell;
+
+use wgpu::util::StagingBelt;
+
+// Big enough that a frame's worth of uniform and small vertex updates fit in
+// one chunk, writes bigger than this get a chunk of their own.
+const STAGING_CHUNK_SIZE: wgpu::BufferAddress = 64 * 1024;
+
+// Per-frame buffer updates go through a staging belt rather than lots of
+// small queue.write_buffer calls. The copies are recorded into the frame's
+// encoder so they happen right before the passes that use the data.
+//
+// Every frame: write() as often as needed, finish() before submitting the
+// encoder and recall() after submitting so the chunks can be reused.
+pub struct Uploader {
+    belt: StagingBelt,
+}
+
+impl Default for Uploader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Uploader {
+    pub fn new() -> Self {
+        Self {
+            belt: StagingBelt::new(STAGING_CHUNK_SIZE),
+        }
+    }
+
+    // Copies between buffers have to start and end on 4 byte boundaries
+    // (wgpu::COPY_BUFFER_ALIGNMENT), so both `offset` and the size of `data`
+    // have to be multiples of 4. Pad structs out to that, like the uniforms
+    // do.
+    pub fn write<T: bytemuck::Pod>(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::Buffer,
+        offset: wgpu::BufferAddress,
+        data: &[T],
+    ) {
+        let bytes: &[u8] = bytemuck::cast_slice(data);
+        // The belt panics on empty writes
+        let size = match wgpu::BufferSize::new(bytes.len() as wgpu::BufferAddress) {
+            Some(size) => size,
+            None => return,
+        };
+        assert!(
+            offset.is_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT),
+            "Uploader::write() offset {} isn't a multiple of {} bytes",
+            offset,
+            wgpu::COPY_BUFFER_ALIGNMENT
+        );
+        assert!(
+            size.get().is_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT),
+            "Uploader::write() of {} bytes isn't a multiple of {} bytes, pad the data out",
+            size,
+            wgpu::COPY_BUFFER_ALIGNMENT
+        );
+        self.belt
+            .write_buffer(encoder, target, offset, size, device)
+            .copy_from_slice(bytes);
+    }
+
+    pub fn finish(&mut self) {
+        self.belt.finish();
+    }
+
+    pub fn recall(&mut self) {
+        self.belt.recall();
+    }
+}
+
+// The Uploader for App::render(), which has no encoder to record copies
+// into. They go into one of their own that's submitted just before the
+// frame's, so everything the app uploads is there for its passes.
+// It borrows the frame's Uploader, which is handed back by finish().
+pub struct AppUploader<'a> {
+    device: &'a wgpu::Device,
+    uploader: RefCell<Uploader>,
+    encoder: RefCell<wgpu::CommandEncoder>,
+}
+
+impl<'a> AppUploader<'a> {
+    pub(crate) fn new(device: &'a wgpu::Device, uploader: Uploader) -> Self {
+        let encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("App Upload Encoder"),
+        });
+        Self {
+            device,
+            uploader: RefCell::new(uploader),
+            encoder: RefCell::new(encoder),
+        }
+    }
+
+    // Like Uploader::write(), with the same alignment rules
+    pub fn write<T: bytemuck::Pod>(&self, target: &wgpu::Buffer, offset: wgpu::BufferAddress, data: &[T]) {
+        let mut encoder = self.encoder.borrow_mut();
+        self.uploader.borrow_mut().write(self.device, &mut encoder, target, offset, data);
+    }
+
+    pub(crate) fn finish(self) -> (Uploader, wgpu::CommandBuffer) {
+        (self.uploader.into_inner(), self.encoder.into_inner().finish())
+    }
+}
@@ -0,0 +1,192 @@
+// Vertex layouts meshes can use. Every attribute has the same shader location
+// whichever layout it's in, so a shader written for fewer attributes works
+// with any layout that has them (unused attributes are simply ignored):
+//
+//     0 position, 1 tex_coords, 2 normal, 3 tangent, 4 color
+//
+// Locations 5 to 8 are taken by the instance transform.
+
+pub trait VertexLayout: bytemuck::Pod {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a>;
+}
+
+// Rust types that can go in a vertex buffer, as the attribute formats they
+// turn into. Matrices take up one attribute (and shader location) per column.
+pub trait VertexAttributeType {
+    const FORMATS: &'static [wgpu::VertexFormat];
+}
+
+macro_rules! attribute_types {
+    ($($ty:ty => [$($format:ident),*]),* $(,)?) => {
+        $(impl VertexAttributeType for $ty {
+            const FORMATS: &'static [wgpu::VertexFormat] = &[$(wgpu::VertexFormat::$format),*];
+        })*
+    };
+}
+
+attribute_types! {
+    f32 => [Float32],
+    [f32; 2] => [Float32x2],
+    [f32; 3] => [Float32x3],
+    [f32; 4] => [Float32x4],
+    u32 => [Uint32],
+    [u32; 2] => [Uint32x2],
+    [u32; 3] => [Uint32x3],
+    [u32; 4] => [Uint32x4],
+    i32 => [Sint32],
+    [i32; 2] => [Sint32x2],
+    [i32; 3] => [Sint32x3],
+    [i32; 4] => [Sint32x4],
+    [u16; 2] => [Uint16x2],
+    [u16; 4] => [Uint16x4],
+    [u8; 4] => [Uint8x4],
+    [[f32; 3]; 3] => [Float32x3, Float32x3, Float32x3],
+    [[f32; 4]; 4] => [Float32x4, Float32x4, Float32x4, Float32x4],
+}
+
+// Used by vertex_layout!, adds the attributes for one field
+pub fn push_attributes(
+    attributes: &mut Vec<wgpu::VertexAttribute>,
+    location: u32,
+    offset: usize,
+    formats: &[wgpu::VertexFormat],
+) {
+    let mut offset = offset as wgpu::BufferAddress;
+    for (i, format) in formats.iter().enumerate() {
+        attributes.push(wgpu::VertexAttribute {
+            format: *format,
+            offset,
+            shader_location: location + i as u32,
+        });
+        offset += format.size();
+    }
+}
+
+// Declares a vertex struct and implements VertexLayout for it, with offsets
+// taken from where the fields really are and formats from their types, so
+// the layout can't drift away from the struct:
+//
+//     vertex_layout! {
+//         #[derive(Debug)]
+//         pub struct MyVertex {
+//             #[location(0)] pub position: [f32; 3],
+//             #[location(1)] pub tex_coords: [f32; 2],
+//         }
+//     }
+//
+// Put `step_mode: Instance,` in front of the struct for per-instance data.
+#[macro_export]
+macro_rules! vertex_layout {
+    (
+        step_mode: $step_mode:ident,
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $(#[location($location:literal)] $field_vis:vis $field:ident: $ty:ty),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[repr(C)]
+        #[derive(Copy, Clone, ::bytemuck::Pod, ::bytemuck::Zeroable)]
+        $vis struct $name {
+            $($field_vis $field: $ty),*
+        }
+
+        impl $crate::vertex::VertexLayout for $name {
+            fn desc<'a>() -> ::wgpu::VertexBufferLayout<'a> {
+                static ATTRIBUTES: ::std::sync::OnceLock<Vec<::wgpu::VertexAttribute>> = ::std::sync::OnceLock::new();
+                let attributes = ATTRIBUTES.get_or_init(|| {
+                    let mut attributes = Vec::new();
+                    $($crate::vertex::push_attributes(
+                        &mut attributes,
+                        $location,
+                        ::std::mem::offset_of!($name, $field),
+                        <$ty as $crate::vertex::VertexAttributeType>::FORMATS,
+                    );)*
+                    attributes
+                });
+                ::wgpu::VertexBufferLayout {
+                    array_stride: ::std::mem::size_of::<$name>() as ::wgpu::BufferAddress,
+                    step_mode: ::wgpu::VertexStepMode::$step_mode,
+                    attributes,
+                }
+            }
+        }
+    };
+    ($(#[$meta:meta])* $vis:vis struct $($rest:tt)*) => {
+        $crate::vertex_layout! {
+            step_mode: Vertex,
+            $(#[$meta])*
+            $vis struct $($rest)*
+        }
+    };
+}
+
+crate::vertex_layout! {
+    #[derive(Debug, Default, PartialEq)]
+    pub struct VertexPosUv {
+        #[location(0)] pub position: [f32; 3],
+        #[location(1)] pub tex_coords: [f32; 2],
+    }
+}
+
+impl VertexPosUv {
+    // The matching vertex shader input
+    pub const WGSL: &'static str = "struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+}
+";
+}
+
+crate::vertex_layout! {
+    #[derive(Debug, Default, PartialEq)]
+    pub struct VertexPosNormalUv {
+        #[location(0)] pub position: [f32; 3],
+        #[location(2)] pub normal: [f32; 3],
+        #[location(1)] pub tex_coords: [f32; 2],
+    }
+}
+
+impl VertexPosNormalUv {
+    pub const WGSL: &'static str = "struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+}
+";
+}
+
+// Everything normal mapping and vertex colours need. The tangent's w is 1 or
+// -1 depending on which way the bitangent points, like glTF does it.
+crate::vertex_layout! {
+    #[derive(Debug, Default, PartialEq)]
+    pub struct VertexPosNormalTangentUvColor {
+        #[location(0)] pub position: [f32; 3],
+        #[location(2)] pub normal: [f32; 3],
+        #[location(3)] pub tangent: [f32; 4],
+        #[location(1)] pub tex_coords: [f32; 2],
+        #[location(4)] pub color: [f32; 4],
+    }
+}
+
+impl VertexPosNormalTangentUvColor {
+    pub const WGSL: &'static str = "struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec4<f32>,
+    @location(4) color: vec4<f32>,
+}
+";
+}
+
+// The second set of texture coordinates, where a mesh is in its lightmap.
+// Goes in its own stream of the mesh pool (see MeshPool::add_stream()) at
+// location 9, after the instance's model matrix, so only the lightmap
+// pipeline has to know about it.
+crate::vertex_layout! {
+    #[derive(Debug, Default, PartialEq)]
+    pub struct VertexLightmapUv {
+        #[location(9)] pub lightmap_coords: [f32; 2],
+    }
+}
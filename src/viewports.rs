@@ -0,0 +1,108 @@
+use glam::{Mat4, Vec3};
+
+use crate::app::{PassRegion, SceneViewport};
+use crate::bounds::Frustum;
+use crate::bvh::Bvh;
+use crate::draw::DrawCommand;
+use crate::upload::Uploader;
+use crate::{Camera, CameraUniform};
+
+// One of RenderSettings::viewports as it gets drawn this frame
+pub struct ViewportCamera {
+    // Where in the scene target it goes
+    pub region: PassRegion,
+    pub camera_position: Vec3,
+    pub view_proj: Mat4,
+    // Group 1 of the mesh pipeline, like RenderContext::camera_bind_group
+    pub camera_bind_group: wgpu::BindGroup,
+    buffer: wgpu::Buffer,
+}
+
+// The cameras of RenderSettings::viewports. Each gets its own uniform buffer
+// and bind group, kept around for as many viewports as there have been at
+// once so adding and removing them doesn't allocate every frame.
+#[derive(Default)]
+pub struct ViewportCameras {
+    cameras: Vec<ViewportCamera>,
+    // How many of them the last prepare() filled
+    active: usize,
+}
+
+impl ViewportCameras {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Works out every viewport's camera for a `target` sized scene target,
+    // with the aspect ratio of its rect
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        uploader: &mut Uploader,
+        camera_layout: &wgpu::BindGroupLayout,
+        viewports: &[SceneViewport],
+        target: (u32, u32),
+    ) {
+        while self.cameras.len() < viewports.len() {
+            let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("viewport_camera"),
+                size: std::mem::size_of::<CameraUniform>() as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("viewport_camera"),
+                layout: camera_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                }],
+            });
+            self.cameras.push(ViewportCamera {
+                region: PassRegion::default(),
+                camera_position: Vec3::ZERO,
+                view_proj: Mat4::IDENTITY,
+                camera_bind_group,
+                buffer,
+            });
+        }
+
+        for (viewport, slot) in viewports.iter().zip(&mut self.cameras) {
+            slot.region = viewport.region();
+            let camera = Camera::from_scene(&viewport.camera, slot.region.aspect(target));
+            let mut uniform = CameraUniform::new();
+            uniform.update_view_proj(&camera);
+            uploader.write(device, encoder, &slot.buffer, 0, &[uniform]);
+            slot.camera_position = camera.eye;
+            slot.view_proj = camera.build_view_projection_matrix();
+        }
+        self.active = viewports.len();
+    }
+
+    pub fn cameras(&self) -> &[ViewportCamera] {
+        &self.cameras[..self.active]
+    }
+
+    // The commands each viewport's camera can see something of. `bvh` holds
+    // the bounds of every instance the commands draw.
+    pub fn cull(&self, bvh: &Bvh, commands: &[&[DrawCommand]]) -> Vec<Vec<DrawCommand>> {
+        let mut visible = vec![false; bvh.len()];
+        self.cameras()
+            .iter()
+            .map(|camera| {
+                visible.fill(false);
+                bvh.query_frustum(&Frustum::from_view_proj(camera.view_proj), |instance| visible[instance] = true);
+                commands
+                    .iter()
+                    .flat_map(|commands| commands.iter())
+                    // A batch stays whole when any of its instances is in view
+                    .filter(|command| {
+                        command.instances.clone().any(|instance| visible.get(instance as usize).copied().unwrap_or(true))
+                    })
+                    .cloned()
+                    .collect()
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,513 @@
+use glam::{Mat4, Vec2, Vec3, Vec4};
+use wgpu::util::DeviceExt;
+
+use crate::render_graph::{GraphResources, RenderGraph, TextureSize, TransientTexture};
+use crate::sky::{Lighting, LightingUniform};
+use crate::texture::Texture;
+use crate::upload::AppUploader;
+use crate::vertex::VertexLayout;
+
+const SOURCE: &str = include_str!("shaders/water.wgsl");
+
+// The most waves the shader adds up
+pub const MAX_WAVES: usize = 4;
+
+crate::vertex_layout! {
+    #[derive(Debug)]
+    struct WaterVertex {
+        #[location(0)] position: [f32; 2],
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct WaterUniform {
+    inverse_view_proj: [[f32; 4]; 4],
+    eye: [f32; 3],
+    level: f32,
+    waves: [[f32; 4]; MAX_WAVES],
+    wave_count: u32,
+    _padding: [u32; 3],
+    lighting: LightingUniform,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Wave {
+    // Which way it travels, along the ground
+    pub direction: Vec2,
+    // Crest to crest, in world units
+    pub wavelength: f32,
+    // 0 is flat, at 1 the crests come to a point. The steepnesses of all the
+    // waves should add up to less than 1 or the surface folds over itself.
+    pub steepness: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WaterConfig {
+    // Height of the surface when it's calm
+    pub level: f32,
+    // Middle of the square the water covers, and how wide it is
+    pub center: Vec2,
+    pub size: f32,
+    // Quads along each side, waves shorter than a couple of quads get lost
+    pub resolution: u32,
+    // Up to MAX_WAVES, the rest are ignored
+    pub waves: Vec<Wave>,
+}
+
+impl Default for WaterConfig {
+    fn default() -> Self {
+        Self {
+            level: 0.0,
+            center: Vec2::ZERO,
+            size: 128.0,
+            resolution: 256,
+            waves: vec![
+                Wave {
+                    direction: Vec2::new(1.0, 0.3),
+                    wavelength: 9.0,
+                    steepness: 0.12,
+                },
+                Wave {
+                    direction: Vec2::new(-0.4, 1.0),
+                    wavelength: 5.0,
+                    steepness: 0.1,
+                },
+                Wave {
+                    direction: Vec2::new(0.7, -0.8),
+                    wavelength: 2.5,
+                    steepness: 0.08,
+                },
+            ],
+        }
+    }
+}
+
+// How far past the calm surface the reflection and refraction passes keep
+// drawing, so waves don't open gaps along the shore
+const CLIP_MARGIN: f32 = 0.25;
+
+// A water surface drawn with the help of two extra passes over the scene:
+// one seen in a mirror under the water for the reflection, one of just what's
+// under the water for the refraction. Both go into half resolution textures
+// of the render graph, and the water pass blends between them with a fresnel
+// term, tinting what's seen through it by how deep it is.
+//
+// Every frame: prepare() with the camera, declare() the textures, draw the
+// scene into reflection_pass() and refraction_pass() with the cameras from
+// reflection_camera() and refraction_camera(), then add_pass() for the water
+// itself after the scene. TerrainApp shows how it fits together.
+pub struct Water {
+    config: WaterConfig,
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    bind_group_layout: wgpu::BindGroupLayout,
+    uniform: WaterUniform,
+    buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    // The view projection the reflection and refraction passes draw with
+    reflection_camera: (wgpu::Buffer, wgpu::BindGroup),
+    refraction_camera: (wgpu::Buffer, wgpu::BindGroup),
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    index_count: u32,
+}
+
+impl Water {
+    // Names of the transient textures in the render graph
+    pub const REFLECTION: &'static str = "water_reflection";
+    pub const REFLECTION_DEPTH: &'static str = "water_reflection_depth";
+    pub const REFRACTION: &'static str = "water_refraction";
+    pub const REFRACTION_DEPTH: &'static str = "water_refraction_depth";
+
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        camera_layout: &wgpu::BindGroupLayout,
+        frame_layout: &wgpu::BindGroupLayout,
+        config: WaterConfig,
+    ) -> Self {
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("water_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                texture_entry(1),
+                texture_entry(2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Water Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, frame_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let mut waves = [[0.0; 4]; MAX_WAVES];
+        for (packed, wave) in waves.iter_mut().zip(&config.waves) {
+            *packed = [wave.direction.x, wave.direction.y, wave.wavelength, wave.steepness];
+        }
+        let uniform = WaterUniform {
+            inverse_view_proj: Mat4::IDENTITY.to_cols_array_2d(),
+            eye: [0.0; 3],
+            level: config.level,
+            waves,
+            wave_count: config.waves.len().min(MAX_WAVES) as u32,
+            _padding: [0; 3],
+            lighting: Lighting::default().into(),
+        };
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Water Buffer"),
+            contents: bytemuck::bytes_of(&uniform),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let camera = |label| {
+            let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: std::mem::size_of::<[[f32; 4]; 4]>() as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(label),
+                layout: camera_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                }],
+            });
+            (buffer, bind_group)
+        };
+
+        let (vertices, indices) = surface_mesh(&config);
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Water Vertex Buffer"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Water Index Buffer"),
+            contents: bytemuck::cast_slice(&indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        Self {
+            pipeline: Self::create_pipeline(device, &layout, color_format, SOURCE),
+            layout,
+            color_format,
+            bind_group_layout,
+            uniform,
+            buffer,
+            sampler,
+            reflection_camera: camera("water_reflection_camera"),
+            refraction_camera: camera("water_refraction_camera"),
+            vertex_buffer,
+            index_buffer,
+            index_count: indices.len() as u32,
+            config,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        shader_source: &str,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Water Shader"),
+            source: wgpu::ShaderSource::Wgsl(shader_source.into()),
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Water Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[WaterVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    // What's under the water comes from the refraction pass,
+                    // so it can be drawn opaque
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            // Seen from below too, when the camera dives in
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+
+    // For apps watching their own copy of water.wgsl, keeps the old pipeline
+    // if the new source doesn't compile
+    pub fn reload_shader(&mut self, device: &wgpu::Device, shader_source: &str) {
+        if let Some(pipeline) = crate::shaders::try_create(device, || {
+            Self::create_pipeline(device, &self.layout, self.color_format, shader_source)
+        }) {
+            self.pipeline = pipeline;
+        }
+    }
+
+    pub fn config(&self) -> &WaterConfig {
+        &self.config
+    }
+
+    // Works out the reflection and refraction cameras for this frame, and
+    // the sun glinting off the waves from `lighting`
+    pub fn prepare(&mut self, uploader: &AppUploader, eye: Vec3, view_proj: Mat4, lighting: &Lighting) {
+        let level = self.config.level;
+        // y goes to 2 * level - y, turning the scene upside down around the
+        // water
+        let mirror =
+            Mat4::from_translation(Vec3::new(0.0, 2.0 * level, 0.0)) * Mat4::from_scale(Vec3::new(1.0, -1.0, 1.0));
+        let above = Vec4::new(0.0, 1.0, 0.0, -(level - CLIP_MARGIN));
+        let below = Vec4::new(0.0, -1.0, 0.0, level + CLIP_MARGIN);
+        let reflection = clip_to_plane(view_proj * mirror, above);
+        let refraction = clip_to_plane(view_proj, below);
+        uploader.write(&self.reflection_camera.0, 0, &reflection.to_cols_array_2d());
+        uploader.write(&self.refraction_camera.0, 0, &refraction.to_cols_array_2d());
+
+        // The depth it reads back is the refraction camera's
+        self.uniform.inverse_view_proj = refraction.inverse().to_cols_array_2d();
+        self.uniform.eye = eye.into();
+        self.uniform.lighting = (*lighting).into();
+        uploader.write(&self.buffer, 0, &[self.uniform]);
+    }
+
+    // In place of the main camera for drawing the scene into the reflection
+    // and refraction passes
+    pub fn reflection_camera(&self) -> &wgpu::BindGroup {
+        &self.reflection_camera.1
+    }
+
+    pub fn refraction_camera(&self) -> &wgpu::BindGroup {
+        &self.refraction_camera.1
+    }
+
+    // Adds the textures the passes draw into to the graph
+    pub fn declare(&self, graph: &mut RenderGraph, color_format: wgpu::TextureFormat) {
+        let half = |format| TransientTexture {
+            size: TextureSize::Divided(2),
+            ..TransientTexture::new(format)
+        };
+        graph.create_texture(Self::REFLECTION, half(color_format));
+        graph.create_texture(Self::REFLECTION_DEPTH, half(Texture::DEPTH_FORMAT));
+        graph.create_texture(Self::REFRACTION, half(color_format));
+        graph.create_texture(Self::REFRACTION_DEPTH, half(Texture::DEPTH_FORMAT));
+    }
+
+    // Starts a pass that clears the reflection texture to `clear` for the
+    // scene to be drawn into, the graph pass it's in has to write
+    // REFLECTION and REFLECTION_DEPTH
+    pub fn reflection_pass<'p>(
+        encoder: &'p mut wgpu::CommandEncoder,
+        resources: &'p GraphResources,
+        clear: wgpu::Color,
+    ) -> wgpu::RenderPass<'p> {
+        begin_scene_pass(encoder, resources, Self::REFLECTION, Self::REFLECTION_DEPTH, clear)
+    }
+
+    // Like reflection_pass(), writing REFRACTION and REFRACTION_DEPTH
+    pub fn refraction_pass<'p>(
+        encoder: &'p mut wgpu::CommandEncoder,
+        resources: &'p GraphResources,
+        clear: wgpu::Color,
+    ) -> wgpu::RenderPass<'p> {
+        begin_scene_pass(encoder, resources, Self::REFRACTION, Self::REFRACTION_DEPTH, clear)
+    }
+
+    // The water itself, drawn into `scene_target` after everything the
+    // reflection and refraction passes drew
+    pub fn add_pass<'g>(
+        &'g self,
+        graph: &mut RenderGraph<'g>,
+        device: &'g wgpu::Device,
+        scene_target: &'static str,
+        camera: &'g wgpu::BindGroup,
+        frame: &'g wgpu::BindGroup,
+    ) {
+        graph
+            .add_pass("water")
+            .reads(&[Self::REFLECTION, Self::REFRACTION, Self::REFRACTION_DEPTH])
+            .writes(&[scene_target, "depth"])
+            .execute(move |encoder, resources| {
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("water_bind_group"),
+                    layout: &self.bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: self.buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::TextureView(resources.view(Self::REFLECTION)),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: wgpu::BindingResource::TextureView(resources.view(Self::REFRACTION)),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 3,
+                            resource: wgpu::BindingResource::TextureView(resources.view(Self::REFRACTION_DEPTH)),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 4,
+                            resource: wgpu::BindingResource::Sampler(&self.sampler),
+                        },
+                    ],
+                });
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Water Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: resources.view(scene_target),
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: resources.view("depth"),
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        }),
+                        stencil_ops: None,
+                    }),
+                });
+                render_pass.set_pipeline(&self.pipeline);
+                render_pass.set_bind_group(0, camera, &[]);
+                render_pass.set_bind_group(1, frame, &[]);
+                render_pass.set_bind_group(2, &bind_group, &[]);
+                render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+                render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                render_pass.draw_indexed(0..self.index_count, 0, 0..1);
+            });
+    }
+}
+
+fn begin_scene_pass<'p>(
+    encoder: &'p mut wgpu::CommandEncoder,
+    resources: &'p GraphResources,
+    color: &str,
+    depth: &str,
+    clear: wgpu::Color,
+) -> wgpu::RenderPass<'p> {
+    encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(color),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: resources.view(color),
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(clear),
+                store: true,
+            },
+        })],
+        depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+            view: resources.view(depth),
+            depth_ops: Some(wgpu::Operations {
+                load: wgpu::LoadOp::Clear(1.0),
+                store: true,
+            }),
+            stencil_ops: None,
+        }),
+    })
+}
+
+// Moves the near plane of `view_proj` onto `plane` (world space, points with
+// plane · p >= 0 are kept) and the far plane out to where it still covers
+// the old frustum. It's Lengyel's oblique near plane, done in clip space so
+// it works on a view projection that already has the mirror in it. Nothing
+// on the wrong side of the water gets drawn, without needing clip distances.
+// Mirrors in the scene use it too, see mirror.rs.
+pub(crate) fn clip_to_plane(view_proj: Mat4, plane: Vec4) -> Mat4 {
+    let clip_plane = view_proj.inverse().transpose() * plane;
+    // The far corner of the frustum on the plane's side
+    let corner = Vec4::new(clip_plane.x.signum(), clip_plane.y.signum(), 1.0, 1.0);
+    let depth_row = plane / clip_plane.dot(corner);
+    let mut clipped = view_proj;
+    clipped.x_axis.z = depth_row.x;
+    clipped.y_axis.z = depth_row.y;
+    clipped.z_axis.z = depth_row.z;
+    clipped.w_axis.z = depth_row.w;
+    clipped
+}
+
+// A flat grid in world x and z, the shader lifts it to the water level
+fn surface_mesh(config: &WaterConfig) -> (Vec<WaterVertex>, Vec<u32>) {
+    let resolution = config.resolution.max(1);
+    let row = resolution + 1;
+    let corner = config.center - Vec2::splat(config.size * 0.5);
+    let step = config.size / resolution as f32;
+    let mut vertices = Vec::with_capacity((row * row) as usize);
+    for z in 0..row {
+        for x in 0..row {
+            vertices.push(WaterVertex {
+                position: (corner + Vec2::new(x as f32, z as f32) * step).into(),
+            });
+        }
+    }
+    let mut indices = Vec::with_capacity((resolution * resolution * 6) as usize);
+    for z in 0..resolution {
+        for x in 0..resolution {
+            let i = z * row + x;
+            indices.extend([i, i + row, i + 1, i + 1, i + row, i + row + 1]);
+        }
+    }
+    (vertices, indices)
+}
@@ -0,0 +1,368 @@
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+
+use glam::Vec2;
+
+use crate::app::Overlay;
+use crate::text::{RichText, TextStyle};
+use crate::ui::{AlignItems, Anchor, Edges, Justify, Node, NodeId, Size, Ui};
+
+// Logical pixels
+const SLIDER_WIDTH: f32 = 160.0;
+const SLIDER_HEIGHT: f32 = 20.0;
+const THUMB_WIDTH: f32 = 12.0;
+const CHECKBOX_SIZE: f32 = 18.0;
+
+// Colours are linear, like the rest of the overlay's
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WidgetTheme {
+    pub text: TextStyle,
+    // Buttons, checkboxes and slider tracks as they are, under the mouse and
+    // held down
+    pub background: [f32; 4],
+    pub hovered: [f32; 4],
+    pub pressed: [f32; 4],
+    // Ticks and slider thumbs
+    pub accent: [f32; 4],
+    pub window: [f32; 4],
+    pub title_bar: [f32; 4],
+}
+
+impl Default for WidgetTheme {
+    fn default() -> Self {
+        Self {
+            text: TextStyle::new(16.0),
+            background: [0.06, 0.06, 0.07, 1.0],
+            hovered: [0.12, 0.12, 0.14, 1.0],
+            pressed: [0.03, 0.03, 0.035, 1.0],
+            accent: [0.1, 0.3, 0.8, 1.0],
+            window: [0.02, 0.02, 0.025, 0.9],
+            title_bar: [0.04, 0.04, 0.05, 1.0],
+        }
+    }
+}
+
+impl WidgetTheme {
+    // The accent a little lighter, for slider thumbs under the mouse
+    fn hovered_accent(&self) -> [f32; 4] {
+        let [r, g, b, a] = self.accent;
+        [r * 1.5, g * 1.5, b * 1.5, a].map(|channel| channel.min(1.0))
+    }
+}
+
+// What the mouse did to a widget, by the node the widget was made as
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WidgetEvent {
+    // A button was pressed and let go of while the mouse was over it
+    Clicked(NodeId),
+    // A slider's new value
+    Changed(NodeId, f32),
+    // A checkbox's new state
+    Toggled(NodeId, bool),
+    // Where a window's top left corner was dragged to
+    Moved(NodeId, Vec2),
+}
+
+impl WidgetEvent {
+    pub fn id(&self) -> NodeId {
+        match *self {
+            Self::Clicked(id) | Self::Changed(id, _) | Self::Toggled(id, _) | Self::Moved(id, _) => id,
+        }
+    }
+}
+
+type Callback = Box<dyn FnMut(&WidgetEvent)>;
+
+enum Kind {
+    Button,
+    Slider { range: RangeInclusive<f32>, value: f32, thumb: NodeId },
+    Checkbox { checked: bool, tick_box: NodeId, tick: NodeId },
+    Window,
+    // Dragging it moves the window
+    TitleBar { window: NodeId },
+}
+
+// Buttons, sliders, checkboxes and windows made out of a Ui's nodes, which
+// light up under the mouse and darken while held. What happens to them comes
+// back from draw(), and goes to callbacks too:
+//
+//     let (window, content) = widgets.window(&mut ui, "Options", Vec2::new(40.0, 40.0));
+//     let volume = widgets.slider(&mut ui, content, 0.0..=1.0, 0.8);
+//     let quit = widgets.button(&mut ui, content, "Quit");
+//     widgets.on_event(quit, |_| std::process::exit(0));
+//
+// then in App::overlay(), instead of ui.draw():
+//
+//     for event in widgets.draw(&mut ui, overlay) { ... }
+//
+// Removing a widget's node from the Ui is all it takes to get rid of it.
+#[derive(Default)]
+pub struct Widgets {
+    pub theme: WidgetTheme,
+    widgets: HashMap<NodeId, Kind>,
+    callbacks: HashMap<NodeId, Vec<Callback>>,
+    hovered: Option<NodeId>,
+    // Pressed and not let go of yet
+    active: Option<NodeId>,
+    // Where the window being dragged was grabbed, from its top left corner
+    grab: Vec2,
+}
+
+impl Widgets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn button(&mut self, ui: &mut Ui, parent: NodeId, label: impl Into<RichText>) -> NodeId {
+        let button = Node::row()
+            .with_padding(Edges::symmetric(12.0, 6.0))
+            .with_align(AlignItems::Center)
+            .with_justify(Justify::Center)
+            .with_background(self.theme.background);
+        let id = ui.add(parent, button);
+        ui.add(id, Node::text(label, self.theme.text));
+        self.widgets.insert(id, Kind::Button);
+        id
+    }
+
+    // A track with a thumb to drag along it, from the start of `range` on
+    // the left to its end on the right
+    pub fn slider(&mut self, ui: &mut Ui, parent: NodeId, range: RangeInclusive<f32>, value: f32) -> NodeId {
+        // See-through, but catching clicks above and below the track
+        let slider = Node::row()
+            .with_size(Size::Pixels(SLIDER_WIDTH), Size::Pixels(SLIDER_HEIGHT))
+            .with_background([0.0; 4]);
+        let id = ui.add(parent, slider);
+        let track = Node::column()
+            .with_size(Size::Fraction(1.0), Size::Pixels(4.0))
+            .with_anchor(Anchor::Left, Vec2::ZERO)
+            .with_background(self.theme.background);
+        ui.add(id, track);
+        let thumb = Node::column()
+            .with_size(Size::Pixels(THUMB_WIDTH), Size::Fraction(1.0))
+            .with_anchor(Anchor::Left, Vec2::ZERO)
+            .with_background(self.theme.accent);
+        let thumb = ui.add(id, thumb);
+        let value = value.clamp(*range.start(), *range.end());
+        self.widgets.insert(id, Kind::Slider { range, value, thumb });
+        id
+    }
+
+    pub fn checkbox(&mut self, ui: &mut Ui, parent: NodeId, label: impl Into<RichText>, checked: bool) -> NodeId {
+        let checkbox = Node::row().with_gap(8.0).with_align(AlignItems::Center).with_background([0.0; 4]);
+        let id = ui.add(parent, checkbox);
+        let tick_box = Node::column()
+            .with_size(Size::Pixels(CHECKBOX_SIZE), Size::Pixels(CHECKBOX_SIZE))
+            .with_background(self.theme.background);
+        let tick_box = ui.add(id, tick_box);
+        let tick = Node::column()
+            .with_size(Size::Pixels(CHECKBOX_SIZE - 8.0), Size::Pixels(CHECKBOX_SIZE - 8.0))
+            .with_anchor(Anchor::Center, Vec2::ZERO)
+            .with_background(self.theme.accent);
+        let tick = ui.add(tick_box, tick);
+        ui.add(id, Node::text(label, self.theme.text));
+        self.widgets.insert(id, Kind::Checkbox { checked, tick_box, tick });
+        id
+    }
+
+    // A panel with a title bar to drag it around by, its top left corner
+    // `position` from the window's. Returns the window and the node to add
+    // its widgets to.
+    pub fn window(&mut self, ui: &mut Ui, title: impl Into<RichText>, position: Vec2) -> (NodeId, NodeId) {
+        let window = ui.add(
+            ui.root(),
+            Node::column().with_anchor(Anchor::TopLeft, position).with_background(self.theme.window),
+        );
+        let title_bar = Node::row().with_padding(Edges::symmetric(8.0, 4.0)).with_background(self.theme.title_bar);
+        let title_bar = ui.add(window, title_bar);
+        ui.add(title_bar, Node::text(title, self.theme.text));
+        let content = ui.add(window, Node::column().with_padding(Edges::all(8.0)).with_gap(6.0));
+        self.widgets.insert(window, Kind::Window);
+        self.widgets.insert(title_bar, Kind::TitleBar { window });
+        (window, content)
+    }
+
+    // Called with every event for the widget `id`, after draw() works out
+    // what happened
+    pub fn on_event(&mut self, id: NodeId, callback: impl FnMut(&WidgetEvent) + 'static) {
+        self.callbacks.entry(id).or_default().push(Box::new(callback));
+    }
+
+    // A slider's value
+    pub fn value(&self, id: NodeId) -> Option<f32> {
+        match self.widgets.get(&id)? {
+            Kind::Slider { value, .. } => Some(*value),
+            _ => None,
+        }
+    }
+
+    // Moves a slider without sending an event, e.g. to a loaded setting
+    pub fn set_value(&mut self, id: NodeId, value: f32) {
+        if let Some(Kind::Slider { range, value: old, .. }) = self.widgets.get_mut(&id) {
+            *old = value.clamp(*range.start(), *range.end());
+        }
+    }
+
+    // Whether a checkbox is ticked
+    pub fn checked(&self, id: NodeId) -> Option<bool> {
+        match self.widgets.get(&id)? {
+            Kind::Checkbox { checked, .. } => Some(*checked),
+            _ => None,
+        }
+    }
+
+    pub fn set_checked(&mut self, id: NodeId, checked: bool) {
+        if let Some(Kind::Checkbox { checked: old, .. }) = self.widgets.get_mut(&id) {
+            *old = checked;
+        }
+    }
+
+    // Whether the mouse is over the widget, or dragging it
+    pub fn is_hovered(&self, id: NodeId) -> bool {
+        self.hovered == Some(id) || self.active == Some(id)
+    }
+
+    // Handles the mouse, updates how the widgets look and draws the whole
+    // Ui. Clicks on any of the Ui's nodes are kept from the scene.
+    pub fn draw(&mut self, ui: &mut Ui, overlay: &mut Overlay) -> Vec<WidgetEvent> {
+        self.widgets.retain(|id, _| ui.node(*id).is_some());
+        self.callbacks.retain(|id, _| ui.node(*id).is_some());
+        ui.layout(overlay.size(), overlay.fonts());
+
+        let pointer = overlay.pointer();
+        let hit = pointer.position.and_then(|position| ui.node_at(position));
+        self.hovered = hit.and_then(|node| self.widget_above(ui, node, |_| true));
+        let mut events = Vec::new();
+        if pointer.pressed {
+            self.active = self.hovered;
+            let window = hit.and_then(|node| self.widget_above(ui, node, |kind| matches!(kind, Kind::Window)));
+            if let Some(window) = window {
+                ui.raise(window);
+            }
+            if let (Some(Kind::TitleBar { window }), Some(position)) = (self.active_kind(), pointer.position) {
+                let corner = ui.rect(*window).unwrap_or_default().min;
+                self.grab = position - corner;
+            }
+        }
+        if let (Some(id), Some(position)) = (self.active, pointer.position) {
+            if pointer.down || pointer.pressed {
+                events.extend(self.drag(ui, id, position));
+            }
+        }
+        if pointer.released {
+            if let Some(id) = self.active.take() {
+                if self.hovered == Some(id) {
+                    events.extend(self.click(id));
+                }
+            }
+        }
+        if hit.is_some() || self.active.is_some() {
+            overlay.capture_pointer();
+        }
+
+        self.restyle(ui);
+        ui.draw(overlay);
+        for event in &events {
+            if let Some(callbacks) = self.callbacks.get_mut(&event.id()) {
+                for callback in callbacks {
+                    callback(event);
+                }
+            }
+        }
+        events
+    }
+
+    // The nearest widget from `node` up that `filter` lets through
+    fn widget_above(&self, ui: &Ui, node: NodeId, filter: impl Fn(&Kind) -> bool) -> Option<NodeId> {
+        let mut id = Some(node);
+        while let Some(node) = id {
+            if self.widgets.get(&node).is_some_and(&filter) {
+                return Some(node);
+            }
+            id = ui.parent(node);
+        }
+        None
+    }
+
+    fn active_kind(&self) -> Option<&Kind> {
+        self.widgets.get(&self.active?)
+    }
+
+    // The mouse held down on `id` at `position`
+    fn drag(&mut self, ui: &mut Ui, id: NodeId, position: Vec2) -> Option<WidgetEvent> {
+        let rect = ui.rect(id)?;
+        match self.widgets.get_mut(&id)? {
+            Kind::Slider { range, value, .. } => {
+                let track = (rect.size.x - THUMB_WIDTH).max(1.0);
+                let t = ((position.x - rect.min.x - THUMB_WIDTH / 2.0) / track).clamp(0.0, 1.0);
+                let new = range.start() + (range.end() - range.start()) * t;
+                (new != *value).then(|| {
+                    *value = new;
+                    WidgetEvent::Changed(id, new)
+                })
+            }
+            Kind::TitleBar { window } => {
+                let window = *window;
+                let corner = ui.rect(window)?.min;
+                let moved = position - self.grab - corner;
+                if moved == Vec2::ZERO {
+                    return None;
+                }
+                let (_, offset) = ui.node_mut(window)?.anchor.as_mut()?;
+                *offset += moved;
+                Some(WidgetEvent::Moved(window, corner + moved))
+            }
+            _ => None,
+        }
+    }
+
+    // The mouse let go of `id` while still over it
+    fn click(&mut self, id: NodeId) -> Option<WidgetEvent> {
+        match self.widgets.get_mut(&id)? {
+            Kind::Button => Some(WidgetEvent::Clicked(id)),
+            Kind::Checkbox { checked, .. } => {
+                *checked = !*checked;
+                Some(WidgetEvent::Toggled(id, *checked))
+            }
+            _ => None,
+        }
+    }
+
+    // Colours for hovering and pressing, and sliders' thumbs and checkboxes'
+    // ticks where their values say
+    fn restyle(&self, ui: &mut Ui) {
+        let theme = &self.theme;
+        let fill = |id: NodeId| match (self.hovered == Some(id), self.active == Some(id)) {
+            (true, true) => theme.pressed,
+            (false, false) => theme.background,
+            _ => theme.hovered,
+        };
+        for (&id, kind) in &self.widgets {
+            match kind {
+                Kind::Button => {
+                    if let Some(node) = ui.node_mut(id) {
+                        node.background = Some(fill(id));
+                    }
+                }
+                Kind::Slider { range, value, thumb } => {
+                    let width = ui.rect(id).unwrap_or_default().size.x;
+                    let span = range.end() - range.start();
+                    let t = if span == 0.0 { 0.0 } else { (value - range.start()) / span };
+                    if let Some(node) = ui.node_mut(*thumb) {
+                        node.anchor = Some((Anchor::Left, Vec2::new(t * (width - THUMB_WIDTH).max(0.0), 0.0)));
+                        node.background = Some(if self.is_hovered(id) { theme.hovered_accent() } else { theme.accent });
+                    }
+                }
+                Kind::Checkbox { checked, tick_box, tick } => {
+                    if let Some(node) = ui.node_mut(*tick_box) {
+                        node.background = Some(fill(id));
+                    }
+                    if let Some(node) = ui.node_mut(*tick) {
+                        node.visible = *checked;
+                    }
+                }
+                Kind::Window | Kind::TitleBar { .. } => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use anyhow::Result;
+use winit::dpi::LogicalSize;
+use winit::window::{Icon, WindowBuilder};
+
+// How the window run() opens should look. Sizes are in logical pixels, so
+// they come out the same physical size on high DPI screens.
+#[derive(Clone, Debug)]
+pub struct WindowConfig {
+    pub title: String,
+    // None lets the platform pick
+    pub size: Option<(f64, f64)>,
+    pub min_size: Option<(f64, f64)>,
+    pub resizable: bool,
+    pub decorations: bool,
+    // Only makes the window itself transparent, whatever we clear the
+    // surface to still covers it
+    pub transparent: bool,
+    pub icon: Option<Icon>,
+    pub maximized: bool,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            title: "learning_wgpu".to_string(),
+            size: None,
+            min_size: None,
+            resizable: true,
+            decorations: true,
+            transparent: false,
+            icon: None,
+            maximized: false,
+        }
+    }
+}
+
+impl WindowConfig {
+    pub fn new(title: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_size(mut self, width: f64, height: f64) -> Self {
+        self.size = Some((width, height));
+        self
+    }
+
+    pub fn with_min_size(mut self, width: f64, height: f64) -> Self {
+        self.min_size = Some((width, height));
+        self
+    }
+
+    pub fn with_resizable(mut self, resizable: bool) -> Self {
+        self.resizable = resizable;
+        self
+    }
+
+    pub fn with_decorations(mut self, decorations: bool) -> Self {
+        self.decorations = decorations;
+        self
+    }
+
+    pub fn with_transparent(mut self, transparent: bool) -> Self {
+        self.transparent = transparent;
+        self
+    }
+
+    pub fn with_maximized(mut self, maximized: bool) -> Self {
+        self.maximized = maximized;
+        self
+    }
+
+    pub fn with_icon(mut self, icon: Icon) -> Self {
+        self.icon = Some(icon);
+        self
+    }
+
+    // Decodes a png or jpeg, e.g. one pulled in with include_bytes!
+    pub fn with_icon_bytes(self, bytes: &[u8]) -> Result<Self> {
+        let image = image::load_from_memory(bytes)?.to_rgba8();
+        let (width, height) = image.dimensions();
+        Ok(self.with_icon(Icon::from_rgba(image.into_raw(), width, height)?))
+    }
+
+    pub fn builder(&self) -> WindowBuilder {
+        let mut builder = WindowBuilder::new()
+            .with_title(&self.title)
+            .with_resizable(self.resizable)
+            .with_decorations(self.decorations)
+            .with_transparent(self.transparent)
+            .with_window_icon(self.icon.clone())
+            .with_maximized(self.maximized);
+        if let Some((width, height)) = self.size {
+            builder = builder.with_inner_size(LogicalSize::new(width, height));
+        }
+        if let Some((width, height)) = self.min_size {
+            builder = builder.with_min_inner_size(LogicalSize::new(width, height));
+        }
+        builder
+    }
+}
@@ -0,0 +1,30 @@
+use learning_wgpu::scene::Scene;
+
+fn scene_with_track(values: &str) -> anyhow::Result<Scene> {
+    Scene::from_ron(&format!(
+        r#"(
+            entities: [(name: "cube", mesh: Cube)],
+            animations: [(
+                name: "bob",
+                tracks: [(
+                    target: "cube",
+                    property: Translation,
+                    times: [0.0, 1.0],
+                    values: [{}],
+                )],
+            )],
+        )"#,
+        values
+    ))
+}
+
+#[test]
+fn tracks_need_a_value_for_every_keyframe() {
+    assert!(scene_with_track("0.0, 0.0, 0.0, 0.0, 1.0, 0.0").is_ok());
+
+    // One short of the second keyframe's translation
+    let error = scene_with_track("0.0, 0.0, 0.0, 0.0, 1.0").unwrap_err();
+    let message = format!("{:#}", error);
+    assert!(message.contains("\"bob\""), "{}", message);
+    assert!(message.contains("has 5 values"), "{}", message);
+}
@@ -0,0 +1,32 @@
+use glam::Vec3;
+
+use learning_wgpu::bake::Baker;
+use learning_wgpu::scene::Scene;
+
+#[test]
+fn baked_light_is_darker_next_to_and_behind_a_box() {
+    // A unit box standing on a big floor, with the sun low down to the left
+    let scene = Scene::from_ron(
+        r#"(
+            entities: [
+                (name: "floor", transform: (translation: (0.0, -0.1, 0.0), scale: (20.0, 0.2, 20.0)), mesh: Cube),
+                (name: "box", transform: (translation: (0.0, 0.5, 0.0)), mesh: Cube),
+            ],
+            lights: [Directional(direction: (1.0, -1.0, 0.0), color: (1.0, 1.0, 1.0), intensity: 1.0)],
+        )"#,
+    )
+    .unwrap();
+    let baker = Baker::new(&scene).unwrap();
+
+    let open = baker.sky_visibility(Vec3::new(5.0, 0.0, 5.0), Vec3::Y, 256, 1.0, 0);
+    let corner = baker.sky_visibility(Vec3::new(0.55, 0.0, 0.0), Vec3::Y, 256, 1.0, 0);
+    assert!(open > 0.99, "{}", open);
+    assert!(corner < 0.8, "{}", corner);
+    // Inside the box nothing gets out
+    assert_eq!(baker.sky_visibility(Vec3::new(0.0, 0.5, 0.0), Vec3::Y, 64, f32::INFINITY, 0), 0.0);
+
+    // Across the box from the sun is in its shadow
+    let shadowed = baker.irradiance(Vec3::new(1.2, 0.0, 0.0), Vec3::Y, 64, 0);
+    let lit = baker.irradiance(Vec3::new(-1.5, 0.0, 0.0), Vec3::Y, 64, 0);
+    assert!(lit.x - shadowed.x > 0.5, "{:?} against {:?}", lit, shadowed);
+}
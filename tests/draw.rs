@@ -0,0 +1,38 @@
+use learning_wgpu::draw::{DrawCommand, DrawList};
+
+fn command(mesh: u32, depth: f32, instances: std::ops::Range<u32>) -> DrawCommand {
+    DrawCommand {
+        pipeline: 0,
+        material: 0,
+        mesh,
+        depth,
+        indices: 0..36,
+        base_vertex: 0,
+        instances,
+    }
+}
+
+#[test]
+fn each_material_draws_front_to_back() {
+    let mut list = DrawList::new();
+    list.push(command(0, 9.0, 0..1));
+    list.push(command(1, 1.0, 1..2));
+    list.push(command(0, 5.0, 2..3));
+    list.sort_and_batch();
+    let depths = list.commands().iter().map(|command| command.depth).collect::<Vec<_>>();
+    assert_eq!(depths, [1.0, 5.0, 9.0]);
+}
+
+#[test]
+fn only_neighbouring_draws_of_a_mesh_merge() {
+    let mut list = DrawList::new();
+    list.push(command(0, 1.0, 0..1));
+    list.push(command(0, 2.0, 1..2));
+    // Between them by depth, so the last one can't join the first two
+    list.push(command(1, 3.0, 2..3));
+    list.push(command(0, 4.0, 3..4));
+    list.sort_and_batch();
+    let draws = list.commands().iter().map(|command| (command.mesh, command.instances.clone())).collect::<Vec<_>>();
+    assert_eq!(draws, [(0, 0..2), (1, 2..3), (0, 3..4)]);
+    assert_eq!(list.stats().submitted, 4);
+}
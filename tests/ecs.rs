@@ -0,0 +1,51 @@
+#![cfg(feature = "ecs")]
+
+use learning_wgpu::ecs::{Camera, EcsWorld, Light};
+use learning_wgpu::scene::{Scene, SceneLight};
+
+#[test]
+fn lights_and_camera_come_from_the_world() {
+    let scene = Scene::from_ron(
+        r#"(
+            lights: [Point(position: (0.0, 2.0, 0.0), color: (1.0, 1.0, 1.0), intensity: 5.0, range: 10.0)],
+        )"#,
+    )
+    .unwrap();
+    let mut ecs = EcsWorld::new();
+    ecs.spawn_scene(&scene);
+    ecs.extract();
+    assert_eq!(ecs.lights(), &scene.lights[..]);
+    assert_eq!(ecs.moved_camera(), None);
+
+    // Gameplay code changing components shows up in the next extract
+    let sun = SceneLight::Directional {
+        direction: [0.0, -1.0, 0.0],
+        color: [1.0, 1.0, 1.0],
+        intensity: 3.0,
+    };
+    ecs.world.spawn(Light(sun.clone()));
+    ecs.extract();
+    assert_eq!(ecs.lights().len(), 2);
+    assert!(ecs.lights().contains(&sun));
+
+    // Changed the way the editor changes them, only the one that's different
+    // goes back
+    let mut lights = ecs.lights().to_vec();
+    let brighter = SceneLight::Directional {
+        direction: [0.0, -1.0, 0.0],
+        color: [1.0, 1.0, 1.0],
+        intensity: 6.0,
+    };
+    *lights.iter_mut().find(|light| **light == sun).unwrap() = brighter.clone();
+    ecs.set_lights(&lights);
+    ecs.extract();
+    assert!(ecs.lights().contains(&brighter) && ecs.lights().contains(&scene.lights[0]));
+
+    let mut query = ecs.world.query::<&mut Camera>();
+    query.single_mut(&mut ecs.world).0.eye = [5.0, 0.0, 0.0];
+    let moved = ecs.moved_camera().unwrap();
+    assert_eq!(moved.eye, [5.0, 0.0, 0.0]);
+    // Until the renderer puts it back
+    ecs.set_camera(moved);
+    assert_eq!(ecs.moved_camera(), None);
+}
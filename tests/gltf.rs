@@ -0,0 +1,118 @@
+use learning_wgpu::assets::read_model;
+
+// One triangle, moved up by its node, with its buffer either embedded as a
+// data URI or left for a .glb's binary chunk
+fn triangle_json(uri: Option<String>) -> String {
+    let buffer = match uri {
+        Some(uri) => format!(r#"{{ "byteLength": 42, "uri": "{}" }}"#, uri),
+        None => r#"{ "byteLength": 42 }"#.to_string(),
+    };
+    format!(
+        r#"{{
+            "asset": {{ "version": "2.0" }},
+            "scene": 0,
+            "scenes": [{{ "nodes": [0] }}],
+            "nodes": [{{ "name": "tri", "mesh": 0, "translation": [0.0, 2.0, 0.0] }}],
+            "meshes": [{{ "primitives": [{{ "attributes": {{ "POSITION": 0 }}, "indices": 1 }}] }}],
+            "accessors": [
+                {{
+                    "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                    "min": [0.0, 0.0, 0.0], "max": [1.0, 1.0, 0.0]
+                }},
+                {{ "bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR" }}
+            ],
+            "bufferViews": [
+                {{ "buffer": 0, "byteOffset": 0, "byteLength": 36 }},
+                {{ "buffer": 0, "byteOffset": 36, "byteLength": 6 }}
+            ],
+            "buffers": [{}]
+        }}"#,
+        buffer
+    )
+}
+
+fn triangle_buffer() -> Vec<u8> {
+    let positions = [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+    let mut bytes = positions.iter().flat_map(|p| p.to_le_bytes()).collect::<Vec<_>>();
+    bytes.extend([0u16, 1, 2].iter().flat_map(|i| i.to_le_bytes()));
+    bytes
+}
+
+fn glb(json: &str, bin: &[u8]) -> Vec<u8> {
+    // Chunks are padded to 4 bytes, JSON with spaces and binary with zeros
+    let mut json = json.as_bytes().to_vec();
+    json.resize(json.len().div_ceil(4) * 4, b' ');
+    let mut bin = bin.to_vec();
+    bin.resize(bin.len().div_ceil(4) * 4, 0);
+
+    let mut bytes = Vec::new();
+    bytes.extend(b"glTF");
+    bytes.extend(2u32.to_le_bytes());
+    bytes.extend(((12 + 8 + json.len() + 8 + bin.len()) as u32).to_le_bytes());
+    bytes.extend((json.len() as u32).to_le_bytes());
+    bytes.extend(b"JSON");
+    bytes.extend(json);
+    bytes.extend((bin.len() as u32).to_le_bytes());
+    bytes.extend(b"BIN\0");
+    bytes.extend(bin);
+    bytes
+}
+
+#[test]
+fn gltf_and_glb_models_load_with_their_node_transforms() {
+    let dir = std::env::temp_dir().join(format!("learning_wgpu_gltf_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let uri = format!("data:application/octet-stream;base64,{}", base64::encode(triangle_buffer()));
+    let gltf = dir.join("triangle.gltf");
+    std::fs::write(&gltf, triangle_json(Some(uri))).unwrap();
+    let binary = dir.join("triangle.glb");
+    std::fs::write(&binary, glb(&triangle_json(None), &triangle_buffer())).unwrap();
+
+    for path in [&gltf, &binary] {
+        let meshes = read_model(path).unwrap();
+        assert_eq!(meshes.len(), 1, "{}", path.display());
+        let (name, mesh) = &meshes[0];
+        assert_eq!(name, "tri");
+        assert_eq!(mesh.positions, [[0.0, 2.0, 0.0], [1.0, 2.0, 0.0], [0.0, 3.0, 0.0]]);
+        assert_eq!(mesh.indices, [0, 1, 2]);
+        // Filled in like an .obj without normals
+        assert_eq!(mesh.normals.len(), 3);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn gltf_reads_percent_encoded_uris_and_sparse_accessors() {
+    let dir = std::env::temp_dir().join(format!("learning_wgpu_gltf_sparse_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    // The triangle, then one sparse index and the position it replaces
+    let mut buffer = triangle_buffer();
+    buffer.extend([0u8; 2]);
+    buffer.extend(2u32.to_le_bytes());
+    buffer.extend([0.0f32, 5.0, 0.0].iter().flat_map(|p| p.to_le_bytes()));
+    std::fs::write(dir.join("tri angle (1).bin"), &buffer).unwrap();
+
+    let json = triangle_json(Some("tri%20angle%20%281%29.bin".to_string()))
+        .replace(r#""byteLength": 42,"#, r#""byteLength": 60,"#)
+        .replace(
+            r#""max": [1.0, 1.0, 0.0]"#,
+            r#""max": [1.0, 5.0, 0.0],
+                    "sparse": {
+                        "count": 1,
+                        "indices": { "bufferView": 2, "componentType": 5125 },
+                        "values": { "bufferView": 3 }
+                    }"#,
+        )
+        .replace(
+            r#"{ "buffer": 0, "byteOffset": 36, "byteLength": 6 }"#,
+            r#"{ "buffer": 0, "byteOffset": 36, "byteLength": 6 },
+                { "buffer": 0, "byteOffset": 44, "byteLength": 4 },
+                { "buffer": 0, "byteOffset": 48, "byteLength": 12 }"#,
+        );
+    let path = dir.join("sparse.gltf");
+    std::fs::write(&path, json).unwrap();
+
+    let meshes = read_model(&path).unwrap();
+    assert_eq!(meshes[0].1.positions, [[0.0, 2.0, 0.0], [1.0, 2.0, 0.0], [0.0, 7.0, 0.0]]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,37 @@
+use std::path::Path;
+
+use learning_wgpu::golden::{self, Tolerance};
+use learning_wgpu::scene::Scene;
+
+const WIDTH: u32 = 256;
+const HEIGHT: u32 = 192;
+
+// Renders every scene in tests/golden/ and compares it against the .png of
+// the same name. After a change that's meant to alter the images, run
+// `UPDATE_GOLDEN=1 cargo test --test golden` and look over the new ones
+// before checking them in.
+#[test]
+fn golden_images() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let mut scenes = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "ron"))
+        .collect::<Vec<_>>();
+    scenes.sort();
+    assert!(!scenes.is_empty(), "no scenes in {}", dir.display());
+
+    let mut failures = Vec::new();
+    for path in scenes {
+        let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+        let scene = Scene::load(&path).unwrap();
+        let Some(image) = pollster::block_on(golden::render_scene(scene, WIDTH, HEIGHT)).unwrap() else {
+            eprintln!("No GPU adapter, skipping the golden image tests");
+            return;
+        };
+        if let Err(e) = golden::check(&dir, &name, &image, &Tolerance::default()) {
+            failures.push(format!("{}: {:#}", name, e));
+        }
+    }
+    assert!(failures.is_empty(), "golden images differ:\n{}", failures.join("\n"));
+}
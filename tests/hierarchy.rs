@@ -0,0 +1,34 @@
+use glam::Vec3;
+
+use learning_wgpu::scene::{Scene, SceneHierarchy};
+use learning_wgpu::transform::Transform;
+
+#[test]
+fn parents_apply_in_any_order_and_bad_ones_become_roots() {
+    // The child comes before its parent, "lost" names nobody and "a" and
+    // "b" are each other's parent
+    let scene = Scene::from_ron(
+        r#"(
+            entities: [
+                (name: "child", parent: Some("root"), transform: (translation: (0.0, 1.0, 0.0)), mesh: Cube),
+                (name: "root", transform: (translation: (2.0, 0.0, 0.0)), mesh: Cube),
+                (name: "lost", parent: Some("nobody"), transform: (translation: (0.0, 0.0, 3.0)), mesh: Cube),
+                (name: "a", parent: Some("b"), transform: (translation: (1.0, 0.0, 0.0)), mesh: Cube),
+                (name: "b", parent: Some("a"), transform: (translation: (1.0, 0.0, 0.0)), mesh: Cube),
+            ],
+        )"#,
+    )
+    .unwrap();
+    let hierarchy = SceneHierarchy::new(&scene.entities);
+    assert_eq!(hierarchy.parent(0), Some(1));
+    assert_eq!(hierarchy.parent(2), None);
+
+    let local = scene.entities.iter().map(|e| Transform::from(&e.transform)).collect::<Vec<_>>();
+    let world = hierarchy.world_transforms(&local);
+    assert_eq!(world[0].translation, Vec3::new(2.0, 1.0, 0.0));
+    assert_eq!(world[2].translation, Vec3::new(0.0, 0.0, 3.0));
+    // One link of the loop gets cut, so one of them ends up under the other
+    let (a, b) = (world[3].translation.x, world[4].translation.x);
+    assert!((a, b) == (1.0, 2.0) || (a, b) == (2.0, 1.0), "{} {}", a, b);
+    assert_eq!(world, scene.world_transforms());
+}
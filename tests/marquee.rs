@@ -0,0 +1,18 @@
+use glam::Vec2;
+
+use learning_wgpu::marquee::Marquee;
+
+#[test]
+fn marquee_needs_a_real_drag() {
+    let mut marquee = Marquee::new();
+    marquee.begin(Vec2::new(10.0, 10.0));
+    marquee.drag(Vec2::new(12.0, 11.0));
+    assert_eq!(marquee.end(), None);
+    assert!(!marquee.is_dragging());
+
+    // Up and to the left still gives min and max corners
+    marquee.begin(Vec2::new(40.0, 30.0));
+    marquee.drag(Vec2::new(10.0, 20.0));
+    assert_eq!(marquee.rect(), Some((Vec2::new(10.0, 20.0), Vec2::new(40.0, 30.0))));
+    assert_eq!(marquee.end(), Some((Vec2::new(10.0, 20.0), Vec2::new(40.0, 30.0))));
+}
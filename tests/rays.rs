@@ -0,0 +1,96 @@
+use glam::{Vec2, Vec3};
+
+use learning_wgpu::bounds::{self, Aabb, Frustum, Ray};
+use learning_wgpu::mesh::Mesh;
+use learning_wgpu::scene::SceneCamera;
+
+// A 200 by 100 viewport starting 50 pixels in from the left, with the
+// default camera at z = 2 looking at the origin
+const VIEWPORT: [u32; 4] = [50, 0, 200, 100];
+
+fn view_proj() -> glam::Mat4 {
+    SceneCamera::default().view_proj(2.0)
+}
+
+#[test]
+fn middle_of_the_viewport_looks_straight_ahead() {
+    let ray = Ray::from_screen(view_proj(), VIEWPORT, Vec2::new(150.0, 50.0)).unwrap();
+    let direction = ray.direction.normalize();
+    assert!(direction.abs_diff_eq(Vec3::NEG_Z, 1e-4), "{:?}", direction);
+    // Starts on the near plane
+    assert!((ray.origin.z - 1.9).abs() < 1e-4, "{:?}", ray.origin);
+}
+
+#[test]
+fn outside_the_viewport_has_no_ray() {
+    assert_eq!(Ray::from_screen(view_proj(), VIEWPORT, Vec2::new(20.0, 50.0)), None);
+    assert_eq!(Ray::from_screen(view_proj(), VIEWPORT, Vec2::new(150.0, 120.0)), None);
+}
+
+#[test]
+fn world_to_screen_undoes_from_screen() {
+    for point in [Vec2::new(60.0, 10.0), Vec2::new(150.0, 50.0), Vec2::new(240.0, 90.0)] {
+        let ray = Ray::from_screen(view_proj(), VIEWPORT, point).unwrap();
+        for distance in [0.0, 0.3, 1.0] {
+            let screen = bounds::world_to_screen(view_proj(), VIEWPORT, ray.at(distance)).unwrap();
+            assert!(screen.abs_diff_eq(point, 1e-2), "{:?} came back as {:?}", point, screen);
+        }
+    }
+}
+
+#[test]
+fn up_in_the_world_is_up_on_screen() {
+    let above = bounds::world_to_screen(view_proj(), VIEWPORT, Vec3::new(0.0, 0.5, 0.0)).unwrap();
+    let right = bounds::world_to_screen(view_proj(), VIEWPORT, Vec3::new(0.5, 0.0, 0.0)).unwrap();
+    assert!(above.y < 50.0 && (above.x - 150.0).abs() < 1e-3, "{:?}", above);
+    assert!(right.x > 150.0 && (right.y - 50.0).abs() < 1e-3, "{:?}", right);
+}
+
+#[test]
+fn behind_the_camera_is_nowhere_on_screen() {
+    assert_eq!(bounds::world_to_screen(view_proj(), VIEWPORT, Vec3::new(0.0, 0.0, 5.0)), None);
+}
+
+#[test]
+fn screen_rect_frustum_holds_what_the_rect_covers() {
+    let small = |center: Vec3| Aabb::new(center - 0.05, center + 0.05);
+    let right = bounds::world_to_screen(view_proj(), VIEWPORT, Vec3::new(0.5, 0.0, 0.0)).unwrap();
+    // Around the right hand box
+    let frustum = Frustum::from_screen_rect(view_proj(), VIEWPORT, right - 5.0, right + 5.0);
+    assert!(frustum.intersects_aabb(&small(Vec3::new(0.5, 0.0, 0.0))));
+    assert!(!frustum.intersects_aabb(&small(Vec3::ZERO)));
+    assert!(!frustum.intersects_aabb(&small(Vec3::new(0.5, 0.5, 0.0))));
+    // Behind the camera, where it would project inside the rect upside down
+    assert!(!frustum.intersects_aabb(&small(Vec3::new(-0.5, 0.0, 4.0))));
+}
+
+#[test]
+fn triangle_hits_say_where_on_the_triangle() {
+    let ray = Ray::new(Vec3::new(0.25, 0.25, 1.0), Vec3::new(0.0, 0.0, -2.0));
+    let (distance, weights) = ray.intersect_triangle(Vec3::ZERO, Vec3::X, Vec3::Y).unwrap();
+    assert!((distance - 0.5).abs() < 1e-5, "{}", distance);
+    assert!(weights.abs_diff_eq(Vec2::new(0.25, 0.25), 1e-5), "{:?}", weights);
+    // From the back as well, but not past its edge or behind the ray
+    let back = Ray::new(Vec3::new(0.25, 0.25, -1.0), Vec3::Z);
+    assert!(back.intersect_triangle(Vec3::ZERO, Vec3::X, Vec3::Y).is_some());
+    let past = Ray::new(Vec3::new(0.75, 0.75, 1.0), Vec3::NEG_Z);
+    assert_eq!(past.intersect_triangle(Vec3::ZERO, Vec3::X, Vec3::Y), None);
+    let behind = Vec3::new(0.0, 0.0, 2.0);
+    assert_eq!(ray.intersect_triangle(behind, behind + Vec3::X, behind + Vec3::Y), None);
+}
+
+#[test]
+fn mesh_raycast_finds_the_tex_coords_under_the_ray() {
+    let plane = Mesh::plane(2.0, 4);
+    let hit = plane.raycast(&Ray::new(Vec3::new(0.5, 1.0, -0.5), Vec3::NEG_Y)).unwrap();
+    assert!((hit.distance - 1.0).abs() < 1e-5, "{}", hit.distance);
+    assert!(hit.tex_coords.abs_diff_eq(Vec2::new(0.75, 0.25), 1e-5), "{:?}", hit.tex_coords);
+    assert_eq!(plane.raycast(&Ray::new(Vec3::new(1.5, 1.0, 0.0), Vec3::NEG_Y)), None);
+
+    // Moved up by 3, hit with a ray in the world
+    let moved = glam::Mat4::from_translation(Vec3::new(0.0, 3.0, 0.0));
+    let ray = Ray::new(Vec3::new(0.0, 5.0, 0.0), Vec3::NEG_Y).transformed(moved.inverse());
+    let hit = plane.raycast(&ray).unwrap();
+    assert!((hit.distance - 2.0).abs() < 1e-5, "{}", hit.distance);
+    assert!(hit.tex_coords.abs_diff_eq(Vec2::splat(0.5), 1e-5), "{:?}", hit.tex_coords);
+}
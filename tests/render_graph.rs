@@ -0,0 +1,26 @@
+use learning_wgpu::render_graph::{RenderGraph, TransientTexture};
+
+fn graph_with_texture<'g>() -> RenderGraph<'g> {
+    let mut graph = RenderGraph::new();
+    graph.create_texture("x", TransientTexture::new(wgpu::TextureFormat::Rgba8Unorm));
+    graph
+}
+
+#[test]
+fn reading_before_a_later_write_sees_the_old_contents() {
+    let mut graph = graph_with_texture();
+    graph.add_pass("a").reads(&["x"]).execute(|_, _| {});
+    graph.add_pass("b").writes(&["x"]).execute(|_, _| {});
+    assert_eq!(graph.pass_order().unwrap(), ["a", "b"]);
+}
+
+#[test]
+fn readers_wait_for_the_write_before_them() {
+    let mut graph = graph_with_texture();
+    graph.add_pass("clear").writes(&["x"]).execute(|_, _| {});
+    graph.add_pass("draw").writes(&["x"]).execute(|_, _| {});
+    graph.add_pass("blur").reads(&["x"]).execute(|_, _| {});
+    graph.add_pass("overwrite").writes(&["x"]).execute(|_, _| {});
+    graph.add_pass("show").reads(&["x"]).execute(|_, _| {});
+    assert_eq!(graph.pass_order().unwrap(), ["clear", "draw", "blur", "overwrite", "show"]);
+}
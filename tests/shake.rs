@@ -0,0 +1,36 @@
+use learning_wgpu::noise::perlin;
+use learning_wgpu::shake::CameraShake;
+use learning_wgpu::transform::Transform;
+
+#[test]
+fn camera_shake_wears_off() {
+    let mut shake = CameraShake::new();
+    assert_eq!(shake.offset(), Transform::IDENTITY);
+    shake.add_shake(0.6);
+    shake.add_shake(0.6);
+    assert_eq!(shake.trauma(), 1.0);
+
+    shake.update(0.1);
+    let offset = shake.offset();
+    assert!(offset.rotation.angle_between(glam::Quat::IDENTITY) > 0.0, "{:?}", offset);
+    assert!(offset.translation.length() <= shake.max_offset.length(), "{:?}", offset);
+
+    // A second at the default recovery takes it all away
+    shake.update(1.0);
+    assert_eq!(shake.trauma(), 0.0);
+    assert_eq!(shake.offset(), Transform::IDENTITY);
+}
+
+#[test]
+fn perlin_noise_is_smooth_and_crosses_zero_on_whole_numbers() {
+    for seed in 0..4 {
+        assert_eq!(perlin(3.0, seed), 0.0);
+        let mut last = perlin(0.0, seed);
+        for i in 1..=400 {
+            let value = perlin(i as f32 * 0.01, seed);
+            assert!((-1.0..=1.0).contains(&value), "{}", value);
+            assert!((value - last).abs() < 0.05, "jumped from {} to {}", last, value);
+            last = value;
+        }
+    }
+}
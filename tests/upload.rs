@@ -0,0 +1,43 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use learning_wgpu::upload::Uploader;
+
+// Any adapter will do, there's nothing to show
+fn device() -> Option<(wgpu::Device, wgpu::Queue)> {
+    let instance = wgpu::Instance::new(wgpu::Backends::all());
+    let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))?;
+    pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor::default(), None)).ok()
+}
+
+#[test]
+fn misaligned_writes_say_what_is_wrong() {
+    let Some((device, _queue)) = device() else {
+        eprintln!("No GPU adapter, skipping the upload tests");
+        return;
+    };
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        size: 64,
+        usage: wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let mut uploader = Uploader::new();
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+
+    // Lined up, and nothing at all, are both fine
+    uploader.write(&device, &mut encoder, &buffer, 4, &[1.0f32, 2.0]);
+    uploader.write::<u8>(&device, &mut encoder, &buffer, 2, &[]);
+
+    let message = |result: std::thread::Result<()>| {
+        let error = result.expect_err("misaligned write went through");
+        error.downcast_ref::<String>().cloned().unwrap_or_default()
+    };
+    let offset = message(catch_unwind(AssertUnwindSafe(|| {
+        uploader.write(&device, &mut encoder, &buffer, 2, &[1.0f32]);
+    })));
+    assert!(offset.contains("offset 2"), "{}", offset);
+    let size = message(catch_unwind(AssertUnwindSafe(|| {
+        uploader.write(&device, &mut encoder, &buffer, 0, &[1u8, 2, 3]);
+    })));
+    assert!(size.contains("3 bytes"), "{}", size);
+}
@@ -0,0 +1,14 @@
+// Run with `cargo run -- toys/plasma.wgsl` and edit away, saving reloads it.
+// F12 saves a screenshot.
+
+fn main_image(frag_coord: vec2<f32>) -> vec4<f32> {
+    let uv = frag_coord / frame.resolution;
+    let mouse = frame.mouse / frame.resolution;
+    let t = frame.time;
+
+    var v = sin(uv.x * 10.0 + t);
+    v = v + sin((uv.y * 10.0 + t) * 0.5);
+    v = v + sin(length((uv - mouse) * 20.0) - t * 2.0);
+    let color = 0.5 + 0.5 * cos(vec3<f32>(0.0, 2.0, 4.0) + v + t);
+    return vec4<f32>(color, 1.0);
+}